@@ -0,0 +1,496 @@
+use std::{
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+    },
+    path::Path,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use async_compat::Compat;
+use bevy::{
+    prelude::*,
+    tasks::{
+        block_on,
+        AsyncComputeTaskPool,
+        Task,
+    },
+};
+use bevy_egui::{
+    egui,
+    EguiContexts,
+    EguiGlobalSettings,
+    EguiPlugin,
+    EguiPrimaryContextPass,
+    PrimaryEguiContext,
+};
+use bevy_persistent::{
+    Persistent,
+    StorageFormat,
+};
+use futures_lite::future;
+use serde_json::{
+    json,
+    Value,
+};
+
+use crate::{
+    napcat::NapcatControlApiConfig,
+    ui::configure_ui_fonts,
+};
+
+const ATTACH_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+const ATTACH_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const ATTACH_MESSAGE_LIMIT: usize = 50;
+
+/// Desktop window for a data directory another instance (normally the headless server) already
+/// owns. The stores are never opened a second time; campaigns, battles and chat are read and
+/// driven through that instance's control API with the GM token.
+pub struct AttachPlugin {
+    /// Why the data directory could not be locked.
+    pub lock_error: String,
+    pub config_dir: std::path::PathBuf,
+}
+
+impl Plugin for AttachPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(EguiPlugin::default())
+            .insert_resource(EguiGlobalSettings {
+                auto_create_primary_context: false,
+                ..default()
+            })
+            .insert_resource(AttachState::new(
+                self.lock_error.clone(),
+                load_attach_client(&self.config_dir),
+            ))
+            .add_systems(Startup, spawn_attach_camera)
+            .add_systems(Update, attach_task_system)
+            .add_systems(
+                EguiPrimaryContextPass,
+                (
+                    configure_ui_fonts,
+                    attach_ui_system.after(configure_ui_fonts),
+                ),
+            );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AttachClient {
+    base_url: String,
+    token: String,
+}
+
+#[derive(Debug, Clone)]
+enum AttachCall {
+    Refresh { group: Option<String> },
+    Send { target: Value, text: String },
+    NextRound { encounter_id: String },
+}
+
+enum AttachOutcome {
+    Refreshed {
+        campaigns: Vec<Value>,
+        battles: Vec<Value>,
+        messages: Vec<Value>,
+    },
+    Done(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum AttachSendKind {
+    #[default]
+    Group,
+    Private,
+    Party,
+}
+
+impl AttachSendKind {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Group => "群聊",
+            Self::Private => "私聊",
+            Self::Party => "队伍",
+        }
+    }
+
+    /// The `ScheduledTarget` JSON the control API expects.
+    fn target(self, id: &str) -> Result<Value, String> {
+        let id = id.trim();
+        let numeric = || {
+            id.parse::<u64>()
+                .map_err(|_| format!("{}号必须是数字", self.label()))
+        };
+        Ok(match self {
+            Self::Group => json!({ "kind": "group", "id": numeric()? }),
+            Self::Private => json!({ "kind": "private", "id": numeric()? }),
+            Self::Party if id.is_empty() => return Err("请填写队伍".to_owned()),
+            Self::Party => json!({ "kind": "party", "id": id }),
+        })
+    }
+}
+
+#[derive(Resource)]
+struct AttachState {
+    lock_error: String,
+    client: Result<AttachClient, String>,
+    task: Option<Task<Result<AttachOutcome, String>>>,
+    last_refresh: Option<Instant>,
+    group: Option<String>,
+    campaigns: Vec<Value>,
+    battles: Vec<Value>,
+    messages: Vec<Value>,
+    send_kind: AttachSendKind,
+    send_id: String,
+    send_text: String,
+    status: String,
+}
+
+impl AttachState {
+    fn new(lock_error: String, client: Result<AttachClient, String>) -> Self {
+        Self {
+            lock_error,
+            client,
+            task: None,
+            last_refresh: None,
+            group: None,
+            campaigns: Vec::new(),
+            battles: Vec::new(),
+            messages: Vec::new(),
+            send_kind: AttachSendKind::default(),
+            send_id: String::new(),
+            send_text: String::new(),
+            status: String::new(),
+        }
+    }
+
+    fn spawn(&mut self, call: AttachCall) {
+        let Ok(client) = self.client.clone() else {
+            return;
+        };
+        if self.task.is_some() {
+            return;
+        }
+        if matches!(call, AttachCall::Refresh { .. }) {
+            self.last_refresh = Some(Instant::now());
+        }
+        self.task = Some(
+            AsyncComputeTaskPool::get().spawn(Compat::new(run_attach_call(
+                client, call,
+            ))),
+        );
+    }
+}
+
+/// Reads the running instance's control API settings without taking over the file.
+fn load_attach_client(config_dir: &Path) -> Result<AttachClient, String> {
+    let path = config_dir.join("control_api.toml");
+    if !path.exists() {
+        return Err("正在运行的柳絮还没有控制API设置，无法附加".to_owned());
+    }
+    let config = Persistent::<NapcatControlApiConfig>::builder()
+        .name("attached control api")
+        .format(StorageFormat::Toml)
+        .path(path)
+        .default(NapcatControlApiConfig::default())
+        .build()
+        .map_err(|err| format!("读取控制API设置失败：{err}"))?;
+    if !config.enabled {
+        return Err(
+            "正在运行的柳絮没有开启控制API：在 control_api.toml 里设置 enabled = true 并重启它后即可附加"
+                .to_owned(),
+        );
+    }
+    Ok(AttachClient {
+        base_url: attach_base_url(&config.listen_addr)?,
+        token: config.token.trim().to_owned(),
+    })
+}
+
+/// The URL to reach a control API listening on `listen_addr`; wildcard binds are reached over
+/// loopback.
+fn attach_base_url(listen_addr: &str) -> Result<String, String> {
+    let addr = listen_addr
+        .trim()
+        .parse::<SocketAddr>()
+        .map_err(|err| format!("控制API监听地址无效：{err}"))?;
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    Ok(format!(
+        "http://{}",
+        SocketAddr::new(ip, addr.port())
+    ))
+}
+
+async fn run_attach_call(client: AttachClient, call: AttachCall) -> Result<AttachOutcome, String> {
+    let http = reqwest::Client::builder()
+        .timeout(ATTACH_REQUEST_TIMEOUT)
+        .build()
+        .map_err(|err| format!("无法创建HTTP客户端：{err}"))?;
+    let get = |path: &'static str, query: Vec<(&'static str, String)>| {
+        http.get(format!("{}{path}", client.base_url))
+            .bearer_auth(&client.token)
+            .query(&query)
+    };
+    let post = |path: &'static str, body: Value| {
+        http.post(format!("{}{path}", client.base_url))
+            .bearer_auth(&client.token)
+            .json(&body)
+    };
+    match call {
+        AttachCall::Refresh { group } => {
+            let campaigns = attach_response(get("/api/campaigns", vec![(
+                "viewer",
+                "gm".to_owned(),
+            )]))
+            .await?;
+            let battles = attach_response(get("/api/battles", vec![(
+                "viewer",
+                "gm".to_owned(),
+            )]))
+            .await?;
+            let mut query = vec![
+                ("viewer", "gm".to_owned()),
+                (
+                    "limit",
+                    ATTACH_MESSAGE_LIMIT.to_string(),
+                ),
+            ];
+            query.extend(group.map(|group| ("group", group)));
+            // A table without a current group has no messages to read yet.
+            let messages = attach_response(get("/api/messages", query))
+                .await
+                .map(|data| data["messages"].as_array().cloned().unwrap_or_default())
+                .unwrap_or_default();
+            Ok(AttachOutcome::Refreshed {
+                campaigns: campaigns.as_array().cloned().unwrap_or_default(),
+                battles: battles.as_array().cloned().unwrap_or_default(),
+                messages,
+            })
+        },
+        AttachCall::Send { target, text } => {
+            attach_response(post(
+                "/api/messages",
+                json!({ "target": target, "text": text }),
+            ))
+            .await?;
+            Ok(AttachOutcome::Done(
+                "消息已交给服务器发送".to_owned(),
+            ))
+        },
+        AttachCall::NextRound { encounter_id } => {
+            let data = attach_response(post(
+                "/api/battles/next-round",
+                json!({ "encounter_id": encounter_id }),
+            ))
+            .await?;
+            Ok(AttachOutcome::Done(format!(
+                "已进入第{}轮",
+                data["round"]
+            )))
+        },
+    }
+}
+
+async fn attach_response(request: reqwest::RequestBuilder) -> Result<Value, String> {
+    let body = request
+        .send()
+        .await
+        .map_err(|err| format!("无法连接正在运行的柳絮：{err}"))?
+        .json::<Value>()
+        .await
+        .map_err(|err| format!("控制API返回了无效数据：{err}"))?;
+    if body["ok"].as_bool() == Some(true) {
+        Ok(body["data"].clone())
+    } else {
+        Err(body["error"]
+            .as_str()
+            .unwrap_or("控制API请求失败")
+            .to_owned())
+    }
+}
+
+fn spawn_attach_camera(mut commands: Commands) { commands.spawn((Camera2d, PrimaryEguiContext)); }
+
+fn attach_task_system(mut state: ResMut<AttachState>) {
+    if let Some(task) = state.task.as_mut() {
+        let Some(outcome) = block_on(future::poll_once(task)) else {
+            return;
+        };
+        state.task = None;
+        match outcome {
+            Ok(AttachOutcome::Refreshed {
+                campaigns,
+                battles,
+                messages,
+            }) => {
+                state.campaigns = campaigns;
+                state.battles = battles;
+                state.messages = messages;
+            },
+            Ok(AttachOutcome::Done(status)) => {
+                state.status = status;
+                state.last_refresh = None;
+            },
+            Err(err) => state.status = err,
+        }
+        return;
+    }
+    if state
+        .last_refresh
+        .is_none_or(|last_refresh| last_refresh.elapsed() >= ATTACH_REFRESH_INTERVAL)
+    {
+        let group = state.group.clone();
+        state.spawn(AttachCall::Refresh { group });
+    }
+}
+
+fn attach_ui_system(mut contexts: EguiContexts, mut state: ResMut<AttachState>) {
+    let Ok(ctx) = contexts.ctx_mut() else {
+        return;
+    };
+    let state = &mut *state;
+    let mut call = None;
+    egui::CentralPanel::default().show(ctx, |ui| {
+        ui.heading("已附加到正在运行的柳絮");
+        ui.small(&state.lock_error);
+        if let Err(err) = &state.client {
+            ui.separator();
+            ui.label(err);
+            return;
+        }
+        if !state.status.is_empty() {
+            ui.label(&state.status);
+        }
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("TRPG组");
+            let current = state
+                .group
+                .clone()
+                .or_else(|| {
+                    state
+                        .campaigns
+                        .iter()
+                        .find(|campaign| campaign["current"].as_bool() == Some(true))
+                        .and_then(|campaign| campaign["group"].as_str().map(str::to_owned))
+                })
+                .unwrap_or_default();
+            egui::ComboBox::from_id_salt("attach_group")
+                .selected_text(&current)
+                .show_ui(ui, |ui| {
+                    for name in state
+                        .campaigns
+                        .iter()
+                        .filter_map(|campaign| campaign["group"].as_str())
+                    {
+                        if ui.selectable_label(current == name, name).clicked() {
+                            state.group = Some(name.to_owned());
+                            state.last_refresh = None;
+                        }
+                    }
+                });
+        });
+
+        ui.label("战斗");
+        for battle in &state.battles {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{} · 第{}轮{}",
+                    battle["name"].as_str().unwrap_or_default(),
+                    battle["round"],
+                    if battle["active"].as_bool() == Some(true) { "" } else { "（未进行）" }
+                ));
+                if battle["active"].as_bool() == Some(true) && ui.button("下一轮").clicked() {
+                    call = Some(AttachCall::NextRound {
+                        encounter_id: battle["encounter_id"]
+                            .as_str()
+                            .unwrap_or_default()
+                            .to_owned(),
+                    });
+                }
+            });
+        }
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("attach_send_kind")
+                .selected_text(state.send_kind.label())
+                .show_ui(ui, |ui| {
+                    for kind in [
+                        AttachSendKind::Group,
+                        AttachSendKind::Private,
+                        AttachSendKind::Party,
+                    ] {
+                        ui.selectable_value(&mut state.send_kind, kind, kind.label());
+                    }
+                });
+            ui.add(egui::TextEdit::singleline(&mut state.send_id).desired_width(100.0));
+            ui.add(egui::TextEdit::singleline(&mut state.send_text).desired_width(240.0));
+            if ui.button("发送").clicked() {
+                match state.send_kind.target(&state.send_id) {
+                    Ok(_) if state.send_text.trim().is_empty() => {
+                        state.status = "消息内容不能为空".to_owned();
+                    },
+                    Ok(target) => {
+                        call = Some(AttachCall::Send {
+                            target,
+                            text: std::mem::take(&mut state.send_text),
+                        });
+                    },
+                    Err(err) => state.status = err,
+                }
+            }
+        });
+        egui::ScrollArea::vertical()
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for message in &state.messages {
+                    ui.label(format!(
+                        "{}：{}",
+                        message["sender_name"].as_str().unwrap_or_default(),
+                        message["text"].as_str().unwrap_or_default()
+                    ));
+                }
+            });
+    });
+    if let Some(call) = call {
+        state.spawn(call);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_listen_addresses_are_reached_over_loopback() {
+        assert_eq!(
+            attach_base_url("0.0.0.0:8686").as_deref(),
+            Ok("http://127.0.0.1:8686")
+        );
+        assert_eq!(
+            attach_base_url("[::]:8686").as_deref(),
+            Ok("http://[::1]:8686")
+        );
+        assert_eq!(
+            attach_base_url("192.168.1.5:9000").as_deref(),
+            Ok("http://192.168.1.5:9000")
+        );
+        assert!(attach_base_url("localhost").is_err());
+        assert!(AttachSendKind::Group.target("abc").is_err());
+        assert_eq!(
+            AttachSendKind::Party.target(" red ").unwrap(),
+            json!({ "kind": "party", "id": "red" })
+        );
+    }
+}
@@ -21,6 +21,7 @@ use bevy_persistent::{
     Persistent,
     StorageFormat,
 };
+use rand::RngExt;
 use serde::{
     Deserialize,
     Serialize,
@@ -35,6 +36,10 @@ use crate::rule_engine::{
     BuffValue,
 };
 use crate::{
+    dice::{
+        parse_dice_expression,
+        roll_dice_expression,
+    },
     napcat::{
         arrogance_damage_dealt_multiplier,
         champion_damage_dealt_multiplier,
@@ -49,6 +54,7 @@ use crate::{
         character_damage_attribute_multiplier,
         character_damage_dealt_talent_buffs,
         character_damage_taken_attribute_multiplier,
+        character_dice_attribute,
         character_dominion_max_hp_bonus_cap,
         character_dominion_max_hp_gain_rate,
         character_dying_target_healing_modifier,
@@ -84,8 +90,10 @@ use crate::{
         character_undying_rage_available,
         character_valorous_battle_damage_multiplier,
         character_wounded_healing_dealt_modifier,
+        consume_character_item,
         dying_target_healing_multiplier,
         endless_pain_bonus_damage,
+        grant_character_experience,
         infinite_focus_damage_dealt_multiplier,
         large_hit_damage_taken_multiplier,
         low_hp_damage_multiplier_with_fatigue,
@@ -99,8 +107,11 @@ use crate::{
         status_damage_attribute_multiplier,
         status_healing_attribute_multiplier,
         trpg_config_with_weave,
+        update_character_from_status_with_config,
+        usable_item_index,
         wounded_healing_dealt_multiplier,
         CharacterStatus,
+        InventoryItem,
         NapcatMessageManager,
         PlayerCharacter,
         SkillRuleArgs,
@@ -108,14 +119,19 @@ use crate::{
         TrpgDamageBonusKind,
         TrpgDamageTakenKind,
         TrpgGroup,
+        TrpgPlayerTurnState,
         UnitPoolEntry,
+        CONSUMABLE_ITEM_SKILL_INDEX,
     },
     rule_engine::{
         apply_skill_type_damage_default,
+        buff_condition_source,
+        buff_conditions,
         legacy_moonberry_buff_machine_skill_cast_rule,
         parse_rule_with_named_args,
         Action,
         ActorRef,
+        BuffCondition,
         BuffTickAction,
         DamageType,
         RuleBuffTemplate,
@@ -123,11 +139,19 @@ use crate::{
         TargetSelector,
         ValueExpr,
     },
-    scene::SceneCharacterPositions,
+    scene::{
+        SceneCharacterPositions,
+        SceneCover,
+    },
     ui::{
         advance_buffs_for_players,
+        refresh_character_derived_stats,
         sync_character_buffs,
     },
+    voxel::{
+        DEFAULT_POSSESSION_MOVEMENT_BONUS,
+        VOXEL_SIZE,
+    },
 };
 
 const MAX_GROUP_CLOCK_CATCH_UP_ROUNDS_PER_FRAME: u32 = 64;
@@ -138,7 +162,14 @@ impl Plugin for BattleRoundPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<BattleRoundUiState>()
             .add_systems(Startup, setup_battle_round_store)
-            .add_systems(Update, sync_battle_round_entities)
+            .add_systems(
+                Update,
+                (
+                    advance_battle_rounds,
+                    sync_battle_round_entities,
+                )
+                    .chain(),
+            )
             .add_systems(
                 EguiPrimaryContextPass,
                 battle_round_panel,
@@ -155,8 +186,19 @@ pub struct BattleRoundUiState {
     selected_add_unit: HashMap<String, String>,
     selected_action_target: HashMap<String, String>,
     selected_skill_index: HashMap<String, usize>,
+    selected_item_index: HashMap<String, usize>,
     action_amount: HashMap<String, f32>,
     confirm_next_round: HashSet<String>,
+    /// Encounters whose experience window the GM closed to settle later.
+    deferred_exp_rewards: HashSet<String>,
+    journal_requests: HashMap<String, BattleJournalRequest>,
+    manager_dirty: bool,
+}
+
+#[derive(Clone, Copy)]
+enum BattleJournalRequest {
+    Undo,
+    Redo,
 }
 
 impl BattleRoundUiState {
@@ -171,6 +213,8 @@ pub struct BattleRoundStore {
     pub active_encounter_id: Option<String>,
     #[serde(default = "default_next_encounter_index")]
     next_encounter_index: u64,
+    #[serde(skip)]
+    pub chat_action_outcomes: Vec<BattleChatActionOutcome>,
 }
 
 pub const BATTLE_ROUND_EXPORT_VERSION: u32 = 1;
@@ -206,12 +250,54 @@ pub struct BattleEncounter {
     pub negative_enabled: bool,
     #[serde(default)]
     pub round: u32,
+    /// Last round whose buff ticks have been applied; `None` until the encounter is first seen,
+    /// so older saves start from their current round instead of replaying every round.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffs_advanced_round: Option<u32>,
     #[serde(default)]
     pub combat_completed_turns: u32,
     #[serde(default)]
     pub participants: Vec<BattleParticipantSnapshot>,
     #[serde(default)]
     pub action_log: Vec<String>,
+    #[serde(default)]
+    pub level_scaling: UnitLevelScaling,
+    /// Kept in memory only; each entry holds full snapshots, too heavy for the persisted store.
+    #[serde(skip)]
+    pub journal: BattleJournal,
+    /// Chat actions wait in `pending_chat_action` for the GM instead of resolving at once.
+    #[serde(default)]
+    pub chat_action_approval: bool,
+    /// Dice expression rolled for a player's `.攻击`; attribute names use the attacker's stats.
+    #[serde(default = "default_chat_attack_damage")]
+    pub chat_attack_damage: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_chat_action: Option<BattleChatAction>,
+    #[serde(default)]
+    pub initiative_mode: BattleInitiativeMode,
+    /// Rolled initiative totals by participant id.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub initiative_rolls: HashMap<String, i32>,
+    /// Popcorn mode: who holds the turn, and who they picked to act after them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub popcorn_actor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub popcorn_next: Option<String>,
+    /// Timeline mode: extra actions each participant has already taken this round.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub timeline_actions_taken: HashMap<String, u32>,
+    /// Standee moves in the voxel scene spend each participant's per-round movement budget.
+    #[serde(default = "default_true")]
+    pub movement_limited: bool,
+    /// Ranged damage and single-target skills check the voxel scene's sight lines to the target.
+    #[serde(default = "default_true")]
+    pub cover_checks: bool,
+    /// Percent of damage partial cover takes off; 100 makes partial cover block like full cover.
+    #[serde(default = "default_partial_cover_damage_penalty")]
+    pub partial_cover_damage_penalty: f32,
+    /// Experience computed when the encounter ended, waiting for the GM to grant or decline it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_exp_rewards: Vec<BattleExpReward>,
 }
 
 impl Default for BattleEncounter {
@@ -225,11 +311,361 @@ impl Default for BattleEncounter {
             sort_by_turn: true,
             negative_enabled: false,
             round: 0,
+            buffs_advanced_round: None,
             combat_completed_turns: 0,
             participants: Vec::new(),
             action_log: Vec::new(),
+            level_scaling: UnitLevelScaling::default(),
+            journal: BattleJournal::default(),
+            chat_action_approval: false,
+            chat_attack_damage: default_chat_attack_damage(),
+            pending_chat_action: None,
+            initiative_mode: BattleInitiativeMode::default(),
+            initiative_rolls: HashMap::new(),
+            popcorn_actor: None,
+            popcorn_next: None,
+            timeline_actions_taken: HashMap::new(),
+            movement_limited: true,
+            cover_checks: true,
+            partial_cover_damage_penalty: default_partial_cover_damage_penalty(),
+            pending_exp_rewards: Vec::new(),
+        }
+    }
+}
+
+/// Oldest journal entries are dropped past this many, since each keeps two full snapshots.
+const BATTLE_JOURNAL_LIMIT: usize = 30;
+
+/// Undo/redo history of GM battle actions. Each entry keeps the state before and after the
+/// action, including the synced player characters and group clock, plus the typed events
+/// derived from the difference.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct BattleJournal {
+    #[serde(default)]
+    pub entries: Vec<BattleJournalEntry>,
+    #[serde(default)]
+    pub redo: Vec<BattleJournalEntry>,
+    #[serde(skip)]
+    pending: Option<(String, BattleStateSnapshot)>,
+}
+
+impl BattleJournal {
+    fn is_empty(&self) -> bool { self.entries.is_empty() && self.redo.is_empty() }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BattleJournalEntry {
+    pub label: String,
+    pub round: u32,
+    #[serde(default)]
+    pub events: Vec<BattleEvent>,
+    #[serde(default)]
+    pub log_lines: Vec<String>,
+    pub before: BattleStateSnapshot,
+    pub after: BattleStateSnapshot,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct BattleStateSnapshot {
+    pub round: u32,
+    pub combat_completed_turns: u32,
+    pub active: bool,
+    pub participants: Vec<BattleParticipantSnapshot>,
+    pub action_log_len: usize,
+    #[serde(default)]
+    pub characters: HashMap<String, PlayerCharacter>,
+    #[serde(default)]
+    pub world_turn: Option<u32>,
+    #[serde(default)]
+    pub player_turns: HashMap<String, TrpgPlayerTurnState>,
+    #[serde(default)]
+    pub popcorn_actor: Option<String>,
+    #[serde(default)]
+    pub popcorn_next: Option<String>,
+    #[serde(default)]
+    pub timeline_actions_taken: HashMap<String, u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BattleEvent {
+    Damage {
+        target_id: String,
+        amount: f32,
+        hp_before: f32,
+        hp_after: f32,
+    },
+    Heal {
+        target_id: String,
+        amount: f32,
+        hp_before: f32,
+        hp_after: f32,
+    },
+    BuffGranted {
+        target_id: String,
+        buff: String,
+    },
+    BuffRemoved {
+        target_id: String,
+        buff: String,
+    },
+    SkillUsed {
+        actor_id: String,
+        skill: String,
+    },
+    CooldownChanged {
+        target_id: String,
+        skill: String,
+        ready_turn: Option<u32>,
+    },
+    Defeated {
+        target_id: String,
+    },
+    Revived {
+        target_id: String,
+    },
+    ActionFinished {
+        target_id: String,
+    },
+    TurnAdvanced {
+        target_id: String,
+        from: u32,
+        to: u32,
+    },
+    RoundAdvanced {
+        from: u32,
+        to: u32,
+    },
+}
+
+impl BattleEvent {
+    pub fn describe(&self, name: impl Fn(&str) -> String) -> String {
+        match self {
+            BattleEvent::Damage {
+                target_id,
+                amount,
+                hp_before,
+                hp_after,
+            } => format!(
+                "{}受到{}点伤害（{}→{}）",
+                name(target_id),
+                format_number(*amount),
+                format_number(*hp_before),
+                format_number(*hp_after)
+            ),
+            BattleEvent::Heal {
+                target_id,
+                amount,
+                hp_before,
+                hp_after,
+            } => format!(
+                "{}恢复{}点生命（{}→{}）",
+                name(target_id),
+                format_number(*amount),
+                format_number(*hp_before),
+                format_number(*hp_after)
+            ),
+            BattleEvent::BuffGranted { target_id, buff } => {
+                format!("{}获得「{buff}」", name(target_id))
+            },
+            BattleEvent::BuffRemoved { target_id, buff } => {
+                format!("{}失去「{buff}」", name(target_id))
+            },
+            BattleEvent::SkillUsed { actor_id, skill } => {
+                format!("{}使用「{skill}」", name(actor_id))
+            },
+            BattleEvent::CooldownChanged {
+                target_id,
+                skill,
+                ready_turn: Some(turn),
+            } => format!(
+                "{}的「{skill}」冷却至第{turn}回合",
+                name(target_id)
+            ),
+            BattleEvent::CooldownChanged {
+                target_id,
+                skill,
+                ready_turn: None,
+            } => format!(
+                "{}的「{skill}」冷却结束",
+                name(target_id)
+            ),
+            BattleEvent::Defeated { target_id } => format!("{}倒下", name(target_id)),
+            BattleEvent::Revived { target_id } => format!("{}重新站起", name(target_id)),
+            BattleEvent::ActionFinished { target_id } => {
+                format!("{}完成行动", name(target_id))
+            },
+            BattleEvent::TurnAdvanced {
+                target_id,
+                from,
+                to,
+            } => format!("{}回合 {from}→{to}", name(target_id)),
+            BattleEvent::RoundAdvanced { from, to } => format!("战斗轮次 {from}→{to}"),
+        }
+    }
+}
+
+/// A `.攻击` / `.施放` a player typed in chat, held until the GM approves it when the encounter
+/// requires approval.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BattleChatAction {
+    pub actor_id: String,
+    pub target_id: String,
+    /// Index into the actor's skills; `None` is a basic attack.
+    #[serde(default)]
+    pub skill_index: Option<usize>,
+    pub summary: String,
+    /// QQ group the command was typed in; `None` for private chat.
+    #[serde(default)]
+    pub group_id: Option<u64>,
+}
+
+/// The final word on an approved or rejected chat action, waiting for NapCat to tell the player.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BattleChatActionOutcome {
+    pub actor_id: String,
+    pub group_id: Option<u64>,
+    pub resolved: bool,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BattleChatActionReply {
+    /// The action ran; carries the new battle log lines.
+    Resolved(String),
+    /// The action waits for the GM; carries its summary.
+    AwaitingApproval(String),
+}
+
+fn default_chat_attack_damage() -> String { "1d6".to_owned() }
+
+/// How an encounter decides who acts next. Every mode starts from the speed order (or name order
+/// with sorting off), which also breaks its ties.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum BattleInitiativeMode {
+    #[default]
+    Speed,
+    /// d20 plus effective speed, rolled once per participant for the whole encounter.
+    Rolled,
+    /// All player characters, then all units.
+    Sides,
+    /// The actor hands the turn to whoever they pick.
+    Popcorn,
+    /// Faster participants act up to `MAX_TIMELINE_ACTIONS_PER_ROUND` times a round, interleaved
+    /// by when each action comes due.
+    Timeline,
+}
+
+impl BattleInitiativeMode {
+    pub const ALL: [Self; 5] = [
+        Self::Speed,
+        Self::Rolled,
+        Self::Sides,
+        Self::Popcorn,
+        Self::Timeline,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Speed => "按速度",
+            Self::Rolled => "先攻掷骰",
+            Self::Sides => "阵营轮流",
+            Self::Popcorn => "点名接力",
+            Self::Timeline => "时间轴",
+        }
+    }
+}
+
+const MAX_TIMELINE_ACTIONS_PER_ROUND: u32 = 3;
+/// Slack for float drift when a path costs exactly the movement left.
+const MOVEMENT_EPSILON: f32 = 1e-3;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitLevelScalingMode {
+    #[default]
+    Template,
+    PartyAverage,
+    PartyMax,
+    TemplateOffset,
+    Curve,
+}
+
+impl UnitLevelScalingMode {
+    pub const ALL: [Self; 5] = [
+        Self::Template,
+        Self::PartyAverage,
+        Self::PartyMax,
+        Self::TemplateOffset,
+        Self::Curve,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Template => "模板等级",
+            Self::PartyAverage => "队伍平均等级",
+            Self::PartyMax => "队伍最高等级",
+            Self::TemplateOffset => "模板等级+偏移",
+            Self::Curve => "自定义曲线",
+        }
+    }
+}
+
+/// How unit templates added to an encounter pick their level. `offset` applies to the party
+/// modes and to `TemplateOffset`; `curve` maps the average party level to a unit level with
+/// linear interpolation between `[party_level, unit_level]` points.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct UnitLevelScaling {
+    #[serde(default)]
+    pub mode: UnitLevelScalingMode,
+    #[serde(default)]
+    pub offset: i32,
+    #[serde(default)]
+    pub curve: Vec<[i32; 2]>,
+}
+
+impl UnitLevelScaling {
+    pub fn target_level(&self, template_level: i32, party_levels: &[i32]) -> i32 {
+        let template_level = template_level.max(1);
+        let average = (!party_levels.is_empty()).then(|| {
+            party_levels.iter().map(|level| *level as f32).sum::<f32>() / party_levels.len() as f32
+        });
+        let level = match self.mode {
+            UnitLevelScalingMode::Template => template_level,
+            UnitLevelScalingMode::PartyAverage => average
+                .map(|average| average.round() as i32 + self.offset)
+                .unwrap_or(template_level),
+            UnitLevelScalingMode::PartyMax => party_levels
+                .iter()
+                .max()
+                .map(|level| level + self.offset)
+                .unwrap_or(template_level),
+            UnitLevelScalingMode::TemplateOffset => template_level + self.offset,
+            UnitLevelScalingMode::Curve => average
+                .and_then(|average| level_curve_value(&self.curve, average))
+                .unwrap_or(template_level),
+        };
+        level.clamp(1, 999)
+    }
+}
+
+fn level_curve_value(curve: &[[i32; 2]], party_level: f32) -> Option<i32> {
+    let mut points = curve.to_vec();
+    points.sort_by_key(|point| point[0]);
+    let first = points.first()?;
+    if party_level <= first[0] as f32 {
+        return Some(first[1]);
+    }
+    for pair in points.windows(2) {
+        let ([left_x, left_y], [right_x, right_y]) = (pair[0], pair[1]);
+        if party_level <= right_x as f32 {
+            let span = (right_x - left_x).max(1) as f32;
+            let t = (party_level - left_x as f32) / span;
+            return Some((left_y as f32 + (right_y - left_y) as f32 * t).round() as i32);
         }
     }
+    points.last().map(|point| point[1])
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -378,6 +814,15 @@ pub struct BattleParticipantSnapshot {
     #[serde(default)]
     pub damage_contributors: Vec<String>,
     #[serde(default)]
+    pub defeated_by: Vec<String>,
+    #[serde(default)]
+    pub loot_rolled: bool,
+    /// Control conditions from the participant's active buffs, refreshed on every sync.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<BuffCondition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taunted_by: Option<String>,
+    #[serde(default)]
     pub wound_healing_taken_turns: i32,
     #[serde(default)]
     pub delayed_damage_ticks: Vec<BattleDelayedDamageTick>,
@@ -391,6 +836,9 @@ pub struct BattleParticipantSnapshot {
     pub skill_last_used_turns: HashMap<String, u32>,
     #[serde(default)]
     pub skill_cooldown_ready_turns: HashMap<String, u32>,
+    /// Voxels walked in the scene this round.
+    #[serde(default)]
+    pub movement_used: f32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -484,6 +932,8 @@ fn trpg_group_campaign_id(group: &TrpgGroup) -> &str {
 
 fn default_true() -> bool { true }
 
+fn default_partial_cover_damage_penalty() -> f32 { 50.0 }
+
 fn default_combat_modifier() -> f32 { 1.0 }
 
 fn record_participant_damage_taken(
@@ -565,6 +1015,8 @@ fn set_encounter_active_state(encounter: &mut BattleEncounter, active: bool) ->
             participant.combat_turns_completed = 0;
             participant.combat_damage_taken_total = 0.0;
             participant.damage_contributors.clear();
+            participant.defeated_by.clear();
+            participant.loot_rolled = false;
             participant.arrogance_damage_source_ids.clear();
             participant.endless_pain_stacks = 0;
             participant.infinite_focus_target_id = None;
@@ -1034,6 +1486,7 @@ fn participant_defeat_outcome(
         return None;
     }
     let contributors = std::mem::take(&mut participant.damage_contributors);
+    participant.defeated_by = contributors.clone();
     Some(BattleDefeatOutcome {
         contributors,
         defeated_player_character: participant.player_character,
@@ -1287,6 +1740,280 @@ fn apply_battle_defeat_outcome(encounter: &mut BattleEncounter, outcome: BattleD
     }
 }
 
+const EXP_LEVEL_DIFFERENCE_RATE: f32 = 0.1;
+const EXP_LEVEL_DIFFERENCE_MIN_MULTIPLIER: f32 = 0.2;
+const EXP_LEVEL_DIFFERENCE_MAX_MULTIPLIER: f32 = 2.0;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BattleExpReward {
+    pub target_id: String,
+    pub display_name: String,
+    pub amount: i32,
+    pub defeated: Vec<String>,
+}
+
+fn participant_battle_level(
+    participant: &BattleParticipantSnapshot,
+    manager: &NapcatMessageManager,
+) -> i32 {
+    participant
+        .unit_character
+        .as_ref()
+        .or_else(|| manager.player_characters.get(&participant.target_id))
+        .map(|character| character.level)
+        .unwrap_or(1)
+        .max(1)
+}
+
+fn experience_level_difference_multiplier(defeated_level: i32, recipient_level: i32) -> f32 {
+    (1.0 + (defeated_level - recipient_level) as f32 * EXP_LEVEL_DIFFERENCE_RATE).clamp(
+        EXP_LEVEL_DIFFERENCE_MIN_MULTIPLIER,
+        EXP_LEVEL_DIFFERENCE_MAX_MULTIPLIER,
+    )
+}
+
+/// Experience earned from the participants defeated in an ended encounter. Each defeated
+/// participant is worth `exp_gain_per_level` (`exp_gain_per_level_pvp` for player characters)
+/// per level, adjusted by the level difference to each recipient and split evenly between the
+/// surviving player characters that damaged it.
+pub fn encounter_experience_rewards(
+    encounter: &BattleEncounter,
+    manager: &NapcatMessageManager,
+) -> Vec<BattleExpReward> {
+    let mut rewards: Vec<(BattleExpReward, f32)> = Vec::new();
+    for defeated in encounter
+        .participants
+        .iter()
+        .filter(|participant| !participant.alive && !participant.defeated_by.is_empty())
+    {
+        let recipients = encounter
+            .participants
+            .iter()
+            .filter(|participant| {
+                participant.alive
+                    && participant.player_character
+                    && participant.unit_template_id.is_none()
+                    && participant.target_id != defeated.target_id
+                    && defeated.defeated_by.contains(&participant.target_id)
+            })
+            .collect::<Vec<_>>();
+        if recipients.is_empty() {
+            continue;
+        }
+        let defeated_level = participant_battle_level(defeated, manager);
+        for recipient in &recipients {
+            let config = encounter_basic_config(encounter, manager, &recipient.target_id);
+            let exp_per_level = if defeated.player_character {
+                config.exp_gain_per_level_pvp
+            } else {
+                config.exp_gain_per_level
+            };
+            let exp_bonus = 1.0
+                + sin_on_sin_exp_bonus_percent(
+                    recipient.sin_on_sin_exp_bonus_per_stack,
+                    recipient.sin_on_sin_stacks,
+                ) / 100.0;
+            let share = exp_per_level.max(0.0)
+                * defeated_level as f32
+                * experience_level_difference_multiplier(
+                    defeated_level,
+                    participant_battle_level(recipient, manager),
+                )
+                * exp_bonus
+                / recipients.len() as f32;
+            let index = match rewards
+                .iter()
+                .position(|(reward, _)| reward.target_id == recipient.target_id)
+            {
+                Some(index) => index,
+                None => {
+                    rewards.push((
+                        BattleExpReward {
+                            target_id: recipient.target_id.clone(),
+                            display_name: recipient.display_name.clone(),
+                            amount: 0,
+                            defeated: Vec::new(),
+                        },
+                        0.0,
+                    ));
+                    rewards.len() - 1
+                },
+            };
+            rewards[index].1 += share;
+            rewards[index]
+                .0
+                .defeated
+                .push(defeated.display_name.clone());
+        }
+    }
+    rewards
+        .into_iter()
+        .filter_map(|(mut reward, total)| {
+            reward.amount = total.round() as i32;
+            (reward.amount > 0).then_some(reward)
+        })
+        .collect()
+}
+
+/// Grants GM-confirmed battle experience through the regular leveling path. Defeat records are
+/// cleared afterwards so the same kills cannot be rewarded twice.
+pub fn apply_battle_experience_rewards(
+    encounter: &mut BattleEncounter,
+    manager: &mut NapcatMessageManager,
+    rule_engine_state: &mut RuleEngineState,
+    rewards: &[BattleExpReward],
+) -> bool {
+    let skill_pool = manager.skill_pool.clone();
+    let mut granted = false;
+    for reward in rewards.iter().filter(|reward| reward.amount > 0) {
+        let stat_config = manager.character_stat_config_for_target(&reward.target_id);
+        let Some(character) = manager.player_characters.get_mut(&reward.target_id) else {
+            continue;
+        };
+        let level_ups = grant_character_experience(character, reward.amount);
+        if level_ups > 0 {
+            refresh_character_derived_stats(
+                &reward.target_id,
+                character,
+                &stat_config,
+                rule_engine_state,
+                &skill_pool,
+            );
+            encounter.action_log.push(format!(
+                "{}获得{}点战斗经验，升至{}级",
+                reward.display_name, reward.amount, character.level
+            ));
+            if let Some(participant) = encounter
+                .participants
+                .iter_mut()
+                .find(|participant| participant.target_id == reward.target_id)
+            {
+                sync_participant_from_manager(participant, manager);
+            }
+        } else {
+            encounter.action_log.push(format!(
+                "{}获得{}点战斗经验",
+                reward.display_name, reward.amount
+            ));
+        }
+        granted = true;
+    }
+    for participant in &mut encounter.participants {
+        participant.defeated_by.clear();
+    }
+    granted
+}
+
+/// Settles the encounter's pending experience. Declining also clears the defeat records, so the
+/// same kills are not offered again.
+pub fn settle_battle_experience_rewards(
+    encounter: &mut BattleEncounter,
+    manager: &mut NapcatMessageManager,
+    rule_engine_state: &mut RuleEngineState,
+    grant: bool,
+) {
+    let rewards = std::mem::take(&mut encounter.pending_exp_rewards);
+    let journaled = encounter.journal_tracks_current_state(manager);
+    if grant {
+        apply_battle_experience_rewards(
+            encounter,
+            manager,
+            rule_engine_state,
+            &rewards,
+        );
+    } else {
+        for participant in &mut encounter.participants {
+            participant.defeated_by.clear();
+        }
+    }
+    if journaled {
+        encounter.fold_follow_up_into_journal(manager);
+    }
+}
+
+/// Returns whether the encounter changed. Closing the window only puts the decision off; the
+/// rewards stay on the encounter until the GM grants or declines them.
+fn battle_experience_confirm_ui(
+    ui: &mut egui::Ui,
+    ui_state: &mut BattleRoundUiState,
+    encounter_id: &str,
+    encounter: &mut BattleEncounter,
+    manager: &mut NapcatMessageManager,
+    rule_engine_state: &mut RuleEngineState,
+) -> bool {
+    if encounter.pending_exp_rewards.is_empty() {
+        ui_state.deferred_exp_rewards.remove(encounter_id);
+        return false;
+    }
+    if ui_state.deferred_exp_rewards.contains(encounter_id) {
+        if ui.button("结算战斗经验").clicked() {
+            ui_state.deferred_exp_rewards.remove(encounter_id);
+        }
+        return false;
+    }
+    let mut open = true;
+    let mut decision = None;
+    let mut edited = false;
+    egui::Window::new(format!(
+        "战斗经验结算 · {}",
+        encounter.name
+    ))
+    .id(egui::Id::new((
+        "battle_exp_rewards",
+        encounter_id,
+    )))
+    .collapsible(false)
+    .resizable(false)
+    .open(&mut open)
+    .show(ui.ctx(), |ui| {
+        ui.label("按击败单位的等级和等级差计算，击杀与助攻的存活玩家平分。可在发放前调整数值。");
+        egui::Grid::new(("battle_exp_reward_grid", encounter_id))
+            .num_columns(3)
+            .striped(true)
+            .show(ui, |ui| {
+                for reward in encounter.pending_exp_rewards.iter_mut() {
+                    ui.label(&reward.display_name);
+                    edited |= ui
+                        .add(
+                            egui::DragValue::new(&mut reward.amount)
+                                .range(0..=999_999)
+                                .suffix(" 经验"),
+                        )
+                        .changed();
+                    ui.small(format!(
+                        "击败：{}",
+                        reward.defeated.join("、")
+                    ));
+                    ui.end_row();
+                }
+            });
+        ui.horizontal(|ui| {
+            if ui.button("发放经验").clicked() {
+                decision = Some(true);
+            }
+            if ui.button("不发放").clicked() {
+                decision = Some(false);
+            }
+        });
+    });
+    if !open {
+        ui_state
+            .deferred_exp_rewards
+            .insert(encounter_id.to_owned());
+    }
+    let Some(grant) = decision else {
+        return edited;
+    };
+    settle_battle_experience_rewards(
+        encounter,
+        manager,
+        rule_engine_state,
+        grant,
+    );
+    ui_state.manager_dirty = true;
+    true
+}
+
 fn reset_participant_turn_totals(participant: &mut BattleParticipantSnapshot) -> bool {
     let changed = participant.damage_taken_this_turn.abs() > f32::EPSILON
         || participant.healing_taken_this_turn.abs() > f32::EPSILON;
@@ -1554,6 +2281,7 @@ fn battle_store_signature(store: &BattleRoundStore) -> u64 {
         encounter.manager_sync_quarantined.hash(&mut hasher);
         encounter.active.hash(&mut hasher);
         encounter.sort_by_turn.hash(&mut hasher);
+        encounter.initiative_mode.hash(&mut hasher);
         encounter.negative_enabled.hash(&mut hasher);
         encounter.round.hash(&mut hasher);
         encounter.combat_completed_turns.hash(&mut hasher);
@@ -1710,6 +2438,12 @@ fn battle_store_signature(store: &BattleRoundStore) -> u64 {
             for contributor in &participant.damage_contributors {
                 contributor.hash(&mut hasher);
             }
+            for contributor in &participant.defeated_by {
+                contributor.hash(&mut hasher);
+            }
+            participant.loot_rolled.hash(&mut hasher);
+            participant.conditions.hash(&mut hasher);
+            participant.taunted_by.hash(&mut hasher);
             participant.wound_healing_taken_turns.hash(&mut hasher);
             for tick in &participant.delayed_damage_ticks {
                 tick.name.hash(&mut hasher);
@@ -1740,39 +2474,103 @@ fn battle_store_signature(store: &BattleRoundStore) -> u64 {
     hasher.finish()
 }
 
-fn battle_round_panel(
-    mut contexts: EguiContexts,
-    mut ui_state: ResMut<BattleRoundUiState>,
-    mut store: Option<ResMut<Persistent<BattleRoundStore>>>,
-    mut manager: Option<ResMut<Persistent<NapcatMessageManager>>>,
+/// Catches encounters up with their group clock and applies round-start buff ticks for every
+/// round an encounter advanced since the last frame, whether the panel, a chat action or the
+/// control API moved it on, so battles also tick when the app runs headless.
+fn advance_battle_rounds(
+    store: Option<ResMut<Persistent<BattleRoundStore>>>,
+    manager: Option<ResMut<Persistent<NapcatMessageManager>>>,
     mut rule_engine_state: ResMut<RuleEngineState>,
-    scene_positions: Option<Res<SceneCharacterPositions>>,
-    encounters: Query<&BattleEncounterEntity>,
 ) {
-    if !ui_state.panel_open {
-        return;
-    }
-    let Ok(ctx) = contexts.ctx_mut() else {
+    let (Some(mut store), Some(mut manager)) = (store, manager) else {
         return;
     };
-    let Some(store) = store.as_deref_mut() else {
-        return;
-    };
-    let Some(manager) = manager.as_deref_mut() else {
-        return;
-    };
-
-    let mut panel_open = ui_state.panel_open;
     let mut changed = false;
     let mut manager_changed = false;
-    let mut close_requested = false;
-
-    egui::Window::new("战斗轮")
-        .default_pos(egui::pos2(390.0, 430.0))
-        .default_width(480.0)
-        .resizable(true)
-        .open(&mut panel_open)
-        .show(ctx, |ui| {
+    let encounter_ids = store.encounters.keys().cloned().collect::<Vec<_>>();
+    for encounter_id in &encounter_ids {
+        if store.encounters[encounter_id].manager_sync_quarantined
+            || !store.encounter_is_canonical(encounter_id)
+            || !store.encounter_group_exists(encounter_id, &manager)
+        {
+            continue;
+        }
+        let round_before_catch_up = store.encounters[encounter_id].round;
+        changed |= sync_encounter_from_group_clock(&mut store, encounter_id, &manager);
+        let encounter = store
+            .encounters
+            .get_mut(encounter_id)
+            .expect("encounter ids were just collected");
+        // The group clock already ticked player buffs for rounds it drove, and the catch-up
+        // ticked unit buffs, so only rounds beyond those are left to advance here.
+        let caught_up = encounter.round - round_before_catch_up;
+        let round = encounter.round;
+        let previous_round = encounter.buffs_advanced_round.map_or(round, |advanced| {
+            advanced.saturating_add(caught_up).min(round)
+        });
+        if encounter.buffs_advanced_round != Some(round) {
+            encounter.buffs_advanced_round = Some(round);
+            changed = true;
+        }
+        if previous_round == round {
+            continue;
+        }
+        if sync_battle_round_buff_advancement(
+            &mut store,
+            encounter_id,
+            previous_round,
+            &mut manager,
+            &mut rule_engine_state,
+        ) {
+            manager_changed = true;
+            store.fold_round_advancement_into_journal(encounter_id, &manager);
+        }
+    }
+    if changed {
+        if let Err(error) = store.persist() {
+            eprintln!("failed to persist battle round advancement: {error}");
+        }
+    }
+    if manager_changed {
+        if let Err(error) = manager.persist() {
+            eprintln!("failed to persist battle round buff ticks: {error}");
+        }
+    }
+}
+
+fn battle_round_panel(
+    mut contexts: EguiContexts,
+    mut ui_state: ResMut<BattleRoundUiState>,
+    mut store: Option<ResMut<Persistent<BattleRoundStore>>>,
+    mut manager: Option<ResMut<Persistent<NapcatMessageManager>>>,
+    mut rule_engine_state: ResMut<RuleEngineState>,
+    scene_positions: Option<Res<SceneCharacterPositions>>,
+    encounters: Query<&BattleEncounterEntity>,
+) {
+    if !ui_state.panel_open {
+        return;
+    }
+    let Ok(ctx) = contexts.ctx_mut() else {
+        return;
+    };
+    let Some(store) = store.as_deref_mut() else {
+        return;
+    };
+    let Some(manager) = manager.as_deref_mut() else {
+        return;
+    };
+
+    let mut panel_open = ui_state.panel_open;
+    let mut changed = false;
+    let mut manager_changed = false;
+    let mut close_requested = false;
+
+    egui::Window::new("战斗轮")
+        .default_pos(egui::pos2(390.0, 430.0))
+        .default_width(480.0)
+        .resizable(true)
+        .open(&mut panel_open)
+        .show(ctx, |ui| {
             egui::ScrollArea::vertical()
                 .auto_shrink([false, false])
                 .show(ui, |ui| {
@@ -1804,6 +2602,7 @@ fn battle_round_panel(
                                 manager,
                             );
                         }
+                        changed |= store.commit_journal_entry(&encounter_entity.id, manager);
                         ui.add_space(6.0);
                     }
 
@@ -1815,6 +2614,7 @@ fn battle_round_panel(
         });
 
     ui_state.panel_open = panel_open && !close_requested;
+    manager_changed |= std::mem::take(&mut ui_state.manager_dirty);
     if changed {
         store.persist().ok();
     }
@@ -1823,6 +2623,61 @@ fn battle_round_panel(
     }
 }
 
+/// Advances an encounter by one round outside the panel, applying the same buff ticks and
+/// manager sync as the "下一轮" button. Returns the new round number.
+pub fn advance_encounter_round(
+    store: &mut BattleRoundStore,
+    manager: &mut NapcatMessageManager,
+    rule_engine_state: &mut RuleEngineState,
+    encounter_id: &str,
+    force: bool,
+) -> Result<u32, String> {
+    let Some(encounter) = store.encounters.get(encounter_id) else {
+        return Err(format!("战斗轮“{encounter_id}”不存在"));
+    };
+    if encounter.manager_sync_quarantined
+        || !store.encounter_group_exists(encounter_id, manager)
+        || !store.encounter_is_canonical(encounter_id)
+    {
+        return Err(format!(
+            "战斗轮“{encounter_id}”已锁定，请在战斗轮面板中处理"
+        ));
+    }
+    if !force && store.encounter_has_pending_actions(encounter_id) {
+        return Err("还有角色未完成行动".to_owned());
+    }
+    let initial_round = encounter.round;
+    store.begin_journal_entry(
+        encounter_id,
+        manager,
+        if force { "强制下一轮" } else { "下一轮" },
+    );
+    if !store.next_round(encounter_id) {
+        store.commit_journal_entry(encounter_id, manager);
+        return Err("无法进入下一轮".to_owned());
+    }
+    sync_battle_round_buff_advancement(
+        store,
+        encounter_id,
+        initial_round,
+        manager,
+        rule_engine_state,
+    );
+    if let Some(encounter) = store.encounters.get_mut(encounter_id) {
+        encounter.buffs_advanced_round = Some(encounter.round);
+    }
+    sync_encounter_to_manager(
+        store.encounters.get(encounter_id),
+        manager,
+    );
+    store.commit_journal_entry(encounter_id, manager);
+    Ok(store
+        .encounters
+        .get(encounter_id)
+        .map(|encounter| encounter.round)
+        .unwrap_or_default())
+}
+
 fn create_encounter_ui(
     ui: &mut egui::Ui,
     ui_state: &mut BattleRoundUiState,
@@ -1979,13 +2834,7 @@ fn encounter_ui(
     if let Some(encounter) = store.encounters.get_mut(encounter_id) {
         changed |= prune_unbound_group_participants(encounter, manager);
     }
-    let initial_round = store
-        .encounters
-        .get(encounter_id)
-        .map(|encounter| encounter.round)
-        .unwrap_or_default();
     let mut remove = false;
-    changed |= sync_encounter_from_group_clock(store, encounter_id, manager);
     let group_rounds_remaining = group_rounds_ahead_of_encounter(store, encounter_id, manager);
 
     ui.group(|ui| {
@@ -2021,8 +2870,16 @@ fn encounter_ui(
                     ui.small("消极已开");
                 }
                 let mut active = encounter.active;
-                if ui.checkbox(&mut active, "进行中").changed() {
-                    changed |= set_encounter_active_state(encounter, active);
+                if ui.checkbox(&mut active, "进行中").changed()
+                    && set_encounter_active_state(encounter, active)
+                {
+                    changed = true;
+                    encounter.pending_exp_rewards = if active {
+                        Vec::new()
+                    } else {
+                        encounter_experience_rewards(encounter, manager)
+                    };
+                    ui_state.deferred_exp_rewards.remove(encounter_id);
                 }
                 changed |= ui
                     .checkbox(&mut encounter.negative_enabled, "消极")
@@ -2031,6 +2888,65 @@ fn encounter_ui(
                     .checkbox(&mut encounter.sort_by_turn, "排序")
                     .on_hover_text("按速度和AGI排序行动顺序。")
                     .changed();
+                ui.label("先攻");
+                let mode_before = encounter.initiative_mode;
+                egui::ComboBox::from_id_salt(format!(
+                    "battle_initiative_{encounter_id}"
+                ))
+                .selected_text(encounter.initiative_mode.label())
+                .show_ui(ui, |ui| {
+                    for mode in BattleInitiativeMode::ALL {
+                        ui.selectable_value(
+                            &mut encounter.initiative_mode,
+                            mode,
+                            mode.label(),
+                        );
+                    }
+                });
+                changed |= encounter.initiative_mode != mode_before;
+                if encounter.initiative_mode == BattleInitiativeMode::Rolled
+                    && ui.button("重掷先攻").clicked()
+                {
+                    encounter.initiative_rolls.clear();
+                }
+                changed |= roll_missing_initiative(encounter);
+                changed |= ui
+                    .checkbox(
+                        &mut encounter.chat_action_approval,
+                        "聊天行动需批准",
+                    )
+                    .on_hover_text("玩家用 .攻击 / .施放 提交的行动要等GM批准后才结算。")
+                    .changed();
+                changed |= ui
+                    .checkbox(
+                        &mut encounter.movement_limited,
+                        "限制移动",
+                    )
+                    .on_hover_text("场景里移动立绘会消耗本轮移动力，超出的移动会被退回。")
+                    .changed();
+                changed |= ui
+                    .checkbox(&mut encounter.cover_checks, "视线掩护")
+                    .on_hover_text("远程伤害和单体技能要求场景中能看到目标；完全掩护会挡住，部分掩护降低伤害。")
+                    .changed();
+                ui.add_enabled_ui(encounter.cover_checks, |ui| {
+                    ui.label("半掩护减伤%");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut encounter.partial_cover_damage_penalty)
+                                .range(0.0..=100.0)
+                                .speed(1.0),
+                        )
+                        .on_hover_text("部分掩护时伤害降低的百分比，100 表示部分掩护也完全挡住。")
+                        .changed();
+                });
+                ui.label("攻击伤害");
+                changed |= ui
+                    .add(
+                        egui::TextEdit::singleline(&mut encounter.chat_attack_damage)
+                            .desired_width(72.0),
+                    )
+                    .on_hover_text("玩家 .攻击 时掷的伤害骰，可用属性名，如 1d6+力量。")
+                    .changed();
                 if ui.button("刷新玩家").clicked() {
                     changed |= refresh_encounter_players(encounter, manager);
                 }
@@ -2048,6 +2964,7 @@ fn encounter_ui(
             {
                 ui_state.confirm_next_round.insert(encounter_id.to_owned());
             } else {
+                store.begin_journal_entry(encounter_id, manager, "下一轮");
                 changed |= store.next_round(encounter_id);
                 ui_state.confirm_next_round.remove(encounter_id);
             }
@@ -2064,6 +2981,7 @@ fn encounter_ui(
                     ui.label("还有角色未完成行动。确定要强制进入下一轮吗？");
                     ui.horizontal(|ui| {
                         if ui.button("确认下一轮").clicked() {
+                            store.begin_journal_entry(encounter_id, manager, "强制下一轮");
                             changed |= store.next_round(encounter_id);
                             ui_state.confirm_next_round.remove(encounter_id);
                         }
@@ -2076,6 +2994,16 @@ fn encounter_ui(
                 ui_state.confirm_next_round.remove(encounter_id);
             }
         }
+        if let Some(encounter) = store.encounters.get_mut(encounter_id) {
+            changed |= battle_experience_confirm_ui(
+                ui,
+                ui_state,
+                encounter_id,
+                encounter,
+                manager,
+                rule_engine_state,
+            );
+        }
 
         changed |= encounter_roster_ui(
             ui,
@@ -2095,16 +3023,20 @@ fn encounter_ui(
             scene_positions,
         );
         ui.separator();
-        encounter_log_ui(ui, store, encounter_id);
+        encounter_log_ui(ui, ui_state, store, encounter_id);
     });
 
-    changed |= sync_battle_round_buff_advancement(
-        store,
-        encounter_id,
-        initial_round,
-        manager,
-        rule_engine_state,
-    );
+    if let Some(request) = ui_state.journal_requests.remove(encounter_id) {
+        let restored = match request {
+            BattleJournalRequest::Undo => store.undo_journal_entry(encounter_id, manager),
+            BattleJournalRequest::Redo => store.redo_journal_entry(encounter_id, manager),
+        };
+        // A refused request still clears the stale history and logs why.
+        changed = true;
+        if restored.is_some() {
+            ui_state.manager_dirty = true;
+        }
+    }
 
     if remove {
         store.encounters.remove(encounter_id);
@@ -2235,8 +3167,22 @@ fn encounter_roster_ui(
     ui.label("行动顺序");
     let order = ordered_participant_indices(encounter);
     let living_player_count = living_player_participant_count(encounter);
+    let movement_limited = encounter.movement_limited;
     for (order_index, participant_index) in order.iter().copied().enumerate() {
         let mut remove = false;
+        let initiative_note = match encounter.initiative_mode {
+            BattleInitiativeMode::Rolled => encounter
+                .initiative_rolls
+                .get(&encounter.participants[participant_index].target_id)
+                .map(|roll| format!("先攻 {roll}")),
+            BattleInitiativeMode::Timeline => Some(format!(
+                "行动 {}/{}",
+                timeline_actions_taken(encounter, participant_index)
+                    + u32::from(encounter.participants[participant_index].action_done),
+                timeline_actions_per_round(encounter, participant_index)
+            )),
+            _ => None,
+        };
         let participant = &mut encounter.participants[participant_index];
         ui.horizontal_wrapped(|ui| {
             ui.label(format!("{}.", order_index + 1));
@@ -2255,6 +3201,16 @@ fn encounter_roster_ui(
                 .text_edit_singleline(&mut participant.display_name)
                 .changed();
             ui.small(&participant.target_id);
+            if let Some(note) = &initiative_note {
+                ui.small(note);
+            }
+            if movement_limited && participant.player_character {
+                ui.small(format!(
+                    "移动 {}/{}格",
+                    format_number(participant.movement_used),
+                    format_number(participant_movement_budget(participant))
+                ));
+            }
             ui.label("速度");
             changed |= ui
                 .add(egui::DragValue::new(&mut participant.speed).speed(0.5))
@@ -2469,6 +3425,11 @@ fn encounter_roster_ui(
             if participant.action_done {
                 ui.small("已完成");
             }
+            if encounter.active {
+                for condition in &participant.conditions {
+                    ui.small(format!("【{}】", condition.label()));
+                }
+            }
             if ui.button("移除").clicked() {
                 remove = true;
             }
@@ -2555,24 +3516,93 @@ fn encounter_roster_ui(
                 let unit_id = selected.as_str();
                 if let Some(unit) = manager.unit_pool.get(unit_id) {
                     let target_id = next_unit_participant_id(encounter, unit_id);
+                    let unit = scaled_unit_for_encounter(encounter, unit, manager);
                     encounter.participants.push(participant_from_unit_template(
-                        &target_id, unit_id, unit,
+                        &target_id, unit_id, &unit,
                     ));
                     changed = true;
                 }
             }
         });
+        changed |= encounter_level_scaling_ui(ui, encounter);
+        if let Some(unit) = manager.unit_pool.get(selected.as_str()) {
+            ui.small(unit_scaling_preview(
+                unit,
+                &scaled_unit_for_encounter(encounter, unit, manager),
+            ));
+        }
     }
 
     if changed {
         normalize_encounter_after_edit(encounter);
     }
     if let Some(target_id) = completion_target {
+        let label = format!(
+            "{}标记完成",
+            participant_display_name(&target_id, manager)
+        );
+        store.begin_journal_entry(encounter_id, manager, label);
         changed |= set_roster_action_done(store, encounter_id, &target_id, true);
     }
     changed
 }
 
+fn encounter_level_scaling_ui(ui: &mut egui::Ui, encounter: &mut BattleEncounter) -> bool {
+    let mut changed = false;
+    let scaling = &mut encounter.level_scaling;
+    ui.horizontal_wrapped(|ui| {
+        ui.label("单位等级");
+        egui::ComboBox::from_id_salt(ui.next_auto_id())
+            .selected_text(scaling.mode.label())
+            .show_ui(ui, |ui| {
+                for mode in UnitLevelScalingMode::ALL {
+                    changed |= ui
+                        .selectable_value(&mut scaling.mode, mode, mode.label())
+                        .changed();
+                }
+            });
+        match scaling.mode {
+            UnitLevelScalingMode::Template => {},
+            UnitLevelScalingMode::Curve => {
+                ui.label("队伍等级→单位等级");
+                let mut remove = None;
+                for (index, point) in scaling.curve.iter_mut().enumerate() {
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut point[0]).range(1..=999))
+                        .changed();
+                    ui.label("→");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut point[1]).range(1..=999))
+                        .changed();
+                    if ui.small_button("×").clicked() {
+                        remove = Some(index);
+                    }
+                }
+                if let Some(index) = remove {
+                    scaling.curve.remove(index);
+                    changed = true;
+                }
+                if ui.small_button("＋").clicked() {
+                    let next = scaling
+                        .curve
+                        .last()
+                        .map(|point| [point[0] + 5, point[1] + 5])
+                        .unwrap_or([1, 1]);
+                    scaling.curve.push(next);
+                    changed = true;
+                }
+            },
+            _ => {
+                ui.label("偏移");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut scaling.offset).range(-99..=99))
+                    .changed();
+            },
+        }
+    });
+    changed
+}
+
 fn set_roster_action_done(
     store: &mut BattleRoundStore,
     encounter_id: &str,
@@ -2591,12 +3621,39 @@ fn encounter_action_ui(
     scene_positions: Option<&SceneCharacterPositions>,
 ) -> bool {
     let mut changed = false;
+    let pending_chat_action = store
+        .encounters
+        .get(encounter_id)
+        .and_then(|encounter| encounter.pending_chat_action.clone());
+    if let Some(action) = pending_chat_action {
+        egui::Frame::new()
+            .fill(ui.visuals().faint_bg_color)
+            .corner_radius(4)
+            .inner_margin(egui::Margin::symmetric(8, 6))
+            .show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label(format!("待批准：{}", action.summary));
+                    if ui.button("批准").clicked() {
+                        changed |=
+                            store.approve_chat_action(encounter_id, manager, scene_positions);
+                        ui_state.manager_dirty = true;
+                    }
+                    if ui.button("驳回").clicked() {
+                        changed |= store.reject_chat_action(encounter_id);
+                    }
+                });
+            });
+        if changed {
+            return true;
+        }
+    }
     let Some(encounter) = store.encounters.get(encounter_id) else {
         return false;
     };
     let Some(actor_index) = current_actor_index(encounter) else {
         ui.label("所有行动已完成。");
         if ui.button("开始下一轮").clicked() {
+            store.begin_journal_entry(encounter_id, manager, "下一轮");
             changed |= store.next_round(encounter_id);
         }
         return changed;
@@ -2632,6 +3689,45 @@ fn encounter_action_ui(
         "当前行动者：{}",
         actor.display_name
     ));
+    if encounter.initiative_mode == BattleInitiativeMode::Popcorn {
+        let candidates = encounter
+            .participants
+            .iter()
+            .filter(|participant| {
+                participant.target_id != actor.target_id && participant_can_act(participant)
+            })
+            .map(|participant| {
+                (
+                    participant.target_id.clone(),
+                    participant.display_name.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut next = encounter.popcorn_next.clone();
+        ui.horizontal_wrapped(|ui| {
+            ui.label("下一位");
+            egui::ComboBox::from_id_salt(format!("battle_popcorn_{encounter_id}"))
+                .selected_text(
+                    next.as_deref()
+                        .map(|next| display_name_for_target(&candidates, next))
+                        .unwrap_or_else(|| "按速度".to_owned()),
+                )
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut next, None, "按速度");
+                    for (target_id, name) in &candidates {
+                        ui.selectable_value(&mut next, Some(target_id.clone()), name);
+                    }
+                })
+                .response
+                .on_hover_text("由当前行动者点名，行动结束后轮到被点名的角色。");
+        });
+        if next != encounter.popcorn_next {
+            if let Some(encounter) = store.encounters.get_mut(encounter_id) {
+                encounter.popcorn_next = next;
+                changed = true;
+            }
+        }
+    }
     let target = ui_state
         .selected_action_target
         .entry(encounter_id.to_owned())
@@ -2688,6 +3784,12 @@ fn encounter_action_ui(
             )
             .clicked()
         {
+            let label = format!(
+                "{}普通攻击{}",
+                actor.display_name,
+                display_name_for_target(&target_options, target)
+            );
+            store.begin_journal_entry(encounter_id, manager, label);
             changed |= store.apply_action_and_finish(
                 encounter_id,
                 &actor.target_id,
@@ -2697,9 +3799,13 @@ fn encounter_action_ui(
             );
         }
         if ui.button("标记完成").clicked() {
+            let label = format!("{}标记完成", actor.display_name);
+            store.begin_journal_entry(encounter_id, manager, label);
             changed |= store.finish_actor_action(encounter_id, &actor.target_id);
         }
         if ui.button("跳过+消极").clicked() {
+            let label = format!("{}跳过行动", actor.display_name);
+            store.begin_journal_entry(encounter_id, manager, label);
             changed |= store.skip_negative_participant(encounter_id, &actor.target_id);
         }
     });
@@ -2769,6 +3875,11 @@ fn encounter_action_ui(
             let can_use = cooldown_remaining == 0 && can_pay && hope_avatar_allows && target_allows;
             let response = ui.add_enabled(can_use, egui::Button::new("使用技能"));
             if response.clicked() {
+                let label = format!(
+                    "{}使用技能「{}」",
+                    actor.display_name, skill.name
+                );
+                store.begin_journal_entry(encounter_id, manager, label);
                 changed |= store.record_skill_use_with_buffs_and_finish(
                     encounter_id,
                     &actor.target_id,
@@ -2797,13 +3908,136 @@ fn encounter_action_ui(
         ui.small("这个角色没有技能。");
     }
 
+    let usable_items = manager
+        .player_characters
+        .get(&actor.target_id)
+        .map(|character| {
+            character
+                .inventory
+                .items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| !item.use_rule.trim().is_empty())
+                .map(|(index, item)| {
+                    (
+                        index,
+                        format!("{} ×{}", item.name, item.stack),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if !usable_items.is_empty() {
+        let selected_item = ui_state
+            .selected_item_index
+            .entry(encounter_id.to_owned())
+            .or_insert(0);
+        if *selected_item >= usable_items.len() {
+            *selected_item = 0;
+        }
+        ui.horizontal_wrapped(|ui| {
+            ui.label("物品");
+            egui::ComboBox::from_id_salt(format!("battle_item_{encounter_id}"))
+                .selected_text(usable_items[*selected_item].1.as_str())
+                .show_ui(ui, |ui| {
+                    for (index, (_, label)) in usable_items.iter().enumerate() {
+                        ui.selectable_value(selected_item, index, label);
+                    }
+                });
+            if ui.button("使用物品").clicked() {
+                let label = format!(
+                    "{}使用物品{}",
+                    actor.display_name, usable_items[*selected_item].1
+                );
+                store.begin_journal_entry(encounter_id, manager, label);
+                changed |= store.use_item_and_finish(
+                    encounter_id,
+                    &actor.target_id,
+                    target,
+                    usable_items[*selected_item].0,
+                    manager,
+                    scene_positions,
+                );
+            }
+        });
+    }
+
     changed
 }
 
-fn encounter_log_ui(ui: &mut egui::Ui, store: &BattleRoundStore, encounter_id: &str) {
+fn encounter_log_ui(
+    ui: &mut egui::Ui,
+    ui_state: &mut BattleRoundUiState,
+    store: &BattleRoundStore,
+    encounter_id: &str,
+) {
     let Some(encounter) = store.encounters.get(encounter_id) else {
         return;
     };
+    let journal = &encounter.journal;
+    if !journal.is_empty() {
+        ui.horizontal_wrapped(|ui| {
+            let undo = journal.entries.last();
+            let response = ui.add_enabled(
+                undo.is_some(),
+                egui::Button::new("撤销"),
+            );
+            let response = match undo {
+                Some(entry) => response.on_hover_text(format!(
+                    "撤销“{}”，同时还原角色卡状态",
+                    entry.label
+                )),
+                None => response,
+            };
+            if response.clicked() {
+                ui_state.journal_requests.insert(
+                    encounter_id.to_owned(),
+                    BattleJournalRequest::Undo,
+                );
+            }
+            let redo = journal.redo.last();
+            let response = ui.add_enabled(
+                redo.is_some(),
+                egui::Button::new("重做"),
+            );
+            let response = match redo {
+                Some(entry) => response.on_hover_text(format!("重做“{}”", entry.label)),
+                None => response,
+            };
+            if response.clicked() {
+                ui_state.journal_requests.insert(
+                    encounter_id.to_owned(),
+                    BattleJournalRequest::Redo,
+                );
+            }
+            ui.small(format!(
+                "可撤销{}步 / 可重做{}步",
+                journal.entries.len(),
+                journal.redo.len()
+            ));
+        });
+        let name = |target_id: &str| {
+            encounter
+                .participants
+                .iter()
+                .find(|participant| participant.target_id == target_id)
+                .map(|participant| participant.display_name.clone())
+                .unwrap_or_else(|| target_id.to_owned())
+        };
+        egui::CollapsingHeader::new("事件记录")
+            .id_salt(("battle_journal", encounter_id))
+            .show(ui, |ui| {
+                for entry in journal.entries.iter().rev().take(10) {
+                    ui.label(format!(
+                        "第{}轮 {}",
+                        entry.round, entry.label
+                    ));
+                    for event in &entry.events {
+                        ui.small(format!("  {}", event.describe(name)));
+                    }
+                }
+            });
+    }
     if encounter.action_log.is_empty() {
         return;
     }
@@ -2813,14 +4047,524 @@ fn encounter_log_ui(ui: &mut egui::Ui, store: &BattleRoundStore, encounter_id: &
     }
 }
 
-impl BattleRoundStore {
-    pub fn to_export_json(&self) -> Result<String, String> {
-        serde_json::to_string_pretty(&BattleRoundStoreExportRef {
-            version: BATTLE_ROUND_EXPORT_VERSION,
-            export_type: "battle_rounds",
-            store: self,
-        })
-        .map_err(|err| err.to_string())
+fn capture_battle_state(
+    encounter: &BattleEncounter,
+    manager: &NapcatMessageManager,
+) -> BattleStateSnapshot {
+    let mut snapshot = BattleStateSnapshot {
+        round: encounter.round,
+        combat_completed_turns: encounter.combat_completed_turns,
+        active: encounter.active,
+        participants: encounter.participants.clone(),
+        action_log_len: encounter.action_log.len(),
+        popcorn_actor: encounter.popcorn_actor.clone(),
+        popcorn_next: encounter.popcorn_next.clone(),
+        timeline_actions_taken: encounter.timeline_actions_taken.clone(),
+        ..Default::default()
+    };
+    if encounter.manager_sync_quarantined {
+        return snapshot;
+    }
+    snapshot.characters = encounter
+        .participants
+        .iter()
+        .filter(|participant| {
+            participant.player_character && participant.unit_template_id.is_none()
+        })
+        .filter_map(|participant| {
+            manager
+                .player_characters
+                .get(&participant.target_id)
+                .map(|character| {
+                    (
+                        participant.target_id.clone(),
+                        character.clone(),
+                    )
+                })
+        })
+        .collect();
+    if let Some(group) = encounter
+        .trpg_group
+        .as_deref()
+        .and_then(|group_name| manager.trpg_groups.get(group_name))
+    {
+        snapshot.world_turn = Some(group.world_turn);
+        snapshot.player_turns = group
+            .player_turns
+            .iter()
+            .filter(|(target_id, _)| snapshot.characters.contains_key(*target_id))
+            .map(|(target_id, turn)| (target_id.clone(), turn.clone()))
+            .collect();
+    }
+    snapshot
+}
+
+/// Puts the encounter back to `snapshot`, which the journal recorded next to `current`. Synced
+/// player characters only get their battle-owned fields back; the inventory and experience are
+/// restored only when the journaled action itself changed them, such as a consumed potion, rolled
+/// loot or a folded-in battle reward.
+fn restore_battle_state(
+    encounter: &mut BattleEncounter,
+    manager: &mut NapcatMessageManager,
+    snapshot: &BattleStateSnapshot,
+    current: &BattleStateSnapshot,
+) {
+    encounter.round = snapshot.round;
+    encounter.buffs_advanced_round = Some(snapshot.round);
+    encounter.combat_completed_turns = snapshot.combat_completed_turns;
+    encounter.active = snapshot.active;
+    encounter.participants = snapshot.participants.clone();
+    encounter.popcorn_actor = snapshot.popcorn_actor.clone();
+    encounter.popcorn_next = snapshot.popcorn_next.clone();
+    encounter.timeline_actions_taken = snapshot.timeline_actions_taken.clone();
+    if encounter.manager_sync_quarantined {
+        return;
+    }
+    for (target_id, stored) in &snapshot.characters {
+        let Some(character) = manager.player_characters.get_mut(target_id) else {
+            continue;
+        };
+        restore_battle_character_fields(character, stored);
+        let inventory_changed = current.characters.get(target_id).is_some_and(|current| {
+            serde_json::to_value(&current.inventory).ok()
+                != serde_json::to_value(&stored.inventory).ok()
+        });
+        if inventory_changed {
+            character.inventory = stored.inventory.clone();
+        }
+        if current
+            .characters
+            .get(target_id)
+            .is_some_and(|current| current.level != stored.level || current.exp != stored.exp)
+        {
+            character.level = stored.level;
+            character.exp = stored.exp;
+        }
+    }
+    if let Some(group) = encounter
+        .trpg_group
+        .as_deref()
+        .and_then(|group_name| manager.trpg_groups.get_mut(group_name))
+    {
+        if let Some(world_turn) = snapshot.world_turn {
+            group.world_turn = world_turn;
+        }
+        for target_id in snapshot.characters.keys() {
+            match snapshot.player_turns.get(target_id) {
+                Some(turn) => {
+                    group.player_turns.insert(target_id.clone(), turn.clone());
+                },
+                None => {
+                    group.player_turns.remove(target_id);
+                },
+            }
+        }
+    }
+}
+
+/// The character fields battle actions and buffs write; everything else (trades, shop,
+/// equipment, creation) stays as it is.
+fn restore_battle_character_fields(character: &mut PlayerCharacter, stored: &PlayerCharacter) {
+    character.hp = stored.hp;
+    character.max_hp = stored.max_hp;
+    character.hp_regen = stored.hp_regen;
+    character.mp = stored.mp;
+    character.max_mp = stored.max_mp;
+    character.mp_regen = stored.mp_regen;
+    character.speed = stored.speed;
+    character.damage_dealt_modifier = stored.damage_dealt_modifier;
+    character.healing_dealt_modifier = stored.healing_dealt_modifier;
+    character.damage_taken_modifier = stored.damage_taken_modifier;
+    character.healing_taken_modifier = stored.healing_taken_modifier;
+    character.damage_taken_this_turn = stored.damage_taken_this_turn;
+    character.healing_taken_this_turn = stored.healing_taken_this_turn;
+    character.status = stored.status.clone();
+    character.extra_status = stored.extra_status.clone();
+    character.skill_last_cast_turns = stored.skill_last_cast_turns.clone();
+    character.skill_cooldown_ready_turns = stored.skill_cooldown_ready_turns.clone();
+    character.active_buffs = stored.active_buffs.clone();
+    character.buff_base_stats = stored.buff_base_stats.clone();
+}
+
+fn battle_states_match(left: &BattleStateSnapshot, right: &BattleStateSnapshot) -> bool {
+    let comparable = |snapshot: &BattleStateSnapshot| {
+        serde_json::to_value((
+            snapshot.round,
+            snapshot.combat_completed_turns,
+            snapshot.active,
+            &snapshot.participants,
+            &snapshot.characters,
+            snapshot.world_turn,
+            &snapshot.player_turns,
+            &snapshot.popcorn_actor,
+            &snapshot.popcorn_next,
+            &snapshot.timeline_actions_taken,
+        ))
+        .ok()
+    };
+    comparable(left) == comparable(right)
+}
+
+fn snapshot_character<'a>(
+    snapshot: &'a BattleStateSnapshot,
+    participant: &'a BattleParticipantSnapshot,
+) -> Option<&'a PlayerCharacter> {
+    participant
+        .unit_character
+        .as_ref()
+        .or_else(|| snapshot.characters.get(&participant.target_id))
+}
+
+fn snapshot_skill_name(
+    snapshot: &BattleStateSnapshot,
+    participant: &BattleParticipantSnapshot,
+    skill_key: &str,
+) -> String {
+    let Ok(index) = skill_key.parse::<usize>() else {
+        return skill_key.to_owned();
+    };
+    if index == CONSUMABLE_ITEM_SKILL_INDEX {
+        return "物品".to_owned();
+    }
+    snapshot_character(snapshot, participant)
+        .and_then(|character| character.skill_names.get(index))
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("技能{}", index + 1))
+}
+
+/// Typed events for everything that differs between two snapshots of the same encounter.
+fn battle_events_between(
+    before: &BattleStateSnapshot,
+    after: &BattleStateSnapshot,
+) -> Vec<BattleEvent> {
+    let mut events = Vec::new();
+    for participant in &after.participants {
+        let target_id = participant.target_id.clone();
+        let Some(previous) = before
+            .participants
+            .iter()
+            .find(|previous| previous.target_id == participant.target_id)
+        else {
+            continue;
+        };
+
+        let used_skills = participant
+            .skill_last_used_turns
+            .iter()
+            .filter(|(key, turn)| previous.skill_last_used_turns.get(*key) != Some(*turn))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in sorted_keys(used_skills) {
+            events.push(BattleEvent::SkillUsed {
+                actor_id: target_id.clone(),
+                skill: snapshot_skill_name(after, participant, &key),
+            });
+        }
+        let hp_change = participant.hp - previous.hp;
+        if hp_change < -f32::EPSILON {
+            events.push(BattleEvent::Damage {
+                target_id: target_id.clone(),
+                amount: -hp_change,
+                hp_before: previous.hp,
+                hp_after: participant.hp,
+            });
+        } else if hp_change > f32::EPSILON {
+            events.push(BattleEvent::Heal {
+                target_id: target_id.clone(),
+                amount: hp_change,
+                hp_before: previous.hp,
+                hp_after: participant.hp,
+            });
+        }
+
+        let buff_names = |snapshot, participant| {
+            snapshot_character(snapshot, participant)
+                .map(|character| {
+                    character
+                        .active_buffs
+                        .iter()
+                        .map(|buff| buff.name.clone())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+        let previous_buffs = buff_names(before, previous);
+        let current_buffs = buff_names(after, participant);
+        for buff in current_buffs
+            .iter()
+            .filter(|buff| !previous_buffs.contains(buff))
+        {
+            events.push(BattleEvent::BuffGranted {
+                target_id: target_id.clone(),
+                buff: buff.clone(),
+            });
+        }
+        for buff in previous_buffs
+            .iter()
+            .filter(|buff| !current_buffs.contains(buff))
+        {
+            events.push(BattleEvent::BuffRemoved {
+                target_id: target_id.clone(),
+                buff: buff.clone(),
+            });
+        }
+
+        let cooldown_keys = participant
+            .skill_cooldown_ready_turns
+            .keys()
+            .chain(previous.skill_cooldown_ready_turns.keys())
+            .filter(|key| {
+                participant.skill_cooldown_ready_turns.get(*key)
+                    != previous.skill_cooldown_ready_turns.get(*key)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in sorted_keys(cooldown_keys) {
+            events.push(BattleEvent::CooldownChanged {
+                target_id: target_id.clone(),
+                skill: snapshot_skill_name(after, participant, &key),
+                ready_turn: participant.skill_cooldown_ready_turns.get(&key).copied(),
+            });
+        }
+
+        if previous.alive && !participant.alive {
+            events.push(BattleEvent::Defeated {
+                target_id: target_id.clone(),
+            });
+        } else if !previous.alive && participant.alive {
+            events.push(BattleEvent::Revived {
+                target_id: target_id.clone(),
+            });
+        }
+        if !previous.action_done && participant.action_done {
+            events.push(BattleEvent::ActionFinished {
+                target_id: target_id.clone(),
+            });
+        }
+        if previous.turn != participant.turn {
+            events.push(BattleEvent::TurnAdvanced {
+                target_id,
+                from: previous.turn,
+                to: participant.turn,
+            });
+        }
+    }
+    if before.round != after.round {
+        events.push(BattleEvent::RoundAdvanced {
+            from: before.round,
+            to: after.round,
+        });
+    }
+    events
+}
+
+/// Whether the encounter still looks exactly like the state the next undo (or redo) starts from;
+/// otherwise something outside the journal changed it, so the whole undo/redo history is cleared
+/// and noted in the log.
+fn battle_journal_entry_applies(
+    encounter: &mut BattleEncounter,
+    manager: &NapcatMessageManager,
+    redo: bool,
+) -> bool {
+    let current = capture_battle_state(encounter, manager);
+    let expected = if redo {
+        encounter.journal.redo.last().map(|entry| &entry.before)
+    } else {
+        encounter.journal.entries.last().map(|entry| &entry.after)
+    };
+    let Some(expected) = expected else {
+        return false;
+    };
+    if battle_states_match(&current, expected) {
+        return true;
+    }
+    encounter.journal.entries.clear();
+    encounter.journal.redo.clear();
+    encounter
+        .action_log
+        .push("战斗或角色卡在记录之后有其他改动（交易、商店、装备等），撤销记录已清空".to_owned());
+    false
+}
+
+impl BattleEncounter {
+    /// Whether the encounter still sits at the latest journal entry's after-state, so a follow-up
+    /// of that action (loot rolls, battle-end experience) may be folded into the entry.
+    pub fn journal_tracks_current_state(&self, manager: &NapcatMessageManager) -> bool {
+        self.journal.pending.is_none()
+            && self.journal.entries.last().is_some_and(|entry| {
+                battle_states_match(
+                    &capture_battle_state(self, manager),
+                    &entry.after,
+                )
+            })
+    }
+
+    /// Moves the latest journal entry's after-state up to the current state, so undoing the entry
+    /// also takes back what followed from it instead of seeing a mismatch and dropping the history.
+    pub fn fold_follow_up_into_journal(&mut self, manager: &NapcatMessageManager) {
+        let after = capture_battle_state(self, manager);
+        let Some(entry) = self.journal.entries.last_mut() else {
+            return;
+        };
+        entry.events = battle_events_between(&entry.before, &after);
+        entry.log_lines = self
+            .action_log
+            .get(entry.before.action_log_len..)
+            .map(<[String]>::to_vec)
+            .unwrap_or_default();
+        entry.after = after;
+    }
+}
+
+fn sorted_keys(mut keys: Vec<String>) -> Vec<String> {
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+impl BattleRoundStore {
+    /// Captures the state an upcoming GM action may change. The entry is only recorded by
+    /// `commit_journal_entry`, which runs after the action and its manager sync; a second begin
+    /// before the commit keeps the first snapshot.
+    pub fn begin_journal_entry(
+        &mut self,
+        encounter_id: &str,
+        manager: &NapcatMessageManager,
+        label: impl Into<String>,
+    ) {
+        let Some(encounter) = self.encounters.get_mut(encounter_id) else {
+            return;
+        };
+        if encounter.journal.pending.is_some() {
+            return;
+        }
+        let before = capture_battle_state(encounter, manager);
+        encounter.journal.pending = Some((label.into(), before));
+    }
+
+    /// Records the pending entry if the action changed anything; failed actions leave no entry.
+    pub fn commit_journal_entry(
+        &mut self,
+        encounter_id: &str,
+        manager: &NapcatMessageManager,
+    ) -> bool {
+        let Some(encounter) = self.encounters.get_mut(encounter_id) else {
+            return false;
+        };
+        let Some((label, before)) = encounter.journal.pending.take() else {
+            return false;
+        };
+        let after = capture_battle_state(encounter, manager);
+        if battle_states_match(&before, &after) {
+            return false;
+        }
+        let entry = BattleJournalEntry {
+            label,
+            round: before.round,
+            events: battle_events_between(&before, &after),
+            log_lines: encounter
+                .action_log
+                .get(before.action_log_len..)
+                .map(<[String]>::to_vec)
+                .unwrap_or_default(),
+            before,
+            after,
+        };
+        let journal = &mut encounter.journal;
+        journal.entries.push(entry);
+        if journal.entries.len() > BATTLE_JOURNAL_LIMIT {
+            journal.entries.remove(0);
+        }
+        journal.redo.clear();
+        true
+    }
+
+    /// Buff ticks land a frame after the action that ended the round, so they are folded into
+    /// that entry's after-state; otherwise undoing it would see a mismatch and drop the history.
+    fn fold_round_advancement_into_journal(
+        &mut self,
+        encounter_id: &str,
+        manager: &NapcatMessageManager,
+    ) {
+        let Some(encounter) = self.encounters.get_mut(encounter_id) else {
+            return;
+        };
+        if encounter.journal.pending.is_some() {
+            return;
+        }
+        let round = encounter.round;
+        if encounter.journal.entries.last().is_some_and(|entry| {
+            entry.before.round < entry.after.round && entry.after.round == round
+        }) {
+            encounter.fold_follow_up_into_journal(manager);
+        }
+    }
+
+    /// Rolls the encounter and its synced player characters back to before the latest journal
+    /// entry. Returns the entry label, or `None` when there is nothing to undo or the state has
+    /// changed since the entry (a trade, purchase or edit outside the journal), in which case the
+    /// stale history is dropped instead of overwriting that change.
+    pub fn undo_journal_entry(
+        &mut self,
+        encounter_id: &str,
+        manager: &mut NapcatMessageManager,
+    ) -> Option<String> {
+        let encounter = self.encounters.get_mut(encounter_id)?;
+        encounter.journal.pending = None;
+        if !battle_journal_entry_applies(encounter, manager, false) {
+            return None;
+        }
+        let entry = encounter.journal.entries.pop()?;
+        restore_battle_state(
+            encounter,
+            manager,
+            &entry.before,
+            &entry.after,
+        );
+        encounter
+            .action_log
+            .push(format!("已撤销：{}", entry.label));
+        let label = entry.label.clone();
+        encounter.journal.redo.push(entry);
+        Some(label)
+    }
+
+    pub fn redo_journal_entry(
+        &mut self,
+        encounter_id: &str,
+        manager: &mut NapcatMessageManager,
+    ) -> Option<String> {
+        let encounter = self.encounters.get_mut(encounter_id)?;
+        encounter.journal.pending = None;
+        if !battle_journal_entry_applies(encounter, manager, true) {
+            return None;
+        }
+        let entry = encounter.journal.redo.pop()?;
+        restore_battle_state(
+            encounter,
+            manager,
+            &entry.after,
+            &entry.before,
+        );
+        encounter
+            .action_log
+            .push(format!("已重做：{}", entry.label));
+        let label = entry.label.clone();
+        encounter.journal.entries.push(entry);
+        Some(label)
+    }
+}
+
+impl BattleRoundStore {
+    pub fn to_export_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&BattleRoundStoreExportRef {
+            version: BATTLE_ROUND_EXPORT_VERSION,
+            export_type: "battle_rounds",
+            store: self,
+        })
+        .map_err(|err| err.to_string())
     }
 
     pub fn from_export_json(text: &str) -> Result<Self, String> {
@@ -2927,9 +4671,24 @@ impl BattleRoundStore {
                 sort_by_turn: group.battle_sort_by_turn,
                 negative_enabled: group.battle_negative_enabled,
                 round: group.world_turn,
+                buffs_advanced_round: Some(group.world_turn),
                 combat_completed_turns: 0,
                 participants,
                 action_log: Vec::new(),
+                level_scaling: UnitLevelScaling::default(),
+                journal: BattleJournal::default(),
+                chat_action_approval: false,
+                chat_attack_damage: default_chat_attack_damage(),
+                pending_chat_action: None,
+                initiative_mode: BattleInitiativeMode::default(),
+                initiative_rolls: HashMap::new(),
+                popcorn_actor: None,
+                popcorn_next: None,
+                timeline_actions_taken: HashMap::new(),
+                movement_limited: true,
+                cover_checks: true,
+                partial_cover_damage_penalty: default_partial_cover_damage_penalty(),
+                pending_exp_rewards: Vec::new(),
             });
         encounter_id
     }
@@ -3053,6 +4812,9 @@ impl BattleRoundStore {
             return false;
         }
         encounter.round = encounter.round.saturating_add(1);
+        encounter.timeline_actions_taken.clear();
+        encounter.popcorn_actor = None;
+        encounter.popcorn_next = None;
         advance_encounter_inspiration(encounter);
         let mut delayed_logs = Vec::new();
         let mut defeat_outcomes = Vec::new();
@@ -3067,6 +4829,7 @@ impl BattleRoundStore {
                 }
             }
             participant.action_done = false;
+            participant.movement_used = 0.0;
             participant.undying_rage_active = false;
             advance_participant_overhealing_shield(participant);
             let previous_damage_taken = participant.damage_taken_this_turn;
@@ -3168,16 +4931,39 @@ impl BattleRoundStore {
         let Some(encounter) = self.encounters.get_mut(encounter_id) else {
             return false;
         };
-        let Some(participant) = encounter
+        let Some(participant_index) = encounter
             .participants
-            .iter_mut()
-            .find(|participant| participant.target_id == target_id)
+            .iter()
+            .position(|participant| participant.target_id == target_id)
         else {
             return false;
         };
+        let timeline_actions_left = if encounter.initiative_mode == BattleInitiativeMode::Timeline {
+            timeline_actions_per_round(encounter, participant_index)
+                .saturating_sub(timeline_actions_taken(encounter, participant_index) + 1)
+        } else {
+            0
+        };
+        let participant = &mut encounter.participants[participant_index];
         if participant.action_done || (!allow_defeated && !participant.alive) {
             return false;
         }
+        if encounter.initiative_mode == BattleInitiativeMode::Popcorn {
+            encounter.popcorn_actor = encounter.popcorn_next.take();
+        }
+        // An extra timeline action keeps the participant's turn clock, so cooldowns and buffs
+        // still count whole turns.
+        if timeline_actions_left > 0 && participant.alive {
+            *encounter
+                .timeline_actions_taken
+                .entry(target_id.to_owned())
+                .or_default() += 1;
+            encounter.action_log.push(format!(
+                "{}本轮还能再行动{}次",
+                participant.display_name, timeline_actions_left
+            ));
+            return true;
+        }
         participant.action_done = true;
         participant.turn = participant.turn.saturating_add(1);
         if encounter.active {
@@ -3189,7 +4975,7 @@ impl BattleRoundStore {
         if encounter
             .participants
             .iter()
-            .all(|participant| !participant.alive || participant.action_done)
+            .all(|participant| !participant_can_act(participant))
         {
             let _ = self.next_round(encounter_id);
         } else if encounter.negative_enabled {
@@ -3240,6 +5026,12 @@ impl BattleRoundStore {
             return false;
         };
         let actor_name = actor.display_name.clone();
+        if actor.alive && participant_has_condition(actor, BuffCondition::Stunned) {
+            encounter.action_log.push(format!(
+                "{actor_name}处于眩晕状态，无法行动"
+            ));
+            return false;
+        }
         if !participant_can_act(actor) {
             encounter.action_log.push(format!(
                 "{}已经倒下或完成本轮行动，无法再次行动",
@@ -3255,6 +5047,13 @@ impl BattleRoundStore {
             ));
             return false;
         }
+        let target_id = match effective_battle_target(encounter, actor_id, target_id) {
+            Ok(target_id) => target_id,
+            Err(log) => {
+                encounter.action_log.push(log);
+                return false;
+            },
+        };
         let Some(target) = encounter
             .participants
             .iter_mut()
@@ -3339,6 +5138,25 @@ impl BattleRoundStore {
         else {
             return false;
         };
+        if actor_snapshot.alive {
+            let blocking_condition =
+                if participant_has_condition(&actor_snapshot, BuffCondition::Stunned) {
+                    Some(("眩晕", "无法行动"))
+                } else if skill.index != CONSUMABLE_ITEM_SKILL_INDEX
+                    && participant_has_condition(&actor_snapshot, BuffCondition::Silenced)
+                {
+                    Some(("沉默", "无法使用技能"))
+                } else {
+                    None
+                };
+            if let Some((condition, consequence)) = blocking_condition {
+                encounter.action_log.push(format!(
+                    "{}处于{condition}状态，{consequence}",
+                    actor_snapshot.display_name
+                ));
+                return false;
+            }
+        }
         if !participant_can_act(&actor_snapshot) {
             encounter.action_log.push(format!(
                 "{}已经倒下或完成本轮行动，无法使用技能",
@@ -3433,13 +5251,15 @@ impl BattleRoundStore {
             return false;
         }
         actor.mp = (actor.mp - mp_cost).max(0.0);
-        actor.skill_last_used_turns.insert(
-            skill.index.to_string(),
-            actor.turn.saturating_add(1),
-        );
-        actor
-            .skill_cooldown_ready_turns
-            .remove(&skill.index.to_string());
+        if skill.index != CONSUMABLE_ITEM_SKILL_INDEX {
+            actor.skill_last_used_turns.insert(
+                skill.index.to_string(),
+                actor.turn.saturating_add(1),
+            );
+            actor
+                .skill_cooldown_ready_turns
+                .remove(&skill.index.to_string());
+        }
 
         if effects.is_empty() {
             let note = skill.note.trim();
@@ -3500,6 +5320,50 @@ impl BattleRoundStore {
                             skill.target_class.as_deref(),
                         ),
                     );
+                    let single_target =
+                        skill_is_single_target(target, skill.target_class.as_deref());
+                    let mut cover_logs = Vec::new();
+                    let mut cover_multipliers = HashMap::new();
+                    let target_ids = target_ids
+                        .into_iter()
+                        .filter(|resolved_target_id| {
+                            let cover = battle_effect_cover(
+                                encounter,
+                                scene_positions,
+                                actor_id,
+                                resolved_target_id,
+                                single_target || damage_type == DamageType::Range,
+                            );
+                            if cover == SceneCover::None {
+                                return true;
+                            }
+                            let target_name = encounter
+                                .participants
+                                .iter()
+                                .find(|participant| &participant.target_id == resolved_target_id)
+                                .map(|participant| participant.display_name.as_str())
+                                .unwrap_or(resolved_target_id);
+                            if cover_blocks_effect(encounter, cover) {
+                                cover_logs.push(format!(
+                                    "{target_name}处于{}，{actor_name}的{}被挡住",
+                                    cover.label(),
+                                    skill.name
+                                ));
+                                return false;
+                            }
+                            cover_logs.push(format!(
+                                "{target_name}处于部分掩护，伤害降低{}%",
+                                format_number(encounter.partial_cover_damage_penalty)
+                            ));
+                            cover_multipliers.insert(
+                                resolved_target_id.clone(),
+                                cover_damage_multiplier(encounter),
+                            );
+                            true
+                        })
+                        .collect::<Vec<_>>();
+                    let blocked_by_cover = target_ids.is_empty() && !cover_logs.is_empty();
+                    encounter.action_log.extend(cover_logs);
                     let infinite_focus_target_id = if encounter.active {
                         infinite_focus_eligible_target_id(
                             target,
@@ -3510,7 +5374,7 @@ impl BattleRoundStore {
                     } else {
                         None
                     };
-                    if target_ids.is_empty() {
+                    if target_ids.is_empty() && !blocked_by_cover {
                         encounter.action_log.push(format!(
                             "{}使用{}，但范围内没有目标",
                             actor_name, skill.name
@@ -3557,8 +5421,12 @@ impl BattleRoundStore {
                         let incoming_amount = (amount
                             * actor_damage_multiplier
                             * infinite_focus_multiplier
-                            * target_damage_multiplier)
-                            .max(0.0);
+                            * target_damage_multiplier
+                            * cover_multipliers
+                                .get(&resolved_target_id)
+                                .copied()
+                                .unwrap_or(1.0))
+                        .max(0.0);
                         let target_large_hit_modifier = target_character
                             .as_ref()
                             .map(character_large_hit_damage_taken_modifier)
@@ -4160,8 +6028,517 @@ impl BattleRoundStore {
         self.finish_resolved_actor_action(encounter_id, actor_id)
     }
 
-    fn advance_participant(&mut self, encounter_id: &str, target_id: &str, resume: bool) -> bool {
-        if !self.encounter_is_canonical(encounter_id) {
+    /// Uses one unit of the actor's bag item at `item_index` through its rule text; like a skill,
+    /// this spends the actor's action.
+    pub fn use_item_and_finish(
+        &mut self,
+        encounter_id: &str,
+        actor_id: &str,
+        target_id: &str,
+        item_index: usize,
+        manager: &mut NapcatMessageManager,
+        scene_positions: Option<&SceneCharacterPositions>,
+    ) -> bool {
+        let Some(item) = manager
+            .player_characters
+            .get(actor_id)
+            .and_then(|character| character.inventory.items.get(item_index))
+            .cloned()
+        else {
+            return false;
+        };
+        let skill = consumable_item_skill(&item);
+        if static_skill_effects(
+            &skill.note,
+            &skill.arg_values,
+            None,
+            None,
+        )
+        .is_empty()
+        {
+            if let Some(encounter) = self.encounters.get_mut(encounter_id) {
+                encounter.action_log.push(format!(
+                    "【{}】的使用效果无法解析",
+                    item.name
+                ));
+            }
+            return false;
+        }
+        if !self.record_skill_use_with_buffs(
+            encounter_id,
+            actor_id,
+            target_id,
+            &skill,
+            manager,
+            scene_positions,
+        ) {
+            return false;
+        }
+        if let Some(character) = manager.player_characters.get_mut(actor_id) {
+            consume_character_item(character, item_index);
+        }
+        let finished = self.finish_resolved_actor_action(encounter_id, actor_id);
+        let _ = sync_encounter_to_manager(
+            self.encounters.get(encounter_id),
+            manager,
+        );
+        finished
+    }
+
+    /// Handles `.使用` for a player fighting in an in-progress encounter. Returns `None` when the
+    /// player is not in battle so the item can be used outside combat instead.
+    pub fn use_item_from_chat(
+        &mut self,
+        player_id: &str,
+        item_query: &str,
+        target_name: Option<&str>,
+        manager: &mut NapcatMessageManager,
+        scene_positions: Option<&SceneCharacterPositions>,
+    ) -> Option<Result<String, String>> {
+        let encounter_id = self.chat_encounter_for_player(player_id)?;
+        let encounter = self.encounters.get(&encounter_id)?;
+        if !is_current_actor(encounter, player_id) {
+            return Some(Err(
+                "还没轮到你行动，暂时不能使用物品。".to_owned(),
+            ));
+        }
+        let target_id = match target_name {
+            None => player_id.to_owned(),
+            Some(name) => match encounter.participants.iter().find(|participant| {
+                participant.display_name == name || participant.target_id == name
+            }) {
+                Some(participant) => participant.target_id.clone(),
+                None => {
+                    return Some(Err(format!(
+                        "战斗中没有名为【{name}】的目标。"
+                    )))
+                },
+            },
+        };
+        let item_index = match manager.player_characters.get(player_id) {
+            Some(character) => match usable_item_index(character, item_query) {
+                Ok(index) => index,
+                Err(err) => return Some(Err(err)),
+            },
+            None => return Some(Err("你还没有角色卡。".to_owned())),
+        };
+        let log_start = encounter.action_log.len();
+        let label = format!(
+            "{}使用{item_query}",
+            encounter
+                .participants
+                .iter()
+                .find(|participant| participant.target_id == player_id)
+                .map_or(player_id, |participant| participant
+                    .display_name
+                    .as_str())
+        );
+        self.begin_journal_entry(&encounter_id, manager, label);
+        let used = self.use_item_and_finish(
+            &encounter_id,
+            player_id,
+            &target_id,
+            item_index,
+            manager,
+            scene_positions,
+        );
+        self.commit_journal_entry(&encounter_id, manager);
+        let new_logs = self
+            .encounters
+            .get(&encounter_id)
+            .and_then(|encounter| encounter.action_log.get(log_start..))
+            .map(<[String]>::to_vec)
+            .unwrap_or_default();
+        Some(if used {
+            Ok(new_logs.join("\n"))
+        } else {
+            Err(new_logs
+                .last()
+                .cloned()
+                .unwrap_or_else(|| "现在无法使用这个物品。".to_owned()))
+        })
+    }
+
+    /// The in-progress encounter a player fights in, as seen from their chat commands.
+    fn chat_encounter_for_player(&self, player_id: &str) -> Option<String> {
+        let mut encounter_ids = self
+            .encounters
+            .iter()
+            .filter(|(_, encounter)| {
+                encounter.active
+                    && encounter
+                        .participants
+                        .iter()
+                        .any(|participant| participant.target_id == player_id)
+            })
+            .map(|(encounter_id, _)| encounter_id.clone())
+            .collect::<Vec<_>>();
+        encounter_ids.sort();
+        encounter_ids
+            .into_iter()
+            .find(|encounter_id| self.encounter_is_canonical(encounter_id))
+    }
+
+    /// TRPG group of the encounter the player is fighting in: `Some(None)` when that encounter
+    /// has no group, `None` when the player is not in battle.
+    pub fn chat_battle_group(&self, player_id: &str) -> Option<Option<&str>> {
+        let encounter_id = self.chat_encounter_for_player(player_id)?;
+        Some(self.encounters[&encounter_id].trpg_group.as_deref())
+    }
+
+    /// Handles `.攻击` (no skill) and `.施放` for the player whose turn it is. The action is
+    /// checked up front so a refused command leaves no trace in the battle log; with approval
+    /// on it waits for the GM instead of resolving. Returns `None` when the player is not in
+    /// battle.
+    pub fn chat_action_from_chat(
+        &mut self,
+        player_id: &str,
+        skill_query: Option<&str>,
+        target_name: Option<&str>,
+        group_id: Option<u64>,
+        manager: &mut NapcatMessageManager,
+        scene_positions: Option<&SceneCharacterPositions>,
+    ) -> Option<Result<BattleChatActionReply, String>> {
+        let encounter_id = self.chat_encounter_for_player(player_id)?;
+        let encounter = self.encounters.get(&encounter_id)?;
+        if !is_current_actor(encounter, player_id) {
+            return Some(Err("还没轮到你行动。".to_owned()));
+        }
+        if encounter.pending_chat_action.is_some() {
+            return Some(Err(
+                "你的上一个行动还在等待GM批准。".to_owned()
+            ));
+        }
+        let target_id = match (target_name, skill_query) {
+            (Some(name), _) => match encounter.participants.iter().find(|participant| {
+                participant.display_name == name || participant.target_id == name
+            }) {
+                Some(participant) => participant.target_id.clone(),
+                None => {
+                    return Some(Err(format!(
+                        "战斗中没有名为【{name}】的目标。"
+                    )))
+                },
+            },
+            (None, Some(_)) => player_id.to_owned(),
+            (None, None) => {
+                return Some(Err(
+                    "请用【.攻击 @目标】指定攻击目标。".to_owned()
+                ))
+            },
+        };
+        let skill_index = match skill_query {
+            Some(query) => match chat_skill_index(encounter, player_id, query, manager) {
+                Ok(index) => Some(index),
+                Err(err) => return Some(Err(err)),
+            },
+            None => None,
+        };
+        let summary = match validate_chat_action(
+            encounter,
+            player_id,
+            &target_id,
+            skill_index,
+            manager,
+            scene_positions,
+        ) {
+            Ok(summary) => summary,
+            Err(err) => return Some(Err(err)),
+        };
+        let action = BattleChatAction {
+            actor_id: player_id.to_owned(),
+            target_id,
+            skill_index,
+            summary: summary.clone(),
+            group_id,
+        };
+        if encounter.chat_action_approval {
+            let encounter = self.encounters.get_mut(&encounter_id)?;
+            encounter.pending_chat_action = Some(action);
+            return Some(Ok(
+                BattleChatActionReply::AwaitingApproval(summary),
+            ));
+        }
+        Some(
+            self.resolve_chat_action(
+                &encounter_id,
+                &action,
+                manager,
+                scene_positions,
+            )
+            .map(BattleChatActionReply::Resolved),
+        )
+    }
+
+    /// Handles `.点名` in popcorn initiative: the current actor picks who acts after them.
+    pub fn pick_popcorn_next_from_chat(
+        &mut self,
+        player_id: &str,
+        target_name: Option<&str>,
+    ) -> Option<Result<String, String>> {
+        let encounter_id = self.chat_encounter_for_player(player_id)?;
+        let encounter = self.encounters.get_mut(&encounter_id)?;
+        if encounter.initiative_mode != BattleInitiativeMode::Popcorn {
+            return Some(Err(
+                "这场战斗没有使用点名接力。".to_owned()
+            ));
+        }
+        if !is_current_actor(encounter, player_id) {
+            return Some(Err("还没轮到你行动。".to_owned()));
+        }
+        let Some(name) = target_name else {
+            return Some(Err(
+                "请用【.点名 @角色】指定下一位行动者。".to_owned(),
+            ));
+        };
+        let Some(next) = encounter
+            .participants
+            .iter()
+            .find(|participant| participant.display_name == name || participant.target_id == name)
+        else {
+            return Some(Err(format!(
+                "战斗中没有名为【{name}】的目标。"
+            )));
+        };
+        if next.target_id == player_id {
+            return Some(Err("不能点名自己。".to_owned()));
+        }
+        if !participant_can_act(next) {
+            return Some(Err(format!(
+                "{}本轮已经不能行动。",
+                next.display_name
+            )));
+        }
+        let text = format!("下一位行动者：{}", next.display_name);
+        encounter.popcorn_next = Some(next.target_id.clone());
+        encounter.action_log.push(text.clone());
+        Some(Ok(text))
+    }
+
+    /// Movement the participant has left this round, in voxels, while they fight in a running
+    /// battle that limits movement. `None` means their standee may move freely.
+    pub fn battle_movement_remaining(&self, target_id: &str) -> Option<f32> {
+        let encounter = &self.encounters[&self.chat_encounter_for_player(target_id)?];
+        if !encounter.movement_limited {
+            return None;
+        }
+        encounter
+            .participants
+            .iter()
+            .find(|participant| participant.target_id == target_id)
+            .map(participant_movement_remaining)
+    }
+
+    /// Spends `voxels` of the participant's movement, refusing moves longer than what is left.
+    /// Returns the movement remaining afterwards.
+    pub fn spend_battle_movement(&mut self, target_id: &str, voxels: f32) -> Result<f32, String> {
+        let Some(participant) = self
+            .chat_encounter_for_player(target_id)
+            .and_then(|encounter_id| self.encounters.get_mut(&encounter_id))
+            .filter(|encounter| encounter.movement_limited)
+            .and_then(|encounter| {
+                encounter
+                    .participants
+                    .iter_mut()
+                    .find(|participant| participant.target_id == target_id)
+            })
+        else {
+            return Ok(f32::INFINITY);
+        };
+        let remaining = participant_movement_remaining(participant);
+        if voxels > remaining + MOVEMENT_EPSILON {
+            return Err(format!(
+                "{}本轮只剩{}格移动，到不了那里（需要{}格）",
+                participant.display_name,
+                format_number(remaining),
+                format_number(voxels)
+            ));
+        }
+        participant.movement_used += voxels.max(0.0);
+        Ok(participant_movement_remaining(
+            participant,
+        ))
+    }
+
+    /// Whose reachable area the scene should show: the current actor of the selected battle and
+    /// the movement they have left.
+    pub fn current_battle_mover(&self) -> Option<(String, f32)> {
+        let encounter = self
+            .encounters
+            .get(self.active_encounter_id.as_deref()?)
+            .filter(|encounter| encounter.active && encounter.movement_limited)?;
+        let participant = &encounter.participants[current_actor_index(encounter)?];
+        Some((
+            participant.target_id.clone(),
+            participant_movement_remaining(participant),
+        ))
+    }
+
+    /// Current actor of the running encounter when cover checks are on, whose sight lines the
+    /// scene shows to the GM.
+    pub fn current_cover_attacker(&self) -> Option<String> {
+        let encounter = self
+            .encounters
+            .get(self.active_encounter_id.as_deref()?)
+            .filter(|encounter| encounter.active && encounter.cover_checks)?;
+        Some(
+            encounter.participants[current_actor_index(encounter)?]
+                .target_id
+                .clone(),
+        )
+    }
+
+    /// Runs the pending chat action after checking it again, since the battle may have moved on
+    /// while it waited.
+    pub fn approve_chat_action(
+        &mut self,
+        encounter_id: &str,
+        manager: &mut NapcatMessageManager,
+        scene_positions: Option<&SceneCharacterPositions>,
+    ) -> bool {
+        let Some(action) = self
+            .encounters
+            .get_mut(encounter_id)
+            .and_then(|encounter| encounter.pending_chat_action.take())
+        else {
+            return false;
+        };
+        let result = self.resolve_chat_action(
+            encounter_id,
+            &action,
+            manager,
+            scene_positions,
+        );
+        self.chat_action_outcomes.push(BattleChatActionOutcome {
+            actor_id: action.actor_id,
+            group_id: action.group_id,
+            resolved: result.is_ok(),
+            text: result.unwrap_or_else(|err| format!("行动未能执行：{err}")),
+        });
+        true
+    }
+
+    pub fn reject_chat_action(&mut self, encounter_id: &str) -> bool {
+        let Some(action) = self
+            .encounters
+            .get_mut(encounter_id)
+            .and_then(|encounter| encounter.pending_chat_action.take())
+        else {
+            return false;
+        };
+        self.chat_action_outcomes.push(BattleChatActionOutcome {
+            actor_id: action.actor_id,
+            group_id: action.group_id,
+            resolved: false,
+            text: format!("GM驳回了你的行动：{}", action.summary),
+        });
+        true
+    }
+
+    fn resolve_chat_action(
+        &mut self,
+        encounter_id: &str,
+        action: &BattleChatAction,
+        manager: &mut NapcatMessageManager,
+        scene_positions: Option<&SceneCharacterPositions>,
+    ) -> Result<String, String> {
+        let encounter = self
+            .encounters
+            .get(encounter_id)
+            .ok_or_else(|| "战斗已经不存在。".to_owned())?;
+        if !is_current_actor(encounter, &action.actor_id) {
+            return Err("已经不是你的行动回合。".to_owned());
+        }
+        validate_chat_action(
+            encounter,
+            &action.actor_id,
+            &action.target_id,
+            action.skill_index,
+            manager,
+            scene_positions,
+        )?;
+        let actor = encounter
+            .participants
+            .iter()
+            .find(|participant| participant.target_id == action.actor_id)
+            .ok_or_else(|| "你不在这场战斗中。".to_owned())?;
+        let actor_character = character_for_participant(actor, manager);
+        let attack_damage = encounter.chat_attack_damage.clone();
+        let log_start = encounter.action_log.len();
+        let mut lines = Vec::new();
+        self.begin_journal_entry(
+            encounter_id,
+            manager,
+            action.summary.clone(),
+        );
+        let resolved = match action.skill_index {
+            None => {
+                let roll = parse_dice_expression(&attack_damage).and_then(|expression| {
+                    roll_dice_expression(&expression, |name| {
+                        character_dice_attribute(actor_character.as_ref()?, name)
+                    })
+                });
+                match roll {
+                    Ok(roll) => {
+                        lines.push(format!("伤害掷骰：{}", roll.detail));
+                        self.apply_action_and_finish(
+                            encounter_id,
+                            &action.actor_id,
+                            &action.target_id,
+                            "普通攻击",
+                            roll.total.max(0) as f32,
+                        )
+                    },
+                    Err(err) => {
+                        lines.push(format!("攻击伤害掷骰无效：{err}"));
+                        false
+                    },
+                }
+            },
+            Some(index) => {
+                let skill = actor_character
+                    .as_ref()
+                    .map(character_skills)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|skill| skill.index == index);
+                skill.is_some_and(|skill| {
+                    self.record_skill_use_with_buffs_and_finish(
+                        encounter_id,
+                        &action.actor_id,
+                        &action.target_id,
+                        &skill,
+                        manager,
+                        scene_positions,
+                    )
+                })
+            },
+        };
+        let _ = sync_encounter_to_manager(
+            self.encounters.get(encounter_id),
+            manager,
+        );
+        self.commit_journal_entry(encounter_id, manager);
+        let new_logs = self
+            .encounters
+            .get(encounter_id)
+            .and_then(|encounter| encounter.action_log.get(log_start..))
+            .map(<[String]>::to_vec)
+            .unwrap_or_default();
+        if resolved {
+            lines.extend(new_logs);
+            Ok(lines.join("\n"))
+        } else {
+            Err(new_logs
+                .last()
+                .or(lines.last())
+                .cloned()
+                .unwrap_or_else(|| "现在无法执行这个行动。".to_owned()))
+        }
+    }
+
+    fn advance_participant(&mut self, encounter_id: &str, target_id: &str, resume: bool) -> bool {
+        if !self.encounter_is_canonical(encounter_id) {
             return false;
         }
         let Some(encounter) = self.encounters.get_mut(encounter_id) else {
@@ -4280,12 +6657,13 @@ fn refresh_encounter_players(
     encounter.participants.retain(|participant| {
         participant.unit_template_id.is_some() || group.players.contains(&participant.target_id)
     });
+    let config = group.basic_config;
     for participant in encounter
         .participants
         .iter_mut()
         .filter(|participant| participant.unit_template_id.is_some())
     {
-        refresh_unit_participant_from_template(participant, manager);
+        refresh_unit_participant_from_template(participant, manager, &config);
     }
     for target_id in &group.players {
         if let Some(participant) = encounter
@@ -4692,17 +7070,7 @@ fn sync_battle_round_buff_advancement(
     if encounter.round <= previous_round {
         return false;
     }
-    let canonical_round = if let Some(group_name) = encounter.trpg_group.as_deref() {
-        let Some(group) = manager.trpg_groups.get(group_name) else {
-            return false;
-        };
-        group.world_turn
-    } else {
-        previous_round
-    };
-    let rounds_to_advance = encounter
-        .round
-        .saturating_sub(canonical_round.max(previous_round));
+    let rounds_to_advance = encounter.round - previous_round;
     let player_ids = encounter
         .participants
         .iter()
@@ -4710,9 +7078,6 @@ fn sync_battle_round_buff_advancement(
         .map(|participant| participant.target_id.clone())
         .collect::<Vec<_>>();
 
-    if rounds_to_advance == 0 {
-        return sync_encounter_to_manager(Some(encounter), manager);
-    }
     let max_hp_adjustments = apply_battle_manager_max_hp_adjustments(encounter, manager);
     let _ = sync_encounter_to_manager(Some(encounter), manager);
     for _ in 0..rounds_to_advance {
@@ -5203,6 +7568,12 @@ fn encounter_participants_signature(participants: &[BattleParticipantSnapshot])
         for contributor in &participant.damage_contributors {
             contributor.hash(&mut hasher);
         }
+        for contributor in &participant.defeated_by {
+            contributor.hash(&mut hasher);
+        }
+        participant.loot_rolled.hash(&mut hasher);
+        participant.conditions.hash(&mut hasher);
+        participant.taunted_by.hash(&mut hasher);
         participant.wound_healing_taken_turns.hash(&mut hasher);
         for tick in &participant.delayed_damage_ticks {
             tick.name.hash(&mut hasher);
@@ -5325,6 +7696,10 @@ fn participant_from_character(
         penance_healing_bonus_percent: character_penance_healing_bonus_percent(character),
         penance_kill_assist_count: 0,
         damage_contributors: Vec::new(),
+        defeated_by: Vec::new(),
+        loot_rolled: false,
+        conditions: Vec::new(),
+        taunted_by: None,
         wound_healing_taken_turns: 0,
         delayed_damage_ticks: Vec::new(),
         delayed_healing_ticks: Vec::new(),
@@ -5332,35 +7707,254 @@ fn participant_from_character(
         healing_taken_this_turn: character.healing_taken_this_turn,
         skill_last_used_turns: HashMap::new(),
         skill_cooldown_ready_turns: HashMap::new(),
+        movement_used: 0.0,
     }
 }
 
-fn participant_from_unit_template(
-    target_id: &str,
-    unit_id: &str,
+/// Party levels a scaled unit is matched against: player participants first, falling back to
+/// every player in the encounter's TRPG group.
+fn encounter_party_levels(encounter: &BattleEncounter, manager: &NapcatMessageManager) -> Vec<i32> {
+    let levels = encounter
+        .participants
+        .iter()
+        .filter(|participant| participant.player_character)
+        .filter_map(|participant| manager.player_characters.get(&participant.target_id))
+        .map(|character| character.level.max(1))
+        .collect::<Vec<_>>();
+    if !levels.is_empty() {
+        return levels;
+    }
+    encounter
+        .trpg_group
+        .as_deref()
+        .and_then(|group_name| manager.trpg_groups.get(group_name))
+        .map(|group| {
+            group
+                .players
+                .iter()
+                .filter_map(|target_id| manager.player_characters.get(target_id))
+                .map(|character| character.level.max(1))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn scaled_unit_for_encounter(
+    encounter: &BattleEncounter,
     unit: &UnitPoolEntry,
-) -> BattleParticipantSnapshot {
-    let character = &unit.character;
-    let mut cooldown_character = character.clone();
-    crate::napcat::materialize_imported_skill_cooldowns(&mut cooldown_character, 0);
-    let status = character.status.combined(&character.extra_status);
-    let (speed, low_survivor_speed) = character_battle_speeds(character);
-    BattleParticipantSnapshot {
-        target_id: target_id.to_owned(),
-        display_name: unit_participant_display_name(target_id, unit_id, unit),
-        unit_template_id: Some(unit_id.to_owned()),
-        unit_character: Some(character.clone()),
-        player_character: false,
-        turn: 0,
-        combat_turns_completed: 0,
-        str_: status.str_,
-        agi: status.agi,
-        dex: status.dex,
-        int_: status.int_,
-        wis: status.wis,
-        action_done: false,
-        alive: character.hp > 0.0,
-        negative_layers: 0,
+    manager: &NapcatMessageManager,
+) -> UnitPoolEntry {
+    let level = encounter.level_scaling.target_level(
+        unit.character.level,
+        &encounter_party_levels(encounter, manager),
+    );
+    UnitPoolEntry {
+        character: scale_unit_character(
+            &unit.character,
+            level,
+            &encounter_basic_config(encounter, manager, ""),
+        ),
+        ..unit.clone()
+    }
+}
+
+const SCALED_SKILL_ARG_KEYWORDS: [&str; 10] = [
+    "伤害", "治疗", "护盾", "回复", "恢复", "吸收", "damage", "heal", "shield", "amount",
+];
+
+/// Rescales a unit template to `level`. Attributes grow with the level ratio; HP, MP, regen and
+/// speed follow the group's `TrpgBasicConfig` formulas relative to the template, so hand-tuned
+/// template values keep their proportions. Numeric skill arguments that name an amount
+/// (damage, healing, shields) grow with the level ratio too.
+pub fn scale_unit_character(
+    template: &PlayerCharacter,
+    level: i32,
+    config: &TrpgBasicConfig,
+) -> PlayerCharacter {
+    let template_level = template.level.max(1);
+    let level = level.max(1);
+    if level == template_level {
+        return template.clone();
+    }
+    let ratio = level as f32 / template_level as f32;
+    let mut character = template.clone();
+    character.level = level;
+    for value in [
+        &mut character.status.str_,
+        &mut character.status.agi,
+        &mut character.status.dex,
+        &mut character.status.vit,
+        &mut character.status.int_,
+        &mut character.status.wis,
+        &mut character.status.k,
+        &mut character.status.cha,
+    ] {
+        *value = (*value as f32 * ratio).round() as i32;
+    }
+
+    let mut template_formula = template.clone();
+    update_character_from_status_with_config(&mut template_formula, config);
+    let mut scaled_formula = character.clone();
+    update_character_from_status_with_config(&mut scaled_formula, config);
+    let rescale = |value: f32, before: f32, after: f32| {
+        if before.abs() > f32::EPSILON {
+            value * after / before
+        } else {
+            value * ratio
+        }
+    };
+    let hp_fraction = if template.max_hp > 0.0 {
+        (template.hp / template.max_hp).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+    let mp_fraction = if template.max_mp > 0.0 {
+        (template.mp / template.max_mp).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+    character.max_hp = rescale(
+        template.max_hp,
+        template_formula.max_hp,
+        scaled_formula.max_hp,
+    )
+    .max(1.0);
+    character.hp = character.max_hp * hp_fraction;
+    character.max_mp = rescale(
+        template.max_mp,
+        template_formula.max_mp,
+        scaled_formula.max_mp,
+    )
+    .max(0.0);
+    character.mp = character.max_mp * mp_fraction;
+    character.hp_regen = rescale(
+        template.hp_regen,
+        template_formula.hp_regen,
+        scaled_formula.hp_regen,
+    );
+    character.mp_regen = rescale(
+        template.mp_regen,
+        template_formula.mp_regen,
+        scaled_formula.mp_regen,
+    );
+    character.speed = rescale(
+        template.speed,
+        template_formula.speed,
+        scaled_formula.speed,
+    );
+
+    for metadata in &mut character.skill_metadata {
+        let numeric = skill_rule_args(&metadata.args).numeric_values;
+        for arg in &mut metadata.args {
+            let name = arg.name.trim();
+            let Some((_, value)) = numeric
+                .iter()
+                .find(|(numeric_name, _)| numeric_name == name)
+            else {
+                continue;
+            };
+            let lower = name.to_lowercase();
+            if SCALED_SKILL_ARG_KEYWORDS
+                .iter()
+                .any(|keyword| lower.contains(keyword))
+            {
+                arg.value = format_number((value * ratio * 10.0).round() / 10.0);
+            }
+        }
+    }
+    character
+}
+
+fn unit_scaling_preview(template: &UnitPoolEntry, scaled: &UnitPoolEntry) -> String {
+    let before = &template.character;
+    let after = &scaled.character;
+    if before.level.max(1) == after.level {
+        return format!(
+            "预览：Lv.{} 不缩放 · HP {} · MP {}",
+            after.level.max(1),
+            format_number(after.max_hp),
+            format_number(after.max_mp)
+        );
+    }
+    let stat = |label: &str, before: i32, after: i32| format!("{label} {before}→{after}");
+    format!(
+        "预览：Lv.{}→{} · HP {}→{} · MP {}→{} · 速度 {}→{} · {}",
+        before.level.max(1),
+        after.level,
+        format_number(before.max_hp),
+        format_number(after.max_hp),
+        format_number(before.max_mp),
+        format_number(after.max_mp),
+        format_number(before.speed),
+        format_number(after.speed),
+        [
+            stat(
+                "力",
+                before.status.str_,
+                after.status.str_
+            ),
+            stat(
+                "敏",
+                before.status.agi,
+                after.status.agi
+            ),
+            stat(
+                "灵",
+                before.status.dex,
+                after.status.dex
+            ),
+            stat(
+                "体",
+                before.status.vit,
+                after.status.vit
+            ),
+            stat(
+                "智",
+                before.status.int_,
+                after.status.int_
+            ),
+            stat(
+                "感",
+                before.status.wis,
+                after.status.wis
+            ),
+            stat("知", before.status.k, after.status.k),
+            stat(
+                "魅",
+                before.status.cha,
+                after.status.cha
+            ),
+        ]
+        .join(" ")
+    )
+}
+
+fn participant_from_unit_template(
+    target_id: &str,
+    unit_id: &str,
+    unit: &UnitPoolEntry,
+) -> BattleParticipantSnapshot {
+    let character = &unit.character;
+    let mut cooldown_character = character.clone();
+    crate::napcat::materialize_imported_skill_cooldowns(&mut cooldown_character, 0);
+    let status = character.status.combined(&character.extra_status);
+    let (speed, low_survivor_speed) = character_battle_speeds(character);
+    BattleParticipantSnapshot {
+        target_id: target_id.to_owned(),
+        display_name: unit_participant_display_name(target_id, unit_id, unit),
+        unit_template_id: Some(unit_id.to_owned()),
+        unit_character: Some(character.clone()),
+        player_character: false,
+        turn: 0,
+        combat_turns_completed: 0,
+        str_: status.str_,
+        agi: status.agi,
+        dex: status.dex,
+        int_: status.int_,
+        wis: status.wis,
+        action_done: false,
+        alive: character.hp > 0.0,
+        negative_layers: 0,
         pending_negative: false,
         hp: character.hp,
         max_hp: character.max_hp,
@@ -5424,6 +8018,10 @@ fn participant_from_unit_template(
         penance_healing_bonus_percent: character_penance_healing_bonus_percent(character),
         penance_kill_assist_count: 0,
         damage_contributors: Vec::new(),
+        defeated_by: Vec::new(),
+        loot_rolled: false,
+        conditions: Vec::new(),
+        taunted_by: None,
         wound_healing_taken_turns: 0,
         delayed_damage_ticks: Vec::new(),
         delayed_healing_ticks: Vec::new(),
@@ -5431,6 +8029,7 @@ fn participant_from_unit_template(
         healing_taken_this_turn: character.healing_taken_this_turn,
         skill_last_used_turns: HashMap::new(),
         skill_cooldown_ready_turns: cooldown_character.skill_cooldown_ready_turns,
+        movement_used: 0.0,
     }
 }
 
@@ -5515,6 +8114,10 @@ fn participant_from_target(
         penance_healing_bonus_percent: 0.0,
         penance_kill_assist_count: 0,
         damage_contributors: Vec::new(),
+        defeated_by: Vec::new(),
+        loot_rolled: false,
+        conditions: Vec::new(),
+        taunted_by: None,
         wound_healing_taken_turns: 0,
         delayed_damage_ticks: Vec::new(),
         delayed_healing_ticks: Vec::new(),
@@ -5522,6 +8125,7 @@ fn participant_from_target(
         healing_taken_this_turn: 0.0,
         skill_last_used_turns: HashMap::new(),
         skill_cooldown_ready_turns: HashMap::new(),
+        movement_used: 0.0,
     }
 }
 
@@ -5614,6 +8218,7 @@ fn sync_participant_from_manager(
             participant.sin_on_sin_recovery_rate = character_sin_on_sin_recovery_rate(&character);
             participant.penance_healing_bonus_percent =
                 character_penance_healing_bonus_percent(&character);
+            sync_participant_conditions(participant, &character);
             participant.hp = character.hp.clamp(0.0, participant.max_hp.max(0.0));
             participant.mp = character.mp.clamp(0.0, participant.max_mp.max(0.0));
             participant.alive = participant.hp > 0.0 || participant_hope_avatar_active(participant);
@@ -5703,6 +8308,7 @@ fn sync_participant_from_manager(
         participant.sin_on_sin_recovery_rate = character_sin_on_sin_recovery_rate(character);
         participant.penance_healing_bonus_percent =
             character_penance_healing_bonus_percent(character);
+        sync_participant_conditions(participant, character);
         participant.hp = participant.hp.min(participant.max_hp);
         participant.mp = participant.mp.min(participant.max_mp);
         participant.alive = participant.hp > 0.0 || participant_hope_avatar_active(participant);
@@ -5732,6 +8338,8 @@ fn sync_participant_from_manager(
         participant.sin_on_sin_exp_bonus_per_stack = 0.0;
         participant.sin_on_sin_recovery_rate = 0.0;
         participant.penance_healing_bonus_percent = 0.0;
+        participant.conditions.clear();
+        participant.taunted_by = None;
         participant.display_name = participant_display_name(&participant.target_id, manager);
     }
 }
@@ -5762,6 +8370,7 @@ fn sync_participant_from_manager_with_vitals(
 fn refresh_unit_participant_from_template(
     participant: &mut BattleParticipantSnapshot,
     manager: &NapcatMessageManager,
+    config: &TrpgBasicConfig,
 ) {
     let Some(unit_id) = participant.unit_template_id.as_deref() else {
         return;
@@ -5770,7 +8379,13 @@ fn refresh_unit_participant_from_template(
         return;
     };
     let current = character_for_participant(participant, manager);
-    let mut refreshed = unit.character.clone();
+    // A unit scaled on entry stays at its scaled level when the template is edited.
+    let mut refreshed = match current.as_ref().map(|current| current.level) {
+        Some(level) if level != unit.character.level.max(1) => {
+            scale_unit_character(&unit.character, level, config)
+        },
+        _ => unit.character.clone(),
+    };
     if let Some(current) = current {
         refreshed.active_buffs = current.active_buffs;
         refreshed.hp = current
@@ -5859,17 +8474,465 @@ fn ordered_participant_indices(encounter: &BattleEncounter) -> Vec<usize> {
                 .cmp(&encounter.participants[*right].display_name)
         });
     }
+    // The sorts below are stable, so the speed order above breaks their ties.
+    match encounter.initiative_mode {
+        BattleInitiativeMode::Speed => {},
+        BattleInitiativeMode::Rolled => indices.sort_by_key(|index| {
+            std::cmp::Reverse(
+                encounter
+                    .initiative_rolls
+                    .get(&encounter.participants[*index].target_id)
+                    .copied()
+                    .unwrap_or(i32::MIN),
+            )
+        }),
+        BattleInitiativeMode::Sides => {
+            indices.sort_by_key(|index| !encounter.participants[*index].player_character)
+        },
+        BattleInitiativeMode::Popcorn => {
+            let picked = encounter.popcorn_actor.as_deref().and_then(|next| {
+                indices.iter().position(|index| {
+                    let participant = &encounter.participants[*index];
+                    participant.target_id == next && participant_can_act(participant)
+                })
+            });
+            if let Some(position) = picked {
+                let index = indices.remove(position);
+                indices.insert(0, index);
+            }
+        },
+        BattleInitiativeMode::Timeline => {
+            let due = |index: &usize| {
+                let taken = timeline_actions_taken(encounter, *index);
+                (
+                    (taken + 1) as f32 / timeline_actions_per_round(encounter, *index) as f32,
+                    taken,
+                )
+            };
+            indices.sort_by(|left, right| {
+                let (left_due, left_taken) = due(left);
+                let (right_due, right_taken) = due(right);
+                left_due
+                    .total_cmp(&right_due)
+                    .then(left_taken.cmp(&right_taken))
+            });
+        },
+    }
     indices
 }
 
+fn timeline_actions_taken(encounter: &BattleEncounter, index: usize) -> u32 {
+    encounter
+        .timeline_actions_taken
+        .get(&encounter.participants[index].target_id)
+        .copied()
+        .unwrap_or(0)
+}
+
+/// How many times the participant acts per round on the timeline: once per multiple of the
+/// slowest living participant's speed.
+fn timeline_actions_per_round(encounter: &BattleEncounter, index: usize) -> u32 {
+    let living_player_count = living_player_participant_count(encounter);
+    let order_speed = |participant: &BattleParticipantSnapshot| {
+        participant_order_speed(
+            participant,
+            living_player_count,
+            encounter.active,
+        )
+    };
+    let slowest = encounter
+        .participants
+        .iter()
+        .filter(|participant| participant.alive)
+        .map(order_speed)
+        .filter(|speed| *speed > 0.0)
+        .min_by(f32::total_cmp);
+    let speed = order_speed(&encounter.participants[index]);
+    match slowest {
+        Some(slowest) if speed > 0.0 => {
+            ((speed / slowest).floor() as u32).clamp(1, MAX_TIMELINE_ACTIONS_PER_ROUND)
+        },
+        _ => 1,
+    }
+}
+
+/// Rolls d20 plus effective speed for participants that have no initiative yet. Returns whether
+/// anyone rolled.
+fn roll_missing_initiative(encounter: &mut BattleEncounter) -> bool {
+    roll_missing_initiative_with(encounter, || {
+        rand::rng().random_range(1..=20)
+    })
+}
+
+fn roll_missing_initiative_with(
+    encounter: &mut BattleEncounter,
+    mut roll_d20: impl FnMut() -> i32,
+) -> bool {
+    if encounter.initiative_mode != BattleInitiativeMode::Rolled {
+        return false;
+    }
+    let living_player_count = living_player_participant_count(encounter);
+    let mut rolled = false;
+    for participant in &encounter.participants {
+        if encounter
+            .initiative_rolls
+            .contains_key(&participant.target_id)
+        {
+            continue;
+        }
+        let die = roll_d20();
+        let modifier = participant_order_speed(
+            participant,
+            living_player_count,
+            encounter.active,
+        )
+        .floor() as i32;
+        let total = die + modifier;
+        encounter
+            .initiative_rolls
+            .insert(participant.target_id.clone(), total);
+        encounter.action_log.push(format!(
+            "{}先攻：d20={die}+{modifier}={total}",
+            participant.display_name
+        ));
+        rolled = true;
+    }
+    rolled
+}
+
 fn current_actor_index(encounter: &BattleEncounter) -> Option<usize> {
     ordered_participant_indices(encounter)
         .into_iter()
         .find(|index| participant_can_act(&encounter.participants[*index]))
 }
 
+fn is_current_actor(encounter: &BattleEncounter, target_id: &str) -> bool {
+    current_actor_index(encounter)
+        .is_some_and(|index| encounter.participants[index].target_id == target_id)
+}
+
+/// Finds the actor's skill by name or 1-based number.
+fn chat_skill_index(
+    encounter: &BattleEncounter,
+    actor_id: &str,
+    query: &str,
+    manager: &NapcatMessageManager,
+) -> Result<usize, String> {
+    let query = query.trim();
+    let skills = encounter
+        .participants
+        .iter()
+        .find(|participant| participant.target_id == actor_id)
+        .and_then(|actor| character_for_participant(actor, manager))
+        .map(|character| character_skills(&character))
+        .unwrap_or_default();
+    skills
+        .iter()
+        .find(|skill| skill.name == query)
+        .or_else(|| {
+            query
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| skills.get(number.checked_sub(1)?))
+        })
+        .map(|skill| skill.index)
+        .ok_or_else(|| format!("你没有名为【{query}】的技能。"))
+}
+
+/// The same checks the GM panel makes before enabling an action, phrased for the player. Returns
+/// a summary of the action as it would resolve, after taunt redirection.
+fn validate_chat_action(
+    encounter: &BattleEncounter,
+    actor_id: &str,
+    target_id: &str,
+    skill_index: Option<usize>,
+    manager: &NapcatMessageManager,
+    scene_positions: Option<&SceneCharacterPositions>,
+) -> Result<String, String> {
+    let find = |id: &str| {
+        encounter
+            .participants
+            .iter()
+            .find(|participant| participant.target_id == id)
+    };
+    let actor = find(actor_id).ok_or_else(|| "你不在这场战斗中。".to_owned())?;
+    if participant_has_condition(actor, BuffCondition::Stunned) {
+        return Err(format!(
+            "{}处于眩晕状态，无法行动",
+            actor.display_name
+        ));
+    }
+    if !participant_can_act(actor) {
+        return Err(format!(
+            "{}已经倒下或完成本轮行动",
+            actor.display_name
+        ));
+    }
+    let target = find(target_id).ok_or_else(|| "战斗中没有这个目标。".to_owned())?;
+    let Some(skill_index) = skill_index else {
+        if encounter.active && participant_hope_avatar_active(actor) {
+            return Err(format!(
+                "{}处于希望化身，只能释放治疗技能",
+                actor.display_name
+            ));
+        }
+        let target = effective_battle_target(encounter, actor_id, target_id)
+            .map(|id| find(&id).unwrap_or(target))?;
+        if !target.alive {
+            return Err(format!(
+                "{}已经倒下，不能成为普通攻击目标",
+                target.display_name
+            ));
+        }
+        return Ok(format!(
+            "{}普通攻击{}",
+            actor.display_name, target.display_name
+        ));
+    };
+    let actor_character = character_for_participant(actor, manager);
+    let skill = actor_character
+        .as_ref()
+        .map(character_skills)
+        .unwrap_or_default()
+        .into_iter()
+        .find(|skill| skill.index == skill_index)
+        .ok_or_else(|| "找不到这个技能。".to_owned())?;
+    if participant_has_condition(actor, BuffCondition::Silenced) {
+        return Err(format!(
+            "{}处于沉默状态，无法使用技能",
+            actor.display_name
+        ));
+    }
+    let cooldown_remaining = skill_cooldown_remaining(
+        actor,
+        skill.index,
+        skill.cooldown_turns,
+        skill.cooldown_left,
+    );
+    if cooldown_remaining > 0 {
+        return Err(format!(
+            "「{}」冷却还剩{cooldown_remaining}轮",
+            skill.name
+        ));
+    }
+    let mp_cost = skill.mp_cost.max(0.0);
+    if actor.mp + f32::EPSILON < mp_cost {
+        return Err(format!(
+            "「{}」需要{} MP，当前只有{} MP",
+            skill.name,
+            format_number(mp_cost),
+            format_number(actor.mp)
+        ));
+    }
+    let effects = static_skill_effects(
+        &skill.note,
+        &skill.arg_values,
+        skill.skill_type.as_deref(),
+        skill.legacy_buff_machine_json.as_deref(),
+    );
+    if encounter.active
+        && participant_hope_avatar_active(actor)
+        && !skill_effects_are_hope_avatar_healing(&effects)
+    {
+        return Err(format!(
+            "{}处于希望化身，只能释放治疗技能",
+            actor.display_name
+        ));
+    }
+    if !skill_effects_allow_selected_target(
+        &effects,
+        skill.target_class.as_deref(),
+        Some(target.alive),
+    ) {
+        return Err(format!(
+            "不能对{}使用「{}」；倒下目标只能接受单目标治疗",
+            target.display_name, skill.name
+        ));
+    }
+    let targets_someone = skill_target_limit(
+        skill.target_count,
+        skill.target_class.as_deref(),
+    ) == Some(0)
+        || effects.is_empty()
+        || effects.iter().any(|effect| {
+            let (target, fallback_radius, defeated_target_policy) = match effect {
+                SkillEffect::Damage {
+                    target,
+                    damage_type,
+                    ..
+                } => (
+                    *target,
+                    battle_skill_damage_range_radius(
+                        skill.range,
+                        actor_character.as_ref(),
+                        *damage_type,
+                        skill.skill_type.as_deref(),
+                    ),
+                    DefeatedTargetPolicy::Exclude,
+                ),
+                SkillEffect::Heal { target, .. } => (
+                    *target,
+                    skill_range_radius(skill.range),
+                    DefeatedTargetPolicy::AllowSingleTarget,
+                ),
+                SkillEffect::GrantBuff { target, .. } => (
+                    *target,
+                    skill_range_radius(skill.range),
+                    DefeatedTargetPolicy::Exclude,
+                ),
+            };
+            !resolve_skill_targets(
+                target,
+                actor_id,
+                target_id,
+                encounter,
+                scene_positions,
+                fallback_radius,
+                skill.target_class.as_deref(),
+                defeated_target_policy,
+            )
+            .is_empty()
+        });
+    if !targets_someone {
+        return Err(
+            effective_battle_target(encounter, actor_id, target_id)
+                .err()
+                .unwrap_or_else(|| {
+                    format!(
+                        "「{}」没有可以命中的目标，可能超出了射程",
+                        skill.name
+                    )
+                }),
+        );
+    }
+    let blocking_cover = effects.iter().find_map(|effect| {
+        let SkillEffect::Damage {
+            target,
+            damage_type,
+            ..
+        } = effect
+        else {
+            return None;
+        };
+        let cover = battle_effect_cover(
+            encounter,
+            scene_positions,
+            actor_id,
+            target_id,
+            skill_is_single_target(*target, skill.target_class.as_deref())
+                || *damage_type == DamageType::Range,
+        );
+        cover_blocks_effect(encounter, cover).then_some(cover)
+    });
+    if let Some(cover) = blocking_cover {
+        return Err(format!(
+            "{}处于{}，「{}」打不到",
+            target.display_name,
+            cover.label(),
+            skill.name
+        ));
+    }
+    Ok(if target_id == actor_id {
+        format!(
+            "{}施放「{}」",
+            actor.display_name, skill.name
+        )
+    } else {
+        format!(
+            "{}对{}施放「{}」",
+            actor.display_name, target.display_name, skill.name
+        )
+    })
+}
+
+/// Stunned participants count as having nothing to do this round: the round can end without them
+/// and `next_round` still advances their turn.
 fn participant_can_act(participant: &BattleParticipantSnapshot) -> bool {
-    participant.alive && !participant.action_done
+    participant.alive
+        && !participant.action_done
+        && !participant_has_condition(participant, BuffCondition::Stunned)
+}
+
+/// Voxels a participant may walk per round: the same speed-plus-bonus distance possession allows,
+/// measured in voxels. Rooted or stunned participants cannot move.
+pub fn participant_movement_budget(participant: &BattleParticipantSnapshot) -> f32 {
+    if !participant.alive
+        || participant_has_condition(participant, BuffCondition::Rooted)
+        || participant_has_condition(participant, BuffCondition::Stunned)
+    {
+        return 0.0;
+    }
+    (participant.speed.max(0.0) + DEFAULT_POSSESSION_MOVEMENT_BONUS) / VOXEL_SIZE
+}
+
+fn participant_movement_remaining(participant: &BattleParticipantSnapshot) -> f32 {
+    (participant_movement_budget(participant) - participant.movement_used).max(0.0)
+}
+
+fn participant_has_condition(
+    participant: &BattleParticipantSnapshot,
+    condition: BuffCondition,
+) -> bool {
+    participant.conditions.contains(&condition)
+}
+
+fn sync_participant_conditions(
+    participant: &mut BattleParticipantSnapshot,
+    character: &PlayerCharacter,
+) {
+    participant.conditions = buff_conditions(&character.active_buffs);
+    participant.taunted_by = buff_condition_source(
+        &character.active_buffs,
+        BuffCondition::Taunted,
+    )
+    .filter(|source_id| *source_id != participant.target_id)
+    .map(str::to_owned);
+}
+
+fn participants_hostile(
+    left: &BattleParticipantSnapshot,
+    right: &BattleParticipantSnapshot,
+) -> bool {
+    left.player_character != right.player_character
+}
+
+/// The participant a single-target action really lands on. A taunted actor's hostile pick is
+/// redirected to the living taunter, and nobody but the participant itself can pick an invisible
+/// one. The error is the action log line explaining the refusal.
+fn effective_battle_target(
+    encounter: &BattleEncounter,
+    actor_id: &str,
+    selected_target_id: &str,
+) -> Result<String, String> {
+    let find = |target_id: &str| {
+        encounter
+            .participants
+            .iter()
+            .find(|participant| participant.target_id == target_id)
+    };
+    let Some(selected) = find(selected_target_id) else {
+        return Ok(selected_target_id.to_owned());
+    };
+    if selected.target_id == actor_id {
+        return Ok(selected.target_id.clone());
+    }
+    if let Some(actor) = find(actor_id) {
+        let taunter = actor
+            .taunted_by
+            .as_deref()
+            .and_then(find)
+            .filter(|taunter| taunter.alive);
+        if let Some(taunter) = taunter.filter(|_| participants_hostile(actor, selected)) {
+            return Ok(taunter.target_id.clone());
+        }
+    }
+    if participant_has_condition(selected, BuffCondition::Invisible) {
+        return Err(format!(
+            "{}处于隐身状态，无法被选为目标",
+            selected.display_name
+        ));
+    }
+    Ok(selected.target_id.clone())
 }
 
 fn normalize_encounter_after_edit(encounter: &mut BattleEncounter) {
@@ -6023,6 +9086,23 @@ fn character_for_participant(
     Some(character)
 }
 
+fn consumable_item_skill(item: &InventoryItem) -> CharacterSkill {
+    CharacterSkill {
+        index: CONSUMABLE_ITEM_SKILL_INDEX,
+        name: item.name.trim().to_owned(),
+        note: item.use_rule.clone(),
+        skill_type: None,
+        legacy_buff_machine_json: None,
+        mp_cost: 0.0,
+        cooldown_turns: 0,
+        cooldown_left: None,
+        target_count: None,
+        target_class: None,
+        range: None,
+        arg_values: SkillRuleArgs::default(),
+    }
+}
+
 fn character_skills(character: &PlayerCharacter) -> Vec<CharacterSkill> {
     character
         .skill_names
@@ -6425,6 +9505,9 @@ fn resolve_skill_targets(
                 .participants
                 .iter()
                 .filter(|participant| participant.alive && participant.target_id != actor_id)
+                .filter(|participant| {
+                    !participant_has_condition(participant, BuffCondition::Invisible)
+                })
                 .map(|participant| participant.target_id.clone())
                 .collect();
         };
@@ -6438,6 +9521,7 @@ fn resolve_skill_targets(
             .participants
             .iter()
             .filter(|participant| participant.alive && participant.target_id != actor_id)
+            .filter(|participant| !participant_has_condition(participant, BuffCondition::Invisible))
             .filter(|participant| {
                 positions
                     .positions
@@ -6449,13 +9533,15 @@ fn resolve_skill_targets(
             .collect();
     }
 
-    let targets = match target.actor {
-        ActorRef::SelfActor => vec![actor_id.to_owned()],
-        ActorRef::Source | ActorRef::Target => vec![selected_target_id.to_owned()],
-    };
     if matches!(target.actor, ActorRef::SelfActor) {
-        targets
+        vec![actor_id.to_owned()]
     } else {
+        let Ok(selected_target_id) =
+            effective_battle_target(encounter, actor_id, selected_target_id)
+        else {
+            return Vec::new();
+        };
+        let targets = vec![selected_target_id.clone()];
         let Some(selected_target) = encounter
             .participants
             .iter()
@@ -6666,6 +9752,41 @@ fn skill_target_limit(target_count: Option<u32>, target_class: Option<&str>) ->
     }
 }
 
+fn skill_is_single_target(target: TargetSelector, target_class: Option<&str>) -> bool {
+    target.area.is_none()
+        && !skill_target_class_is_area(target_class)
+        && !matches!(target.actor, ActorRef::SelfActor)
+}
+
+/// Scene cover between actor and target when it matters: ranged damage and single-target effects
+/// need a clear sight line, while other area effects reach around walls.
+fn battle_effect_cover(
+    encounter: &BattleEncounter,
+    scene_positions: Option<&SceneCharacterPositions>,
+    actor_id: &str,
+    target_id: &str,
+    needs_sight_line: bool,
+) -> SceneCover {
+    if !encounter.cover_checks || !needs_sight_line || actor_id == target_id {
+        return SceneCover::None;
+    }
+    scene_positions
+        .map(|positions| positions.cover_between(actor_id, target_id))
+        .unwrap_or_default()
+}
+
+fn cover_blocks_effect(encounter: &BattleEncounter, cover: SceneCover) -> bool {
+    match cover {
+        SceneCover::None => false,
+        SceneCover::Partial => encounter.partial_cover_damage_penalty >= 100.0,
+        SceneCover::Full => true,
+    }
+}
+
+fn cover_damage_multiplier(encounter: &BattleEncounter) -> f32 {
+    (1.0 - encounter.partial_cover_damage_penalty / 100.0).clamp(0.0, 1.0)
+}
+
 fn skill_target_class_is_area(target_class: Option<&str>) -> bool {
     matches!(
         target_class.map(str::trim),
@@ -6766,6 +9887,7 @@ mod area_tests {
                     Vec3::new(3.1, 0.0, 0.0),
                 ),
             ]),
+            cover: HashMap::new(),
         };
 
         let targets = resolve_skill_targets(
@@ -6809,6 +9931,7 @@ mod area_tests {
                     Vec3::new(3.1, 0.0, 0.0),
                 ),
             ]),
+            cover: HashMap::new(),
         };
 
         let targets = resolve_skill_targets(
@@ -6828,6 +9951,65 @@ mod area_tests {
         assert_eq!(targets, vec!["near".to_owned()]);
     }
 
+    #[test]
+    fn single_target_skills_follow_taunt_and_skip_invisible_participants() {
+        let mut actor = battle_participant("actor");
+        actor.player_character = true;
+        actor.taunted_by = Some("guard".to_owned());
+        let mut ally = battle_participant("ally");
+        ally.player_character = true;
+        let mut hidden = battle_participant("hidden");
+        hidden.conditions = vec![BuffCondition::Invisible];
+        let mut encounter = BattleEncounter {
+            participants: vec![
+                actor,
+                ally,
+                battle_participant("guard"),
+                battle_participant("other"),
+                hidden,
+            ],
+            ..default()
+        };
+        let single = |selected: &str, encounter: &BattleEncounter| {
+            resolve_skill_targets(
+                TargetSelector::single(ActorRef::Target),
+                "actor",
+                selected,
+                encounter,
+                None,
+                None,
+                None,
+                DefeatedTargetPolicy::Exclude,
+            )
+        };
+
+        assert_eq!(single("other", &encounter), vec![
+            "guard".to_owned()
+        ]);
+        assert_eq!(single("ally", &encounter), vec![
+            "ally".to_owned()
+        ]);
+        encounter.participants[0].taunted_by = None;
+        assert!(single("hidden", &encounter).is_empty());
+        let area = resolve_skill_targets(
+            TargetSelector {
+                actor: ActorRef::Target,
+                area: Some(AreaSelector {
+                    radius_meters: None,
+                }),
+            },
+            "actor",
+            "other",
+            &encounter,
+            None,
+            None,
+            None,
+            DefeatedTargetPolicy::Exclude,
+        );
+        assert!(!area.contains(&"hidden".to_owned()));
+        assert!(area.contains(&"other".to_owned()));
+    }
+
     fn battle_participant(target_id: &str) -> BattleParticipantSnapshot {
         BattleParticipantSnapshot {
             target_id: target_id.to_owned(),
@@ -6902,6 +10084,10 @@ mod area_tests {
             penance_healing_bonus_percent: 0.0,
             penance_kill_assist_count: 0,
             damage_contributors: Vec::new(),
+            defeated_by: Vec::new(),
+            loot_rolled: false,
+            conditions: Vec::new(),
+            taunted_by: None,
             wound_healing_taken_turns: 0,
             delayed_damage_ticks: Vec::new(),
             delayed_healing_ticks: Vec::new(),
@@ -6909,6 +10095,7 @@ mod area_tests {
             healing_taken_this_turn: 0.0,
             skill_last_used_turns: HashMap::new(),
             skill_cooldown_ready_turns: HashMap::new(),
+            movement_used: 0.0,
         }
     }
 }
@@ -6916,6 +10103,7 @@ mod area_tests {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::napcat::UnitLootTable;
 
     fn empty_manager() -> NapcatMessageManager {
         NapcatMessageManager {
@@ -6935,6 +10123,7 @@ mod tests {
             skill_pool: Vec::new(),
             item_pool: Vec::new(),
             unit_pool: HashMap::default(),
+            chat_target_accounts: HashMap::default(),
         }
     }
 
@@ -7012,6 +10201,10 @@ mod tests {
             penance_healing_bonus_percent: 0.0,
             penance_kill_assist_count: 0,
             damage_contributors: Vec::new(),
+            defeated_by: Vec::new(),
+            loot_rolled: false,
+            conditions: Vec::new(),
+            taunted_by: None,
             wound_healing_taken_turns: 0,
             delayed_damage_ticks: Vec::new(),
             delayed_healing_ticks: Vec::new(),
@@ -7019,6 +10212,7 @@ mod tests {
             healing_taken_this_turn: 0.0,
             skill_last_used_turns: HashMap::new(),
             skill_cooldown_ready_turns: HashMap::new(),
+            movement_used: 0.0,
         }
     }
 
@@ -7042,11 +10236,14 @@ mod tests {
                     combat_completed_turns: 13,
                     participants: vec![actor],
                     action_log: vec!["a使用技能".to_owned()],
+                    initiative_mode: BattleInitiativeMode::Rolled,
+                    initiative_rolls: HashMap::from([("a".to_owned(), 17)]),
                     ..Default::default()
                 })],
             ),
             active_encounter_id: Some("battle-4".to_owned()),
             next_encounter_index: 9,
+            chat_action_outcomes: Vec::new(),
         };
         let json = store.to_export_json().unwrap();
         let restored = BattleRoundStore::from_export_json(&json).unwrap();
@@ -7065,6 +10262,11 @@ mod tests {
         assert!(encounter.manager_sync_quarantined);
         assert_eq!(encounter.round, 7);
         assert_eq!(encounter.combat_completed_turns, 13);
+        assert_eq!(
+            encounter.initiative_mode,
+            BattleInitiativeMode::Rolled
+        );
+        assert_eq!(encounter.initiative_rolls["a"], 17);
         assert_eq!(encounter.action_log, vec![
             "a使用技能".to_owned()
         ]);
@@ -7136,6 +10338,7 @@ mod tests {
             })]),
             active_encounter_id: Some("missing".to_owned()),
             next_encounter_index: 2,
+            chat_action_outcomes: Vec::new(),
         };
 
         let restored =
@@ -8242,6 +11445,153 @@ mod tests {
         );
     }
 
+    #[test]
+    fn unit_level_scaling_modes_pick_levels_from_the_party() {
+        let party = [3, 5, 10];
+        let mut scaling = UnitLevelScaling {
+            mode: UnitLevelScalingMode::PartyAverage,
+            offset: 1,
+            curve: vec![[10, 20], [1, 1]],
+        };
+        assert_eq!(scaling.target_level(2, &party), 7);
+        scaling.mode = UnitLevelScalingMode::PartyMax;
+        assert_eq!(scaling.target_level(2, &party), 11);
+        scaling.mode = UnitLevelScalingMode::TemplateOffset;
+        assert_eq!(scaling.target_level(2, &party), 3);
+        scaling.mode = UnitLevelScalingMode::Curve;
+        assert_eq!(scaling.target_level(2, &party), 12);
+        assert_eq!(scaling.target_level(2, &[30]), 20);
+        scaling.mode = UnitLevelScalingMode::PartyAverage;
+        assert_eq!(scaling.target_level(2, &[]), 2);
+    }
+
+    #[test]
+    fn scaled_unit_follows_config_formulas_and_skill_amounts() {
+        let config = TrpgBasicConfig::default();
+        let mut template = PlayerCharacter {
+            level: 2,
+            status: crate::napcat::CharacterStatus {
+                str_: 4,
+                vit: 6,
+                int_: 2,
+                wis: 2,
+                k: 3,
+                cha: 5,
+                ..Default::default()
+            },
+            skill_metadata: vec![crate::napcat::CharacterSkillMetadata {
+                args: vec![
+                    crate::napcat::SkillPoolArg {
+                        name: "伤害值".to_owned(),
+                        kind: "数字".to_owned(),
+                        value: "4".to_owned(),
+                    },
+                    crate::napcat::SkillPoolArg {
+                        name: "范围".to_owned(),
+                        kind: "数字".to_owned(),
+                        value: "3".to_owned(),
+                    },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        update_character_from_status_with_config(&mut template, &config);
+        template.max_hp *= 2.0;
+        template.hp = template.max_hp / 2.0;
+
+        let scaled = scale_unit_character(&template, 4, &config);
+        let mut expected = scaled.clone();
+        update_character_from_status_with_config(&mut expected, &config);
+
+        assert_eq!(scaled.level, 4);
+        assert_eq!(scaled.status.str_, 8);
+        assert_eq!(scaled.status.vit, 12);
+        assert_eq!(scaled.status.k, 6);
+        assert_eq!(scaled.status.cha, 10);
+        let preview = unit_scaling_preview(
+            &UnitPoolEntry {
+                character: template.clone(),
+                ..Default::default()
+            },
+            &UnitPoolEntry {
+                character: scaled.clone(),
+                ..Default::default()
+            },
+        );
+        assert!(preview.contains("知 3→6"));
+        assert!(preview.contains("魅 5→10"));
+        assert!((scaled.max_hp - expected.max_hp * 2.0).abs() < 0.001);
+        assert!((scaled.hp - scaled.max_hp / 2.0).abs() < 0.001);
+        assert!((scaled.max_mp - expected.max_mp).abs() < 0.001);
+        let args = &scaled.skill_metadata[0].args;
+        assert_eq!(args[0].value, "8");
+        assert_eq!(args[1].value, "3");
+        assert_eq!(
+            scale_unit_character(&template, 2, &config).max_hp,
+            template.max_hp
+        );
+    }
+
+    #[test]
+    fn scaled_units_keep_their_level_when_the_roster_refreshes() {
+        let mut manager = empty_manager();
+        let unit = UnitPoolEntry {
+            label: "狼".to_owned(),
+            character: PlayerCharacter {
+                level: 1,
+                max_hp: 10.0,
+                hp: 10.0,
+                status: crate::napcat::CharacterStatus {
+                    str_: 2,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        manager.unit_pool.insert("wolf".to_owned(), unit.clone());
+        manager
+            .player_characters
+            .insert("10001".to_owned(), PlayerCharacter {
+                level: 3,
+                ..Default::default()
+            });
+        manager.trpg_groups.insert("table".to_owned(), TrpgGroup {
+            players: vec!["10001".to_owned()],
+            ..Default::default()
+        });
+        let mut encounter = BattleEncounter {
+            trpg_group: Some("table".to_owned()),
+            level_scaling: UnitLevelScaling {
+                mode: UnitLevelScalingMode::PartyMax,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let scaled = scaled_unit_for_encounter(&encounter, &unit, &manager);
+        assert_eq!(scaled.character.level, 3);
+        assert_eq!(scaled.character.status.str_, 6);
+        encounter.participants.push(participant_from_unit_template(
+            "unit:wolf",
+            "wolf",
+            &scaled,
+        ));
+
+        refresh_encounter_players(&mut encounter, &manager);
+
+        let wolf = encounter
+            .participants
+            .iter()
+            .find(|participant| participant.target_id == "unit:wolf")
+            .unwrap();
+        assert_eq!(
+            wolf.unit_character.as_ref().unwrap().level,
+            3
+        );
+        assert_eq!(wolf.str_, 6);
+    }
+
     #[test]
     fn unit_template_participant_uses_template_stats_and_skills() {
         let mut manager = empty_manager();
@@ -8264,6 +11614,7 @@ mod tests {
                 skill_cooldown_turns: vec![2],
                 ..Default::default()
             },
+            loot: UnitLootTable::default(),
         };
         manager.unit_pool.insert("slime".to_owned(), unit.clone());
 
@@ -8308,6 +11659,7 @@ mod tests {
                 max_hp: 20.0,
                 ..Default::default()
             },
+            loot: UnitLootTable::default(),
         };
         manager.unit_pool.insert("slime".to_owned(), unit.clone());
         let mut store = BattleRoundStore::default();
@@ -8508,6 +11860,7 @@ mod tests {
                 max_hp: 20.0,
                 ..Default::default()
             },
+            loot: UnitLootTable::default(),
         };
         manager.unit_pool.insert("slime".to_owned(), unit.clone());
         let mut target = participant_from_unit_template("unit:slime", "slime", &unit);
@@ -8531,6 +11884,7 @@ mod tests {
                     amount: 4.0,
                     damage_type: DamageType::Magical,
                 }],
+                conditions: Vec::new(),
             });
         sync_participant_from_manager(&mut target, &manager);
         let mut store = BattleRoundStore::default();
@@ -8586,6 +11940,7 @@ mod tests {
                 max_hp: 20.0,
                 ..Default::default()
             },
+            loot: UnitLootTable::default(),
         };
         manager.unit_pool.insert("slime".to_owned(), unit.clone());
         let mut participant = participant_from_unit_template("unit:slime", "slime", &unit);
@@ -8606,6 +11961,7 @@ mod tests {
                     value: BuffValue::Add(5.0),
                 }],
                 tick_actions: Vec::new(),
+                conditions: Vec::new(),
             });
 
         sync_participant_from_manager(&mut participant, &manager);
@@ -8659,6 +12015,7 @@ mod tests {
                 },
                 ..Default::default()
             },
+            loot: UnitLootTable::default(),
         });
         let unit = manager.unit_pool["slime"].clone();
         let mut encounter = BattleEncounter {
@@ -12313,6 +15670,7 @@ mod tests {
                 beneficial: true,
                 effects: Vec::new(),
                 tick_actions: vec![BuffTickAction::Heal { amount: 20.0 }],
+                conditions: Vec::new(),
             }],
             ..Default::default()
         };
@@ -13429,6 +16787,7 @@ mod tests {
                     Vec3::new(10.0, 0.0, 0.0),
                 ),
             ]),
+            cover: HashMap::new(),
         };
         assert!(store.record_skill_use(
             "battle",
@@ -14139,6 +17498,7 @@ mod tests {
                 ("b".to_owned(), Vec3::new(2.9, 0.0, 0.0)),
                 ("c".to_owned(), Vec3::new(3.1, 0.0, 0.0)),
             ]),
+            cover: HashMap::new(),
         };
         let skill = CharacterSkill {
             index: 0,
@@ -14197,6 +17557,7 @@ mod tests {
                 ("a".to_owned(), Vec3::ZERO),
                 ("b".to_owned(), Vec3::new(3.1, 0.0, 0.0)),
             ]),
+            cover: HashMap::new(),
         };
         let skill = CharacterSkill {
             index: 0,
@@ -14274,6 +17635,7 @@ mod tests {
                     Vec3::new(20.0, 0.0, 0.0),
                 ),
             ]),
+            cover: HashMap::new(),
         };
 
         assert!(store.record_skill_use(
@@ -14336,6 +17698,7 @@ mod tests {
                     Vec3::new(10.4, 0.0, 0.0),
                 ),
             ]),
+            cover: HashMap::new(),
         };
 
         assert!(store.record_skill_use(
@@ -14810,4 +18173,990 @@ mod tests {
         assert!(revived.alive);
         assert_eq!(encounter.round, 0);
     }
+
+    #[test]
+    fn consumable_item_use_spends_a_stack_and_the_action_without_cooldown() {
+        let mut manager = empty_manager();
+        let mut character = PlayerCharacter::default();
+        crate::napcat::add_item_to_inventory(
+            &mut character.inventory,
+            InventoryItem {
+                name: "治疗药水".to_owned(),
+                stack: 2,
+                max_stack: 20,
+                use_rule: "主动使用对目标回复5点生命值".to_owned(),
+                ..Default::default()
+            },
+        );
+        manager
+            .player_characters
+            .insert("actor".to_owned(), character);
+        let mut actor = participant("actor", 0);
+        actor.hp = 4.0;
+        let mut store = BattleRoundStore::default();
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                active: true,
+                participants: vec![actor, participant("ally", 0)],
+                ..Default::default()
+            });
+
+        assert!(store.use_item_and_finish(
+            "battle",
+            "actor",
+            "actor",
+            0,
+            &mut manager,
+            None,
+        ));
+        let actor = &store.encounters["battle"].participants[0];
+        assert_eq!(actor.hp, 9.0);
+        assert!(actor.action_done);
+        assert!(actor.skill_last_used_turns.is_empty());
+        assert_eq!(
+            manager.player_characters["actor"].inventory.items[0].stack,
+            1
+        );
+
+        let reply = store
+            .use_item_from_chat(
+                "actor",
+                "治疗药水",
+                None,
+                &mut manager,
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            reply,
+            Err("还没轮到你行动，暂时不能使用物品。".to_owned())
+        );
+        assert!(store
+            .use_item_from_chat(
+                "stranger",
+                "治疗药水",
+                None,
+                &mut manager,
+                None
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn battle_end_experience_splits_between_surviving_contributors() {
+        let mut manager = empty_manager();
+        for (id, level) in [("a", 4), ("b", 6), ("c", 6)] {
+            manager
+                .player_characters
+                .insert(id.to_owned(), PlayerCharacter {
+                    level,
+                    ..Default::default()
+                });
+        }
+        let mut wolf = participant("wolf", 0);
+        wolf.unit_template_id = Some("wolf".to_owned());
+        wolf.unit_character = Some(PlayerCharacter {
+            level: 6,
+            ..Default::default()
+        });
+        for (source, amount) in [("a", 4.0), ("b", 3.0), ("c", 3.0)] {
+            apply_participant_damage_for_battle(&mut wolf, amount, source, true);
+        }
+        assert!(!wolf.alive);
+        let mut fallen = participant("c", 0);
+        fallen.player_character = true;
+        fallen.alive = false;
+        let mut encounter = BattleEncounter {
+            participants: vec![
+                BattleParticipantSnapshot {
+                    player_character: true,
+                    ..participant("a", 0)
+                },
+                BattleParticipantSnapshot {
+                    player_character: true,
+                    ..participant("b", 0)
+                },
+                fallen,
+                wolf,
+            ],
+            ..Default::default()
+        };
+        assert!(set_encounter_active_state(
+            &mut encounter,
+            false
+        ));
+
+        let rewards = encounter_experience_rewards(&encounter, &manager);
+        assert_eq!(
+            rewards
+                .iter()
+                .map(|reward| (reward.target_id.as_str(), reward.amount))
+                .collect::<Vec<_>>(),
+            vec![("a", 11), ("b", 9)]
+        );
+        assert_eq!(rewards[0].defeated, vec![
+            "wolf".to_owned()
+        ]);
+
+        let mut rule_engine_state = RuleEngineState::default();
+        assert!(apply_battle_experience_rewards(
+            &mut encounter,
+            &mut manager,
+            &mut rule_engine_state,
+            &rewards,
+        ));
+        assert_eq!(manager.player_characters["a"].exp, 11);
+        assert_eq!(manager.player_characters["b"].exp, 9);
+        assert_eq!(manager.player_characters["c"].exp, 0);
+        assert!(encounter
+            .action_log
+            .iter()
+            .any(|entry| entry == "a获得11点战斗经验"));
+        assert!(encounter_experience_rewards(&encounter, &manager).is_empty());
+    }
+
+    #[test]
+    fn pending_experience_survives_a_restart_until_the_gm_declines_it() {
+        let mut manager = empty_manager();
+        manager
+            .player_characters
+            .insert("a".to_owned(), PlayerCharacter {
+                level: 3,
+                ..Default::default()
+            });
+        let mut wolf = participant("wolf", 0);
+        wolf.unit_template_id = Some("wolf".to_owned());
+        apply_participant_damage_for_battle(&mut wolf, 20.0, "a", true);
+        let mut encounter = BattleEncounter {
+            participants: vec![
+                BattleParticipantSnapshot {
+                    player_character: true,
+                    ..participant("a", 0)
+                },
+                wolf,
+            ],
+            ..Default::default()
+        };
+        assert!(set_encounter_active_state(
+            &mut encounter,
+            false
+        ));
+        encounter.pending_exp_rewards = encounter_experience_rewards(&encounter, &manager);
+        assert_eq!(encounter.pending_exp_rewards.len(), 1);
+
+        let store = BattleRoundStore {
+            encounters: HashMap::from([("battle".to_owned(), encounter)]),
+            ..Default::default()
+        };
+        let saved = serde_json::to_string(&store).unwrap();
+        let mut restored = serde_json::from_str::<BattleRoundStore>(&saved).unwrap();
+        let encounter = restored.encounters.get_mut("battle").unwrap();
+        assert_eq!(
+            encounter.pending_exp_rewards[0].target_id,
+            "a"
+        );
+
+        settle_battle_experience_rewards(
+            encounter,
+            &mut manager,
+            &mut RuleEngineState::default(),
+            false,
+        );
+        assert!(encounter.pending_exp_rewards.is_empty());
+        assert_eq!(manager.player_characters["a"].exp, 0);
+        assert!(encounter_experience_rewards(encounter, &manager).is_empty());
+    }
+
+    #[test]
+    fn player_kills_use_pvp_experience_and_reset_on_new_combat() {
+        let mut manager = empty_manager();
+        for id in ["a", "b"] {
+            manager
+                .player_characters
+                .insert(id.to_owned(), PlayerCharacter {
+                    level: 10,
+                    ..Default::default()
+                });
+        }
+        let mut victim = participant("b", 0);
+        victim.player_character = true;
+        apply_participant_damage_for_battle(&mut victim, 20.0, "a", true);
+        let mut encounter = BattleEncounter {
+            participants: vec![
+                BattleParticipantSnapshot {
+                    player_character: true,
+                    ..participant("a", 0)
+                },
+                victim,
+            ],
+            ..Default::default()
+        };
+
+        let rewards = encounter_experience_rewards(&encounter, &manager);
+        assert_eq!(rewards.len(), 1);
+        assert_eq!(rewards[0].amount, 2);
+
+        assert!(set_encounter_active_state(
+            &mut encounter,
+            false
+        ));
+        assert!(set_encounter_active_state(
+            &mut encounter,
+            true
+        ));
+        assert!(encounter_experience_rewards(&encounter, &manager).is_empty());
+    }
+
+    #[test]
+    fn stunned_participant_is_skipped_and_the_round_completes_without_them() {
+        let mut stunned = participant("a", 0);
+        stunned.conditions = vec![BuffCondition::Stunned];
+        let mut store = BattleRoundStore::default();
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                name: "battle".to_owned(),
+                participants: vec![stunned, participant("b", 0)],
+                ..Default::default()
+            });
+
+        let encounter = &store.encounters["battle"];
+        assert_eq!(
+            current_actor_index(encounter)
+                .map(|index| encounter.participants[index].target_id.as_str()),
+            Some("b")
+        );
+        assert!(!store.apply_action("battle", "a", "b", "普通攻击", 3.0));
+        assert_eq!(
+            store.encounters["battle"]
+                .action_log
+                .last()
+                .map(String::as_str),
+            Some("a处于眩晕状态，无法行动")
+        );
+
+        assert!(store.finish_actor_action("battle", "b"));
+        let encounter = &store.encounters["battle"];
+        assert_eq!(encounter.round, 1);
+        assert_eq!(encounter.participants[0].turn, 1);
+    }
+
+    #[test]
+    fn silenced_participant_cannot_cast_skills_but_can_attack() {
+        let manager = empty_manager();
+        let mut silenced = participant("a", 0);
+        silenced.conditions = vec![BuffCondition::Silenced];
+        let mut target = participant("b", 0);
+        target.hp = 10.0;
+        target.max_hp = 20.0;
+        let mut store = BattleRoundStore::default();
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                name: "battle".to_owned(),
+                participants: vec![silenced, target],
+                ..Default::default()
+            });
+        let strike = CharacterSkill {
+            index: 0,
+            name: "重击".to_owned(),
+            note: "主动使用对目标造成2点伤害".to_owned(),
+            skill_type: None,
+            legacy_buff_machine_json: None,
+            mp_cost: 0.0,
+            cooldown_turns: 0,
+            cooldown_left: None,
+            target_count: None,
+            target_class: None,
+            range: None,
+            arg_values: SkillRuleArgs::default(),
+        };
+
+        assert!(!store.record_skill_use("battle", "a", "b", &strike, &manager, None));
+        assert_eq!(
+            store.encounters["battle"]
+                .action_log
+                .last()
+                .map(String::as_str),
+            Some("a处于沉默状态，无法使用技能")
+        );
+        assert!(store.apply_action("battle", "a", "b", "普通攻击", 3.0));
+        assert_eq!(
+            store.encounters["battle"].participants[1].hp,
+            7.0
+        );
+    }
+
+    #[test]
+    fn journal_undo_and_redo_restore_encounter_and_synced_character() {
+        let mut manager = empty_manager();
+        manager
+            .player_characters
+            .insert("a".to_owned(), PlayerCharacter {
+                hp: 10.0,
+                max_hp: 10.0,
+                ..Default::default()
+            });
+        manager.trpg_groups.insert("party".to_owned(), TrpgGroup {
+            players: vec!["a".to_owned()],
+            ..Default::default()
+        });
+        let mut player = participant("a", 0);
+        player.player_character = true;
+        let mut store = BattleRoundStore::default();
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                trpg_group: Some("party".to_owned()),
+                participants: vec![participant("enemy", 0), player],
+                ..Default::default()
+            });
+
+        store.begin_journal_entry("battle", &manager, "enemy攻击a");
+        assert!(store.apply_action_and_finish("battle", "enemy", "a", "攻击", 4.0));
+        sync_encounter_to_manager(
+            store.encounters.get("battle"),
+            &mut manager,
+        );
+        assert!(store.commit_journal_entry("battle", &manager));
+        assert_eq!(manager.player_characters["a"].hp, 6.0);
+
+        let entry = &store.encounters["battle"].journal.entries[0];
+        assert_eq!(entry.label, "enemy攻击a");
+        assert!(
+            entry.events.contains(&BattleEvent::Damage {
+                target_id: "a".to_owned(),
+                amount: 4.0,
+                hp_before: 10.0,
+                hp_after: 6.0,
+            })
+        );
+        assert!(
+            entry.events.contains(&BattleEvent::ActionFinished {
+                target_id: "enemy".to_owned(),
+            })
+        );
+
+        store.begin_journal_entry("battle", &manager, "无变化");
+        assert!(!store.commit_journal_entry("battle", &manager));
+        assert_eq!(
+            store.encounters["battle"].journal.entries.len(),
+            1
+        );
+
+        assert_eq!(
+            store.undo_journal_entry("battle", &mut manager).as_deref(),
+            Some("enemy攻击a")
+        );
+        let encounter = &store.encounters["battle"];
+        assert_eq!(encounter.participants[1].hp, 10.0);
+        assert!(!encounter.participants[0].action_done);
+        assert_eq!(manager.player_characters["a"].hp, 10.0);
+        assert!(encounter.journal.entries.is_empty());
+        assert_eq!(
+            encounter.action_log.last().map(String::as_str),
+            Some("已撤销：enemy攻击a")
+        );
+
+        assert!(store.redo_journal_entry("battle", &mut manager).is_some());
+        assert_eq!(
+            store.encounters["battle"].participants[1].hp,
+            6.0
+        );
+        assert_eq!(manager.player_characters["a"].hp, 6.0);
+        assert!(store.redo_journal_entry("battle", &mut manager).is_none());
+
+        // Loot delivered after the action must not be rolled back by a stale undo.
+        manager.player_characters.get_mut("a").unwrap().exp += 5;
+        assert!(store.undo_journal_entry("battle", &mut manager).is_none());
+        let encounter = &store.encounters["battle"];
+        assert!(encounter.journal.is_empty());
+        assert!(encounter
+            .action_log
+            .last()
+            .is_some_and(|line| line.contains("撤销记录已清空")));
+        assert_eq!(manager.player_characters["a"].hp, 6.0);
+        assert_eq!(manager.player_characters["a"].exp, 5);
+    }
+
+    #[test]
+    fn chat_item_use_is_journaled_and_undo_only_restores_battle_fields() {
+        let mut manager = empty_manager();
+        let mut character = PlayerCharacter {
+            hp: 4.0,
+            max_hp: 10.0,
+            ..Default::default()
+        };
+        crate::napcat::add_item_to_inventory(
+            &mut character.inventory,
+            InventoryItem {
+                name: "治疗药水".to_owned(),
+                stack: 2,
+                max_stack: 20,
+                use_rule: "主动使用对目标回复5点生命值".to_owned(),
+                ..Default::default()
+            },
+        );
+        manager
+            .player_characters
+            .insert("actor".to_owned(), character);
+        let mut actor = participant("actor", 0);
+        actor.player_character = true;
+        actor.hp = 4.0;
+        let mut store = BattleRoundStore::default();
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                active: true,
+                participants: vec![actor, participant("ally", 0)],
+                ..Default::default()
+            });
+
+        assert!(store
+            .use_item_from_chat(
+                "actor",
+                "治疗药水",
+                None,
+                &mut manager,
+                None,
+            )
+            .is_some_and(|reply| reply.is_ok()));
+        assert_eq!(
+            manager.player_characters["actor"].hp,
+            9.0
+        );
+        assert_eq!(
+            store.encounters["battle"].journal.entries[0].label,
+            "actor使用治疗药水"
+        );
+
+        assert!(store.undo_journal_entry("battle", &mut manager).is_some());
+        let character = &manager.player_characters["actor"];
+        assert_eq!(character.hp, 4.0);
+        assert_eq!(character.inventory.items[0].stack, 2);
+    }
+
+    fn chat_battle(manager: &mut NapcatMessageManager) -> BattleRoundStore {
+        manager
+            .player_characters
+            .insert("1001".to_owned(), PlayerCharacter {
+                skill_names: vec!["重击".to_owned()],
+                skill_notes: vec!["主动使用对目标造成3点物理伤害".to_owned()],
+                skill_mp_costs: vec![4.0],
+                ..Default::default()
+            });
+        let mut player = participant("1001", 0);
+        player.player_character = true;
+        player.mp = 3.0;
+        let mut goblin = participant("goblin", 0);
+        goblin.display_name = "哥布林".to_owned();
+        goblin.action_done = true;
+        let mut store = BattleRoundStore::default();
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                participants: vec![player, goblin],
+                chat_attack_damage: "2".to_owned(),
+                ..Default::default()
+            });
+        store
+    }
+
+    #[test]
+    fn chat_attack_resolves_for_the_current_actor_only() {
+        let mut manager = empty_manager();
+        let mut store = chat_battle(&mut manager);
+
+        assert_eq!(
+            store.chat_action_from_chat(
+                "goblin",
+                None,
+                Some("1001"),
+                None,
+                &mut manager,
+                None
+            ),
+            Some(Err("还没轮到你行动。".to_owned()))
+        );
+        assert!(store
+            .chat_action_from_chat(
+                "outsider",
+                None,
+                Some("哥布林"),
+                None,
+                &mut manager,
+                None
+            )
+            .is_none());
+        assert!(matches!(
+            store.chat_action_from_chat(
+                "1001",
+                None,
+                None,
+                None,
+                &mut manager,
+                None
+            ),
+            Some(Err(_))
+        ));
+        assert!(store.encounters["battle"].action_log.is_empty());
+
+        let Some(Ok(BattleChatActionReply::Resolved(text))) = store.chat_action_from_chat(
+            "1001",
+            None,
+            Some("哥布林"),
+            None,
+            &mut manager,
+            None,
+        ) else {
+            panic!("attack should resolve");
+        };
+        assert!(text.starts_with("伤害掷骰："));
+        assert_eq!(
+            store.encounters["battle"].participants[1].hp,
+            8.0
+        );
+        assert_eq!(
+            store.encounters["battle"].journal.entries[0].label,
+            "1001普通攻击哥布林"
+        );
+    }
+
+    #[test]
+    fn headless_chat_attack_ending_the_round_ticks_buffs_once_in_update() {
+        let mut manager = empty_manager();
+        let store = chat_battle(&mut manager);
+        manager
+            .player_characters
+            .get_mut("1001")
+            .unwrap()
+            .active_buffs
+            .push(BuffSpec {
+                name: "专注".to_owned(),
+                kind: BuffKind::Magic,
+                priority: 0,
+                turns_remaining: 3,
+                source_id: "1001".to_owned(),
+                beneficial: true,
+                effects: Vec::new(),
+                tick_actions: Vec::new(),
+                conditions: Vec::new(),
+            });
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = App::new();
+        app.add_plugins(crate::rule_engine::RuleEnginePlugin)
+            .insert_resource(
+                Persistent::<BattleRoundStore>::builder()
+                    .name("test_battle_rounds")
+                    .format(StorageFormat::Toml)
+                    .path(dir.path().join("battle_rounds.toml"))
+                    .default(store)
+                    .build()
+                    .unwrap(),
+            )
+            .insert_resource(
+                Persistent::<NapcatMessageManager>::builder()
+                    .name("test_messages")
+                    .format(StorageFormat::Toml)
+                    .path(dir.path().join("messages.toml"))
+                    .default(manager)
+                    .build()
+                    .unwrap(),
+            )
+            .add_systems(Update, advance_battle_rounds);
+        app.update();
+        let turns_remaining = |app: &App| {
+            app.world()
+                .resource::<Persistent<NapcatMessageManager>>()
+                .player_characters["1001"]
+                .active_buffs[0]
+                .turns_remaining
+        };
+        assert!(app
+            .world()
+            .resource::<RuleEngineState>()
+            .character("1001")
+            .is_some());
+
+        app.world_mut().resource_scope(
+            |world, mut store: Mut<Persistent<BattleRoundStore>>| {
+                let mut manager = world.resource_mut::<Persistent<NapcatMessageManager>>();
+                assert!(matches!(
+                    store.chat_action_from_chat(
+                        "1001",
+                        None,
+                        Some("哥布林"),
+                        None,
+                        &mut manager,
+                        None,
+                    ),
+                    Some(Ok(BattleChatActionReply::Resolved(_)))
+                ));
+            },
+        );
+        assert_eq!(
+            app.world()
+                .resource::<Persistent<BattleRoundStore>>()
+                .encounters["battle"]
+                .round,
+            1
+        );
+        assert_eq!(turns_remaining(&app), 3);
+
+        app.update();
+        assert_eq!(turns_remaining(&app), 2);
+        app.update();
+        assert_eq!(turns_remaining(&app), 2);
+
+        app.world_mut().resource_scope(
+            |world, mut store: Mut<Persistent<BattleRoundStore>>| {
+                let mut manager = world.resource_mut::<Persistent<NapcatMessageManager>>();
+                assert_eq!(
+                    store.undo_journal_entry("battle", &mut manager).as_deref(),
+                    Some("1001普通攻击哥布林")
+                );
+            },
+        );
+        app.update();
+        assert_eq!(
+            app.world()
+                .resource::<Persistent<BattleRoundStore>>()
+                .encounters["battle"]
+                .round,
+            0
+        );
+        assert_eq!(turns_remaining(&app), 3);
+    }
+
+    #[test]
+    fn chat_skill_checks_mp_and_waits_for_gm_approval() {
+        let mut manager = empty_manager();
+        let mut store = chat_battle(&mut manager);
+        store
+            .encounters
+            .get_mut("battle")
+            .unwrap()
+            .chat_action_approval = true;
+
+        assert_eq!(
+            store.chat_action_from_chat(
+                "1001",
+                Some("重击"),
+                Some("哥布林"),
+                Some(42),
+                &mut manager,
+                None,
+            ),
+            Some(Err(
+                "「重击」需要4 MP，当前只有3 MP".to_owned()
+            ))
+        );
+        store.encounters.get_mut("battle").unwrap().participants[0].mp = 5.0;
+        assert_eq!(
+            store.chat_action_from_chat(
+                "1001",
+                Some("1"),
+                Some("哥布林"),
+                Some(42),
+                &mut manager,
+                None
+            ),
+            Some(Ok(
+                BattleChatActionReply::AwaitingApproval("1001对哥布林施放「重击」".to_owned())
+            ))
+        );
+        assert!(matches!(
+            store.chat_action_from_chat(
+                "1001",
+                Some("重击"),
+                Some("哥布林"),
+                Some(42),
+                &mut manager,
+                None
+            ),
+            Some(Err(_))
+        ));
+        assert_eq!(
+            store.encounters["battle"].participants[1].hp,
+            10.0
+        );
+
+        assert!(store.approve_chat_action("battle", &mut manager, None));
+        let encounter = &store.encounters["battle"];
+        assert!(encounter.pending_chat_action.is_none());
+        assert_eq!(encounter.participants[1].hp, 7.0);
+        // 4 MP spent, then 1 regained as the finished action closes the round.
+        assert_eq!(encounter.participants[0].mp, 2.0);
+        let outcome = &store.chat_action_outcomes[0];
+        assert!(outcome.resolved);
+        assert_eq!(outcome.group_id, Some(42));
+        assert!(outcome.text.contains("造成3点伤害"));
+    }
+
+    #[test]
+    fn scene_cover_blocks_or_weakens_single_target_skills() {
+        let mut manager = empty_manager();
+        let mut store = chat_battle(&mut manager);
+        store.encounters.get_mut("battle").unwrap().participants[0].mp = 5.0;
+        let cover = |cover| SceneCharacterPositions {
+            positions: HashMap::from([
+                ("1001".to_owned(), Vec3::ZERO),
+                (
+                    "goblin".to_owned(),
+                    Vec3::new(1.0, 0.0, 0.0),
+                ),
+            ]),
+            cover: HashMap::from([(
+                ("1001".to_owned(), "goblin".to_owned()),
+                cover,
+            )]),
+        };
+
+        assert_eq!(
+            store.chat_action_from_chat(
+                "1001",
+                Some("重击"),
+                Some("哥布林"),
+                None,
+                &mut manager,
+                Some(&cover(SceneCover::Full)),
+            ),
+            Some(Err(
+                "哥布林处于完全掩护，「重击」打不到".to_owned()
+            ))
+        );
+        assert!(matches!(
+            store.chat_action_from_chat(
+                "1001",
+                Some("重击"),
+                Some("哥布林"),
+                None,
+                &mut manager,
+                Some(&cover(SceneCover::Partial)),
+            ),
+            Some(Ok(_))
+        ));
+        let encounter = &store.encounters["battle"];
+        assert_eq!(encounter.participants[1].hp, 8.5);
+        assert!(encounter
+            .action_log
+            .iter()
+            .any(|line| line == "哥布林处于部分掩护，伤害降低50%"));
+
+        let mut store = chat_battle(&mut manager);
+        store.encounters.get_mut("battle").unwrap().participants[0].mp = 10.0;
+        let skill = character_skills(&manager.player_characters["1001"])
+            .into_iter()
+            .next()
+            .unwrap();
+        assert!(store.record_skill_use(
+            "battle",
+            "1001",
+            "goblin",
+            &skill,
+            &manager,
+            Some(&cover(SceneCover::Full)),
+        ));
+        let encounter = &store.encounters["battle"];
+        assert_eq!(encounter.participants[1].hp, 10.0);
+        assert!(encounter
+            .action_log
+            .iter()
+            .any(|line| line == "哥布林处于完全掩护，1001的重击被挡住"));
+
+        store.encounters.get_mut("battle").unwrap().cover_checks = false;
+        assert!(store.record_skill_use(
+            "battle",
+            "1001",
+            "goblin",
+            &skill,
+            &manager,
+            Some(&cover(SceneCover::Full)),
+        ));
+        assert_eq!(
+            store.encounters["battle"].participants[1].hp,
+            7.0
+        );
+    }
+
+    fn actor_order(encounter: &BattleEncounter) -> Vec<&str> {
+        ordered_participant_indices(encounter)
+            .into_iter()
+            .map(|index| encounter.participants[index].target_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn rolled_and_side_initiative_reorder_the_speed_queue() {
+        let mut fast = participant("fast", 0);
+        fast.speed = 5.0;
+        let mut hero = participant("hero", 0);
+        hero.player_character = true;
+        let mut encounter = BattleEncounter {
+            participants: vec![fast, hero, participant("slow", 0)],
+            initiative_mode: BattleInitiativeMode::Rolled,
+            ..Default::default()
+        };
+        let mut dice = [3, 9, 12].into_iter();
+
+        assert!(roll_missing_initiative_with(
+            &mut encounter,
+            || { dice.next().unwrap() }
+        ));
+        assert!(!roll_missing_initiative_with(
+            &mut encounter,
+            || 20
+        ));
+        assert_eq!(encounter.initiative_rolls["fast"], 8);
+        assert_eq!(actor_order(&encounter), vec![
+            "slow", "hero", "fast"
+        ]);
+        assert!(encounter
+            .action_log
+            .contains(&"fast先攻：d20=3+5=8".to_owned()));
+
+        encounter.initiative_mode = BattleInitiativeMode::Sides;
+        assert_eq!(actor_order(&encounter), vec![
+            "hero", "fast", "slow"
+        ]);
+    }
+
+    #[test]
+    fn popcorn_pick_hands_over_after_the_current_actor_finishes() {
+        let mut hero = participant("hero", 0);
+        hero.player_character = true;
+        hero.speed = 5.0;
+        let mut store = BattleRoundStore::default();
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                participants: vec![hero, participant("ally", 0), participant("wolf", 0)],
+                initiative_mode: BattleInitiativeMode::Popcorn,
+                ..Default::default()
+            });
+
+        assert_eq!(
+            store.pick_popcorn_next_from_chat("ally", Some("wolf")),
+            Some(Err("还没轮到你行动。".to_owned()))
+        );
+        assert_eq!(
+            store.pick_popcorn_next_from_chat("hero", Some("wolf")),
+            Some(Ok("下一位行动者：wolf".to_owned()))
+        );
+        assert_eq!(
+            actor_order(&store.encounters["battle"])[0],
+            "hero"
+        );
+
+        assert!(store.finish_actor_action("battle", "hero"));
+        assert_eq!(
+            actor_order(&store.encounters["battle"])[0],
+            "wolf"
+        );
+        assert_eq!(
+            store.pick_popcorn_next_from_chat("wolf", Some("hero")),
+            Some(Err("hero本轮已经不能行动。".to_owned()))
+        );
+
+        assert!(store.finish_actor_action("battle", "wolf"));
+        assert!(store.finish_actor_action("battle", "ally"));
+        let encounter = &store.encounters["battle"];
+        assert_eq!(encounter.round, 1);
+        assert_eq!(encounter.popcorn_actor, None);
+    }
+
+    #[test]
+    fn timeline_lets_faster_participants_act_more_often_per_round() {
+        let mut fast = participant("fast", 0);
+        fast.speed = 6.0;
+        let mut slow = participant("slow", 0);
+        slow.speed = 3.0;
+        let mut store = BattleRoundStore::default();
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                participants: vec![fast, slow],
+                initiative_mode: BattleInitiativeMode::Timeline,
+                ..Default::default()
+            });
+        let current = |store: &BattleRoundStore| {
+            let encounter = &store.encounters["battle"];
+            current_actor_index(encounter)
+                .map(|index| encounter.participants[index].target_id.clone())
+        };
+
+        assert_eq!(current(&store).as_deref(), Some("fast"));
+        assert!(store.finish_actor_action("battle", "fast"));
+        assert_eq!(current(&store).as_deref(), Some("slow"));
+        assert!(store.finish_actor_action("battle", "slow"));
+        assert_eq!(current(&store).as_deref(), Some("fast"));
+        assert_eq!(store.encounters["battle"].round, 0);
+        assert!(store.finish_actor_action("battle", "fast"));
+
+        let encounter = &store.encounters["battle"];
+        assert_eq!(encounter.round, 1);
+        assert_eq!(encounter.participants[0].turn, 1);
+        assert!(encounter.timeline_actions_taken.is_empty());
+    }
+
+    #[test]
+    fn scene_movement_spends_the_round_budget_and_resets_next_round() {
+        let mut hero = participant("hero", 0);
+        hero.speed = 2.0;
+        let mut rooted = participant("rooted", 0);
+        rooted.conditions = vec![BuffCondition::Rooted];
+        let mut store = BattleRoundStore {
+            active_encounter_id: Some("battle".to_owned()),
+            ..Default::default()
+        };
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                participants: vec![hero, rooted],
+                ..Default::default()
+            });
+
+        assert_eq!(
+            store.battle_movement_remaining("hero"),
+            Some(48.0)
+        );
+        assert_eq!(
+            store.current_battle_mover(),
+            Some(("hero".to_owned(), 48.0))
+        );
+        assert_eq!(
+            store.spend_battle_movement("hero", 40.0),
+            Ok(8.0)
+        );
+        assert_eq!(
+            store.spend_battle_movement("hero", 9.5),
+            Err("hero本轮只剩8格移动，到不了那里（需要9.5格）".to_owned())
+        );
+        assert_eq!(
+            store.battle_movement_remaining("rooted"),
+            Some(0.0)
+        );
+        assert_eq!(
+            store.battle_movement_remaining("outsider"),
+            None
+        );
+
+        assert!(store.finish_actor_action("battle", "hero"));
+        assert!(store.finish_actor_action("battle", "rooted"));
+        assert_eq!(
+            store.battle_movement_remaining("hero"),
+            Some(48.0)
+        );
+
+        store.encounters.get_mut("battle").unwrap().movement_limited = false;
+        assert_eq!(
+            store.battle_movement_remaining("hero"),
+            None
+        );
+        assert_eq!(
+            store.spend_battle_movement("hero", 1000.0),
+            Ok(f32::INFINITY)
+        );
+    }
 }
@@ -0,0 +1,4 @@
+use bevy::prelude::*;
+use willowblossom::HeadlessPlugin;
+
+fn main() { App::new().add_plugins(HeadlessPlugin).run(); }
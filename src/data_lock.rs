@@ -0,0 +1,105 @@
+use std::{
+    fs::{
+        self,
+        File,
+        OpenOptions,
+        TryLockError,
+    },
+    io::{
+        Read,
+        Seek,
+        SeekFrom,
+        Write,
+    },
+    path::Path,
+};
+
+use bevy::prelude::*;
+
+const DATA_DIR_LOCK_FILE: &str = "instance.lock";
+
+/// Exclusive hold on a data directory so two processes (for example the headless server and the
+/// desktop app) never persist the same stores at once. The OS drops the lock when the process
+/// exits, so a crash never leaves the directory blocked.
+#[derive(Resource)]
+pub struct DataDirLock {
+    _file: File,
+}
+
+impl DataDirLock {
+    /// Locks `config_dir` for `mode`, or reports which process already owns it.
+    pub fn acquire(config_dir: &Path, mode: &str) -> Result<Self, String> {
+        fs::create_dir_all(config_dir).map_err(|err| {
+            format!(
+                "failed to create {}: {err}",
+                config_dir.display()
+            )
+        })?;
+        let path = config_dir.join(DATA_DIR_LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|err| {
+                format!(
+                    "failed to open {}: {err}",
+                    path.display()
+                )
+            })?;
+        match file.try_lock() {
+            Ok(()) => {},
+            Err(TryLockError::WouldBlock) => {
+                let mut owner = String::new();
+                let _ = file.read_to_string(&mut owner);
+                let owner = owner.trim();
+                return Err(format!(
+                    "数据目录 {} 正被另一个柳絮进程使用（{}），请先关闭它再启动",
+                    config_dir.display(),
+                    if owner.is_empty() { "未知进程" } else { owner }
+                ));
+            },
+            Err(TryLockError::Error(err)) => {
+                return Err(format!(
+                    "failed to lock {}: {err}",
+                    path.display()
+                ));
+            },
+        }
+
+        let owner = format!("{mode} pid {}", std::process::id());
+        file.set_len(0)
+            .and_then(|()| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(owner.as_bytes()))
+            .map_err(|err| {
+                format!(
+                    "failed to record lock owner in {}: {err}",
+                    path.display()
+                )
+            })?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_instance_is_refused_until_the_first_releases_the_data_dir() {
+        let directory = tempfile::tempdir().unwrap();
+        let headless = DataDirLock::acquire(directory.path(), "headless").unwrap();
+
+        let err = DataDirLock::acquire(directory.path(), "gui")
+            .err()
+            .expect("second lock must fail");
+        assert!(err.contains(&format!(
+            "headless pid {}",
+            std::process::id()
+        )));
+
+        drop(headless);
+        assert!(DataDirLock::acquire(directory.path(), "gui").is_ok());
+    }
+}
@@ -0,0 +1,536 @@
+use rand::RngExt;
+
+const MAX_DICE_COUNT: u32 = 100;
+const MAX_DICE_SIDES: u32 = 1000;
+const MAX_EXPLOSIONS_PER_GROUP: u32 = 100;
+const MAX_DICE_TERMS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceExpression {
+    pub raw: String,
+    pub terms: Vec<DiceTerm>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceTerm {
+    pub negative: bool,
+    pub kind: DiceTermKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiceTermKind {
+    Dice(DiceGroup),
+    Constant(i32),
+    Attribute(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiceGroup {
+    pub count: u32,
+    pub sides: u32,
+    pub keep: Option<DiceKeep>,
+    pub exploding: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiceKeep {
+    KeepHighest(u32),
+    KeepLowest(u32),
+    DropHighest(u32),
+    DropLowest(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceRoll {
+    pub total: i32,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DieResult {
+    value: u32,
+    exploded: bool,
+    kept: bool,
+}
+
+pub fn parse_dice_expression(text: &str) -> Result<DiceExpression, String> {
+    let raw = text
+        .chars()
+        .filter(|ch| !ch.is_whitespace())
+        .collect::<String>();
+    if raw.is_empty() {
+        return Err("骰子表达式不能为空。".to_owned());
+    }
+
+    let chars = raw.chars().collect::<Vec<_>>();
+    let mut terms = Vec::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let negative = match chars[index] {
+            '+' | '＋' => {
+                index += 1;
+                false
+            },
+            '-' | '－' => {
+                index += 1;
+                true
+            },
+            _ if terms.is_empty() => false,
+            other => return Err(format!("无法识别的符号「{other}」。")),
+        };
+        if index >= chars.len() {
+            return Err("表达式不能以运算符结尾。".to_owned());
+        }
+        let kind = parse_dice_term(&chars, &mut index)?;
+        terms.push(DiceTerm { negative, kind });
+        if terms.len() > MAX_DICE_TERMS {
+            return Err(format!(
+                "表达式最多包含{MAX_DICE_TERMS}项。"
+            ));
+        }
+    }
+
+    Ok(DiceExpression { raw, terms })
+}
+
+fn parse_dice_term(chars: &[char], index: &mut usize) -> Result<DiceTermKind, String> {
+    let count = parse_number(chars, index);
+    if matches!(chars.get(*index), Some('d' | 'D'))
+        && chars
+            .get(*index + 1)
+            .is_some_and(|ch| ch.is_ascii_digit() || *ch == '%')
+    {
+        *index += 1;
+        let count = count.unwrap_or(1);
+        let sides = if chars.get(*index) == Some(&'%') {
+            *index += 1;
+            100
+        } else {
+            parse_number(chars, index).unwrap_or_default()
+        };
+        return parse_dice_group(chars, index, count, sides).map(DiceTermKind::Dice);
+    }
+    if let Some(value) = count {
+        return i32::try_from(value)
+            .map(DiceTermKind::Constant)
+            .map_err(|_| "常数过大。".to_owned());
+    }
+
+    let start = *index;
+    while *index < chars.len() && !matches!(chars[*index], '+' | '-' | '＋' | '－') {
+        *index += 1;
+    }
+    let name = chars[start..*index].iter().collect::<String>();
+    if name.is_empty() {
+        return Err("缺少骰子、数字或属性名。".to_owned());
+    }
+    Ok(DiceTermKind::Attribute(name))
+}
+
+fn parse_dice_group(
+    chars: &[char],
+    index: &mut usize,
+    count: u32,
+    sides: u32,
+) -> Result<DiceGroup, String> {
+    if count == 0 || count > MAX_DICE_COUNT {
+        return Err(format!(
+            "骰子数量必须在1到{MAX_DICE_COUNT}之间。"
+        ));
+    }
+    if sides == 0 || sides > MAX_DICE_SIDES {
+        return Err(format!(
+            "骰子面数必须在1到{MAX_DICE_SIDES}之间。"
+        ));
+    }
+
+    let mut group = DiceGroup {
+        count,
+        sides,
+        keep: None,
+        exploding: false,
+    };
+    loop {
+        match chars.get(*index).map(|ch| ch.to_ascii_lowercase()) {
+            Some('!') => {
+                if group.exploding {
+                    return Err("爆骰标记重复。".to_owned());
+                }
+                if sides < 2 {
+                    return Err("一面骰不能爆骰。".to_owned());
+                }
+                group.exploding = true;
+                *index += 1;
+            },
+            Some(ch @ ('k' | 'd')) => {
+                if group.keep.is_some() {
+                    return Err("每组骰子只能使用一个保留/丢弃规则。".to_owned());
+                }
+                *index += 1;
+                let highest = match chars.get(*index).map(|ch| ch.to_ascii_lowercase()) {
+                    Some('h') => {
+                        *index += 1;
+                        true
+                    },
+                    Some('l') => {
+                        *index += 1;
+                        false
+                    },
+                    // Bare `k` keeps highest and bare `d` drops lowest, as in most roller dialects.
+                    _ => ch == 'k',
+                };
+                let amount = parse_number(chars, index).unwrap_or(1);
+                if amount > count || (ch == 'k' && amount == 0) {
+                    return Err(format!(
+                        "保留/丢弃数量必须在1到{count}之间。"
+                    ));
+                }
+                group.keep = Some(match (ch, highest) {
+                    ('k', true) => DiceKeep::KeepHighest(amount),
+                    ('k', false) => DiceKeep::KeepLowest(amount),
+                    (_, true) => DiceKeep::DropHighest(amount),
+                    (_, false) => DiceKeep::DropLowest(amount),
+                });
+            },
+            None | Some('+' | '-' | '＋' | '－') => return Ok(group),
+            Some(_) => {
+                return Err(format!(
+                    "骰子「{count}d{sides}」后有无法识别的内容。"
+                ));
+            },
+        }
+    }
+}
+
+fn parse_number(chars: &[char], index: &mut usize) -> Option<u32> {
+    let start = *index;
+    while chars.get(*index).is_some_and(char::is_ascii_digit) {
+        *index += 1;
+    }
+    (*index > start).then(|| {
+        chars[start..*index]
+            .iter()
+            .collect::<String>()
+            .parse::<u32>()
+            .unwrap_or(u32::MAX)
+    })
+}
+
+pub fn roll_dice_expression(
+    expression: &DiceExpression,
+    resolve_attribute: impl Fn(&str) -> Option<i32>,
+) -> Result<DiceRoll, String> {
+    roll_dice_expression_with(expression, resolve_attribute, |sides| {
+        rand::rng().random_range(1..=sides)
+    })
+}
+
+pub fn roll_dice_expression_with(
+    expression: &DiceExpression,
+    resolve_attribute: impl Fn(&str) -> Option<i32>,
+    mut roll_die: impl FnMut(u32) -> u32,
+) -> Result<DiceRoll, String> {
+    let mut total = 0_i64;
+    let mut parts = Vec::new();
+    for (index, term) in expression.terms.iter().enumerate() {
+        let (value, detail) = match &term.kind {
+            DiceTermKind::Dice(group) => roll_dice_group(group, &mut roll_die),
+            DiceTermKind::Constant(value) => (i64::from(*value), value.to_string()),
+            DiceTermKind::Attribute(name) => {
+                let value =
+                    resolve_attribute(name).ok_or_else(|| format!("未知属性「{name}」。"))?;
+                (
+                    i64::from(value),
+                    format!("{name}({value})"),
+                )
+            },
+        };
+        total += if term.negative { -value } else { value };
+        let sign = match (index, term.negative) {
+            (0, false) => "",
+            (0, true) => "-",
+            (_, false) => " + ",
+            (_, true) => " - ",
+        };
+        parts.push(format!("{sign}{detail}"));
+    }
+
+    let total = total.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32;
+    Ok(DiceRoll {
+        total,
+        detail: format!(
+            "{} = {} = {}",
+            format_dice_expression(expression),
+            parts.concat(),
+            total
+        ),
+    })
+}
+
+fn roll_dice_group(group: &DiceGroup, roll_die: &mut impl FnMut(u32) -> u32) -> (i64, String) {
+    let mut dice = Vec::new();
+    let mut explosions = 0;
+    for _ in 0..group.count {
+        let mut value = roll_die(group.sides).clamp(1, group.sides);
+        let mut total = value;
+        while group.exploding && value == group.sides && explosions < MAX_EXPLOSIONS_PER_GROUP {
+            explosions += 1;
+            value = roll_die(group.sides).clamp(1, group.sides);
+            total += value;
+        }
+        dice.push(DieResult {
+            value: total,
+            exploded: total > group.sides,
+            kept: true,
+        });
+    }
+    apply_dice_keep(&mut dice, group.keep);
+
+    let total = dice
+        .iter()
+        .filter(|die| die.kept)
+        .map(|die| i64::from(die.value))
+        .sum::<i64>();
+    let rolls = dice
+        .iter()
+        .map(|die| {
+            let value =
+                if die.exploded { format!("{}!", die.value) } else { die.value.to_string() };
+            if die.kept {
+                value
+            } else {
+                format!("({value})")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    (total, format!("[{rolls}]"))
+}
+
+fn apply_dice_keep(dice: &mut [DieResult], keep: Option<DiceKeep>) {
+    let Some(keep) = keep else {
+        return;
+    };
+    let mut order = (0..dice.len()).collect::<Vec<_>>();
+    order.sort_by_key(|index| dice[*index].value);
+    let dropped = match keep {
+        DiceKeep::KeepHighest(amount) => order[..dice.len() - amount as usize].to_vec(),
+        DiceKeep::KeepLowest(amount) => order[amount as usize..].to_vec(),
+        DiceKeep::DropHighest(amount) => order[dice.len() - amount as usize..].to_vec(),
+        DiceKeep::DropLowest(amount) => order[..amount as usize].to_vec(),
+    };
+    for index in dropped {
+        dice[index].kept = false;
+    }
+}
+
+fn format_dice_expression(expression: &DiceExpression) -> String {
+    expression
+        .terms
+        .iter()
+        .enumerate()
+        .map(|(index, term)| {
+            let sign = match (index, term.negative) {
+                (0, false) => "",
+                (_, true) => "-",
+                (_, false) => "+",
+            };
+            let body = match &term.kind {
+                DiceTermKind::Dice(group) => format_dice_group(group),
+                DiceTermKind::Constant(value) => value.to_string(),
+                DiceTermKind::Attribute(name) => name.clone(),
+            };
+            format!("{sign}{body}")
+        })
+        .collect()
+}
+
+fn format_dice_group(group: &DiceGroup) -> String {
+    let keep = match group.keep {
+        Some(DiceKeep::KeepHighest(amount)) => format!("kh{amount}"),
+        Some(DiceKeep::KeepLowest(amount)) => format!("kl{amount}"),
+        Some(DiceKeep::DropHighest(amount)) => format!("dh{amount}"),
+        Some(DiceKeep::DropLowest(amount)) => format!("dl{amount}"),
+        None => String::new(),
+    };
+    let exploding = if group.exploding { "!" } else { "" };
+    format!(
+        "{}d{}{exploding}{keep}",
+        group.count, group.sides
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scripted_rolls(values: &[u32]) -> impl FnMut(u32) -> u32 + '_ {
+        let mut values = values.iter().copied();
+        move |_| values.next().expect("test ran out of scripted dice")
+    }
+
+    fn no_attributes(_: &str) -> Option<i32> { None }
+
+    #[test]
+    fn parses_dice_with_modifiers() {
+        let expression = parse_dice_expression("3d6 + 2").unwrap();
+
+        assert_eq!(expression.terms, vec![
+            DiceTerm {
+                negative: false,
+                kind: DiceTermKind::Dice(DiceGroup {
+                    count: 3,
+                    sides: 6,
+                    keep: None,
+                    exploding: false,
+                }),
+            },
+            DiceTerm {
+                negative: false,
+                kind: DiceTermKind::Constant(2),
+            },
+        ]);
+    }
+
+    #[test]
+    fn rolls_sum_with_constant_modifier() {
+        let expression = parse_dice_expression("3d6+2").unwrap();
+        let roll = roll_dice_expression_with(
+            &expression,
+            no_attributes,
+            scripted_rolls(&[4, 2, 6]),
+        )
+        .unwrap();
+
+        assert_eq!(roll.total, 14);
+        assert_eq!(
+            roll.detail,
+            "3d6+2 = [4, 2, 6] + 2 = 14"
+        );
+    }
+
+    #[test]
+    fn keep_highest_discards_lower_dice() {
+        let expression = parse_dice_expression("2d20kh1").unwrap();
+        let roll = roll_dice_expression_with(
+            &expression,
+            no_attributes,
+            scripted_rolls(&[7, 18]),
+        )
+        .unwrap();
+
+        assert_eq!(roll.total, 18);
+        assert_eq!(roll.detail, "2d20kh1 = [(7), 18] = 18");
+    }
+
+    #[test]
+    fn keep_and_drop_variants_select_expected_dice() {
+        let cases = [
+            ("4d6kl2", 3),
+            ("4d6dl1", 13),
+            ("4d6dh1", 8),
+            ("4d6k3", 13),
+            ("4d6d", 13),
+        ];
+        for (text, expected) in cases {
+            let expression = parse_dice_expression(text).unwrap();
+            let roll = roll_dice_expression_with(
+                &expression,
+                no_attributes,
+                scripted_rolls(&[5, 1, 6, 2]),
+            )
+            .unwrap();
+            assert_eq!(roll.total, expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn exploding_dice_reroll_on_maximum_face() {
+        let expression = parse_dice_expression("2d6!").unwrap();
+        let roll = roll_dice_expression_with(
+            &expression,
+            no_attributes,
+            scripted_rolls(&[6, 6, 3, 2]),
+        )
+        .unwrap();
+
+        assert_eq!(roll.total, 17);
+        assert_eq!(roll.detail, "2d6! = [15!, 2] = 17");
+    }
+
+    #[test]
+    fn attribute_references_resolve_through_lookup() {
+        let expression = parse_dice_expression("1d20+力量-1").unwrap();
+        let roll = roll_dice_expression_with(
+            &expression,
+            |name| (name == "力量").then_some(5),
+            scripted_rolls(&[12]),
+        )
+        .unwrap();
+
+        assert_eq!(roll.total, 16);
+        assert_eq!(
+            roll.detail,
+            "1d20+力量-1 = [12] + 力量(5) - 1 = 16"
+        );
+    }
+
+    #[test]
+    fn ascii_attribute_starting_with_d_is_not_a_die() {
+        let expression = parse_dice_expression("d20+dex").unwrap();
+
+        assert_eq!(
+            expression.terms[1].kind,
+            DiceTermKind::Attribute("dex".to_owned())
+        );
+    }
+
+    #[test]
+    fn unknown_attribute_is_reported() {
+        let expression = parse_dice_expression("1d20+运气").unwrap();
+
+        assert_eq!(
+            roll_dice_expression_with(
+                &expression,
+                no_attributes,
+                scripted_rolls(&[1])
+            ),
+            Err("未知属性「运气」。".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        for text in [
+            "",
+            "1d20+",
+            "0d6",
+            "1d0",
+            "1001d6",
+            "2d6kh3",
+            "1d1!",
+            "1d20x",
+            "3d6kh1kl1",
+        ] {
+            assert!(
+                parse_dice_expression(text).is_err(),
+                "{text} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn percent_die_is_d100() {
+        let expression = parse_dice_expression("d%").unwrap();
+
+        assert_eq!(
+            expression.terms[0].kind,
+            DiceTermKind::Dice(DiceGroup {
+                count: 1,
+                sides: 100,
+                keep: None,
+                exploding: false,
+            })
+        );
+    }
+}
@@ -1,6 +1,9 @@
+mod attach;
 mod battle_round;
 mod camera;
+mod data_lock;
 mod deepseek;
+mod dice;
 mod moonberry_talents;
 mod napcat;
 mod replay;
@@ -10,12 +13,20 @@ mod ui;
 mod voxel;
 mod voxel_radiance;
 
-use std::path::Path;
+use std::{
+    path::Path,
+    time::Duration,
+};
 
 use bevy::{
+    app::{
+        ScheduleRunnerPlugin,
+        TerminalCtrlCHandlerPlugin,
+    },
     asset::AssetPlugin,
     log::LogPlugin,
     prelude::*,
+    state::app::StatesPlugin,
     window::{
         PrimaryWindow,
         WindowMoved,
@@ -40,6 +51,8 @@ const DEFAULT_WINDOW_HEIGHT: u32 = 600;
 const MIN_WINDOW_WIDTH: u32 = 800;
 const MIN_WINDOW_HEIGHT: u32 = 600;
 const WINDOWS_MINIMIZED_POSITION: i32 = -30_000;
+const HEADLESS_TICK_RATE_HZ: f64 = 20.0;
+const LOG_FILTER: &str = "wgpu=error,naga=warn,bevy_persistent=warn";
 
 #[derive(Resource, Serialize, Deserialize)]
 struct AppSettings {
@@ -140,7 +153,7 @@ fn default_window_width() -> u32 { DEFAULT_WINDOW_WIDTH }
 fn default_window_height() -> u32 { DEFAULT_WINDOW_HEIGHT }
 
 fn load_app_settings() -> Persistent<AppSettings> {
-    let config_dir = Path::new(".data").join("willowblossom");
+    let config_dir = data_dir();
     Persistent::<AppSettings>::builder()
         .name("app_settings")
         .format(StorageFormat::Toml)
@@ -152,6 +165,17 @@ fn load_app_settings() -> Persistent<AppSettings> {
         .expect("failed to init app settings")
 }
 
+fn data_dir() -> std::path::PathBuf { Path::new(".data").join("willowblossom") }
+
+/// Takes the data directory for the headless server, exiting when another instance already owns
+/// it.
+fn lock_data_dir(mode: &str) -> data_lock::DataDirLock {
+    data_lock::DataDirLock::acquire(&data_dir(), mode).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    })
+}
+
 #[derive(States, Debug, Default, Clone, Eq, PartialEq, Hash)]
 pub enum GameState {
     #[default]
@@ -175,6 +199,17 @@ impl Plugin for GamePlugin {
             });
         }
 
+        // A second window would fight the running instance over the stores, so it attaches to
+        // that instance's control API instead.
+        let lock = match data_lock::DataDirLock::acquire(&data_dir(), "gui") {
+            Ok(lock) => lock,
+            Err(lock_error) => {
+                build_attached_app(app, lock_error);
+                return;
+            },
+        };
+        app.insert_resource(lock);
+
         let mut app_settings = load_app_settings();
         if app_settings.normalize() {
             if let Err(err) = app_settings.persist() {
@@ -219,7 +254,7 @@ impl Plugin for GamePlugin {
         app.add_plugins(
             DefaultPlugins
                 .set(LogPlugin {
-                    filter: "wgpu=error,naga=warn,bevy_persistent=warn".to_string(),
+                    filter: LOG_FILTER.to_string(),
                     ..default()
                 })
                 .set(window_plugin)
@@ -247,6 +282,58 @@ impl Plugin for GamePlugin {
     }
 }
 
+fn build_attached_app(app: &mut App, lock_error: String) {
+    app.add_plugins(
+        DefaultPlugins
+            .set(LogPlugin {
+                filter: LOG_FILTER.to_string(),
+                ..default()
+            })
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    title: format!("{GAME_TITLE}（已附加）"),
+                    resolution: WindowResolution::new(
+                        DEFAULT_WINDOW_WIDTH,
+                        DEFAULT_WINDOW_HEIGHT,
+                    ),
+                    canvas: Some("#bevy".to_string()),
+                    ..default()
+                }),
+                ..default()
+            }),
+    )
+    .add_plugins(attach::AttachPlugin {
+        lock_error,
+        config_dir: data_dir(),
+    });
+}
+
+/// Always-on bot host: NapCat chat commands, battle rounds and rule effects with their
+/// persistent stores, without a window, renderer or egui.
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(lock_data_dir("headless"));
+        app.add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(
+                Duration::from_secs_f64(1.0 / HEADLESS_TICK_RATE_HZ),
+            )),
+            LogPlugin {
+                filter: LOG_FILTER.to_string(),
+                ..default()
+            },
+            StatesPlugin,
+            TerminalCtrlCHandlerPlugin,
+        ));
+        app.add_plugins((
+            battle_round::BattleRoundPlugin,
+            napcat::NapcatPlugin,
+            rule_engine::RuleEnginePlugin,
+        ));
+    }
+}
+
 fn persist_primary_window_size(
     mut resize_events: MessageReader<WindowResized>,
     mut moved_events: MessageReader<WindowMoved>,
@@ -0,0 +1,463 @@
+use bevy::prelude::*;
+use bevy_persistent::Persistent;
+
+use super::{
+    dice_roll_recipients,
+    message_text,
+    private_command_body,
+    queue_group_text_response,
+    queue_private_text_response,
+    trade::message_mention,
+    NapcatAutomaticReplyRequests,
+    NapcatIOSender,
+    NapcatMessage,
+    NapcatMessageManager,
+    NapcatMessageType,
+    Visibility,
+};
+use crate::{
+    battle_round::{
+        BattleChatActionReply,
+        BattleRoundStore,
+    },
+    scene::SceneCharacterPositions,
+};
+
+const BATTLE_COMMAND_USAGE: &str =
+    "输入【.施放 技能 @目标】施放技能，【.攻击 @目标】普通攻击；不写目标的技能对自己施放。点名接力时用【.点名 @角色】指定下一位。";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BattleCommand<'a> {
+    Attack {
+        target: Option<&'a str>,
+    },
+    Cast {
+        skill: &'a str,
+        target: Option<&'a str>,
+    },
+    /// `.点名`: in popcorn initiative, picks who acts next.
+    Pick {
+        target: Option<&'a str>,
+    },
+}
+
+/// What to tell the acting player, plus `(player id, text)` notices for everyone else who may see
+/// the result.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(super) struct BattleCommandOutcome {
+    pub reply: String,
+    pub notices: Vec<(String, String)>,
+    /// Set for group commands from a player whose party results are hidden: the reply goes to
+    /// the player privately instead of to the group.
+    pub private_reply: bool,
+}
+
+/// Splits a trailing `@目标` typed as text off the command arguments.
+fn split_command_target(args: &str) -> (&str, Option<&str>) {
+    match args.rsplit_once('@') {
+        Some((rest, target)) => (
+            rest.trim(),
+            Some(target.trim()).filter(|target| !target.is_empty()),
+        ),
+        None => (args.trim(), None),
+    }
+}
+
+fn parse_battle_command(text: &str) -> Option<BattleCommand<'_>> {
+    let body = private_command_body(text)?;
+    let args_after = |command: &str| {
+        let args = body.strip_prefix(command)?;
+        (args.is_empty() || args.starts_with(char::is_whitespace) || args.starts_with('@'))
+            .then_some(args)
+    };
+    if let Some(args) = args_after("攻击") {
+        return Some(BattleCommand::Attack {
+            target: split_command_target(args).1,
+        });
+    }
+    if let Some(args) = args_after("点名") {
+        return Some(BattleCommand::Pick {
+            target: split_command_target(args).1,
+        });
+    }
+    let (skill, target) = split_command_target(args_after("施放")?);
+    Some(BattleCommand::Cast { skill, target })
+}
+
+/// Handles `.攻击` / `.施放` / `.点名` typed by a player in private chat or in a QQ group.
+/// `group_id` is set for group messages, whose reply goes back to the group instead of fanning out
+/// privately, unless the player's party keeps its results hidden. Stays silent for players who are
+/// not in battle and for QQ groups that are not linked to the encounter's TRPG group.
+pub(super) fn handle_battle_command(
+    manager: &mut NapcatMessageManager,
+    message: &NapcatMessage,
+    battle_store: Option<&mut BattleRoundStore>,
+    scene_positions: Option<&SceneCharacterPositions>,
+) -> Option<BattleCommandOutcome> {
+    let text = message_text(message);
+    let command = parse_battle_command(&text)?;
+    let mention = message_mention(message);
+    let player_id = message.data.user_id.to_string();
+    let battle_store = battle_store?;
+    let battle_group = battle_store.chat_battle_group(&player_id)?;
+    let group_id = match message.data.message_type {
+        NapcatMessageType::Group => {
+            let group_id = message.data.group_id?;
+            let group = battle_group
+                .and_then(|group_name| manager.trpg_groups.get(group_name))
+                .or_else(|| manager.group_for_player_target(&player_id))?;
+            if !group
+                .group_chats
+                .iter()
+                .any(|chat_id| *chat_id == group_id.to_string())
+            {
+                return None;
+            }
+            Some(group_id)
+        },
+        NapcatMessageType::Private => None,
+    };
+    let private_reply =
+        group_id.is_some() && battle_result_visibility(manager, &player_id) != Visibility::Public;
+    // Results of hidden parties fan out privately even when the command came from a group.
+    let fan_out = group_id.is_none() || private_reply;
+    let outcome = |reply: String, notices: Vec<(String, String)>| BattleCommandOutcome {
+        reply,
+        notices,
+        private_reply,
+    };
+    let (skill, target) = match command {
+        BattleCommand::Pick { target } => {
+            let target = mention.as_deref().or(target);
+            return Some(
+                match battle_store.pick_popcorn_next_from_chat(&player_id, target)? {
+                    Err(err) => outcome(err, Vec::new()),
+                    Ok(text) => outcome(
+                        text.clone(),
+                        if fan_out {
+                            battle_result_audience(manager, &player_id, &text)
+                        } else {
+                            Vec::new()
+                        },
+                    ),
+                },
+            );
+        },
+        BattleCommand::Attack { target } => (None, target),
+        BattleCommand::Cast { skill: "", .. } => {
+            return Some(outcome(
+                BATTLE_COMMAND_USAGE.to_owned(),
+                Vec::new(),
+            ))
+        },
+        BattleCommand::Cast { skill, target } => (Some(skill), target),
+    };
+    let target = mention.as_deref().or(target);
+    let result = battle_store.chat_action_from_chat(
+        &player_id,
+        skill,
+        target,
+        group_id,
+        manager,
+        scene_positions,
+    )?;
+    Some(match result {
+        Err(err) => outcome(err, Vec::new()),
+        Ok(BattleChatActionReply::AwaitingApproval(summary)) => outcome(
+            format!("已提交行动：{summary}，等待GM批准。"),
+            Vec::new(),
+        ),
+        Ok(BattleChatActionReply::Resolved(text)) => outcome(
+            text.clone(),
+            if fan_out {
+                battle_result_audience(manager, &player_id, &text)
+            } else {
+                Vec::new()
+            },
+        ),
+    })
+}
+
+/// Public unless the actor belongs to a party, whose battle results only that party may see.
+fn battle_result_visibility(manager: &NapcatMessageManager, actor_id: &str) -> Visibility {
+    let party_id = manager
+        .group_for_player_target(actor_id)
+        .zip(actor_id.parse::<u64>().ok())
+        .and_then(|(group, actor)| group.player_access(actor).party_id);
+    match party_id {
+        Some(party_id) => Visibility::Party(party_id),
+        None => Visibility::Public,
+    }
+}
+
+/// Everyone in the actor's TRPG group allowed to see the actor's battle results, with the same
+/// party visibility as an open dice roll.
+fn battle_result_audience(
+    manager: &NapcatMessageManager,
+    actor_id: &str,
+    text: &str,
+) -> Vec<(String, String)> {
+    let (Some(group), Ok(actor)) = (
+        manager.group_for_player_target(actor_id),
+        actor_id.parse::<u64>(),
+    ) else {
+        return Vec::new();
+    };
+    dice_roll_recipients(
+        group,
+        &battle_result_visibility(manager, actor_id),
+        actor,
+    )
+    .into_iter()
+    .map(|user_id| (user_id.to_string(), text.to_owned()))
+    .collect()
+}
+
+/// Tells players how the GM ruled on their held chat actions.
+pub(super) fn battle_chat_action_system(
+    store: Option<ResMut<Persistent<BattleRoundStore>>>,
+    manager: Res<Persistent<NapcatMessageManager>>,
+    sender: Option<Res<NapcatIOSender>>,
+    mut automatic_replies: ResMut<NapcatAutomaticReplyRequests>,
+) {
+    // Without a sender the outcomes stay queued until the connection is back.
+    let (Some(mut store), Some(sender)) = (store, sender) else {
+        return;
+    };
+    if store.chat_action_outcomes.is_empty() {
+        return;
+    }
+    let outcomes = std::mem::take(&mut store.chat_action_outcomes);
+    for outcome in outcomes {
+        let group_id = outcome.group_id.filter(|_| {
+            battle_result_visibility(&manager, &outcome.actor_id) == Visibility::Public
+        });
+        if let Some(group_id) = group_id {
+            queue_group_text_response(
+                &sender,
+                &mut automatic_replies,
+                group_id,
+                outcome.text,
+            );
+            continue;
+        }
+        let mut recipients = vec![(
+            outcome.actor_id.clone(),
+            outcome.text.clone(),
+        )];
+        if outcome.resolved {
+            recipients.extend(battle_result_audience(
+                &manager,
+                &outcome.actor_id,
+                &outcome.text,
+            ));
+        }
+        for (recipient, text) in recipients {
+            if let Ok(user_id) = recipient.parse::<u64>() {
+                queue_private_text_response(
+                    &sender,
+                    &mut automatic_replies,
+                    user_id,
+                    text,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        battle_round::{
+            BattleEncounter,
+            BattleParticipantSnapshot,
+        },
+        napcat::{
+            tests::{
+                empty_manager,
+                test_message_with_text,
+            },
+            TrpgGroup,
+        },
+    };
+
+    fn participant(value: serde_json::Value) -> BattleParticipantSnapshot {
+        serde_json::from_value(value).expect("participant json")
+    }
+
+    #[test]
+    fn parses_attack_and_cast_with_typed_targets() {
+        assert_eq!(
+            parse_battle_command(".攻击 @哥布林"),
+            Some(BattleCommand::Attack {
+                target: Some("哥布林"),
+            })
+        );
+        assert_eq!(
+            parse_battle_command("。施放 火球 术 @ 哥布林"),
+            Some(BattleCommand::Cast {
+                skill: "火球 术",
+                target: Some("哥布林"),
+            })
+        );
+        assert_eq!(
+            parse_battle_command(".施放 治疗"),
+            Some(BattleCommand::Cast {
+                skill: "治疗",
+                target: None,
+            })
+        );
+        assert_eq!(
+            parse_battle_command(".点名 @艾琳"),
+            Some(BattleCommand::Pick {
+                target: Some("艾琳"),
+            })
+        );
+        assert_eq!(parse_battle_command(".攻击力"), None);
+        assert_eq!(parse_battle_command("施放 火球"), None);
+    }
+
+    #[test]
+    fn group_attack_resolves_without_private_notices() {
+        let mut manager = empty_manager();
+        manager.trpg_groups.insert("party".to_owned(), TrpgGroup {
+            players: vec!["2".to_owned(), "3".to_owned()],
+            group_chats: vec!["500".to_owned()],
+            ..Default::default()
+        });
+        let mut store = BattleRoundStore::default();
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                trpg_group: Some("party".to_owned()),
+                participants: vec![
+                    participant(serde_json::json!({
+                        "target_id": "2",
+                        "display_name": "艾琳",
+                        "player_character": true,
+                        "hp": 10.0,
+                        "max_hp": 10.0,
+                    })),
+                    participant(serde_json::json!({
+                        "target_id": "goblin",
+                        "display_name": "哥布林",
+                        "action_done": true,
+                        "hp": 10.0,
+                        "max_hp": 10.0,
+                    })),
+                ],
+                chat_attack_damage: "3".to_owned(),
+                ..Default::default()
+            });
+        let mut message = test_message_with_text(
+            NapcatMessageType::Group,
+            ".攻击 @哥布林",
+        );
+        message.data.group_id = Some(501);
+        assert_eq!(
+            handle_battle_command(
+                &mut manager,
+                &message,
+                Some(&mut store),
+                None,
+            ),
+            None
+        );
+        message.data.group_id = Some(500);
+
+        let outcome = handle_battle_command(
+            &mut manager,
+            &message,
+            Some(&mut store),
+            None,
+        )
+        .unwrap();
+
+        assert!(outcome.reply.contains("伤害掷骰"));
+        assert!(outcome.notices.is_empty());
+        assert!(!outcome.private_reply);
+        assert_eq!(
+            store.encounters["battle"].participants[1].hp,
+            7.0
+        );
+
+        assert_eq!(
+            handle_battle_command(
+                &mut manager,
+                &test_message_with_text(
+                    NapcatMessageType::Private,
+                    ".攻击 @艾琳",
+                ),
+                Some(&mut BattleRoundStore::default()),
+                None,
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn hidden_party_group_attack_fans_out_privately() {
+        let mut manager = empty_manager();
+        manager.trpg_groups.insert("party".to_owned(), TrpgGroup {
+            players: vec!["2".to_owned(), "3".to_owned(), "4".to_owned()],
+            group_chats: vec!["500".to_owned()],
+            player_parties: HashMap::from([
+                ("2".to_owned(), "red".to_owned()),
+                ("3".to_owned(), "red".to_owned()),
+                ("4".to_owned(), "blue".to_owned()),
+            ]),
+            ..Default::default()
+        });
+        let mut store = BattleRoundStore::default();
+        store
+            .encounters
+            .insert("battle".to_owned(), BattleEncounter {
+                trpg_group: Some("party".to_owned()),
+                participants: vec![
+                    participant(serde_json::json!({
+                        "target_id": "2",
+                        "display_name": "艾琳",
+                        "player_character": true,
+                        "hp": 10.0,
+                        "max_hp": 10.0,
+                    })),
+                    participant(serde_json::json!({
+                        "target_id": "goblin",
+                        "display_name": "哥布林",
+                        "action_done": true,
+                        "hp": 10.0,
+                        "max_hp": 10.0,
+                    })),
+                ],
+                chat_attack_damage: "3".to_owned(),
+                ..Default::default()
+            });
+        let mut message = test_message_with_text(
+            NapcatMessageType::Group,
+            ".攻击 @哥布林",
+        );
+        message.data.group_id = Some(500);
+
+        let outcome = handle_battle_command(
+            &mut manager,
+            &message,
+            Some(&mut store),
+            None,
+        )
+        .unwrap();
+
+        assert!(outcome.private_reply);
+        assert!(outcome.reply.contains("伤害掷骰"));
+        assert_eq!(
+            outcome
+                .notices
+                .iter()
+                .map(|(recipient, _)| recipient.as_str())
+                .collect::<Vec<_>>(),
+            vec!["3"]
+        );
+    }
+}
@@ -0,0 +1,539 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use crossbeam_channel::Sender as CBSender;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::sync::{
+    mpsc::{
+        self,
+        error::TrySendError,
+        Receiver,
+    },
+    oneshot,
+    watch,
+};
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest,
+    handshake::client::Request,
+    http::{
+        header::AUTHORIZATION,
+        HeaderValue,
+    },
+};
+use url::Url;
+
+use super::{
+    transport::{
+        NapcatConnectionTask,
+        NapcatTransport,
+    },
+    NapcatInboundMessage,
+    NapcatOutboundMessage,
+    NapcatSendResult,
+};
+
+pub const DEFAULT_NAPCAT_WS_URL: &str = "ws://localhost:3001";
+pub const DEFAULT_NAPCAT_CONNECTION_ID: &str = "default";
+pub const DEFAULT_NAPCAT_LISTEN_ADDR: &str = "127.0.0.1:8080";
+
+const NAPCAT_CONNECTION_QUEUE_CAPACITY: usize = 100;
+
+/// One OneBot account. Messages received through it are tagged with `id`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NapcatConnectionProfile {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub transport: NapcatTransport,
+    /// Websocket server for forward mode, HTTP API base for HTTP POST mode.
+    #[serde(default = "default_napcat_ws_url")]
+    pub url: String,
+    /// Local address the reverse websocket or HTTP event receiver binds.
+    #[serde(default = "default_napcat_listen_addr")]
+    pub listen_addr: String,
+    /// Sent as `Authorization: Bearer <token>` when dialing out; required from reverse
+    /// websocket clients when not empty.
+    #[serde(default)]
+    pub access_token: String,
+    /// HMAC-SHA1 key checked against `X-Signature` on HTTP POST events; empty disables it.
+    #[serde(default)]
+    pub secret: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_reconnect_initial_ms")]
+    pub reconnect_initial_ms: u64,
+    #[serde(default = "default_reconnect_max_ms")]
+    pub reconnect_max_ms: u64,
+    #[serde(default = "default_reconnect_multiplier")]
+    pub reconnect_multiplier: f32,
+}
+
+fn default_napcat_ws_url() -> String { DEFAULT_NAPCAT_WS_URL.to_owned() }
+
+fn default_napcat_listen_addr() -> String { DEFAULT_NAPCAT_LISTEN_ADDR.to_owned() }
+
+fn default_true() -> bool { true }
+
+fn default_reconnect_initial_ms() -> u64 { 2_000 }
+
+fn default_reconnect_max_ms() -> u64 { 60_000 }
+
+fn default_reconnect_multiplier() -> f32 { 2.0 }
+
+impl NapcatConnectionProfile {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            name: String::new(),
+            transport: NapcatTransport::default(),
+            url: default_napcat_ws_url(),
+            listen_addr: default_napcat_listen_addr(),
+            access_token: String::new(),
+            secret: String::new(),
+            enabled: true,
+            reconnect_initial_ms: default_reconnect_initial_ms(),
+            reconnect_max_ms: default_reconnect_max_ms(),
+            reconnect_multiplier: default_reconnect_multiplier(),
+        }
+    }
+
+    pub fn display_name(&self) -> &str {
+        let name = self.name.trim();
+        if name.is_empty() {
+            &self.id
+        } else {
+            name
+        }
+    }
+
+    /// Delay before the `attempt`-th reconnect (0-based), capped at `reconnect_max_ms`.
+    pub fn reconnect_delay(&self, attempt: u32) -> Duration {
+        let initial = self.reconnect_initial_ms.max(100) as f64;
+        let max = self
+            .reconnect_max_ms
+            .max(self.reconnect_initial_ms.max(100)) as f64;
+        let multiplier = (self.reconnect_multiplier as f64).max(1.0);
+        let delay = initial * multiplier.powi(attempt.min(64) as i32);
+        Duration::from_millis(delay.min(max) as u64)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.transport.listens() {
+            self.listen_addr
+                .trim()
+                .parse::<SocketAddr>()
+                .map_err(|err| format!("监听地址无效：{err}"))?;
+        }
+        match self.transport {
+            NapcatTransport::ForwardWebsocket => self.connection_request().map(|_| ()),
+            NapcatTransport::ReverseWebsocket => Ok(()),
+            NapcatTransport::HttpPost => {
+                let url = Url::parse(self.url.trim())
+                    .map_err(|err| format!("HTTP API地址无效：{err}"))?;
+                if matches!(url.scheme(), "http" | "https") {
+                    Ok(())
+                } else {
+                    Err("HTTP API地址必须以http://或https://开头".to_owned())
+                }
+            },
+        }
+    }
+
+    pub fn connection_request(&self) -> Result<Request, String> {
+        let mut request = self
+            .url
+            .trim()
+            .into_client_request()
+            .map_err(|err| format!("NapCat连接地址无效：{err}"))?;
+        let token = self.access_token.trim();
+        if !token.is_empty() {
+            let value = HeaderValue::from_str(&format!("Bearer {token}"))
+                .map_err(|err| format!("NapCat access_token无效：{err}"))?;
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+        Ok(request)
+    }
+}
+
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NapcatConnectionProfiles {
+    #[serde(default)]
+    pub profiles: Vec<NapcatConnectionProfile>,
+}
+
+impl Default for NapcatConnectionProfiles {
+    fn default() -> Self {
+        Self {
+            profiles: vec![NapcatConnectionProfile::new(DEFAULT_NAPCAT_CONNECTION_ID)],
+        }
+    }
+}
+
+impl NapcatConnectionProfiles {
+    pub fn profile(&self, id: &str) -> Option<&NapcatConnectionProfile> {
+        self.profiles.iter().find(|profile| profile.id == id)
+    }
+
+    pub fn next_profile_id(&self) -> String {
+        (self.profiles.len() + 1..)
+            .map(|index| format!("account-{index}"))
+            .find(|id| self.profile(id).is_none())
+            .unwrap_or_default()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let mut seen = HashMap::new();
+        for profile in &self.profiles {
+            let id = profile.id.trim();
+            if id.is_empty() {
+                return Err("连接ID不能为空".to_owned());
+            }
+            if seen.insert(id, ()).is_some() {
+                return Err(format!("连接ID重复：{id}"));
+            }
+            profile
+                .validate()
+                .map_err(|err| format!("{}：{err}", profile.display_name()))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NapcatConnectionStatus {
+    Connecting,
+    Connected,
+    Disconnected(String),
+    Disabled,
+}
+
+impl NapcatConnectionStatus {
+    pub fn label(&self) -> String {
+        match self {
+            Self::Connecting => "连接中".to_owned(),
+            Self::Connected => "已连接".to_owned(),
+            Self::Disconnected(err) => format!("未连接：{err}"),
+            Self::Disabled => "已停用".to_owned(),
+        }
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct NapcatConnectionStatuses(pub HashMap<String, NapcatConnectionStatus>);
+
+impl NapcatConnectionStatuses {
+    pub fn any_connected(&self) -> bool {
+        self.0
+            .values()
+            .any(|status| *status == NapcatConnectionStatus::Connected)
+    }
+}
+
+/// Snapshot pushed from the game to the websocket thread whenever profiles or
+/// chat-target accounts change.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct NapcatRouting {
+    pub profiles: Vec<NapcatConnectionProfile>,
+    pub target_accounts: HashMap<String, String>,
+}
+
+impl NapcatRouting {
+    /// Explicit account first, then the account that last received the target, then the first
+    /// enabled profile.
+    pub fn route(&self, account_id: Option<&str>, target_id: &str) -> Result<&str, String> {
+        let enabled = |id: &str| {
+            self.profiles
+                .iter()
+                .find(|profile| profile.enabled && profile.id == id)
+                .map(|profile| profile.id.as_str())
+        };
+        if let Some(account_id) = account_id {
+            return enabled(account_id).ok_or_else(|| format!("NapCat连接「{account_id}」未启用"));
+        }
+        self.target_accounts
+            .get(target_id)
+            .and_then(|account_id| enabled(account_id))
+            .or_else(|| {
+                self.profiles
+                    .iter()
+                    .find(|profile| profile.enabled)
+                    .map(|profile| profile.id.as_str())
+            })
+            .ok_or_else(|| "没有启用的NapCat连接".to_owned())
+    }
+}
+
+#[derive(Resource)]
+pub(super) struct NapcatRoutingSender(pub watch::Sender<NapcatRouting>);
+
+pub(super) type NapcatStatusSender = CBSender<(String, NapcatConnectionStatus)>;
+
+struct NapcatConnectionHandle {
+    profile: NapcatConnectionProfile,
+    outbound: mpsc::Sender<NapcatOutboundMessage>,
+    // Dropping the handle closes this and stops the connection task.
+    _shutdown: oneshot::Sender<()>,
+}
+
+pub(super) async fn run_napcat_connections(
+    client_to_game_sender: CBSender<NapcatInboundMessage>,
+    mut game_to_client_receiver: Receiver<NapcatOutboundMessage>,
+    send_result_sender: CBSender<NapcatSendResult>,
+    status_sender: NapcatStatusSender,
+    mut routing_receiver: watch::Receiver<NapcatRouting>,
+) {
+    let mut connections = HashMap::<String, NapcatConnectionHandle>::new();
+    let mut routing = routing_receiver.borrow_and_update().clone();
+    reconcile_napcat_connections(
+        &mut connections,
+        &routing.profiles,
+        &client_to_game_sender,
+        &send_result_sender,
+        &status_sender,
+    );
+
+    loop {
+        tokio::select! {
+            changed = routing_receiver.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                routing = routing_receiver.borrow_and_update().clone();
+                reconcile_napcat_connections(
+                    &mut connections,
+                    &routing.profiles,
+                    &client_to_game_sender,
+                    &send_result_sender,
+                    &status_sender,
+                );
+            }
+            outbound = game_to_client_receiver.recv() => {
+                let Some(outbound) = outbound else {
+                    return;
+                };
+                let routed = routing
+                    .route(outbound.account_id.as_deref(), &outbound.target_id)
+                    .map(str::to_owned)
+                    .and_then(|account_id| {
+                        connections
+                            .get(&account_id)
+                            .ok_or_else(|| format!("NapCat连接「{account_id}」未启动"))
+                    });
+                let connection = match routed {
+                    Ok(connection) => connection,
+                    Err(error) => {
+                        fail_napcat_outbound(&send_result_sender, outbound, error);
+                        continue;
+                    },
+                };
+                match connection.outbound.try_send(outbound) {
+                    Ok(()) => {},
+                    Err(TrySendError::Full(outbound)) => fail_napcat_outbound(
+                        &send_result_sender,
+                        outbound,
+                        format!("NapCat连接「{}」发送队列已满", connection.profile.display_name()),
+                    ),
+                    Err(TrySendError::Closed(outbound)) => fail_napcat_outbound(
+                        &send_result_sender,
+                        outbound,
+                        format!("NapCat连接「{}」已关闭", connection.profile.display_name()),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+fn reconcile_napcat_connections(
+    connections: &mut HashMap<String, NapcatConnectionHandle>,
+    profiles: &[NapcatConnectionProfile],
+    client_to_game_sender: &CBSender<NapcatInboundMessage>,
+    send_result_sender: &CBSender<NapcatSendResult>,
+    status_sender: &NapcatStatusSender,
+) {
+    connections.retain(|id, handle| {
+        let keep = profiles
+            .iter()
+            .any(|profile| profile.enabled && profile.id == *id && *profile == handle.profile);
+        if !keep {
+            let _ = status_sender.send((
+                id.clone(),
+                NapcatConnectionStatus::Disabled,
+            ));
+        }
+        keep
+    });
+
+    for profile in profiles {
+        if !profile.enabled {
+            let _ = status_sender.send((
+                profile.id.clone(),
+                NapcatConnectionStatus::Disabled,
+            ));
+            continue;
+        }
+        if connections.contains_key(&profile.id) {
+            continue;
+        }
+        let (outbound_sender, outbound_receiver) = mpsc::channel(NAPCAT_CONNECTION_QUEUE_CAPACITY);
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+        tokio::spawn(
+            NapcatConnectionTask {
+                profile: profile.clone(),
+                client_to_game_sender: client_to_game_sender.clone(),
+                game_to_client_receiver: outbound_receiver,
+                send_result_sender: send_result_sender.clone(),
+                status_sender: status_sender.clone(),
+                shutdown: shutdown_receiver,
+            }
+            .run(),
+        );
+        connections.insert(
+            profile.id.clone(),
+            NapcatConnectionHandle {
+                profile: profile.clone(),
+                outbound: outbound_sender,
+                _shutdown: shutdown_sender,
+            },
+        );
+    }
+}
+
+pub(super) fn fail_napcat_outbound(
+    send_result_sender: &CBSender<NapcatSendResult>,
+    outbound: NapcatOutboundMessage,
+    error: String,
+) {
+    let _ = send_result_sender.send(NapcatSendResult {
+        request_id: outbound.request_id,
+        target_id: outbound.target_id,
+        error: Some(error),
+        delivery_unknown: false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(id: &str, enabled: bool) -> NapcatConnectionProfile {
+        NapcatConnectionProfile {
+            enabled,
+            ..NapcatConnectionProfile::new(id)
+        }
+    }
+
+    #[test]
+    fn reconnect_delay_grows_exponentially_up_to_the_cap() {
+        let profile = NapcatConnectionProfile {
+            reconnect_initial_ms: 1_000,
+            reconnect_max_ms: 5_000,
+            reconnect_multiplier: 2.0,
+            ..NapcatConnectionProfile::new("a")
+        };
+
+        assert_eq!(
+            profile.reconnect_delay(0),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            profile.reconnect_delay(2),
+            Duration::from_secs(4)
+        );
+        assert_eq!(
+            profile.reconnect_delay(3),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            profile.reconnect_delay(u32::MAX),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn access_token_is_sent_as_bearer_authorization() {
+        let profile = NapcatConnectionProfile {
+            url: "ws://10.0.0.2:3001".to_owned(),
+            access_token: "secret".to_owned(),
+            ..NapcatConnectionProfile::new("remote")
+        };
+
+        let request = profile.connection_request().unwrap();
+
+        assert_eq!(request.uri(), "ws://10.0.0.2:3001/");
+        assert_eq!(
+            request.headers().get(AUTHORIZATION).unwrap(),
+            "Bearer secret"
+        );
+        assert!(NapcatConnectionProfile::new("local")
+            .connection_request()
+            .unwrap()
+            .headers()
+            .get(AUTHORIZATION)
+            .is_none());
+    }
+
+    #[test]
+    fn profiles_reject_duplicate_ids_and_invalid_urls() {
+        let mut profiles = NapcatConnectionProfiles::default();
+        assert!(profiles.validate().is_ok());
+
+        profiles.profiles.push(profile(
+            DEFAULT_NAPCAT_CONNECTION_ID,
+            true,
+        ));
+        assert!(profiles.validate().unwrap_err().contains("重复"));
+
+        profiles.profiles[1] = NapcatConnectionProfile {
+            url: "not a url".to_owned(),
+            ..profile("second", true)
+        };
+        assert!(profiles.validate().is_err());
+        assert_eq!(profiles.next_profile_id(), "account-3");
+
+        profiles.profiles[1] = NapcatConnectionProfile {
+            transport: NapcatTransport::HttpPost,
+            url: "ws://127.0.0.1:3000".to_owned(),
+            ..profile("second", true)
+        };
+        assert!(profiles.validate().is_err());
+        profiles.profiles[1].url = "http://127.0.0.1:3000".to_owned();
+        profiles.profiles[1].listen_addr = "localhost".to_owned();
+        assert!(profiles.validate().is_err());
+        profiles.profiles[1].listen_addr = "0.0.0.0:5700".to_owned();
+        assert!(profiles.validate().is_ok());
+    }
+
+    #[test]
+    fn replies_route_through_the_receiving_account() {
+        let routing = NapcatRouting {
+            profiles: vec![
+                profile("main", true),
+                profile("side", true),
+                profile("off", false),
+            ],
+            target_accounts: HashMap::from([
+                ("100".to_owned(), "side".to_owned()),
+                ("200".to_owned(), "off".to_owned()),
+            ]),
+        };
+
+        assert_eq!(routing.route(None, "100"), Ok("side"));
+        assert_eq!(routing.route(None, "200"), Ok("main"));
+        assert_eq!(routing.route(None, "300"), Ok("main"));
+        assert_eq!(
+            routing.route(Some("main"), "100"),
+            Ok("main")
+        );
+        assert!(routing.route(Some("off"), "100").is_err());
+        assert!(NapcatRouting::default().route(None, "100").is_err());
+    }
+}
@@ -0,0 +1,579 @@
+use rand::RngExt;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    character_creation_prompt,
+    character_status_confirmation,
+    inventory::add_item_to_inventory,
+    set_character_status_value,
+    total_allocated_status_points,
+    CharacterCreationStep,
+    CharacterSkillMetadata,
+    CharacterSkillSourceKind,
+    InventoryItem,
+    PlayerCharacter,
+    SkillPoolEntry,
+    StatusKey,
+};
+use crate::dice::{
+    parse_dice_expression,
+    roll_dice_expression_with,
+};
+
+/// How a TRPG group's players build characters in the `.兑换` wizard. The default template is the
+/// original flow: point-buy stats, free-text skills, then image and nickname.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrpgCreationTemplate {
+    #[serde(default)]
+    pub stat_mode: CreationStatMode,
+    /// Dice expression rolled once per stat in `Rolled` mode.
+    #[serde(default = "default_creation_stat_roll")]
+    pub stat_roll: String,
+    #[serde(default)]
+    pub race_step: bool,
+    /// Races offered in the race step; when empty the player types one freely.
+    #[serde(default)]
+    pub races: Vec<CreationChoice>,
+    #[serde(default)]
+    pub background_step: bool,
+    #[serde(default)]
+    pub backgrounds: Vec<CreationChoice>,
+    /// Limits the skill step to skill pool entries carrying one of these tags. Empty keeps
+    /// free-text skill submissions for GM review.
+    #[serde(default)]
+    pub skill_tags: Vec<String>,
+    /// How many tagged skills a player may pick; 0 means no limit.
+    #[serde(default = "default_creation_skill_picks")]
+    pub skill_picks: u32,
+    #[serde(default)]
+    pub starting_kits: Vec<StartingKit>,
+}
+
+impl Default for TrpgCreationTemplate {
+    fn default() -> Self {
+        Self {
+            stat_mode: CreationStatMode::default(),
+            stat_roll: default_creation_stat_roll(),
+            race_step: false,
+            races: Vec::new(),
+            background_step: false,
+            backgrounds: Vec::new(),
+            skill_tags: Vec::new(),
+            skill_picks: default_creation_skill_picks(),
+            starting_kits: Vec::new(),
+        }
+    }
+}
+
+fn default_creation_stat_roll() -> String { "3d6".to_owned() }
+fn default_creation_skill_picks() -> u32 { 2 }
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CreationStatMode {
+    #[default]
+    PointBuy,
+    Rolled,
+}
+
+impl CreationStatMode {
+    pub const ALL: [CreationStatMode; 2] = [CreationStatMode::PointBuy, CreationStatMode::Rolled];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CreationStatMode::PointBuy => "点数购买",
+            CreationStatMode::Rolled => "掷骰",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct CreationChoice {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// A starting equipment package. Items are item pool names, looked up when the character is
+/// finished; a name listed twice gives two copies.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct StartingKit {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub items: Vec<String>,
+    #[serde(default)]
+    pub gold: u32,
+}
+
+impl TrpgCreationTemplate {
+    pub(super) fn first_step(&self) -> CharacterCreationStep {
+        if self.race_step {
+            CharacterCreationStep::Race
+        } else {
+            self.next_step(CharacterCreationStep::Race)
+        }
+    }
+
+    /// The step that follows one of the template-dependent steps. The stat phase is entered
+    /// through `Str` and settled by `enter_status_phase`.
+    pub(super) fn next_step(&self, step: CharacterCreationStep) -> CharacterCreationStep {
+        match step {
+            CharacterCreationStep::Race if self.background_step => {
+                CharacterCreationStep::Background
+            },
+            CharacterCreationStep::Race | CharacterCreationStep::Background => {
+                CharacterCreationStep::Str
+            },
+            CharacterCreationStep::ConfirmSkill if !self.starting_kits.is_empty() => {
+                CharacterCreationStep::Kit
+            },
+            CharacterCreationStep::ConfirmSkill | CharacterCreationStep::Kit => {
+                CharacterCreationStep::Image
+            },
+            step => step,
+        }
+    }
+
+    pub(super) fn previous_step(
+        &self,
+        step: CharacterCreationStep,
+    ) -> Option<CharacterCreationStep> {
+        match step {
+            CharacterCreationStep::Race => None,
+            CharacterCreationStep::Background => {
+                self.race_step.then_some(CharacterCreationStep::Race)
+            },
+            CharacterCreationStep::Image if !self.starting_kits.is_empty() => {
+                Some(CharacterCreationStep::Kit)
+            },
+            CharacterCreationStep::Image | CharacterCreationStep::Kit => {
+                Some(CharacterCreationStep::Skill)
+            },
+            step if step.status_key().is_some() || step == CharacterCreationStep::ConfirmStatus => {
+                if self.background_step {
+                    Some(CharacterCreationStep::Background)
+                } else {
+                    self.race_step.then_some(CharacterCreationStep::Race)
+                }
+            },
+            _ => None,
+        }
+    }
+
+    pub(super) fn limits_skills(&self) -> bool {
+        self.skill_tags.iter().any(|tag| !tag.trim().is_empty())
+    }
+}
+
+/// Everything the wizard reads besides the character itself.
+pub(super) struct CreationContext<'a> {
+    pub template: &'a TrpgCreationTemplate,
+    pub skill_pool: &'a [SkillPoolEntry],
+    pub item_pool: &'a [InventoryItem],
+}
+
+impl CreationContext<'_> {
+    fn skill_candidates(&self) -> Vec<&SkillPoolEntry> {
+        self.skill_pool
+            .iter()
+            .filter(|entry| {
+                entry
+                    .tags
+                    .iter()
+                    .any(|tag| self.template.skill_tags.contains(tag))
+            })
+            .collect()
+    }
+}
+
+const CREATION_STATUS_KEYS: [StatusKey; 8] = [
+    StatusKey::Str,
+    StatusKey::Agi,
+    StatusKey::Dex,
+    StatusKey::Vit,
+    StatusKey::Int,
+    StatusKey::Wis,
+    StatusKey::K,
+    StatusKey::Cha,
+];
+
+/// Starts the stat phase: point-buy walks the stats one by one, rolled mode rolls every stat
+/// once and goes straight to confirmation. Stats that were already rolled are kept, so stepping
+/// back to the race or background step cannot be used to reroll.
+pub(super) fn enter_status_phase(
+    character: &mut PlayerCharacter,
+    context: &CreationContext,
+) -> String {
+    enter_status_phase_with(character, context, |sides| {
+        rand::rng().random_range(1..=sides)
+    })
+}
+
+fn enter_status_phase_with(
+    character: &mut PlayerCharacter,
+    context: &CreationContext,
+    mut roll_die: impl FnMut(u32) -> u32,
+) -> String {
+    if context.template.stat_mode == CreationStatMode::PointBuy {
+        character.creation_step = CharacterCreationStep::Str;
+        return format!(
+            "你拥有{}点属性点，请将它分配到力量/敏捷/灵巧/体质/智力/智慧/知识/魅力上。\n请直接输入数字来增加当前属性；输入【..】退回上一个属性。\n{}",
+            character.status_points,
+            character_creation_prompt(character, context)
+        );
+    }
+
+    if total_allocated_status_points(&character.status) == 0 {
+        let expression = match parse_dice_expression(&context.template.stat_roll) {
+            Ok(expression) => expression,
+            Err(err) => return format!("建卡模板的属性掷骰无效：{err}请联系GM。"),
+        };
+        for status_key in CREATION_STATUS_KEYS {
+            let roll = match roll_dice_expression_with(&expression, |_| None, &mut roll_die) {
+                Ok(roll) => roll,
+                Err(err) => return format!("建卡模板的属性掷骰无效：{err}请联系GM。"),
+            };
+            set_character_status_value(
+                &mut character.status,
+                status_key,
+                roll.total.max(0),
+            );
+        }
+        character.status_points = 0;
+    }
+    character.creation_step = CharacterCreationStep::ConfirmStatus;
+    format!(
+        "已按{}为你掷出全部属性。\n{}",
+        context.template.stat_roll,
+        character_status_confirmation(character)
+    )
+}
+
+/// Reads a race or background answer: an option number or name, or free text when the template
+/// lists no options.
+pub(super) fn pick_creation_choice(choices: &[CreationChoice], text: &str) -> Option<String> {
+    let text = text.trim();
+    if choices.is_empty() {
+        return (!text.is_empty()).then(|| text.to_owned());
+    }
+    text.parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_sub(1))
+        .and_then(|index| choices.get(index))
+        .or_else(|| choices.iter().find(|choice| choice.name == text))
+        .map(|choice| choice.name.clone())
+}
+
+pub(super) fn creation_choice_prompt(title: &str, choices: &[CreationChoice]) -> String {
+    if choices.is_empty() {
+        return format!("请直接发送你的{title}。");
+    }
+    let lines = choices
+        .iter()
+        .enumerate()
+        .map(|(index, choice)| {
+            if choice.description.trim().is_empty() {
+                format!("{}. {}", index + 1, choice.name)
+            } else {
+                format!(
+                    "{}. {} - {}",
+                    index + 1,
+                    choice.name,
+                    choice.description.trim()
+                )
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("请选择你的{title}，发送编号或名称：\n{lines}")
+}
+
+fn creation_skill_pick_count(character: &PlayerCharacter) -> usize {
+    character
+        .skill_metadata
+        .iter()
+        .filter(|metadata| metadata.source == CharacterSkillSourceKind::SkillPool)
+        .count()
+}
+
+pub(super) fn creation_skill_prompt(
+    character: &PlayerCharacter,
+    context: &CreationContext,
+) -> String {
+    let candidates = context.skill_candidates();
+    if candidates.is_empty() {
+        return "技能池里没有符合本团标签的技能，输入【.】跳过技能选择。".to_owned();
+    }
+    let limit = match context.template.skill_picks {
+        0 => "不限数量".to_owned(),
+        picks => format!(
+            "还可以选择{}个",
+            (picks as usize).saturating_sub(creation_skill_pick_count(character))
+        ),
+    };
+    let lines = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            format!(
+                "{}. {}：{}",
+                index + 1,
+                entry.name,
+                entry.note
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("请选择技能（{limit}），发送编号或技能名；输入【.】结束技能选择。\n{lines}")
+}
+
+pub(super) fn pick_creation_skill(
+    character: &mut PlayerCharacter,
+    context: &CreationContext,
+    text: &str,
+) -> String {
+    let candidates = context.skill_candidates();
+    let text = text.trim();
+    let Some(entry) = text
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_sub(1))
+        .and_then(|index| candidates.get(index))
+        .or_else(|| candidates.iter().find(|entry| entry.name == text))
+    else {
+        return format!(
+            "没有找到这个技能。\n{}",
+            creation_skill_prompt(character, context)
+        );
+    };
+    let already_picked = character
+        .skill_names
+        .iter()
+        .zip(&character.skill_notes)
+        .any(|(name, note)| *name == entry.name && *note == entry.note);
+    if already_picked {
+        return format!("你已经选择过「{}」了。", entry.name);
+    }
+
+    character.skill_names.push(entry.name.clone());
+    character.skill_notes.push(entry.note.clone());
+    character.skill_mp_costs.push(entry.mp_cost.max(0.0));
+    character.skill_cooldown_turns.push(entry.cooldown_turns);
+    character
+        .skill_metadata
+        .push(CharacterSkillMetadata::skill_pool(
+            entry,
+        ));
+    let picks = context.template.skill_picks as usize;
+    if picks > 0 && creation_skill_pick_count(character) >= picks {
+        character.creation_step = CharacterCreationStep::ConfirmSkill;
+        return format!(
+            "已选择技能「{}」，技能选择完成，输入【.】继续。",
+            entry.name
+        );
+    }
+    format!(
+        "已选择技能「{}」。继续发送编号，或输入【.】结束技能选择。",
+        entry.name
+    )
+}
+
+pub(super) fn starting_kit_prompt(kits: &[StartingKit]) -> String {
+    let lines = kits
+        .iter()
+        .enumerate()
+        .map(|(index, kit)| {
+            let mut contents = kit.items.clone();
+            if kit.gold > 0 {
+                contents.push(format!("{}金币", kit.gold));
+            }
+            format!(
+                "{}. {}：{}",
+                index + 1,
+                kit.name,
+                if contents.is_empty() { "空".to_owned() } else { contents.join("、") }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("请选择一套起始装备，发送编号或名称：\n{lines}")
+}
+
+pub(super) fn pick_starting_kit(kits: &[StartingKit], text: &str) -> Option<String> {
+    let text = text.trim();
+    text.parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_sub(1))
+        .and_then(|index| kits.get(index))
+        .or_else(|| kits.iter().find(|kit| kit.name == text))
+        .map(|kit| kit.name.clone())
+}
+
+/// Hands out the chosen kit when the character is finished. Names missing from the item pool
+/// are skipped.
+pub(super) fn grant_starting_kit(character: &mut PlayerCharacter, context: &CreationContext) {
+    let Some(kit) = context
+        .template
+        .starting_kits
+        .iter()
+        .find(|kit| kit.name == character.starting_kit)
+    else {
+        return;
+    };
+    for name in &kit.items {
+        if let Some(item) = context.item_pool.iter().find(|item| item.name == *name) {
+            add_item_to_inventory(&mut character.inventory, item.clone());
+        }
+    }
+    character.inventory.gold = character.inventory.gold.saturating_add(kit.gold);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napcat::{
+        handle_character_creation_message,
+        tests::{
+            empty_manager,
+            test_message_with_text,
+        },
+        NapcatMessageManager,
+        NapcatMessageType,
+        TrpgGroup,
+    };
+
+    fn send(manager: &mut NapcatMessageManager, text: &str) -> String {
+        handle_character_creation_message(
+            manager,
+            &test_message_with_text(NapcatMessageType::Private, text),
+            "2",
+        )
+        .unwrap_or_default()
+    }
+
+    fn manager_with_template(template: TrpgCreationTemplate) -> NapcatMessageManager {
+        let mut manager = empty_manager();
+        manager.trpg_groups.insert("party".to_owned(), TrpgGroup {
+            players: vec!["2".to_owned()],
+            creation_template: template,
+            ..Default::default()
+        });
+        manager
+    }
+
+    #[test]
+    fn rolled_stats_are_rolled_once_and_kept_when_stepping_back() {
+        let template = TrpgCreationTemplate {
+            stat_mode: CreationStatMode::Rolled,
+            stat_roll: "2d6".to_owned(),
+            background_step: true,
+            ..Default::default()
+        };
+        let skill_pool = Vec::new();
+        let item_pool = Vec::new();
+        let context = CreationContext {
+            template: &template,
+            skill_pool: &skill_pool,
+            item_pool: &item_pool,
+        };
+        let mut character = PlayerCharacter::default();
+
+        let reply = enter_status_phase_with(&mut character, &context, |_| 3);
+
+        assert!(reply.contains("已按2d6为你掷出全部属性"));
+        assert_eq!(
+            character.creation_step,
+            CharacterCreationStep::ConfirmStatus
+        );
+        assert_eq!(character.status.str_, 6);
+        assert_eq!(character.status.cha, 6);
+        assert_eq!(character.status_points, 0);
+
+        enter_status_phase_with(&mut character, &context, |_| 1);
+        assert_eq!(character.status.str_, 6);
+    }
+
+    #[test]
+    fn template_wizard_walks_race_background_tagged_skills_and_kit() {
+        let mut manager = manager_with_template(TrpgCreationTemplate {
+            race_step: true,
+            races: vec![
+                CreationChoice {
+                    name: "人类".to_owned(),
+                    description: String::new(),
+                },
+                CreationChoice {
+                    name: "精灵".to_owned(),
+                    description: "长寿".to_owned(),
+                },
+            ],
+            background_step: true,
+            skill_tags: vec!["新手".to_owned()],
+            skill_picks: 1,
+            starting_kits: vec![StartingKit {
+                name: "冒险者".to_owned(),
+                items: vec!["短剑".to_owned()],
+                gold: 30,
+            }],
+            ..Default::default()
+        });
+        manager.skill_pool = vec![
+            SkillPoolEntry {
+                name: "火球".to_owned(),
+                note: "主动使用对目标造成2点伤害".to_owned(),
+                tags: vec!["新手".to_owned()],
+                ..Default::default()
+            },
+            SkillPoolEntry {
+                name: "陨石".to_owned(),
+                note: "主动使用对目标造成9点伤害".to_owned(),
+                tags: vec!["高阶".to_owned()],
+                ..Default::default()
+            },
+        ];
+        manager.item_pool = vec![InventoryItem {
+            name: "短剑".to_owned(),
+            ..Default::default()
+        }];
+
+        let reply = send(&mut manager, ".兑换");
+        assert!(reply.contains("2. 精灵 - 长寿"));
+        assert!(send(&mut manager, "3").contains("请选择你的种族"));
+        send(&mut manager, "精灵");
+        assert_eq!(
+            manager.player_characters["2"].creation_step,
+            CharacterCreationStep::Background
+        );
+        let reply = send(&mut manager, "流浪学者");
+        assert!(reply.contains("属性点"));
+        for value in ["2", "1", "1", "1"] {
+            send(&mut manager, value);
+        }
+
+        let reply = send(&mut manager, ".");
+        assert!(reply.contains("1. 火球"));
+        assert!(!reply.contains("陨石"));
+        assert!(send(&mut manager, "1").contains("技能选择完成"));
+        assert!(send(&mut manager, ".").contains("1. 冒险者：短剑、30金币"));
+        send(&mut manager, "1");
+        send(&mut manager, ".");
+        send(&mut manager, "艾琳");
+
+        let character = &manager.player_characters["2"];
+        assert!(character.inited);
+        assert_eq!(character.race, "精灵");
+        assert_eq!(character.background, "流浪学者");
+        assert_eq!(character.skill_names, vec![
+            "火球".to_owned()
+        ]);
+        assert!(character.skill_metadata[0].is_approved());
+        assert_eq!(character.starting_kit, "冒险者");
+        assert_eq!(character.inventory.items.len(), 1);
+        assert_eq!(character.inventory.gold, 30);
+    }
+}
@@ -0,0 +1,952 @@
+use super::{
+    private_command_body,
+    CharacterHotbarSlot,
+    CharacterInventory,
+    EquipmentSlot,
+    InventoryItem,
+    InventoryQuality,
+    NapcatMessageManager,
+    PlayerCharacter,
+};
+use crate::{
+    battle_round::BattleRoundStore,
+    rule_engine::RuleEngineState,
+    scene::SceneCharacterPositions,
+    ui::{
+        sync_character_buffs,
+        use_character_item,
+    },
+};
+
+/// Skill index given to consumable items when they resolve through the skill pipelines, so using
+/// one never starts a skill cooldown.
+pub(crate) const CONSUMABLE_ITEM_SKILL_INDEX: usize = usize::MAX;
+
+pub(crate) fn shift_character_hotbar_after_remove(
+    hotbar: &mut [CharacterHotbarSlot],
+    removed: CharacterHotbarSlot,
+) {
+    for slot in hotbar {
+        *slot = match (*slot, removed) {
+            (CharacterHotbarSlot::Item(index), CharacterHotbarSlot::Item(removed_index))
+                if index == removed_index =>
+            {
+                CharacterHotbarSlot::Empty
+            },
+            (CharacterHotbarSlot::Item(index), CharacterHotbarSlot::Item(removed_index))
+                if index > removed_index =>
+            {
+                CharacterHotbarSlot::Item(index - 1)
+            },
+            (CharacterHotbarSlot::Skill(index), CharacterHotbarSlot::Skill(removed_index))
+                if index == removed_index =>
+            {
+                CharacterHotbarSlot::Empty
+            },
+            (CharacterHotbarSlot::Skill(index), CharacterHotbarSlot::Skill(removed_index))
+                if index > removed_index =>
+            {
+                CharacterHotbarSlot::Skill(index - 1)
+            },
+            (current, _) => current,
+        };
+    }
+}
+
+pub(crate) fn remove_character_inventory_item(
+    character: &mut PlayerCharacter,
+    index: usize,
+    equip: bool,
+) {
+    if index >= character.inventory.items.len() {
+        return;
+    }
+    shift_character_hotbar_after_remove(
+        &mut character.inventory.hotbar,
+        CharacterHotbarSlot::Item(index),
+    );
+    if equip {
+        equip_inventory_item(&mut character.inventory, index);
+    } else {
+        character.inventory.items.remove(index);
+    }
+}
+
+pub(crate) fn normalize_item(item: &mut InventoryItem) -> bool {
+    let mut changed = false;
+    if item.max_stack == 0 {
+        item.max_stack = 1;
+        changed = true;
+    }
+    if item.stack == 0 {
+        item.stack = 1;
+        changed = true;
+    }
+    if item.stack > item.max_stack {
+        item.stack = item.max_stack;
+        changed = true;
+    }
+    changed
+}
+
+pub(crate) fn add_item_to_inventory(inventory: &mut CharacterInventory, mut item: InventoryItem) {
+    normalize_item(&mut item);
+    if !item.name.trim().is_empty() && item.max_stack > 1 {
+        let mut remaining = item.stack;
+        for existing in &mut inventory.items {
+            if same_stackable_item(existing, &item) && existing.stack < existing.max_stack {
+                let free = existing.max_stack - existing.stack;
+                let moved = free.min(remaining);
+                existing.stack += moved;
+                remaining -= moved;
+                if remaining == 0 {
+                    return;
+                }
+            }
+        }
+        item.stack = remaining;
+    }
+    inventory.items.push(item);
+}
+
+pub(crate) fn same_stackable_item(left: &InventoryItem, right: &InventoryItem) -> bool {
+    left.name == right.name
+        && left.description == right.description
+        && left.icon == right.icon
+        && left.quality == right.quality
+        && left.equipment_slot == right.equipment_slot
+        && left.max_stack == right.max_stack
+        && left.item_level == right.item_level
+        && left.soulbound == right.soulbound
+        && left.stat_effects == right.stat_effects
+        && left.use_rule == right.use_rule
+        && left.max_stack > 1
+}
+
+pub(crate) fn equip_inventory_item(inventory: &mut CharacterInventory, index: usize) {
+    if index >= inventory.items.len() {
+        return;
+    }
+    let item = inventory.items.remove(index);
+    let slot = item.equipment_slot;
+    if slot == EquipmentSlot::None {
+        inventory.items.insert(index, item);
+        return;
+    }
+    for conflict in conflicting_equipment_slots(slot) {
+        if let Some(previous) = inventory.equipment.remove(conflict) {
+            add_item_to_inventory(inventory, previous);
+        }
+    }
+    if let Some(previous) = inventory.equipment.insert(slot, item) {
+        add_item_to_inventory(inventory, previous);
+    }
+}
+
+/// Slots that cannot stay occupied together with `slot`: a two-handed weapon needs both hands.
+pub(crate) fn conflicting_equipment_slots(slot: EquipmentSlot) -> &'static [EquipmentSlot] {
+    match slot {
+        EquipmentSlot::TwoHand => &[EquipmentSlot::MainHand, EquipmentSlot::OffHand],
+        EquipmentSlot::MainHand | EquipmentSlot::OffHand => &[EquipmentSlot::TwoHand],
+        _ => &[],
+    }
+}
+
+pub(crate) fn equipment_slot_options() -> [EquipmentSlot; 17] {
+    [
+        EquipmentSlot::None,
+        EquipmentSlot::Head,
+        EquipmentSlot::Neck,
+        EquipmentSlot::Shoulder,
+        EquipmentSlot::Back,
+        EquipmentSlot::Chest,
+        EquipmentSlot::Wrist,
+        EquipmentSlot::Hands,
+        EquipmentSlot::Waist,
+        EquipmentSlot::Legs,
+        EquipmentSlot::Feet,
+        EquipmentSlot::Finger,
+        EquipmentSlot::Trinket,
+        EquipmentSlot::MainHand,
+        EquipmentSlot::OffHand,
+        EquipmentSlot::TwoHand,
+        EquipmentSlot::Ranged,
+    ]
+}
+
+pub(crate) fn equipment_slot_label(slot: EquipmentSlot) -> &'static str {
+    match slot {
+        EquipmentSlot::Head => "头部",
+        EquipmentSlot::Neck => "颈部",
+        EquipmentSlot::Shoulder => "肩部",
+        EquipmentSlot::Back => "背部",
+        EquipmentSlot::Chest => "胸部",
+        EquipmentSlot::Wrist => "手腕",
+        EquipmentSlot::Hands => "手",
+        EquipmentSlot::Waist => "腰部",
+        EquipmentSlot::Legs => "腿部",
+        EquipmentSlot::Feet => "脚",
+        EquipmentSlot::Finger => "戒指",
+        EquipmentSlot::Trinket => "饰品",
+        EquipmentSlot::MainHand => "主手",
+        EquipmentSlot::OffHand => "副手",
+        EquipmentSlot::TwoHand => "双手",
+        EquipmentSlot::Ranged => "远程",
+        EquipmentSlot::None => "非装备",
+    }
+}
+
+fn parse_equipment_slot(text: &str) -> Option<EquipmentSlot> {
+    equipment_slot_options()
+        .into_iter()
+        .filter(|slot| *slot != EquipmentSlot::None)
+        .find(|slot| equipment_slot_label(*slot) == text)
+}
+
+/// Finds a bag item by 1-based bag number or exact name; among same-named stacks the first one
+/// matching `prefer` wins.
+fn bag_item_index(
+    inventory: &CharacterInventory,
+    query: &str,
+    prefer: impl Fn(&InventoryItem) -> bool,
+) -> Option<usize> {
+    query
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_sub(1))
+        .filter(|index| *index < inventory.items.len())
+        .or_else(|| {
+            let mut named = inventory
+                .items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| item.name.trim() == query);
+            let first = named.clone().next().map(|(index, _)| index);
+            named
+                .find(|(_, item)| prefer(item))
+                .map(|(index, _)| index)
+                .or(first)
+        })
+}
+
+/// Resolves the bag item `query` names and checks that it has a use rule.
+pub(crate) fn usable_item_index(character: &PlayerCharacter, query: &str) -> Result<usize, String> {
+    let index = bag_item_index(&character.inventory, query, |item| {
+        !item.use_rule.trim().is_empty()
+    })
+    .ok_or_else(|| format!("背包里没有【{query}】。"))?;
+    let item = &character.inventory.items[index];
+    if item.use_rule.trim().is_empty() {
+        return Err(format!(
+            "【{}】没有使用效果。",
+            item.name
+        ));
+    }
+    Ok(index)
+}
+
+/// Takes one unit from the bag stack at `index`, removing the slot (and its hotbar binding) when
+/// the last unit is used.
+pub(crate) fn consume_character_item(
+    character: &mut PlayerCharacter,
+    index: usize,
+) -> Option<InventoryItem> {
+    let item = character.inventory.items.get_mut(index)?;
+    let consumed = InventoryItem {
+        stack: 1,
+        ..item.clone()
+    };
+    if item.stack > 1 {
+        item.stack -= 1;
+    } else {
+        remove_character_inventory_item(character, index, false);
+    }
+    Some(consumed)
+}
+
+/// Equips one unit of the bag item named `query` (or at the 1-based bag number). Whatever sat in
+/// the slot or a conflicting hand slot goes back to the bag; nothing changes when the item is not
+/// equipment or the bag cannot hold the unequipped gear.
+pub(crate) fn equip_character_item(
+    character: &mut PlayerCharacter,
+    query: &str,
+) -> Result<(InventoryItem, Vec<InventoryItem>), String> {
+    let inventory = &character.inventory;
+    let index = bag_item_index(inventory, query, |item| {
+        item.equipment_slot != EquipmentSlot::None
+    })
+    .ok_or_else(|| format!("背包里没有【{query}】。"))?;
+    let item = inventory.items[index].clone();
+    let slot = item.equipment_slot;
+    if slot == EquipmentSlot::None {
+        return Err(format!(
+            "【{}】不是装备，无法穿戴。",
+            item.name
+        ));
+    }
+    let displaced = std::iter::once(slot)
+        .chain(conflicting_equipment_slots(slot).iter().copied())
+        .filter_map(|slot| inventory.equipment.get(&slot).cloned())
+        .collect::<Vec<_>>();
+
+    let mut inventory = inventory.clone();
+    let equip_index = if item.stack > 1 {
+        inventory.items[index].stack -= 1;
+        inventory.items.push(InventoryItem {
+            stack: 1,
+            ..item.clone()
+        });
+        inventory.items.len() - 1
+    } else {
+        index
+    };
+    shift_character_hotbar_after_remove(
+        &mut inventory.hotbar,
+        CharacterHotbarSlot::Item(equip_index),
+    );
+    equip_inventory_item(&mut inventory, equip_index);
+    if inventory.items.len() > inventory.bag_slots {
+        return Err(format!(
+            "背包空间不足，装备【{}】后放不下换下的装备。",
+            item.name
+        ));
+    }
+    character.inventory = inventory;
+    Ok((
+        InventoryItem { stack: 1, ..item },
+        displaced,
+    ))
+}
+
+/// Moves the item in the named slot (or the equipped item with that name) back into the bag.
+pub(crate) fn unequip_character_item(
+    character: &mut PlayerCharacter,
+    query: &str,
+) -> Result<InventoryItem, String> {
+    let inventory = &mut character.inventory;
+    let slot = parse_equipment_slot(query)
+        .filter(|slot| inventory.equipment.contains_key(slot))
+        .or_else(|| {
+            inventory
+                .equipment
+                .iter()
+                .find(|(_, item)| item.name.trim() == query)
+                .map(|(slot, _)| *slot)
+        })
+        .ok_or_else(|| format!("你没有装备【{query}】。"))?;
+    let item = inventory.equipment[&slot].clone();
+    if inventory_capacity_for(inventory, &item) < item.stack.max(1) {
+        return Err(format!(
+            "背包已满，无法卸下【{}】。",
+            item.name
+        ));
+    }
+    inventory.equipment.remove(&slot);
+    add_item_to_inventory(inventory, item.clone());
+    Ok(item)
+}
+
+fn equipment_overview_text(character: &PlayerCharacter) -> String {
+    let mut lines = vec!["当前装备：".to_owned()];
+    let equipped = equipment_slot_options()
+        .into_iter()
+        .filter_map(|slot| {
+            character.inventory.equipment.get(&slot).map(|item| {
+                format!(
+                    "{}：{}",
+                    equipment_slot_label(slot),
+                    item.name
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    if equipped.is_empty() {
+        lines.push("（无）".to_owned());
+    }
+    lines.extend(equipped);
+    let equippable = character
+        .inventory
+        .items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| item.equipment_slot != EquipmentSlot::None)
+        .map(|(index, item)| {
+            format!(
+                "{}. {}（{}）",
+                index + 1,
+                item.name,
+                equipment_slot_label(item.equipment_slot)
+            )
+        })
+        .collect::<Vec<_>>();
+    if !equippable.is_empty() {
+        lines.push("背包中可装备：".to_owned());
+        lines.extend(equippable);
+    }
+    lines.push("使用【.装备 <物品或背包编号>】穿戴，【.卸下 <槽位或装备名>】卸下。".to_owned());
+    lines.join("\n")
+}
+
+/// Handles `.装备` and `.卸下`, then re-syncs equipment buffs so derived stats follow the gear.
+pub(super) fn handle_equipment_command(
+    manager: &mut NapcatMessageManager,
+    target_id: &str,
+    text: &str,
+    rule_engine_state: Option<&mut RuleEngineState>,
+) -> Option<String> {
+    let body = private_command_body(text)?;
+    let (command, args) = body
+        .split_once(char::is_whitespace)
+        .map(|(command, args)| (command, args.trim()))
+        .unwrap_or((body, ""));
+    if command != "装备" && command != "卸下" {
+        return None;
+    }
+    let stat_config = manager.character_stat_config_for_target(target_id);
+    let skill_pool = manager.skill_pool.clone();
+    let Some(character) = manager.player_characters.get_mut(target_id) else {
+        return Some("你还没有角色卡。输入【.兑换】开始建卡。".to_owned());
+    };
+    if !character.inited {
+        return Some("角色卡尚未完成，暂时无法更换装备。".to_owned());
+    }
+    if args.is_empty() {
+        return Some(if command == "装备" {
+            equipment_overview_text(character)
+        } else {
+            "请输入要卸下的槽位或装备名称，例如【.卸下 主手】。".to_owned()
+        });
+    }
+    let result = if command == "装备" {
+        equip_character_item(character, args).map(|(item, displaced)| {
+            let mut reply = format!(
+                "已装备【{}】（{}）。",
+                item.name,
+                equipment_slot_label(item.equipment_slot)
+            );
+            if !displaced.is_empty() {
+                reply.push_str(&format!(
+                    "换下：{}。",
+                    displaced
+                        .iter()
+                        .map(|item| format!("【{}】", item.name))
+                        .collect::<Vec<_>>()
+                        .join("、")
+                ));
+            }
+            reply
+        })
+    } else {
+        unequip_character_item(character, args)
+            .map(|item| format!("已卸下【{}】，放回背包。", item.name))
+    };
+    let reply = match result {
+        Ok(reply) => reply,
+        Err(err) => return Some(err),
+    };
+    let mut local_rule_engine_state = RuleEngineState::default();
+    sync_character_buffs(
+        target_id,
+        character,
+        &stat_config,
+        rule_engine_state.unwrap_or(&mut local_rule_engine_state),
+        &skill_pool,
+    );
+    Some(format!(
+        "{reply}属性已按当前装备重新计算，发送【.状态】查看。"
+    ))
+}
+
+fn usable_items_overview_text(character: &PlayerCharacter) -> String {
+    let usable = character
+        .inventory
+        .items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| !item.use_rule.trim().is_empty())
+        .map(|(index, item)| {
+            format!(
+                "{}. {} ×{}",
+                index + 1,
+                item.name,
+                item.stack
+            )
+        })
+        .collect::<Vec<_>>();
+    if usable.is_empty() {
+        return "背包里没有可使用的物品。".to_owned();
+    }
+    let mut lines = vec!["可使用的物品：".to_owned()];
+    lines.extend(usable);
+    lines.push("使用【.使用 <物品或背包编号>】使用，战斗中可在后面加目标名。".to_owned());
+    lines.join("\n")
+}
+
+/// Handles `.使用 <物品> [目标]`. In an in-progress encounter the item spends the player's action;
+/// outside battle its effects land on the player.
+pub(super) fn handle_item_use_command(
+    manager: &mut NapcatMessageManager,
+    target_id: &str,
+    text: &str,
+    rule_engine_state: Option<&mut RuleEngineState>,
+    battle_store: Option<&mut BattleRoundStore>,
+    scene_positions: Option<&SceneCharacterPositions>,
+) -> Option<String> {
+    let body = private_command_body(text)?;
+    let (command, args) = body
+        .split_once(char::is_whitespace)
+        .map(|(command, args)| (command, args.trim()))
+        .unwrap_or((body, ""));
+    if command != "使用" {
+        return None;
+    }
+    let Some(character) = manager.player_characters.get(target_id) else {
+        return Some("你还没有角色卡。输入【.兑换】开始建卡。".to_owned());
+    };
+    if !character.inited {
+        return Some("角色卡尚未完成，暂时无法使用物品。".to_owned());
+    }
+    if args.is_empty() {
+        return Some(usable_items_overview_text(character));
+    }
+    // Item names may contain spaces, so a trailing word only names a target when the whole
+    // argument is not an item in the bag.
+    let (item_query, target_name) = match args.rsplit_once(char::is_whitespace) {
+        Some((item_query, target_name)) if usable_item_index(character, args).is_err() => (
+            item_query.trim(),
+            Some(target_name.trim()),
+        ),
+        _ => (args, None),
+    };
+
+    if let Some(result) = battle_store.and_then(|store| {
+        store.use_item_from_chat(
+            target_id,
+            item_query,
+            target_name,
+            manager,
+            scene_positions,
+        )
+    }) {
+        return Some(result.unwrap_or_else(|err| err));
+    }
+    if target_name.is_some() {
+        return Some("不在战斗中时，物品只能对自己使用。".to_owned());
+    }
+    let item_index = match manager
+        .player_characters
+        .get(target_id)
+        .map(|character| usable_item_index(character, item_query))
+    {
+        Some(Ok(index)) => index,
+        Some(Err(err)) => return Some(err),
+        None => return None,
+    };
+    let mut local_rule_engine_state = RuleEngineState::default();
+    Some(
+        use_character_item(
+            manager,
+            rule_engine_state.unwrap_or(&mut local_rule_engine_state),
+            target_id,
+            item_index,
+        )
+        .unwrap_or_else(|err| err),
+    )
+}
+
+pub(crate) fn inventory_quality_label(quality: InventoryQuality) -> &'static str {
+    match quality {
+        InventoryQuality::Poor => "粗糙",
+        InventoryQuality::Common => "普通",
+        InventoryQuality::Uncommon => "优秀",
+        InventoryQuality::Rare => "精良",
+        InventoryQuality::Epic => "史诗",
+        InventoryQuality::Legendary => "传说",
+    }
+}
+
+/// How many more units of `item` fit in the bag, counting free room in matching stacks plus empty
+/// bag slots. Equipped items never take a bag slot.
+pub(crate) fn inventory_capacity_for(inventory: &CharacterInventory, item: &InventoryItem) -> u32 {
+    let mut item = item.clone();
+    normalize_item(&mut item);
+    let stack_room = if item.name.trim().is_empty() || item.max_stack <= 1 {
+        0
+    } else {
+        inventory
+            .items
+            .iter()
+            .filter(|existing| same_stackable_item(existing, &item))
+            .map(|existing| existing.max_stack.saturating_sub(existing.stack))
+            .fold(0_u32, u32::saturating_add)
+    };
+    let free_slots = inventory.bag_slots.saturating_sub(inventory.items.len()) as u32;
+    stack_room.saturating_add(free_slots.saturating_mul(item.max_stack))
+}
+
+/// Adds `count` copies of `item`, splitting them into stacks no larger than `max_stack`.
+pub(crate) fn add_item_count_to_inventory(
+    inventory: &mut CharacterInventory,
+    item: &InventoryItem,
+    count: u32,
+) {
+    let mut item = item.clone();
+    normalize_item(&mut item);
+    let mut remaining = count;
+    while remaining > 0 {
+        let moved = remaining.min(item.max_stack);
+        add_item_to_inventory(inventory, InventoryItem {
+            stack: moved,
+            ..item.clone()
+        });
+        remaining -= moved;
+    }
+}
+
+/// Number of tradeable (not soulbound) units named `name` in the bag.
+pub(crate) fn tradeable_item_count(inventory: &CharacterInventory, name: &str) -> u32 {
+    inventory
+        .items
+        .iter()
+        .filter(|item| !item.soulbound && item.name.trim() == name)
+        .map(|item| item.stack.max(1))
+        .fold(0_u32, u32::saturating_add)
+}
+
+/// Takes `count` tradeable units named `name` out of the bag, newest stacks first, and returns
+/// what was removed. Nothing is removed when the bag holds fewer than `count`.
+pub(crate) fn take_tradeable_items(
+    character: &mut PlayerCharacter,
+    name: &str,
+    count: u32,
+) -> Option<Vec<InventoryItem>> {
+    if count == 0 || tradeable_item_count(&character.inventory, name) < count {
+        return None;
+    }
+    let mut taken = Vec::new();
+    let mut remaining = count;
+    for index in (0..character.inventory.items.len()).rev() {
+        if remaining == 0 {
+            break;
+        }
+        let item = &mut character.inventory.items[index];
+        if item.soulbound || item.name.trim() != name {
+            continue;
+        }
+        let stack = item.stack.max(1);
+        if stack > remaining {
+            item.stack = stack - remaining;
+            taken.push(InventoryItem {
+                stack: remaining,
+                ..item.clone()
+            });
+            remaining = 0;
+        } else {
+            taken.push(InventoryItem {
+                stack,
+                ..item.clone()
+            });
+            remaining -= stack;
+            remove_character_inventory_item(character, index, false);
+        }
+    }
+    Some(taken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn potion() -> InventoryItem {
+        InventoryItem {
+            name: "治疗药水".to_owned(),
+            max_stack: 5,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn equipping_item_moves_previous_item_to_bag() {
+        let mut inventory = CharacterInventory::default();
+        inventory.items.push(InventoryItem {
+            name: "旧剑".to_owned(),
+            equipment_slot: EquipmentSlot::MainHand,
+            ..Default::default()
+        });
+        inventory.items.push(InventoryItem {
+            name: "新剑".to_owned(),
+            equipment_slot: EquipmentSlot::MainHand,
+            ..Default::default()
+        });
+
+        equip_inventory_item(&mut inventory, 0);
+        equip_inventory_item(&mut inventory, 0);
+
+        assert_eq!(
+            inventory.equipment[&EquipmentSlot::MainHand].name,
+            "新剑"
+        );
+        assert_eq!(inventory.items.len(), 1);
+        assert_eq!(inventory.items[0].name, "旧剑");
+    }
+
+    #[test]
+    fn capacity_counts_stack_room_and_free_slots() {
+        let mut inventory = CharacterInventory {
+            bag_slots: 2,
+            ..Default::default()
+        };
+        add_item_count_to_inventory(&mut inventory, &potion(), 3);
+        assert_eq!(
+            inventory_capacity_for(&inventory, &potion()),
+            7
+        );
+
+        add_item_count_to_inventory(&mut inventory, &potion(), 7);
+        assert_eq!(inventory.items.len(), 2);
+        assert!(inventory.items.iter().all(|item| item.stack == 5));
+        assert_eq!(
+            inventory_capacity_for(&inventory, &potion()),
+            0
+        );
+    }
+
+    #[test]
+    fn taking_items_skips_soulbound_stacks_and_clears_hotbar_slots() {
+        let mut character = PlayerCharacter::default();
+        add_item_count_to_inventory(&mut character.inventory, &potion(), 6);
+        add_item_to_inventory(
+            &mut character.inventory,
+            InventoryItem {
+                soulbound: true,
+                ..potion()
+            },
+        );
+        character.inventory.hotbar[0] = CharacterHotbarSlot::Item(1);
+
+        assert_eq!(
+            tradeable_item_count(&character.inventory, "治疗药水"),
+            6
+        );
+        assert!(take_tradeable_items(&mut character, "治疗药水", 7).is_none());
+
+        let taken = take_tradeable_items(&mut character, "治疗药水", 2).unwrap();
+        assert_eq!(
+            taken.iter().map(|item| item.stack).sum::<u32>(),
+            2
+        );
+        assert_eq!(character.inventory.items.len(), 2);
+        assert_eq!(character.inventory.items[0].stack, 4);
+        assert!(character.inventory.items[1].soulbound);
+        assert_eq!(
+            character.inventory.hotbar[0],
+            CharacterHotbarSlot::Empty
+        );
+    }
+
+    fn gear(name: &str, slot: EquipmentSlot) -> InventoryItem {
+        InventoryItem {
+            name: name.to_owned(),
+            equipment_slot: slot,
+            max_stack: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn two_handed_weapons_clear_both_hands() {
+        let mut character = PlayerCharacter::default();
+        for item in [
+            gear("短剑", EquipmentSlot::MainHand),
+            gear("圆盾", EquipmentSlot::OffHand),
+            gear("巨斧", EquipmentSlot::TwoHand),
+        ] {
+            add_item_to_inventory(&mut character.inventory, item);
+        }
+        equip_character_item(&mut character, "短剑").unwrap();
+        equip_character_item(&mut character, "圆盾").unwrap();
+
+        let (_, displaced) = equip_character_item(&mut character, "巨斧").unwrap();
+        assert_eq!(displaced.len(), 2);
+        assert_eq!(
+            character.inventory.equipment.keys().collect::<Vec<_>>(),
+            vec![&EquipmentSlot::TwoHand]
+        );
+        assert_eq!(character.inventory.items.len(), 2);
+
+        equip_character_item(&mut character, "圆盾").unwrap();
+        assert!(!character
+            .inventory
+            .equipment
+            .contains_key(&EquipmentSlot::TwoHand));
+        assert_eq!(
+            character.inventory.items[1].name,
+            "巨斧"
+        );
+        assert!(equip_character_item(&mut character, "治疗药水").is_err());
+    }
+
+    #[test]
+    fn equipping_fails_without_room_for_displaced_gear() {
+        let mut character = PlayerCharacter::default();
+        character.inventory.bag_slots = 2;
+        add_item_to_inventory(
+            &mut character.inventory,
+            gear("巨斧", EquipmentSlot::TwoHand),
+        );
+        add_item_to_inventory(&mut character.inventory, potion());
+        character.inventory.equipment.insert(
+            EquipmentSlot::MainHand,
+            gear("短剑", EquipmentSlot::MainHand),
+        );
+        character.inventory.equipment.insert(
+            EquipmentSlot::OffHand,
+            gear("圆盾", EquipmentSlot::OffHand),
+        );
+
+        let err = equip_character_item(&mut character, "1").unwrap_err();
+        assert!(err.contains("背包空间不足"));
+        assert_eq!(character.inventory.items.len(), 2);
+        assert_eq!(character.inventory.equipment.len(), 2);
+
+        add_item_to_inventory(&mut character.inventory, potion());
+        character.inventory.items[1].stack = 5;
+        let err = unequip_character_item(&mut character, "主手").unwrap_err();
+        assert!(err.contains("背包已满"));
+    }
+
+    #[test]
+    fn equipment_commands_update_derived_stats() {
+        let mut manager = crate::napcat::tests::empty_manager();
+        let mut character = PlayerCharacter {
+            inited: true,
+            max_hp: 50.0,
+            hp: 50.0,
+            ..Default::default()
+        };
+        add_item_to_inventory(
+            &mut character.inventory,
+            InventoryItem {
+                stat_effects: vec![crate::rule_engine::BuffEffect {
+                    field: crate::rule_engine::BuffField::MaxHp,
+                    value: crate::rule_engine::BuffValue::Add(10.0),
+                }],
+                ..gear("铁甲", EquipmentSlot::Chest)
+            },
+        );
+        manager.player_characters.insert("1".to_owned(), character);
+        let mut rule_engine_state = RuleEngineState::default();
+
+        let reply = handle_equipment_command(
+            &mut manager,
+            "1",
+            ".装备 铁甲",
+            Some(&mut rule_engine_state),
+        )
+        .unwrap();
+        assert!(reply.contains("已装备【铁甲】（胸部）"));
+        assert_eq!(
+            manager.player_characters["1"].max_hp,
+            60.0
+        );
+        assert!(
+            handle_equipment_command(&mut manager, "1", ".装备", None)
+                .unwrap()
+                .contains("胸部：铁甲")
+        );
+
+        let reply = handle_equipment_command(&mut manager, "1", "。卸下 胸部", None).unwrap();
+        assert!(reply.contains("已卸下【铁甲】"));
+        assert_eq!(
+            manager.player_characters["1"].max_hp,
+            50.0
+        );
+        assert!(handle_equipment_command(&mut manager, "1", ".商店", None).is_none());
+    }
+
+    #[test]
+    fn use_command_applies_item_rule_outside_battle_and_consumes_one() {
+        let mut manager = crate::napcat::tests::empty_manager();
+        let mut character = PlayerCharacter {
+            inited: true,
+            hp: 10.0,
+            max_hp: 50.0,
+            ..Default::default()
+        };
+        add_item_to_inventory(
+            &mut character.inventory,
+            InventoryItem {
+                name: "治疗药水".to_owned(),
+                stack: 2,
+                max_stack: 20,
+                use_rule: "主动使用对自己回复20点生命值".to_owned(),
+                ..Default::default()
+            },
+        );
+        add_item_to_inventory(
+            &mut character.inventory,
+            InventoryItem {
+                name: "石头".to_owned(),
+                ..Default::default()
+            },
+        );
+        manager.player_characters.insert("1".to_owned(), character);
+
+        let reply = handle_item_use_command(
+            &mut manager,
+            "1",
+            ".使用 治疗药水",
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(
+            reply.starts_with("使用了【治疗药水】，剩余1个。"),
+            "{reply}"
+        );
+        let character = &manager.player_characters["1"];
+        assert_eq!(character.hp, 30.0);
+        assert_eq!(character.inventory.items[0].stack, 1);
+        assert!(character.skill_last_cast_turns.is_empty());
+
+        assert_eq!(
+            handle_item_use_command(
+                &mut manager,
+                "1",
+                ".使用 石头",
+                None,
+                None,
+                None
+            ),
+            Some("【石头】没有使用效果。".to_owned())
+        );
+        assert_eq!(
+            handle_item_use_command(
+                &mut manager,
+                "1",
+                ".使用 1 哥布林",
+                None,
+                None,
+                None
+            ),
+            Some("不在战斗中时，物品只能对自己使用。".to_owned())
+        );
+        handle_item_use_command(
+            &mut manager,
+            "1",
+            ".使用 1",
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let character = &manager.player_characters["1"];
+        assert_eq!(character.hp, 50.0);
+        assert_eq!(character.inventory.items.len(), 1);
+        assert_eq!(
+            character.inventory.items[0].name,
+            "石头"
+        );
+    }
+}
@@ -0,0 +1,675 @@
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use bevy::prelude::*;
+use bevy_persistent::Persistent;
+use rand::RngExt;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    inventory::{
+        add_item_count_to_inventory,
+        inventory_capacity_for,
+        normalize_item,
+    },
+    normalized_random_pool_counts,
+    queue_private_text_response,
+    InventoryItem,
+    NapcatAutomaticReplyRequests,
+    NapcatIOSender,
+    NapcatMessageManager,
+    RandomPoolEntry,
+};
+use crate::battle_round::{
+    BattleParticipantSnapshot,
+    BattleRoundStore,
+};
+
+/// What a unit template drops when it falls in battle. Entries use the random pool format; each
+/// roll first checks `drop_chance`, then picks one entry by weight.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnitLootTable {
+    #[serde(default)]
+    pub entries: Vec<RandomPoolEntry>,
+    #[serde(default = "default_loot_rolls")]
+    pub rolls: u32,
+    #[serde(default = "default_loot_drop_chance")]
+    pub drop_chance: f32,
+    #[serde(default)]
+    pub gold_min: u32,
+    #[serde(default)]
+    pub gold_max: u32,
+}
+
+impl Default for UnitLootTable {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            rolls: default_loot_rolls(),
+            drop_chance: default_loot_drop_chance(),
+            gold_min: 0,
+            gold_max: 0,
+        }
+    }
+}
+
+impl UnitLootTable {
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() && self.gold_max == 0 }
+}
+
+fn default_loot_rolls() -> u32 { 1 }
+
+fn default_loot_drop_chance() -> f32 { 1.0 }
+
+/// Loot rolled from one defeated unit.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct LootDrop {
+    #[serde(default)]
+    pub id: u64,
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub items: Vec<InventoryItem>,
+    #[serde(default)]
+    pub gold: u32,
+    #[serde(default)]
+    pub contributors: Vec<String>,
+    #[serde(default)]
+    pub created_at: u64,
+}
+
+/// Battle loot a TRPG group's GM still has to hand out.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct TrpgLootBook {
+    #[serde(default)]
+    pub require_gm_approval: bool,
+    #[serde(default)]
+    pub staged: Vec<LootDrop>,
+    #[serde(default)]
+    pub next_id: u64,
+}
+
+/// GM reply plus the private notices for each player who received a share.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LootOutcome {
+    pub reply: String,
+    pub notices: Vec<(String, String)>,
+}
+
+pub(crate) fn pick_weighted_pool_entry(entries: &[RandomPoolEntry]) -> Option<&RandomPoolEntry> {
+    let total = entries
+        .iter()
+        .filter(|entry| entry.enabled)
+        .map(|entry| entry.weight.max(0.0))
+        .sum::<f32>();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut roll = rand::rng().random_range(0.0..total);
+    for entry in entries.iter().filter(|entry| entry.enabled) {
+        let weight = entry.weight.max(0.0);
+        if roll < weight {
+            return Some(entry);
+        }
+        roll -= weight;
+    }
+    None
+}
+
+pub fn roll_unit_loot(table: &UnitLootTable) -> (Vec<InventoryItem>, u32) {
+    let mut rng = rand::rng();
+    let drop_chance = table.drop_chance.clamp(0.0, 1.0);
+    let mut items = Vec::new();
+    for _ in 0..table.rolls {
+        if drop_chance < 1.0 && rng.random_range(0.0..1.0) >= drop_chance {
+            continue;
+        }
+        let Some(entry) = pick_weighted_pool_entry(&table.entries) else {
+            continue;
+        };
+        let (min_count, max_count) =
+            normalized_random_pool_counts(entry.min_count, entry.max_count);
+        let count = if min_count == max_count {
+            min_count
+        } else {
+            rng.random_range(min_count..=max_count)
+        };
+        if count == 0 || entry.item.name.trim().is_empty() {
+            continue;
+        }
+        let mut item = entry.item.clone();
+        normalize_item(&mut item);
+        item.stack = item.stack.max(1).saturating_mul(count);
+        items.push(item);
+    }
+    let gold = if table.gold_max <= table.gold_min {
+        table.gold_min
+    } else {
+        rng.random_range(table.gold_min..=table.gold_max)
+    };
+    (items, gold)
+}
+
+/// Splits a drop evenly: gold by integer division with the remainder going to the first
+/// recipients, item units dealt out one at a time.
+pub fn split_loot(
+    items: &[InventoryItem],
+    gold: u32,
+    recipients: &[String],
+) -> Vec<(String, Vec<InventoryItem>, u32)> {
+    if recipients.is_empty() {
+        return Vec::new();
+    }
+    let count = recipients.len() as u32;
+    let mut shares = recipients
+        .iter()
+        .enumerate()
+        .map(|(index, recipient)| {
+            (
+                recipient.clone(),
+                Vec::<InventoryItem>::new(),
+                gold / count + u32::from((index as u32) < gold % count),
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut next = 0;
+    for item in items {
+        for _ in 0..item.stack {
+            let share = &mut shares[next % recipients.len()].1;
+            let unit = InventoryItem {
+                stack: 1,
+                ..item.clone()
+            };
+            match share.iter_mut().find(|existing| {
+                InventoryItem {
+                    stack: 1,
+                    ..(*existing).clone()
+                } == unit
+            }) {
+                Some(existing) => existing.stack += 1,
+                None => share.push(unit),
+            }
+            next += 1;
+        }
+    }
+    shares
+}
+
+pub(crate) fn loot_summary(items: &[InventoryItem], gold: u32) -> String {
+    let mut parts = items
+        .iter()
+        .map(|item| format!("{}×{}", item.name, item.stack))
+        .collect::<Vec<_>>();
+    if gold > 0 {
+        parts.push(format!("{gold}金币"));
+    }
+    if parts.is_empty() {
+        "无".to_owned()
+    } else {
+        parts.join("、")
+    }
+}
+
+/// Puts each share into its owner's bag and returns the private notices. Items that no longer
+/// fit are named in the notice instead of being dropped silently.
+fn deliver_loot(
+    manager: &mut NapcatMessageManager,
+    source: &str,
+    shares: Vec<(String, Vec<InventoryItem>, u32)>,
+) -> Vec<(String, String)> {
+    let mut notices = Vec::new();
+    for (recipient, items, gold) in shares {
+        if items.is_empty() && gold == 0 {
+            continue;
+        }
+        let Some(character) = manager.player_characters.get_mut(&recipient) else {
+            continue;
+        };
+        character.inventory.gold = character.inventory.gold.saturating_add(gold);
+        let mut received = Vec::new();
+        let mut overflow = Vec::new();
+        for item in items {
+            let fits = inventory_capacity_for(&character.inventory, &item).min(item.stack);
+            add_item_count_to_inventory(&mut character.inventory, &item, fits);
+            if fits > 0 {
+                received.push(InventoryItem {
+                    stack: fits,
+                    ..item.clone()
+                });
+            }
+            if fits < item.stack {
+                overflow.push(InventoryItem {
+                    stack: item.stack - fits,
+                    ..item
+                });
+            }
+        }
+        let mut text = format!(
+            "击败【{source}】获得战利品：{}。",
+            loot_summary(&received, gold)
+        );
+        if !overflow.is_empty() {
+            text.push_str(&format!(
+                "背包已满，未能放入：{}，请联系GM。",
+                loot_summary(&overflow, 0)
+            ));
+        }
+        notices.push((recipient, text));
+    }
+    notices
+}
+
+fn participant_needs_loot_roll(participant: &BattleParticipantSnapshot) -> bool {
+    participant.unit_template_id.is_some() && !participant.alive && !participant.loot_rolled
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Rolls loot for every unit that fell since the last pass. The players who brought a unit down
+/// share its loot directly unless the group wants GM approval or nobody is credited, in which case
+/// it is staged on the group's loot book. Returns whether anything changed and the notices to send.
+pub fn collect_battle_loot(
+    store: &mut BattleRoundStore,
+    manager: &mut NapcatMessageManager,
+    now: u64,
+) -> (bool, Vec<(String, String)>) {
+    let mut changed = false;
+    let mut notices = Vec::new();
+    let mut encounter_ids = store.encounters.keys().cloned().collect::<Vec<_>>();
+    encounter_ids.sort();
+    for encounter_id in encounter_ids {
+        let Some(encounter) = store.encounters.get_mut(&encounter_id) else {
+            continue;
+        };
+        let names = encounter
+            .participants
+            .iter()
+            .map(|participant| {
+                (
+                    participant.target_id.clone(),
+                    participant.display_name.clone(),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let journaled = encounter.journal_tracks_current_state(manager);
+        let mut encounter_changed = false;
+        let mut logs = Vec::new();
+        for participant in encounter
+            .participants
+            .iter_mut()
+            .filter(|participant| participant_needs_loot_roll(participant))
+        {
+            participant.loot_rolled = true;
+            encounter_changed = true;
+            let Some(table) = participant
+                .unit_template_id
+                .as_ref()
+                .and_then(|unit_id| manager.unit_pool.get(unit_id))
+                .map(|unit| unit.loot.clone())
+                .filter(|table| !table.is_empty())
+            else {
+                continue;
+            };
+            let source = participant.display_name.clone();
+            let (items, gold) = roll_unit_loot(&table);
+            if items.is_empty() && gold == 0 {
+                logs.push(format!("{source}没有掉落战利品"));
+                continue;
+            }
+            let mut seen = HashSet::new();
+            let contributors = participant
+                .defeated_by
+                .iter()
+                .filter(|target_id| manager.player_characters.contains_key(*target_id))
+                .filter(|target_id| seen.insert((*target_id).clone()))
+                .cloned()
+                .collect::<Vec<_>>();
+            let group = encounter
+                .trpg_group
+                .as_ref()
+                .and_then(|group_name| manager.trpg_groups.get_mut(group_name));
+            match group {
+                Some(group) if group.loot.require_gm_approval || contributors.is_empty() => {
+                    group.loot.next_id += 1;
+                    let summary = loot_summary(&items, gold);
+                    group.loot.staged.push(LootDrop {
+                        id: group.loot.next_id,
+                        source: source.clone(),
+                        items,
+                        gold,
+                        contributors,
+                        created_at: now,
+                    });
+                    logs.push(format!(
+                        "{source}掉落{summary}，等待GM分配"
+                    ));
+                },
+                _ if contributors.is_empty() => {
+                    logs.push(format!("{source}掉落的战利品无人拾取"));
+                },
+                _ => {
+                    let names = contributors
+                        .iter()
+                        .map(|target_id| names.get(target_id).unwrap_or(target_id).as_str())
+                        .collect::<Vec<_>>()
+                        .join("、");
+                    logs.push(format!(
+                        "{source}掉落{}，由{names}平分",
+                        loot_summary(&items, gold)
+                    ));
+                    let shares = split_loot(&items, gold, &contributors);
+                    notices.extend(deliver_loot(manager, &source, shares));
+                },
+            }
+        }
+        encounter.action_log.extend(logs);
+        // The roll belongs to the blow that felled the unit, so undoing it takes the loot back.
+        if encounter_changed && journaled {
+            encounter.fold_follow_up_into_journal(manager);
+        }
+        changed |= encounter_changed;
+    }
+    (changed, notices)
+}
+
+/// Hands a staged drop to `recipients` (split evenly) and removes it from the loot book.
+pub fn approve_staged_loot(
+    manager: &mut NapcatMessageManager,
+    group_name: &str,
+    drop_id: u64,
+    recipients: &[String],
+) -> Result<LootOutcome, String> {
+    if recipients.is_empty() {
+        return Err("请至少选择一名玩家".to_owned());
+    }
+    let group = manager
+        .trpg_groups
+        .get_mut(group_name)
+        .ok_or_else(|| format!("TRPG组“{group_name}”不存在"))?;
+    let index = group
+        .loot
+        .staged
+        .iter()
+        .position(|drop| drop.id == drop_id)
+        .ok_or_else(|| format!("战利品#{drop_id}不存在"))?;
+    let drop = group.loot.staged.remove(index);
+    let shares = split_loot(&drop.items, drop.gold, recipients);
+    let notices = deliver_loot(manager, &drop.source, shares);
+    Ok(LootOutcome {
+        reply: format!(
+            "战利品#{drop_id}（{}）已分配给{}名玩家",
+            drop.source,
+            recipients.len()
+        ),
+        notices,
+    })
+}
+
+/// Removes a staged drop without handing it to anyone.
+pub fn discard_staged_loot(
+    manager: &mut NapcatMessageManager,
+    group_name: &str,
+    drop_id: u64,
+) -> Result<String, String> {
+    let group = manager
+        .trpg_groups
+        .get_mut(group_name)
+        .ok_or_else(|| format!("TRPG组“{group_name}”不存在"))?;
+    let index = group
+        .loot
+        .staged
+        .iter()
+        .position(|drop| drop.id == drop_id)
+        .ok_or_else(|| format!("战利品#{drop_id}不存在"))?;
+    let drop = group.loot.staged.remove(index);
+    Ok(format!(
+        "已丢弃战利品#{drop_id}（{}）",
+        drop.source
+    ))
+}
+
+/// Rolls loot as soon as a unit falls, whichever path defeated it, and messages the winners.
+pub(super) fn battle_loot_system(
+    store: Option<ResMut<Persistent<BattleRoundStore>>>,
+    mut manager: ResMut<Persistent<NapcatMessageManager>>,
+    sender: Option<Res<NapcatIOSender>>,
+    mut automatic_replies: ResMut<NapcatAutomaticReplyRequests>,
+) {
+    let Some(mut store) = store else {
+        return;
+    };
+    if !store.is_changed()
+        || !store.encounters.values().any(|encounter| {
+            encounter
+                .participants
+                .iter()
+                .any(participant_needs_loot_roll)
+        })
+    {
+        return;
+    }
+    let (changed, notices) = collect_battle_loot(&mut store, &mut manager, unix_now());
+    if !changed {
+        return;
+    }
+    if let Err(err) = store.persist() {
+        eprintln!("failed to persist battle loot rolls: {err}");
+    }
+    if let Err(err) = manager.persist() {
+        eprintln!("failed to persist battle loot: {err}");
+    }
+    let Some(sender) = sender else {
+        return;
+    };
+    for (recipient, text) in notices {
+        if let Ok(user_id) = recipient.parse::<u64>() {
+            queue_private_text_response(
+                &sender,
+                &mut automatic_replies,
+                user_id,
+                text,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        battle_round::BattleEncounter,
+        napcat::{
+            tests::empty_manager,
+            PlayerCharacter,
+            TrpgGroup,
+            UnitPoolEntry,
+        },
+    };
+
+    fn herb(stack: u32) -> InventoryItem {
+        InventoryItem {
+            name: "草药".to_owned(),
+            stack,
+            max_stack: 20,
+            ..Default::default()
+        }
+    }
+
+    fn participant(value: serde_json::Value) -> BattleParticipantSnapshot {
+        serde_json::from_value(value).expect("participant json")
+    }
+
+    fn goblin_encounter(manager: &mut NapcatMessageManager) -> BattleRoundStore {
+        manager
+            .unit_pool
+            .insert("goblin".to_owned(), UnitPoolEntry {
+                label: "哥布林".to_owned(),
+                loot: UnitLootTable {
+                    entries: vec![RandomPoolEntry {
+                        item: herb(1),
+                        min_count: 3,
+                        max_count: 3,
+                        ..Default::default()
+                    }],
+                    gold_min: 5,
+                    gold_max: 5,
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+        for player in ["1001", "1002"] {
+            manager.player_characters.insert(
+                player.to_owned(),
+                PlayerCharacter::default(),
+            );
+        }
+        manager.trpg_groups.insert(
+            "测试团".to_owned(),
+            TrpgGroup::default(),
+        );
+        let mut store = BattleRoundStore::default();
+        store.encounters.insert("e1".to_owned(), BattleEncounter {
+            trpg_group: Some("测试团".to_owned()),
+            participants: vec![
+                participant(serde_json::json!({
+                    "target_id": "1001",
+                    "display_name": "甲",
+                    "player_character": true,
+                })),
+                participant(serde_json::json!({
+                    "target_id": "unit:goblin#1",
+                    "display_name": "哥布林1",
+                    "unit_template_id": "goblin",
+                    "alive": false,
+                    "defeated_by": ["1001", "1002", "1001"],
+                })),
+            ],
+            ..Default::default()
+        });
+        store
+    }
+
+    #[test]
+    fn split_loot_deals_gold_remainder_and_item_units_in_turn() {
+        let shares = split_loot(&[herb(3)], 7, &[
+            "a".to_owned(),
+            "b".to_owned(),
+        ]);
+
+        assert_eq!(shares.len(), 2);
+        assert_eq!(shares[0].2, 4);
+        assert_eq!(shares[1].2, 3);
+        assert_eq!(shares[0].1[0].stack, 2);
+        assert_eq!(shares[1].1[0].stack, 1);
+    }
+
+    #[test]
+    fn defeated_unit_loot_is_split_among_contributors_once() {
+        let mut manager = empty_manager();
+        let mut store = goblin_encounter(&mut manager);
+
+        let (changed, notices) = collect_battle_loot(&mut store, &mut manager, 10);
+
+        assert!(changed);
+        assert_eq!(notices.len(), 2);
+        assert!(notices[0].1.contains("哥布林1"));
+        let first = &manager.player_characters["1001"].inventory;
+        let second = &manager.player_characters["1002"].inventory;
+        assert_eq!(first.gold + second.gold, 5);
+        assert_eq!(
+            first.items[0].stack + second.items[0].stack,
+            3
+        );
+        assert!(store.encounters["e1"]
+            .action_log
+            .last()
+            .is_some_and(|line| line.contains("平分")));
+
+        let (changed, notices) = collect_battle_loot(&mut store, &mut manager, 11);
+        assert!(!changed);
+        assert!(notices.is_empty());
+    }
+
+    #[test]
+    fn killing_blow_stays_undoable_after_the_loot_roll() {
+        let mut manager = empty_manager();
+        let mut store = goblin_encounter(&mut manager);
+        store.encounters.get_mut("e1").unwrap().participants[1].alive = true;
+        store.begin_journal_entry("e1", &manager, "甲攻击哥布林1");
+        store.encounters.get_mut("e1").unwrap().participants[1].alive = false;
+        assert!(store.commit_journal_entry("e1", &manager));
+
+        let (changed, _) = collect_battle_loot(&mut store, &mut manager, 10);
+        assert!(changed);
+        assert!(manager.player_characters["1001"].inventory.gold > 0);
+
+        assert_eq!(
+            store.undo_journal_entry("e1", &mut manager).as_deref(),
+            Some("甲攻击哥布林1")
+        );
+        let goblin = &store.encounters["e1"].participants[1];
+        assert!(goblin.alive);
+        assert!(!goblin.loot_rolled);
+        let inventory = &manager.player_characters["1001"].inventory;
+        assert_eq!(inventory.gold, 0);
+        assert!(inventory.items.is_empty());
+
+        assert!(store.redo_journal_entry("e1", &mut manager).is_some());
+        assert!(store.encounters["e1"].participants[1].loot_rolled);
+        assert!(manager.player_characters["1001"].inventory.gold > 0);
+        let (changed, _) = collect_battle_loot(&mut store, &mut manager, 11);
+        assert!(!changed);
+    }
+
+    #[test]
+    fn gm_approval_stages_loot_until_assigned() {
+        let mut manager = empty_manager();
+        let mut store = goblin_encounter(&mut manager);
+        manager
+            .trpg_groups
+            .get_mut("测试团")
+            .unwrap()
+            .loot
+            .require_gm_approval = true;
+
+        let (_, notices) = collect_battle_loot(&mut store, &mut manager, 10);
+        assert!(notices.is_empty());
+        let staged = manager.trpg_groups["测试团"].loot.staged.clone();
+        assert_eq!(staged.len(), 1);
+        assert_eq!(staged[0].contributors, vec![
+            "1001".to_owned(),
+            "1002".to_owned()
+        ]);
+
+        let outcome = approve_staged_loot(
+            &mut manager,
+            "测试团",
+            staged[0].id,
+            &["1002".to_owned()],
+        )
+        .unwrap();
+        assert_eq!(outcome.notices.len(), 1);
+        assert_eq!(outcome.notices[0].0, "1002");
+        assert!(manager.trpg_groups["测试团"].loot.staged.is_empty());
+        let inventory = &manager.player_characters["1002"].inventory;
+        assert_eq!(inventory.gold, 5);
+        assert_eq!(inventory.items[0].stack, 3);
+    }
+}
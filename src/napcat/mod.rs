@@ -1,3 +1,17 @@
+mod battle_command;
+mod connection;
+mod control;
+mod creation;
+mod inventory;
+mod loot;
+mod outbound;
+mod schedule;
+mod segments;
+mod shop;
+mod status_card;
+mod trade;
+mod transport;
+
 use std::{
     collections::{
         hash_map::DefaultHasher,
@@ -9,6 +23,7 @@ use std::{
         Hash,
         Hasher,
     },
+    ops::RangeInclusive,
     path::{
         Path,
         PathBuf,
@@ -21,20 +36,132 @@ use std::{
     },
 };
 
+use base64::{
+    engine::general_purpose::STANDARD as BASE64,
+    Engine,
+};
 use bevy_persistent::prelude::*;
 extern crate dirs;
 
-use bevy::prelude::*;
+use battle_command::{
+    battle_chat_action_system,
+    handle_battle_command,
+};
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    tasks::{
+        block_on,
+        futures_lite::future,
+        AsyncComputeTaskPool,
+        Task,
+    },
+};
+use connection::{
+    run_napcat_connections,
+    NapcatRouting,
+    NapcatRoutingSender,
+    NapcatStatusSender,
+};
+pub use connection::{
+    NapcatConnectionProfile,
+    NapcatConnectionProfiles,
+    NapcatConnectionStatus,
+    NapcatConnectionStatuses,
+};
+use control::{
+    control_api_system,
+    setup_control_api,
+    sync_control_api_config_system,
+};
+pub use control::{
+    NapcatControlApiConfig,
+    NapcatControlApiState,
+    NapcatControlApiViewerToken,
+};
+use creation::{
+    creation_choice_prompt,
+    creation_skill_prompt,
+    enter_status_phase,
+    grant_starting_kit,
+    pick_creation_choice,
+    pick_creation_skill,
+    pick_starting_kit,
+    starting_kit_prompt,
+    CreationContext,
+};
+pub use creation::{
+    CreationChoice,
+    CreationStatMode,
+    StartingKit,
+    TrpgCreationTemplate,
+};
 use crossbeam_channel::{
     unbounded,
     Receiver as CBReceiver,
     Sender as CBSender,
 };
-use futures_util::{
-    SinkExt,
-    StreamExt,
+pub(crate) use inventory::{
+    add_item_to_inventory,
+    consume_character_item,
+    equipment_slot_label,
+    equipment_slot_options,
+    inventory_quality_label,
+    normalize_item,
+    remove_character_inventory_item,
+    shift_character_hotbar_after_remove,
+    usable_item_index,
+    CONSUMABLE_ITEM_SKILL_INDEX,
+};
+use inventory::{
+    handle_equipment_command,
+    handle_item_use_command,
+};
+use loot::battle_loot_system;
+pub use loot::{
+    approve_staged_loot,
+    discard_staged_loot,
+    LootOutcome,
+    TrpgLootBook,
+    UnitLootTable,
+};
+pub(crate) use loot::{
+    loot_summary,
+    pick_weighted_pool_entry,
+};
+use outbound::{
+    outbound_queue_system,
+    restore_outbound_queue_system,
+    NapcatOutboundQueueChannels,
+    NapcatQueueSettledResults,
+};
+pub use outbound::{
+    NapcatDeliveryState,
+    NapcatOutboundQueue,
+    NAPCAT_QUEUE_MAX_RESPONSE_TIMEOUT_SECS,
 };
 use rand::RngExt;
+use schedule::scheduled_message_system;
+pub use schedule::{
+    NapcatMessageScheduler,
+    ScheduledMessage,
+    ScheduledTarget,
+    ScheduledTrigger,
+};
+use segments::{
+    deserialize_forward_nodes,
+    deserialize_id_string,
+    deserialize_json_payload,
+};
+pub use segments::{
+    escape_cq_param,
+    file_display_name,
+    forward_export_nodes,
+    json_card_prompt,
+    message_segment_preview,
+    outbound_segments_json,
+    parse_outbound_segments,
+};
 use serde::{
     Deserialize,
     Serialize,
@@ -44,25 +171,45 @@ use serde_json::{
     Map,
     Value,
 };
+use shop::handle_shop_command;
+pub use shop::{
+    ShopListing,
+    ShopTransactionKind,
+    TrpgShop,
+};
+use status_card::render_character_status_card;
 use tokio::{
     runtime::Builder,
-    sync::mpsc::{
-        Receiver,
-        Sender,
-    },
-    time::{
-        interval,
-        sleep,
-        Instant,
-        MissedTickBehavior,
+    sync::{
+        mpsc::{
+            Receiver,
+            Sender,
+        },
+        watch,
     },
+    time::Instant,
+};
+use tokio_tungstenite::tungstenite::protocol::Message;
+pub use trade::{
+    approve_trade,
+    veto_trade,
+    PlayerTrade,
+    TradeStatus,
+    TrpgTradeBook,
+    TRADE_TIMEOUT_SECS,
 };
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::protocol::Message,
+use trade::{
+    handle_trade_command,
+    trade_expiry_system,
 };
+pub use transport::NapcatTransport;
 
 use crate::{
+    battle_round::BattleRoundStore,
+    dice::{
+        parse_dice_expression,
+        roll_dice_expression,
+    },
     moonberry_talents::{
         MoonberryTalent,
         NORMAL_TALENT_POOL,
@@ -75,7 +222,9 @@ use crate::{
         BuffSpec,
         BuffTickAction,
         BuffValue,
+        CheckOutcome,
         DamageType,
+        RuleEngineState,
     },
     scene::{
         SceneCaptureRequest,
@@ -92,7 +241,17 @@ pub enum ConnectionState {
 }
 
 #[derive(Resource)]
-struct NapcatIOReceiver(CBReceiver<Message>);
+struct NapcatIOReceiver(CBReceiver<NapcatInboundMessage>);
+
+/// A raw OneBot frame tagged with the connection profile it arrived on.
+#[derive(Debug)]
+pub struct NapcatInboundMessage {
+    pub account_id: String,
+    pub message: Message,
+}
+
+#[derive(Resource)]
+struct NapcatConnectionStatusReceiver(CBReceiver<(String, NapcatConnectionStatus)>);
 
 #[derive(Resource)]
 struct NapcatSendResultReceiver(CBReceiver<NapcatSendResult>);
@@ -104,11 +263,16 @@ pub struct NapcatIOSender(pub Sender<NapcatOutboundMessage>);
 pub struct NapcatOutboundMessage {
     pub request_id: u64,
     pub target_id: String,
+    /// Connection profile to send through; `None` replies through the account that last
+    /// received `target_id`.
+    pub account_id: Option<String>,
     pub message: Message,
 }
 
 const NAPCAT_RESPONSE_ECHO_PREFIX: &str = "willowblossom:";
-const NAPCAT_ACTION_RESPONSE_TIMEOUT: Duration = Duration::from_secs(15);
+/// The outbound queue owns the configurable response timeout and retries; connections only
+/// drop correlation state that has outlived this.
+const NAPCAT_PENDING_REQUEST_TTL: Duration = Duration::from_secs(120);
 
 #[derive(Debug)]
 struct PendingNapcatRequest {
@@ -117,11 +281,14 @@ struct PendingNapcatRequest {
     sent_at: Instant,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NapcatSendResult {
     pub request_id: u64,
     pub target_id: String,
     pub error: Option<String>,
+    /// Set when the request was already written to the connection, so the outbound queue must
+    /// not resend it automatically.
+    pub delivery_unknown: bool,
 }
 
 #[derive(Resource, Default)]
@@ -132,12 +299,14 @@ pub struct NapcatSendManager {
 #[derive(Resource)]
 struct NapcatAutomaticReplyRequests {
     next_request_id: u64,
-    pending: HashMap<u64, PendingAutomaticPrivateReply>,
+    pending: HashMap<u64, PendingAutomaticReply>,
 }
 
 #[derive(Debug)]
-struct PendingAutomaticPrivateReply {
+struct PendingAutomaticReply {
+    /// A QQ user, or a QQ group when `group` is set.
     recipient_id: u64,
+    group: bool,
     text: String,
 }
 
@@ -178,6 +347,84 @@ pub struct ImageData {
     pub local_path: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct AtData {
+    /// QQ number, or `all` for 全体成员.
+    #[serde(default, deserialize_with = "deserialize_id_string")]
+    pub qq: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct ReplyData {
+    /// `message_id` of the quoted message.
+    #[serde(default, deserialize_with = "deserialize_id_string")]
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct FaceData {
+    #[serde(default, deserialize_with = "deserialize_id_string")]
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct RecordData {
+    #[serde(default)]
+    pub file: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub path: String,
+    #[serde(default, deserialize_with = "deserialize_id_string")]
+    pub file_size: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct FileData {
+    #[serde(default)]
+    pub file: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub file_id: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub path: String,
+    #[serde(default, deserialize_with = "deserialize_id_string")]
+    pub file_size: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct ForwardData {
+    #[serde(default, deserialize_with = "deserialize_id_string")]
+    pub id: String,
+    /// Flattened preview of the forwarded nodes when NapCat inlines them.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_forward_nodes"
+    )]
+    pub content: Vec<ForwardNode>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct ForwardNode {
+    #[serde(default)]
+    pub nickname: String,
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct JsonData {
+    /// Raw JSON card payload as sent by QQ.
+    #[serde(default, deserialize_with = "deserialize_json_payload")]
+    pub data: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Source {
     id: u64,
@@ -195,6 +442,27 @@ pub enum NapcatMessageChainType {
     Image {
         data: ImageData,
     },
+    At {
+        data: AtData,
+    },
+    Reply {
+        data: ReplyData,
+    },
+    Face {
+        data: FaceData,
+    },
+    Record {
+        data: RecordData,
+    },
+    File {
+        data: FileData,
+    },
+    Forward {
+        data: ForwardData,
+    },
+    Json {
+        data: JsonData,
+    },
     #[serde(other)]
     Unsupported,
 }
@@ -300,6 +568,13 @@ where
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct NapcatMessageData {
     pub time: u64,
+    /// OneBot `message_id`, referenced by reply segments; empty for local records.
+    #[serde(
+        default,
+        skip_serializing_if = "String::is_empty",
+        deserialize_with = "deserialize_id_string"
+    )]
+    pub message_id: String,
     pub message_type: NapcatMessageType,
     #[serde(deserialize_with = "deserialize_message_chains")]
     pub message: Vec<NapcatMessageChain>,
@@ -321,6 +596,9 @@ pub struct NapcatMessageData {
     /// False only for legacy or not-yet-annotated records whose scope must be derived.
     #[serde(default)]
     pub access_scope_resolved: bool,
+    /// Connection profile that received this message; empty for local records.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub account_id: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -400,6 +678,7 @@ pub enum EquipmentSlot {
     Trinket,
     MainHand,
     OffHand,
+    TwoHand,
     Ranged,
     #[default]
     None,
@@ -427,6 +706,9 @@ pub struct InventoryItem {
     pub soulbound: bool,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub stat_effects: Vec<BuffEffect>,
+    /// Rule text run when the item is used, e.g. "主动使用对自己回复20点生命值".
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub use_rule: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
@@ -451,6 +733,7 @@ impl Default for InventoryItem {
             item_level: 0,
             soulbound: false,
             stat_effects: Vec::new(),
+            use_rule: String::new(),
         }
     }
 }
@@ -575,6 +858,8 @@ pub struct UnitPoolEntry {
     pub legacy_member_id: Option<String>,
     #[serde(default)]
     pub character: PlayerCharacter,
+    #[serde(default, skip_serializing_if = "UnitLootTable::is_empty")]
+    pub loot: UnitLootTable,
 }
 
 impl Default for UnitPoolEntry {
@@ -584,6 +869,7 @@ impl Default for UnitPoolEntry {
             note: String::new(),
             legacy_member_id: None,
             character: PlayerCharacter::default(),
+            loot: UnitLootTable::default(),
         }
     }
 }
@@ -911,6 +1197,12 @@ pub struct PlayerCharacter {
     #[serde(default)]
     pub image: String,
     #[serde(default)]
+    pub race: String,
+    #[serde(default)]
+    pub background: String,
+    #[serde(default)]
+    pub starting_kit: String,
+    #[serde(default)]
     pub creation_step: CharacterCreationStep,
     #[serde(default = "default_status_points")]
     pub status_points: i32,
@@ -979,6 +1271,9 @@ impl Default for PlayerCharacter {
             name: String::new(),
             nickname: String::new(),
             image: String::new(),
+            race: String::new(),
+            background: String::new(),
+            starting_kit: String::new(),
             creation_step: CharacterCreationStep::Normal,
             status_points: default_status_points(),
             exchange_points: default_exchange_points(),
@@ -1104,6 +1399,9 @@ pub enum CharacterCreationStep {
     ConfirmSkill,
     Image,
     Nickname,
+    Race,
+    Background,
+    Kit,
 }
 
 impl CharacterCreationStep {
@@ -1136,7 +1434,7 @@ impl CharacterCreationStep {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum StatusKey {
     Str,
     Agi,
@@ -1390,6 +1688,92 @@ fn default_str_speed() -> f32 { 0.5 }
 fn default_agi_speed() -> f32 { 1.0 }
 fn default_dex_speed() -> f32 { 0.5 }
 
+pub const CHARACTER_STATUS_NAMES: [&str; 8] = [
+    "力量", "敏捷", "灵巧", "体质", "智力", "智慧", "知识", "魅力",
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct TrpgCheckConfig {
+    #[serde(default = "default_check_dice_sides")]
+    pub dice_sides: u32,
+    /// Natural rolls at or above this face are critical successes; 0 disables.
+    #[serde(default = "default_check_critical_success_roll")]
+    pub critical_success_roll: u32,
+    /// Natural rolls at or below this face are critical failures; 0 disables.
+    #[serde(default = "default_check_critical_failure_roll")]
+    pub critical_failure_roll: u32,
+    /// Beating the DC by this much is a critical success; 0 disables.
+    #[serde(default = "default_check_critical_margin")]
+    pub critical_success_margin: i32,
+    /// Missing the DC by this much is a critical failure; 0 disables.
+    #[serde(default = "default_check_critical_margin")]
+    pub critical_failure_margin: i32,
+}
+
+impl Default for TrpgCheckConfig {
+    fn default() -> Self {
+        Self {
+            dice_sides: default_check_dice_sides(),
+            critical_success_roll: default_check_critical_success_roll(),
+            critical_failure_roll: default_check_critical_failure_roll(),
+            critical_success_margin: default_check_critical_margin(),
+            critical_failure_margin: default_check_critical_margin(),
+        }
+    }
+}
+
+impl TrpgCheckConfig {
+    pub fn outcome(&self, natural: i32, total: i32, dc: i32) -> CheckOutcome {
+        if self.critical_success_roll > 0 && natural >= self.critical_success_roll as i32 {
+            return CheckOutcome::CriticalSuccess;
+        }
+        if self.critical_failure_roll > 0 && natural <= self.critical_failure_roll as i32 {
+            return CheckOutcome::CriticalFailure;
+        }
+        if total >= dc {
+            if self.critical_success_margin > 0 && total >= dc + self.critical_success_margin {
+                CheckOutcome::CriticalSuccess
+            } else {
+                CheckOutcome::Success
+            }
+        } else if self.critical_failure_margin > 0 && total <= dc - self.critical_failure_margin {
+            CheckOutcome::CriticalFailure
+        } else {
+            CheckOutcome::Failure
+        }
+    }
+}
+
+fn default_check_dice_sides() -> u32 { 20 }
+fn default_check_critical_success_roll() -> u32 { 20 }
+fn default_check_critical_failure_roll() -> u32 { 1 }
+fn default_check_critical_margin() -> i32 { 10 }
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrpgCheckRequest {
+    pub id: u64,
+    pub status: String,
+    pub dc: i32,
+    #[serde(default)]
+    pub targets: Vec<String>,
+    #[serde(default)]
+    pub results: HashMap<String, TrpgCheckResult>,
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default)]
+    pub closed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct TrpgCheckResult {
+    pub natural: i32,
+    pub modifier: i32,
+    pub total: i32,
+    pub outcome: CheckOutcome,
+    #[serde(default)]
+    pub time: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct TrpgParty {
     #[serde(default)]
@@ -1562,6 +1946,13 @@ pub struct TrpgGroup {
     pub initial_exchange_points: i32,
     #[serde(default)]
     pub basic_config: TrpgBasicConfig,
+    #[serde(default)]
+    pub check_config: TrpgCheckConfig,
+    #[serde(default)]
+    pub check_requests: Vec<TrpgCheckRequest>,
+    /// Last check request id handed out, so ids stay unique after requests are deleted.
+    #[serde(default)]
+    pub next_check_id: u64,
     #[serde(default, alias = "runTimes")]
     pub run_times: u32,
     #[serde(default = "default_battle_sort_by_turn", alias = "orderByTurn")]
@@ -1592,6 +1983,14 @@ pub struct TrpgGroup {
     pub world_turn: u32,
     #[serde(default)]
     pub player_turns: HashMap<String, TrpgPlayerTurnState>,
+    #[serde(default)]
+    pub shop: TrpgShop,
+    #[serde(default)]
+    pub trades: TrpgTradeBook,
+    #[serde(default)]
+    pub loot: TrpgLootBook,
+    #[serde(default)]
+    pub creation_template: TrpgCreationTemplate,
 }
 
 impl Default for TrpgGroup {
@@ -1605,6 +2004,9 @@ impl Default for TrpgGroup {
             initial_status_points: default_status_points(),
             initial_exchange_points: default_exchange_points(),
             basic_config: TrpgBasicConfig::default(),
+            check_config: TrpgCheckConfig::default(),
+            check_requests: Vec::new(),
+            next_check_id: 0,
             run_times: 0,
             battle_sort_by_turn: default_battle_sort_by_turn(),
             battle_negative_enabled: false,
@@ -1620,6 +2022,10 @@ impl Default for TrpgGroup {
             group_chats: Vec::new(),
             world_turn: 0,
             player_turns: HashMap::default(),
+            shop: TrpgShop::default(),
+            trades: TrpgTradeBook::default(),
+            loot: TrpgLootBook::default(),
+            creation_template: TrpgCreationTemplate::default(),
         }
     }
 }
@@ -2464,6 +2870,58 @@ impl TrpgGroup {
         members
     }
 
+    pub fn open_check_request(
+        &mut self,
+        status: &str,
+        dc: i32,
+        targets: Vec<String>,
+        time: u64,
+    ) -> Option<u64> {
+        let status_key = parse_status_key(status)?;
+        let targets = targets
+            .into_iter()
+            .filter(|target_id| self.players.contains(target_id))
+            .collect::<Vec<_>>();
+        if targets.is_empty() {
+            return None;
+        }
+        // Saves from before the counter existed start above their highest surviving id.
+        let id = self
+            .check_requests
+            .iter()
+            .map(|request| request.id)
+            .fold(self.next_check_id, u64::max)
+            + 1;
+        self.next_check_id = id;
+        self.check_requests.push(TrpgCheckRequest {
+            id,
+            status: status_key.zh().to_owned(),
+            dc,
+            targets,
+            results: HashMap::default(),
+            created_at: time,
+            closed: false,
+        });
+        Some(id)
+    }
+
+    fn pending_check_request_mut(
+        &mut self,
+        target_id: &str,
+        status_key: Option<StatusKey>,
+    ) -> Option<&mut TrpgCheckRequest> {
+        self.check_requests.iter_mut().rev().find(|request| {
+            !request.closed
+                && request
+                    .targets
+                    .iter()
+                    .any(|member_id| member_id == target_id)
+                && !request.results.contains_key(target_id)
+                && status_key
+                    .is_none_or(|status_key| parse_status_key(&request.status) == Some(status_key))
+        })
+    }
+
     pub fn player_access(&self, player_id: u64) -> PlayerAccess {
         let target_id = player_id.to_string();
         let is_player = self.players.iter().any(|member_id| member_id == &target_id);
@@ -2636,6 +3094,10 @@ pub struct NapcatMessageManager {
     pub item_pool: Vec<InventoryItem>,
     #[serde(default)]
     pub unit_pool: HashMap<String, UnitPoolEntry>,
+    /// Connection profile that most recently received each chat target, kept up to date as
+    /// messages arrive so routing never has to walk the message history.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub chat_target_accounts: HashMap<String, String>,
 }
 
 pub const NAPCAT_MANAGER_EXPORT_VERSION: u32 = 1;
@@ -2768,7 +3230,9 @@ impl NapcatMessageManager {
                 export.version, NAPCAT_MANAGER_EXPORT_VERSION
             ));
         }
-        Ok(export.manager)
+        let mut manager = export.manager;
+        manager.migrate_chat_target_accounts();
+        Ok(manager)
     }
 
     pub fn to_player_characters_export_json(&self) -> Result<String, String> {
@@ -3316,6 +3780,7 @@ impl NapcatMessageManager {
                 note: note_parts.join("\n"),
                 legacy_member_id,
                 character,
+                loot: UnitLootTable::default(),
             });
             summary.unit_templates += 1;
         }
@@ -3487,6 +3952,43 @@ impl NapcatMessageManager {
         entries
     }
 
+    pub fn record_chat_target_account(&mut self, target_id: &str, account_id: &str) {
+        if account_id.is_empty()
+            || self.chat_target_accounts.get(target_id).map(String::as_str) == Some(account_id)
+        {
+            return;
+        }
+        self.chat_target_accounts.insert(
+            target_id.to_owned(),
+            account_id.to_owned(),
+        );
+    }
+
+    /// Fills `chat_target_accounts` from the message history for saves written before it was
+    /// kept; only runs while the map is still empty.
+    pub fn migrate_chat_target_accounts(&mut self) -> bool {
+        if !self.chat_target_accounts.is_empty() {
+            return false;
+        }
+        self.chat_target_accounts = self
+            .messages
+            .iter()
+            .filter_map(|(target_id, messages)| {
+                messages
+                    .iter()
+                    .rev()
+                    .find(|message| !message.data.account_id.is_empty())
+                    .map(|message| {
+                        (
+                            target_id.clone(),
+                            message.data.account_id.clone(),
+                        )
+                    })
+            })
+            .collect();
+        !self.chat_target_accounts.is_empty()
+    }
+
     pub fn chat_target_export_entries(&self) -> Vec<ChatTargetExportEntry> {
         let mut target_ids = self
             .chat_targets
@@ -3905,6 +4407,12 @@ impl NapcatMessageManager {
             })
     }
 
+    pub fn creation_template_for_target(&self, target_id: &str) -> TrpgCreationTemplate {
+        self.group_for_player_target(target_id)
+            .map(|group| group.creation_template.clone())
+            .unwrap_or_default()
+    }
+
     pub fn character_stat_config_for_target(&self, target_id: &str) -> TrpgBasicConfig {
         let Some(group) = self.group_for_player_target(target_id) else {
             return TrpgBasicConfig::default();
@@ -4028,6 +4536,69 @@ impl NapcatMessageManager {
             .collect()
     }
 
+    /// Audiences a campaign log can be exported for, from least to most privileged.
+    pub fn forward_audience_options(&self) -> Vec<(String, PlayerAccess)> {
+        let mut options = vec![(
+            "公开".to_owned(),
+            PlayerAccess::default(),
+        )];
+        if let Some(group) = self.current_group() {
+            let mut parties = group.parties.keys().cloned().collect::<Vec<_>>();
+            parties.sort();
+            options.extend(parties.into_iter().map(|party_id| {
+                (
+                    format!("队伍：{party_id}"),
+                    PlayerAccess {
+                        party_id: Some(party_id),
+                        ..Default::default()
+                    },
+                )
+            }));
+            let mut players = group
+                .players
+                .iter()
+                .filter_map(|id| id.parse::<u64>().ok())
+                .collect::<Vec<_>>();
+            players.sort_unstable();
+            options.extend(players.into_iter().map(|player_id| {
+                (
+                    format!("玩家：{player_id}"),
+                    group.player_access(player_id),
+                )
+            }));
+        }
+        options.push((
+            "GM（包含私密内容）".to_owned(),
+            self.gm_access(),
+        ));
+        options
+    }
+
+    /// Messages in `range` (indices into `messages`) that `audience` may read, limited to the
+    /// active campaign when one is set.
+    pub fn forward_export_messages<'a>(
+        &self,
+        target_id: &str,
+        messages: &'a [NapcatMessage],
+        range: RangeInclusive<usize>,
+        audience: &PlayerAccess,
+    ) -> Vec<&'a NapcatMessage> {
+        let campaign_id = self.active_campaign_id();
+        messages
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| range.contains(index))
+            .map(|(_, message)| message)
+            .filter(|message| {
+                let campaign_message = self.campaign_message_for_target(target_id, message);
+                campaign_id
+                    .as_ref()
+                    .is_none_or(|campaign_id| campaign_message.campaign_id == *campaign_id)
+                    && audience.can_read(&campaign_message.visibility)
+            })
+            .collect()
+    }
+
     pub fn campaign_message_for_target(
         &self,
         target_id: &str,
@@ -4069,7 +4640,13 @@ impl NapcatMessageManager {
                     source: MessageSource::Friend { user_id: peer_id },
                     character_id: access.character_id,
                     party_id: access.party_id,
-                    visibility: Visibility::Player(peer_id),
+                    // Bot records such as hidden rolls and scheduled sends persist a narrower
+                    // scope than the private peer.
+                    visibility: if message.data.access_scope_resolved {
+                        message.data.visibility.clone()
+                    } else {
+                        Visibility::Player(peer_id)
+                    },
                     text,
                     time: message.data.time,
                 }
@@ -5063,6 +5640,7 @@ fn moonberry_chat_to_napcat_message(
     Some((target_id, NapcatMessage {
         data: NapcatMessageData {
             time,
+            message_id: String::new(),
             message_type,
             message: chains,
             self_id: 0,
@@ -5079,6 +5657,7 @@ fn moonberry_chat_to_napcat_message(
             party_id: None,
             visibility,
             access_scope_resolved: true,
+            account_id: String::new(),
         },
     }))
 }
@@ -5236,29 +5815,99 @@ impl Plugin for NapcatPlugin {
     fn build(&self, app: &mut App) {
         app.insert_state(ConnectionState::Disconnected)
             .add_systems(Startup, setup)
+            .init_resource::<RemoteImageCache>()
             .add_systems(Update, message_system)
+            .add_systems(Update, remote_image_prefetch_system)
             .add_systems(
                 Update,
                 request_missing_group_info_system,
             )
-            .add_systems(Update, send_result_system);
+            .add_systems(
+                PostStartup,
+                restore_outbound_queue_system,
+            )
+            .add_systems(
+                Update,
+                (
+                    outbound_queue_system,
+                    send_result_system,
+                )
+                    .chain(),
+            )
+            .add_systems(Update, scheduled_message_system)
+            .add_systems(Update, trade_expiry_system)
+            .add_systems(Update, battle_loot_system)
+            .add_systems(Update, battle_chat_action_system)
+            .add_systems(
+                Update,
+                (
+                    sync_control_api_config_system,
+                    control_api_system,
+                ),
+            )
+            .add_systems(Update, connection_status_system)
+            .add_systems(Update, sync_napcat_routing_system);
     }
 }
 
 fn setup(mut commands: Commands) {
-    let (client_to_game_sender, client_to_game_receiver) = unbounded::<Message>();
+    let config_dir = Path::new(".data").join("willowblossom");
+    let connection_profiles = Persistent::<NapcatConnectionProfiles>::builder()
+        .name("napcat connections")
+        .format(StorageFormat::Toml)
+        .path(config_dir.join("connections.toml"))
+        .default(NapcatConnectionProfiles::default())
+        .build()
+        .expect("failed to init NapCat connection profiles");
+    let (client_to_game_sender, client_to_game_receiver) = unbounded::<NapcatInboundMessage>();
+    let outbound_queue = Persistent::<NapcatOutboundQueue>::builder()
+        .name("napcat outbound queue")
+        .format(StorageFormat::Toml)
+        .path(config_dir.join("outbound_queue.toml"))
+        .default(NapcatOutboundQueue::default())
+        .build()
+        .expect("failed to init NapCat outbound queue");
+    let scheduler = Persistent::<NapcatMessageScheduler>::builder()
+        .name("napcat scheduled messages")
+        .format(StorageFormat::Toml)
+        .path(config_dir.join("schedule.toml"))
+        .default(NapcatMessageScheduler::default())
+        .build()
+        .expect("failed to init NapCat scheduled messages");
+    let (intake_sender, intake_receiver) = tokio::sync::mpsc::channel(1000);
     let (game_to_client_sender, game_to_client_receiver) = tokio::sync::mpsc::channel(100);
     let (send_result_sender, send_result_receiver) = unbounded::<NapcatSendResult>();
+    let (status_sender, status_receiver) = unbounded();
+    let (routing_sender, routing_receiver) = watch::channel(NapcatRouting {
+        profiles: connection_profiles.profiles.clone(),
+        target_accounts: HashMap::default(),
+    });
     let napcat_io = NapcatIOReceiver(client_to_game_receiver.clone());
     let napcat_send_results = NapcatSendResultReceiver(send_result_receiver);
     spawn_napcat_connection(
         client_to_game_sender.clone(),
         game_to_client_receiver,
         send_result_sender,
+        status_sender,
+        routing_receiver,
     );
     commands.insert_resource(napcat_io);
     commands.insert_resource(napcat_send_results);
-    commands.insert_resource(NapcatIOSender(game_to_client_sender));
+    commands.insert_resource(NapcatIOSender(intake_sender));
+    commands.insert_resource(NapcatOutboundQueueChannels {
+        intake: intake_receiver,
+        dispatch: game_to_client_sender,
+    });
+    commands.insert_resource(NapcatQueueSettledResults::default());
+    commands.insert_resource(outbound_queue);
+    commands.insert_resource(scheduler);
+    setup_control_api(&mut commands, &config_dir);
+    commands.insert_resource(NapcatRoutingSender(routing_sender));
+    commands.insert_resource(NapcatConnectionStatusReceiver(
+        status_receiver,
+    ));
+    commands.insert_resource(NapcatConnectionStatuses::default());
+    commands.insert_resource(connection_profiles);
     commands.insert_resource(NapcatSendManager::default());
     commands.insert_resource(NapcatAutomaticReplyRequests {
         next_request_id: 1_000_000,
@@ -5286,23 +5935,27 @@ fn setup(mut commands: Commands) {
         skill_pool: Vec::new(),
         item_pool: Vec::new(),
         unit_pool: HashMap::default(),
+        chat_target_accounts: HashMap::default(),
     };
-    let config_dir = Path::new(".data").join("willowblossom");
-    commands.insert_resource(
-        Persistent::<NapcatMessageManager>::builder()
-            .name("messages")
-            .format(StorageFormat::Toml)
-            .path(config_dir.join("messages.toml"))
-            .default(message_manager)
-            .build()
-            .expect("failed to init messages"),
-    );
+    let mut manager = Persistent::<NapcatMessageManager>::builder()
+        .name("messages")
+        .format(StorageFormat::Toml)
+        .path(config_dir.join("messages.toml"))
+        .default(message_manager)
+        .build()
+        .expect("failed to init messages");
+    if manager.migrate_chat_target_accounts() {
+        manager.persist().ok();
+    }
+    commands.insert_resource(manager);
 }
 
 fn spawn_napcat_connection(
-    client_to_game_sender: CBSender<Message>,
+    client_to_game_sender: CBSender<NapcatInboundMessage>,
     game_to_client_receiver: Receiver<NapcatOutboundMessage>,
     send_result_sender: CBSender<NapcatSendResult>,
+    status_sender: NapcatStatusSender,
+    routing_receiver: watch::Receiver<NapcatRouting>,
 ) {
     thread::Builder::new()
         .name("napcat-websocket".to_owned())
@@ -5311,137 +5964,61 @@ fn spawn_napcat_connection(
                 .enable_all()
                 .build()
                 .expect("failed to create NapCat Tokio runtime");
-            runtime.block_on(run_napcat_connection(
+            runtime.block_on(run_napcat_connections(
                 client_to_game_sender,
                 game_to_client_receiver,
                 send_result_sender,
+                status_sender,
+                routing_receiver,
             ));
         })
         .expect("failed to spawn NapCat websocket thread");
 }
 
-async fn run_napcat_connection(
-    client_to_game_sender: CBSender<Message>,
-    mut game_to_client_receiver: Receiver<NapcatOutboundMessage>,
-    send_result_sender: CBSender<NapcatSendResult>,
+fn connection_status_system(
+    receiver: Res<NapcatConnectionStatusReceiver>,
+    mut statuses: ResMut<NapcatConnectionStatuses>,
+    state: Res<State<ConnectionState>>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
 ) {
-    const NAPCAT_WS_URL: &str = "ws://localhost:3001";
-
-    loop {
-        let (ws_stream, _) = match connect_async(NAPCAT_WS_URL).await {
-            Ok(connection) => connection,
-            Err(err) => {
-                eprintln!("failed to connect NapCat websocket: {err}");
-                sleep(Duration::from_secs(2)).await;
-                continue;
-            },
-        };
-
-        eprintln!("connected to NapCat websocket at {NAPCAT_WS_URL}");
-        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
-        let mut pending_requests = HashMap::<String, PendingNapcatRequest>::new();
-        let mut timeout_check = interval(Duration::from_secs(1));
-        timeout_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
-
-        loop {
-            tokio::select! {
-                msg = ws_receiver.next() => {
-                    match msg {
-                        Some(Ok(msg)) => {
-                            if msg.is_text() || msg.is_binary() {
-                                if let Some(result) = correlated_action_result(
-                                    &msg,
-                                    &mut pending_requests,
-                                ) {
-                                    let _ = send_result_sender.send(result);
-                                }
-                                if client_to_game_sender.send(msg).is_err() {
-                                    fail_pending_napcat_requests(
-                                        &mut pending_requests,
-                                        &send_result_sender,
-                                        "NapCat响应接收端已关闭",
-                                    );
-                                    return;
-                                }
-                            } else if msg.is_close() {
-                                break;
-                            }
-                        }
-                        Some(Err(err)) => {
-                            eprintln!("NapCat websocket receive error: {err}");
-                            break;
-                        },
-                        None => break,
-                    }
-                }
-                outbound = game_to_client_receiver.recv() => {
-                    let Some(outbound) = outbound else {
-                        fail_pending_napcat_requests(
-                            &mut pending_requests,
-                            &send_result_sender,
-                            "NapCat发送端已关闭",
-                        );
-                        return;
-                    };
-                    let (message, echo) = match correlated_outbound_message(&outbound) {
-                        Ok(prepared) => prepared,
-                        Err(error) => {
-                            let _ = send_result_sender.send(NapcatSendResult {
-                                request_id: outbound.request_id,
-                                target_id: outbound.target_id,
-                                error: Some(error),
-                            });
-                            continue;
-                        },
-                    };
-                    if pending_requests.contains_key(&echo) {
-                        let _ = send_result_sender.send(NapcatSendResult {
-                            request_id: outbound.request_id,
-                            target_id: outbound.target_id,
-                            error: Some(format!("NapCat请求echo重复：{echo}")),
-                        });
-                        continue;
-                    }
-                    if let Err(err) = ws_sender.send(message).await {
-                        let error = format!("NapCat websocket send error: {err}");
-                        eprintln!("{error}");
-                        let _ = send_result_sender.send(NapcatSendResult {
-                            request_id: outbound.request_id,
-                            target_id: outbound.target_id,
-                            error: Some(error.clone()),
-                        });
-                        fail_pending_napcat_requests(
-                            &mut pending_requests,
-                            &send_result_sender,
-                            &error,
-                        );
-                        break;
-                    }
-                    pending_requests.insert(echo, PendingNapcatRequest {
-                        request_id: outbound.request_id,
-                        target_id: outbound.target_id,
-                        sent_at: Instant::now(),
-                    });
-                    eprintln!("sent NapCat websocket message");
-                }
-                _ = timeout_check.tick() => {
-                    expire_pending_napcat_requests(
-                        &mut pending_requests,
-                        &send_result_sender,
-                        Instant::now(),
-                    );
-                }
-            }
-        }
-
-        fail_pending_napcat_requests(
-            &mut pending_requests,
-            &send_result_sender,
-            "NapCat websocket连接在响应前断开",
-        );
+    let mut changed = false;
+    while let Ok((account_id, status)) = receiver.0.try_recv() {
+        statuses.0.insert(account_id, status);
+        changed = true;
+    }
+    if !changed {
+        return;
+    }
+    let connection_state = if statuses.any_connected() {
+        ConnectionState::Connected
+    } else {
+        ConnectionState::Disconnected
+    };
+    if *state.get() != connection_state {
+        next_state.set(connection_state);
+    }
+}
 
-        sleep(Duration::from_secs(2)).await;
+fn sync_napcat_routing_system(
+    routing_sender: Res<NapcatRoutingSender>,
+    profiles: Res<Persistent<NapcatConnectionProfiles>>,
+    manager: Res<Persistent<NapcatMessageManager>>,
+) {
+    if !profiles.is_changed() && !manager.is_changed() {
+        return;
     }
+    routing_sender.0.send_if_modified(|current| {
+        if current.profiles == profiles.profiles
+            && current.target_accounts == manager.chat_target_accounts
+        {
+            return false;
+        }
+        *current = NapcatRouting {
+            profiles: profiles.profiles.clone(),
+            target_accounts: manager.chat_target_accounts.clone(),
+        };
+        true
+    });
 }
 
 fn correlated_outbound_message(
@@ -5488,6 +6065,7 @@ fn correlated_action_result(
         request_id: pending.request_id,
         target_id: pending.target_id,
         error: napcat_action_response_error(&response),
+        delivery_unknown: false,
     })
 }
 
@@ -5520,7 +6098,7 @@ fn expire_pending_napcat_requests(
     let expired = pending_requests
         .iter()
         .filter(|(_, pending)| {
-            now.saturating_duration_since(pending.sent_at) >= NAPCAT_ACTION_RESPONSE_TIMEOUT
+            now.saturating_duration_since(pending.sent_at) >= NAPCAT_PENDING_REQUEST_TTL
         })
         .map(|(echo, _)| echo.clone())
         .collect::<Vec<_>>();
@@ -5533,8 +6111,9 @@ fn expire_pending_napcat_requests(
             target_id: pending.target_id,
             error: Some(format!(
                 "NapCat响应超时（{}秒）",
-                NAPCAT_ACTION_RESPONSE_TIMEOUT.as_secs()
+                NAPCAT_PENDING_REQUEST_TTL.as_secs()
             )),
+            delivery_unknown: true,
         });
     }
 }
@@ -5549,19 +6128,20 @@ fn fail_pending_napcat_requests(
             request_id: pending.request_id,
             target_id: pending.target_id,
             error: Some(error.to_owned()),
+            delivery_unknown: true,
         });
     }
 }
 
 fn send_result_system(
-    receiver: Res<NapcatSendResultReceiver>,
+    mut settled: ResMut<NapcatQueueSettledResults>,
     mut send_manager: ResMut<NapcatSendManager>,
     mut automatic_replies: ResMut<NapcatAutomaticReplyRequests>,
     mut manager: ResMut<Persistent<NapcatMessageManager>>,
 ) {
     let mut manager_changed = false;
-    while let Ok(result) = receiver.0.try_recv() {
-        if let Some(changed) = apply_automatic_private_reply_result(
+    for result in settled.0.drain(..) {
+        if let Some(changed) = apply_automatic_reply_result(
             &result,
             &mut automatic_replies,
             &mut manager,
@@ -5578,7 +6158,7 @@ fn send_result_system(
     }
 }
 
-fn apply_automatic_private_reply_result(
+fn apply_automatic_reply_result(
     result: &NapcatSendResult,
     automatic_replies: &mut NapcatAutomaticReplyRequests,
     manager: &mut NapcatMessageManager,
@@ -5599,12 +6179,20 @@ fn apply_automatic_private_reply_result(
         );
         return Some(false);
     }
-    Some(append_local_private_text_response(
-        manager,
-        &expected_target_id,
-        pending.recipient_id,
-        &pending.text,
-    ))
+    Some(if pending.group {
+        append_local_group_text_response(
+            manager,
+            pending.recipient_id,
+            &pending.text,
+        )
+    } else {
+        append_local_private_text_response(
+            manager,
+            &expected_target_id,
+            pending.recipient_id,
+            &pending.text,
+        )
+    })
 }
 
 #[derive(Debug, Deserialize)]
@@ -5690,6 +6278,7 @@ fn queue_group_info_request(
     match sender.0.try_send(NapcatOutboundMessage {
         request_id,
         target_id: target_id.clone(),
+        account_id: None,
         message,
     }) {
         Ok(()) => {
@@ -5748,20 +6337,34 @@ fn value_to_target_id(value: &Value) -> Option<String> {
     }
 }
 
+/// Optional resources owned by other plugins that incoming messages can feed.
+#[derive(SystemParam)]
+struct MessageSystemHooks<'w> {
+    scene_capture_requests: Option<ResMut<'w, SceneCaptureRequests>>,
+    scene_character_positions: Option<Res<'w, SceneCharacterPositions>>,
+    rule_engine_state: Option<ResMut<'w, RuleEngineState>>,
+    battle_round_store: Option<ResMut<'w, Persistent<BattleRoundStore>>>,
+}
+
 fn message_system(
     receiver: Res<NapcatIOReceiver>,
     sender: Option<Res<NapcatIOSender>>,
     mut automatic_replies: ResMut<NapcatAutomaticReplyRequests>,
     mut group_info_requests: ResMut<NapcatGroupInfoRequests>,
-    mut scene_capture_requests: Option<ResMut<SceneCaptureRequests>>,
-    scene_character_positions: Option<Res<SceneCharacterPositions>>,
+    mut hooks: MessageSystemHooks,
+    mut image_cache: ResMut<RemoteImageCache>,
     mut manager: ResMut<Persistent<NapcatMessageManager>>,
 ) {
-    while let Ok(msg) = receiver.0.try_recv() {
+    while let Ok(NapcatInboundMessage {
+        account_id,
+        message: msg,
+    }) = receiver.0.try_recv()
+    {
         let json_res = serde_json::from_str::<NapcatMessage>(&msg.to_string());
         if let Ok(mut json) = json_res {
+            json.data.account_id = account_id;
             dbg!(&json);
-            cache_message_images(&mut json);
+            cache_message_images(&image_cache.dir, &mut json);
             let target_id = match json.data.message_type {
                 NapcatMessageType::Private => {
                     if json.data.user_id == json.data.self_id {
@@ -5779,33 +6382,138 @@ fn message_system(
             manager.annotate_incoming_message_access(&target_id, &mut json);
 
             let auto_forward = auto_forward_request(&manager, &json, &target_id);
-            let character_creation_response = if is_incoming_message
+            let is_incoming_private = is_incoming_message
                 && matches!(
                     json.data.message_type,
                     NapcatMessageType::Private
-                ) {
-                private_detect_magic_response(
-                    &manager,
-                    &json,
+                );
+            let dice_roll = if is_incoming_private {
+                dice_roll_broadcast(&manager, &json, &target_id)
+            } else {
+                None
+            };
+            let skill_check = if is_incoming_private {
+                resolve_private_skill_check(
+                    &mut manager,
                     &target_id,
-                    scene_character_positions.as_deref(),
+                    message_text(&json).trim(),
+                    json.data.time,
                 )
-                .or_else(|| handle_character_creation_message(&mut manager, &json, &target_id))
+            } else {
+                None
+            };
+            if let (Some(rule_engine_state), Some((outcome, total))) = (
+                hooks.rule_engine_state.as_deref_mut(),
+                skill_check.as_ref().and_then(|check| check.outcome),
+            ) {
+                rule_engine_state.resolve_check(&target_id, outcome, total as f32);
+            }
+            let battle_command = if is_incoming_message {
+                let outcome = handle_battle_command(
+                    &mut manager,
+                    &json,
+                    hooks
+                        .battle_round_store
+                        .as_deref_mut()
+                        .map(|store| &mut **store),
+                    hooks.scene_character_positions.as_deref(),
+                );
+                if let (Some(_), Some(store)) = (
+                    outcome.as_ref(),
+                    hooks.battle_round_store.as_ref(),
+                ) {
+                    store.persist().ok();
+                }
+                outcome
+            } else {
+                None
+            };
+            let trade = if is_incoming_private {
+                handle_trade_command(&mut manager, &target_id, &json)
+            } else {
+                None
+            };
+            let character_creation_response = if is_incoming_private {
+                battle_command
+                    .as_ref()
+                    .map(|outcome| outcome.reply.clone())
+                    .or_else(|| {
+                        dice_roll
+                            .as_ref()
+                            .map(|dice_roll| dice_roll.roller_reply.clone())
+                    })
+                    .or_else(|| skill_check.map(|check| check.reply))
+                    .or_else(|| trade.as_ref().map(|trade| trade.reply.clone()))
+                    .or_else(|| {
+                        handle_shop_command(
+                            &mut manager,
+                            &target_id,
+                            &message_text(&json),
+                            json.data.time,
+                        )
+                    })
+                    .or_else(|| {
+                        handle_equipment_command(
+                            &mut manager,
+                            &target_id,
+                            &message_text(&json),
+                            hooks.rule_engine_state.as_deref_mut(),
+                        )
+                    })
+                    .or_else(|| {
+                        let reply = handle_item_use_command(
+                            &mut manager,
+                            &target_id,
+                            &message_text(&json),
+                            hooks.rule_engine_state.as_deref_mut(),
+                            hooks
+                                .battle_round_store
+                                .as_deref_mut()
+                                .map(|store| &mut **store),
+                            hooks.scene_character_positions.as_deref(),
+                        )?;
+                        if let Some(store) = hooks.battle_round_store.as_ref() {
+                            store.persist().ok();
+                        }
+                        Some(reply)
+                    })
+                    .or_else(|| {
+                        private_detect_magic_response(
+                            &manager,
+                            &json,
+                            &target_id,
+                            hooks.scene_character_positions.as_deref(),
+                        )
+                    })
+                    .or_else(|| handle_character_creation_message(&mut manager, &json, &target_id))
+            } else {
+                None
+            };
+            let status_card = if is_incoming_private
+                && character_creation_response.is_some()
+                && private_command_body(message_text(&json).trim()) == Some("状态")
+            {
+                render_character_status_card(&manager, &target_id, &mut image_cache)
+                    .inspect_err(|err| eprintln!("failed to render status card: {err}"))
+                    .ok()
             } else {
                 None
             };
             if let (Some(scene_capture_requests), Some(request)) = (
-                scene_capture_requests.as_deref_mut(),
+                hooks.scene_capture_requests.as_deref_mut(),
                 scene_capture_request(&manager, &json),
             ) {
                 scene_capture_requests.requests.push(request);
             }
-
-            manager
-                .messages
-                .entry(target_id.clone())
-                .or_default()
-                .push(json);
+            let dice_roll_record = dice_roll
+                .as_ref()
+                .filter(|dice_roll| !dice_roll.message.text.is_empty())
+                .map(|dice_roll| dice_roll_record(&json, &dice_roll.message));
+
+            manager.record_chat_target_account(&target_id, &json.data.account_id);
+            let chat = manager.messages.entry(target_id.clone()).or_default();
+            chat.push(json);
+            chat.extend(dice_roll_record);
             manager.chat_targets.entry(target_id.clone()).or_default();
             if let Some(kind) = manager
                 .messages
@@ -5832,18 +6540,84 @@ fn message_system(
                 sender.as_deref(),
                 character_creation_response.as_deref(),
             ) {
-                queue_private_text_response(
-                    sender,
-                    &mut automatic_replies,
-                    incoming_user_id,
-                    response.to_owned(),
-                );
+                if let Some(png) = status_card.as_deref() {
+                    queue_private_image_response(
+                        sender,
+                        &mut automatic_replies,
+                        incoming_user_id,
+                        png,
+                        response.to_owned(),
+                    );
+                } else {
+                    queue_private_text_response(
+                        sender,
+                        &mut automatic_replies,
+                        incoming_user_id,
+                        response.to_owned(),
+                    );
+                }
             }
 
             if let Err(err) = manager.persist() {
                 eprintln!("failed to persist NapCat messages: {err}");
             }
 
+            if let (Some(sender), Some(battle_command)) = (sender.as_deref(), battle_command) {
+                // Private replies already went out with the other private command replies.
+                if battle_command.private_reply {
+                    queue_private_text_response(
+                        sender,
+                        &mut automatic_replies,
+                        incoming_user_id,
+                        battle_command.reply,
+                    );
+                } else if let (false, Ok(group_id)) = (
+                    is_incoming_private,
+                    target_id.parse::<u64>(),
+                ) {
+                    queue_group_text_response(
+                        sender,
+                        &mut automatic_replies,
+                        group_id,
+                        battle_command.reply,
+                    );
+                }
+                for (recipient, text) in battle_command.notices {
+                    if let Ok(user_id) = recipient.parse::<u64>() {
+                        queue_private_text_response(
+                            sender,
+                            &mut automatic_replies,
+                            user_id,
+                            text,
+                        );
+                    }
+                }
+            }
+
+            if let (Some(sender), Some(trade)) = (sender.as_deref(), trade) {
+                for (recipient, text) in trade.notices {
+                    if let Ok(user_id) = recipient.parse::<u64>() {
+                        queue_private_text_response(
+                            sender,
+                            &mut automatic_replies,
+                            user_id,
+                            text,
+                        );
+                    }
+                }
+            }
+
+            if let (Some(sender), Some(dice_roll)) = (sender.as_deref(), dice_roll) {
+                for user_id in dice_roll.recipients {
+                    queue_private_text_response(
+                        sender,
+                        &mut automatic_replies,
+                        user_id,
+                        dice_roll.message.text.clone(),
+                    );
+                }
+            }
+
             if let (Some(sender), Some(auto_forward)) = (sender.as_deref(), auto_forward) {
                 for user_id in auto_forward.recipients {
                     queue_private_text_response(
@@ -5877,7 +6651,7 @@ fn message_system(
     }
 }
 
-fn cache_message_images(message: &mut NapcatMessage) {
+fn cache_message_images(cache_dir: &Path, message: &mut NapcatMessage) {
     for chain in &mut message.data.message {
         let NapcatMessageChainType::Image { data } = &mut chain.variant else {
             continue;
@@ -5886,7 +6660,7 @@ fn cache_message_images(message: &mut NapcatMessage) {
             continue;
         }
 
-        match cache_remote_image(data.url.trim()) {
+        match cache_remote_image(cache_dir, data.url.trim()) {
             Ok(path) => data.local_path = path.to_string_lossy().to_string(),
             Err(err) => eprintln!(
                 "failed to cache NapCat image {}: {err}",
@@ -5896,7 +6670,76 @@ fn cache_message_images(message: &mut NapcatMessage) {
     }
 }
 
-fn cache_remote_image(url: &str) -> Result<PathBuf, String> {
+/// Extensions `cache_remote_image` names its files with, by detected format.
+const REMOTE_IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "gif", "webp", "bmp", "img"];
+
+/// Where downloaded chat images and portraits live, plus the downloads still running in the
+/// background.
+#[derive(Resource)]
+struct RemoteImageCache {
+    dir: PathBuf,
+    prefetches: HashMap<String, Task<Result<PathBuf, String>>>,
+}
+
+impl Default for RemoteImageCache {
+    fn default() -> Self { Self::new(Path::new(".data").join("willowblossom").join("image_cache")) }
+}
+
+impl RemoteImageCache {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            prefetches: HashMap::new(),
+        }
+    }
+
+    /// The already downloaded copy of `url`, whatever format it turned out to be.
+    fn cached(&self, url: &str) -> Option<PathBuf> { cached_remote_image(&self.dir, url) }
+
+    /// Starts downloading `url` into the cache, for callers that run inside a frame and must not
+    /// wait on the network. Repeated requests share one download.
+    fn prefetch(&mut self, url: &str) {
+        if self.prefetches.contains_key(url) {
+            return;
+        }
+        let dir = self.dir.clone();
+        let owned_url = url.to_owned();
+        let task =
+            AsyncComputeTaskPool::get().spawn(async move { cache_remote_image(&dir, &owned_url) });
+        self.prefetches.insert(url.to_owned(), task);
+    }
+}
+
+fn remote_image_prefetch_system(mut image_cache: ResMut<RemoteImageCache>) {
+    image_cache.prefetches.retain(|url, task| {
+        let Some(result) = block_on(future::poll_once(task)) else {
+            return true;
+        };
+        if let Err(err) = result {
+            eprintln!("failed to cache image {url}: {err}");
+        }
+        false
+    });
+}
+
+fn remote_image_cache_stem(url: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn cached_remote_image(cache_dir: &Path, url: &str) -> Option<PathBuf> {
+    let stem = remote_image_cache_stem(url);
+    REMOTE_IMAGE_EXTENSIONS
+        .iter()
+        .map(|extension| cache_dir.join(format!("{stem}.{extension}")))
+        .find(|path| path.is_file())
+}
+
+fn cache_remote_image(cache_dir: &Path, url: &str) -> Result<PathBuf, String> {
+    if let Some(path) = cached_remote_image(cache_dir, url) {
+        return Ok(path);
+    }
     let response = reqwest::blocking::get(url).map_err(|err| err.to_string())?;
     if !response.status().is_success() {
         return Err(format!("HTTP {}", response.status()));
@@ -5913,13 +6756,10 @@ fn cache_remote_image(url: &str) -> Result<PathBuf, String> {
         _ => "img",
     };
 
-    let mut hasher = DefaultHasher::new();
-    url.hash(&mut hasher);
-    let cache_dir = Path::new(".data").join("willowblossom").join("image_cache");
-    fs::create_dir_all(&cache_dir).map_err(|err| err.to_string())?;
+    fs::create_dir_all(cache_dir).map_err(|err| err.to_string())?;
     let path = cache_dir.join(format!(
-        "{:016x}.{extension}",
-        hasher.finish()
+        "{}.{extension}",
+        remote_image_cache_stem(url)
     ));
     if !path.exists() {
         fs::write(&path, &bytes).map_err(|err| err.to_string())?;
@@ -5932,43 +6772,136 @@ fn queue_private_text_response(
     automatic_replies: &mut NapcatAutomaticReplyRequests,
     user_id: u64,
     text: String,
+) -> bool {
+    let segments = json!([
+        {
+            "type": "text",
+            "data": {
+                "text": &text
+            }
+        }
+    ]);
+    queue_private_segments_response(
+        sender,
+        automatic_replies,
+        user_id,
+        segments,
+        text,
+    )
+}
+
+/// Sends `png` as an inline image segment. `text` is what gets recorded in the local chat log once
+/// NapCat acknowledges the message.
+fn queue_private_image_response(
+    sender: &NapcatIOSender,
+    automatic_replies: &mut NapcatAutomaticReplyRequests,
+    user_id: u64,
+    png: &[u8],
+    text: String,
+) -> bool {
+    let segments = json!([
+        {
+            "type": "image",
+            "data": {
+                "file": format!("base64://{}", BASE64.encode(png))
+            }
+        }
+    ]);
+    queue_private_segments_response(
+        sender,
+        automatic_replies,
+        user_id,
+        segments,
+        text,
+    )
+}
+
+fn queue_private_segments_response(
+    sender: &NapcatIOSender,
+    automatic_replies: &mut NapcatAutomaticReplyRequests,
+    user_id: u64,
+    segments: Value,
+    text: String,
+) -> bool {
+    queue_automatic_reply(
+        sender,
+        automatic_replies,
+        user_id,
+        false,
+        segments,
+        text,
+    )
+}
+
+fn queue_group_text_response(
+    sender: &NapcatIOSender,
+    automatic_replies: &mut NapcatAutomaticReplyRequests,
+    group_id: u64,
+    text: String,
+) -> bool {
+    let segments = json!([
+        {
+            "type": "text",
+            "data": {
+                "text": &text
+            }
+        }
+    ]);
+    queue_automatic_reply(
+        sender,
+        automatic_replies,
+        group_id,
+        true,
+        segments,
+        text,
+    )
+}
+
+fn queue_automatic_reply(
+    sender: &NapcatIOSender,
+    automatic_replies: &mut NapcatAutomaticReplyRequests,
+    recipient_id: u64,
+    group: bool,
+    segments: Value,
+    text: String,
 ) -> bool {
     let request_id = automatic_replies.next_request_id;
     automatic_replies.next_request_id += 1;
-    let message = Message::Text(
+    let payload = if group {
+        json!({
+            "action": "send_group_msg",
+            "params": {
+                "group_id": recipient_id,
+                "message": segments
+            }
+        })
+    } else {
         json!({
             "action": "send_private_msg",
             "params": {
-                "user_id": user_id,
-                "message": [
-                    {
-                        "type": "text",
-                        "data": {
-                            "text": &text
-                        }
-                    }
-                ]
+                "user_id": recipient_id,
+                "message": segments
             }
         })
-        .to_string()
-        .into(),
-    );
+    };
+    let message = Message::Text(payload.to_string().into());
 
     if let Err(err) = sender.0.try_send(NapcatOutboundMessage {
         request_id,
-        target_id: user_id.to_string(),
+        target_id: recipient_id.to_string(),
+        account_id: None,
         message,
     }) {
-        eprintln!("failed to queue NapCat private text response: {err}");
+        eprintln!("failed to queue NapCat automatic reply: {err}");
         false
     } else {
-        automatic_replies.pending.insert(
-            request_id,
-            PendingAutomaticPrivateReply {
-                recipient_id: user_id,
+        automatic_replies
+            .pending
+            .insert(request_id, PendingAutomaticReply {
+                recipient_id,
+                group,
                 text,
-            },
-        );
+            });
         true
     }
 }
@@ -5978,6 +6911,36 @@ fn append_local_private_text_response(
     target_id: &str,
     recipient_id: u64,
     text: &str,
+) -> bool {
+    append_local_text_response(
+        manager,
+        target_id,
+        recipient_id,
+        false,
+        text,
+    )
+}
+
+fn append_local_group_text_response(
+    manager: &mut NapcatMessageManager,
+    group_id: u64,
+    text: &str,
+) -> bool {
+    append_local_text_response(
+        manager,
+        &group_id.to_string(),
+        group_id,
+        true,
+        text,
+    )
+}
+
+fn append_local_text_response(
+    manager: &mut NapcatMessageManager,
+    target_id: &str,
+    recipient_id: u64,
+    group: bool,
+    text: &str,
 ) -> bool {
     let self_id = manager
         .messages
@@ -5999,7 +6962,8 @@ fn append_local_private_text_response(
     let mut message = NapcatMessage {
         data: NapcatMessageData {
             time,
-            message_type: NapcatMessageType::Private,
+            message_id: String::new(),
+            message_type: if group { NapcatMessageType::Group } else { NapcatMessageType::Private },
             message: vec![NapcatMessageChain {
                 variant: NapcatMessageChainType::Text {
                     data: TextData {
@@ -6009,9 +6973,9 @@ fn append_local_private_text_response(
             }],
             self_id,
             user_id: self_id,
-            group_id: None,
+            group_id: group.then_some(recipient_id),
             group_name: None,
-            target_id: Some(recipient_id),
+            target_id: (!group).then_some(recipient_id),
             sender: NapcatSender {
                 user_id: self_id,
                 nickname: "GM".to_owned(),
@@ -6021,6 +6985,7 @@ fn append_local_private_text_response(
             party_id: None,
             visibility: Visibility::Public,
             access_scope_resolved: false,
+            account_id: String::new(),
         },
     };
     manager.annotate_message_access(target_id, &mut message);
@@ -6032,7 +6997,7 @@ fn append_local_private_text_response(
         .push(message);
     manager.chat_target_kinds.insert(
         target_id.to_owned(),
-        ChatTargetExportKind::Private,
+        if group { ChatTargetExportKind::Group } else { ChatTargetExportKind::Private },
     );
     true
 }
@@ -6221,6 +7186,12 @@ fn handle_character_creation_message(
 
     let creation_config = manager.character_creation_config_for_target(target_id);
     let stat_config = manager.character_stat_config_for_target(target_id);
+    let template = manager.creation_template_for_target(target_id);
+    let context = CreationContext {
+        template: &template,
+        skill_pool: &manager.skill_pool,
+        item_pool: &manager.item_pool,
+    };
     let character = manager
         .player_characters
         .entry(target_id.to_owned())
@@ -6231,18 +7202,23 @@ fn handle_character_creation_message(
             return Some("你已经有完成的角色卡了，如需修改请联系GM在角色编辑器中调整。".to_owned());
         }
         if character.creation_step != CharacterCreationStep::Normal {
-            return Some(character_creation_prompt(character));
+            return Some(character_creation_prompt(
+                character, &context,
+            ));
         }
 
         *character = PlayerCharacter::default();
         character.name = message.data.sender.nickname.clone();
         character.status_points = creation_config.0;
         character.exchange_points = creation_config.1;
-        character.creation_step = CharacterCreationStep::Str;
+        character.creation_step = template.first_step();
+        let first_prompt = if character.creation_step == CharacterCreationStep::Str {
+            enter_status_phase(character, &context)
+        } else {
+            character_creation_prompt(character, &context)
+        };
         return Some(format!(
-            "你还没有角色卡呢，接下来会开始建卡。\n你拥有{}点属性点，请将它分配到力量/敏捷/灵巧/体质/智力/智慧/知识/魅力上。\n请直接输入数字来增加当前属性；输入【..】退回上一个属性。\n{}",
-            character.status_points,
-            character_creation_prompt(character)
+            "你还没有角色卡呢，接下来会开始建卡。\n{first_prompt}"
         ));
     }
 
@@ -6254,10 +7230,14 @@ fn handle_character_creation_message(
         text.as_str(),
         ".." | ".。" | "。." | "。。"
     ) {
-        return Some(character_creation_back(character));
+        return Some(character_creation_back(
+            character, &context,
+        ));
     }
     if matches!(text.as_str(), "." | "。") {
-        return Some(character_creation_next(character));
+        return Some(character_creation_next(
+            character, &context,
+        ));
     }
 
     if let Some(status_key) = character.creation_step.status_key() {
@@ -6285,10 +7265,51 @@ fn handle_character_creation_message(
             return Some(character_status_confirmation(character));
         }
         character.creation_step = character.creation_step.next_status_step();
-        return Some(character_creation_prompt(character));
+        return Some(character_creation_prompt(
+            character, &context,
+        ));
     }
 
     match character.creation_step {
+        CharacterCreationStep::Race | CharacterCreationStep::Background => {
+            let race = character.creation_step == CharacterCreationStep::Race;
+            let choices = if race { &template.races } else { &template.backgrounds };
+            let Some(choice) = pick_creation_choice(choices, &text) else {
+                return Some(character_creation_prompt(
+                    character, &context,
+                ));
+            };
+            if race {
+                character.race = choice;
+            } else {
+                character.background = choice;
+            }
+            character.creation_step = template.next_step(character.creation_step);
+            Some(
+                if character.creation_step == CharacterCreationStep::Str {
+                    enter_status_phase(character, &context)
+                } else {
+                    character_creation_prompt(character, &context)
+                },
+            )
+        },
+        CharacterCreationStep::Skill if template.limits_skills() => Some(pick_creation_skill(
+            character, &context, &text,
+        )),
+        CharacterCreationStep::Kit => {
+            let Some(kit) = pick_starting_kit(&template.starting_kits, &text) else {
+                return Some(character_creation_prompt(
+                    character, &context,
+                ));
+            };
+            character.starting_kit = kit;
+            character.creation_step = template.next_step(CharacterCreationStep::Kit);
+            Some(format!(
+                "已选择起始装备「{}」。\n{}",
+                character.starting_kit,
+                character_creation_prompt(character, &context)
+            ))
+        },
         CharacterCreationStep::Skill => {
             character.skill_names.push(String::new());
             character.skill_notes.push(text);
@@ -6311,7 +7332,9 @@ fn handle_character_creation_message(
             }
             character.image = image;
             character.creation_step = CharacterCreationStep::Nickname;
-            Some(character_creation_prompt(character))
+            Some(character_creation_prompt(
+                character, &context,
+            ))
         },
         CharacterCreationStep::Nickname => {
             if text.is_empty() {
@@ -6325,17 +7348,343 @@ fn handle_character_creation_message(
             character.nickname = text;
             character.inited = true;
             character.creation_step = CharacterCreationStep::Normal;
+            grant_starting_kit(character, &context);
             update_character_from_status_with_config(character, &stat_config);
             Some(format!(
                 "是吗？「{}」真是个好名字呢，我十分期待您以后的表现。\n——兑换结束——",
                 character.nickname
             ))
         },
-        _ => None,
+        _ => None,
+    }
+}
+
+fn is_exchange_command(text: &str) -> bool { matches!(text.trim(), ".兑换" | "。兑换") }
+
+#[derive(Debug, Clone, PartialEq)]
+struct SkillCheckResolution {
+    reply: String,
+    outcome: Option<(CheckOutcome, i32)>,
+}
+
+fn parse_skill_check_command(text: &str) -> Option<Vec<&str>> {
+    let body = private_command_body(text)?;
+    let mut parts = body.split_whitespace();
+    let command = parts.next()?;
+    (command == "检定" || command.eq_ignore_ascii_case("check")).then(|| parts.collect())
+}
+
+fn parse_check_dc(text: &str) -> Option<i32> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix("dc")
+        .or_else(|| text.strip_prefix("DC"))
+        .or_else(|| text.strip_prefix("Dc"))
+        .unwrap_or(text);
+    text.parse::<i32>().ok()
+}
+
+fn resolve_private_skill_check(
+    manager: &mut NapcatMessageManager,
+    target_id: &str,
+    text: &str,
+    message_time: u64,
+) -> Option<SkillCheckResolution> {
+    resolve_private_skill_check_with(
+        manager,
+        target_id,
+        text,
+        message_time,
+        |sides| rand::rng().random_range(1..=sides),
+    )
+}
+
+fn resolve_private_skill_check_with(
+    manager: &mut NapcatMessageManager,
+    target_id: &str,
+    text: &str,
+    message_time: u64,
+    roll_die: impl FnOnce(u32) -> u32,
+) -> Option<SkillCheckResolution> {
+    let args = parse_skill_check_command(text)?;
+    let reply_only = |reply: &str| {
+        Some(SkillCheckResolution {
+            reply: reply.to_owned(),
+            outcome: None,
+        })
+    };
+    let mut status_key = None;
+    let mut dc = None;
+    for arg in &args {
+        if let Some(value) = parse_check_dc(arg).filter(|_| dc.is_none()) {
+            dc = Some(value);
+        } else if let Some(key) = parse_status_key(arg).filter(|_| status_key.is_none()) {
+            status_key = Some(key);
+        } else {
+            return reply_only("检定格式：【.检定 敏捷 15】或【.check dex dc15】。");
+        }
+    }
+
+    let Some(character) = manager
+        .player_characters
+        .get(target_id)
+        .filter(|character| character.inited)
+        .cloned()
+    else {
+        return reply_only("你还没有完成的角色卡。输入【.兑换】开始建卡。");
+    };
+    let group_name = manager
+        .group_name_for_player_target(target_id)
+        .map(str::to_owned);
+    let mut group = group_name
+        .as_deref()
+        .and_then(|group_name| manager.trpg_groups.get_mut(group_name));
+    let config = group
+        .as_ref()
+        .map(|group| group.check_config)
+        .unwrap_or_default();
+    let mut request = group
+        .as_mut()
+        .and_then(|group| group.pending_check_request_mut(target_id, status_key))
+        .filter(|request| dc.is_none_or(|dc| dc == request.dc));
+    let Some(status_key) = status_key.or_else(|| {
+        request
+            .as_ref()
+            .and_then(|request| parse_status_key(&request.status))
+    }) else {
+        return reply_only("请指定检定属性，例如【.检定 敏捷 15】。");
+    };
+    let Some(dc) = dc.or_else(|| request.as_ref().map(|request| request.dc)) else {
+        return reply_only("请指定检定难度，例如【.检定 敏捷 15】或【.check dex dc15】。");
+    };
+
+    let sides = config.dice_sides.max(1);
+    let natural = roll_die(sides).clamp(1, sides) as i32;
+    let modifier = get_character_status_value(
+        &character_total_status(&character),
+        status_key,
+    );
+    let total = natural + modifier;
+    let outcome = config.outcome(natural, total, dc);
+    if let Some(request) = request.as_mut() {
+        request
+            .results
+            .insert(target_id.to_owned(), TrpgCheckResult {
+                natural,
+                modifier,
+                total,
+                outcome,
+                time: message_time,
+            });
+    }
+
+    Some(SkillCheckResolution {
+        reply: format!(
+            "「{}」进行{}检定（DC {}）：1d{} = [{}] + {}({}) = {}，{}！",
+            character_display_name(&character, target_id),
+            status_key.zh(),
+            dc,
+            sides,
+            natural,
+            status_key.zh(),
+            modifier,
+            total,
+            outcome.explain()
+        ),
+        outcome: Some((outcome, total)),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct DiceRollBroadcast {
+    message: CampaignMessage,
+    roller_reply: String,
+    recipients: Vec<u64>,
+}
+
+/// Splits `.r <表达式> [原因]` / `.rh ...` into (hidden, expression, reason).
+fn parse_dice_roll_command(text: &str) -> Option<(bool, &str, &str)> {
+    let body = private_command_body(text)?;
+    let (hidden, rest) = if let Some(rest) = body.strip_prefix("rh") {
+        (true, rest)
+    } else {
+        (false, body.strip_prefix('r')?)
+    };
+    let is_roll = rest
+        .chars()
+        .next()
+        .is_none_or(|ch| ch.is_whitespace() || ch.is_ascii_digit() || matches!(ch, 'd' | 'D'));
+    if !is_roll {
+        return None;
+    }
+
+    let rest = rest.trim();
+    let (expression, reason) = rest
+        .split_once(char::is_whitespace)
+        .map(|(expression, reason)| (expression, reason.trim()))
+        .unwrap_or((rest, ""));
+    Some((
+        hidden,
+        if expression.is_empty() { "1d20" } else { expression },
+        reason,
+    ))
+}
+
+fn dice_roll_broadcast(
+    manager: &NapcatMessageManager,
+    message: &NapcatMessage,
+    target_id: &str,
+) -> Option<DiceRollBroadcast> {
+    let text = message_text(message);
+    let (hidden, expression, reason) = parse_dice_roll_command(&text)?;
+    let roller_id = message.data.user_id;
+    let group = manager.group_for_player_target(target_id);
+    let access = group
+        .map(|group| group.player_access(roller_id))
+        .unwrap_or(PlayerAccess {
+            player_id: roller_id,
+            ..Default::default()
+        });
+    let character = manager.player_characters.get(target_id);
+    let roller_name = character
+        .filter(|character| character.inited)
+        .map(|character| character_display_name(character, &message.data.sender.nickname))
+        .unwrap_or_else(|| message.data.sender.nickname.clone());
+
+    let roll = parse_dice_expression(expression).and_then(|expression| {
+        roll_dice_expression(&expression, |name| {
+            character_dice_attribute(
+                character.filter(|character| character.inited)?,
+                name,
+            )
+        })
+    });
+    let roll = match roll {
+        Ok(roll) => roll,
+        Err(err) => {
+            return Some(DiceRollBroadcast {
+                message: CampaignMessage {
+                    campaign_id: String::new(),
+                    sender_id: roller_id,
+                    sender_name: roller_name,
+                    source: MessageSource::Friend { user_id: roller_id },
+                    character_id: access.character_id,
+                    party_id: access.party_id,
+                    visibility: Visibility::Player(roller_id),
+                    text: String::new(),
+                    time: message.data.time,
+                },
+                roller_reply: format!("掷骰失败：{err}"),
+                recipients: Vec::new(),
+            });
+        },
+    };
+
+    let reason = if reason.is_empty() { String::new() } else { format!("（{reason}）") };
+    let text = format!(
+        "「{roller_name}」{}{reason}：{}",
+        if hidden { "暗骰" } else { "掷骰" },
+        roll.detail
+    );
+    let visibility = match (group, hidden, access.party_id.as_ref()) {
+        (None, ..) => Visibility::Player(roller_id),
+        (Some(_), true, _) => Visibility::Gm,
+        (Some(_), false, Some(party_id)) => Visibility::Party(party_id.clone()),
+        (Some(_), false, None) => Visibility::Public,
+    };
+    let recipients = group
+        .map(|group| dice_roll_recipients(group, &visibility, roller_id))
+        .unwrap_or_default();
+    let roller_reply = match (
+        hidden,
+        group.is_some(),
+        recipients.is_empty(),
+    ) {
+        (false, ..) => text.clone(),
+        (true, false, _) => "你当前不在TRPG组中，暗骰结果无法送达GM。".to_owned(),
+        (true, true, true) => "当前TRPG组没有配置GM，暗骰结果无法送达。".to_owned(),
+        (true, true, false) => format!("你进行了一次暗骰{reason}，结果已私下发送给GM。"),
+    };
+
+    Some(DiceRollBroadcast {
+        message: CampaignMessage {
+            campaign_id: group
+                .map(|group| group.campaign_id.trim())
+                .filter(|campaign_id| !campaign_id.is_empty())
+                .unwrap_or("default")
+                .to_owned(),
+            sender_id: roller_id,
+            sender_name: roller_name,
+            source: MessageSource::Friend { user_id: roller_id },
+            character_id: access.character_id,
+            party_id: access.party_id,
+            visibility,
+            text,
+            time: message.data.time,
+        },
+        roller_reply,
+        recipients,
+    })
+}
+
+/// The roll as a bot message in the roller's chat. It keeps the roll's visibility, so hidden and
+/// party rolls stay in the campaign log for exactly the readers the roll was sent to.
+fn dice_roll_record(incoming: &NapcatMessage, roll: &CampaignMessage) -> NapcatMessage {
+    let self_id = incoming.data.self_id;
+    NapcatMessage {
+        data: NapcatMessageData {
+            time: roll.time,
+            message_id: String::new(),
+            message_type: NapcatMessageType::Private,
+            message: vec![NapcatMessageChain {
+                variant: NapcatMessageChainType::Text {
+                    data: TextData {
+                        text: roll.text.clone(),
+                    },
+                },
+            }],
+            self_id,
+            user_id: self_id,
+            group_id: None,
+            group_name: None,
+            target_id: Some(roll.sender_id),
+            sender: NapcatSender {
+                user_id: self_id,
+                nickname: "骰子".to_owned(),
+            },
+            campaign_id: roll.campaign_id.clone(),
+            character_id: roll.character_id.clone(),
+            party_id: roll.party_id.clone(),
+            visibility: roll.visibility.clone(),
+            access_scope_resolved: true,
+            account_id: incoming.data.account_id.clone(),
+        },
     }
 }
 
-fn is_exchange_command(text: &str) -> bool { matches!(text.trim(), ".兑换" | "。兑换") }
+/// Resolves an attribute name in a dice expression, such as `力量` in `1d6+力量`, to the
+/// character's total stat.
+pub(crate) fn character_dice_attribute(character: &PlayerCharacter, name: &str) -> Option<i32> {
+    let status_key = parse_status_key(name)?;
+    Some(get_character_status_value(
+        &character_total_status(character),
+        status_key,
+    ))
+}
+
+fn dice_roll_recipients(group: &TrpgGroup, visibility: &Visibility, roller_id: u64) -> Vec<u64> {
+    let mut recipients = group
+        .players
+        .iter()
+        .filter_map(|player_id| player_id.parse::<u64>().ok())
+        .chain(group.gm_users.iter().copied())
+        .filter(|user_id| *user_id != roller_id)
+        .filter(|user_id| group.player_access(*user_id).can_read(visibility))
+        .collect::<Vec<_>>();
+    recipients.sort_unstable();
+    recipients.dedup();
+    recipients
+}
 
 fn handle_private_player_command(
     manager: &mut NapcatMessageManager,
@@ -6403,11 +7752,16 @@ fn format_private_help() -> String {
         "玩家命令帮助",
         "命令前缀可混用半角【.】或全角【。】，例如 .help / 。help。",
         "【.兑换】开始创建角色",
-        "【.状态】查看角色当前状态",
+        "【.状态】查看角色状态卡（图片，含立绘、装备、状态效果和技能冷却）",
         "【.属性说明】查看八项属性的完整说明",
+        "【.检定 <属性> <难度>】或【.check dex dc15】进行属性检定；回应GM检定请求时可只发【.检定】",
+        "【.r <表达式> [原因]】掷骰，例如 .r 3d6+2、.r 2d20kh1、.r 1d20+力量；支持kh/kl/dh/dl保留丢弃与!爆骰",
+        "【.rh <表达式> [原因]】暗骰，结果只私下发送给GM",
         "【.魔网】或【.weave】感知魔网",
         "【.侦测魔法】或【.detect magic】侦测附近的施法痕迹（需要INT 20）",
         "【.已兑换】查看技能与兑换内容",
+        "【.装备 [物品或背包编号]】查看或穿戴装备，【.卸下 <槽位或装备名>】卸下装备",
+        "【.使用 [物品或背包编号] [目标]】使用消耗品，战斗中会消耗本轮行动",
         "【.冷却】查看技能冷却",
         "【.频道人员】查看当前可见频道成员",
         "【.指南】查看当前TRPG组指南",
@@ -6787,9 +8141,15 @@ fn format_private_character_status(manager: &NapcatMessageManager, target_id: &s
         return "你还没有角色卡。输入【.兑换】开始建卡。".to_owned();
     };
     if !character.inited {
+        let template = manager.creation_template_for_target(target_id);
+        let context = CreationContext {
+            template: &template,
+            skill_pool: &manager.skill_pool,
+            item_pool: &manager.item_pool,
+        };
         return format!(
             "角色卡尚未完成。\n{}",
-            character_creation_prompt(character)
+            character_creation_prompt(character, &context)
         );
     }
 
@@ -7188,18 +8548,32 @@ fn format_character_status_totals(character: &PlayerCharacter) -> String {
     .join("\n")
 }
 
-fn character_creation_next(character: &mut PlayerCharacter) -> String {
+fn character_creation_next(character: &mut PlayerCharacter, context: &CreationContext) -> String {
     match character.creation_step {
         CharacterCreationStep::ConfirmStatus => {
             character.creation_step = CharacterCreationStep::Skill;
+            if context.template.limits_skills() {
+                return format!(
+                    "属性数据已录入。\n{}",
+                    creation_skill_prompt(character, context)
+                );
+            }
             "属性数据已录入。\n现在是技能兑换，请按你的技能描述发送文本；输入【.】可以跳过或结束技能录入。".to_owned()
         },
         CharacterCreationStep::Skill => {
             character.creation_step = CharacterCreationStep::ConfirmSkill;
-            character_creation_next(character)
+            character_creation_next(character, context)
         },
         CharacterCreationStep::ConfirmSkill => {
-            character.creation_step = CharacterCreationStep::Image;
+            character.creation_step = context
+                .template
+                .next_step(CharacterCreationStep::ConfirmSkill);
+            if character.creation_step == CharacterCreationStep::Kit {
+                return format!(
+                    "技能数据已录入。\n{}",
+                    starting_kit_prompt(&context.template.starting_kits)
+                );
+            }
             "技能数据已录入。现在请发送人物立绘图片链接；如果暂时没有，输入【.】跳过。".to_owned()
         },
         CharacterCreationStep::Image => {
@@ -7207,30 +8581,90 @@ fn character_creation_next(character: &mut PlayerCharacter) -> String {
             "图片已跳过。\n最后，请告诉我你的角色名，兑换即将结束。".to_owned()
         },
         CharacterCreationStep::Nickname => "请直接发送角色名完成建卡。".to_owned(),
-        _ => character_creation_prompt(character),
+        _ => character_creation_prompt(character, context),
     }
 }
 
-fn character_creation_back(character: &mut PlayerCharacter) -> String {
+fn character_creation_back(character: &mut PlayerCharacter, context: &CreationContext) -> String {
+    let template = context.template;
     if character.creation_step.status_key().is_some()
         || character.creation_step == CharacterCreationStep::ConfirmStatus
     {
+        let at_phase_start = template.stat_mode == CreationStatMode::Rolled
+            || (character.creation_step == CharacterCreationStep::Str
+                && total_allocated_status_points(&character.status) == 0);
+        if at_phase_start {
+            let Some(previous) = template.previous_step(character.creation_step) else {
+                return if template.stat_mode == CreationStatMode::Rolled {
+                    "属性已经掷定，不能重新掷骰。输入【.】确认。".to_owned()
+                } else {
+                    character_creation_prompt(character, context)
+                };
+            };
+            character.creation_step = previous;
+            return format!(
+                "已退回上一步。\n{}",
+                character_creation_prompt(character, context)
+            );
+        }
         reset_character_status_phase(character);
         return format!(
             "已退回属性兑换第一步，属性点已全部返还。\n{}",
-            character_creation_prompt(character)
+            character_creation_prompt(character, context)
         );
     }
 
     match character.creation_step {
+        CharacterCreationStep::Skill | CharacterCreationStep::ConfirmSkill
+            if template.stat_mode == CreationStatMode::Rolled =>
+        {
+            character.creation_step = CharacterCreationStep::ConfirmStatus;
+            format!(
+                "已退回属性确认。\n{}",
+                character_creation_prompt(character, context)
+            )
+        },
         CharacterCreationStep::Skill | CharacterCreationStep::ConfirmSkill => {
             reset_character_status_phase(character);
             format!(
                 "已退回属性兑换第一步，属性点已全部返还。\n{}",
-                character_creation_prompt(character)
+                character_creation_prompt(character, context)
             )
         },
-        CharacterCreationStep::Image => {
+        CharacterCreationStep::Race | CharacterCreationStep::Background => {
+            match template.previous_step(character.creation_step) {
+                Some(previous) => character.creation_step = previous,
+                None => {
+                    return format!(
+                        "已经是建卡第一步了。\n{}",
+                        character_creation_prompt(character, context)
+                    );
+                },
+            }
+            format!(
+                "已退回上一步。\n{}",
+                character_creation_prompt(character, context)
+            )
+        },
+        CharacterCreationStep::Image | CharacterCreationStep::Kit
+            if template.previous_step(character.creation_step)
+                == Some(CharacterCreationStep::Kit) =>
+        {
+            character.creation_step = CharacterCreationStep::Kit;
+            character.starting_kit.clear();
+            format!(
+                "已退回起始装备选择。\n{}",
+                character_creation_prompt(character, context)
+            )
+        },
+        CharacterCreationStep::Image | CharacterCreationStep::Kit if template.limits_skills() => {
+            character.creation_step = CharacterCreationStep::Skill;
+            format!(
+                "已退回技能选择。\n{}",
+                character_creation_prompt(character, context)
+            )
+        },
+        CharacterCreationStep::Image | CharacterCreationStep::Kit => {
             character.creation_step = CharacterCreationStep::Skill;
             "已退回技能兑换。请继续发送技能描述；输入【.】结束技能录入。".to_owned()
         },
@@ -7252,7 +8686,7 @@ fn character_creation_back(character: &mut PlayerCharacter) -> String {
     }
 }
 
-fn character_creation_prompt(character: &PlayerCharacter) -> String {
+fn character_creation_prompt(character: &PlayerCharacter, context: &CreationContext) -> String {
     if let Some(status_key) = character.creation_step.status_key() {
         return format!(
             "当前{}:「{}」 剩余属性点:「{}」",
@@ -7264,6 +8698,14 @@ fn character_creation_prompt(character: &PlayerCharacter) -> String {
 
     match character.creation_step {
         CharacterCreationStep::ConfirmStatus => character_status_confirmation(character),
+        CharacterCreationStep::Race => creation_choice_prompt("种族", &context.template.races),
+        CharacterCreationStep::Background => {
+            creation_choice_prompt("背景", &context.template.backgrounds)
+        },
+        CharacterCreationStep::Skill if context.template.limits_skills() => {
+            creation_skill_prompt(character, context)
+        },
+        CharacterCreationStep::Kit => starting_kit_prompt(&context.template.starting_kits),
         CharacterCreationStep::Skill => {
             format!(
                 "现在是技能兑换。你还剩余{}分，请发送技能描述；输入【.】结束技能录入。",
@@ -7526,6 +8968,7 @@ pub fn moonberry_physical_damage_followup_buff(source_id: &str, amount: f32) ->
             amount: amount.max(0.0),
             damage_type: DamageType::Magical,
         }],
+        conditions: Vec::new(),
     }
 }
 
@@ -7982,6 +9425,7 @@ fn moonberry_wound_buff(source_id: &str) -> BuffSpec {
             value: BuffValue::AddPercent(-25.0),
         }],
         tick_actions: Vec::new(),
+        conditions: Vec::new(),
     }
 }
 
@@ -8069,6 +9513,13 @@ fn message_text(message: &NapcatMessage) -> String {
             NapcatMessageChainType::Text { data } => Some(data.text.as_str()),
             NapcatMessageChainType::Source(_) => None,
             NapcatMessageChainType::Image { .. } => None,
+            NapcatMessageChainType::At { .. }
+            | NapcatMessageChainType::Reply { .. }
+            | NapcatMessageChainType::Face { .. }
+            | NapcatMessageChainType::Record { .. }
+            | NapcatMessageChainType::File { .. }
+            | NapcatMessageChainType::Forward { .. }
+            | NapcatMessageChainType::Json { .. } => None,
             NapcatMessageChainType::Unsupported => None,
         })
         .collect::<Vec<_>>()
@@ -8205,7 +9656,7 @@ fn is_auto_forward_quote(character: char) -> bool {
 mod tests {
     use super::*;
 
-    fn empty_manager() -> NapcatMessageManager {
+    pub(super) fn empty_manager() -> NapcatMessageManager {
         NapcatMessageManager {
             messages: HashMap::default(),
             chat_targets: HashMap::default(),
@@ -8223,6 +9674,7 @@ mod tests {
             skill_pool: Vec::new(),
             item_pool: Vec::new(),
             unit_pool: HashMap::default(),
+            chat_target_accounts: HashMap::default(),
         }
     }
 
@@ -8238,10 +9690,14 @@ mod tests {
         message
     }
 
-    fn test_message_with_text(message_type: NapcatMessageType, text: &str) -> NapcatMessage {
+    pub(super) fn test_message_with_text(
+        message_type: NapcatMessageType,
+        text: &str,
+    ) -> NapcatMessage {
         NapcatMessage {
             data: NapcatMessageData {
                 time: 1780132600,
+                message_id: String::new(),
                 message_type,
                 message: vec![NapcatMessageChain {
                     variant: NapcatMessageChainType::Text {
@@ -8264,6 +9720,7 @@ mod tests {
                 party_id: None,
                 visibility: Visibility::Public,
                 access_scope_resolved: false,
+                account_id: String::new(),
             },
         }
     }
@@ -8272,6 +9729,7 @@ mod tests {
         NapcatMessage {
             data: NapcatMessageData {
                 time: 1780132600,
+                message_id: String::new(),
                 message_type: NapcatMessageType::Private,
                 message: vec![NapcatMessageChain {
                     variant: NapcatMessageChainType::Image {
@@ -8299,6 +9757,7 @@ mod tests {
                 party_id: None,
                 visibility: Visibility::Public,
                 access_scope_resolved: false,
+                account_id: String::new(),
             },
         }
     }
@@ -9175,6 +10634,98 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn forward_export_keeps_range_audience_and_sender_media() {
+        let mut manager = empty_manager();
+        let mut group = TrpgGroup {
+            players: vec!["2".to_owned(), "3".to_owned(), "5".to_owned()],
+            group_chats: vec!["99".to_owned()],
+            ..Default::default()
+        };
+        group.ensure_party("red");
+        group.ensure_party("blue");
+        group.set_player_party("2", Some("red"));
+        group.set_player_party("3", Some("blue"));
+        manager.trpg_groups.insert("table".to_owned(), group);
+        manager.current_trpg_group = Some("table".to_owned());
+
+        let group_message = |user_id: u64, text: &str| {
+            let mut message = test_message_with_text(NapcatMessageType::Group, text);
+            message.data.group_id = Some(99);
+            message.data.user_id = user_id;
+            message.data.sender.user_id = user_id;
+            message.data.sender.nickname = format!("user-{user_id}");
+            manager.annotate_message_access("99", &mut message);
+            message
+        };
+        let mut red_image = group_message(2, "red scout");
+        red_image.data.message.push(NapcatMessageChain {
+            variant: NapcatMessageChainType::Image {
+                data: ImageData {
+                    sub_type: 0,
+                    file: "abc.jpg".to_owned(),
+                    url: "https://example.test/abc.jpg".to_owned(),
+                    file_id: String::new(),
+                    file_size: String::new(),
+                    local_path: String::new(),
+                },
+            },
+        });
+        let messages = vec![
+            group_message(5, "before range"),
+            red_image,
+            group_message(3, "blue secret"),
+            group_message(5, "public recap"),
+        ];
+
+        let red_audience = manager
+            .forward_audience_options()
+            .into_iter()
+            .find(|(label, _)| label == "队伍：red")
+            .map(|(_, access)| access)
+            .unwrap();
+        let selected = manager.forward_export_messages("99", &messages, 1..=3, &red_audience);
+        assert_eq!(
+            selected
+                .iter()
+                .map(|message| message_text(message))
+                .collect::<Vec<_>>(),
+            vec!["red scout".to_owned(), "public recap".to_owned()]
+        );
+        assert_eq!(
+            manager
+                .forward_export_messages(
+                    "99",
+                    &messages,
+                    1..=3,
+                    &manager.gm_access()
+                )
+                .len(),
+            3
+        );
+        assert_eq!(
+            manager
+                .forward_export_messages(
+                    "99",
+                    &messages,
+                    1..=3,
+                    &PlayerAccess::default()
+                )
+                .len(),
+            1
+        );
+
+        let nodes = forward_export_nodes(&selected);
+        assert_eq!(nodes[0]["type"], "node");
+        assert_eq!(nodes[0]["data"]["nickname"], "user-2");
+        assert_eq!(nodes[0]["data"]["user_id"], "2");
+        assert_eq!(
+            nodes[0]["data"]["content"][1],
+            serde_json::json!({ "type": "image", "data": { "file": "https://example.test/abc.jpg" } })
+        );
+        assert_eq!(nodes[1]["data"]["nickname"], "user-5");
+    }
+
     #[test]
     fn resolved_public_group_history_stays_public_after_sender_joins_a_party() {
         let mut manager = empty_manager();
@@ -9831,6 +11382,7 @@ mod tests {
                 note: "缓慢近战单位".to_owned(),
                 legacy_member_id: None,
                 character: completed_character("行尸"),
+                loot: UnitLootTable::default(),
             });
         manager
             .unit_pool
@@ -9839,6 +11391,7 @@ mod tests {
                 note: "远程单位".to_owned(),
                 legacy_member_id: None,
                 character: completed_character("弓手"),
+                loot: UnitLootTable::default(),
             });
         manager.messages.insert("2".to_owned(), vec![test_message(
             NapcatMessageType::Private,
@@ -9879,6 +11432,7 @@ mod tests {
                 note: String::new(),
                 legacy_member_id: None,
                 character: completed_character("直接单位"),
+                loot: UnitLootTable::default(),
             });
         manager
             .unit_pool
@@ -9887,6 +11441,7 @@ mod tests {
                 note: String::new(),
                 legacy_member_id: Some("20001".to_owned()),
                 character: completed_character("别名B"),
+                loot: UnitLootTable::default(),
             });
         manager
             .unit_pool
@@ -9895,6 +11450,7 @@ mod tests {
                 note: String::new(),
                 legacy_member_id: Some("20001".to_owned()),
                 character: completed_character("别名A"),
+                loot: UnitLootTable::default(),
             });
         manager.unit_pool.insert(
             "moonberry-unit-30001".to_owned(),
@@ -9903,6 +11459,7 @@ mod tests {
                 note: String::new(),
                 legacy_member_id: None,
                 character: completed_character("旧兼容单位"),
+                loot: UnitLootTable::default(),
             },
         );
 
@@ -9930,12 +11487,14 @@ mod tests {
             note: "导入版本".to_owned(),
             legacy_member_id: None,
             character: completed_character("新弓手"),
+            loot: UnitLootTable::default(),
         });
         source.unit_pool.insert("zombie".to_owned(), UnitPoolEntry {
             label: "行尸".to_owned(),
             note: "缓慢近战单位".to_owned(),
             legacy_member_id: None,
             character: completed_character("行尸"),
+            loot: UnitLootTable::default(),
         });
         let json = source.to_unit_pool_export_json().unwrap();
 
@@ -9950,6 +11509,7 @@ mod tests {
                 note: "本地旧版本".to_owned(),
                 legacy_member_id: None,
                 character: completed_character("旧弓手"),
+                loot: UnitLootTable::default(),
             });
 
         let imported = manager.merge_unit_pool_export_json(&json).unwrap();
@@ -10902,6 +12462,7 @@ mod tests {
                     Vec3::new(3.0, 0.0, -3.0),
                 ),
             ]),
+            cover: HashMap::new(),
         };
 
         let response = format_private_detect_magic(&manager, "2", Some(&positions));
@@ -10991,6 +12552,7 @@ mod tests {
                     Vec3::new(10.0, 0.0, 0.0),
                 ),
             ]),
+            cover: HashMap::new(),
         };
 
         let response = format_private_detect_magic(&manager, "2", Some(&positions));
@@ -11646,6 +13208,7 @@ mod tests {
                 "message_type": "group",
                 "message": [
                     { "type": "at", "data": { "qq": "123" } },
+                    { "type": "mface", "data": { "emoji_id": "1" } },
                     { "type": "text", "data": { "text": "hello group" } }
                 ],
                 "self_id": 3432505351,
@@ -11659,15 +13222,20 @@ mod tests {
         )
         .expect("group message should parse");
 
-        assert_eq!(message.data.message.len(), 2);
+        assert_eq!(message.data.message.len(), 3);
         assert!(matches!(
-            message.data.message[0].variant,
-            NapcatMessageChainType::Unsupported
+            &message.data.message[0].variant,
+            NapcatMessageChainType::At { data } if data.qq == "123"
         ));
         assert!(matches!(
             message.data.message[1].variant,
+            NapcatMessageChainType::Unsupported
+        ));
+        assert!(matches!(
+            message.data.message[2].variant,
             NapcatMessageChainType::Text { .. }
         ));
+        assert_eq!(message_text(&message), "hello group");
     }
 
     #[test]
@@ -12146,6 +13714,7 @@ mod tests {
         let outbound = NapcatOutboundMessage {
             request_id: 42,
             target_id: "10002".to_owned(),
+            account_id: None,
             message: Message::Text(
                 json!({
                     "action": "send_private_msg",
@@ -12165,6 +13734,7 @@ mod tests {
         let semantic_echo = NapcatOutboundMessage {
             request_id: 2_000_000,
             target_id: "99".to_owned(),
+            account_id: None,
             message: Message::Text(
                 json!({
                     "action": "get_group_info",
@@ -12254,7 +13824,7 @@ mod tests {
             PendingNapcatRequest {
                 request_id: 43,
                 target_id: "10003".to_owned(),
-                sent_at: now - NAPCAT_ACTION_RESPONSE_TIMEOUT - Duration::from_secs(1),
+                sent_at: now - NAPCAT_PENDING_REQUEST_TTL - Duration::from_secs(1),
             },
         )]);
         let (sender, receiver) = unbounded();
@@ -12262,6 +13832,7 @@ mod tests {
         let timed_out = receiver.try_recv().unwrap();
         assert_eq!(timed_out.request_id, 43);
         assert_eq!(timed_out.target_id, "10003");
+        assert!(timed_out.delivery_unknown);
         assert!(timed_out.error.unwrap().contains("响应超时"));
         assert!(timed_out_pending.is_empty());
     }
@@ -13238,11 +14809,12 @@ mod tests {
         assert!(automatic_replies.pending.contains_key(&outbound.request_id));
 
         assert_eq!(
-            apply_automatic_private_reply_result(
+            apply_automatic_reply_result(
                 &NapcatSendResult {
                     request_id: outbound.request_id,
                     target_id: "2".to_owned(),
                     error: None,
+                    delivery_unknown: false,
                 },
                 &mut automatic_replies,
                 &mut manager,
@@ -13280,11 +14852,12 @@ mod tests {
         let outbound = receiver.try_recv().unwrap();
 
         assert_eq!(
-            apply_automatic_private_reply_result(
+            apply_automatic_reply_result(
                 &NapcatSendResult {
                     request_id: outbound.request_id,
                     target_id: "2".to_owned(),
                     error: Some("NapCat rejected reply".to_owned()),
+                    delivery_unknown: false,
                 },
                 &mut automatic_replies,
                 &mut manager,
@@ -13745,4 +15318,304 @@ mod tests {
             .iter()
             .all(|slot| *slot == CharacterHotbarSlot::Empty));
     }
+
+    fn dice_roll_manager() -> NapcatMessageManager {
+        let mut manager = empty_manager();
+        manager.player_characters.insert(
+            "2".to_owned(),
+            completed_character("roller"),
+        );
+        manager.trpg_groups.insert("table".to_owned(), TrpgGroup {
+            campaign_id: "dice-test".to_owned(),
+            players: vec!["2".to_owned(), "3".to_owned(), "4".to_owned()],
+            gm_users: HashSet::from([9]),
+            parties: HashMap::from([
+                ("red".to_owned(), TrpgParty {
+                    name: "red".to_owned(),
+                    players: vec!["2".to_owned(), "3".to_owned()],
+                }),
+                ("blue".to_owned(), TrpgParty {
+                    name: "blue".to_owned(),
+                    players: vec!["4".to_owned()],
+                }),
+            ]),
+            player_parties: HashMap::from([
+                ("2".to_owned(), "red".to_owned()),
+                ("3".to_owned(), "red".to_owned()),
+                ("4".to_owned(), "blue".to_owned()),
+            ]),
+            ..Default::default()
+        });
+        manager.current_trpg_group = Some("table".to_owned());
+        manager
+    }
+
+    #[test]
+    fn dice_roll_command_accepts_expression_and_reason() {
+        assert_eq!(
+            parse_dice_roll_command(".r 3d6+2 攀爬"),
+            Some((false, "3d6+2", "攀爬"))
+        );
+        assert_eq!(
+            parse_dice_roll_command("。rh2d20kh1"),
+            Some((true, "2d20kh1", ""))
+        );
+        assert_eq!(
+            parse_dice_roll_command(".r"),
+            Some((false, "1d20", ""))
+        );
+        assert_eq!(parse_dice_roll_command(".状态"), None);
+        assert_eq!(parse_dice_roll_command(".rest"), None);
+    }
+
+    #[test]
+    fn public_dice_roll_resolves_attributes_and_reaches_party_and_gm() {
+        let manager = dice_roll_manager();
+        let message = test_private_message_from(2, ".r 1d1+力量 攀爬");
+
+        let roll = dice_roll_broadcast(&manager, &message, "2").unwrap();
+
+        assert_eq!(roll.message.campaign_id, "dice-test");
+        assert_eq!(
+            roll.message.visibility,
+            Visibility::Party("red".to_owned())
+        );
+        assert_eq!(
+            roll.message.text,
+            "「roller」掷骰（攀爬）：1d1+力量 = [1] + 力量(1) = 2"
+        );
+        assert_eq!(roll.roller_reply, roll.message.text);
+        assert_eq!(roll.recipients, vec![3, 9]);
+    }
+
+    #[test]
+    fn hidden_dice_roll_is_only_delivered_to_gm() {
+        let manager = dice_roll_manager();
+        let message = test_private_message_from(2, ".rh 1d1");
+
+        let roll = dice_roll_broadcast(&manager, &message, "2").unwrap();
+
+        assert_eq!(roll.message.visibility, Visibility::Gm);
+        assert_eq!(roll.recipients, vec![9]);
+        assert!(!roll.roller_reply.contains("= 1"));
+        assert!(!manager
+            .player_access_for_user(2)
+            .can_read(&roll.message.visibility));
+
+        let record = dice_roll_record(&message, &roll.message);
+        let logged = manager.campaign_message_for_target("2", &record);
+        assert_eq!(logged.visibility, Visibility::Gm);
+        assert_eq!(logged.campaign_id, "dice-test");
+        assert_eq!(logged.text, roll.message.text);
+        assert!(!manager
+            .player_access_for_user(2)
+            .can_read(&logged.visibility));
+        assert!(manager.gm_access().can_read(&logged.visibility));
+    }
+
+    #[test]
+    fn invalid_dice_roll_only_replies_to_roller() {
+        let manager = dice_roll_manager();
+        let message = test_private_message_from(2, ".r 1d20+运气");
+
+        let roll = dice_roll_broadcast(&manager, &message, "2").unwrap();
+
+        assert!(roll.recipients.is_empty());
+        assert_eq!(
+            roll.roller_reply,
+            "掷骰失败：未知属性「运气」。"
+        );
+    }
+
+    #[test]
+    fn check_config_orders_natural_rolls_before_margins() {
+        let config = TrpgCheckConfig::default();
+
+        assert_eq!(
+            config.outcome(20, 5, 30),
+            CheckOutcome::CriticalSuccess
+        );
+        assert_eq!(
+            config.outcome(1, 40, 10),
+            CheckOutcome::CriticalFailure
+        );
+        assert_eq!(
+            config.outcome(12, 25, 15),
+            CheckOutcome::CriticalSuccess
+        );
+        assert_eq!(
+            config.outcome(12, 15, 15),
+            CheckOutcome::Success
+        );
+        assert_eq!(
+            config.outcome(12, 14, 15),
+            CheckOutcome::Failure
+        );
+        assert_eq!(
+            config.outcome(3, 5, 15),
+            CheckOutcome::CriticalFailure
+        );
+
+        let config = TrpgCheckConfig {
+            critical_success_roll: 0,
+            critical_failure_margin: 0,
+            ..TrpgCheckConfig::default()
+        };
+        assert_eq!(
+            config.outcome(20, 10, 15),
+            CheckOutcome::Failure
+        );
+        assert_eq!(
+            config.outcome(2, 3, 15),
+            CheckOutcome::Failure
+        );
+    }
+
+    #[test]
+    fn skill_check_command_accepts_chinese_and_english_forms() {
+        let mut manager = dice_roll_manager();
+
+        let check = resolve_private_skill_check_with(
+            &mut manager,
+            "2",
+            ".检定 敏捷 15",
+            1,
+            |_| 13,
+        )
+        .unwrap();
+        assert_eq!(
+            check.reply,
+            "「roller」进行敏捷检定（DC 15）：1d20 = [13] + 敏捷(2) = 15，成功！"
+        );
+        assert_eq!(
+            check.outcome,
+            Some((CheckOutcome::Success, 15))
+        );
+
+        let check = resolve_private_skill_check_with(
+            &mut manager,
+            "2",
+            ".check dex dc15",
+            1,
+            |_| 10,
+        )
+        .unwrap();
+        assert_eq!(
+            check.outcome,
+            Some((CheckOutcome::Failure, 13))
+        );
+
+        let check = resolve_private_skill_check_with(
+            &mut manager,
+            "2",
+            ".检定 敏捷",
+            1,
+            |_| 10,
+        )
+        .unwrap();
+        assert!(check.outcome.is_none());
+        assert!(resolve_private_skill_check_with(&mut manager, "2", ".状态", 1, |_| 10).is_none());
+    }
+
+    #[test]
+    fn gm_check_request_collects_player_results() {
+        let mut manager = dice_roll_manager();
+        let request_id = manager
+            .trpg_groups
+            .get_mut("table")
+            .unwrap()
+            .open_check_request(
+                "力量",
+                12,
+                vec!["2".to_owned(), "3".to_owned(), "stranger".to_owned()],
+                1,
+            )
+            .unwrap();
+
+        let check =
+            resolve_private_skill_check_with(&mut manager, "2", ".检定", 2, |_| 20).unwrap();
+        assert_eq!(
+            check.outcome,
+            Some((CheckOutcome::CriticalSuccess, 21))
+        );
+
+        let request = &manager.trpg_groups["table"].check_requests[0];
+        assert_eq!(request.id, request_id);
+        assert_eq!(request.targets, vec![
+            "2".to_owned(),
+            "3".to_owned()
+        ]);
+        let result = &request.results["2"];
+        assert_eq!(
+            (
+                result.natural,
+                result.modifier,
+                result.total
+            ),
+            (20, 1, 21)
+        );
+        assert_eq!(
+            result.outcome,
+            CheckOutcome::CriticalSuccess
+        );
+        assert!(manager
+            .trpg_groups
+            .get_mut("table")
+            .unwrap()
+            .open_check_request(
+                "力量",
+                12,
+                vec!["stranger".to_owned()],
+                3
+            )
+            .is_none());
+
+        // Deleting the newest request must not let the next one reuse its id.
+        let group = manager.trpg_groups.get_mut("table").unwrap();
+        group.check_requests.clear();
+        let next_id = group
+            .open_check_request("力量", 12, vec!["2".to_owned()], 4)
+            .unwrap();
+        assert_eq!(next_id, request_id + 1);
+        assert!(resolve_private_skill_check_with(&mut manager, "2", ".检定", 5, |_| 10).is_some());
+        let request = &manager.trpg_groups["table"].check_requests[0];
+        assert_eq!(request.id, next_id);
+        assert!(request.results.contains_key("2"));
+    }
+
+    #[test]
+    fn chat_target_accounts_follow_the_latest_receiving_connection() {
+        let mut manager = empty_manager();
+        let mut first = test_private_message_from(2, "hi");
+        first.data.account_id = "main".to_owned();
+        let mut second = test_private_message_from(2, "again");
+        second.data.account_id = "side".to_owned();
+        let local_reply = test_private_message_from(2, "reply");
+        manager.messages.insert("2".to_owned(), vec![
+            first,
+            second,
+            local_reply,
+        ]);
+        manager.messages.insert("3".to_owned(), vec![
+            test_private_message_from(3, "legacy"),
+        ]);
+
+        assert!(manager.migrate_chat_target_accounts());
+        assert_eq!(
+            manager.chat_target_accounts,
+            HashMap::from([("2".to_owned(), "side".to_owned())])
+        );
+        assert!(!manager.migrate_chat_target_accounts());
+        manager.record_chat_target_account("2", "main");
+        manager.record_chat_target_account("3", "");
+        assert_eq!(
+            manager.chat_target_accounts,
+            HashMap::from([("2".to_owned(), "main".to_owned())])
+        );
+
+        let persisted = serde_json::to_string(&manager.messages["3"][0]).unwrap();
+        assert!(!persisted.contains("account_id"));
+        let restored = serde_json::from_str::<NapcatMessage>(&persisted).unwrap();
+        assert!(restored.data.account_id.is_empty());
+    }
 }
@@ -0,0 +1,1020 @@
+use std::{
+    collections::VecDeque,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use bevy::prelude::*;
+use bevy_persistent::prelude::*;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::Value;
+use tokio::sync::mpsc::{
+    error::TryRecvError,
+    Receiver,
+    Sender,
+};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use super::{
+    message_segment_preview,
+    NapcatConnectionStatuses,
+    NapcatMessageChain,
+    NapcatOutboundMessage,
+    NapcatSendResult,
+    NapcatSendResultReceiver,
+    NAPCAT_PENDING_REQUEST_TTL,
+};
+
+/// Attempt ids handed to the connection layer live above every producer's request id range,
+/// so a late response to a timed-out attempt can never be mistaken for a producer's request.
+const NAPCAT_QUEUE_DISPATCH_ID_BASE: u64 = 1 << 40;
+/// Request ids from `allocate_request_id` sit above the per-session counters some producers
+/// still keep and below the attempt ids.
+const NAPCAT_QUEUE_REQUEST_ID_BASE: u64 = 1 << 32;
+const NAPCAT_RATE_LIMIT_WINDOW_MS: u64 = 60_000;
+/// The queue's response timeout has to fire before connections drop their correlation state,
+/// otherwise every slow answer would surface as a lost connection instead.
+pub const NAPCAT_QUEUE_MAX_RESPONSE_TIMEOUT_SECS: u64 = NAPCAT_PENDING_REQUEST_TTL.as_secs() - 1;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct NapcatOutboundQueueConfig {
+    /// Sends per minute across all recipients; 0 disables the limit.
+    #[serde(default = "default_queue_global_per_minute")]
+    pub global_per_minute: u32,
+    /// Sends per minute to one user or group; 0 disables the limit.
+    #[serde(default = "default_queue_per_target_per_minute")]
+    pub per_target_per_minute: u32,
+    #[serde(default = "default_queue_min_interval_ms")]
+    pub min_interval_ms: u64,
+    #[serde(default = "default_queue_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_queue_retry_base_ms")]
+    pub retry_base_ms: u64,
+    #[serde(default = "default_queue_retry_max_ms")]
+    pub retry_max_ms: u64,
+    /// How long an attempt may wait for NapCat's action response before it counts as failed.
+    /// Capped below the connections' own request TTL.
+    #[serde(default = "default_queue_response_timeout_secs")]
+    pub response_timeout_secs: u64,
+    /// Finished entries kept in the delivery log.
+    #[serde(default = "default_queue_log_limit")]
+    pub log_limit: usize,
+}
+
+fn default_queue_global_per_minute() -> u32 { 20 }
+
+fn default_queue_per_target_per_minute() -> u32 { 8 }
+
+fn default_queue_min_interval_ms() -> u64 { 800 }
+
+fn default_queue_max_attempts() -> u32 { 4 }
+
+fn default_queue_retry_base_ms() -> u64 { 2_000 }
+
+fn default_queue_retry_max_ms() -> u64 { 60_000 }
+
+fn default_queue_response_timeout_secs() -> u64 { 15 }
+
+fn default_queue_log_limit() -> usize { 300 }
+
+impl Default for NapcatOutboundQueueConfig {
+    fn default() -> Self {
+        Self {
+            global_per_minute: default_queue_global_per_minute(),
+            per_target_per_minute: default_queue_per_target_per_minute(),
+            min_interval_ms: default_queue_min_interval_ms(),
+            max_attempts: default_queue_max_attempts(),
+            retry_base_ms: default_queue_retry_base_ms(),
+            retry_max_ms: default_queue_retry_max_ms(),
+            response_timeout_secs: default_queue_response_timeout_secs(),
+            log_limit: default_queue_log_limit(),
+        }
+    }
+}
+
+impl NapcatOutboundQueueConfig {
+    /// Backoff before the attempt after `attempts` failures: base, 2×base, 4×base… capped.
+    pub fn retry_delay_ms(&self, attempts: u32) -> u64 {
+        let exponent = attempts.saturating_sub(1).min(16);
+        self.retry_base_ms
+            .saturating_mul(1 << exponent)
+            .min(self.retry_max_ms.max(self.retry_base_ms))
+    }
+
+    pub fn effective_response_timeout_secs(&self) -> u64 {
+        self.response_timeout_secs.clamp(
+            1,
+            NAPCAT_QUEUE_MAX_RESPONSE_TIMEOUT_SECS,
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NapcatDeliveryState {
+    Queued,
+    InFlight,
+    Retrying,
+    Sent,
+    Failed,
+    Cancelled,
+    /// The response timed out, so the message may or may not have reached QQ. It is not retried
+    /// automatically; the GM can resend it from the delivery log.
+    Unknown,
+}
+
+impl NapcatDeliveryState {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Queued => "排队中",
+            Self::InFlight => "发送中",
+            Self::Retrying => "等待重试",
+            Self::Sent => "已送达",
+            Self::Failed => "失败",
+            Self::Cancelled => "已取消",
+            Self::Unknown => "结果未知",
+        }
+    }
+
+    pub fn is_active(self) -> bool {
+        matches!(
+            self,
+            Self::Queued | Self::InFlight | Self::Retrying
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct NapcatQueuedMessage {
+    pub id: u64,
+    /// Producer's request id, reported back once the entry finishes.
+    pub request_id: u64,
+    pub target_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
+    pub action: String,
+    /// `private:<user>` or `group:<group>`; rate limits are counted per recipient.
+    pub recipient: String,
+    #[serde(default)]
+    pub summary: String,
+    pub payload: String,
+    pub state: NapcatDeliveryState,
+    #[serde(default)]
+    pub attempts: u32,
+    pub created_at_ms: u64,
+    pub updated_at_ms: u64,
+    #[serde(default)]
+    pub next_attempt_at_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispatch_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Only entries queued by a producer in this run report back; restored and resent entries
+    /// have nobody waiting for them.
+    #[serde(skip)]
+    pub report_result: bool,
+}
+
+#[derive(Resource, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct NapcatOutboundQueue {
+    #[serde(default)]
+    pub config: NapcatOutboundQueueConfig,
+    #[serde(default)]
+    pub entries: Vec<NapcatQueuedMessage>,
+    #[serde(default)]
+    next_entry_id: u64,
+    #[serde(default)]
+    next_dispatch_id: u64,
+    /// Last producer request id handed out. Persisted with the log, so a restart never hands out
+    /// an id an entry still carries.
+    #[serde(default)]
+    next_request_id: u64,
+    #[serde(skip)]
+    recent_dispatches: VecDeque<(u64, String)>,
+}
+
+/// Outcome of feeding a connection-layer result into the queue.
+#[derive(Debug, PartialEq, Eq)]
+pub enum NapcatQueueResult {
+    /// Not a queued send; hand it to the consumers unchanged.
+    Passthrough(NapcatSendResult),
+    /// A queued send finished; report it under the producer's request id.
+    Finished(NapcatSendResult),
+    /// Consumed by the queue (retry scheduled, stale attempt, or nobody waiting).
+    Handled,
+}
+
+impl NapcatOutboundQueue {
+    /// Only message sends are queued; lookups such as `get_group_info` bypass the queue.
+    pub fn is_queued_action(action: &str) -> bool { action.starts_with("send_") }
+
+    /// Returns false when the same producer request is still waiting in the queue.
+    pub fn enqueue(&mut self, outbound: &NapcatOutboundMessage, now_ms: u64) -> bool {
+        if self.entries.iter().any(|entry| {
+            entry.report_result
+                && entry.request_id == outbound.request_id
+                && entry.target_id == outbound.target_id
+        }) {
+            return false;
+        }
+        let payload = outbound.message.to_text().unwrap_or_default().to_owned();
+        let parsed = serde_json::from_str::<Value>(&payload).unwrap_or_default();
+        let action = parsed
+            .get("action")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned();
+        let id = self.allocate_entry_id();
+        self.entries.push(NapcatQueuedMessage {
+            id,
+            request_id: outbound.request_id,
+            target_id: outbound.target_id.clone(),
+            account_id: outbound.account_id.clone(),
+            recipient: action_recipient(&parsed),
+            summary: action_summary(&parsed),
+            action,
+            payload,
+            state: NapcatDeliveryState::Queued,
+            attempts: 0,
+            created_at_ms: now_ms,
+            updated_at_ms: now_ms,
+            next_attempt_at_ms: now_ms,
+            dispatch_id: None,
+            last_error: None,
+            report_result: true,
+        });
+        true
+    }
+
+    /// Settles the log after a restart. Attempts that were in flight may already have gone out,
+    /// so like a timeout they become unknown instead of being resent; queued and retrying entries
+    /// keep their place. Their producers are gone, so none of them report results.
+    pub fn restore_after_restart(&mut self, now_ms: u64) -> bool {
+        let mut changed = false;
+        for entry in &mut self.entries {
+            entry.report_result = false;
+            if entry.state == NapcatDeliveryState::InFlight {
+                entry.state = NapcatDeliveryState::Unknown;
+                entry.dispatch_id = None;
+                entry.updated_at_ms = now_ms;
+                entry.last_error = Some("发送中途重启，可能已经送达，未自动重发".to_owned());
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Starts the next eligible entry, honouring backoff and rate limits. The returned message
+    /// carries a fresh attempt id instead of the producer's request id.
+    pub fn next_dispatch(&mut self, now_ms: u64) -> Option<NapcatOutboundMessage> {
+        self.prune_recent(now_ms);
+        if self
+            .recent_dispatches
+            .back()
+            .is_some_and(|(sent_at, _)| now_ms < sent_at + self.config.min_interval_ms)
+        {
+            return None;
+        }
+        if self.config.global_per_minute > 0
+            && self.recent_dispatches.len() >= self.config.global_per_minute as usize
+        {
+            return None;
+        }
+        let per_target = self.config.per_target_per_minute as usize;
+        let index = self.entries.iter().position(|entry| {
+            matches!(
+                entry.state,
+                NapcatDeliveryState::Queued | NapcatDeliveryState::Retrying
+            ) && entry.next_attempt_at_ms <= now_ms
+                && (per_target == 0
+                    || self
+                        .recent_dispatches
+                        .iter()
+                        .filter(|(_, recipient)| *recipient == entry.recipient)
+                        .count()
+                        < per_target)
+        })?;
+
+        let dispatch_id = NAPCAT_QUEUE_DISPATCH_ID_BASE + self.next_dispatch_id;
+        self.next_dispatch_id += 1;
+        let entry = &mut self.entries[index];
+        entry.state = NapcatDeliveryState::InFlight;
+        entry.attempts += 1;
+        entry.dispatch_id = Some(dispatch_id);
+        entry.updated_at_ms = now_ms;
+        self.recent_dispatches
+            .push_back((now_ms, entry.recipient.clone()));
+        Some(NapcatOutboundMessage {
+            request_id: dispatch_id,
+            target_id: entry.target_id.clone(),
+            account_id: entry.account_id.clone(),
+            message: Message::Text(entry.payload.clone().into()),
+        })
+    }
+
+    /// Returns a dispatched attempt to the line when the connection layer could not take it.
+    pub fn requeue_undispatched(&mut self, dispatch_id: u64) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.dispatch_id == Some(dispatch_id))
+        {
+            entry.state = NapcatDeliveryState::Queued;
+            entry.attempts = entry.attempts.saturating_sub(1);
+            entry.dispatch_id = None;
+        }
+    }
+
+    pub fn apply_result(&mut self, result: NapcatSendResult, now_ms: u64) -> NapcatQueueResult {
+        if result.request_id < NAPCAT_QUEUE_DISPATCH_ID_BASE {
+            return NapcatQueueResult::Passthrough(result);
+        }
+        let Some(index) = self.entries.iter().position(|entry| {
+            matches!(
+                entry.state,
+                NapcatDeliveryState::InFlight | NapcatDeliveryState::Unknown
+            ) && entry.dispatch_id == Some(result.request_id)
+        }) else {
+            return NapcatQueueResult::Handled;
+        };
+        if self.entries[index].state == NapcatDeliveryState::Unknown {
+            // A late answer only settles the log; the producer already heard about the timeout.
+            let entry = &mut self.entries[index];
+            entry.dispatch_id = None;
+            entry.updated_at_ms = now_ms;
+            entry.state = if result.error.is_none() {
+                NapcatDeliveryState::Sent
+            } else {
+                NapcatDeliveryState::Failed
+            };
+            entry.last_error = result.error;
+            return NapcatQueueResult::Handled;
+        }
+        if let (true, Some(error)) = (result.delivery_unknown, &result.error) {
+            // The connection dropped the request after writing it, so no answer will follow.
+            self.entries[index].dispatch_id = None;
+            let finished = self.mark_unknown(
+                index,
+                format!("{error}，可能已经送达，未自动重发"),
+                now_ms,
+            );
+            self.trim_log();
+            return finished;
+        }
+        self.finish_attempt(index, result.error, now_ms)
+    }
+
+    /// Gives up on attempts that have waited longer than the configured response timeout. A slow
+    /// response may still mean the message went out, so they are marked unknown instead of being
+    /// retried, which would risk a duplicate; a late answer still settles the log entry.
+    pub fn expire_in_flight(&mut self, now_ms: u64) -> Vec<NapcatSendResult> {
+        let timeout_secs = self.config.effective_response_timeout_secs();
+        let timeout_ms = timeout_secs * 1000;
+        let expired = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                entry.state == NapcatDeliveryState::InFlight
+                    && now_ms >= entry.updated_at_ms + timeout_ms
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        let error = format!("NapCat响应超时（{timeout_secs}秒），可能已经送达，未自动重发");
+        let results = expired
+            .into_iter()
+            .filter_map(
+                |index| match self.mark_unknown(index, error.clone(), now_ms) {
+                    NapcatQueueResult::Finished(result) => Some(result),
+                    _ => None,
+                },
+            )
+            .collect();
+        self.trim_log();
+        results
+    }
+
+    fn mark_unknown(&mut self, index: usize, error: String, now_ms: u64) -> NapcatQueueResult {
+        let entry = &mut self.entries[index];
+        entry.state = NapcatDeliveryState::Unknown;
+        entry.updated_at_ms = now_ms;
+        entry.last_error = Some(error.clone());
+        match self.entry_result(index, Some(error)) {
+            NapcatQueueResult::Finished(result) => NapcatQueueResult::Finished(NapcatSendResult {
+                delivery_unknown: true,
+                ..result
+            }),
+            other => other,
+        }
+    }
+
+    fn finish_attempt(
+        &mut self,
+        index: usize,
+        error: Option<String>,
+        now_ms: u64,
+    ) -> NapcatQueueResult {
+        let max_attempts = self.config.max_attempts.max(1);
+        let retry_delay_ms = self.config.retry_delay_ms(self.entries[index].attempts);
+        let entry = &mut self.entries[index];
+        entry.dispatch_id = None;
+        entry.updated_at_ms = now_ms;
+        match &error {
+            None => {
+                entry.state = NapcatDeliveryState::Sent;
+                entry.last_error = None;
+            },
+            Some(error) if entry.attempts < max_attempts => {
+                entry.state = NapcatDeliveryState::Retrying;
+                entry.next_attempt_at_ms = now_ms + retry_delay_ms;
+                entry.last_error = Some(error.clone());
+                return NapcatQueueResult::Handled;
+            },
+            Some(error) => {
+                entry.state = NapcatDeliveryState::Failed;
+                entry.last_error = Some(error.clone());
+            },
+        }
+        let finished = self.entry_result(index, error);
+        self.trim_log();
+        finished
+    }
+
+    fn entry_result(&mut self, index: usize, error: Option<String>) -> NapcatQueueResult {
+        let entry = &mut self.entries[index];
+        if !std::mem::take(&mut entry.report_result) {
+            return NapcatQueueResult::Handled;
+        }
+        NapcatQueueResult::Finished(NapcatSendResult {
+            request_id: entry.request_id,
+            target_id: entry.target_id.clone(),
+            error,
+            delivery_unknown: false,
+        })
+    }
+
+    /// Queues a copy of a logged entry. Nobody waits on the copy, so its result only shows in
+    /// the delivery log.
+    pub fn resend(&mut self, id: u64, now_ms: u64) -> bool {
+        let Some(entry) = self.entries.iter().find(|entry| entry.id == id).cloned() else {
+            return false;
+        };
+        let id = self.allocate_entry_id();
+        self.entries.push(NapcatQueuedMessage {
+            id,
+            state: NapcatDeliveryState::Queued,
+            attempts: 0,
+            created_at_ms: now_ms,
+            updated_at_ms: now_ms,
+            next_attempt_at_ms: now_ms,
+            dispatch_id: None,
+            last_error: None,
+            report_result: false,
+            ..entry
+        });
+        true
+    }
+
+    /// Skips the backoff of a waiting retry.
+    pub fn retry_now(&mut self, id: u64, now_ms: u64) -> bool {
+        let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.id == id && entry.state == NapcatDeliveryState::Retrying)
+        else {
+            return false;
+        };
+        entry.next_attempt_at_ms = now_ms;
+        true
+    }
+
+    /// Cancels an entry that has not been handed to NapCat yet. Its producer hears about it
+    /// through `settle_cancelled`.
+    pub fn cancel(&mut self, id: u64, now_ms: u64) -> bool {
+        let Some(entry) = self.entries.iter_mut().find(|entry| {
+            entry.id == id
+                && matches!(
+                    entry.state,
+                    NapcatDeliveryState::Queued | NapcatDeliveryState::Retrying
+                )
+        }) else {
+            return false;
+        };
+        entry.state = NapcatDeliveryState::Cancelled;
+        entry.updated_at_ms = now_ms;
+        entry.last_error = Some("已从发送队列取消".to_owned());
+        true
+    }
+
+    pub fn settle_cancelled(&mut self) -> Vec<NapcatSendResult> {
+        let cancelled = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                entry.state == NapcatDeliveryState::Cancelled && entry.report_result
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        let results = cancelled
+            .into_iter()
+            .filter_map(|index| {
+                let error = self.entries[index].last_error.clone();
+                match self.entry_result(index, error) {
+                    NapcatQueueResult::Finished(result) => Some(result),
+                    _ => None,
+                }
+            })
+            .collect();
+        self.trim_log();
+        results
+    }
+
+    pub fn clear_finished(&mut self) { self.entries.retain(|entry| entry.state.is_active()); }
+
+    /// A request id for a producer that has no counter of its own to keep across restarts.
+    pub fn allocate_request_id(&mut self) -> u64 {
+        self.next_request_id += 1;
+        NAPCAT_QUEUE_REQUEST_ID_BASE + self.next_request_id
+    }
+
+    fn allocate_entry_id(&mut self) -> u64 {
+        self.next_entry_id += 1;
+        self.next_entry_id
+    }
+
+    fn prune_recent(&mut self, now_ms: u64) {
+        while self
+            .recent_dispatches
+            .front()
+            .is_some_and(|(sent_at, _)| now_ms >= sent_at + NAPCAT_RATE_LIMIT_WINDOW_MS)
+        {
+            self.recent_dispatches.pop_front();
+        }
+    }
+
+    fn trim_log(&mut self) {
+        let finished = self
+            .entries
+            .iter()
+            .filter(|entry| !entry.state.is_active())
+            .count();
+        let mut excess = finished.saturating_sub(self.config.log_limit);
+        self.entries.retain(|entry| {
+            if excess > 0 && !entry.state.is_active() && !entry.report_result {
+                excess -= 1;
+                return false;
+            }
+            true
+        });
+    }
+}
+
+fn action_recipient(payload: &Value) -> String {
+    let params = payload.get("params");
+    let id = |key: &str| {
+        params.and_then(|params| params.get(key)).and_then(|id| {
+            id.as_u64()
+                .map(|id| id.to_string())
+                .or(id.as_str().map(str::to_owned))
+        })
+    };
+    match (id("group_id"), id("user_id")) {
+        (Some(group_id), _) => format!("group:{group_id}"),
+        (None, Some(user_id)) => format!("private:{user_id}"),
+        (None, None) => "unknown".to_owned(),
+    }
+}
+
+fn action_summary(payload: &Value) -> String {
+    let Some(params) = payload.get("params") else {
+        return String::new();
+    };
+    if let Some(nodes) = params.get("messages").and_then(Value::as_array) {
+        return format!("[合并转发] {}条", nodes.len());
+    }
+    match params.get("message") {
+        Some(Value::String(text)) => text.clone(),
+        Some(segments) => serde_json::from_value::<Vec<NapcatMessageChain>>(segments.clone())
+            .map(|chains| {
+                chains
+                    .iter()
+                    .filter_map(message_segment_preview)
+                    .collect::<String>()
+            })
+            .unwrap_or_default(),
+        None => String::new(),
+    }
+}
+
+pub(super) fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Producer side of the queue: `NapcatIOSender` feeds `intake`, attempts leave through
+/// `dispatch` to the connection supervisor.
+#[derive(Resource)]
+pub(super) struct NapcatOutboundQueueChannels {
+    pub intake: Receiver<NapcatOutboundMessage>,
+    pub dispatch: Sender<NapcatOutboundMessage>,
+}
+
+/// Results the queue has settled, for `send_result_system` to hand to their producers.
+#[derive(Resource, Default)]
+pub(super) struct NapcatQueueSettledResults(pub Vec<NapcatSendResult>);
+
+pub(super) fn restore_outbound_queue_system(mut queue: ResMut<Persistent<NapcatOutboundQueue>>) {
+    if queue.restore_after_restart(unix_time_ms()) {
+        if let Err(err) = queue.persist() {
+            eprintln!("failed to persist NapCat outbound queue: {err}");
+        }
+    }
+}
+
+pub(super) fn outbound_queue_system(
+    mut queue: ResMut<Persistent<NapcatOutboundQueue>>,
+    mut channels: ResMut<NapcatOutboundQueueChannels>,
+    mut settled: ResMut<NapcatQueueSettledResults>,
+    results: Res<NapcatSendResultReceiver>,
+    statuses: Res<NapcatConnectionStatuses>,
+) {
+    let now_ms = unix_time_ms();
+    let mut changed = false;
+    loop {
+        let outbound = match channels.intake.try_recv() {
+            Ok(outbound) => outbound,
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+        };
+        let action = outbound
+            .message
+            .to_text()
+            .ok()
+            .and_then(|text| serde_json::from_str::<Value>(text).ok())
+            .and_then(|payload| payload.get("action")?.as_str().map(str::to_owned))
+            .unwrap_or_default();
+        if !NapcatOutboundQueue::is_queued_action(&action) {
+            if let Err(err) = channels.dispatch.try_send(outbound) {
+                eprintln!("failed to forward NapCat action: {err}");
+            }
+            continue;
+        }
+        if queue.enqueue(&outbound, now_ms) {
+            changed = true;
+        } else {
+            eprintln!(
+                "dropped duplicate NapCat request {} for {}",
+                outbound.request_id, outbound.target_id
+            );
+        }
+    }
+
+    while let Ok(result) = results.0.try_recv() {
+        match queue.apply_result(result, now_ms) {
+            NapcatQueueResult::Passthrough(result) => settled.0.push(result),
+            NapcatQueueResult::Finished(result) => {
+                settled.0.push(result);
+                changed = true;
+            },
+            NapcatQueueResult::Handled => changed = true,
+        }
+    }
+
+    let expired = queue.expire_in_flight(now_ms);
+    changed |= !expired.is_empty();
+    settled.0.extend(expired);
+    settled.0.extend(queue.settle_cancelled());
+
+    if statuses.any_connected() {
+        while let Some(outbound) = queue.next_dispatch(now_ms) {
+            let dispatch_id = outbound.request_id;
+            changed = true;
+            if let Err(err) = channels.dispatch.try_send(outbound) {
+                eprintln!("NapCat outbound channel is full, deferring: {err}");
+                queue.requeue_undispatched(dispatch_id);
+                break;
+            }
+        }
+    }
+
+    if changed {
+        if let Err(err) = queue.persist() {
+            eprintln!("failed to persist NapCat outbound queue: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn private_send(request_id: u64, user_id: u64, text: &str) -> NapcatOutboundMessage {
+        NapcatOutboundMessage {
+            request_id,
+            target_id: user_id.to_string(),
+            account_id: None,
+            message: Message::Text(
+                json!({
+                    "action": "send_private_msg",
+                    "params": {
+                        "user_id": user_id,
+                        "message": [{ "type": "text", "data": { "text": text } }]
+                    }
+                })
+                .to_string()
+                .into(),
+            ),
+        }
+    }
+
+    fn unlimited_queue() -> NapcatOutboundQueue {
+        NapcatOutboundQueue {
+            config: NapcatOutboundQueueConfig {
+                global_per_minute: 0,
+                per_target_per_minute: 0,
+                min_interval_ms: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rate_limits_spread_sends_per_recipient_and_globally() {
+        let mut queue = NapcatOutboundQueue {
+            config: NapcatOutboundQueueConfig {
+                global_per_minute: 3,
+                per_target_per_minute: 2,
+                min_interval_ms: 500,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for request_id in 1..=3 {
+            assert!(queue.enqueue(&private_send(request_id, 42, "hi"), 0));
+        }
+        assert!(queue.enqueue(&private_send(4, 43, "hi"), 0));
+        assert!(!queue.enqueue(&private_send(4, 43, "hi"), 0));
+        assert_eq!(queue.entries.len(), 4);
+        assert_eq!(queue.entries[0].recipient, "private:42");
+        assert_eq!(queue.entries[0].summary, "hi");
+
+        let first = queue.next_dispatch(0).unwrap();
+        assert!(first.request_id >= NAPCAT_QUEUE_DISPATCH_ID_BASE);
+        assert!(queue.next_dispatch(100).is_none());
+        assert!(queue.next_dispatch(500).is_some());
+        // The third message to 42 waits for the per-recipient window; 43 goes ahead.
+        let third = queue.next_dispatch(1000).unwrap();
+        assert_eq!(third.target_id, "43");
+        assert!(queue.next_dispatch(2000).is_none());
+        assert_eq!(
+            queue.next_dispatch(60_000).unwrap().target_id,
+            "42"
+        );
+    }
+
+    #[test]
+    fn failed_attempts_back_off_exponentially_then_report_once() {
+        let mut queue = unlimited_queue();
+        queue.config.max_attempts = 3;
+        queue.config.retry_base_ms = 1_000;
+        queue.enqueue(&private_send(7, 42, "hi"), 0);
+
+        let attempt = queue.next_dispatch(0).unwrap();
+        let failure = |request_id| NapcatSendResult {
+            request_id,
+            target_id: "42".to_owned(),
+            error: Some("risk control".to_owned()),
+            delivery_unknown: false,
+        };
+        assert_eq!(
+            queue.apply_result(failure(attempt.request_id), 10),
+            NapcatQueueResult::Handled
+        );
+        assert_eq!(
+            queue.entries[0].state,
+            NapcatDeliveryState::Retrying
+        );
+        assert!(queue.next_dispatch(500).is_none());
+        let attempt = queue.next_dispatch(1_010).unwrap();
+        // A late answer to the first attempt no longer matches anything.
+        assert_eq!(
+            queue.apply_result(failure(attempt.request_id - 1), 1_020),
+            NapcatQueueResult::Handled
+        );
+        assert_eq!(
+            queue.entries[0].state,
+            NapcatDeliveryState::InFlight
+        );
+
+        assert_eq!(
+            queue.apply_result(failure(attempt.request_id), 1_030),
+            NapcatQueueResult::Handled
+        );
+        assert_eq!(
+            queue.entries[0].next_attempt_at_ms,
+            1_030 + 2_000
+        );
+        let attempt = queue.next_dispatch(40_000).unwrap();
+        assert_eq!(
+            queue.apply_result(failure(attempt.request_id), 40_010),
+            NapcatQueueResult::Finished(NapcatSendResult {
+                request_id: 7,
+                target_id: "42".to_owned(),
+                error: Some("risk control".to_owned()),
+                delivery_unknown: false,
+            })
+        );
+        assert_eq!(
+            queue.entries[0].state,
+            NapcatDeliveryState::Failed
+        );
+        assert_eq!(queue.entries[0].attempts, 3);
+
+        assert!(queue.resend(queue.entries[0].id, 50_000));
+        let resent = queue.next_dispatch(50_000).unwrap();
+        assert_eq!(
+            queue.apply_result(
+                NapcatSendResult {
+                    request_id: resent.request_id,
+                    target_id: "42".to_owned(),
+                    error: None,
+                    delivery_unknown: false,
+                },
+                50_010,
+            ),
+            NapcatQueueResult::Handled
+        );
+        assert_eq!(
+            queue.entries[1].state,
+            NapcatDeliveryState::Sent
+        );
+    }
+
+    #[test]
+    fn timed_out_attempts_are_reported_once_and_never_resent_automatically() {
+        let mut queue = unlimited_queue();
+        queue.enqueue(&private_send(7, 42, "hi"), 0);
+        let attempt = queue.next_dispatch(0).unwrap();
+
+        assert!(queue.expire_in_flight(14_999).is_empty());
+        let expired = queue.expire_in_flight(15_000);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].request_id, 7);
+        assert!(expired[0].error.as_deref().unwrap().contains("超时"));
+        assert_eq!(
+            queue.entries[0].state,
+            NapcatDeliveryState::Unknown
+        );
+        assert!(queue.next_dispatch(60_000).is_none());
+        assert!(queue.expire_in_flight(60_000).is_empty());
+
+        // The slow answer arrives after all: the log shows it sent, nobody hears twice.
+        assert_eq!(
+            queue.apply_result(
+                NapcatSendResult {
+                    request_id: attempt.request_id,
+                    target_id: "42".to_owned(),
+                    error: None,
+                    delivery_unknown: false,
+                },
+                61_000,
+            ),
+            NapcatQueueResult::Handled
+        );
+        assert_eq!(
+            queue.entries[0].state,
+            NapcatDeliveryState::Sent
+        );
+        assert_eq!(queue.entries[0].attempts, 1);
+    }
+
+    #[test]
+    fn disconnect_after_dispatch_marks_unknown_instead_of_requeueing() {
+        let mut queue = unlimited_queue();
+        queue.enqueue(&private_send(7, 42, "hi"), 0);
+        let attempt = queue.next_dispatch(0).unwrap();
+
+        let NapcatQueueResult::Finished(result) = queue.apply_result(
+            NapcatSendResult {
+                request_id: attempt.request_id,
+                target_id: "42".to_owned(),
+                error: Some("NapCat websocket连接在响应前断开".to_owned()),
+                delivery_unknown: true,
+            },
+            10,
+        ) else {
+            panic!("a possibly delivered attempt should be reported to its producer");
+        };
+        assert_eq!(result.request_id, 7);
+        assert!(result.delivery_unknown);
+        assert!(result.error.as_deref().unwrap().contains("未自动重发"));
+        assert_eq!(
+            queue.entries[0].state,
+            NapcatDeliveryState::Unknown
+        );
+        assert_eq!(queue.entries[0].attempts, 1);
+        assert!(queue.next_dispatch(60_000).is_none());
+    }
+
+    #[test]
+    fn response_timeout_stays_below_the_connection_request_ttl() {
+        let mut queue = unlimited_queue();
+        queue.config.response_timeout_secs = 120;
+        queue.enqueue(&private_send(7, 42, "hi"), 0);
+        queue.next_dispatch(0).unwrap();
+
+        let expired = queue.expire_in_flight(NAPCAT_QUEUE_MAX_RESPONSE_TIMEOUT_SECS * 1000);
+        assert_eq!(expired.len(), 1);
+        assert!(NAPCAT_QUEUE_MAX_RESPONSE_TIMEOUT_SECS < NAPCAT_PENDING_REQUEST_TTL.as_secs());
+    }
+
+    #[test]
+    fn allocated_request_ids_keep_climbing_across_restarts() {
+        let mut queue = unlimited_queue();
+        let first = queue.allocate_request_id();
+        assert!(queue.enqueue(&private_send(first, 42, "hi"), 0));
+        let persisted = serde_json::to_string(&queue).unwrap();
+
+        let mut restored = serde_json::from_str::<NapcatOutboundQueue>(&persisted).unwrap();
+        let second = restored.allocate_request_id();
+        assert_eq!(second, first + 1);
+        assert!(second < NAPCAT_QUEUE_DISPATCH_ID_BASE);
+        assert!(restored.enqueue(&private_send(second, 42, "hi"), 0));
+    }
+
+    #[test]
+    fn restored_queue_marks_interrupted_entries_unknown_without_reporting() {
+        let mut queue = unlimited_queue();
+        queue.enqueue(&private_send(1, 42, "hi"), 0);
+        queue.enqueue(&private_send(2, 43, "later"), 0);
+        queue.next_dispatch(0).unwrap();
+        let persisted = serde_json::to_string(&queue).unwrap();
+
+        let mut restored = serde_json::from_str::<NapcatOutboundQueue>(&persisted).unwrap();
+        assert!(restored.restore_after_restart(5_000));
+        assert_eq!(
+            restored.entries[0].state,
+            NapcatDeliveryState::Unknown
+        );
+        assert_eq!(
+            restored.entries[1].state,
+            NapcatDeliveryState::Queued
+        );
+        assert!(restored.entries.iter().all(|entry| !entry.report_result));
+        let pending = restored.next_dispatch(5_000).unwrap();
+        assert_eq!(
+            restored.apply_result(
+                NapcatSendResult {
+                    request_id: pending.request_id,
+                    target_id: "43".to_owned(),
+                    error: None,
+                    delivery_unknown: false,
+                },
+                5_005,
+            ),
+            NapcatQueueResult::Handled
+        );
+        // The restarted producer may reuse request id 1 for a new message.
+        assert!(restored.enqueue(&private_send(1, 42, "new"), 5_000));
+        let attempt = restored.next_dispatch(5_000).unwrap();
+        assert_eq!(
+            restored.apply_result(
+                NapcatSendResult {
+                    request_id: attempt.request_id,
+                    target_id: "42".to_owned(),
+                    error: None,
+                    delivery_unknown: false,
+                },
+                5_010,
+            ),
+            NapcatQueueResult::Handled
+        );
+        assert_eq!(
+            restored.apply_result(
+                NapcatSendResult {
+                    request_id: 3,
+                    target_id: "group-info".to_owned(),
+                    error: None,
+                    delivery_unknown: false,
+                },
+                5_010,
+            ),
+            NapcatQueueResult::Passthrough(NapcatSendResult {
+                request_id: 3,
+                target_id: "group-info".to_owned(),
+                error: None,
+                delivery_unknown: false,
+            })
+        );
+    }
+}
@@ -0,0 +1,481 @@
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use bevy::prelude::*;
+use bevy_persistent::prelude::*;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::json;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use super::{
+    outbound::{
+        unix_time_ms,
+        NapcatOutboundQueue,
+    },
+    outbound_segments_json,
+    parse_outbound_segments,
+    ChatTargetExportKind,
+    NapcatIOSender,
+    NapcatMessage,
+    NapcatMessageData,
+    NapcatMessageManager,
+    NapcatMessageType,
+    NapcatOutboundMessage,
+    NapcatSender,
+    TrpgGroup,
+    Visibility,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", content = "id", rename_all = "snake_case")]
+pub enum ScheduledTarget {
+    Private(u64),
+    Group(u64),
+    /// Private message to every player currently in the party.
+    Party(String),
+}
+
+impl ScheduledTarget {
+    pub fn label(&self) -> String {
+        match self {
+            Self::Private(user_id) => format!("私聊 {user_id}"),
+            Self::Group(group_id) => format!("群聊 {group_id}"),
+            Self::Party(party_id) => format!("队伍 {party_id}"),
+        }
+    }
+
+    /// Visibility a send to this target gets unless the GM picks another.
+    pub fn default_visibility(&self) -> Visibility {
+        match self {
+            Self::Private(user_id) => Visibility::Player(*user_id),
+            Self::Group(_) => Visibility::Public,
+            Self::Party(party_id) => Visibility::Party(party_id.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScheduledTrigger {
+    At {
+        at_ms: u64,
+    },
+    /// Fires once the message's TRPG group reaches this world turn.
+    WorldTurn {
+        turn: u32,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ScheduledMessage {
+    pub id: u64,
+    /// TRPG group that resolves party members and world turns.
+    #[serde(default)]
+    pub group_name: String,
+    pub target: ScheduledTarget,
+    #[serde(default)]
+    pub visibility: Visibility,
+    pub trigger: ScheduledTrigger,
+    pub text: String,
+    #[serde(default)]
+    pub created_at_ms: u64,
+}
+
+impl ScheduledMessage {
+    pub fn is_due(&self, now_ms: u64, group: Option<&TrpgGroup>) -> bool {
+        match self.trigger {
+            ScheduledTrigger::At { at_ms } => now_ms >= at_ms,
+            ScheduledTrigger::WorldTurn { turn } => {
+                group.is_some_and(|group| group.world_turn >= turn)
+            },
+        }
+    }
+
+    /// Orders due messages: timed ones by their time, then world-turn ones by their turn.
+    fn trigger_order(&self) -> (u8, u64) {
+        match self.trigger {
+            ScheduledTrigger::At { at_ms } => (0, at_ms),
+            ScheduledTrigger::WorldTurn { turn } => (1, u64::from(turn)),
+        }
+    }
+
+    /// Private or group recipients, expanding a party to its current members.
+    pub fn recipients(&self, group: Option<&TrpgGroup>) -> Vec<ScheduledRecipient> {
+        match &self.target {
+            ScheduledTarget::Private(user_id) => vec![ScheduledRecipient::Private(*user_id)],
+            ScheduledTarget::Group(group_id) => vec![ScheduledRecipient::Group(*group_id)],
+            ScheduledTarget::Party(party_id) => group
+                .and_then(|group| group.parties.get(party_id))
+                .map(|party| {
+                    party
+                        .players
+                        .iter()
+                        .filter_map(|player_id| player_id.parse::<u64>().ok())
+                        .map(ScheduledRecipient::Private)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduledRecipient {
+    Private(u64),
+    Group(u64),
+}
+
+#[derive(Resource, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct NapcatMessageScheduler {
+    #[serde(default)]
+    pub messages: Vec<ScheduledMessage>,
+    #[serde(default)]
+    next_id: u64,
+}
+
+impl NapcatMessageScheduler {
+    /// Adds `message`, or replaces the entry with the same id when editing.
+    pub fn upsert(&mut self, mut message: ScheduledMessage) -> u64 {
+        if let Some(existing) = self
+            .messages
+            .iter_mut()
+            .find(|existing| message.id != 0 && existing.id == message.id)
+        {
+            *existing = message;
+            return existing.id;
+        }
+        self.next_id += 1;
+        message.id = self.next_id;
+        self.messages.push(message);
+        self.next_id
+    }
+
+    pub fn cancel(&mut self, id: u64) -> bool {
+        let before = self.messages.len();
+        self.messages.retain(|message| message.id != id);
+        self.messages.len() != before
+    }
+
+    /// Removes and returns every due message, oldest trigger first and by id within a trigger.
+    pub fn take_due(
+        &mut self,
+        now_ms: u64,
+        manager: &NapcatMessageManager,
+    ) -> Vec<ScheduledMessage> {
+        let (mut due, pending) = std::mem::take(&mut self.messages)
+            .into_iter()
+            .partition::<Vec<_>, _>(|message| {
+                message.is_due(
+                    now_ms,
+                    manager.trpg_groups.get(&message.group_name),
+                )
+            });
+        self.messages = pending;
+        due.sort_by_key(|message| (message.trigger_order(), message.id));
+        due
+    }
+}
+
+pub(super) fn scheduled_message_system(
+    mut scheduler: ResMut<Persistent<NapcatMessageScheduler>>,
+    mut manager: ResMut<Persistent<NapcatMessageManager>>,
+    sender: Option<Res<NapcatIOSender>>,
+    mut queue: ResMut<Persistent<NapcatOutboundQueue>>,
+) {
+    let Some(sender) = sender else {
+        return;
+    };
+    if scheduler.messages.is_empty() {
+        return;
+    }
+    let due = scheduler.take_due(unix_time_ms(), &manager);
+    if due.is_empty() {
+        return;
+    }
+    let mut manager_changed = false;
+    for message in due {
+        let recipients = message.recipients(manager.trpg_groups.get(&message.group_name));
+        if recipients.is_empty() {
+            eprintln!(
+                "scheduled message {} has no recipients for {}",
+                message.id,
+                message.target.label()
+            );
+        }
+        let mut undelivered = Vec::new();
+        for recipient in recipients {
+            match sender.0.try_send(scheduled_outbound(
+                queue.allocate_request_id(),
+                recipient,
+                &message.text,
+            )) {
+                Ok(()) => {
+                    append_local_scheduled_message(&mut manager, recipient, &message);
+                    manager_changed = true;
+                },
+                Err(err) => {
+                    eprintln!(
+                        "failed to queue scheduled message {}: {err}",
+                        message.id
+                    );
+                    undelivered.push(recipient);
+                },
+            }
+        }
+        // Whatever could not be queued stays scheduled, narrowed to the recipients that missed it.
+        for recipient in undelivered {
+            let target = match recipient {
+                ScheduledRecipient::Private(user_id) => ScheduledTarget::Private(user_id),
+                ScheduledRecipient::Group(group_id) => ScheduledTarget::Group(group_id),
+            };
+            scheduler.upsert(ScheduledMessage {
+                id: 0,
+                target,
+                ..message.clone()
+            });
+        }
+    }
+
+    if let Err(err) = scheduler.persist() {
+        eprintln!("failed to persist scheduled messages: {err}");
+    }
+    if manager_changed {
+        if let Err(err) = manager.persist() {
+            eprintln!("failed to persist scheduled message records: {err}");
+        }
+    }
+}
+
+fn scheduled_outbound(
+    request_id: u64,
+    recipient: ScheduledRecipient,
+    text: &str,
+) -> NapcatOutboundMessage {
+    let segments = outbound_segments_json(&parse_outbound_segments(text));
+    let (payload, target_id) = match recipient {
+        ScheduledRecipient::Private(user_id) => (
+            json!({
+                "action": "send_private_msg",
+                "params": { "user_id": user_id, "message": segments }
+            }),
+            user_id.to_string(),
+        ),
+        ScheduledRecipient::Group(group_id) => (
+            json!({
+                "action": "send_group_msg",
+                "params": { "group_id": group_id, "message": segments }
+            }),
+            group_id.to_string(),
+        ),
+    };
+    NapcatOutboundMessage {
+        request_id,
+        target_id,
+        account_id: None,
+        message: Message::Text(payload.to_string().into()),
+    }
+}
+
+/// Records the send in the recipient's chat so the campaign log shows it with the chosen
+/// visibility.
+fn append_local_scheduled_message(
+    manager: &mut NapcatMessageManager,
+    recipient: ScheduledRecipient,
+    scheduled: &ScheduledMessage,
+) {
+    let (target_id, message_type, group_id, peer_id, kind) = match recipient {
+        ScheduledRecipient::Private(user_id) => (
+            user_id.to_string(),
+            NapcatMessageType::Private,
+            None,
+            Some(user_id),
+            ChatTargetExportKind::Private,
+        ),
+        ScheduledRecipient::Group(group_id) => (
+            group_id.to_string(),
+            NapcatMessageType::Group,
+            Some(group_id),
+            None,
+            ChatTargetExportKind::Group,
+        ),
+    };
+    let self_id = manager.napcat_self_id().unwrap_or_default();
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let mut message = NapcatMessage {
+        data: NapcatMessageData {
+            time,
+            message_id: String::new(),
+            message_type,
+            message: parse_outbound_segments(&scheduled.text),
+            self_id,
+            user_id: self_id,
+            group_id,
+            group_name: None,
+            target_id: peer_id,
+            sender: NapcatSender {
+                user_id: self_id,
+                nickname: "GM".to_owned(),
+            },
+            campaign_id: String::new(),
+            character_id: None,
+            party_id: None,
+            visibility: Visibility::Public,
+            access_scope_resolved: false,
+            account_id: String::new(),
+        },
+    };
+    manager.annotate_message_access(&target_id, &mut message);
+    message.data.visibility = scheduled.visibility.clone();
+    if let Visibility::Party(party_id) = &scheduled.visibility {
+        message.data.party_id = Some(party_id.clone());
+    }
+    message.data.access_scope_resolved = true;
+    manager
+        .messages
+        .entry(target_id.clone())
+        .or_default()
+        .push(message);
+    manager.chat_target_kinds.entry(target_id).or_insert(kind);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napcat::tests::empty_manager;
+
+    fn scheduled(target: ScheduledTarget, trigger: ScheduledTrigger) -> ScheduledMessage {
+        ScheduledMessage {
+            id: 0,
+            group_name: "table".to_owned(),
+            visibility: target.default_visibility(),
+            target,
+            trigger,
+            text: "风暴来了".to_owned(),
+            created_at_ms: 0,
+        }
+    }
+
+    #[test]
+    fn due_messages_follow_time_and_world_turn_triggers() {
+        let mut manager = empty_manager();
+        let mut group = TrpgGroup {
+            players: vec!["2".to_owned(), "3".to_owned()],
+            ..Default::default()
+        };
+        group.ensure_party("red");
+        group.set_player_party("2", Some("red"));
+        manager.trpg_groups.insert("table".to_owned(), group);
+
+        let mut scheduler = NapcatMessageScheduler::default();
+        let timed = scheduler.upsert(scheduled(
+            ScheduledTarget::Group(99),
+            ScheduledTrigger::At { at_ms: 10_000 },
+        ));
+        let party = scheduler.upsert(scheduled(
+            ScheduledTarget::Party("red".to_owned()),
+            ScheduledTrigger::WorldTurn { turn: 2 },
+        ));
+        assert!(scheduler.take_due(9_999, &manager).is_empty());
+
+        let mut edited = scheduler.messages[0].clone();
+        edited.trigger = ScheduledTrigger::At { at_ms: 5_000 };
+        assert_eq!(scheduler.upsert(edited), timed);
+        assert_eq!(scheduler.messages.len(), 2);
+        let due = scheduler.take_due(5_000, &manager);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id, timed);
+
+        manager.trpg_groups.get_mut("table").unwrap().world_turn = 2;
+        let due = scheduler.take_due(5_000, &manager);
+        assert_eq!(due[0].id, party);
+        assert_eq!(
+            due[0].recipients(manager.trpg_groups.get("table")),
+            vec![ScheduledRecipient::Private(2)]
+        );
+        assert!(scheduler.messages.is_empty());
+        assert!(!scheduler.cancel(party));
+    }
+
+    #[test]
+    fn due_messages_come_out_in_trigger_order() {
+        let mut manager = empty_manager();
+        manager.trpg_groups.insert("table".to_owned(), TrpgGroup {
+            world_turn: 5,
+            ..Default::default()
+        });
+        let mut scheduler = NapcatMessageScheduler::default();
+        let mut add = |trigger: ScheduledTrigger, created_at_ms: u64| {
+            scheduler.upsert(ScheduledMessage {
+                created_at_ms,
+                ..scheduled(ScheduledTarget::Group(99), trigger)
+            })
+        };
+        let later_turn = add(
+            ScheduledTrigger::WorldTurn { turn: 4 },
+            1,
+        );
+        let earlier_turn = add(
+            ScheduledTrigger::WorldTurn { turn: 3 },
+            2,
+        );
+        let later_time = add(ScheduledTrigger::At { at_ms: 2_000 }, 3);
+        let earlier_time = add(ScheduledTrigger::At { at_ms: 1_000 }, 9);
+        let same_time = add(ScheduledTrigger::At { at_ms: 1_000 }, 4);
+
+        let due = scheduler
+            .take_due(5_000, &manager)
+            .into_iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>();
+        assert_eq!(due, vec![
+            earlier_time,
+            same_time,
+            later_time,
+            earlier_turn,
+            later_turn
+        ]);
+    }
+
+    #[test]
+    fn scheduled_party_send_is_recorded_with_party_visibility() {
+        let mut manager = empty_manager();
+        let message = scheduled(
+            ScheduledTarget::Party("red".to_owned()),
+            ScheduledTrigger::WorldTurn { turn: 1 },
+        );
+        let outbound = scheduled_outbound(
+            7,
+            ScheduledRecipient::Private(2),
+            &message.text,
+        );
+        assert!(outbound.message.to_string().contains("send_private_msg"));
+        assert_eq!(outbound.target_id, "2");
+
+        let mut group_message = message.clone();
+        group_message.target = ScheduledTarget::Group(99);
+        append_local_scheduled_message(
+            &mut manager,
+            ScheduledRecipient::Group(99),
+            &group_message,
+        );
+        let recorded = &manager.messages["99"][0];
+        assert_eq!(
+            recorded.data.visibility,
+            Visibility::Party("red".to_owned())
+        );
+        assert_eq!(
+            manager
+                .campaign_message_for_target("99", recorded)
+                .visibility,
+            Visibility::Party("red".to_owned())
+        );
+    }
+}
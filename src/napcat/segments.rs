@@ -0,0 +1,461 @@
+use serde::{
+    Deserialize,
+    Deserializer,
+};
+use serde_json::{
+    json,
+    Value,
+};
+
+use super::{
+    AtData,
+    FaceData,
+    FileData,
+    ForwardData,
+    ForwardNode,
+    ImageData,
+    JsonData,
+    NapcatMessage,
+    NapcatMessageChain,
+    NapcatMessageChainType,
+    RecordData,
+    ReplyData,
+    TextData,
+};
+
+/// OneBot ids arrive as strings from some implementations and numbers from others.
+pub(super) fn deserialize_id_string<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(
+        match Value::deserialize(deserializer)? {
+            Value::String(value) => value,
+            Value::Number(value) => value.to_string(),
+            Value::Bool(value) => value.to_string(),
+            Value::Null | Value::Array(_) | Value::Object(_) => String::new(),
+        },
+    )
+}
+
+/// Card payloads are usually JSON text, but some implementations inline the object.
+pub(super) fn deserialize_json_payload<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(
+        match Value::deserialize(deserializer)? {
+            Value::String(value) => value,
+            Value::Null => String::new(),
+            value => value.to_string(),
+        },
+    )
+}
+
+/// Accepts both the persisted `{nickname, text}` form and NapCat's inlined message nodes.
+pub(super) fn deserialize_forward_nodes<'de, D>(
+    deserializer: D,
+) -> Result<Vec<ForwardNode>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Value::Array(nodes) = Value::deserialize(deserializer)? else {
+        return Ok(Vec::new());
+    };
+    Ok(nodes.iter().filter_map(forward_node_from_value).collect())
+}
+
+fn forward_node_from_value(node: &Value) -> Option<ForwardNode> {
+    let node = node
+        .get("data")
+        .filter(|data| data.is_object())
+        .unwrap_or(node);
+    let nickname = node
+        .get("nickname")
+        .or_else(|| node.get("sender").and_then(|sender| sender.get("nickname")))
+        .or_else(|| node.get("name"))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_owned();
+    let text = match node.get("text").and_then(Value::as_str) {
+        Some(text) => text.to_owned(),
+        None => {
+            let segments = node.get("message").or_else(|| node.get("content"))?;
+            match segments {
+                Value::String(text) => text.clone(),
+                segments => serde_json::from_value::<Vec<NapcatMessageChain>>(segments.clone())
+                    .map(|chains| {
+                        chains
+                            .iter()
+                            .filter_map(message_segment_preview)
+                            .collect::<Vec<_>>()
+                            .join("")
+                    })
+                    .unwrap_or_default(),
+            }
+        },
+    };
+    Some(ForwardNode { nickname, text })
+}
+
+/// Plain-text stand-in for a segment, used for previews, quotes and forwarded nodes.
+pub fn message_segment_preview(chain: &NapcatMessageChain) -> Option<String> {
+    match &chain.variant {
+        NapcatMessageChainType::Text { data } => Some(data.text.clone()),
+        NapcatMessageChainType::Image { .. } => Some("[图片]".to_owned()),
+        NapcatMessageChainType::At { data } => Some(if data.qq == "all" {
+            "@全体成员".to_owned()
+        } else if data.name.is_empty() {
+            format!("@{}", data.qq)
+        } else {
+            format!("@{}", data.name)
+        }),
+        NapcatMessageChainType::Reply { .. } => None,
+        NapcatMessageChainType::Face { .. } => Some("[表情]".to_owned()),
+        NapcatMessageChainType::Record { .. } => Some("[语音]".to_owned()),
+        NapcatMessageChainType::File { data } => Some(format!(
+            "[文件] {}",
+            file_display_name(data)
+        )),
+        NapcatMessageChainType::Forward { .. } => Some("[合并转发]".to_owned()),
+        NapcatMessageChainType::Json { data } => Some(match json_card_prompt(data) {
+            Some(prompt) => format!("[卡片] {prompt}"),
+            None => "[卡片]".to_owned(),
+        }),
+        NapcatMessageChainType::Source(_) | NapcatMessageChainType::Unsupported => None,
+    }
+}
+
+pub fn file_display_name(data: &FileData) -> &str {
+    [&data.name, &data.file, &data.file_id]
+        .into_iter()
+        .map(|value| value.trim())
+        .find(|value| !value.is_empty())
+        .unwrap_or("未命名文件")
+}
+
+/// QQ ark cards carry a human readable `prompt`; fall back to nothing for other payloads.
+pub fn json_card_prompt(data: &JsonData) -> Option<String> {
+    let payload = serde_json::from_str::<Value>(&data.data).ok()?;
+    payload
+        .get("prompt")
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|prompt| !prompt.is_empty())
+        .map(str::to_owned)
+}
+
+/// Splits composer text into segments. `[CQ:type,key=value]` codes become typed segments;
+/// everything else, including unknown codes, stays text.
+pub fn parse_outbound_segments(text: &str) -> Vec<NapcatMessageChain> {
+    let mut segments = Vec::new();
+    let mut pending_text = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("[CQ:") {
+        let Some(end) = rest[start..].find(']').map(|end| start + end) else {
+            break;
+        };
+        match cq_code_segment(&rest[start + 4..end]) {
+            Some(segment) => {
+                pending_text.push_str(&unescape_cq_text(&rest[..start]));
+                push_text_segment(&mut segments, &mut pending_text);
+                segments.push(segment);
+            },
+            None => pending_text.push_str(&unescape_cq_text(&rest[..=end])),
+        }
+        rest = &rest[end + 1..];
+    }
+    pending_text.push_str(&unescape_cq_text(rest));
+    push_text_segment(&mut segments, &mut pending_text);
+    segments
+}
+
+fn push_text_segment(segments: &mut Vec<NapcatMessageChain>, text: &mut String) {
+    if text.is_empty() {
+        return;
+    }
+    segments.push(NapcatMessageChain {
+        variant: NapcatMessageChainType::Text {
+            data: TextData {
+                text: std::mem::take(text),
+            },
+        },
+    });
+}
+
+fn cq_code_segment(code: &str) -> Option<NapcatMessageChain> {
+    let mut parts = code.split(',');
+    let kind = parts.next()?.trim();
+    let params = parts
+        .filter_map(|part| part.split_once('='))
+        .map(|(key, value)| (key.trim(), unescape_cq_param(value)))
+        .collect::<Vec<_>>();
+    let param = |key: &str| {
+        params
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    };
+    let required = |key: &str| Some(param(key)).filter(|value| !value.is_empty());
+
+    let variant = match kind {
+        "at" => NapcatMessageChainType::At {
+            data: AtData {
+                qq: required("qq")?,
+                name: param("name"),
+            },
+        },
+        "reply" => NapcatMessageChainType::Reply {
+            data: ReplyData {
+                id: required("id")?,
+            },
+        },
+        "face" => NapcatMessageChainType::Face {
+            data: FaceData {
+                id: required("id")?,
+            },
+        },
+        "image" => NapcatMessageChainType::Image {
+            data: ImageData {
+                sub_type: 0,
+                file: required("file")?,
+                url: param("url"),
+                file_id: String::new(),
+                file_size: String::new(),
+                local_path: String::new(),
+            },
+        },
+        "record" => NapcatMessageChainType::Record {
+            data: RecordData {
+                file: required("file")?,
+                ..RecordData::default()
+            },
+        },
+        "file" => NapcatMessageChainType::File {
+            data: FileData {
+                file: required("file")?,
+                name: param("name"),
+                ..FileData::default()
+            },
+        },
+        "forward" => NapcatMessageChainType::Forward {
+            data: ForwardData {
+                id: required("id")?,
+                content: Vec::new(),
+            },
+        },
+        "json" => NapcatMessageChainType::Json {
+            data: JsonData {
+                data: required("data")?,
+            },
+        },
+        _ => return None,
+    };
+    Some(NapcatMessageChain { variant })
+}
+
+fn unescape_cq_text(text: &str) -> String {
+    text.replace("&#91;", "[")
+        .replace("&#93;", "]")
+        .replace("&amp;", "&")
+}
+
+fn unescape_cq_param(text: &str) -> String { unescape_cq_text(&text.replace("&#44;", ",")) }
+
+/// Escapes a value for use inside a `[CQ:...]` parameter.
+pub fn escape_cq_param(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('[', "&#91;")
+        .replace(']', "&#93;")
+        .replace(',', "&#44;")
+}
+
+/// OneBot `message` array for an outbound action. Receive-only fields are left out.
+pub fn outbound_segments_json(segments: &[NapcatMessageChain]) -> Vec<Value> {
+    segments.iter().filter_map(outbound_segment_json).collect()
+}
+
+fn outbound_segment_json(chain: &NapcatMessageChain) -> Option<Value> {
+    Some(match &chain.variant {
+        NapcatMessageChainType::Text { data } => {
+            json!({ "type": "text", "data": { "text": data.text } })
+        },
+        NapcatMessageChainType::Image { data } => {
+            let file = media_source(&data.url, &data.local_path, &data.file)?;
+            json!({ "type": "image", "data": { "file": file } })
+        },
+        NapcatMessageChainType::At { data } => json!({ "type": "at", "data": { "qq": data.qq } }),
+        NapcatMessageChainType::Reply { data } => {
+            json!({ "type": "reply", "data": { "id": data.id } })
+        },
+        NapcatMessageChainType::Face { data } => {
+            json!({ "type": "face", "data": { "id": data.id } })
+        },
+        NapcatMessageChainType::Record { data } => {
+            let file = media_source(&data.url, &data.path, &data.file)?;
+            json!({ "type": "record", "data": { "file": file } })
+        },
+        NapcatMessageChainType::File { data } => {
+            let file = media_source(&data.url, &data.path, &data.file)?;
+            let mut segment = json!({ "type": "file", "data": { "file": file } });
+            if !data.name.trim().is_empty() {
+                segment["data"]["name"] = Value::String(data.name.trim().to_owned());
+            }
+            segment
+        },
+        NapcatMessageChainType::Forward { data } => {
+            json!({ "type": "forward", "data": { "id": data.id } })
+        },
+        NapcatMessageChainType::Json { data } => {
+            json!({ "type": "json", "data": { "data": data.data } })
+        },
+        NapcatMessageChainType::Source(_) | NapcatMessageChainType::Unsupported => return None,
+    })
+}
+
+/// Received media carries a bare file name, so prefer the URL or local cache when resending;
+/// composed CQ codes only set `file`.
+fn media_source(url: &str, path: &str, file: &str) -> Option<String> {
+    let (url, path, file) = (url.trim(), path.trim(), file.trim());
+    if url.contains("://") {
+        Some(url.to_owned())
+    } else if !path.is_empty() {
+        Some(format!(
+            "file://{}",
+            path.replace('\\', "/")
+        ))
+    } else {
+        (!file.is_empty()).then(|| file.to_owned())
+    }
+}
+
+/// Builds a `send_*_forward_msg` node list that keeps each sender's name and media.
+/// Reply segments are dropped because quoted ids do not resolve inside a forward.
+pub fn forward_export_nodes(messages: &[&NapcatMessage]) -> Vec<Value> {
+    messages
+        .iter()
+        .filter_map(|message| {
+            let content = message
+                .data
+                .message
+                .iter()
+                .filter(|chain| {
+                    !matches!(
+                        chain.variant,
+                        NapcatMessageChainType::Reply { .. }
+                    )
+                })
+                .filter_map(outbound_segment_json)
+                .collect::<Vec<_>>();
+            (!content.is_empty()).then(|| {
+                json!({
+                    "type": "node",
+                    "data": {
+                        "user_id": message.data.sender.user_id.to_string(),
+                        "nickname": message.data.sender.nickname,
+                        "content": content,
+                    }
+                })
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn napcat_segments_deserialize_into_typed_variants() {
+        let message = serde_json::from_value::<NapcatMessage>(json!({
+            "time": 1,
+            "message_id": 1234,
+            "message_type": "group",
+            "self_id": 1,
+            "user_id": 2,
+            "group_id": 3,
+            "target_id": null,
+            "sender": { "user_id": 2, "nickname": "p" },
+            "message": [
+                { "type": "reply", "data": { "id": "99" } },
+                { "type": "at", "data": { "qq": 1, "name": "GM" } },
+                { "type": "text", "data": { "text": " 看这个" } },
+                { "type": "face", "data": { "id": 14 } },
+                { "type": "record", "data": { "file": "a.amr", "url": "http://x/a.amr", "file_size": 2048 } },
+                { "type": "file", "data": { "file": "map.pdf", "file_id": "f1", "file_size": "10" } },
+                { "type": "forward", "data": { "id": "fw", "content": [
+                    { "sender": { "nickname": "甲" }, "message": [{ "type": "text", "data": { "text": "前情" } }] }
+                ] } },
+                { "type": "json", "data": { "data": "{\"prompt\":\"[分享]地图\"}" } },
+                { "type": "poke", "data": {} }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(message.data.message_id, "1234");
+        let previews = message
+            .data
+            .message
+            .iter()
+            .filter_map(message_segment_preview)
+            .collect::<Vec<_>>();
+        assert_eq!(previews, vec![
+            "@GM",
+            " 看这个",
+            "[表情]",
+            "[语音]",
+            "[文件] map.pdf",
+            "[合并转发]",
+            "[卡片] [分享]地图",
+        ]);
+        let NapcatMessageChainType::Forward { data } = &message.data.message[6].variant else {
+            panic!("expected forward segment");
+        };
+        assert_eq!(data.content, vec![ForwardNode {
+            nickname: "甲".to_owned(),
+            text: "前情".to_owned(),
+        }]);
+        assert!(matches!(
+            message.data.message[8].variant,
+            NapcatMessageChainType::Unsupported
+        ));
+
+        let persisted = serde_json::to_string(&message).unwrap();
+        let restored = serde_json::from_str::<NapcatMessage>(&persisted).unwrap();
+        let NapcatMessageChainType::Forward { data } = &restored.data.message[6].variant else {
+            panic!("expected forward segment");
+        };
+        assert_eq!(data.content[0].text, "前情");
+        let NapcatMessageChainType::Record { data } = &restored.data.message[4].variant else {
+            panic!("expected record segment");
+        };
+        assert_eq!(data.file_size, "2048");
+    }
+
+    #[test]
+    fn cq_codes_compose_typed_outbound_segments() {
+        let segments = parse_outbound_segments(
+            "[CQ:reply,id=99][CQ:at,qq=10002] 请看&#91;地图&#93;[CQ:face,id=14][CQ:file,file=file:///tmp/a&#44;b.pdf,name=地图.pdf][CQ:unknown,x=1]",
+        );
+
+        assert_eq!(outbound_segments_json(&segments), vec![
+            json!({ "type": "reply", "data": { "id": "99" } }),
+            json!({ "type": "at", "data": { "qq": "10002" } }),
+            json!({ "type": "text", "data": { "text": " 请看[地图]" } }),
+            json!({ "type": "face", "data": { "id": "14" } }),
+            json!({ "type": "file", "data": { "file": "file:///tmp/a,b.pdf", "name": "地图.pdf" } }),
+            json!({ "type": "text", "data": { "text": "[CQ:unknown,x=1]" } }),
+        ]);
+        assert_eq!(
+            outbound_segments_json(&parse_outbound_segments(
+                "普通文本 [1, 2]"
+            )),
+            vec![json!({ "type": "text", "data": { "text": "普通文本 [1, 2]" } })]
+        );
+        assert_eq!(
+            escape_cq_param("a,b[c]&"),
+            "a&#44;b&#91;c&#93;&amp;"
+        );
+    }
+}
@@ -0,0 +1,766 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    inventory::{
+        add_item_count_to_inventory,
+        inventory_capacity_for,
+        inventory_quality_label,
+        take_tradeable_items,
+        tradeable_item_count,
+    },
+    private_command_body,
+    InventoryItem,
+    InventoryQuality,
+    NapcatMessageManager,
+    PlayerCharacter,
+};
+
+/// Transactions kept in a shop's audit log; the log is saved with the manager, so older entries
+/// are dropped instead of growing the save file forever.
+const SHOP_AUDIT_LOG_LIMIT: usize = 500;
+
+/// A TRPG group's shop. Listings carry their own copy of the item so later edits to the item pool
+/// do not change what the shop sells.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrpgShop {
+    #[serde(default = "default_shop_name")]
+    pub name: String,
+    #[serde(default)]
+    pub listings: Vec<ShopListing>,
+    #[serde(default)]
+    pub pricing: ShopPricing,
+    #[serde(default = "default_shop_sell_rate_percent")]
+    pub sell_rate_percent: u32,
+    #[serde(default)]
+    pub audit_log: Vec<ShopTransaction>,
+}
+
+impl Default for TrpgShop {
+    fn default() -> Self {
+        Self {
+            name: default_shop_name(),
+            listings: Vec::new(),
+            pricing: ShopPricing::default(),
+            sell_rate_percent: default_shop_sell_rate_percent(),
+            audit_log: Vec::new(),
+        }
+    }
+}
+
+fn default_shop_name() -> String { "商店".to_owned() }
+fn default_shop_sell_rate_percent() -> u32 { 50 }
+
+/// Prices used when a listing has no fixed price: the quality base price, raised by
+/// `item_level_percent` per item level.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ShopPricing {
+    #[serde(default = "default_poor_price")]
+    pub poor: u32,
+    #[serde(default = "default_common_price")]
+    pub common: u32,
+    #[serde(default = "default_uncommon_price")]
+    pub uncommon: u32,
+    #[serde(default = "default_rare_price")]
+    pub rare: u32,
+    #[serde(default = "default_epic_price")]
+    pub epic: u32,
+    #[serde(default = "default_legendary_price")]
+    pub legendary: u32,
+    #[serde(default = "default_item_level_percent")]
+    pub item_level_percent: u32,
+}
+
+impl Default for ShopPricing {
+    fn default() -> Self {
+        Self {
+            poor: default_poor_price(),
+            common: default_common_price(),
+            uncommon: default_uncommon_price(),
+            rare: default_rare_price(),
+            epic: default_epic_price(),
+            legendary: default_legendary_price(),
+            item_level_percent: default_item_level_percent(),
+        }
+    }
+}
+
+fn default_poor_price() -> u32 { 2 }
+fn default_common_price() -> u32 { 10 }
+fn default_uncommon_price() -> u32 { 40 }
+fn default_rare_price() -> u32 { 150 }
+fn default_epic_price() -> u32 { 600 }
+fn default_legendary_price() -> u32 { 2500 }
+fn default_item_level_percent() -> u32 { 10 }
+
+impl ShopPricing {
+    pub fn quality_price(&self, quality: InventoryQuality) -> u32 {
+        match quality {
+            InventoryQuality::Poor => self.poor,
+            InventoryQuality::Common => self.common,
+            InventoryQuality::Uncommon => self.uncommon,
+            InventoryQuality::Rare => self.rare,
+            InventoryQuality::Epic => self.epic,
+            InventoryQuality::Legendary => self.legendary,
+        }
+    }
+
+    pub fn quality_price_mut(&mut self, quality: InventoryQuality) -> &mut u32 {
+        match quality {
+            InventoryQuality::Poor => &mut self.poor,
+            InventoryQuality::Common => &mut self.common,
+            InventoryQuality::Uncommon => &mut self.uncommon,
+            InventoryQuality::Rare => &mut self.rare,
+            InventoryQuality::Epic => &mut self.epic,
+            InventoryQuality::Legendary => &mut self.legendary,
+        }
+    }
+
+    pub fn item_price(&self, item: &InventoryItem) -> u32 {
+        let percent = 100_u64 + u64::from(item.item_level) * u64::from(self.item_level_percent);
+        let price = u64::from(self.quality_price(item.quality)) * percent / 100;
+        price.min(u64::from(u32::MAX)) as u32
+    }
+}
+
+/// One item for sale. `max_stock == 0` means unlimited stock; otherwise every
+/// `restock_every_turns` world turns the stock grows by `restock_amount` (0 refills to max).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ShopListing {
+    #[serde(default)]
+    pub item: InventoryItem,
+    #[serde(default)]
+    pub price: u32,
+    #[serde(default)]
+    pub stock: u32,
+    #[serde(default)]
+    pub max_stock: u32,
+    #[serde(default)]
+    pub restock_every_turns: u32,
+    #[serde(default)]
+    pub restock_amount: u32,
+    #[serde(default)]
+    pub last_restock_turn: u32,
+}
+
+impl ShopListing {
+    pub fn from_item(item: &InventoryItem, world_turn: u32) -> Self {
+        Self {
+            item: InventoryItem {
+                stack: 1,
+                ..item.clone()
+            },
+            last_restock_turn: world_turn,
+            ..Default::default()
+        }
+    }
+
+    pub fn unlimited(&self) -> bool { self.max_stock == 0 }
+
+    pub fn stock_label(&self) -> String {
+        if self.unlimited() {
+            "不限".to_owned()
+        } else {
+            format!("{}/{}", self.stock, self.max_stock)
+        }
+    }
+
+    fn restock(&mut self, world_turn: u32) -> bool {
+        if world_turn < self.last_restock_turn {
+            self.last_restock_turn = world_turn;
+            return true;
+        }
+        if self.unlimited() || self.restock_every_turns == 0 {
+            return false;
+        }
+        let periods = (world_turn - self.last_restock_turn) / self.restock_every_turns;
+        if periods == 0 {
+            return false;
+        }
+        self.last_restock_turn += periods * self.restock_every_turns;
+        self.stock = if self.restock_amount == 0 {
+            self.max_stock.max(self.stock)
+        } else {
+            self.stock
+                .saturating_add(self.restock_amount.saturating_mul(periods))
+                .min(self.max_stock.max(self.stock))
+        };
+        true
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ShopTransactionKind {
+    #[default]
+    Buy,
+    Sell,
+}
+
+impl ShopTransactionKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Buy => "购买",
+            Self::Sell => "出售",
+        }
+    }
+}
+
+/// Audit record of one purchase or sale; `time` is the triggering message's unix time in seconds.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct ShopTransaction {
+    #[serde(default)]
+    pub time: u64,
+    #[serde(default)]
+    pub world_turn: u32,
+    #[serde(default)]
+    pub player_id: String,
+    #[serde(default)]
+    pub character_name: String,
+    #[serde(default)]
+    pub kind: ShopTransactionKind,
+    #[serde(default)]
+    pub item_name: String,
+    #[serde(default)]
+    pub quantity: u32,
+    #[serde(default)]
+    pub total: u32,
+    #[serde(default)]
+    pub gold_after: u32,
+}
+
+impl TrpgShop {
+    pub fn buy_price(&self, listing: &ShopListing) -> u32 {
+        if listing.price > 0 {
+            listing.price
+        } else {
+            self.pricing.item_price(&listing.item)
+        }
+    }
+
+    /// What the shop pays for one unit: the listed price of the same item when it sells one,
+    /// otherwise the quality price, scaled by `sell_rate_percent`.
+    pub fn sell_price(&self, item: &InventoryItem) -> u32 {
+        let base = self
+            .listings
+            .iter()
+            .find(|listing| listing.item.name.trim() == item.name.trim())
+            .map(|listing| self.buy_price(listing))
+            .unwrap_or_else(|| self.pricing.item_price(item));
+        (u64::from(base) * u64::from(self.sell_rate_percent) / 100).min(u64::from(u32::MAX)) as u32
+    }
+
+    /// Applies every listing's turn-based restock rule up to `world_turn`.
+    pub fn restock(&mut self, world_turn: u32) -> bool {
+        let mut changed = false;
+        for listing in &mut self.listings {
+            changed |= listing.restock(world_turn);
+        }
+        changed
+    }
+
+    /// Refills every limited listing to its maximum stock.
+    pub fn refill(&mut self) {
+        for listing in &mut self.listings {
+            listing.stock = listing.max_stock.max(listing.stock);
+        }
+    }
+
+    /// Finds a listing by exact item name, or by the 1-based number shown in `.商店`.
+    pub fn find_listing(&self, query: &str) -> Option<usize> {
+        let query = query.trim();
+        self.listings
+            .iter()
+            .position(|listing| listing.item.name.trim() == query)
+            .or_else(|| {
+                query
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| number.checked_sub(1))
+                    .filter(|index| *index < self.listings.len())
+            })
+    }
+
+    pub fn buy(
+        &mut self,
+        character: &mut PlayerCharacter,
+        player_id: &str,
+        query: &str,
+        quantity: u32,
+        world_turn: u32,
+        time: u64,
+    ) -> Result<ShopTransaction, String> {
+        if quantity == 0 {
+            return Err("购买数量至少为1。".to_owned());
+        }
+        let Some(index) = self.find_listing(query) else {
+            return Err(format!(
+                "{}没有出售【{}】。输入【.商店】查看货架。",
+                self.name,
+                query.trim()
+            ));
+        };
+        let price = self.buy_price(&self.listings[index]);
+        let listing = &self.listings[index];
+        let item_name = listing.item.name.trim().to_owned();
+        if !listing.unlimited() && listing.stock < quantity {
+            return Err(format!(
+                "【{item_name}】库存不足：剩余{}。",
+                listing.stock
+            ));
+        }
+        let Some(total) = price.checked_mul(quantity) else {
+            return Err("购买数量过大。".to_owned());
+        };
+        if character.inventory.gold < total {
+            return Err(format!(
+                "金币不足：需要{total}，当前{}。",
+                character.inventory.gold
+            ));
+        }
+        let capacity = inventory_capacity_for(&character.inventory, &listing.item);
+        if capacity < quantity {
+            return Err(format!(
+                "背包空间不足：最多还能放下{capacity}个【{item_name}】。"
+            ));
+        }
+
+        character.inventory.gold -= total;
+        add_item_count_to_inventory(
+            &mut character.inventory,
+            &listing.item,
+            quantity,
+        );
+        let listing = &mut self.listings[index];
+        if !listing.unlimited() {
+            listing.stock -= quantity;
+        }
+        Ok(self.record(ShopTransaction {
+            time,
+            world_turn,
+            player_id: player_id.to_owned(),
+            character_name: character.name.clone(),
+            kind: ShopTransactionKind::Buy,
+            item_name,
+            quantity,
+            total,
+            gold_after: character.inventory.gold,
+        }))
+    }
+
+    pub fn sell(
+        &mut self,
+        character: &mut PlayerCharacter,
+        player_id: &str,
+        name: &str,
+        quantity: u32,
+        world_turn: u32,
+        time: u64,
+    ) -> Result<ShopTransaction, String> {
+        if quantity == 0 {
+            return Err("出售数量至少为1。".to_owned());
+        }
+        let name = name.trim();
+        let owned = tradeable_item_count(&character.inventory, name);
+        if owned < quantity {
+            let soulbound = character
+                .inventory
+                .items
+                .iter()
+                .any(|item| item.soulbound && item.name.trim() == name);
+            return Err(if owned == 0 && soulbound {
+                format!("【{name}】已灵魂绑定，无法出售。")
+            } else {
+                format!("背包里可出售的【{name}】只有{owned}个。")
+            });
+        }
+        let Some(taken) = take_tradeable_items(character, name, quantity) else {
+            return Err(format!(
+                "背包里可出售的【{name}】只有{owned}个。"
+            ));
+        };
+        let total = taken
+            .iter()
+            .map(|item| u64::from(self.sell_price(item)) * u64::from(item.stack))
+            .sum::<u64>()
+            .min(u64::from(u32::MAX)) as u32;
+        character.inventory.gold = character.inventory.gold.saturating_add(total);
+        if let Some(listing) = self
+            .listings
+            .iter_mut()
+            .find(|listing| !listing.unlimited() && listing.item.name.trim() == name)
+        {
+            listing.stock = listing
+                .stock
+                .saturating_add(quantity)
+                .min(listing.max_stock.max(listing.stock));
+        }
+        Ok(self.record(ShopTransaction {
+            time,
+            world_turn,
+            player_id: player_id.to_owned(),
+            character_name: character.name.clone(),
+            kind: ShopTransactionKind::Sell,
+            item_name: name.to_owned(),
+            quantity,
+            total,
+            gold_after: character.inventory.gold,
+        }))
+    }
+
+    fn record(&mut self, transaction: ShopTransaction) -> ShopTransaction {
+        self.audit_log.push(transaction.clone());
+        let excess = self.audit_log.len().saturating_sub(SHOP_AUDIT_LOG_LIMIT);
+        self.audit_log.drain(..excess);
+        transaction
+    }
+
+    pub fn catalog_text(&self, gold: u32) -> String {
+        let mut lines = vec![format!("【{}】", self.name)];
+        if self.listings.is_empty() {
+            lines.push("货架上暂时没有商品。".to_owned());
+        }
+        for (index, listing) in self.listings.iter().enumerate() {
+            let mut line = format!(
+                "{}. {}［{}］ {}金币 库存{}",
+                index + 1,
+                listing.item.name.trim(),
+                inventory_quality_label(listing.item.quality),
+                self.buy_price(listing),
+                listing.stock_label()
+            );
+            if !listing.item.description.trim().is_empty() {
+                line.push_str(&format!(
+                    " —— {}",
+                    listing.item.description.trim()
+                ));
+            }
+            lines.push(line);
+        }
+        lines.push(format!(
+            "你的金币：{gold}。回收价为售价的{}%。",
+            self.sell_rate_percent
+        ));
+        lines.push("输入【.购买 物品或编号 数量】购买，【.出售 物品 数量】出售。".to_owned());
+        lines.join("\n")
+    }
+}
+
+enum ShopCommand<'a> {
+    Catalog,
+    Buy(&'a str, u32),
+    Sell(&'a str, u32),
+}
+
+fn parse_shop_command(text: &str) -> Option<ShopCommand<'_>> {
+    let body = private_command_body(text)?;
+    let (command, args) = body
+        .split_once(char::is_whitespace)
+        .map(|(command, args)| (command, args.trim()))
+        .unwrap_or((body, ""));
+    match command {
+        "商店" if args.is_empty() => Some(ShopCommand::Catalog),
+        "购买" => {
+            let (name, quantity) = parse_item_quantity(args);
+            Some(ShopCommand::Buy(name, quantity))
+        },
+        "出售" => {
+            let (name, quantity) = parse_item_quantity(args);
+            Some(ShopCommand::Sell(name, quantity))
+        },
+        _ => None,
+    }
+}
+
+/// Splits `物品 [数量]`; the quantity defaults to 1 and an unparsable tail stays part of the name.
+pub(crate) fn parse_item_quantity(args: &str) -> (&str, u32) {
+    let args = args.trim();
+    args.rsplit_once(char::is_whitespace)
+        .and_then(|(name, quantity)| {
+            quantity
+                .parse::<u32>()
+                .ok()
+                .map(|quantity| (name.trim(), quantity))
+        })
+        .unwrap_or((args, 1))
+}
+
+/// Handles `.商店`, `.购买` and `.出售` from a player's private chat and returns the reply.
+pub(super) fn handle_shop_command(
+    manager: &mut NapcatMessageManager,
+    target_id: &str,
+    text: &str,
+    time: u64,
+) -> Option<String> {
+    let command = parse_shop_command(text)?;
+    let Some(group_name) = manager
+        .group_name_for_player_target(target_id)
+        .map(str::to_owned)
+    else {
+        return Some("你当前不在TRPG组中，无法使用商店。".to_owned());
+    };
+    let Some(character) = manager.player_characters.get_mut(target_id) else {
+        return Some("你还没有角色卡。输入【.兑换】开始建卡。".to_owned());
+    };
+    if !character.inited {
+        return Some("角色卡尚未完成，暂时无法使用商店。".to_owned());
+    }
+    let group = manager.trpg_groups.get_mut(&group_name)?;
+    let world_turn = group.world_turn;
+    let shop = &mut group.shop;
+    shop.restock(world_turn);
+    let result = match command {
+        ShopCommand::Catalog => return Some(shop.catalog_text(character.inventory.gold)),
+        ShopCommand::Buy("", _) | ShopCommand::Sell("", _) => {
+            return Some("请输入物品名称，例如【.购买 治疗药水 2】。".to_owned());
+        },
+        ShopCommand::Buy(query, quantity) => shop.buy(
+            character, target_id, query, quantity, world_turn, time,
+        ),
+        ShopCommand::Sell(name, quantity) => shop.sell(
+            character, target_id, name, quantity, world_turn, time,
+        ),
+    };
+    Some(match result {
+        Ok(transaction) => format!(
+            "{}【{}】x{}，{}{}金币。剩余金币：{}。",
+            transaction.kind.label(),
+            transaction.item_name,
+            transaction.quantity,
+            match transaction.kind {
+                ShopTransactionKind::Buy => "花费",
+                ShopTransactionKind::Sell => "获得",
+            },
+            transaction.total,
+            transaction.gold_after
+        ),
+        Err(err) => err,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shop_with_potion() -> TrpgShop {
+        TrpgShop {
+            listings: vec![ShopListing {
+                item: InventoryItem {
+                    name: "治疗药水".to_owned(),
+                    max_stack: 10,
+                    ..Default::default()
+                },
+                price: 15,
+                stock: 3,
+                max_stock: 5,
+                restock_every_turns: 2,
+                restock_amount: 1,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn rich_character() -> PlayerCharacter {
+        let mut character = PlayerCharacter {
+            name: "艾拉".to_owned(),
+            ..Default::default()
+        };
+        character.inventory.gold = 100;
+        character
+    }
+
+    #[test]
+    fn quality_pricing_scales_with_item_level() {
+        let pricing = ShopPricing::default();
+        let item = InventoryItem {
+            quality: InventoryQuality::Rare,
+            item_level: 5,
+            ..Default::default()
+        };
+        assert_eq!(pricing.item_price(&item), 225);
+        let shop = TrpgShop::default();
+        assert_eq!(shop.sell_price(&item), 112);
+    }
+
+    #[test]
+    fn buying_checks_stock_and_gold_then_logs_the_purchase() {
+        let mut shop = shop_with_potion();
+        let mut character = rich_character();
+
+        let err = shop
+            .buy(
+                &mut character,
+                "10001",
+                "治疗药水",
+                4,
+                0,
+                0,
+            )
+            .unwrap_err();
+        assert!(err.contains("库存不足"));
+
+        let transaction = shop.buy(&mut character, "10001", "1", 2, 0, 42).unwrap();
+        assert_eq!(transaction.total, 30);
+        assert_eq!(character.inventory.gold, 70);
+        assert_eq!(character.inventory.items[0].stack, 2);
+        assert_eq!(shop.listings[0].stock, 1);
+        assert_eq!(shop.audit_log, vec![transaction]);
+
+        character.inventory.gold = 10;
+        assert!(shop
+            .buy(
+                &mut character,
+                "10001",
+                "治疗药水",
+                1,
+                0,
+                0
+            )
+            .unwrap_err()
+            .contains("金币不足"));
+        assert_eq!(shop.audit_log.len(), 1);
+    }
+
+    #[test]
+    fn buying_respects_bag_slots() {
+        let mut shop = shop_with_potion();
+        shop.listings[0].item.max_stack = 1;
+        let mut character = rich_character();
+        character.inventory.bag_slots = 1;
+
+        let err = shop
+            .buy(
+                &mut character,
+                "10001",
+                "治疗药水",
+                2,
+                0,
+                0,
+            )
+            .unwrap_err();
+        assert!(err.contains("背包空间不足"));
+        assert_eq!(character.inventory.gold, 100);
+    }
+
+    #[test]
+    fn selling_refuses_soulbound_items_and_returns_stock() {
+        let mut shop = shop_with_potion();
+        let mut character = rich_character();
+        character.inventory.items.push(InventoryItem {
+            name: "家传戒指".to_owned(),
+            soulbound: true,
+            ..Default::default()
+        });
+        assert!(shop
+            .sell(
+                &mut character,
+                "10001",
+                "家传戒指",
+                1,
+                0,
+                0
+            )
+            .unwrap_err()
+            .contains("灵魂绑定"));
+
+        shop.buy(
+            &mut character,
+            "10001",
+            "治疗药水",
+            2,
+            0,
+            0,
+        )
+        .unwrap();
+        let transaction = shop
+            .sell(
+                &mut character,
+                "10001",
+                "治疗药水",
+                2,
+                0,
+                0,
+            )
+            .unwrap();
+        assert_eq!(transaction.total, 14);
+        assert_eq!(character.inventory.gold, 84);
+        assert_eq!(shop.listings[0].stock, 3);
+        assert_eq!(shop.audit_log.len(), 2);
+    }
+
+    #[test]
+    fn selling_never_stocks_past_the_maximum_and_the_log_stays_bounded() {
+        let mut shop = shop_with_potion();
+        let mut character = rich_character();
+        character.inventory.items.push(InventoryItem {
+            name: "治疗药水".to_owned(),
+            max_stack: 10,
+            stack: 4,
+            ..Default::default()
+        });
+        shop.sell(
+            &mut character,
+            "10001",
+            "治疗药水",
+            4,
+            0,
+            0,
+        )
+        .unwrap();
+        assert_eq!(shop.listings[0].stock, 5);
+        assert!(shop.restock(2));
+        assert_eq!(shop.listings[0].stock, 5);
+
+        character.inventory.gold = 1_000_000;
+        for time in 0..SHOP_AUDIT_LOG_LIMIT as u64 + 2 {
+            shop.buy(&mut character, "10001", "1", 1, 0, time).unwrap();
+            shop.sell(
+                &mut character,
+                "10001",
+                "治疗药水",
+                1,
+                0,
+                time,
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            shop.audit_log.len(),
+            SHOP_AUDIT_LOG_LIMIT
+        );
+        assert_eq!(
+            shop.audit_log.last().unwrap().time,
+            SHOP_AUDIT_LOG_LIMIT as u64 + 1
+        );
+    }
+
+    #[test]
+    fn restock_follows_world_turns() {
+        let mut shop = shop_with_potion();
+        assert!(!shop.restock(1));
+        assert!(shop.restock(5));
+        assert_eq!(shop.listings[0].stock, 5);
+        assert_eq!(shop.listings[0].last_restock_turn, 4);
+
+        shop.listings[0].restock_amount = 0;
+        shop.listings[0].stock = 0;
+        assert!(shop.restock(6));
+        assert_eq!(shop.listings[0].stock, 5);
+    }
+
+    #[test]
+    fn item_quantity_parsing_keeps_spaced_names() {
+        assert_eq!(
+            parse_item_quantity("治疗药水 3"),
+            ("治疗药水", 3)
+        );
+        assert_eq!(
+            parse_item_quantity("龙 之 牙"),
+            ("龙 之 牙", 1)
+        );
+        assert_eq!(parse_item_quantity("铁剑"), ("铁剑", 1));
+    }
+}
@@ -0,0 +1,667 @@
+use std::{
+    io::Cursor,
+    path::Path,
+};
+
+use ab_glyph::{
+    point,
+    Font,
+    FontRef,
+    PxScale,
+    ScaleFont,
+};
+use image::{
+    imageops::{
+        self,
+        FilterType,
+    },
+    ImageFormat,
+    Rgba,
+    RgbaImage,
+};
+
+use super::{
+    character_display_name,
+    character_next_level_exp,
+    character_skill_count,
+    character_skill_display_name,
+    current_player_cooldown_turn,
+    get_character_status_value,
+    inventory::{
+        equipment_slot_label,
+        equipment_slot_options,
+    },
+    materialize_imported_skill_cooldowns,
+    private_character_hp_status,
+    skill_cooldown_remaining,
+    NapcatMessageManager,
+    PlayerCharacter,
+    RemoteImageCache,
+    StatusKey,
+};
+
+const CARD_WIDTH: u32 = 640;
+const CARD_PADDING: i32 = 24;
+const PORTRAIT_SIZE: u32 = 168;
+const LINE_HEIGHT: i32 = 26;
+const SECTION_GAP: i32 = 14;
+const BACKGROUND: [u8; 3] = [28, 30, 38];
+const PANEL: [u8; 3] = [40, 43, 54];
+const TEXT: [u8; 3] = [232, 232, 238];
+const MUTED: [u8; 3] = [150, 154, 170];
+const HEADING: [u8; 3] = [236, 196, 120];
+const HP_COLOR: [u8; 3] = [196, 62, 62];
+const MP_COLOR: [u8; 3] = [66, 116, 206];
+const BAR_BACKGROUND: [u8; 3] = [62, 64, 76];
+const BUFF_COLOR: [u8; 3] = [120, 200, 130];
+const DEBUFF_COLOR: [u8; 3] = [222, 120, 110];
+const STATUS_KEYS: [StatusKey; 8] = [
+    StatusKey::Str,
+    StatusKey::Agi,
+    StatusKey::Dex,
+    StatusKey::Vit,
+    StatusKey::Int,
+    StatusKey::Wis,
+    StatusKey::K,
+    StatusKey::Cha,
+];
+
+struct CardLine {
+    text: String,
+    color: [u8; 3],
+}
+
+impl CardLine {
+    fn new(text: impl Into<String>, color: [u8; 3]) -> Self {
+        Self {
+            text: text.into(),
+            color,
+        }
+    }
+}
+
+/// Renders the `.状态` character card as PNG bytes: portrait, HP/MP bars, the eight stats,
+/// equipment, active buffs and skill cooldowns. Fails when the player has no finished character,
+/// so the caller can fall back to the text reply.
+pub(super) fn render_character_status_card(
+    manager: &NapcatMessageManager,
+    target_id: &str,
+    image_cache: &mut RemoteImageCache,
+) -> Result<Vec<u8>, String> {
+    let mut character = manager
+        .player_characters
+        .get(target_id)
+        .filter(|character| character.inited)
+        .cloned()
+        .ok_or_else(|| "还没有完成的角色卡".to_owned())?;
+    let current_turn = current_player_cooldown_turn(manager, target_id);
+    materialize_imported_skill_cooldowns(&mut character, current_turn);
+    let font = FontRef::try_from_slice(include_bytes!(
+        "../../assets/fonts/AlibabaHealthFont.ttf"
+    ))
+    .map_err(|err| err.to_string())?;
+
+    let sections = [
+        ("装备", equipment_lines(&character)),
+        ("状态效果", buff_lines(&character)),
+        (
+            "技能",
+            skill_lines(&character, current_turn),
+        ),
+    ];
+    let header_height = PORTRAIT_SIZE as i32;
+    let stats_height = LINE_HEIGHT * 2 + SECTION_GAP;
+    let sections_height = sections
+        .iter()
+        .map(|(_, lines)| LINE_HEIGHT * (lines.len() as i32 + 1) + SECTION_GAP)
+        .sum::<i32>();
+    let height = CARD_PADDING * 2 + header_height + SECTION_GAP + stats_height + sections_height;
+    let mut canvas = RgbaImage::from_pixel(
+        CARD_WIDTH,
+        height as u32,
+        Rgba([BACKGROUND[0], BACKGROUND[1], BACKGROUND[2], u8::MAX]),
+    );
+
+    draw_portrait(
+        &mut canvas,
+        &font,
+        &character,
+        target_id,
+        image_cache,
+    );
+    draw_header(
+        &mut canvas,
+        &font,
+        &character,
+        target_id,
+    );
+
+    let mut y = CARD_PADDING + header_height + SECTION_GAP;
+    draw_stats(&mut canvas, &font, &character, y);
+    y += stats_height;
+
+    for (title, lines) in sections {
+        draw_text(
+            &mut canvas,
+            &font,
+            title,
+            CARD_PADDING,
+            y,
+            20.0,
+            HEADING,
+        );
+        y += LINE_HEIGHT;
+        for line in lines {
+            draw_text_clipped(
+                &mut canvas,
+                &font,
+                &line.text,
+                CARD_PADDING + 12,
+                y,
+                18.0,
+                line.color,
+                CARD_WIDTH as i32 - CARD_PADDING * 2 - 12,
+            );
+            y += LINE_HEIGHT;
+        }
+        y += SECTION_GAP;
+    }
+
+    let mut bytes = Vec::new();
+    canvas
+        .write_to(
+            &mut Cursor::new(&mut bytes),
+            ImageFormat::Png,
+        )
+        .map_err(|err| err.to_string())?;
+    Ok(bytes)
+}
+
+fn equipment_lines(character: &PlayerCharacter) -> Vec<CardLine> {
+    let lines = equipment_slot_options()
+        .into_iter()
+        .filter_map(|slot| {
+            let item = character.inventory.equipment.get(&slot)?;
+            Some(CardLine::new(
+                format!(
+                    "{}：{}",
+                    equipment_slot_label(slot),
+                    item.name
+                ),
+                TEXT,
+            ))
+        })
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        vec![CardLine::new("未装备任何物品", MUTED)]
+    } else {
+        lines
+    }
+}
+
+fn buff_lines(character: &PlayerCharacter) -> Vec<CardLine> {
+    if character.active_buffs.is_empty() {
+        return vec![CardLine::new("无", MUTED)];
+    }
+    character
+        .active_buffs
+        .iter()
+        .map(|buff| {
+            let duration = if buff.turns_remaining > 0 {
+                format!("剩余{}轮", buff.turns_remaining)
+            } else {
+                "持续".to_owned()
+            };
+            CardLine::new(
+                format!("{}  {duration}", buff.name),
+                if buff.beneficial { BUFF_COLOR } else { DEBUFF_COLOR },
+            )
+        })
+        .collect()
+}
+
+fn skill_lines(character: &PlayerCharacter, current_turn: u32) -> Vec<CardLine> {
+    let skill_count = character_skill_count(character);
+    if skill_count == 0 {
+        return vec![CardLine::new("还没有已兑换技能", MUTED)];
+    }
+    (0..skill_count)
+        .map(|index| {
+            let cooldown = character
+                .skill_cooldown_turns
+                .get(index)
+                .copied()
+                .unwrap_or_default();
+            let cooldown_left = character
+                .skill_metadata
+                .get(index)
+                .and_then(|metadata| metadata.cooldown_left);
+            let remaining = skill_cooldown_remaining(
+                character,
+                index,
+                cooldown,
+                cooldown_left,
+                current_turn,
+            );
+            let name = character_skill_display_name(character, index);
+            if remaining == 0 {
+                CardLine::new(format!("{name}  可用"), TEXT)
+            } else {
+                CardLine::new(
+                    format!("{name}  还剩{remaining}轮"),
+                    MUTED,
+                )
+            }
+        })
+        .collect()
+}
+
+fn draw_portrait(
+    canvas: &mut RgbaImage,
+    font: &FontRef<'_>,
+    character: &PlayerCharacter,
+    target_id: &str,
+    image_cache: &mut RemoteImageCache,
+) {
+    let x = CARD_PADDING;
+    let y = CARD_PADDING;
+    match load_portrait(character.image.trim(), image_cache) {
+        Some(portrait) => {
+            imageops::overlay(canvas, &portrait, x as i64, y as i64);
+        },
+        None => {
+            fill_rect(
+                canvas,
+                x,
+                y,
+                PORTRAIT_SIZE as i32,
+                PORTRAIT_SIZE as i32,
+                PANEL,
+            );
+            let initial = character_display_name(character, target_id)
+                .chars()
+                .next()
+                .unwrap_or('?')
+                .to_string();
+            let width = text_width(font, &initial, 96.0);
+            draw_text(
+                canvas,
+                font,
+                &initial,
+                x + (PORTRAIT_SIZE as i32 - width) / 2,
+                y + 28,
+                96.0,
+                MUTED,
+            );
+        },
+    }
+}
+
+/// The portrait is either a cached local file or a remote URL recorded during character creation.
+/// A URL that is not cached yet is fetched in the background and the card falls back to the
+/// initial until the next request, since cards render inside the message system.
+fn load_portrait(source: &str, image_cache: &mut RemoteImageCache) -> Option<RgbaImage> {
+    if source.is_empty() {
+        return None;
+    }
+    let path = if Path::new(source).is_file() {
+        Path::new(source).to_path_buf()
+    } else if source.starts_with("http://") || source.starts_with("https://") {
+        let cached = image_cache.cached(source);
+        if cached.is_none() {
+            image_cache.prefetch(source);
+        }
+        cached?
+    } else {
+        return None;
+    };
+    let image = image::open(path).ok()?;
+    Some(
+        image
+            .resize_to_fill(
+                PORTRAIT_SIZE,
+                PORTRAIT_SIZE,
+                FilterType::Triangle,
+            )
+            .to_rgba8(),
+    )
+}
+
+fn draw_header(
+    canvas: &mut RgbaImage,
+    font: &FontRef<'_>,
+    character: &PlayerCharacter,
+    target_id: &str,
+) {
+    let x = CARD_PADDING * 2 + PORTRAIT_SIZE as i32;
+    let width = CARD_WIDTH as i32 - x - CARD_PADDING;
+    let mut y = CARD_PADDING;
+    draw_text_clipped(
+        canvas,
+        font,
+        &character_display_name(character, target_id),
+        x,
+        y,
+        30.0,
+        TEXT,
+        width,
+    );
+    y += 42;
+    draw_text(
+        canvas,
+        font,
+        &format!(
+            "等级 {}  经验 {} / {}",
+            character.level,
+            character.exp,
+            character_next_level_exp(character.level)
+        ),
+        x,
+        y,
+        18.0,
+        MUTED,
+    );
+    y += 34;
+
+    // Players with low knowledge only see their wound level, matching the text reply.
+    let hp_label = if get_character_status_value(&character.status, StatusKey::K)
+        + get_character_status_value(&character.extra_status, StatusKey::K)
+        >= 10
+    {
+        format!(
+            "HP {:.0} / {:.0}",
+            character.hp, character.max_hp
+        )
+    } else {
+        format!(
+            "HP 【{}】",
+            private_character_hp_status(character.hp, character.max_hp)
+        )
+    };
+    draw_bar(
+        canvas,
+        font,
+        x,
+        y,
+        width,
+        &hp_label,
+        character.hp,
+        character.max_hp,
+        HP_COLOR,
+    );
+    y += 40;
+    draw_bar(
+        canvas,
+        font,
+        x,
+        y,
+        width,
+        &format!(
+            "MP {:.0} / {:.0}",
+            character.mp, character.max_mp
+        ),
+        character.mp,
+        character.max_mp,
+        MP_COLOR,
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_bar(
+    canvas: &mut RgbaImage,
+    font: &FontRef<'_>,
+    x: i32,
+    y: i32,
+    width: i32,
+    label: &str,
+    value: f32,
+    max: f32,
+    color: [u8; 3],
+) {
+    const BAR_HEIGHT: i32 = 28;
+    fill_rect(
+        canvas,
+        x,
+        y,
+        width,
+        BAR_HEIGHT,
+        BAR_BACKGROUND,
+    );
+    let ratio = if max > 0.0 { (value / max).clamp(0.0, 1.0) } else { 0.0 };
+    fill_rect(
+        canvas,
+        x,
+        y,
+        (width as f32 * ratio).round() as i32,
+        BAR_HEIGHT,
+        color,
+    );
+    draw_text(
+        canvas,
+        font,
+        label,
+        x + 8,
+        y + 3,
+        18.0,
+        TEXT,
+    );
+}
+
+fn draw_stats(canvas: &mut RgbaImage, font: &FontRef<'_>, character: &PlayerCharacter, y: i32) {
+    let column_width = (CARD_WIDTH as i32 - CARD_PADDING * 2) / 4;
+    fill_rect(
+        canvas,
+        CARD_PADDING,
+        y - 4,
+        CARD_WIDTH as i32 - CARD_PADDING * 2,
+        LINE_HEIGHT * 2 + 8,
+        PANEL,
+    );
+    for (index, status_key) in STATUS_KEYS.iter().enumerate() {
+        let base = get_character_status_value(&character.status, *status_key);
+        let extra = get_character_status_value(&character.extra_status, *status_key);
+        let text = if extra == 0 {
+            format!("{} {}", status_key.zh(), base + extra)
+        } else {
+            format!(
+                "{} {}({:+})",
+                status_key.zh(),
+                base + extra,
+                extra
+            )
+        };
+        draw_text(
+            canvas,
+            font,
+            &text,
+            CARD_PADDING + 12 + column_width * (index % 4) as i32,
+            y + LINE_HEIGHT * (index / 4) as i32,
+            18.0,
+            TEXT,
+        );
+    }
+}
+
+fn fill_rect(canvas: &mut RgbaImage, x: i32, y: i32, width: i32, height: i32, color: [u8; 3]) {
+    let x_end = (x + width).clamp(0, canvas.width() as i32);
+    let y_end = (y + height).clamp(0, canvas.height() as i32);
+    for py in y.max(0)..y_end {
+        for px in x.max(0)..x_end {
+            canvas.put_pixel(
+                px as u32,
+                py as u32,
+                Rgba([color[0], color[1], color[2], u8::MAX]),
+            );
+        }
+    }
+}
+
+fn text_width(font: &FontRef<'_>, text: &str, size: f32) -> i32 {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut width = 0.0;
+    let mut previous = None;
+    for ch in text.chars() {
+        let glyph_id = scaled.glyph_id(ch);
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, glyph_id);
+        }
+        width += scaled.h_advance(glyph_id);
+        previous = Some(glyph_id);
+    }
+    width.ceil() as i32
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_text_clipped(
+    canvas: &mut RgbaImage,
+    font: &FontRef<'_>,
+    text: &str,
+    x: i32,
+    y: i32,
+    size: f32,
+    color: [u8; 3],
+    max_width: i32,
+) {
+    if text_width(font, text, size) <= max_width {
+        draw_text(canvas, font, text, x, y, size, color);
+        return;
+    }
+    let mut clipped = text.chars().collect::<Vec<_>>();
+    while !clipped.is_empty() {
+        clipped.pop();
+        let candidate = format!(
+            "{}…",
+            clipped.iter().collect::<String>()
+        );
+        if text_width(font, &candidate, size) <= max_width {
+            draw_text(
+                canvas, font, &candidate, x, y, size, color,
+            );
+            return;
+        }
+    }
+}
+
+/// Draws `text` with its top edge at `y`, blending glyph coverage over the existing pixels.
+fn draw_text(
+    canvas: &mut RgbaImage,
+    font: &FontRef<'_>,
+    text: &str,
+    x: i32,
+    y: i32,
+    size: f32,
+    color: [u8; 3],
+) {
+    let scale = PxScale::from(size);
+    let scaled = font.as_scaled(scale);
+    let baseline = y as f32 + scaled.ascent();
+    let mut caret = x as f32;
+    let mut previous = None;
+    for ch in text.chars() {
+        let glyph_id = scaled.glyph_id(ch);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, glyph_id);
+        }
+        let glyph = glyph_id.with_scale_and_position(scale, point(caret, baseline));
+        caret += scaled.h_advance(glyph_id);
+        previous = Some(glyph_id);
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|glyph_x, glyph_y, coverage| {
+            let px = bounds.min.x as i32 + glyph_x as i32;
+            let py = bounds.min.y as i32 + glyph_y as i32;
+            if px < 0 || py < 0 || px >= canvas.width() as i32 || py >= canvas.height() as i32 {
+                return;
+            }
+            let pixel = canvas.get_pixel_mut(px as u32, py as u32);
+            for (channel, target) in pixel.0.iter_mut().zip(color) {
+                *channel =
+                    (*channel as f32 * (1.0 - coverage) + target as f32 * coverage).round() as u8;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napcat::tests::empty_manager;
+
+    #[test]
+    fn status_card_renders_a_png_sized_to_its_sections() {
+        let mut manager = empty_manager();
+        let mut character = PlayerCharacter {
+            inited: true,
+            name: "艾琳".to_owned(),
+            hp: 12.0,
+            max_hp: 20.0,
+            mp: 5.0,
+            max_mp: 10.0,
+            ..Default::default()
+        };
+        character.skill_names = vec!["火球术".to_owned(), "护盾".to_owned()];
+        character.skill_cooldown_turns = vec![2, 0];
+        manager
+            .player_characters
+            .insert("1001".to_owned(), character);
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut image_cache = RemoteImageCache::new(cache_dir.path().to_path_buf());
+        let png = render_character_status_card(&manager, "1001", &mut image_cache).unwrap();
+        let image = image::load_from_memory_with_format(&png, ImageFormat::Png)
+            .unwrap()
+            .to_rgba8();
+
+        assert_eq!(image.width(), CARD_WIDTH);
+        let expected_height = CARD_PADDING * 2
+            + PORTRAIT_SIZE as i32
+            + SECTION_GAP
+            + LINE_HEIGHT * 2
+            + SECTION_GAP
+            + (LINE_HEIGHT * 2 + SECTION_GAP) * 2
+            + LINE_HEIGHT * 3
+            + SECTION_GAP;
+        assert_eq!(image.height() as i32, expected_height);
+        assert!(image.pixels().any(|pixel| pixel.0[..3] == HP_COLOR));
+    }
+
+    #[test]
+    fn remote_portraits_come_from_the_image_cache_without_fetching() {
+        // Nothing listens on port 9, so the portrait can only come from the cache.
+        let url = "http://127.0.0.1:9/status-card-cached-portrait.png";
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut image_cache = RemoteImageCache::new(cache_dir.path().to_path_buf());
+        RgbaImage::from_pixel(4, 4, Rgba([200, 40, 40, 255]))
+            .save(cache_dir.path().join(format!(
+                "{}.png",
+                crate::napcat::remote_image_cache_stem(url)
+            )))
+            .unwrap();
+
+        let portrait = load_portrait(url, &mut image_cache).unwrap();
+        assert!(image_cache.prefetches.is_empty());
+        assert_eq!(
+            portrait.dimensions(),
+            (PORTRAIT_SIZE, PORTRAIT_SIZE)
+        );
+        assert_eq!(portrait.get_pixel(0, 0).0, [
+            200, 40, 40, 255
+        ]);
+    }
+
+    #[test]
+    fn status_card_requires_a_finished_character() {
+        let mut manager = empty_manager();
+        manager.player_characters.insert(
+            "1001".to_owned(),
+            PlayerCharacter::default(),
+        );
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut image_cache = RemoteImageCache::new(cache_dir.path().to_path_buf());
+        assert!(render_character_status_card(&manager, "1001", &mut image_cache).is_err());
+        assert!(render_character_status_card(&manager, "1002", &mut image_cache).is_err());
+    }
+}
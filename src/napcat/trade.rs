@@ -0,0 +1,1101 @@
+use std::{
+    collections::HashMap,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use bevy::prelude::*;
+use bevy_persistent::Persistent;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    inventory::{
+        add_item_to_inventory,
+        inventory_capacity_for,
+        take_tradeable_items,
+        tradeable_item_count,
+    },
+    message_text,
+    private_command_body,
+    queue_private_text_response,
+    shop::parse_item_quantity,
+    InventoryItem,
+    NapcatAutomaticReplyRequests,
+    NapcatIOSender,
+    NapcatMessage,
+    NapcatMessageChainType,
+    NapcatMessageManager,
+    PlayerCharacter,
+    TrpgGroup,
+};
+
+/// Open trades with no activity for this long expire. Trades waiting for the GM never expire.
+pub const TRADE_TIMEOUT_SECS: u64 = 5 * 60;
+
+/// Every trade a TRPG group has seen, open or closed, so the GM can review them later.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct TrpgTradeBook {
+    #[serde(default)]
+    pub require_gm_approval: bool,
+    #[serde(default)]
+    pub trades: Vec<PlayerTrade>,
+    #[serde(default)]
+    pub next_id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct TradeOfferItem {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub quantity: u32,
+}
+
+/// What one side puts on the table. Items stay in the owner's bag until the swap.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct TradeOffer {
+    #[serde(default)]
+    pub items: Vec<TradeOfferItem>,
+    #[serde(default)]
+    pub gold: u32,
+    #[serde(default)]
+    pub confirmed: bool,
+}
+
+impl TradeOffer {
+    pub fn summary(&self) -> String {
+        let mut parts = self
+            .items
+            .iter()
+            .map(|item| format!("【{}】x{}", item.name, item.quantity))
+            .collect::<Vec<_>>();
+        if self.gold > 0 {
+            parts.push(format!("{}金币", self.gold));
+        }
+        if parts.is_empty() {
+            "（空）".to_owned()
+        } else {
+            parts.join("、")
+        }
+    }
+
+    fn offered_quantity(&self, name: &str) -> u32 {
+        self.items
+            .iter()
+            .filter(|item| item.name == name)
+            .map(|item| item.quantity)
+            .sum()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeStatus {
+    #[default]
+    Open,
+    AwaitingGm,
+    Completed,
+    Cancelled,
+    Expired,
+    Vetoed,
+}
+
+impl TradeStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Open => "进行中",
+            Self::AwaitingGm => "待GM审核",
+            Self::Completed => "已完成",
+            Self::Cancelled => "已取消",
+            Self::Expired => "已超时",
+            Self::Vetoed => "GM否决",
+        }
+    }
+
+    pub fn is_active(self) -> bool { matches!(self, Self::Open | Self::AwaitingGm) }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct PlayerTrade {
+    #[serde(default)]
+    pub id: u64,
+    #[serde(default)]
+    pub initiator: String,
+    #[serde(default)]
+    pub partner: String,
+    #[serde(default)]
+    pub initiator_offer: TradeOffer,
+    #[serde(default)]
+    pub partner_offer: TradeOffer,
+    #[serde(default)]
+    pub status: TradeStatus,
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default)]
+    pub updated_at: u64,
+    #[serde(default)]
+    pub note: String,
+}
+
+impl PlayerTrade {
+    pub fn involves(&self, target_id: &str) -> bool {
+        self.initiator == target_id || self.partner == target_id
+    }
+
+    pub fn counterpart(&self, target_id: &str) -> &str {
+        if self.initiator == target_id {
+            &self.partner
+        } else {
+            &self.initiator
+        }
+    }
+
+    fn offer_mut(&mut self, target_id: &str) -> &mut TradeOffer {
+        if self.initiator == target_id {
+            &mut self.initiator_offer
+        } else {
+            &mut self.partner_offer
+        }
+    }
+
+    fn reset_confirmations(&mut self) {
+        self.initiator_offer.confirmed = false;
+        self.partner_offer.confirmed = false;
+    }
+
+    fn expired(&self, now: u64) -> bool {
+        self.status == TradeStatus::Open
+            && now.saturating_sub(self.updated_at) >= TRADE_TIMEOUT_SECS
+    }
+}
+
+impl TrpgTradeBook {
+    pub fn active_trade_index(&self, target_id: &str) -> Option<usize> {
+        self.trades
+            .iter()
+            .position(|trade| trade.status.is_active() && trade.involves(target_id))
+    }
+
+    pub fn trade_index(&self, id: u64) -> Option<usize> {
+        self.trades.iter().position(|trade| trade.id == id)
+    }
+
+    /// Expires idle open trades and returns them.
+    pub fn expire(&mut self, now: u64) -> Vec<PlayerTrade> {
+        let mut expired = Vec::new();
+        for trade in &mut self.trades {
+            if trade.expired(now) {
+                trade.status = TradeStatus::Expired;
+                trade.updated_at = now;
+                expired.push(trade.clone());
+            }
+        }
+        expired
+    }
+}
+
+/// Private replies produced by a trade step: one for the player who acted and notices for others.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TradeOutcome {
+    pub reply: String,
+    pub notices: Vec<(String, String)>,
+}
+
+impl TradeOutcome {
+    fn reply(reply: impl Into<String>) -> Self {
+        Self {
+            reply: reply.into(),
+            notices: Vec::new(),
+        }
+    }
+}
+
+enum TradeCommand<'a> {
+    Show,
+    Open(&'a str),
+    Put(&'a str, u32),
+    Take(&'a str, u32),
+    Gold(Option<u32>),
+    Confirm,
+    Cancel,
+}
+
+fn parse_trade_command(text: &str) -> Option<TradeCommand<'_>> {
+    let body = private_command_body(text)?;
+    let args = body.strip_prefix("交易")?;
+    if !(args.is_empty() || args.starts_with(char::is_whitespace) || args.starts_with('@')) {
+        return None;
+    }
+    let args = args.trim();
+    let (sub, rest) = args
+        .split_once(char::is_whitespace)
+        .map(|(sub, rest)| (sub, rest.trim()))
+        .unwrap_or((args, ""));
+    Some(match sub {
+        "" | "查看" => TradeCommand::Show,
+        "放入" => {
+            let (name, quantity) = parse_item_quantity(rest);
+            TradeCommand::Put(name, quantity)
+        },
+        "取回" => {
+            let (name, quantity) = parse_item_quantity(rest);
+            TradeCommand::Take(name, quantity)
+        },
+        "金币" => TradeCommand::Gold(rest.parse().ok()),
+        "确认" => TradeCommand::Confirm,
+        "取消" => TradeCommand::Cancel,
+        _ => TradeCommand::Open(args.trim_start_matches('@').trim()),
+    })
+}
+
+pub(super) fn message_mention(message: &NapcatMessage) -> Option<String> {
+    message
+        .data
+        .message
+        .iter()
+        .find_map(|chain| match &chain.variant {
+            NapcatMessageChainType::At { data } if data.qq != "all" => Some(data.qq.clone()),
+            _ => None,
+        })
+}
+
+fn trader_name(manager: &NapcatMessageManager, target_id: &str) -> String {
+    manager
+        .player_characters
+        .get(target_id)
+        .map(|character| character.name.trim())
+        .filter(|name| !name.is_empty())
+        .map(str::to_owned)
+        .unwrap_or_else(|| target_id.to_owned())
+}
+
+fn resolve_trade_partner(
+    group: &TrpgGroup,
+    manager: &NapcatMessageManager,
+    query: &str,
+) -> Option<String> {
+    let query = query.trim();
+    if query.is_empty() {
+        return None;
+    }
+    if let Some(player_id) = group.players.iter().find(|player_id| *player_id == query) {
+        return Some(player_id.clone());
+    }
+    let mut matches = group.players.iter().filter(|player_id| {
+        manager
+            .player_characters
+            .get(*player_id)
+            .is_some_and(|character| {
+                character.name.trim() == query || character.nickname.trim() == query
+            })
+    });
+    let player_id = matches.next()?;
+    matches.next().is_none().then(|| player_id.clone())
+}
+
+fn trade_summary(manager: &NapcatMessageManager, trade: &PlayerTrade) -> String {
+    let side = |target_id: &str, offer: &TradeOffer| {
+        format!(
+            "{}{}：{}",
+            trader_name(manager, target_id),
+            if offer.confirmed { "（已确认）" } else { "" },
+            offer.summary()
+        )
+    };
+    format!(
+        "交易#{} {}\n{}\n{}",
+        trade.id,
+        trade.status.label(),
+        side(&trade.initiator, &trade.initiator_offer),
+        side(&trade.partner, &trade.partner_offer)
+    )
+}
+
+const TRADE_USAGE: &str = "输入【.交易 @玩家】发起交易，【.交易 放入 物品 数量】【.交易 取回 物品 数量】【.交易 金币 数量】调整报价，双方【.交易 确认】后成交，【.交易 取消】结束交易。";
+
+/// Handles `.交易 …` from a player's private chat.
+pub(super) fn handle_trade_command(
+    manager: &mut NapcatMessageManager,
+    target_id: &str,
+    message: &NapcatMessage,
+) -> Option<TradeOutcome> {
+    let text = message_text(message);
+    let mention = message_mention(message);
+    let command = match parse_trade_command(&text)? {
+        TradeCommand::Show if mention.is_some() => TradeCommand::Open(""),
+        command => command,
+    };
+    let now = message.data.time;
+    let Some(group_name) = manager
+        .group_name_for_player_target(target_id)
+        .map(str::to_owned)
+    else {
+        return Some(TradeOutcome::reply(
+            "你当前不在TRPG组中，无法交易。",
+        ));
+    };
+    if !manager
+        .player_characters
+        .get(target_id)
+        .is_some_and(|character| character.inited)
+    {
+        return Some(TradeOutcome::reply(
+            "你还没有完成角色卡，暂时无法交易。",
+        ));
+    }
+
+    let mut outcome = TradeOutcome::default();
+    for trade in manager.trpg_groups.get_mut(&group_name)?.trades.expire(now) {
+        outcome.notices.extend(expiry_notices(manager, &trade));
+    }
+    let result = match command {
+        TradeCommand::Open(query) => open_trade(
+            manager,
+            &group_name,
+            target_id,
+            query,
+            mention,
+            now,
+        ),
+        command => update_trade(
+            manager,
+            &group_name,
+            target_id,
+            command,
+            now,
+        ),
+    };
+    match result {
+        Ok(step) => {
+            outcome.reply = step.reply;
+            outcome.notices.extend(step.notices);
+        },
+        Err(err) => outcome.reply = err,
+    }
+    // Notices meant for the acting player are folded into the reply instead of sent twice.
+    let (own, others): (Vec<_>, Vec<_>) = outcome
+        .notices
+        .into_iter()
+        .partition(|(recipient, _)| recipient == target_id);
+    outcome.notices = others;
+    for (_, text) in own.into_iter().rev() {
+        if text != outcome.reply {
+            outcome.reply = format!("{text}\n{}", outcome.reply);
+        }
+    }
+    Some(outcome)
+}
+
+fn open_trade(
+    manager: &mut NapcatMessageManager,
+    group_name: &str,
+    target_id: &str,
+    query: &str,
+    mention: Option<String>,
+    now: u64,
+) -> Result<TradeOutcome, String> {
+    let group = manager
+        .trpg_groups
+        .get(group_name)
+        .ok_or_else(|| "TRPG组不存在。".to_owned())?;
+    let query = if query.is_empty() { mention.unwrap_or_default() } else { query.to_owned() };
+    let partner = resolve_trade_partner(group, manager, &query).ok_or_else(|| {
+        format!(
+            "同组里找不到玩家【{}】。{TRADE_USAGE}",
+            query.trim()
+        )
+    })?;
+    if partner == target_id {
+        return Err("不能和自己交易。".to_owned());
+    }
+    if !manager
+        .player_characters
+        .get(&partner)
+        .is_some_and(|character| character.inited)
+    {
+        return Err(format!(
+            "{}还没有完成角色卡，无法交易。",
+            trader_name(manager, &partner)
+        ));
+    }
+    if group.trades.active_trade_index(target_id).is_some() {
+        return Err("你已有进行中的交易，先【.交易 取消】或完成它。".to_owned());
+    }
+    if group.trades.active_trade_index(&partner).is_some() {
+        return Err(format!(
+            "{}正在进行另一笔交易。",
+            trader_name(manager, &partner)
+        ));
+    }
+
+    let initiator_name = trader_name(manager, target_id);
+    let partner_name = trader_name(manager, &partner);
+    let book = &mut manager.trpg_groups.get_mut(group_name).unwrap().trades;
+    book.next_id += 1;
+    let id = book.next_id;
+    book.trades.push(PlayerTrade {
+        id,
+        initiator: target_id.to_owned(),
+        partner: partner.clone(),
+        created_at: now,
+        updated_at: now,
+        ..Default::default()
+    });
+    Ok(TradeOutcome {
+        reply: format!("已向{partner_name}发起交易#{id}。{TRADE_USAGE}"),
+        notices: vec![(
+            partner,
+            format!("{initiator_name}向你发起交易#{id}。{TRADE_USAGE}"),
+        )],
+    })
+}
+
+fn update_trade(
+    manager: &mut NapcatMessageManager,
+    group_name: &str,
+    target_id: &str,
+    command: TradeCommand<'_>,
+    now: u64,
+) -> Result<TradeOutcome, String> {
+    let group = manager
+        .trpg_groups
+        .get(group_name)
+        .ok_or_else(|| "TRPG组不存在。".to_owned())?;
+    let Some(index) = group.trades.active_trade_index(target_id) else {
+        return Err(format!(
+            "你当前没有进行中的交易。{TRADE_USAGE}"
+        ));
+    };
+    let require_gm_approval = group.trades.require_gm_approval;
+    let mut trade = group.trades.trades[index].clone();
+    if trade.status == TradeStatus::AwaitingGm
+        && !matches!(
+            command,
+            TradeCommand::Show | TradeCommand::Cancel
+        )
+    {
+        return Err("双方已确认，交易正等待GM审核，无法再修改。".to_owned());
+    }
+    let character = manager
+        .player_characters
+        .get(target_id)
+        .ok_or_else(|| "你还没有角色卡。".to_owned())?;
+    let other = trade.counterpart(target_id).to_owned();
+    let name = trader_name(manager, target_id);
+
+    let notify_other;
+    let reply = match command {
+        TradeCommand::Show | TradeCommand::Open(_) => {
+            return Ok(TradeOutcome::reply(trade_summary(
+                manager, &trade,
+            )));
+        },
+        TradeCommand::Put("", _) | TradeCommand::Take("", _) => {
+            return Err("请输入物品名称，例如【.交易 放入 治疗药水 2】。".to_owned());
+        },
+        TradeCommand::Put(_, 0) | TradeCommand::Take(_, 0) => {
+            return Err("数量至少为1。".to_owned());
+        },
+        TradeCommand::Put(item_name, quantity) => {
+            let offer = trade.offer_mut(target_id);
+            let wanted = offer.offered_quantity(item_name) + quantity;
+            let owned = tradeable_item_count(&character.inventory, item_name);
+            if owned < wanted {
+                return Err(
+                    if owned == 0
+                        && character
+                            .inventory
+                            .items
+                            .iter()
+                            .any(|item| item.soulbound && item.name.trim() == item_name)
+                    {
+                        format!("【{item_name}】已灵魂绑定，无法交易。")
+                    } else {
+                        format!("背包里可交易的【{item_name}】只有{owned}个。")
+                    },
+                );
+            }
+            match offer.items.iter_mut().find(|item| item.name == item_name) {
+                Some(item) => item.quantity = wanted,
+                None => offer.items.push(TradeOfferItem {
+                    name: item_name.to_owned(),
+                    quantity,
+                }),
+            }
+            trade.reset_confirmations();
+            notify_other = Some(format!("{name}调整了交易报价。"));
+            format!("已放入【{item_name}】x{quantity}。")
+        },
+        TradeCommand::Take(item_name, quantity) => {
+            let offer = trade.offer_mut(target_id);
+            let Some(position) = offer.items.iter().position(|item| item.name == item_name) else {
+                return Err(format!("报价里没有【{item_name}】。"));
+            };
+            let item = &mut offer.items[position];
+            item.quantity = item.quantity.saturating_sub(quantity);
+            if item.quantity == 0 {
+                offer.items.remove(position);
+            }
+            trade.reset_confirmations();
+            notify_other = Some(format!("{name}调整了交易报价。"));
+            format!("已取回【{item_name}】x{quantity}。")
+        },
+        TradeCommand::Gold(None) => {
+            return Err("请输入金币数量，例如【.交易 金币 50】。".to_owned());
+        },
+        TradeCommand::Gold(Some(gold)) => {
+            if character.inventory.gold < gold {
+                return Err(format!(
+                    "金币不足：当前{}。",
+                    character.inventory.gold
+                ));
+            }
+            trade.offer_mut(target_id).gold = gold;
+            trade.reset_confirmations();
+            notify_other = Some(format!("{name}调整了交易报价。"));
+            format!("报价金币设为{gold}。")
+        },
+        TradeCommand::Cancel => {
+            trade.status = TradeStatus::Cancelled;
+            trade.updated_at = now;
+            let text = format!("交易#{}已被{name}取消。", trade.id);
+            store_trade(manager, group_name, index, trade);
+            return Ok(TradeOutcome {
+                reply: text.clone(),
+                notices: vec![(other, text)],
+            });
+        },
+        TradeCommand::Confirm => {
+            trade.offer_mut(target_id).confirmed = true;
+            if !(trade.initiator_offer.confirmed && trade.partner_offer.confirmed) {
+                notify_other = Some(format!(
+                    "{name}已确认交易，输入【.交易 确认】完成交换。"
+                ));
+                "已确认，等待对方确认。".to_owned()
+            } else if require_gm_approval {
+                trade.status = TradeStatus::AwaitingGm;
+                trade.updated_at = now;
+                let text = format!(
+                    "双方已确认交易#{}，等待GM审核。",
+                    trade.id
+                );
+                store_trade(manager, group_name, index, trade);
+                return Ok(TradeOutcome {
+                    reply: text.clone(),
+                    notices: vec![(other, text)],
+                });
+            } else {
+                trade.updated_at = now;
+                return finish_trade(manager, group_name, index, trade);
+            }
+        },
+    };
+
+    trade.updated_at = now;
+    let mut outcome = TradeOutcome {
+        reply: format!(
+            "{reply}\n{}",
+            trade_summary(manager, &trade)
+        ),
+        notices: Vec::new(),
+    };
+    if let Some(text) = notify_other {
+        outcome.notices.push((
+            other,
+            format!(
+                "{text}\n{}",
+                trade_summary(manager, &trade)
+            ),
+        ));
+    }
+    store_trade(manager, group_name, index, trade);
+    Ok(outcome)
+}
+
+fn store_trade(
+    manager: &mut NapcatMessageManager,
+    group_name: &str,
+    index: usize,
+    trade: PlayerTrade,
+) {
+    if let Some(group) = manager.trpg_groups.get_mut(group_name) {
+        group.trades.trades[index] = trade;
+    }
+}
+
+/// Runs the swap for a trade both sides confirmed. On failure the trade stays open with both
+/// confirmations cleared so the players can fix their offers.
+fn finish_trade(
+    manager: &mut NapcatMessageManager,
+    group_name: &str,
+    index: usize,
+    mut trade: PlayerTrade,
+) -> Result<TradeOutcome, String> {
+    let result = swap_trade_goods(&mut manager.player_characters, &trade);
+    let text = match &result {
+        Ok(()) => {
+            trade.status = TradeStatus::Completed;
+            format!(
+                "交易#{}完成。\n{}",
+                trade.id,
+                trade_summary(manager, &trade)
+            )
+        },
+        Err(err) => {
+            trade.status = TradeStatus::Open;
+            trade.reset_confirmations();
+            trade.note = err.clone();
+            format!(
+                "交易#{}未能完成：{err}\n请调整报价后重新确认。",
+                trade.id
+            )
+        },
+    };
+    let notices = vec![
+        (trade.initiator.clone(), text.clone()),
+        (trade.partner.clone(), text.clone()),
+    ];
+    store_trade(manager, group_name, index, trade);
+    Ok(TradeOutcome {
+        reply: text,
+        notices,
+    })
+}
+
+/// Moves both offers at once. Works on copies of the two characters and only writes them back
+/// when every step succeeds, so a failed trade leaves both bags untouched.
+pub fn swap_trade_goods(
+    characters: &mut HashMap<String, PlayerCharacter>,
+    trade: &PlayerTrade,
+) -> Result<(), String> {
+    let mut initiator = characters
+        .get(&trade.initiator)
+        .cloned()
+        .ok_or_else(|| "发起方的角色卡不存在".to_owned())?;
+    let mut partner = characters
+        .get(&trade.partner)
+        .cloned()
+        .ok_or_else(|| "对方的角色卡不存在".to_owned())?;
+
+    let from_initiator = take_offer(&mut initiator, &trade.initiator_offer)?;
+    let from_partner = take_offer(&mut partner, &trade.partner_offer)?;
+    give_offer(
+        &mut partner,
+        from_initiator,
+        trade.initiator_offer.gold,
+    )?;
+    give_offer(
+        &mut initiator,
+        from_partner,
+        trade.partner_offer.gold,
+    )?;
+
+    characters.insert(trade.initiator.clone(), initiator);
+    characters.insert(trade.partner.clone(), partner);
+    Ok(())
+}
+
+fn take_offer(
+    character: &mut PlayerCharacter,
+    offer: &TradeOffer,
+) -> Result<Vec<InventoryItem>, String> {
+    if character.inventory.gold < offer.gold {
+        return Err(format!(
+            "{}的金币不足{}",
+            character.name, offer.gold
+        ));
+    }
+    character.inventory.gold -= offer.gold;
+    let mut taken = Vec::new();
+    for item in &offer.items {
+        let Some(items) = take_tradeable_items(character, &item.name, item.quantity) else {
+            return Err(format!(
+                "{}可交易的【{}】不足{}个",
+                character.name, item.name, item.quantity
+            ));
+        };
+        taken.extend(items);
+    }
+    Ok(taken)
+}
+
+fn give_offer(
+    character: &mut PlayerCharacter,
+    items: Vec<InventoryItem>,
+    gold: u32,
+) -> Result<(), String> {
+    for item in items {
+        if inventory_capacity_for(&character.inventory, &item) < item.stack {
+            return Err(format!(
+                "{}的背包放不下【{}】x{}",
+                character.name, item.name, item.stack
+            ));
+        }
+        add_item_to_inventory(&mut character.inventory, item);
+    }
+    character.inventory.gold = character.inventory.gold.saturating_add(gold);
+    Ok(())
+}
+
+fn expiry_notices(manager: &NapcatMessageManager, trade: &PlayerTrade) -> Vec<(String, String)> {
+    let text = format!(
+        "交易#{}（{}与{}）超过{}分钟无操作，已自动取消。",
+        trade.id,
+        trader_name(manager, &trade.initiator),
+        trader_name(manager, &trade.partner),
+        TRADE_TIMEOUT_SECS / 60
+    );
+    vec![
+        (trade.initiator.clone(), text.clone()),
+        (trade.partner.clone(), text),
+    ]
+}
+
+/// GM approval of a trade waiting for review; runs the swap and returns the notices for both
+/// players.
+pub fn approve_trade(
+    manager: &mut NapcatMessageManager,
+    group_name: &str,
+    trade_id: u64,
+    now: u64,
+) -> Result<TradeOutcome, String> {
+    let book = &manager
+        .trpg_groups
+        .get(group_name)
+        .ok_or_else(|| "TRPG组不存在".to_owned())?
+        .trades;
+    let index = book
+        .trade_index(trade_id)
+        .ok_or_else(|| format!("交易#{trade_id}不存在"))?;
+    let mut trade = book.trades[index].clone();
+    if trade.status != TradeStatus::AwaitingGm {
+        return Err(format!(
+            "交易#{trade_id}当前{}，无需审核",
+            trade.status.label()
+        ));
+    }
+    trade.updated_at = now;
+    finish_trade(manager, group_name, index, trade)
+}
+
+/// GM veto of any active trade; nothing changes hands.
+pub fn veto_trade(
+    manager: &mut NapcatMessageManager,
+    group_name: &str,
+    trade_id: u64,
+    reason: &str,
+    now: u64,
+) -> Result<TradeOutcome, String> {
+    let group = manager
+        .trpg_groups
+        .get_mut(group_name)
+        .ok_or_else(|| "TRPG组不存在".to_owned())?;
+    let index = group
+        .trades
+        .trade_index(trade_id)
+        .ok_or_else(|| format!("交易#{trade_id}不存在"))?;
+    let trade = &mut group.trades.trades[index];
+    if !trade.status.is_active() {
+        return Err(format!(
+            "交易#{trade_id}已{}",
+            trade.status.label()
+        ));
+    }
+    trade.status = TradeStatus::Vetoed;
+    trade.updated_at = now;
+    trade.note = reason.trim().to_owned();
+    let mut text = format!("交易#{trade_id}被GM否决，物品和金币未发生变动。");
+    if !trade.note.is_empty() {
+        text.push_str(&format!("理由：{}", trade.note));
+    }
+    Ok(TradeOutcome {
+        reply: text.clone(),
+        notices: vec![
+            (trade.initiator.clone(), text.clone()),
+            (trade.partner.clone(), text),
+        ],
+    })
+}
+
+/// Expires idle trades even when nobody types, and tells both players.
+pub(super) fn trade_expiry_system(
+    mut manager: ResMut<Persistent<NapcatMessageManager>>,
+    sender: Option<Res<NapcatIOSender>>,
+    mut automatic_replies: ResMut<NapcatAutomaticReplyRequests>,
+) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    if !manager
+        .trpg_groups
+        .values()
+        .any(|group| group.trades.trades.iter().any(|trade| trade.expired(now)))
+    {
+        return;
+    }
+    let mut expired = Vec::new();
+    for group in manager.trpg_groups.values_mut() {
+        expired.extend(group.trades.expire(now));
+    }
+    let notices = expired
+        .iter()
+        .flat_map(|trade| expiry_notices(&manager, trade))
+        .collect::<Vec<_>>();
+    if let Err(err) = manager.persist() {
+        eprintln!("failed to persist expired trades: {err}");
+    }
+    let Some(sender) = sender else {
+        return;
+    };
+    for (recipient, text) in notices {
+        if let Ok(user_id) = recipient.parse::<u64>() {
+            queue_private_text_response(
+                &sender,
+                &mut automatic_replies,
+                user_id,
+                text,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napcat::{
+        tests::{
+            empty_manager,
+            test_message_with_text,
+        },
+        NapcatMessageType,
+    };
+
+    fn trader(name: &str, gold: u32, items: Vec<InventoryItem>) -> PlayerCharacter {
+        let mut character = PlayerCharacter {
+            inited: true,
+            name: name.to_owned(),
+            ..Default::default()
+        };
+        character.inventory.gold = gold;
+        character.inventory.items = items;
+        character
+    }
+
+    fn sword() -> InventoryItem {
+        InventoryItem {
+            name: "长剑".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn trading_manager() -> NapcatMessageManager {
+        let mut manager = empty_manager();
+        manager.trpg_groups.insert("table".to_owned(), TrpgGroup {
+            players: vec!["10001".to_owned(), "10002".to_owned()],
+            ..Default::default()
+        });
+        manager.player_characters.insert(
+            "10001".to_owned(),
+            trader("艾拉", 0, vec![sword()]),
+        );
+        manager.player_characters.insert(
+            "10002".to_owned(),
+            trader("博恩", 80, Vec::new()),
+        );
+        manager
+    }
+
+    fn say(
+        manager: &mut NapcatMessageManager,
+        target_id: &str,
+        text: &str,
+        time: u64,
+    ) -> TradeOutcome {
+        let mut message = test_message_with_text(NapcatMessageType::Private, text);
+        message.data.time = time;
+        handle_trade_command(manager, target_id, &message).expect("trade command")
+    }
+
+    #[test]
+    fn both_confirmations_swap_items_and_gold() {
+        let mut manager = trading_manager();
+        let opened = say(&mut manager, "10001", ".交易 @博恩", 0);
+        assert_eq!(opened.notices[0].0, "10002");
+
+        say(
+            &mut manager,
+            "10001",
+            ".交易 放入 长剑",
+            1,
+        );
+        say(
+            &mut manager,
+            "10002",
+            ".交易 金币 50",
+            2,
+        );
+        say(&mut manager, "10001", ".交易 确认", 3);
+        assert_eq!(
+            manager.player_characters["10001"].inventory.gold,
+            0
+        );
+        let done = say(&mut manager, "10002", ".交易 确认", 4);
+        assert!(done.reply.contains("完成"));
+
+        assert_eq!(
+            manager.player_characters["10001"].inventory.gold,
+            50
+        );
+        assert_eq!(
+            manager.player_characters["10002"].inventory.gold,
+            30
+        );
+        assert_eq!(
+            manager.player_characters["10002"].inventory.items[0].name,
+            "长剑"
+        );
+        assert_eq!(
+            manager.trpg_groups["table"].trades.trades[0].status,
+            TradeStatus::Completed
+        );
+    }
+
+    #[test]
+    fn changing_an_offer_clears_confirmations() {
+        let mut manager = trading_manager();
+        say(&mut manager, "10001", ".交易 10002", 0);
+        say(&mut manager, "10001", ".交易 确认", 1);
+        say(
+            &mut manager,
+            "10002",
+            ".交易 金币 10",
+            2,
+        );
+        let trade = &manager.trpg_groups["table"].trades.trades[0];
+        assert!(!trade.initiator_offer.confirmed);
+        assert_eq!(trade.status, TradeStatus::Open);
+    }
+
+    #[test]
+    fn soulbound_items_cannot_be_offered() {
+        let mut manager = trading_manager();
+        manager
+            .player_characters
+            .get_mut("10001")
+            .unwrap()
+            .inventory
+            .items[0]
+            .soulbound = true;
+        say(&mut manager, "10001", ".交易 10002", 0);
+        let reply = say(
+            &mut manager,
+            "10001",
+            ".交易 放入 长剑",
+            1,
+        );
+        assert!(reply.reply.contains("灵魂绑定"));
+    }
+
+    #[test]
+    fn a_full_bag_aborts_the_whole_swap() {
+        let mut manager = trading_manager();
+        let partner = manager.player_characters.get_mut("10002").unwrap();
+        partner.inventory.bag_slots = 1;
+        partner.inventory.items.push(InventoryItem {
+            name: "石头".to_owned(),
+            ..Default::default()
+        });
+        say(&mut manager, "10001", ".交易 10002", 0);
+        say(
+            &mut manager,
+            "10001",
+            ".交易 放入 长剑",
+            1,
+        );
+        say(
+            &mut manager,
+            "10002",
+            ".交易 金币 50",
+            2,
+        );
+        say(&mut manager, "10001", ".交易 确认", 3);
+        let failed = say(&mut manager, "10002", ".交易 确认", 4);
+
+        assert!(failed.reply.contains("放不下"));
+        assert_eq!(
+            manager.player_characters["10001"].inventory.items.len(),
+            1
+        );
+        assert_eq!(
+            manager.player_characters["10002"].inventory.gold,
+            80
+        );
+        let trade = &manager.trpg_groups["table"].trades.trades[0];
+        assert_eq!(trade.status, TradeStatus::Open);
+        assert!(!trade.partner_offer.confirmed);
+    }
+
+    #[test]
+    fn gm_review_holds_the_swap_until_approved_or_vetoed() {
+        let mut manager = trading_manager();
+        manager
+            .trpg_groups
+            .get_mut("table")
+            .unwrap()
+            .trades
+            .require_gm_approval = true;
+        say(&mut manager, "10001", ".交易 10002", 0);
+        say(
+            &mut manager,
+            "10001",
+            ".交易 放入 长剑",
+            1,
+        );
+        say(&mut manager, "10001", ".交易 确认", 2);
+        say(&mut manager, "10002", ".交易 确认", 3);
+        assert_eq!(
+            manager.trpg_groups["table"].trades.trades[0].status,
+            TradeStatus::AwaitingGm
+        );
+        assert!(say(
+            &mut manager,
+            "10001",
+            ".交易 取回 长剑",
+            4
+        )
+        .reply
+        .contains("等待GM审核"));
+
+        let vetoed = veto_trade(&mut manager, "table", 1, "物价异常", 5).unwrap();
+        assert_eq!(vetoed.notices.len(), 2);
+        assert_eq!(
+            manager.player_characters["10001"].inventory.items.len(),
+            1
+        );
+        assert!(approve_trade(&mut manager, "table", 1, 6).is_err());
+    }
+
+    #[test]
+    fn idle_trades_expire() {
+        let mut manager = trading_manager();
+        say(&mut manager, "10001", ".交易 10002", 0);
+        let reply = say(
+            &mut manager,
+            "10002",
+            ".交易 确认",
+            TRADE_TIMEOUT_SECS,
+        );
+        assert!(reply.reply.contains("没有进行中的交易"));
+        assert_eq!(reply.notices.len(), 1);
+        assert_eq!(
+            manager.trpg_groups["table"].trades.trades[0].status,
+            TradeStatus::Expired
+        );
+    }
+}
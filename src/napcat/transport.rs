@@ -0,0 +1,1208 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::Duration,
+};
+
+use crossbeam_channel::Sender as CBSender;
+use futures_util::{
+    Sink,
+    SinkExt,
+    Stream,
+    StreamExt,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::Value;
+use sha1::{
+    Digest,
+    Sha1,
+};
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    sync::{
+        mpsc::{
+            self,
+            Receiver,
+        },
+        oneshot,
+    },
+    time::{
+        interval,
+        sleep,
+        timeout,
+        Instant,
+        MissedTickBehavior,
+    },
+};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    connect_async,
+    tungstenite::{
+        self,
+        handshake::server::{
+            ErrorResponse,
+            Request,
+            Response,
+        },
+        http::{
+            header::AUTHORIZATION,
+            StatusCode,
+        },
+        protocol::Message,
+    },
+    WebSocketStream,
+};
+
+use super::{
+    connection::{
+        fail_napcat_outbound,
+        NapcatStatusSender,
+    },
+    correlated_action_result,
+    correlated_outbound_message,
+    expire_pending_napcat_requests,
+    fail_pending_napcat_requests,
+    NapcatConnectionProfile,
+    NapcatConnectionStatus,
+    NapcatInboundMessage,
+    NapcatOutboundMessage,
+    NapcatSendResult,
+    PendingNapcatRequest,
+};
+
+const HTTP_MAX_HEADER_BYTES: usize = 16 * 1024;
+const HTTP_MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+/// Each HTTP action runs on its own task; keep it well under the pending-request TTL.
+const HTTP_ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+/// A posted event must arrive in full within this long, so a silent client cannot hold a
+/// connection task open forever.
+const HTTP_EVENT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// A reverse-websocket client that stalls mid-handshake is dropped after this long.
+const REVERSE_WEBSOCKET_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const REVERSE_WEBSOCKET_ACCEPT_CAPACITY: usize = 4;
+const HTTP_EVENT_CHANNEL_CAPACITY: usize = 256;
+
+type ReverseWebsocketStream = WebSocketStream<TcpStream>;
+
+/// How a connection profile talks to its OneBot implementation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NapcatTransport {
+    /// Dial out to the OneBot websocket server at `url`.
+    #[default]
+    ForwardWebsocket,
+    /// Listen on `listen_addr` and wait for OneBot to dial in.
+    ReverseWebsocket,
+    /// Receive events as HTTP POSTs on `listen_addr`; send actions to the HTTP API at `url`.
+    HttpPost,
+}
+
+impl NapcatTransport {
+    pub const ALL: [Self; 3] = [
+        Self::ForwardWebsocket,
+        Self::ReverseWebsocket,
+        Self::HttpPost,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::ForwardWebsocket => "正向WS",
+            Self::ReverseWebsocket => "反向WS",
+            Self::HttpPost => "HTTP POST",
+        }
+    }
+
+    pub fn listens(self) -> bool { self != Self::ForwardWebsocket }
+}
+
+/// Channels and settings shared by every transport of one connection profile.
+pub(super) struct NapcatConnectionTask {
+    pub profile: NapcatConnectionProfile,
+    pub client_to_game_sender: CBSender<NapcatInboundMessage>,
+    pub game_to_client_receiver: Receiver<NapcatOutboundMessage>,
+    pub send_result_sender: CBSender<NapcatSendResult>,
+    pub status_sender: NapcatStatusSender,
+    pub shutdown: oneshot::Receiver<()>,
+}
+
+enum SessionEnd {
+    Disconnected,
+    Stopped,
+    /// OneBot dialed in again while the old session still looked open; the new one takes over.
+    Replaced(ReverseWebsocketStream),
+}
+
+impl NapcatConnectionTask {
+    pub async fn run(mut self) {
+        match self.profile.transport {
+            NapcatTransport::ForwardWebsocket => self.run_forward_websocket().await,
+            NapcatTransport::ReverseWebsocket => self.run_reverse_websocket().await,
+            NapcatTransport::HttpPost => self.run_http_post().await,
+        }
+        self.close_queue();
+    }
+
+    fn report(&self, status: NapcatConnectionStatus) {
+        let _ = self.status_sender.send((self.profile.id.clone(), status));
+    }
+
+    fn forward_inbound(&self, message: Message) -> bool {
+        self.client_to_game_sender
+            .send(NapcatInboundMessage {
+                account_id: self.profile.id.clone(),
+                message,
+            })
+            .is_ok()
+    }
+
+    fn close_queue(&mut self) {
+        self.game_to_client_receiver.close();
+        while let Ok(outbound) = self.game_to_client_receiver.try_recv() {
+            fail_napcat_outbound(
+                &self.send_result_sender,
+                outbound,
+                "NapCat连接已停用".to_owned(),
+            );
+        }
+    }
+
+    /// Reports the failure and waits out the backoff; false when the profile was stopped meanwhile.
+    async fn back_off(&mut self, error: String, attempt: &mut u32) -> bool {
+        eprintln!(
+            "NapCat connection {} failed: {error}",
+            self.profile.display_name()
+        );
+        self.report(NapcatConnectionStatus::Disconnected(
+            error,
+        ));
+        let delay = self.profile.reconnect_delay(*attempt);
+        *attempt = attempt.saturating_add(1);
+        tokio::select! {
+            _ = sleep(delay) => true,
+            _ = &mut self.shutdown => false,
+        }
+    }
+
+    async fn run_forward_websocket(&mut self) {
+        let mut attempt = 0;
+        loop {
+            self.report(NapcatConnectionStatus::Connecting);
+            let connected = match self.profile.connection_request() {
+                Ok(request) => tokio::select! {
+                    connected = connect_async(request) => connected.map_err(|err| err.to_string()),
+                    _ = &mut self.shutdown => return,
+                },
+                Err(err) => Err(err),
+            };
+            let ws_stream = match connected {
+                Ok((ws_stream, _)) => ws_stream,
+                Err(err) => {
+                    if self.back_off(err, &mut attempt).await {
+                        continue;
+                    }
+                    return;
+                },
+            };
+
+            attempt = 0;
+            eprintln!(
+                "connected to NapCat websocket {} at {}",
+                self.profile.display_name(),
+                self.profile.url
+            );
+            if let SessionEnd::Stopped = self.run_websocket_session(ws_stream, None).await {
+                return;
+            }
+            if !self.back_off("连接已断开".to_owned(), &mut attempt).await {
+                return;
+            }
+        }
+    }
+
+    /// Binds `listen_addr`, retrying with backoff; `None` when the profile was stopped first.
+    async fn bind_listener(&mut self) -> Option<TcpListener> {
+        let mut attempt = 0;
+        loop {
+            self.report(NapcatConnectionStatus::Connecting);
+            let bound = match self.profile.listen_addr.trim().parse::<SocketAddr>() {
+                Ok(addr) => TcpListener::bind(addr)
+                    .await
+                    .map_err(|err| format!("无法监听{addr}：{err}")),
+                Err(err) => Err(format!("监听地址无效：{err}")),
+            };
+            match bound {
+                Ok(listener) => return Some(listener),
+                Err(err) => {
+                    if !self.back_off(err, &mut attempt).await {
+                        return None;
+                    }
+                },
+            }
+        }
+    }
+
+    async fn run_reverse_websocket(&mut self) {
+        let Some(listener) = self.bind_listener().await else {
+            return;
+        };
+        eprintln!(
+            "NapCat reverse websocket {} listening on {}",
+            self.profile.display_name(),
+            self.profile.listen_addr
+        );
+
+        // Clients are accepted and authenticated on their own tasks while a session runs, so a
+        // reconnecting OneBot replaces a stale session instead of waiting behind it.
+        let (accepted_sender, mut accepted) = mpsc::channel(REVERSE_WEBSOCKET_ACCEPT_CAPACITY);
+        let acceptor = tokio::spawn(accept_reverse_websockets(
+            listener,
+            self.profile.access_token.trim().to_owned(),
+            accepted_sender,
+        ));
+        let mut replacement = None;
+        loop {
+            let ws_stream = match replacement.take() {
+                Some(ws_stream) => ws_stream,
+                None => tokio::select! {
+                    ws_stream = accepted.recv() => match ws_stream {
+                        Some(ws_stream) => ws_stream,
+                        None => break,
+                    },
+                    _ = &mut self.shutdown => break,
+                },
+            };
+            eprintln!(
+                "NapCat reverse websocket {} started a session",
+                self.profile.display_name()
+            );
+            match self
+                .run_websocket_session(ws_stream, Some(&mut accepted))
+                .await
+            {
+                SessionEnd::Stopped => break,
+                SessionEnd::Replaced(ws_stream) => replacement = Some(ws_stream),
+                SessionEnd::Disconnected => self.report(NapcatConnectionStatus::Connecting),
+            }
+        }
+        acceptor.abort();
+    }
+
+    async fn run_http_post(&mut self) {
+        let client = match reqwest::Client::builder()
+            .timeout(HTTP_ACTION_TIMEOUT)
+            .build()
+        {
+            Ok(client) => client,
+            Err(err) => {
+                self.report(NapcatConnectionStatus::Disconnected(
+                    err.to_string(),
+                ));
+                let _ = (&mut self.shutdown).await;
+                return;
+            },
+        };
+        let Some(listener) = self.bind_listener().await else {
+            return;
+        };
+        eprintln!(
+            "NapCat HTTP POST receiver {} listening on {}",
+            self.profile.display_name(),
+            self.profile.listen_addr
+        );
+        self.report(NapcatConnectionStatus::Connected);
+
+        // Each connection is read on its own task so a slow client never stalls event intake or
+        // outbound actions; accepted bodies come back over this channel.
+        let (event_sender, mut event_receiver) = mpsc::channel(HTTP_EVENT_CHANNEL_CAPACITY);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let Ok((stream, peer)) = accepted else {
+                        continue;
+                    };
+                    tokio::spawn(serve_http_event_connection(
+                        stream,
+                        peer,
+                        self.profile.secret.trim().to_owned(),
+                        event_sender.clone(),
+                    ));
+                }
+                body = event_receiver.recv() => {
+                    let Some(body) = body else {
+                        continue;
+                    };
+                    if !self.forward_inbound(Message::Text(body.into())) {
+                        return;
+                    }
+                }
+                outbound = self.game_to_client_receiver.recv() => {
+                    let Some(outbound) = outbound else {
+                        return;
+                    };
+                    // Posted on its own task so a slow API call holds up neither events nor later
+                    // actions; the reply comes back with the events.
+                    tokio::spawn(send_http_action(
+                        client.clone(),
+                        self.profile.url.clone(),
+                        self.profile.access_token.trim().to_owned(),
+                        outbound,
+                        self.send_result_sender.clone(),
+                        event_sender.clone(),
+                    ));
+                }
+                _ = &mut self.shutdown => return,
+            }
+        }
+    }
+
+    /// Runs one websocket session until it ends. Reverse websockets pass `replacements`, the
+    /// clients that dialed in meanwhile; the first one ends this session and takes its place.
+    async fn run_websocket_session<S>(
+        &mut self,
+        ws_stream: S,
+        mut replacements: Option<&mut Receiver<ReverseWebsocketStream>>,
+    ) -> SessionEnd
+    where
+        S: Stream<Item = Result<Message, tungstenite::Error>>
+            + Sink<Message, Error = tungstenite::Error>
+            + Unpin,
+    {
+        self.report(NapcatConnectionStatus::Connected);
+        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+        let mut pending_requests = HashMap::<String, PendingNapcatRequest>::new();
+        let mut timeout_check = interval(Duration::from_secs(1));
+        timeout_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let end = loop {
+            tokio::select! {
+                msg = ws_receiver.next() => {
+                    match msg {
+                        Some(Ok(msg)) => {
+                            if msg.is_text() || msg.is_binary() {
+                                if let Some(result) = correlated_action_result(
+                                    &msg,
+                                    &mut pending_requests,
+                                ) {
+                                    let _ = self.send_result_sender.send(result);
+                                }
+                                if !self.forward_inbound(msg) {
+                                    fail_pending_napcat_requests(
+                                        &mut pending_requests,
+                                        &self.send_result_sender,
+                                        "NapCat响应接收端已关闭",
+                                    );
+                                    return SessionEnd::Stopped;
+                                }
+                            } else if msg.is_close() {
+                                break SessionEnd::Disconnected;
+                            }
+                        }
+                        Some(Err(err)) => {
+                            eprintln!("NapCat websocket receive error: {err}");
+                            break SessionEnd::Disconnected;
+                        },
+                        None => break SessionEnd::Disconnected,
+                    }
+                }
+                outbound = self.game_to_client_receiver.recv() => {
+                    let Some(outbound) = outbound else {
+                        fail_pending_napcat_requests(
+                            &mut pending_requests,
+                            &self.send_result_sender,
+                            "NapCat发送端已关闭",
+                        );
+                        return SessionEnd::Stopped;
+                    };
+                    let (message, echo) = match correlated_outbound_message(&outbound) {
+                        Ok(prepared) => prepared,
+                        Err(error) => {
+                            fail_napcat_outbound(&self.send_result_sender, outbound, error);
+                            continue;
+                        },
+                    };
+                    if pending_requests.contains_key(&echo) {
+                        fail_napcat_outbound(
+                            &self.send_result_sender,
+                            outbound,
+                            format!("NapCat请求echo重复：{echo}"),
+                        );
+                        continue;
+                    }
+                    if let Err(err) = ws_sender.send(message).await {
+                        let error = format!("NapCat websocket send error: {err}");
+                        eprintln!("{error}");
+                        fail_napcat_outbound(&self.send_result_sender, outbound, error.clone());
+                        fail_pending_napcat_requests(
+                            &mut pending_requests,
+                            &self.send_result_sender,
+                            &error,
+                        );
+                        break SessionEnd::Disconnected;
+                    }
+                    pending_requests.insert(echo, PendingNapcatRequest {
+                        request_id: outbound.request_id,
+                        target_id: outbound.target_id,
+                        sent_at: Instant::now(),
+                    });
+                    eprintln!("sent NapCat websocket message");
+                }
+                replacement = next_replacement(&mut replacements) => {
+                    eprintln!("NapCat reverse websocket reconnected, dropping the stale session");
+                    break SessionEnd::Replaced(replacement);
+                }
+                _ = timeout_check.tick() => {
+                    expire_pending_napcat_requests(
+                        &mut pending_requests,
+                        &self.send_result_sender,
+                        Instant::now(),
+                    );
+                }
+                _ = &mut self.shutdown => {
+                    fail_pending_napcat_requests(
+                        &mut pending_requests,
+                        &self.send_result_sender,
+                        "NapCat连接已停用",
+                    );
+                    return SessionEnd::Stopped;
+                }
+            }
+        };
+
+        fail_pending_napcat_requests(
+            &mut pending_requests,
+            &self.send_result_sender,
+            "NapCat websocket连接在响应前断开",
+        );
+        end
+    }
+}
+
+/// Posts one OneBot action to the HTTP API and hands the echo-tagged response to `replies`, which
+/// forwards it like a websocket reply.
+async fn send_http_action(
+    client: reqwest::Client,
+    url: String,
+    access_token: String,
+    outbound: NapcatOutboundMessage,
+    send_result_sender: CBSender<NapcatSendResult>,
+    replies: mpsc::Sender<String>,
+) {
+    let (message, echo) = match correlated_outbound_message(&outbound) {
+        Ok(prepared) => prepared,
+        Err(error) => {
+            fail_napcat_outbound(&send_result_sender, outbound, error);
+            return;
+        },
+    };
+    let request = match http_action_request(&url, &message) {
+        Ok(request) => request,
+        Err(error) => {
+            fail_napcat_outbound(&send_result_sender, outbound, error);
+            return;
+        },
+    };
+    let mut builder = client.post(request.0).json(&request.1);
+    if !access_token.is_empty() {
+        builder = builder.bearer_auth(access_token);
+    }
+    let response = match builder.send().await {
+        Ok(response) => response.json::<Value>().await,
+        Err(err) => Err(err),
+    };
+    let mut response = match response {
+        Ok(response) => response,
+        Err(err) => {
+            fail_napcat_outbound(
+                &send_result_sender,
+                outbound,
+                format!("NapCat HTTP请求失败：{err}"),
+            );
+            return;
+        },
+    };
+    if let Some(object) = response.as_object_mut() {
+        object.insert(
+            "echo".to_owned(),
+            Value::String(echo.clone()),
+        );
+    }
+    let reply = Message::Text(response.to_string().into());
+    let mut pending = HashMap::from([(echo, PendingNapcatRequest {
+        request_id: outbound.request_id,
+        target_id: outbound.target_id.clone(),
+        sent_at: Instant::now(),
+    })]);
+    match correlated_action_result(&reply, &mut pending) {
+        Some(result) => {
+            let _ = send_result_sender.send(result);
+        },
+        None => fail_napcat_outbound(
+            &send_result_sender,
+            outbound,
+            "NapCat HTTP响应不是有效的OneBot响应".to_owned(),
+        ),
+    }
+    let _ = replies.send(response.to_string()).await;
+}
+
+/// Accepts reverse-websocket clients for as long as the listener lives, checking each handshake on
+/// its own task so a stalled client never blocks the next one.
+async fn accept_reverse_websockets(
+    listener: TcpListener,
+    access_token: String,
+    accepted: mpsc::Sender<ReverseWebsocketStream>,
+) {
+    while !accepted.is_closed() {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                eprintln!("NapCat reverse websocket accept error: {err}");
+                continue;
+            },
+        };
+        let access_token = access_token.clone();
+        let accepted = accepted.clone();
+        tokio::spawn(async move {
+            // tungstenite fixes the handshake callback error type to a full HTTP response.
+            #[allow(clippy::result_large_err)]
+            let handshake = accept_hdr_async(
+                stream,
+                |request: &Request, response: Response| {
+                    if request_access_token_matches(request, &access_token) {
+                        Ok(response)
+                    } else {
+                        Err(unauthorized_response())
+                    }
+                },
+            );
+            match timeout(
+                REVERSE_WEBSOCKET_HANDSHAKE_TIMEOUT,
+                handshake,
+            )
+            .await
+            {
+                Ok(Ok(ws_stream)) => {
+                    eprintln!("NapCat reverse websocket accepted {peer}");
+                    let _ = accepted.send(ws_stream).await;
+                },
+                Ok(Err(err)) => {
+                    eprintln!("rejected NapCat reverse websocket from {peer}: {err}");
+                },
+                Err(_) => eprintln!("NapCat reverse websocket handshake from {peer} timed out"),
+            }
+        });
+    }
+}
+
+/// The next client waiting to replace the current session; never resolves without one.
+async fn next_replacement(
+    replacements: &mut Option<&mut Receiver<ReverseWebsocketStream>>,
+) -> ReverseWebsocketStream {
+    match replacements {
+        Some(receiver) => match receiver.recv().await {
+            Some(ws_stream) => ws_stream,
+            None => std::future::pending().await,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+/// OneBot reverse websockets send the token as a bearer header or an `access_token` query.
+fn request_access_token_matches(request: &Request, access_token: &str) -> bool {
+    if access_token.is_empty() {
+        return true;
+    }
+    let header_token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .strip_prefix("Bearer ")
+                .or_else(|| value.strip_prefix("Token "))
+                .unwrap_or(value)
+                .trim()
+        });
+    let query_token = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("access_token="))
+    });
+    [header_token, query_token]
+        .into_iter()
+        .flatten()
+        .any(|token| {
+            constant_time_eq(
+                token.as_bytes(),
+                access_token.as_bytes(),
+            )
+        })
+}
+
+fn unauthorized_response() -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some("invalid access_token".to_owned()));
+    *response.status_mut() = StatusCode::UNAUTHORIZED;
+    response
+}
+
+/// Splits an outbound `{action, params}` frame into the HTTP API endpoint and its JSON body.
+fn http_action_request(base_url: &str, message: &Message) -> Result<(String, Value), String> {
+    let payload = message
+        .to_text()
+        .ok()
+        .and_then(|text| serde_json::from_str::<Value>(text).ok())
+        .ok_or_else(|| "NapCat请求不是有效JSON".to_owned())?;
+    let action = payload
+        .get("action")
+        .and_then(Value::as_str)
+        .filter(|action| !action.trim().is_empty())
+        .ok_or_else(|| "NapCat请求缺少action".to_owned())?;
+    let params = payload
+        .get("params")
+        .cloned()
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    let base_url = base_url.trim().trim_end_matches('/');
+    if base_url.is_empty() {
+        return Err("HTTP API地址为空".to_owned());
+    }
+    Ok((format!("{base_url}/{action}"), params))
+}
+
+pub(super) struct HttpRequest {
+    pub method: String,
+    /// Request target as sent, e.g. `/api/messages?limit=20`.
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Reads one POSTed OneBot event and answers it. `Ok(None)` for requests that carry no event.
+async fn serve_http_event_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    secret: String,
+    events: mpsc::Sender<String>,
+) {
+    let event = match timeout(
+        HTTP_EVENT_REQUEST_TIMEOUT,
+        receive_http_event(&mut stream, &secret),
+    )
+    .await
+    {
+        Ok(event) => event,
+        Err(_) => {
+            write_http_status(&mut stream, "408 Request Timeout").await;
+            Err("请求超时".to_owned())
+        },
+    };
+    match event {
+        Ok(Some(body)) => {
+            let _ = events.send(body).await;
+        },
+        Ok(None) => {},
+        Err(err) => eprintln!("rejected NapCat HTTP event from {peer}: {err}"),
+    }
+}
+
+async fn receive_http_event(
+    stream: &mut TcpStream,
+    secret: &str,
+) -> Result<Option<String>, String> {
+    let request = match read_http_request(stream).await {
+        Ok(request) => request,
+        Err(err) => {
+            write_http_status(stream, "400 Bad Request").await;
+            return Err(err);
+        },
+    };
+    if !request.method.eq_ignore_ascii_case("POST") {
+        write_http_status(stream, "405 Method Not Allowed").await;
+        return Ok(None);
+    }
+    if !secret.is_empty()
+        && !http_signature_matches(
+            request.header("X-Signature"),
+            secret,
+            &request.body,
+        )
+    {
+        write_http_status(stream, "401 Unauthorized").await;
+        return Err("X-Signature校验失败".to_owned());
+    }
+    let body = String::from_utf8(request.body).map_err(|err| format!("事件不是UTF-8：{err}"));
+    let status = if body.is_ok() { "204 No Content" } else { "400 Bad Request" };
+    write_http_status(stream, status).await;
+    body.map(Some)
+}
+
+/// Parses a single HTTP/1.1 request.
+pub(super) async fn read_http_request(stream: &mut TcpStream) -> Result<HttpRequest, String> {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        if let Some(index) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break index;
+        }
+        if buffer.len() > HTTP_MAX_HEADER_BYTES {
+            return Err("HTTP请求头过长".to_owned());
+        }
+        let mut chunk = [0; 4096];
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|err| err.to_string())?;
+        if read == 0 {
+            return Err("HTTP请求不完整".to_owned());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = std::str::from_utf8(&buffer[..header_end]).map_err(|err| err.to_string())?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err("HTTP请求行无效".to_owned());
+    };
+    let (method, target) = (method.to_owned(), target.to_owned());
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| {
+            (
+                key.trim().to_owned(),
+                value.trim().to_owned(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let content_length = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Content-Length"))
+        .map(|(_, value)| value.parse::<usize>())
+        .transpose()
+        .map_err(|err| format!("Content-Length无效：{err}"))?
+        .unwrap_or(0);
+    if content_length > HTTP_MAX_BODY_BYTES {
+        return Err("HTTP请求体过大".to_owned());
+    }
+    let mut body = buffer.split_off(header_end + 4);
+    while body.len() < content_length {
+        let mut chunk = vec![0; content_length - body.len()];
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|err| err.to_string())?;
+        if read == 0 {
+            return Err("HTTP请求体不完整".to_owned());
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(content_length);
+    Ok(HttpRequest {
+        method,
+        target,
+        headers,
+        body,
+    })
+}
+
+async fn write_http_status(stream: &mut TcpStream, status: &str) {
+    write_http_response(stream, status, None, &[]).await;
+}
+
+/// Writes a complete response and closes the connection.
+pub(super) async fn write_http_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: Option<&str>,
+    body: &[u8],
+) {
+    let content_type = content_type
+        .map(|content_type| format!("Content-Type: {content_type}\r\n"))
+        .unwrap_or_default();
+    let head = format!(
+        "HTTP/1.1 {status}\r\n{content_type}Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(body).await;
+    let _ = stream.shutdown().await;
+}
+
+/// OneBot v11 signs HTTP POST bodies as `X-Signature: sha1=<hex hmac-sha1(secret, body)>`.
+fn http_signature_matches(signature: Option<&str>, secret: &str, body: &[u8]) -> bool {
+    let Some(signature) = signature.and_then(|signature| signature.trim().strip_prefix("sha1="))
+    else {
+        return false;
+    };
+    let expected = hex_lower(&hmac_sha1(secret.as_bytes(), body));
+    constant_time_eq(
+        signature.to_ascii_lowercase().as_bytes(),
+        expected.as_bytes(),
+    )
+}
+
+fn hmac_sha1(key: &[u8], data: &[u8]) -> [u8; 20] {
+    const BLOCK_SIZE: usize = 64;
+    let mut key_block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        key_block[..20].copy_from_slice(&Sha1::digest(key));
+    } else {
+        key_block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha1::new();
+    inner.update(key_block.map(|byte| byte ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha1::new();
+    outer.update(key_block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+fn hex_lower(bytes: &[u8]) -> String { bytes.iter().map(|byte| format!("{byte:02x}")).collect() }
+
+pub(super) fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len()
+        && left.iter().zip(right).fold(0, |diff, (left, right)| {
+            diff | (left ^ right)
+        }) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::{
+        unbounded,
+        Receiver as CBReceiver,
+    };
+    use serde_json::json;
+    use tokio::{
+        runtime::Builder,
+        sync::mpsc,
+        time::timeout,
+    };
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    use super::*;
+
+    struct MockConnection {
+        inbound: CBReceiver<NapcatInboundMessage>,
+        results: CBReceiver<NapcatSendResult>,
+        outbound: mpsc::Sender<NapcatOutboundMessage>,
+        _shutdown: oneshot::Sender<()>,
+    }
+
+    fn free_local_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn spawn_mock_connection(profile: NapcatConnectionProfile) -> MockConnection {
+        let (client_to_game_sender, inbound) = unbounded();
+        let (send_result_sender, results) = unbounded();
+        let (status_sender, _status_receiver) = unbounded();
+        let (outbound, game_to_client_receiver) = mpsc::channel(8);
+        let (shutdown_sender, shutdown) = oneshot::channel();
+        tokio::spawn(
+            NapcatConnectionTask {
+                profile,
+                client_to_game_sender,
+                game_to_client_receiver,
+                send_result_sender,
+                status_sender,
+                shutdown,
+            }
+            .run(),
+        );
+        MockConnection {
+            inbound,
+            results,
+            outbound,
+            _shutdown: shutdown_sender,
+        }
+    }
+
+    fn private_msg_outbound(request_id: u64) -> NapcatOutboundMessage {
+        NapcatOutboundMessage {
+            request_id,
+            target_id: "10002".to_owned(),
+            account_id: None,
+            message: Message::Text(
+                json!({
+                    "action": "send_private_msg",
+                    "params": { "user_id": 10002, "message": "hi" },
+                })
+                .to_string()
+                .into(),
+            ),
+        }
+    }
+
+    async fn recv_blocking<T: Send + 'static>(receiver: &CBReceiver<T>) -> T {
+        let receiver = receiver.clone();
+        tokio::task::spawn_blocking(move || receiver.recv_timeout(Duration::from_secs(5)))
+            .await
+            .unwrap()
+            .expect("timed out waiting for NapCat channel")
+    }
+
+    async fn connect_retrying(
+        request: Request,
+    ) -> Result<
+        tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>,
+        tungstenite::Error,
+    > {
+        let mut last_error = None;
+        for _ in 0..50 {
+            match connect_async(request.clone()).await {
+                Ok((ws_stream, _)) => return Ok(ws_stream),
+                Err(tungstenite::Error::Io(err)) => {
+                    last_error = Some(tungstenite::Error::Io(err));
+                    sleep(Duration::from_millis(20)).await;
+                },
+                Err(err) => return Err(err),
+            }
+        }
+        Err(last_error.unwrap())
+    }
+
+    async fn post_event(addr: &str, body: &str, signature: Option<String>) -> String {
+        let mut stream = None;
+        for _ in 0..50 {
+            if let Ok(connected) = TcpStream::connect(addr).await {
+                stream = Some(connected);
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        let mut stream = stream.expect("mock HTTP receiver did not start");
+        let signature = signature
+            .map(|signature| format!("X-Signature: {signature}\r\n"))
+            .unwrap_or_default();
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: {addr}\r\nContent-Type: application/json\r\n{signature}Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    fn run_async(test: impl std::future::Future<Output = ()>) {
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async { timeout(Duration::from_secs(20), test).await.unwrap() });
+    }
+
+    #[test]
+    fn hmac_sha1_matches_rfc_2202_vector() {
+        // RFC 2202 section 3, test case 2.
+        assert_eq!(
+            hex_lower(&hmac_sha1(
+                b"Jefe",
+                b"what do ya want for nothing?"
+            )),
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+        );
+        // Test case 6: a key longer than the block size is hashed first.
+        assert_eq!(
+            hex_lower(&hmac_sha1(
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "aa4ae5e15272d00e95705637ce8a3b55ed402112"
+        );
+        assert!(http_signature_matches(
+            Some("sha1=DE7C9B85B8B78AA6BC8A7A36F70A90701C9DB4D9"),
+            "key",
+            b"The quick brown fox jumps over the lazy dog"
+        ));
+        assert!(!http_signature_matches(
+            None, "key", b"body"
+        ));
+    }
+
+    #[test]
+    fn http_actions_post_params_to_the_action_endpoint() {
+        let (url, params) = http_action_request(
+            "http://127.0.0.1:3000/",
+            &private_msg_outbound(1).message,
+        )
+        .unwrap();
+
+        assert_eq!(
+            url,
+            "http://127.0.0.1:3000/send_private_msg"
+        );
+        assert_eq!(
+            params,
+            json!({ "user_id": 10002, "message": "hi" })
+        );
+    }
+
+    #[test]
+    fn reverse_websocket_feeds_events_and_correlates_actions() {
+        run_async(async {
+            let listen_addr = free_local_addr();
+            let connection = spawn_mock_connection(NapcatConnectionProfile {
+                transport: NapcatTransport::ReverseWebsocket,
+                listen_addr: listen_addr.clone(),
+                access_token: "token".to_owned(),
+                ..NapcatConnectionProfile::new("reverse")
+            });
+            let url = format!("ws://{listen_addr}/");
+
+            let rejected = connect_retrying(url.as_str().into_client_request().unwrap()).await;
+            assert!(rejected.is_err());
+
+            let mut request = url.as_str().into_client_request().unwrap();
+            request.headers_mut().insert(
+                AUTHORIZATION,
+                "Bearer token".parse().unwrap(),
+            );
+            let mut onebot = connect_retrying(request).await.unwrap();
+
+            let event = json!({ "post_type": "message", "raw_message": "hello" }).to_string();
+            onebot
+                .send(Message::Text(event.clone().into()))
+                .await
+                .unwrap();
+            let inbound = recv_blocking(&connection.inbound).await;
+            assert_eq!(inbound.account_id, "reverse");
+            assert_eq!(
+                inbound.message.to_text().unwrap(),
+                event
+            );
+
+            connection
+                .outbound
+                .send(private_msg_outbound(7))
+                .await
+                .unwrap();
+            let action = onebot.next().await.unwrap().unwrap();
+            let action = serde_json::from_str::<Value>(action.to_text().unwrap()).unwrap();
+            assert_eq!(action["action"], "send_private_msg");
+            onebot
+                .send(Message::Text(
+                    json!({ "status": "ok", "retcode": 0, "echo": action["echo"] })
+                        .to_string()
+                        .into(),
+                ))
+                .await
+                .unwrap();
+            let result = recv_blocking(&connection.results).await;
+            assert_eq!(result.request_id, 7);
+            assert_eq!(result.error, None);
+
+            // A reconnect while the first session still looks open takes over from it.
+            let mut request = format!("{url}?access_token=token")
+                .into_client_request()
+                .unwrap();
+            request.headers_mut().remove(AUTHORIZATION);
+            let mut reconnected = connect_retrying(request).await.unwrap();
+            reconnected
+                .send(Message::Text(event.clone().into()))
+                .await
+                .unwrap();
+            let inbound = recv_blocking(&connection.inbound).await;
+            assert_eq!(
+                inbound.message.to_text().unwrap(),
+                event
+            );
+            connection
+                .outbound
+                .send(private_msg_outbound(8))
+                .await
+                .unwrap();
+            let action = reconnected.next().await.unwrap().unwrap();
+            let action = serde_json::from_str::<Value>(action.to_text().unwrap()).unwrap();
+            assert_eq!(action["action"], "send_private_msg");
+            drop(onebot);
+        });
+    }
+
+    #[test]
+    fn http_post_receiver_verifies_signatures_and_sends_actions() {
+        run_async(async {
+            let api = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let api_addr = api.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (mut stream, _) = api.accept().await.unwrap();
+                let request = read_http_request(&mut stream).await.unwrap();
+                assert_eq!(
+                    request.header("Authorization"),
+                    Some("Bearer token")
+                );
+                let body = json!({ "status": "ok", "retcode": 0, "data": null }).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            });
+
+            let listen_addr = free_local_addr();
+            let connection = spawn_mock_connection(NapcatConnectionProfile {
+                transport: NapcatTransport::HttpPost,
+                url: format!("http://{api_addr}"),
+                listen_addr: listen_addr.clone(),
+                access_token: "token".to_owned(),
+                secret: "secret".to_owned(),
+                ..NapcatConnectionProfile::new("http")
+            });
+
+            let event = json!({ "post_type": "message", "raw_message": "hello" }).to_string();
+            let response = post_event(
+                &listen_addr,
+                &event,
+                Some("sha1=0000".to_owned()),
+            )
+            .await;
+            assert!(response.starts_with("HTTP/1.1 401"));
+            // A client that connects and never sends must not hold up other events.
+            let _silent = TcpStream::connect(&listen_addr).await.unwrap();
+            let signature = format!(
+                "sha1={}",
+                hex_lower(&hmac_sha1(b"secret", event.as_bytes()))
+            );
+            let response = post_event(&listen_addr, &event, Some(signature)).await;
+            assert!(response.starts_with("HTTP/1.1 204"));
+            let inbound = recv_blocking(&connection.inbound).await;
+            assert_eq!(inbound.account_id, "http");
+            assert_eq!(
+                inbound.message.to_text().unwrap(),
+                event
+            );
+
+            connection
+                .outbound
+                .send(private_msg_outbound(9))
+                .await
+                .unwrap();
+            let result = recv_blocking(&connection.results).await;
+            assert_eq!(result.request_id, 9);
+            assert_eq!(result.error, None);
+            let reply = recv_blocking(&connection.inbound).await;
+            assert!(reply.message.to_text().unwrap().contains("willowblossom:9"));
+        });
+    }
+}
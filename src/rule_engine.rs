@@ -10,6 +10,7 @@ use bevy_egui::{
     EguiContexts,
     EguiPrimaryContextPass,
 };
+use bevy_persistent::Persistent;
 use rand::RngExt;
 use serde::{
     Deserialize,
@@ -20,15 +21,37 @@ use serde_json::{
     Value,
 };
 
+use crate::napcat::NapcatMessageManager;
+
 pub struct RuleEnginePlugin;
 
 impl Plugin for RuleEnginePlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<RuleEngineState>().add_systems(
-            EguiPrimaryContextPass,
-            rule_engine_panel,
-        );
+        app.init_resource::<RuleEngineState>()
+            .add_systems(
+                Update,
+                sync_rule_engine_characters_system,
+            )
+            .add_systems(
+                EguiPrimaryContextPass,
+                rule_engine_panel,
+            );
+    }
+}
+
+/// Keeps the engine's characters in step with the stored player characters whenever the
+/// message manager changes, instead of relying on the character editors to push them.
+fn sync_rule_engine_characters_system(
+    manager: Option<Res<Persistent<NapcatMessageManager>>>,
+    mut state: ResMut<RuleEngineState>,
+) {
+    let Some(manager) = manager else {
+        return;
+    };
+    if !manager.is_changed() {
+        return;
     }
+    crate::ui::sync_rule_engine_characters(&manager, &mut state);
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -49,6 +72,25 @@ pub enum EventKind {
     DamageTaken,
     DamageDealt,
     SkillCast,
+    Check(CheckTrigger),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckTrigger {
+    Any,
+    Success,
+    Failure,
+    CriticalSuccess,
+    CriticalFailure,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckOutcome {
+    CriticalSuccess,
+    Success,
+    Failure,
+    CriticalFailure,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -739,6 +781,7 @@ fn append_legacy_buff_actions(
         .unwrap_or(&[]);
     let target = legacy_target_selector(buff.get("from"));
     let mut buff_effects = Vec::new();
+    let mut buff_conditions = Vec::new();
 
     for (index, effect) in effects.iter().enumerate() {
         let Some(effect_name) = legacy_string_value(effect) else {
@@ -775,7 +818,11 @@ fn append_legacy_buff_actions(
                 depth,
             ),
             _ => {
-                if let (Some(field), Some(value)) = (
+                if let Some(condition) = legacy_buff_condition(&effect_name) {
+                    if !buff_conditions.contains(&condition) {
+                        buff_conditions.push(condition);
+                    }
+                } else if let (Some(field), Some(value)) = (
                     legacy_buff_field(&effect_name),
                     legacy_buff_value(value, named_values),
                 ) {
@@ -785,7 +832,7 @@ fn append_legacy_buff_actions(
         }
     }
 
-    if !buff_effects.is_empty() {
+    if !buff_effects.is_empty() || !buff_conditions.is_empty() {
         actions.push(Action::GrantBuff {
             target,
             buff: RuleBuffTemplate {
@@ -795,9 +842,10 @@ fn append_legacy_buff_actions(
                 turns_remaining: legacy_buff_turns_with_named(buff.get("life"), named_values),
                 beneficial: legacy_bool_value(buff.get("benifit"))
                     .or_else(|| legacy_bool_value(buff.get("benefit")))
-                    .unwrap_or(true),
+                    .unwrap_or(!buff_conditions.iter().any(|condition| condition.harmful())),
                 effects: buff_effects,
                 tick_actions: Vec::new(),
+                conditions: buff_conditions,
             },
         });
     }
@@ -890,6 +938,7 @@ fn append_legacy_granted_buff_actions(
                     .unwrap_or(false),
                 effects: Vec::new(),
                 tick_actions,
+                conditions: Vec::new(),
             },
         });
     }
@@ -920,8 +969,18 @@ fn append_legacy_passive_buff(
             })
         })
         .collect::<Vec<_>>();
+    let mut buff_conditions = Vec::new();
+    for condition in effects
+        .iter()
+        .filter_map(legacy_string_value)
+        .filter_map(|effect_name| legacy_buff_condition(&effect_name))
+    {
+        if !buff_conditions.contains(&condition) {
+            buff_conditions.push(condition);
+        }
+    }
 
-    if buff_effects.is_empty() {
+    if buff_effects.is_empty() && buff_conditions.is_empty() {
         return;
     }
 
@@ -936,6 +995,7 @@ fn append_legacy_passive_buff(
             .unwrap_or(true),
         effects: buff_effects,
         tick_actions: Vec::new(),
+        conditions: buff_conditions,
     });
 }
 
@@ -1031,6 +1091,17 @@ fn legacy_buff_field(effect: &str) -> Option<BuffField> {
     }
 }
 
+fn legacy_buff_condition(effect: &str) -> Option<BuffCondition> {
+    match effect {
+        "stun" | "眩晕" => Some(BuffCondition::Stunned),
+        "silence" | "沉默" => Some(BuffCondition::Silenced),
+        "root" | "定身" => Some(BuffCondition::Rooted),
+        "taunt" | "嘲讽" => Some(BuffCondition::Taunted),
+        "stealth" | "invisible" | "隐身" => Some(BuffCondition::Invisible),
+        _ => None,
+    }
+}
+
 fn legacy_buff_value(value: Option<&Value>, named_values: &[(String, f32)]) -> Option<BuffValue> {
     let raw = legacy_string_value(value?)?;
     let raw = raw.trim();
@@ -1225,6 +1296,7 @@ pub struct RuleBuffTemplate {
     pub beneficial: bool,
     pub effects: Vec<BuffEffect>,
     pub tick_actions: Vec<BuffTickAction>,
+    pub conditions: Vec<BuffCondition>,
 }
 
 #[derive(Debug, Clone)]
@@ -1350,6 +1422,77 @@ pub struct BuffSpec {
     pub effects: Vec<BuffEffect>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub tick_actions: Vec<BuffTickAction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<BuffCondition>,
+}
+
+/// Control effects a buff can put on its holder. Unlike `BuffField`s they change what the holder
+/// may do in battle rather than a number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuffCondition {
+    /// Skips the holder's action.
+    Stunned,
+    /// Blocks skills; attacks and items still work.
+    Silenced,
+    /// Blocks movement in the scene.
+    Rooted,
+    /// Hostile single-target actions go to the buff's source.
+    Taunted,
+    /// Cannot be targeted by anyone else.
+    Invisible,
+}
+
+impl BuffCondition {
+    pub const ALL: [BuffCondition; 5] = [
+        BuffCondition::Stunned,
+        BuffCondition::Silenced,
+        BuffCondition::Rooted,
+        BuffCondition::Taunted,
+        BuffCondition::Invisible,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BuffCondition::Stunned => "眩晕",
+            BuffCondition::Silenced => "沉默",
+            BuffCondition::Rooted => "定身",
+            BuffCondition::Taunted => "嘲讽",
+            BuffCondition::Invisible => "隐身",
+        }
+    }
+
+    /// Whether the condition hinders its holder. Invisibility is the one a caster puts on
+    /// themselves.
+    pub fn harmful(self) -> bool { !matches!(self, BuffCondition::Invisible) }
+
+    fn keywords(self) -> &'static [&'static str] {
+        match self {
+            BuffCondition::Stunned => &["眩晕", "昏迷", "击晕"],
+            BuffCondition::Silenced => &["沉默", "禁言"],
+            BuffCondition::Rooted => &["定身", "禁锢", "缠绕"],
+            BuffCondition::Taunted => &["嘲讽"],
+            BuffCondition::Invisible => &["隐身", "隐形"],
+        }
+    }
+}
+
+/// Every condition granted by `buffs`, in `BuffCondition::ALL` order.
+pub fn buff_conditions(buffs: &[BuffSpec]) -> Vec<BuffCondition> {
+    BuffCondition::ALL
+        .into_iter()
+        .filter(|condition| buffs.iter().any(|buff| buff.conditions.contains(condition)))
+        .collect()
+}
+
+/// Source of the most recently granted buff carrying `condition`, e.g. who a taunted holder must
+/// attack.
+pub fn buff_condition_source(buffs: &[BuffSpec], condition: BuffCondition) -> Option<&str> {
+    buffs
+        .iter()
+        .rev()
+        .find(|buff| buff.conditions.contains(&condition))
+        .map(|buff| buff.source_id.as_str())
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -1442,6 +1585,11 @@ pub enum RuleEvent {
         source_id: String,
         target_ids: Vec<String>,
     },
+    Check {
+        actor_id: String,
+        outcome: CheckOutcome,
+        total: f32,
+    },
 }
 
 pub struct RuleEngine {
@@ -1586,6 +1734,10 @@ impl RuleEngineState {
     pub fn cast_skill(&mut self, source_id: &str, target_ids: impl IntoIterator<Item = String>) {
         self.engine.cast_skill(source_id, target_ids);
     }
+
+    pub fn resolve_check(&mut self, actor_id: &str, outcome: CheckOutcome, total: f32) {
+        self.engine.resolve_check(actor_id, outcome, total);
+    }
 }
 
 impl Character {
@@ -1710,6 +1862,47 @@ impl EventKind {
             EventKind::DamageTaken => "受到伤害",
             EventKind::DamageDealt => "造成伤害",
             EventKind::SkillCast => "释放技能",
+            EventKind::Check(trigger) => trigger.explain(),
+        }
+    }
+}
+
+impl CheckTrigger {
+    fn explain(self) -> &'static str {
+        match self {
+            CheckTrigger::Any => "进行检定",
+            CheckTrigger::Success => "检定成功",
+            CheckTrigger::Failure => "检定失败",
+            CheckTrigger::CriticalSuccess => "检定大成功",
+            CheckTrigger::CriticalFailure => "检定大失败",
+        }
+    }
+
+    fn matches(self, outcome: CheckOutcome) -> bool {
+        match self {
+            CheckTrigger::Any => true,
+            CheckTrigger::Success => outcome.is_success(),
+            CheckTrigger::Failure => !outcome.is_success(),
+            CheckTrigger::CriticalSuccess => outcome == CheckOutcome::CriticalSuccess,
+            CheckTrigger::CriticalFailure => outcome == CheckOutcome::CriticalFailure,
+        }
+    }
+}
+
+impl CheckOutcome {
+    pub fn is_success(self) -> bool {
+        matches!(
+            self,
+            CheckOutcome::CriticalSuccess | CheckOutcome::Success
+        )
+    }
+
+    pub fn explain(self) -> &'static str {
+        match self {
+            CheckOutcome::CriticalSuccess => "大成功",
+            CheckOutcome::Success => "成功",
+            CheckOutcome::Failure => "失败",
+            CheckOutcome::CriticalFailure => "大失败",
         }
     }
 }
@@ -1795,6 +1988,7 @@ impl RuleBuffTemplate {
             beneficial: self.beneficial,
             effects: self.effects.clone(),
             tick_actions: self.tick_actions.clone(),
+            conditions: self.conditions.clone(),
         }
     }
 }
@@ -1807,7 +2001,7 @@ impl ValueExpr {
                 RuleEvent::DamageTaken { amount, .. } | RuleEvent::DamageDealt { amount, .. } => {
                     *amount
                 },
-                RuleEvent::SkillCast { .. } => 0.0,
+                RuleEvent::SkillCast { .. } | RuleEvent::Check { .. } => 0.0,
             },
         }
     }
@@ -2386,6 +2580,14 @@ impl RuleEngine {
         });
     }
 
+    pub fn resolve_check(&mut self, actor_id: &str, outcome: CheckOutcome, total: f32) {
+        self.resolve_event(RuleEvent::Check {
+            actor_id: actor_id.to_owned(),
+            outcome,
+            total,
+        });
+    }
+
     fn queue_event(&mut self, event: RuleEvent) { self.event_queue.push_back(event); }
 
     fn resolve_queued_events(&mut self) {
@@ -2672,7 +2874,10 @@ pub fn parse_rule_with_named_args(
         return Err("规则必须以“每当”开头".to_owned());
     }
     let Some(trigger_end) = trigger_end_index(&normalized) else {
-        return Err("没有识别到触发条件；目前支持“受到伤害 / 造成伤害 / 释放技能”".to_owned());
+        return Err(
+            "没有识别到触发条件；目前支持“受到伤害 / 造成伤害 / 释放技能 / 检定成功或失败”"
+                .to_owned(),
+        );
     };
     if !normalized[trigger_end..].starts_with('时') {
         return Err(
@@ -2700,6 +2905,12 @@ fn is_active_skill_rule(text: &str) -> bool {
 }
 
 fn parse_trigger(text: &str) -> Result<Trigger, String> {
+    if let Some(trigger) = parse_check_trigger(text) {
+        return Ok(Trigger {
+            subject: parse_trigger_subject(text),
+            event: EventKind::Check(trigger),
+        });
+    }
     if text.contains("受到伤害") || text.contains("承受伤害") || text.contains("受伤害")
     {
         return Ok(Trigger {
@@ -2720,7 +2931,25 @@ fn parse_trigger(text: &str) -> Result<Trigger, String> {
         });
     }
 
-    Err("没有识别到触发条件；目前支持“受到伤害 / 造成伤害 / 释放技能”".to_owned())
+    Err("没有识别到触发条件；目前支持“受到伤害 / 造成伤害 / 释放技能 / 检定成功或失败”".to_owned())
+}
+
+fn parse_check_trigger(text: &str) -> Option<CheckTrigger> {
+    let trigger_clause = text.split(['，', ',', '；', ';']).next().unwrap_or(text);
+    if !trigger_clause.contains("检定") {
+        return None;
+    }
+    Some(if trigger_clause.contains("大成功") {
+        CheckTrigger::CriticalSuccess
+    } else if trigger_clause.contains("大失败") {
+        CheckTrigger::CriticalFailure
+    } else if trigger_clause.contains("成功") {
+        CheckTrigger::Success
+    } else if trigger_clause.contains("失败") {
+        CheckTrigger::Failure
+    } else {
+        CheckTrigger::Any
+    })
 }
 
 fn parse_trigger_subject(text: &str) -> ActorRef {
@@ -2821,6 +3050,7 @@ fn parse_grant_buff_action(clause: &str) -> Option<Action> {
         ActorRef::Target
     };
 
+    let conditions = parse_buff_conditions(&name);
     Some(Action::GrantBuff {
         target: parse_target_selector(clause, default_target),
         buff: RuleBuffTemplate {
@@ -2831,10 +3061,18 @@ fn parse_grant_buff_action(clause: &str) -> Option<Action> {
             beneficial: parse_buff_beneficial(clause),
             effects: parse_buff_effects(clause),
             tick_actions: Vec::new(),
+            conditions,
         },
     })
 }
 
+fn parse_buff_conditions(name: &str) -> Vec<BuffCondition> {
+    BuffCondition::ALL
+        .into_iter()
+        .filter(|condition| condition.keywords().iter().any(|word| name.contains(word)))
+        .collect()
+}
+
 fn grant_buff_words() -> [&'static str; 5] { ["给予", "施加", "附加", "添加", "获得"] }
 
 fn parse_buff_name(clause: &str) -> Option<String> {
@@ -2915,7 +3153,8 @@ fn parse_buff_kind(clause: &str) -> BuffKind {
 
 fn parse_buff_beneficial(clause: &str) -> bool {
     ![
-        "负面", "减益", "诅咒", "疾病", "流血", "中毒", "虚弱", "脆弱",
+        "负面", "减益", "诅咒", "疾病", "流血", "中毒", "虚弱", "脆弱", "眩晕", "昏迷", "击晕",
+        "沉默", "禁言", "定身", "禁锢", "缠绕", "嘲讽",
     ]
     .iter()
     .any(|word| clause.contains(word))
@@ -3092,9 +3331,18 @@ fn trigger_end_index(text: &str) -> Option<usize> {
     ]
     .iter()
     .filter_map(|event| text.find(event).map(|index| index + event.len()))
+    .chain(check_trigger_end_index(text))
     .min()
 }
 
+fn check_trigger_end_index(text: &str) -> Option<usize> {
+    let start = text.find("检定")?;
+    ["检定大成功", "检定大失败", "检定成功", "检定失败", "检定"]
+        .iter()
+        .find(|phrase| text[start..].starts_with(**phrase))
+        .map(|phrase| start + phrase.len())
+}
+
 fn contains_action_word(text: &str) -> bool {
     [
         "回复", "恢复", "治疗", "造成", "给予", "施加", "附加", "添加", "获得",
@@ -3268,6 +3516,9 @@ fn rule_matches(rule: &Rule, event: &RuleEvent) -> bool {
             EventKind::SkillCast,
             RuleEvent::SkillCast { .. }
         )
+    ) || matches!(
+        (&rule.ast.trigger.event, event),
+        (EventKind::Check(trigger), RuleEvent::Check { outcome, .. }) if trigger.matches(*outcome)
     )
 }
 
@@ -3277,6 +3528,7 @@ fn event_primary_actor_id(event: &RuleEvent) -> Option<&str> {
         RuleEvent::DamageDealt { source_id, .. } | RuleEvent::SkillCast { source_id, .. } => {
             Some(source_id)
         },
+        RuleEvent::Check { actor_id, .. } => Some(actor_id),
     }
 }
 
@@ -3309,6 +3561,7 @@ fn event_source_id(event: &RuleEvent) -> Option<&str> {
         RuleEvent::DamageTaken { source_id, .. }
         | RuleEvent::DamageDealt { source_id, .. }
         | RuleEvent::SkillCast { source_id, .. } => Some(source_id),
+        RuleEvent::Check { actor_id, .. } => Some(actor_id),
     }
 }
 
@@ -3318,6 +3571,7 @@ fn event_target_id(event: &RuleEvent) -> Option<&str> {
             Some(target_id)
         },
         RuleEvent::SkillCast { target_ids, .. } => target_ids.first().map(String::as_str),
+        RuleEvent::Check { actor_id, .. } => Some(actor_id),
     }
 }
 
@@ -3346,6 +3600,13 @@ fn rule_event_name(event: &RuleEvent) -> String {
             )
         },
         RuleEvent::SkillCast { .. } => "释放技能".to_owned(),
+        RuleEvent::Check { outcome, total, .. } => {
+            format!(
+                "检定{}（{}）",
+                outcome.explain(),
+                format_number(*total)
+            )
+        },
     }
 }
 
@@ -3379,6 +3640,7 @@ fn sousas_claw_followup_buff(source_id: &str, amount: f32) -> BuffSpec {
             amount: amount.max(0.0),
             damage_type: DamageType::Magical,
         }],
+        conditions: Vec::new(),
     }
 }
 
@@ -3503,7 +3765,9 @@ fn rule_words(ui: &mut egui::Ui) {
             ui.end_row();
 
             ui.label("触发事件");
-            ui.label("受到伤害, 造成伤害, 释放技能");
+            ui.label(
+                "受到伤害, 造成伤害, 释放技能, 检定, 检定成功, 检定失败, 检定大成功, 检定大失败",
+            );
             ui.end_row();
 
             ui.label("动作标记");
@@ -3763,6 +4027,7 @@ mod tests {
                     value: BuffValue::Set(0.5),
                 }],
                 tick_actions: Vec::new(),
+                conditions: Vec::new(),
             },
         }]);
     }
@@ -3844,10 +4109,38 @@ mod tests {
                     },
                 ],
                 tick_actions: Vec::new(),
+                conditions: Vec::new(),
             },
         }]);
     }
 
+    #[test]
+    fn legacy_moonberry_condition_buffs_default_beneficial_per_condition() {
+        let beneficial = |effect: &str| {
+            let ast = legacy_moonberry_buff_machine_skill_cast_rule(
+                &format!(
+                    r#"{{"技能释放":[{{"name":"状态","life":2,"effect":["{effect}"],"type":0,"from":"自己"}}]}}"#
+                ),
+                &[],
+                None,
+            )
+            .unwrap();
+            let [Action::GrantBuff { buff, .. }] = ast.actions.as_slice() else {
+                panic!(
+                    "expected one granted buff, got {:?}",
+                    ast.actions
+                );
+            };
+            buff.beneficial
+        };
+
+        assert!(beneficial("隐身"));
+        assert!(beneficial("stealth"));
+        for effect in ["眩晕", "沉默", "定身", "嘲讽"] {
+            assert!(!beneficial(effect), "{effect}");
+        }
+    }
+
     #[test]
     fn legacy_moonberry_buff_machine_converts_passive_basic_buffs() {
         let buffs = legacy_moonberry_buff_machine_passive_buffs(
@@ -3878,6 +4171,7 @@ mod tests {
                 },
             ],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         }]);
     }
 
@@ -3915,6 +4209,7 @@ mod tests {
                     value: BuffValue::Add(0.25),
                 }],
                 tick_actions: Vec::new(),
+                conditions: Vec::new(),
             },
         }]);
     }
@@ -3953,6 +4248,7 @@ mod tests {
                     amount: 3.0,
                     damage_type: DamageType::Magical,
                 }],
+                conditions: Vec::new(),
             },
         }]);
 
@@ -4026,6 +4322,7 @@ mod tests {
                     value: BuffValue::Add(4.0),
                 }],
                 tick_actions: Vec::new(),
+                conditions: Vec::new(),
             },
         }]);
     }
@@ -4081,6 +4378,7 @@ mod tests {
                 value: BuffValue::Add(3.0),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         }]);
     }
 
@@ -4102,6 +4400,7 @@ mod tests {
                 beneficial: true,
                 effects: Vec::new(),
                 tick_actions: Vec::new(),
+                conditions: Vec::new(),
             },
         }]);
         assert_eq!(
@@ -4110,6 +4409,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parses_control_condition_buff_as_harmful() {
+        let ast = parse_rule("主动使用给予目标2回合眩晕状态").unwrap();
+
+        assert_eq!(ast.actions, vec![Action::GrantBuff {
+            target: TargetSelector::single(ActorRef::Target),
+            buff: RuleBuffTemplate {
+                name: "眩晕".to_owned(),
+                kind: BuffKind::None,
+                priority: 0,
+                turns_remaining: 2,
+                beneficial: false,
+                effects: Vec::new(),
+                tick_actions: Vec::new(),
+                conditions: vec![BuffCondition::Stunned],
+            },
+        }]);
+        let Action::GrantBuff { buff, .. } = &ast.actions[0] else {
+            unreachable!();
+        };
+        assert_eq!(
+            buff_conditions(&[buff.to_buff_spec("caster")]),
+            vec![BuffCondition::Stunned]
+        );
+    }
+
     #[test]
     fn parses_grant_buff_rule_with_typed_effects() {
         let ast = parse_rule("每当自己受到伤害时，给予自己2回合守护状态使承伤设为0.5").unwrap();
@@ -4127,6 +4452,7 @@ mod tests {
                     value: BuffValue::Set(0.5),
                 }],
                 tick_actions: Vec::new(),
+                conditions: Vec::new(),
             },
         }]);
     }
@@ -4645,6 +4971,7 @@ mod tests {
                 value: BuffValue::AddPercent(-25.0),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         }];
         engine.add_character(source);
         engine.add_character(Character::new("target", "目标", 20.0));
@@ -4906,6 +5233,7 @@ mod tests {
                 value: BuffValue::Set(0.5),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         }));
 
         engine.attack(
@@ -4953,6 +5281,7 @@ mod tests {
                 value: BuffValue::Add(-3.0),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         });
         engine.give_buff("alice", BuffSpec {
             name: "Bless".to_owned(),
@@ -4966,6 +5295,7 @@ mod tests {
                 value: BuffValue::Add(5.0),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         });
 
         let alice = engine.characters.get("alice").unwrap();
@@ -4996,6 +5326,7 @@ mod tests {
                 value: BuffValue::AddPercent(20.0),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         });
 
         let alice = engine.characters.get("alice").unwrap();
@@ -5020,6 +5351,7 @@ mod tests {
                 value: BuffValue::Set(0.5),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         }));
 
         engine.advance_turn();
@@ -5055,6 +5387,7 @@ mod tests {
                 value: BuffValue::Set(0.5),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         }];
 
         engine.replace_buffs_for_target("alice", buffs.clone());
@@ -5091,6 +5424,7 @@ mod tests {
                 value: BuffValue::AddPercent(100.0),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         }];
 
         engine.replace_buffs_for_target("alice", buffs.clone());
@@ -5105,4 +5439,68 @@ mod tests {
             20.0
         );
     }
+
+    #[test]
+    fn parses_check_outcome_triggers() {
+        let cases = [
+            (
+                "每当自己检定大成功时，回复2点生命值",
+                CheckTrigger::CriticalSuccess,
+            ),
+            (
+                "每当自己检定大失败时，回复2点生命值",
+                CheckTrigger::CriticalFailure,
+            ),
+            (
+                "每当自己检定成功时，回复2点生命值",
+                CheckTrigger::Success,
+            ),
+            (
+                "每当自己检定失败时，回复2点生命值",
+                CheckTrigger::Failure,
+            ),
+            (
+                "每当自己进行检定时，回复2点生命值",
+                CheckTrigger::Any,
+            ),
+        ];
+        for (text, expected) in cases {
+            let ast = parse_rule(text).unwrap();
+            assert_eq!(
+                ast.trigger.event,
+                EventKind::Check(expected),
+                "{text}"
+            );
+            assert_eq!(ast.trigger.subject, ActorRef::SelfActor);
+        }
+    }
+
+    #[test]
+    fn check_rule_runs_only_for_matching_outcome() {
+        let mut engine = RuleEngine::default();
+        let mut alice = Character::new("alice", "自己", 10.0);
+        alice.hp = 5.0;
+        engine.add_character(alice);
+        engine.add_rule(
+            "alice",
+            parse_rule("每当自己检定成功时，回复2点生命值").unwrap(),
+        );
+
+        engine.resolve_check("alice", CheckOutcome::Failure, 8.0);
+        assert_eq!(
+            engine.characters.get("alice").unwrap().hp,
+            5.0
+        );
+
+        engine.resolve_check(
+            "alice",
+            CheckOutcome::CriticalSuccess,
+            25.0,
+        );
+        assert_eq!(
+            engine.characters.get("alice").unwrap().hp,
+            7.0
+        );
+        assert!(engine.log.iter().any(|line| line.contains("检定大成功")));
+    }
 }
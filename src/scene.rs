@@ -504,6 +504,39 @@ pub struct SceneCaptureRequests {
 #[derive(Resource, Default)]
 pub struct SceneCharacterPositions {
     pub positions: HashMap<String, Vec3>,
+    /// Cover between standees keyed by `(attacker, target)`, measured by the voxel scene.
+    pub cover: HashMap<(String, String), SceneCover>,
+}
+
+impl SceneCharacterPositions {
+    pub fn cover_between(&self, attacker_id: &str, target_id: &str) -> SceneCover {
+        self.cover
+            .get(&(
+                attacker_id.to_owned(),
+                target_id.to_owned(),
+            ))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// How much of a target the voxel scene hides from an attacker: some or all sight lines blocked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SceneCover {
+    #[default]
+    None,
+    Partial,
+    Full,
+}
+
+impl SceneCover {
+    pub fn label(self) -> &'static str {
+        match self {
+            SceneCover::None => "无掩护",
+            SceneCover::Partial => "部分掩护",
+            SceneCover::Full => "完全掩护",
+        }
+    }
 }
 
 #[derive(Resource, Default)]
@@ -7426,6 +7459,7 @@ fn scene_capture_request_system(
                     if let Err(err) = napcat_sender.0.try_send(NapcatOutboundMessage {
                         request_id: pending.request_id,
                         target_id: pending.user_id.to_string(),
+                        account_id: None,
                         message,
                     }) {
                         eprintln!("failed to queue scene capture image: {err}");
@@ -8648,6 +8682,7 @@ mod tests {
             skill_pool: Vec::new(),
             item_pool: Vec::new(),
             unit_pool: HashMap::default(),
+            chat_target_accounts: HashMap::default(),
         }
     }
 
@@ -8,10 +8,15 @@ use bevy_egui::{
     egui,
     input::EguiContextImeState,
 };
-use serde_json::json;
+use serde_json::{
+    json,
+    Value,
+};
 use tungstenite::Message;
 
 use crate::napcat::{
+    outbound_segments_json,
+    parse_outbound_segments,
     NapcatIOSender,
     NapcatOutboundMessage,
     NapcatSendResult,
@@ -59,6 +64,7 @@ mod tests {
             request_id: outbound.request_id,
             target_id: "42".to_owned(),
             error: None,
+            delivery_unknown: false,
         }]);
 
         assert_eq!(sent, vec![ChatInputSendCompletion {
@@ -69,6 +75,45 @@ mod tests {
         }]);
     }
 
+    #[test]
+    fn queue_forward_send_uses_forward_actions_per_target() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+        let sender = NapcatIOSender(sender);
+        let mut ime = ImeManager::default();
+        let nodes = vec![json!({
+            "type": "node",
+            "data": { "user_id": "2", "nickname": "甲", "content": [] }
+        })];
+
+        assert!(ime
+            .queue_forward_send(
+                "forward:99",
+                "[合并转发] 1条消息",
+                Vec::new(),
+                &sender,
+                vec![NapcatSendTarget::Group(99),]
+            )
+            .is_err());
+        ime.queue_forward_send(
+            "forward:99",
+            "[合并转发] 1条消息",
+            nodes,
+            &sender,
+            vec![NapcatSendTarget::Group(99), NapcatSendTarget::Private(42)],
+        )
+        .unwrap();
+
+        let group = receiver.try_recv().unwrap().message.to_string();
+        let private = receiver.try_recv().unwrap();
+        assert!(group.contains("send_group_forward_msg"));
+        assert!(group.contains("\"messages\""));
+        assert!(private
+            .message
+            .to_string()
+            .contains("send_private_forward_msg"));
+        assert_eq!(private.target_id, "forward:99");
+    }
+
     #[test]
     fn batch_send_preserves_partial_success_and_error_after_out_of_order_failure() {
         let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
@@ -90,6 +135,7 @@ mod tests {
                 request_id: first.request_id,
                 target_id: "broadcast".to_owned(),
                 error: Some("recipient rejected message".to_owned()),
+                delivery_unknown: false,
             }])
             .is_empty());
 
@@ -97,6 +143,7 @@ mod tests {
             request_id: second.request_id,
             target_id: "broadcast".to_owned(),
             error: None,
+            delivery_unknown: false,
         }]);
 
         assert_eq!(completed, vec![
@@ -133,6 +180,7 @@ mod tests {
             request_id: queued.request_id,
             target_id: "broadcast".to_owned(),
             error: None,
+            delivery_unknown: false,
         }]);
 
         assert_eq!(completed, vec![
@@ -177,11 +225,13 @@ mod tests {
                 request_id: first.request_id,
                 target_id: "broadcast".to_owned(),
                 error: None,
+                delivery_unknown: false,
             },
             NapcatSendResult {
                 request_id: second.request_id,
                 target_id: "broadcast".to_owned(),
                 error: Some("recipient rejected message".to_owned()),
+                delivery_unknown: false,
             },
         ]);
         assert_eq!(completed[0].successful_targets, vec![
@@ -205,6 +255,7 @@ mod tests {
             request_id: retry.request_id,
             target_id: "broadcast".to_owned(),
             error: None,
+            delivery_unknown: false,
         }]);
         assert_eq!(completed, vec![
             ChatInputSendCompletion {
@@ -236,11 +287,13 @@ mod tests {
                 request_id: first.request_id,
                 target_id: "broadcast".to_owned(),
                 error: None,
+                delivery_unknown: false,
             },
             NapcatSendResult {
                 request_id: second.request_id,
                 target_id: "broadcast".to_owned(),
                 error: Some("recipient rejected message".to_owned()),
+                delivery_unknown: false,
             },
         ]);
 
@@ -341,14 +394,7 @@ impl ImeManager {
             });
         }
 
-        let send_state = self.send_states.entry(target_id.to_owned()).or_default();
-        if send_state.pending_requests.is_empty() {
-            if let Some(error) = &send_state.error {
-                ui.colored_label(egui::Color32::LIGHT_RED, error);
-            }
-        } else {
-            ui.label("发送中...");
-        }
+        self.send_status_ui(ui, target_id);
 
         if send_on_enter {
             if self
@@ -372,6 +418,19 @@ impl ImeManager {
         teo
     }
 
+    pub fn send_status_ui(&self, ui: &mut egui::Ui, input_id: &str) {
+        let Some(send_state) = self.send_states.get(input_id) else {
+            return;
+        };
+        if send_state.pending_requests.is_empty() {
+            if let Some(error) = &send_state.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+        } else {
+            ui.label("发送中...");
+        }
+    }
+
     pub fn queue_text_send(
         &mut self,
         target_id: &str,
@@ -384,6 +443,70 @@ impl ImeManager {
             return Ok(());
         }
 
+        let segments = outbound_segments_json(&parse_outbound_segments(&message_text));
+        self.queue_action_send(
+            target_id,
+            message_text,
+            sender,
+            targets,
+            |target| match target {
+                NapcatSendTarget::Private(user_id) => json!({
+                    "action": "send_private_msg",
+                    "params": { "user_id": user_id, "message": segments }
+                }),
+                NapcatSendTarget::Group(group_id) => json!({
+                    "action": "send_group_msg",
+                    "params": { "group_id": group_id, "message": segments }
+                }),
+            },
+        )
+    }
+
+    /// Sends `nodes` as one merged-forward message per target. `summary` stands in for the
+    /// message text in send state and completions.
+    pub fn queue_forward_send(
+        &mut self,
+        input_id: &str,
+        summary: impl AsRef<str>,
+        nodes: Vec<Value>,
+        sender: &NapcatIOSender,
+        targets: Vec<NapcatSendTarget>,
+    ) -> Result<(), String> {
+        if nodes.is_empty() {
+            let error = "所选范围内没有该受众可见的消息".to_owned();
+            self.send_states
+                .entry(input_id.to_owned())
+                .or_default()
+                .error = Some(error.clone());
+            return Err(error);
+        }
+
+        self.queue_action_send(
+            input_id,
+            summary.as_ref().trim().to_owned(),
+            sender,
+            targets,
+            |target| match target {
+                NapcatSendTarget::Private(user_id) => json!({
+                    "action": "send_private_forward_msg",
+                    "params": { "user_id": user_id, "messages": nodes }
+                }),
+                NapcatSendTarget::Group(group_id) => json!({
+                    "action": "send_group_forward_msg",
+                    "params": { "group_id": group_id, "messages": nodes }
+                }),
+            },
+        )
+    }
+
+    fn queue_action_send(
+        &mut self,
+        target_id: &str,
+        message_text: String,
+        sender: &NapcatIOSender,
+        targets: Vec<NapcatSendTarget>,
+        action: impl Fn(&NapcatSendTarget) -> Value,
+    ) -> Result<(), String> {
         if targets.is_empty() {
             let error = "没有可发送的NapCat目标".to_owned();
             self.send_states
@@ -427,34 +550,14 @@ impl ImeManager {
         let mut pending_requests = Vec::new();
         let mut error = None;
         for target in targets {
-            let (action, id_key, id) = match &target {
-                NapcatSendTarget::Private(user_id) => ("send_private_msg", "user_id", user_id),
-                NapcatSendTarget::Group(group_id) => ("send_group_msg", "group_id", group_id),
-            };
             let request_id = self.next_send_request_id;
             self.next_send_request_id += 1;
-            let message = Message::Text(
-                json!({
-                    "action": action,
-                    "params": {
-                        id_key: id,
-                        "message": [
-                            {
-                                "type": "text",
-                                "data": {
-                                    "text": message_text
-                                }
-                            }
-                        ]
-                    }
-                })
-                .to_string()
-                .into(),
-            );
+            let message = Message::Text(action(&target).to_string().into());
 
             if let Err(err) = sender.0.try_send(NapcatOutboundMessage {
                 request_id,
                 target_id: target_id.to_owned(),
+                account_id: None,
                 message,
             }) {
                 error = Some(format!(
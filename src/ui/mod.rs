@@ -84,6 +84,7 @@ const GROUP_MEMBER_WINDOW_TOP_GAP: f32 = 58.0;
 const GROUP_MEMBER_WINDOW_BOTTOM_GAP: f32 = GROUP_BROADCAST_INPUT_HEIGHT + 7.0;
 const GROUP_MEMBER_WINDOW_MAX_SIZE: Vec2 = Vec2::new(520.0, 620.0);
 const CHAT_AUTO_SCROLL_THRESHOLD: f32 = 48.0;
+const CHAT_REPLY_QUOTE_MAX_CHARS: usize = 40;
 const CHAT_IMAGE_MAX_SIZE: Vec2 = Vec2::new(220.0, 220.0);
 const CHARACTER_WINDOW_DEFAULT_WIDTH: f32 = 360.0;
 const CHARACTER_WINDOW_MIN_WIDTH: f32 = 320.0;
@@ -658,7 +659,11 @@ use crate::{
         DeepseekSummaryBlock,
         DEEPSEEK_SUMMARY_EXPORT_VERSION,
     },
+    dice::parse_dice_expression,
     napcat::{
+        add_item_to_inventory,
+        approve_staged_loot,
+        approve_trade,
         character_chaos_output_variance,
         character_damage_attribute_multiplier,
         character_damage_dealt_talent_buffs,
@@ -677,22 +682,39 @@ use crate::{
         character_physical_damage_lifesteal,
         character_spell_range_multiplier,
         character_wounded_healing_dealt_modifier,
+        consume_character_item,
+        discard_staged_loot,
         dying_target_healing_multiplier,
+        equipment_slot_label,
+        equipment_slot_options,
+        escape_cq_param,
+        file_display_name,
+        forward_export_nodes,
         grant_character_experience,
+        inventory_quality_label,
         is_scene_capture_command_text,
+        json_card_prompt,
         large_hit_damage_taken_multiplier,
+        loot_summary,
+        message_segment_preview,
         moonberry_chaos_output_multiplier,
         moonberry_effective_skill_range_radius_with_multiplier,
         moonberry_physical_damage_followup_buff,
         moonberry_skill_type_is_spell,
+        normalize_item,
         normalized_random_pool_counts,
+        parse_outbound_segments,
+        pick_weighted_pool_entry,
         record_character_damage_taken,
         record_character_healing_taken,
+        remove_character_inventory_item,
         reset_character_turn_totals,
+        shift_character_hotbar_after_remove,
         skill_rule_args,
         update_character_from_status,
         update_character_from_status_with_config,
         upsert_character_active_buff,
+        veto_trade,
         wounded_healing_dealt_multiplier,
         CampaignMessage,
         CharacterBuffBaseStats,
@@ -704,38 +726,67 @@ use crate::{
         CharacterStatus,
         ChatGroup,
         ChatTargetExportKind,
+        CreationChoice,
+        CreationStatMode,
         EquipmentSlot,
         ImageData,
         InventoryItem,
         InventoryQuality,
+        LootOutcome,
+        NapcatConnectionProfile,
+        NapcatConnectionProfiles,
+        NapcatConnectionStatus,
+        NapcatConnectionStatuses,
+        NapcatControlApiConfig,
+        NapcatControlApiState,
+        NapcatControlApiViewerToken,
+        NapcatDeliveryState,
         NapcatIOSender,
         NapcatMessage,
         NapcatMessageChain,
         NapcatMessageChainType,
         NapcatMessageData,
         NapcatMessageManager,
+        NapcatMessageScheduler,
         NapcatMessageType,
+        NapcatOutboundQueue,
         NapcatSendManager,
         NapcatSender,
+        NapcatTransport,
+        PlayerAccess,
         PlayerCharacter,
+        PlayerTrade,
         RandomPool,
         RandomPoolCheckedResult,
         RandomPoolEntry,
         RandomPoolTextResult,
+        ScheduledMessage,
+        ScheduledTarget,
+        ScheduledTrigger,
+        ShopListing,
+        ShopTransactionKind,
         SkillPoolEntry,
         SkillRuleArgs,
-        TextData,
+        StartingKit,
+        TradeStatus,
         TrpgBasicConfig,
+        TrpgCheckConfig,
+        TrpgCreationTemplate,
         TrpgDamageBonusKind,
         TrpgDamageTakenKind,
         TrpgGroup,
         TrpgLegacyNegativeTimer,
         TrpgLegacySendPane,
         TrpgLegacyTeamChatMessage,
+        UnitLootTable,
         UnitPoolEntry,
         Visibility,
+        CHARACTER_STATUS_NAMES,
+        CONSUMABLE_ITEM_SKILL_INDEX,
         LEGACY_NEGATIVE_TIMEOUT_MS,
         NAPCAT_MANAGER_EXPORT_VERSION,
+        NAPCAT_QUEUE_MAX_RESPONSE_TIMEOUT_SECS,
+        TRADE_TIMEOUT_SECS,
     },
     rule_engine::{
         apply_skill_type_damage_default,
@@ -744,6 +795,7 @@ use crate::{
         parse_rule_with_named_args,
         Action,
         ActorRef,
+        BuffCondition,
         BuffEffect,
         BuffField,
         BuffKind,
@@ -831,8 +883,15 @@ pub(crate) struct TrpgGroupSettingsState {
     skill_pool_draft: SkillPoolEntry,
     item_pool_draft: InventoryItem,
     item_pool_award_target: String,
+    shop_item_pick: usize,
+    trade_veto_reasons: HashMap<u64, String>,
+    trade_review_status: String,
+    loot_recipients: HashMap<u64, HashSet<String>>,
+    loot_review_status: String,
     party_name_drafts: HashMap<String, String>,
     party_merge_targets: HashMap<(String, String), String>,
+    check_request_drafts: HashMap<String, CheckRequestDraft>,
+    check_request_status: HashMap<String, String>,
     export_path: String,
     pc_export_path: String,
     chat_list_export_path: String,
@@ -845,6 +904,23 @@ pub(crate) struct TrpgGroupSettingsState {
     import_export_status: String,
 }
 
+#[derive(Clone)]
+pub(crate) struct CheckRequestDraft {
+    scope: String,
+    status: String,
+    dc: i32,
+}
+
+impl Default for CheckRequestDraft {
+    fn default() -> Self {
+        Self {
+            scope: BROADCAST_SCOPE_ALL.to_owned(),
+            status: CHARACTER_STATUS_NAMES[0].to_owned(),
+            dc: 15,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Default, PartialEq, Eq)]
 enum PoolWindowTab {
     #[default]
@@ -854,6 +930,9 @@ enum PoolWindowTab {
     Unit,
     Skill,
     Item,
+    Shop,
+    Trade,
+    Loot,
 }
 
 #[derive(Default)]
@@ -863,6 +942,7 @@ pub(crate) struct CharacterEditState {
     buff_drafts: HashMap<String, BuffDraft>,
     pending_character_reset: Option<String>,
     quick_cast_skill_index: HashMap<String, usize>,
+    quick_use_item_index: HashMap<String, usize>,
     pending_force_cast: Option<(String, usize)>,
     skill_pool_selected_index: HashMap<String, usize>,
     item_pool_selected_index: HashMap<String, usize>,
@@ -878,6 +958,7 @@ pub(crate) struct BuffDraft {
     beneficial: bool,
     field: BuffField,
     value: BuffValue,
+    conditions: Vec<BuffCondition>,
 }
 
 impl Default for BuffDraft {
@@ -890,6 +971,7 @@ impl Default for BuffDraft {
             beneficial: true,
             field: BuffField::DamageTakenModifier,
             value: BuffValue::Set(0.5),
+            conditions: Vec::new(),
         }
     }
 }
@@ -910,10 +992,28 @@ pub struct UiSystemLocals<'w, 's> {
     chat_turn_count_drafts: Local<'s, HashMap<(String, String), u32>>,
     group_broadcast_scopes: Local<'s, HashMap<String, String>>,
     chat_player_visible_previews: Local<'s, HashMap<String, String>>,
+    chat_forward_exports: Local<'s, HashMap<String, ForwardExportDraft>>,
     chat_list_player_visible_filter: Local<'s, Option<String>>,
     voxel_editor: ResMut<'w, VoxelEditorState>,
     voxel_possession: ResMut<'w, VoxelPossessionState>,
     battle_store: Option<ResMut<'w, Persistent<BattleRoundStore>>>,
+    napcat_connection_panel: Local<'s, NapcatConnectionPanelState>,
+    napcat_connection_profiles: Option<ResMut<'w, Persistent<NapcatConnectionProfiles>>>,
+    napcat_connection_statuses: Option<Res<'w, NapcatConnectionStatuses>>,
+    napcat_delivery_log: Local<'s, NapcatDeliveryLogState>,
+    napcat_outbound_queue: Option<ResMut<'w, Persistent<NapcatOutboundQueue>>>,
+    napcat_schedule: Local<'s, NapcatScheduleState>,
+    napcat_scheduler: Option<ResMut<'w, Persistent<NapcatMessageScheduler>>>,
+    napcat_control_api_panel: Local<'s, NapcatControlApiPanelState>,
+    napcat_control_api: Option<ResMut<'w, Persistent<NapcatControlApiConfig>>>,
+    napcat_control_api_state: Option<Res<'w, NapcatControlApiState>>,
+}
+
+#[derive(Default)]
+pub(crate) struct NapcatConnectionPanelState {
+    open: bool,
+    draft: Option<NapcatConnectionProfiles>,
+    status: String,
 }
 
 pub struct CircleImageButton {
@@ -968,10 +1068,30 @@ fn tools_menu_button(
     ui: &mut Ui,
     rule_engine_state: &mut RuleEngineState,
     battle_round_state: &mut BattleRoundUiState,
+    napcat_connections_open: &mut bool,
+    delivery_log_open: &mut bool,
+    schedule_open: &mut bool,
+    control_api_open: &mut bool,
 ) {
     ui.menu_button("工具", |ui| {
         ui.style_mut().wrap_mode = Some(egui::TextWrapMode::Extend);
 
+        if ui.button("NapCat连接").clicked() {
+            *napcat_connections_open = true;
+            ui.close();
+        }
+        if ui.button("发送队列").clicked() {
+            *delivery_log_open = true;
+            ui.close();
+        }
+        if ui.button("定时消息").clicked() {
+            *schedule_open = true;
+            ui.close();
+        }
+        if ui.button("控制API").clicked() {
+            *control_api_open = true;
+            ui.close();
+        }
         if ui.button("战斗轮").clicked() {
             battle_round_state.open_panel();
             ui.close();
@@ -983,168 +1103,1241 @@ fn tools_menu_button(
     });
 }
 
-fn pool_menu_button(
-    ui: &mut Ui,
-    manager: &NapcatMessageManager,
-    state: &mut TrpgGroupSettingsState,
+fn napcat_connections_window(
+    ctx: &Context,
+    state: &mut NapcatConnectionPanelState,
+    profiles: &mut Persistent<NapcatConnectionProfiles>,
+    statuses: Option<&NapcatConnectionStatuses>,
 ) {
-    let player_targets = sorted_pool_targets(manager, false);
-    let group_chat_targets = sorted_pool_targets(manager, true);
+    if !state.open {
+        return;
+    }
 
-    ui.menu_button("池", |ui| {
-        ui.style_mut().wrap_mode = Some(egui::TextWrapMode::Extend);
+    let mut open = state.open;
+    let draft = state.draft.get_or_insert_with(|| (**profiles).clone());
+    let mut save_clicked = false;
+    let mut revert_clicked = false;
+    let mut profile_to_delete = None;
 
-        for (label, tab) in [
-            (
-                format!("玩家池 ({})", player_targets.len()),
-                PoolWindowTab::Player,
-            ),
-            (
-                format!("群聊池 ({})", group_chat_targets.len()),
-                PoolWindowTab::GroupChat,
-            ),
-            (
-                format!(
-                    "随机池 ({})",
-                    manager.random_pools.len()
-                ),
-                PoolWindowTab::Random,
-            ),
-            (
-                format!("单位池 ({})", manager.unit_pool.len()),
-                PoolWindowTab::Unit,
-            ),
-            (
-                format!("技能池 ({})", manager.skill_pool.len()),
-                PoolWindowTab::Skill,
-            ),
-            (
-                format!("物品池 ({})", manager.item_pool.len()),
-                PoolWindowTab::Item,
-            ),
-        ] {
-            if ui.button(label).clicked() {
-                state.pool_window_tab = tab;
-                state.pool_window_open = true;
-                ui.close();
+    egui::Window::new("NapCat连接")
+        .id(Id::new("napcat_connections_window"))
+        .open(&mut open)
+        .default_size(Vec2::new(520.0, 360.0))
+        .show(ctx, |ui| {
+            ui.small(
+                "每个启用的连接各自维持一条OneBot通道（正向WS、反向WS或HTTP POST）；回复会从收到该会话消息的连接发出。",
+            );
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, true])
+                .show(ui, |ui| {
+                    for (index, profile) in draft.profiles.iter_mut().enumerate() {
+                        ui.separator();
+                        ui.horizontal_wrapped(|ui| {
+                            ui.checkbox(&mut profile.enabled, "启用");
+                            ui.strong(profile.display_name().to_owned());
+                            let status = statuses
+                                .and_then(|statuses| statuses.0.get(&profile.id))
+                                .map(NapcatConnectionStatus::label)
+                                .unwrap_or_else(|| "未启动".to_owned());
+                            ui.small(status);
+                            if ui.small_button("删除").clicked() {
+                                profile_to_delete = Some(index);
+                            }
+                        });
+                        egui::Grid::new(("napcat_connection_profile", index))
+                            .num_columns(2)
+                            .show(ui, |ui| {
+                                ui.label("ID");
+                                ui.text_edit_singleline(&mut profile.id);
+                                ui.end_row();
+                                ui.label("名称");
+                                ui.text_edit_singleline(&mut profile.name);
+                                ui.end_row();
+                                ui.label("模式");
+                                egui::ComboBox::from_id_salt((
+                                    "napcat_connection_transport",
+                                    index,
+                                ))
+                                .selected_text(profile.transport.label())
+                                .show_ui(ui, |ui| {
+                                    for transport in NapcatTransport::ALL {
+                                        ui.selectable_value(
+                                            &mut profile.transport,
+                                            transport,
+                                            transport.label(),
+                                        );
+                                    }
+                                });
+                                ui.end_row();
+                                if profile.transport != NapcatTransport::ReverseWebsocket {
+                                    ui.label(
+                                        if profile.transport == NapcatTransport::HttpPost {
+                                            "HTTP API地址"
+                                        } else {
+                                            "地址"
+                                        },
+                                    );
+                                    ui.text_edit_singleline(&mut profile.url);
+                                    ui.end_row();
+                                }
+                                if profile.transport.listens() {
+                                    ui.label("监听地址");
+                                    ui.text_edit_singleline(&mut profile.listen_addr);
+                                    ui.end_row();
+                                }
+                                ui.label("access_token");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut profile.access_token)
+                                        .password(true),
+                                );
+                                ui.end_row();
+                                if profile.transport == NapcatTransport::HttpPost {
+                                    ui.label("上报secret");
+                                    ui.add(
+                                        egui::TextEdit::singleline(&mut profile.secret)
+                                            .password(true),
+                                    )
+                                    .on_hover_text("校验X-Signature（HMAC-SHA1），留空则不校验");
+                                    ui.end_row();
+                                }
+                                ui.label("重连退避");
+                                ui.horizontal(|ui| {
+                                    ui.add(
+                                        egui::DragValue::new(&mut profile.reconnect_initial_ms)
+                                            .range(100..=600_000)
+                                            .speed(100.0)
+                                            .suffix("ms"),
+                                    );
+                                    ui.label("×");
+                                    ui.add(
+                                        egui::DragValue::new(&mut profile.reconnect_multiplier)
+                                            .range(1.0..=10.0)
+                                            .speed(0.1),
+                                    );
+                                    ui.label("最多");
+                                    ui.add(
+                                        egui::DragValue::new(&mut profile.reconnect_max_ms)
+                                            .range(100..=3_600_000)
+                                            .speed(1000.0)
+                                            .suffix("ms"),
+                                    );
+                                });
+                                ui.end_row();
+                            });
+                    }
+                });
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("添加连接").clicked() {
+                    let id = draft.next_profile_id();
+                    draft.profiles.push(NapcatConnectionProfile::new(id));
+                }
+                save_clicked = ui.button("保存并应用").clicked();
+                revert_clicked = ui.button("还原").clicked();
+            });
+            if !state.status.is_empty() {
+                ui.small(&state.status);
             }
+        });
+
+    if let Some(index) = profile_to_delete {
+        draft.profiles.remove(index);
+    }
+    if save_clicked {
+        for profile in &mut draft.profiles {
+            profile.id = profile.id.trim().to_owned();
         }
-    });
+        state.status = match draft.validate() {
+            Ok(()) => {
+                **profiles = draft.clone();
+                match profiles.persist() {
+                    Ok(()) => "已保存，连接会按新配置重连".to_owned(),
+                    Err(err) => format!("保存失败：{err}"),
+                }
+            },
+            Err(err) => err,
+        };
+    }
+    if revert_clicked {
+        state.draft = None;
+        state.status.clear();
+    }
+    if !open {
+        state.draft = None;
+        state.status.clear();
+    }
+    state.open = open;
 }
 
-fn pool_management_window(
+#[derive(Default)]
+pub(crate) struct NapcatControlApiPanelState {
+    open: bool,
+    draft: Option<NapcatControlApiConfig>,
+    show_token: bool,
+    status: String,
+}
+
+fn napcat_control_api_window(
     ctx: &Context,
-    manager: &mut Persistent<NapcatMessageManager>,
-    state: &mut TrpgGroupSettingsState,
-    napcat_sender: Option<&NapcatIOSender>,
-    ime: &mut ImeManager,
-    mut scene_store: Option<&mut Persistent<VoxelSceneStore>>,
+    state: &mut NapcatControlApiPanelState,
+    config: &mut Persistent<NapcatControlApiConfig>,
+    api_state: Option<&NapcatControlApiState>,
 ) {
-    if !state.pool_window_open {
+    if !state.open {
         return;
     }
 
-    let player_targets = sorted_pool_targets(manager, false);
-    let group_chat_targets = sorted_pool_targets(manager, true);
-    let mut open = state.pool_window_open;
-    let mut changed = false;
-    egui::Window::new("池管理")
-        .id(Id::new("pool_management_window"))
+    let mut open = state.open;
+    let draft = state.draft.get_or_insert_with(|| (**config).clone());
+    let mut save_clicked = false;
+    let mut revert_clicked = false;
+
+    egui::Window::new("控制API")
+        .id(Id::new("napcat_control_api_window"))
         .open(&mut open)
-        .default_size(Vec2::new(720.0, 600.0))
-        .min_size(Vec2::new(460.0, 320.0))
+        .default_size(Vec2::new(460.0, 320.0))
         .show(ctx, |ui| {
-            ui.horizontal_wrapped(|ui| {
-                ui.selectable_value(
-                    &mut state.pool_window_tab,
-                    PoolWindowTab::Player,
-                    format!("玩家 ({})", player_targets.len()),
-                );
-                ui.selectable_value(
-                    &mut state.pool_window_tab,
-                    PoolWindowTab::GroupChat,
-                    format!("群聊 ({})", group_chat_targets.len()),
-                );
-                ui.selectable_value(
-                    &mut state.pool_window_tab,
-                    PoolWindowTab::Random,
-                    format!(
-                        "随机池 ({})",
-                        manager.random_pools.len()
-                    ),
-                );
-                ui.selectable_value(
-                    &mut state.pool_window_tab,
-                    PoolWindowTab::Unit,
-                    format!("单位池 ({})", manager.unit_pool.len()),
-                );
-                ui.selectable_value(
-                    &mut state.pool_window_tab,
-                    PoolWindowTab::Skill,
-                    format!("技能池 ({})", manager.skill_pool.len()),
-                );
-                ui.selectable_value(
-                    &mut state.pool_window_tab,
-                    PoolWindowTab::Item,
-                    format!("物品池 ({})", manager.item_pool.len()),
-                );
-            });
+            ui.small(
+                "本机HTTP/JSON接口，供脚本和手机控制柳絮；请求需带 Authorization: Bearer <令牌>。访问令牌即GM令牌，可读取全部内容并执行写操作；读者令牌只能以绑定的viewer（public、party:<队伍>、player:<QQ>）读取可见内容。",
+            );
+            if let Some(api_state) = api_state {
+                ui.label(api_state.status.label());
+            }
             ui.separator();
-            egui::ScrollArea::vertical()
-                .id_salt("pool_management_scroll")
-                .show(ui, |ui| match state.pool_window_tab {
-                    PoolWindowTab::Player => pool_target_list_ui(
-                        ui,
-                        manager,
-                        &player_targets,
-                        "还没有玩家私聊。",
-                    ),
-                    PoolWindowTab::GroupChat => pool_target_list_ui(
-                        ui,
-                        manager,
-                        &group_chat_targets,
-                        "还没有QQ群聊。",
-                    ),
-                    PoolWindowTab::Random => {
-                        changed |= random_pool_settings_ui(
-                            ui,
-                            manager,
-                            state,
-                            &player_targets,
-                            napcat_sender,
-                            Some(&mut *ime),
-                        )
-                    },
-                    PoolWindowTab::Unit => {
-                        changed |= unit_pool_settings_ui(
-                            ui,
-                            manager,
-                            state,
-                            &player_targets,
-                            scene_store.as_deref_mut(),
-                        )
-                    },
-                    PoolWindowTab::Skill => changed |= skill_pool_settings_ui(ui, manager, state),
-                    PoolWindowTab::Item => {
-                        changed |= item_pool_settings_ui(ui, manager, state, &player_targets)
-                    },
+            egui::Grid::new("napcat_control_api_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("启用");
+                    ui.checkbox(&mut draft.enabled, "");
+                    ui.end_row();
+                    ui.label("监听地址");
+                    ui.text_edit_singleline(&mut draft.listen_addr)
+                        .on_hover_text("只监听127.0.0.1时仅本机可访问；手机访问需改为局域网地址");
+                    ui.end_row();
+                    ui.label("访问令牌");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut draft.token)
+                                .password(!state.show_token)
+                                .desired_width(240.0),
+                        );
+                        ui.toggle_value(&mut state.show_token, "显示");
+                        if ui.button("重新生成").clicked() {
+                            draft.token = NapcatControlApiConfig::generate_token();
+                        }
+                        if ui.button("复制").clicked() {
+                            ui.ctx().copy_text(draft.token.clone());
+                        }
+                    });
+                    ui.end_row();
+                });
+            egui::CollapsingHeader::new("读者令牌")
+                .id_salt("napcat_control_api_viewer_tokens")
+                .show(ui, |ui| {
+                    let mut removed = None;
+                    for (index, viewer_token) in draft.viewer_tokens.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::TextEdit::singleline(&mut viewer_token.viewer)
+                                    .hint_text("player:<QQ>")
+                                    .desired_width(120.0),
+                            );
+                            ui.add(
+                                egui::TextEdit::singleline(&mut viewer_token.token)
+                                    .password(!state.show_token)
+                                    .desired_width(180.0),
+                            );
+                            if ui.button("复制").clicked() {
+                                ui.ctx().copy_text(viewer_token.token.clone());
+                            }
+                            if ui.button("删除").clicked() {
+                                removed = Some(index);
+                            }
+                        });
+                    }
+                    if let Some(index) = removed {
+                        draft.viewer_tokens.remove(index);
+                    }
+                    if ui.button("添加读者令牌").clicked() {
+                        draft.viewer_tokens.push(NapcatControlApiViewerToken {
+                            viewer: "public".to_owned(),
+                            token: NapcatControlApiConfig::generate_token(),
+                        });
+                    }
+                });
+            egui::CollapsingHeader::new("接口")
+                .id_salt("napcat_control_api_routes")
+                .show(ui, |ui| {
+                    for route in [
+                        "GET  /api/campaigns?viewer=",
+                        "GET  /api/players?group=&viewer=",
+                        "GET  /api/messages?group=&viewer=&target=&limit=",
+                        "GET  /api/battles?viewer=",
+                        "POST /api/messages {target, text, visibility?, group?}",
+                        "POST /api/battles/next-round {encounter_id, force?}",
+                        "POST /api/experience {player_id, amount, group?}",
+                        "POST /api/scene-captures {user_id}",
+                    ] {
+                        ui.monospace(route);
+                    }
                 });
+            ui.separator();
+            ui.horizontal(|ui| {
+                save_clicked = ui.button("保存并应用").clicked();
+                revert_clicked = ui.button("还原").clicked();
+            });
+            if !state.status.is_empty() {
+                ui.small(&state.status);
+            }
         });
-    state.pool_window_open = open;
-    if changed {
-        manager.persist().ok();
+
+    if save_clicked {
+        if let Some(draft) = state.draft.as_ref() {
+            match draft.validate() {
+                Ok(()) => {
+                    **config = draft.clone();
+                    state.status = match config.persist() {
+                        Ok(()) => "已保存，接口会按新设置重新监听".to_owned(),
+                        Err(err) => format!("保存失败：{err}"),
+                    };
+                },
+                Err(err) => state.status = err,
+            }
+        }
     }
+    if revert_clicked {
+        state.draft = None;
+        state.status.clear();
+    }
+    state.open = open;
 }
 
-fn pool_target_list_ui(
-    ui: &mut Ui,
-    manager: &NapcatMessageManager,
-    targets: &[String],
-    empty_text: &str,
-) {
-    if targets.is_empty() {
-        ui.label(empty_text);
-        return;
+#[derive(Default)]
+pub(crate) struct NapcatDeliveryLogState {
+    open: bool,
+    only_unfinished: bool,
+}
+
+fn napcat_delivery_log_window(
+    ctx: &Context,
+    state: &mut NapcatDeliveryLogState,
+    queue: &mut Persistent<NapcatOutboundQueue>,
+) {
+    if !state.open {
+        return;
+    }
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default();
+    let mut open = state.open;
+    let mut config = queue.config;
+    let mut resend = None;
+    let mut retry_now = None;
+    let mut cancel = None;
+    let mut clear_finished = false;
+
+    egui::Window::new("发送队列")
+        .id(Id::new("napcat_delivery_log_window"))
+        .open(&mut open)
+        .default_size(Vec2::new(620.0, 420.0))
+        .show(ctx, |ui| {
+            ui.small("所有发出的消息先进入队列，按频率限制依次发送，失败后按指数退避重试。");
+            egui::CollapsingHeader::new("频率与重试")
+                .id_salt("napcat_delivery_config")
+                .show(ui, |ui| {
+                    egui::Grid::new("napcat_delivery_config_grid")
+                        .num_columns(2)
+                        .show(ui, |ui| {
+                            ui.label("全局每分钟");
+                            ui.add(
+                                egui::DragValue::new(&mut config.global_per_minute)
+                                    .range(0..=600)
+                                    .suffix("条"),
+                            )
+                            .on_hover_text("0 表示不限制");
+                            ui.end_row();
+                            ui.label("单个目标每分钟");
+                            ui.add(
+                                egui::DragValue::new(&mut config.per_target_per_minute)
+                                    .range(0..=600)
+                                    .suffix("条"),
+                            )
+                            .on_hover_text("0 表示不限制");
+                            ui.end_row();
+                            ui.label("最小间隔");
+                            ui.add(
+                                egui::DragValue::new(&mut config.min_interval_ms)
+                                    .range(0..=60_000)
+                                    .speed(50.0)
+                                    .suffix("ms"),
+                            );
+                            ui.end_row();
+                            ui.label("最多尝试");
+                            ui.add(
+                                egui::DragValue::new(&mut config.max_attempts)
+                                    .range(1..=20)
+                                    .suffix("次"),
+                            );
+                            ui.end_row();
+                            ui.label("重试退避");
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::DragValue::new(&mut config.retry_base_ms)
+                                        .range(100..=600_000)
+                                        .speed(100.0)
+                                        .suffix("ms"),
+                                );
+                                ui.label("翻倍，最多");
+                                ui.add(
+                                    egui::DragValue::new(&mut config.retry_max_ms)
+                                        .range(100..=3_600_000)
+                                        .speed(1000.0)
+                                        .suffix("ms"),
+                                );
+                            });
+                            ui.end_row();
+                            ui.label("响应超时");
+                            ui.add(
+                                egui::DragValue::new(&mut config.response_timeout_secs)
+                                    .range(1..=NAPCAT_QUEUE_MAX_RESPONSE_TIMEOUT_SECS)
+                                    .suffix("秒"),
+                            );
+                            ui.end_row();
+                            ui.label("保留记录");
+                            ui.add(
+                                egui::DragValue::new(&mut config.log_limit)
+                                    .range(10..=5000)
+                                    .suffix("条"),
+                            );
+                            ui.end_row();
+                        });
+                });
+            ui.horizontal(|ui| {
+                let active = queue
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.state.is_active())
+                    .count();
+                ui.label(format!(
+                    "待发送 {active} / 记录 {}",
+                    queue.entries.len()
+                ));
+                ui.checkbox(
+                    &mut state.only_unfinished,
+                    "只看未完成和失败",
+                );
+                clear_finished = ui.button("清除已完成").clicked();
+            });
+            ui.separator();
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    for entry in queue.entries.iter().rev() {
+                        if state.only_unfinished
+                            && matches!(
+                                entry.state,
+                                NapcatDeliveryState::Sent | NapcatDeliveryState::Cancelled
+                            )
+                        {
+                            continue;
+                        }
+                        ui.horizontal_wrapped(|ui| {
+                            let color = match entry.state {
+                                NapcatDeliveryState::Sent => egui::Color32::from_rgb(90, 170, 90),
+                                NapcatDeliveryState::Failed => egui::Color32::LIGHT_RED,
+                                NapcatDeliveryState::Retrying | NapcatDeliveryState::Unknown => {
+                                    egui::Color32::from_rgb(220, 170, 60)
+                                },
+                                _ => ui.visuals().text_color(),
+                            };
+                            ui.colored_label(color, entry.state.label());
+                            ui.small(format!(
+                                "{}前",
+                                format_elapsed_ms(now_ms.saturating_sub(entry.created_at_ms))
+                            ));
+                            ui.strong(&entry.recipient);
+                            if entry.attempts > 1 {
+                                ui.small(format!("第{}次", entry.attempts));
+                            }
+                            if entry.state == NapcatDeliveryState::Retrying {
+                                ui.small(format!(
+                                    "{}后重试",
+                                    format_elapsed_ms(
+                                        entry.next_attempt_at_ms.saturating_sub(now_ms)
+                                    )
+                                ));
+                                if ui.small_button("立即重试").clicked() {
+                                    retry_now = Some(entry.id);
+                                }
+                            }
+                            if matches!(
+                                entry.state,
+                                NapcatDeliveryState::Queued | NapcatDeliveryState::Retrying
+                            ) && ui.small_button("取消").clicked()
+                            {
+                                cancel = Some(entry.id);
+                            }
+                            if !entry.state.is_active() && ui.small_button("重发").clicked() {
+                                resend = Some(entry.id);
+                            }
+                        });
+                        ui.add(
+                            egui::Label::new(if entry.summary.is_empty() {
+                                entry.action.as_str()
+                            } else {
+                                entry.summary.as_str()
+                            })
+                            .truncate(),
+                        )
+                        .on_hover_text(&entry.summary);
+                        if let Some(error) = &entry.last_error {
+                            ui.small(error);
+                        }
+                        ui.separator();
+                    }
+                });
+        });
+
+    let mut changed = config != queue.config;
+    if changed {
+        queue.config = config;
+    }
+    if let Some(id) = resend {
+        changed |= queue.resend(id, now_ms);
+    }
+    if let Some(id) = retry_now {
+        changed |= queue.retry_now(id, now_ms);
+    }
+    if let Some(id) = cancel {
+        changed |= queue.cancel(id, now_ms);
+    }
+    if clear_finished {
+        queue.clear_finished();
+        changed = true;
+    }
+    if changed {
+        if let Err(err) = queue.persist() {
+            eprintln!("failed to persist NapCat outbound queue: {err}");
+        }
+    }
+    state.open = open;
+}
+
+#[derive(Default)]
+pub(crate) struct NapcatScheduleState {
+    open: bool,
+    draft: Option<ScheduledMessageDraft>,
+    status: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ScheduledTargetKind {
+    Private,
+    Group,
+    Party,
+}
+
+impl ScheduledTargetKind {
+    const ALL: [Self; 3] = [Self::Private, Self::Group, Self::Party];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Private => "私聊",
+            Self::Group => "群聊",
+            Self::Party => "队伍",
+        }
+    }
+}
+
+struct ScheduledMessageDraft {
+    id: u64,
+    group_name: String,
+    target_kind: ScheduledTargetKind,
+    target_id: String,
+    visibility: Visibility,
+    /// Follow the target's default visibility until the GM picks one explicitly.
+    visibility_overridden: bool,
+    by_world_turn: bool,
+    delay_minutes: u64,
+    world_turn: u32,
+    text: String,
+    created_at_ms: u64,
+}
+
+impl ScheduledMessageDraft {
+    fn new(manager: &NapcatMessageManager) -> Self {
+        Self {
+            id: 0,
+            group_name: manager.current_trpg_group.clone().unwrap_or_default(),
+            target_kind: ScheduledTargetKind::Group,
+            target_id: String::new(),
+            visibility: Visibility::Public,
+            visibility_overridden: false,
+            by_world_turn: false,
+            delay_minutes: 10,
+            world_turn: manager
+                .current_group()
+                .map(|group| group.world_turn + 1)
+                .unwrap_or(1),
+            text: String::new(),
+            created_at_ms: 0,
+        }
+    }
+
+    fn from_message(message: &ScheduledMessage, now_ms: u64) -> Self {
+        let (target_kind, target_id) = match &message.target {
+            ScheduledTarget::Private(user_id) => (
+                ScheduledTargetKind::Private,
+                user_id.to_string(),
+            ),
+            ScheduledTarget::Group(group_id) => (
+                ScheduledTargetKind::Group,
+                group_id.to_string(),
+            ),
+            ScheduledTarget::Party(party_id) => (
+                ScheduledTargetKind::Party,
+                party_id.clone(),
+            ),
+        };
+        let (by_world_turn, delay_minutes, world_turn) = match message.trigger {
+            ScheduledTrigger::At { at_ms } => (
+                false,
+                at_ms.saturating_sub(now_ms).div_ceil(60_000),
+                1,
+            ),
+            ScheduledTrigger::WorldTurn { turn } => (true, 10, turn),
+        };
+        Self {
+            id: message.id,
+            group_name: message.group_name.clone(),
+            target_kind,
+            target_id,
+            visibility: message.visibility.clone(),
+            visibility_overridden: message.visibility != message.target.default_visibility(),
+            by_world_turn,
+            delay_minutes,
+            world_turn,
+            text: message.text.clone(),
+            created_at_ms: message.created_at_ms,
+        }
+    }
+
+    fn target(&self) -> Result<ScheduledTarget, String> {
+        let target_id = self.target_id.trim();
+        match self.target_kind {
+            ScheduledTargetKind::Private => target_id
+                .parse()
+                .map(ScheduledTarget::Private)
+                .map_err(|_| "请输入有效的QQ号".to_owned()),
+            ScheduledTargetKind::Group => target_id
+                .parse()
+                .map(ScheduledTarget::Group)
+                .map_err(|_| "请输入有效的群号".to_owned()),
+            ScheduledTargetKind::Party if target_id.is_empty() => Err("请选择队伍".to_owned()),
+            ScheduledTargetKind::Party => Ok(ScheduledTarget::Party(
+                target_id.to_owned(),
+            )),
+        }
+    }
+
+    fn to_message(&self, now_ms: u64) -> Result<ScheduledMessage, String> {
+        let target = self.target()?;
+        if self.text.trim().is_empty() {
+            return Err("消息内容不能为空".to_owned());
+        }
+        if self.group_name.is_empty()
+            && (self.by_world_turn || self.target_kind == ScheduledTargetKind::Party)
+        {
+            return Err("按世界回合或队伍发送需要先选择TRPG组".to_owned());
+        }
+        let trigger = if self.by_world_turn {
+            ScheduledTrigger::WorldTurn {
+                turn: self.world_turn,
+            }
+        } else {
+            ScheduledTrigger::At {
+                at_ms: now_ms + self.delay_minutes * 60_000,
+            }
+        };
+        Ok(ScheduledMessage {
+            id: self.id,
+            group_name: self.group_name.clone(),
+            visibility: if self.visibility_overridden {
+                self.visibility.clone()
+            } else {
+                target.default_visibility()
+            },
+            target,
+            trigger,
+            text: self.text.clone(),
+            created_at_ms: if self.created_at_ms == 0 { now_ms } else { self.created_at_ms },
+        })
+    }
+}
+
+fn scheduled_visibility_label(visibility: &Visibility) -> String {
+    match visibility {
+        Visibility::Public => "公开".to_owned(),
+        Visibility::Party(party_id) => format!("队伍：{party_id}"),
+        Visibility::Player(user_id) => format!("玩家：{user_id}"),
+        Visibility::Gm => "GM".to_owned(),
+        Visibility::System => "系统".to_owned(),
+    }
+}
+
+fn scheduled_trigger_label(
+    message: &ScheduledMessage,
+    manager: &NapcatMessageManager,
+    now_ms: u64,
+) -> String {
+    match message.trigger {
+        ScheduledTrigger::At { at_ms } => {
+            format!(
+                "{}后",
+                format_elapsed_ms(at_ms.saturating_sub(now_ms))
+            )
+        },
+        ScheduledTrigger::WorldTurn { turn } => {
+            match manager.trpg_groups.get(&message.group_name) {
+                Some(group) => format!(
+                    "世界回合 {turn}（当前 {}）",
+                    group.world_turn
+                ),
+                None => format!("世界回合 {turn}（TRPG组已不存在）"),
+            }
+        },
+    }
+}
+
+fn napcat_schedule_window(
+    ctx: &Context,
+    state: &mut NapcatScheduleState,
+    scheduler: &mut Persistent<NapcatMessageScheduler>,
+    manager: &NapcatMessageManager,
+) {
+    if !state.open {
+        return;
+    }
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default();
+    let mut open = state.open;
+    let mut edit = None;
+    let mut cancel = None;
+    let mut save = false;
+    let mut discard = false;
+
+    egui::Window::new("定时消息")
+        .id(Id::new("napcat_schedule_window"))
+        .open(&mut open)
+        .default_size(Vec2::new(560.0, 420.0))
+        .show(ctx, |ui| {
+            ui.small("到时间或TRPG组推进到指定世界回合后，消息会进入发送队列；重启后仍会补发。");
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "待发送 {}",
+                    scheduler.messages.len()
+                ));
+                if state.draft.is_none() && ui.button("新建").clicked() {
+                    state.draft = Some(ScheduledMessageDraft::new(manager));
+                    state.status.clear();
+                }
+            });
+            if let Some(draft) = state.draft.as_mut() {
+                egui::Frame::new()
+                    .fill(ui.visuals().faint_bg_color)
+                    .corner_radius(4)
+                    .inner_margin(egui::Margin::symmetric(8, 6))
+                    .show(ui, |ui| {
+                        scheduled_message_draft_ui(ui, draft, manager);
+                        ui.horizontal(|ui| {
+                            save = ui
+                                .button(if draft.id == 0 { "加入计划" } else { "保存修改" })
+                                .clicked();
+                            discard = ui.button("放弃").clicked();
+                        });
+                    });
+            }
+            if !state.status.is_empty() {
+                ui.small(&state.status);
+            }
+            ui.separator();
+            let mut messages = scheduler.messages.iter().collect::<Vec<_>>();
+            messages.sort_by_key(|message| match message.trigger {
+                ScheduledTrigger::At { at_ms } => (0, at_ms, message.id),
+                ScheduledTrigger::WorldTurn { turn } => (1, u64::from(turn), message.id),
+            });
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    if messages.is_empty() {
+                        ui.weak("暂无定时消息");
+                    }
+                    for message in messages {
+                        ui.horizontal_wrapped(|ui| {
+                            ui.strong(message.target.label());
+                            ui.small(scheduled_visibility_label(
+                                &message.visibility,
+                            ));
+                            ui.label(scheduled_trigger_label(
+                                message, manager, now_ms,
+                            ));
+                            if ui.small_button("编辑").clicked() {
+                                edit = Some(message.id);
+                            }
+                            if ui.small_button("取消").clicked() {
+                                cancel = Some(message.id);
+                            }
+                        });
+                        ui.add(egui::Label::new(message.text.as_str()).truncate())
+                            .on_hover_text(&message.text);
+                        ui.separator();
+                    }
+                });
+        });
+
+    let mut changed = false;
+    if save {
+        if let Some(draft) = state.draft.as_ref() {
+            match draft.to_message(now_ms) {
+                Ok(message) => {
+                    scheduler.upsert(message);
+                    state.draft = None;
+                    state.status = "已保存定时消息".to_owned();
+                    changed = true;
+                },
+                Err(err) => state.status = err,
+            }
+        }
+    }
+    if discard {
+        state.draft = None;
+        state.status.clear();
+    }
+    if let Some(id) = edit {
+        state.draft = scheduler
+            .messages
+            .iter()
+            .find(|message| message.id == id)
+            .map(|message| ScheduledMessageDraft::from_message(message, now_ms));
+        state.status.clear();
+    }
+    if let Some(id) = cancel {
+        changed |= scheduler.cancel(id);
+        if state.draft.as_ref().is_some_and(|draft| draft.id == id) {
+            state.draft = None;
+        }
+    }
+    if changed {
+        if let Err(err) = scheduler.persist() {
+            eprintln!("failed to persist scheduled messages: {err}");
+        }
+    }
+    state.open = open;
+}
+
+fn scheduled_message_draft_ui(
+    ui: &mut Ui,
+    draft: &mut ScheduledMessageDraft,
+    manager: &NapcatMessageManager,
+) {
+    let mut group_names = manager.trpg_groups.keys().cloned().collect::<Vec<_>>();
+    group_names.sort();
+    let group = manager.trpg_groups.get(&draft.group_name);
+    egui::Grid::new("napcat_schedule_draft_grid")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("TRPG组");
+            egui::ComboBox::from_id_salt("napcat_schedule_group")
+                .selected_text(if draft.group_name.is_empty() {
+                    "未选择"
+                } else {
+                    draft.group_name.as_str()
+                })
+                .show_ui(ui, |ui| {
+                    for group_name in &group_names {
+                        ui.selectable_value(
+                            &mut draft.group_name,
+                            group_name.clone(),
+                            group_name,
+                        );
+                    }
+                });
+            ui.end_row();
+
+            ui.label("发送到");
+            ui.horizontal(|ui| {
+                for kind in ScheduledTargetKind::ALL {
+                    if ui
+                        .selectable_label(draft.target_kind == kind, kind.label())
+                        .clicked()
+                        && draft.target_kind != kind
+                    {
+                        draft.target_kind = kind;
+                        draft.target_id.clear();
+                    }
+                }
+                if draft.target_kind == ScheduledTargetKind::Party {
+                    let mut parties = group
+                        .map(|group| group.parties.keys().cloned().collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    parties.sort();
+                    egui::ComboBox::from_id_salt("napcat_schedule_party")
+                        .selected_text(if draft.target_id.is_empty() {
+                            "选择队伍"
+                        } else {
+                            draft.target_id.as_str()
+                        })
+                        .show_ui(ui, |ui| {
+                            for party_id in parties {
+                                let label = party_id.clone();
+                                ui.selectable_value(&mut draft.target_id, party_id, label);
+                            }
+                        });
+                } else {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut draft.target_id)
+                            .desired_width(140.0)
+                            .hint_text(
+                                if draft.target_kind == ScheduledTargetKind::Private {
+                                    "QQ号"
+                                } else {
+                                    "群号"
+                                },
+                            ),
+                    );
+                }
+            });
+            ui.end_row();
+
+            ui.label("可见范围");
+            let default_visibility = draft
+                .target()
+                .ok()
+                .map(|target| target.default_visibility());
+            let current = if draft.visibility_overridden {
+                draft.visibility.clone()
+            } else {
+                default_visibility.clone().unwrap_or_default()
+            };
+            let mut options = vec![Visibility::Public, Visibility::Gm];
+            if let Some(group) = group {
+                let mut parties = group.parties.keys().cloned().collect::<Vec<_>>();
+                parties.sort();
+                options.extend(parties.into_iter().map(Visibility::Party));
+                options.extend(
+                    group
+                        .players
+                        .iter()
+                        .filter_map(|player_id| player_id.parse().ok())
+                        .map(Visibility::Player),
+                );
+            }
+            if let Some(visibility) =
+                default_visibility.filter(|visibility| !options.contains(visibility))
+            {
+                options.push(visibility);
+            }
+            egui::ComboBox::from_id_salt("napcat_schedule_visibility")
+                .selected_text(if draft.visibility_overridden {
+                    scheduled_visibility_label(&current)
+                } else {
+                    format!(
+                        "{}（默认）",
+                        scheduled_visibility_label(&current)
+                    )
+                })
+                .show_ui(ui, |ui| {
+                    if ui
+                        .selectable_label(!draft.visibility_overridden, "跟随目标")
+                        .clicked()
+                    {
+                        draft.visibility_overridden = false;
+                    }
+                    for visibility in options {
+                        let selected =
+                            draft.visibility_overridden && draft.visibility == visibility;
+                        if ui
+                            .selectable_label(
+                                selected,
+                                scheduled_visibility_label(&visibility),
+                            )
+                            .clicked()
+                        {
+                            draft.visibility = visibility;
+                            draft.visibility_overridden = true;
+                        }
+                    }
+                });
+            ui.end_row();
+
+            ui.label("触发");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut draft.by_world_turn, false, "延时");
+                ui.radio_value(
+                    &mut draft.by_world_turn,
+                    true,
+                    "世界回合",
+                );
+                if draft.by_world_turn {
+                    ui.add(egui::DragValue::new(&mut draft.world_turn).range(0..=u32::MAX));
+                    if let Some(group) = group {
+                        ui.small(format!("当前 {}", group.world_turn));
+                    }
+                } else {
+                    ui.add(
+                        egui::DragValue::new(&mut draft.delay_minutes)
+                            .range(0..=60 * 24 * 30)
+                            .suffix("分钟后"),
+                    );
+                }
+            });
+            ui.end_row();
+        });
+    ui.add(
+        egui::TextEdit::multiline(&mut draft.text)
+            .desired_rows(3)
+            .desired_width(f32::INFINITY)
+            .hint_text("支持 [CQ:at,qq=...] 等CQ码"),
+    );
+}
+
+fn format_elapsed_ms(ms: u64) -> String {
+    let seconds = ms / 1000;
+    if seconds < 60 {
+        format!("{seconds}秒")
+    } else if seconds < 3600 {
+        format!("{}分", seconds / 60)
+    } else {
+        format!("{}小时", seconds / 3600)
+    }
+}
+
+fn pool_menu_button(
+    ui: &mut Ui,
+    manager: &NapcatMessageManager,
+    state: &mut TrpgGroupSettingsState,
+) {
+    let player_targets = sorted_pool_targets(manager, false);
+    let group_chat_targets = sorted_pool_targets(manager, true);
+
+    ui.menu_button("池", |ui| {
+        ui.style_mut().wrap_mode = Some(egui::TextWrapMode::Extend);
+
+        for (label, tab) in [
+            (
+                format!("玩家池 ({})", player_targets.len()),
+                PoolWindowTab::Player,
+            ),
+            (
+                format!("群聊池 ({})", group_chat_targets.len()),
+                PoolWindowTab::GroupChat,
+            ),
+            (
+                format!(
+                    "随机池 ({})",
+                    manager.random_pools.len()
+                ),
+                PoolWindowTab::Random,
+            ),
+            (
+                format!("单位池 ({})", manager.unit_pool.len()),
+                PoolWindowTab::Unit,
+            ),
+            (
+                format!("技能池 ({})", manager.skill_pool.len()),
+                PoolWindowTab::Skill,
+            ),
+            (
+                format!("物品池 ({})", manager.item_pool.len()),
+                PoolWindowTab::Item,
+            ),
+            (
+                format!(
+                    "商店 ({})",
+                    current_shop_listing_count(manager)
+                ),
+                PoolWindowTab::Shop,
+            ),
+            (
+                format!(
+                    "交易 ({})",
+                    current_active_trade_count(manager)
+                ),
+                PoolWindowTab::Trade,
+            ),
+            (
+                format!(
+                    "战利品 ({})",
+                    current_staged_loot_count(manager)
+                ),
+                PoolWindowTab::Loot,
+            ),
+        ] {
+            if ui.button(label).clicked() {
+                state.pool_window_tab = tab;
+                state.pool_window_open = true;
+                ui.close();
+            }
+        }
+    });
+}
+
+fn pool_management_window(
+    ctx: &Context,
+    manager: &mut Persistent<NapcatMessageManager>,
+    state: &mut TrpgGroupSettingsState,
+    napcat_sender: Option<&NapcatIOSender>,
+    ime: &mut ImeManager,
+    mut scene_store: Option<&mut Persistent<VoxelSceneStore>>,
+) {
+    if !state.pool_window_open {
+        return;
+    }
+
+    let player_targets = sorted_pool_targets(manager, false);
+    let group_chat_targets = sorted_pool_targets(manager, true);
+    let mut open = state.pool_window_open;
+    let mut changed = false;
+    egui::Window::new("池管理")
+        .id(Id::new("pool_management_window"))
+        .open(&mut open)
+        .default_size(Vec2::new(720.0, 600.0))
+        .min_size(Vec2::new(460.0, 320.0))
+        .show(ctx, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                ui.selectable_value(
+                    &mut state.pool_window_tab,
+                    PoolWindowTab::Player,
+                    format!("玩家 ({})", player_targets.len()),
+                );
+                ui.selectable_value(
+                    &mut state.pool_window_tab,
+                    PoolWindowTab::GroupChat,
+                    format!("群聊 ({})", group_chat_targets.len()),
+                );
+                ui.selectable_value(
+                    &mut state.pool_window_tab,
+                    PoolWindowTab::Random,
+                    format!(
+                        "随机池 ({})",
+                        manager.random_pools.len()
+                    ),
+                );
+                ui.selectable_value(
+                    &mut state.pool_window_tab,
+                    PoolWindowTab::Unit,
+                    format!("单位池 ({})", manager.unit_pool.len()),
+                );
+                ui.selectable_value(
+                    &mut state.pool_window_tab,
+                    PoolWindowTab::Skill,
+                    format!("技能池 ({})", manager.skill_pool.len()),
+                );
+                ui.selectable_value(
+                    &mut state.pool_window_tab,
+                    PoolWindowTab::Item,
+                    format!("物品池 ({})", manager.item_pool.len()),
+                );
+                ui.selectable_value(
+                    &mut state.pool_window_tab,
+                    PoolWindowTab::Shop,
+                    format!(
+                        "商店 ({})",
+                        current_shop_listing_count(manager)
+                    ),
+                );
+                ui.selectable_value(
+                    &mut state.pool_window_tab,
+                    PoolWindowTab::Trade,
+                    format!(
+                        "交易 ({})",
+                        current_active_trade_count(manager)
+                    ),
+                );
+                ui.selectable_value(
+                    &mut state.pool_window_tab,
+                    PoolWindowTab::Loot,
+                    format!(
+                        "战利品 ({})",
+                        current_staged_loot_count(manager)
+                    ),
+                );
+            });
+            ui.separator();
+            egui::ScrollArea::vertical()
+                .id_salt("pool_management_scroll")
+                .show(ui, |ui| match state.pool_window_tab {
+                    PoolWindowTab::Player => pool_target_list_ui(
+                        ui,
+                        manager,
+                        &player_targets,
+                        "还没有玩家私聊。",
+                    ),
+                    PoolWindowTab::GroupChat => pool_target_list_ui(
+                        ui,
+                        manager,
+                        &group_chat_targets,
+                        "还没有QQ群聊。",
+                    ),
+                    PoolWindowTab::Random => {
+                        changed |= random_pool_settings_ui(
+                            ui,
+                            manager,
+                            state,
+                            &player_targets,
+                            napcat_sender,
+                            Some(&mut *ime),
+                        )
+                    },
+                    PoolWindowTab::Unit => {
+                        changed |= unit_pool_settings_ui(
+                            ui,
+                            manager,
+                            state,
+                            &player_targets,
+                            scene_store.as_deref_mut(),
+                        )
+                    },
+                    PoolWindowTab::Skill => changed |= skill_pool_settings_ui(ui, manager, state),
+                    PoolWindowTab::Item => {
+                        changed |= item_pool_settings_ui(ui, manager, state, &player_targets)
+                    },
+                    PoolWindowTab::Shop => changed |= shop_settings_ui(ui, manager, state),
+                    PoolWindowTab::Trade => {
+                        changed |= trade_review_ui(ui, manager, state, napcat_sender, ime)
+                    },
+                    PoolWindowTab::Loot => {
+                        changed |= loot_review_ui(
+                            ui,
+                            manager,
+                            state,
+                            &player_targets,
+                            napcat_sender,
+                            ime,
+                        )
+                    },
+                });
+        });
+    state.pool_window_open = open;
+    if changed {
+        manager.persist().ok();
+    }
+}
+
+fn pool_target_list_ui(
+    ui: &mut Ui,
+    manager: &NapcatMessageManager,
+    targets: &[String],
+    empty_text: &str,
+) {
+    if targets.is_empty() {
+        ui.label(empty_text);
+        return;
     }
     for target_id in targets {
         ui.horizontal(|ui| {
@@ -1316,6 +2509,7 @@ fn chat_window(
     focused_trpg_group_name: Option<&str>,
     turn_count_drafts: &mut Local<HashMap<(String, String), u32>>,
     chat_player_visible_previews: &mut Local<HashMap<String, String>>,
+    chat_forward_exports: &mut Local<HashMap<String, ForwardExportDraft>>,
     rule_engine_state: &mut RuleEngineState,
     mut player_view_request: Option<&mut ScenePlayerViewRequest>,
 ) {
@@ -1504,6 +2698,21 @@ fn chat_window(
                         }
                     }
                 }
+                let mut forward_export_open = chat_forward_exports.contains_key(target_id);
+                if ui
+                    .toggle_value(&mut forward_export_open, "合并转发")
+                    .on_hover_text("把一段聊天记录按受众过滤后作为合并转发发出")
+                    .changed()
+                {
+                    if forward_export_open {
+                        chat_forward_exports.insert(
+                            target_id.to_owned(),
+                            ForwardExportDraft::new(messages.len()),
+                        );
+                    } else {
+                        chat_forward_exports.remove(target_id);
+                    }
+                }
                 if let Some((group_name, _, _, acted, _)) = trpg_turn_snapshot.as_ref() {
                     let button_text = if *acted { "已行动" } else { "行动" };
                     if ui.button(button_text).clicked() {
@@ -1563,6 +2772,28 @@ fn chat_window(
                 });
             }
         }
+        if let Some(draft) = chat_forward_exports.get_mut(target_id) {
+            let input_id = forward_export_input_id(target_id);
+            ui.group(|ui| {
+                if let Some((summary, nodes)) =
+                    forward_export_panel_ui(ui, manager, target_id, messages, draft)
+                {
+                    match napcat_sender {
+                        Some(napcat_sender) => {
+                            let _ = ime.queue_forward_send(
+                                &input_id,
+                                summary,
+                                nodes,
+                                napcat_sender,
+                                targets.clone(),
+                            );
+                        },
+                        None => draft.status = "NapCat未连接".to_owned(),
+                    }
+                }
+                ime.send_status_ui(ui, &input_id);
+            });
+        }
         let preview_messages = chat_player_visible_previews
             .get(target_id)
             .and_then(|player_id| player_id.parse::<u64>().ok())
@@ -1857,6 +3088,7 @@ fn chat_body_ui(
             (available_height - input_height - ui.spacing().item_spacing.y).max(0.0);
 
         let message_width = ui.available_width();
+        let mut composer_insertion = None;
         ui.allocate_ui(
             egui::vec2(message_width, message_height),
             |ui| {
@@ -1882,12 +3114,15 @@ fn chat_body_ui(
                         egui::Layout::top_down(egui::Align::LEFT),
                         |ui| {
                             for message in messages {
-                                message_row_ui(
+                                if let Some(insertion) = message_row_ui(
                                     ui,
                                     message,
+                                    messages,
                                     message_width,
                                     image_textures,
-                                );
+                                ) {
+                                    composer_insertion = Some(insertion);
+                                }
                                 ui.add_space(ui.spacing().item_spacing.y);
                             }
                         },
@@ -1904,6 +3139,9 @@ fn chat_body_ui(
 
         ui.add_space(ui.spacing().item_spacing.y);
         let text = chat_input_msgs.get_mut(target_id).unwrap();
+        if let Some(insertion) = composer_insertion {
+            text.push_str(&insertion);
+        }
         if let Some(napcat_sender) = napcat_sender {
             let _ = ime.chat_input_multiline(
                 target_id,
@@ -2772,12 +4010,156 @@ fn group_drop_area_ui(ui: &mut Ui, group_name: &str, members: &[String]) {
     }
 }
 
+pub(crate) struct ForwardExportDraft {
+    /// 1-based, inclusive message positions in the chat window.
+    start: usize,
+    end: usize,
+    audience: PlayerAccess,
+    status: String,
+}
+
+impl ForwardExportDraft {
+    fn new(message_count: usize) -> Self {
+        Self {
+            start: message_count.saturating_sub(19).max(1),
+            end: message_count.max(1),
+            audience: PlayerAccess::default(),
+            status: String::new(),
+        }
+    }
+}
+
+fn forward_export_input_id(target_id: &str) -> String { format!("forward:{target_id}") }
+
+/// Range and audience picker for a merged-forward recap. Returns the summary text and node
+/// list when the GM presses send.
+fn forward_export_panel_ui(
+    ui: &mut Ui,
+    manager: &NapcatMessageManager,
+    target_id: &str,
+    messages: &[NapcatMessage],
+    draft: &mut ForwardExportDraft,
+) -> Option<(String, Vec<serde_json::Value>)> {
+    let message_count = messages.len().max(1);
+    let audiences = manager.forward_audience_options();
+    if !audiences
+        .iter()
+        .any(|(_, access)| *access == draft.audience)
+    {
+        draft.audience = PlayerAccess::default();
+    }
+    ui.horizontal_wrapped(|ui| {
+        ui.label("范围");
+        ui.add(
+            egui::DragValue::new(&mut draft.start)
+                .range(1..=message_count)
+                .speed(1),
+        );
+        ui.label("至");
+        ui.add(
+            egui::DragValue::new(&mut draft.end)
+                .range(1..=message_count)
+                .speed(1),
+        );
+        if ui.small_button("全部").clicked() {
+            draft.start = 1;
+            draft.end = message_count;
+        }
+        ui.label("受众");
+        let selected_label = audiences
+            .iter()
+            .find(|(_, access)| *access == draft.audience)
+            .map(|(label, _)| label.clone())
+            .unwrap_or_default();
+        egui::ComboBox::from_id_salt(("chat_forward_audience", target_id))
+            .selected_text(selected_label)
+            .show_ui(ui, |ui| {
+                for (label, access) in &audiences {
+                    ui.selectable_value(
+                        &mut draft.audience,
+                        access.clone(),
+                        label,
+                    );
+                }
+            });
+    });
+    draft.start = draft.start.clamp(1, message_count);
+    draft.end = draft.end.clamp(1, message_count);
+    if draft.start > draft.end {
+        std::mem::swap(&mut draft.start, &mut draft.end);
+    }
+
+    let selected = manager.forward_export_messages(
+        target_id,
+        messages,
+        draft.start - 1..=draft.end - 1,
+        &draft.audience,
+    );
+    let mut send = None;
+    ui.horizontal_wrapped(|ui| {
+        ui.small(format!(
+            "可见 {}/{} 条",
+            selected.len(),
+            draft.end + 1 - draft.start
+        ));
+        if let (Some(first), Some(last)) = (selected.first(), selected.last()) {
+            ui.small(format!(
+                "{} … {}",
+                message_segments_preview(first),
+                message_segments_preview(last)
+            ));
+        }
+        if draft.audience.is_gm {
+            ui.colored_label(
+                egui::Color32::from_rgb(210, 90, 70),
+                "GM 范围可能包含私密内容",
+            );
+        }
+        if ui
+            .add_enabled(
+                !selected.is_empty(),
+                egui::Button::new("发送合并转发"),
+            )
+            .clicked()
+        {
+            draft.status.clear();
+            send = Some((
+                format!("[合并转发] {}条消息", selected.len()),
+                forward_export_nodes(&selected),
+            ));
+        }
+        if !draft.status.is_empty() {
+            ui.small(&draft.status);
+        }
+    });
+    send
+}
+
+fn message_segments_preview(message: &NapcatMessage) -> String {
+    let preview = message
+        .data
+        .message
+        .iter()
+        .filter_map(message_segment_preview)
+        .collect::<String>();
+    let preview = preview.trim();
+    let preview = match preview.char_indices().nth(CHAT_REPLY_QUOTE_MAX_CHARS) {
+        Some((index, _)) => format!("{}…", &preview[..index]),
+        None => preview.to_owned(),
+    };
+    format!(
+        "{}: {preview}",
+        message.data.sender.nickname
+    )
+}
+
 fn message_row_ui(
     ui: &mut Ui,
     message: &NapcatMessage,
+    thread: &[NapcatMessage],
     row_width: f32,
     image_textures: &mut Local<HashMap<String, TextureHandle>>,
-) {
+) -> Option<String> {
     let is_self = message.data.self_id == message.data.user_id;
     let max_message_width = if row_width < 120.0 {
         row_width
@@ -2795,44 +4177,228 @@ fn message_row_ui(
                 ui.set_max_width(max_message_width);
                 ui.with_layout(
                     egui::Layout::top_down(egui::Align::RIGHT),
-                    |ui| {
-                        message_text_ui(ui, message, image_textures);
-                    },
-                );
-            });
+                    |ui| message_text_ui(ui, message, thread, image_textures),
+                )
+                .inner
+            })
+            .inner
         } else {
-            ui.vertical(|ui| {
-                ui.set_width(max_message_width);
-                ui.set_max_width(max_message_width);
-                message_text_ui(ui, message, image_textures);
-            });
+            let insertion = ui
+                .vertical(|ui| {
+                    ui.set_width(max_message_width);
+                    ui.set_max_width(max_message_width);
+                    message_text_ui(ui, message, thread, image_textures)
+                })
+                .inner;
             ui.add_space(margin_width);
+            insertion
         }
-    });
+    })
+    .inner
 }
 
+/// Renders one chat message. Returns CQ code text to append to the composer when the
+/// sender name's context menu asks to reply to or mention this message.
 fn message_text_ui(
     ui: &mut Ui,
     message: &NapcatMessage,
+    thread: &[NapcatMessage],
     image_textures: &mut Local<HashMap<String, TextureHandle>>,
-) {
-    ui.label(&message.data.sender.nickname);
+) -> Option<String> {
+    let mut insertion = None;
+    ui.label(&message.data.sender.nickname)
+        .interact(egui::Sense::click())
+        .on_hover_text("右键回复或@")
+        .context_menu(|ui| {
+            if !message.data.message_id.is_empty() && ui.button("回复此消息").clicked() {
+                insertion = Some(format!(
+                    "[CQ:reply,id={}]",
+                    escape_cq_param(&message.data.message_id)
+                ));
+                ui.close();
+            }
+            if matches!(
+                message.data.message_type,
+                NapcatMessageType::Group
+            ) && message.data.self_id != message.data.user_id
+                && ui.button("@发送者").clicked()
+            {
+                insertion = Some(format!(
+                    "[CQ:at,qq={}] ",
+                    message.data.sender.user_id
+                ));
+                ui.close();
+            }
+        });
+
+    let mut inline = Vec::new();
     for chain in &message.data.message {
         match &chain.variant {
-            NapcatMessageChainType::Text { data: text_data } => {
-                ui.add(
-                    egui::Label::new(text_data.text.trim())
-                        .wrap()
-                        .selectable(false),
-                );
-            },
-            NapcatMessageChainType::Image { data } => {
-                message_image_ui(ui, data, image_textures);
+            NapcatMessageChainType::Text { .. }
+            | NapcatMessageChainType::At { .. }
+            | NapcatMessageChainType::Face { .. } => inline.push(chain),
+            variant => {
+                message_inline_segments_ui(ui, &std::mem::take(&mut inline));
+                match variant {
+                    NapcatMessageChainType::Image { data } => {
+                        message_image_ui(ui, data, image_textures);
+                    },
+                    NapcatMessageChainType::Reply { data } => {
+                        message_reply_quote_ui(ui, &data.id, thread);
+                    },
+                    NapcatMessageChainType::Record { data } => {
+                        let link = segment_link(&[&data.url, &data.file], &data.path);
+                        match link {
+                            Some(link) => {
+                                if ui.button("▶ 语音").on_hover_text(&link).clicked() {
+                                    ui.ctx().open_url(egui::OpenUrl::new_tab(link));
+                                }
+                            },
+                            None => {
+                                ui.label("[语音]");
+                            },
+                        }
+                    },
+                    NapcatMessageChainType::File { data } => {
+                        let label = format!("📎 {}", file_display_name(data));
+                        match segment_link(&[&data.url], &data.path) {
+                            Some(link) => {
+                                ui.hyperlink_to(label, link);
+                            },
+                            None => {
+                                ui.label(label);
+                            },
+                        }
+                    },
+                    NapcatMessageChainType::Forward { data } => {
+                        egui::CollapsingHeader::new(format!(
+                            "[合并转发] {}条",
+                            data.content.len()
+                        ))
+                        .id_salt((
+                            "chat-forward",
+                            &message.data.message_id,
+                            &data.id,
+                        ))
+                        .show(ui, |ui| {
+                            if data.content.is_empty() {
+                                ui.small("NapCat未附带转发内容");
+                            }
+                            for node in &data.content {
+                                ui.add(
+                                    egui::Label::new(format!(
+                                        "{}: {}",
+                                        node.nickname, node.text
+                                    ))
+                                    .wrap(),
+                                );
+                            }
+                        });
+                    },
+                    NapcatMessageChainType::Json { data } => {
+                        let prompt =
+                            json_card_prompt(data).unwrap_or_else(|| "卡片消息".to_owned());
+                        egui::Frame::new()
+                            .stroke(ui.visuals().widgets.noninteractive.bg_stroke)
+                            .corner_radius(4)
+                            .inner_margin(4)
+                            .show(ui, |ui| {
+                                ui.label(format!("[卡片] {prompt}"));
+                            })
+                            .response
+                            .on_hover_text(&data.data);
+                    },
+                    NapcatMessageChainType::Text { .. }
+                    | NapcatMessageChainType::At { .. }
+                    | NapcatMessageChainType::Face { .. }
+                    | NapcatMessageChainType::Source(_)
+                    | NapcatMessageChainType::Unsupported => {},
+                }
             },
-            NapcatMessageChainType::Source(_) => {},
-            NapcatMessageChainType::Unsupported => {},
         }
     }
+    message_inline_segments_ui(ui, &inline);
+    insertion
+}
+
+fn message_inline_segments_ui(ui: &mut Ui, chains: &[&NapcatMessageChain]) {
+    if chains.is_empty() {
+        return;
+    }
+    if let [chain] = chains {
+        if let NapcatMessageChainType::Text { data } = &chain.variant {
+            ui.add(egui::Label::new(data.text.trim()).wrap().selectable(false));
+            return;
+        }
+    }
+
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 2.0;
+        for chain in chains {
+            let Some(preview) = message_segment_preview(chain) else {
+                continue;
+            };
+            match &chain.variant {
+                NapcatMessageChainType::At { data } => {
+                    egui::Frame::new()
+                        .fill(ui.visuals().selection.bg_fill)
+                        .corner_radius(4)
+                        .inner_margin(egui::Margin::symmetric(4, 0))
+                        .show(ui, |ui| {
+                            ui.label(
+                                egui::RichText::new(preview)
+                                    .color(ui.visuals().selection.stroke.color),
+                            );
+                        })
+                        .response
+                        .on_hover_text(&data.qq);
+                },
+                NapcatMessageChainType::Face { data } => {
+                    ui.label(preview)
+                        .on_hover_text(format!("表情 #{}", data.id));
+                },
+                _ => {
+                    let text = preview.trim();
+                    if !text.is_empty() {
+                        ui.add(egui::Label::new(text).wrap().selectable(false));
+                    }
+                },
+            }
+        }
+    });
+}
+
+fn message_reply_quote_ui(ui: &mut Ui, reply_id: &str, thread: &[NapcatMessage]) {
+    let quoted = thread
+        .iter()
+        .rev()
+        .find(|message| !reply_id.is_empty() && message.data.message_id == reply_id);
+    let text = quoted
+        .map(message_segments_preview)
+        .unwrap_or_else(|| format!("回复消息 #{reply_id}"));
+    egui::Frame::new()
+        .fill(ui.visuals().faint_bg_color)
+        .corner_radius(4)
+        .inner_margin(egui::Margin::symmetric(6, 2))
+        .show(ui, |ui| {
+            ui.add(
+                egui::Label::new(egui::RichText::new(format!("↪ {text}")).weak())
+                    .wrap()
+                    .selectable(false),
+            );
+        });
+}
+
+/// Prefers a remote URL and falls back to a local cache path as a `file://` link.
+fn segment_link(urls: &[&String], path: &str) -> Option<String> {
+    urls.iter()
+        .map(|url| url.trim())
+        .find(|url| url.contains("://"))
+        .map(str::to_owned)
+        .or_else(|| {
+            let path = path.trim();
+            (!path.is_empty()).then(|| format!("file://{}", path.replace('\\', "/")))
+        })
 }
 
 fn message_image_ui(
@@ -2924,7 +4490,10 @@ pub fn get_nickname_lens(target_id: String, messages: &Vec<NapcatMessage>) -> (&
                 NapcatMessageChainType::Image { .. } => {
                     len += 12;
                 },
-                NapcatMessageChainType::Unsupported => {},
+                NapcatMessageChainType::Unsupported | NapcatMessageChainType::Reply { .. } => {},
+                _ => {
+                    len += message_segment_preview(chain).map_or(0, |preview| preview.len());
+                },
             };
         }
 
@@ -4280,6 +5849,7 @@ fn quick_character_windows(
         let mut open = true;
         let mut changed = false;
         let mut cast_action = None;
+        let mut use_item_index = None;
         let window_max_width = ctx
             .content_rect()
             .width()
@@ -4334,6 +5904,13 @@ fn quick_character_windows(
                     scene_positions,
                     player_camera_positions,
                 );
+                use_item_index = quick_use_item_ui(
+                    ui,
+                    &target_id,
+                    character,
+                    character_edit_state,
+                    &skill_pool_snapshot,
+                );
                 ui.separator();
                 ui.collapsing("编辑角色", |ui| {
                     changed |= character_editor_ui(
@@ -4350,6 +5927,15 @@ fn quick_character_windows(
                 });
             });
 
+        if let Some(item_index) = use_item_index {
+            changed |= use_character_item(
+                manager,
+                rule_engine_state,
+                &target_id,
+                item_index,
+            )
+            .is_ok();
+        }
         if !open {
             closed_targets.push(target_id);
         }
@@ -4587,6 +6173,75 @@ fn quick_cast_ui(
     action
 }
 
+fn quick_use_item_ui(
+    ui: &mut Ui,
+    caster_id: &str,
+    character: &PlayerCharacter,
+    edit_state: &mut CharacterEditState,
+    skill_pool: &[SkillPoolEntry],
+) -> Option<usize> {
+    let items = character
+        .inventory
+        .items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| !item.use_rule.trim().is_empty())
+        .collect::<Vec<_>>();
+    if items.is_empty() {
+        return None;
+    }
+    let selected = edit_state
+        .quick_use_item_index
+        .entry(caster_id.to_owned())
+        .or_insert(0);
+    if *selected >= items.len() {
+        *selected = 0;
+    }
+
+    let mut used = None;
+    egui::CollapsingHeader::new("使用物品")
+        .default_open(true)
+        .show(ui, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                ui.label("物品");
+                egui::ComboBox::from_id_salt(format!("quick_use_item_{caster_id}"))
+                    .selected_text(format!(
+                        "{} ×{}",
+                        items[*selected].1.name, items[*selected].1.stack
+                    ))
+                    .show_ui(ui, |ui| {
+                        for (index, (_, item)) in items.iter().enumerate() {
+                            ui.selectable_value(
+                                selected,
+                                index,
+                                format!("{} ×{}", item.name, item.stack),
+                            );
+                        }
+                    });
+                let (item_index, item) = items[*selected];
+                let usable = quick_cast_effect(
+                    &item.use_rule,
+                    &SkillRuleArgs::default(),
+                    None,
+                    None,
+                    skill_pool,
+                )
+                .is_some();
+                if ui
+                    .add_enabled(usable, egui::Button::new("使用"))
+                    .on_hover_text(item.use_rule.as_str())
+                    .clicked()
+                {
+                    used = Some(item_index);
+                }
+                if !usable {
+                    ui.small("使用效果需要是可解析的固定伤害、治疗或状态规则。");
+                }
+            });
+        });
+    used
+}
+
 fn quick_cast_skills(character: &mut PlayerCharacter) -> Vec<QuickCastSkill> {
     normalize_character_skill_fields(character);
     character
@@ -5030,7 +6685,7 @@ fn quick_cast_character_targets(manager: &NapcatMessageManager) -> Vec<(String,
 }
 
 fn apply_quick_cast_action(
-    manager: &mut ResMut<Persistent<NapcatMessageManager>>,
+    manager: &mut NapcatMessageManager,
     rule_engine_state: &mut RuleEngineState,
     action: QuickCastAction,
 ) -> bool {
@@ -5041,7 +6696,7 @@ fn apply_quick_cast_action(
     {
         affected_ids.push(action.caster_id.clone());
     }
-    let changed = apply_quick_cast_action_to_manager(manager.as_mut(), action);
+    let changed = apply_quick_cast_action_to_manager(manager, action);
     if changed {
         let skill_pool = manager.skill_pool.clone();
         for target_id in affected_ids {
@@ -5061,6 +6716,137 @@ fn apply_quick_cast_action(
     changed
 }
 
+/// Uses one unit of the caster's bag item at `item_index` outside battle. Every effect of the
+/// item's rule lands on the caster through the quick-cast resolution, and the stack shrinks only
+/// when the effect applied.
+pub(crate) fn use_character_item(
+    manager: &mut NapcatMessageManager,
+    rule_engine_state: &mut RuleEngineState,
+    caster_id: &str,
+    item_index: usize,
+) -> Result<String, String> {
+    let Some(item) = manager
+        .player_characters
+        .get(caster_id)
+        .and_then(|character| character.inventory.items.get(item_index))
+        .cloned()
+    else {
+        return Err("背包里没有这个物品。".to_owned());
+    };
+    let mut effect = quick_cast_effect(
+        &item.use_rule,
+        &SkillRuleArgs::default(),
+        None,
+        None,
+        &manager.skill_pool,
+    )
+    .ok_or_else(|| {
+        format!(
+            "【{}】的使用效果无法解析，需要是“主动使用”开头的固定伤害、治疗或状态规则。",
+            item.name
+        )
+    })?;
+    let granted_buffs = match &mut effect {
+        QuickCastEffect::Sequence(effects) => effects
+            .iter_mut()
+            .filter_map(|resolved| {
+                resolved.targets = vec![caster_id.to_owned()];
+                match &resolved.effect {
+                    QuickCastEffect::GrantBuff { buff, .. } => Some(buff.name.clone()),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>(),
+        QuickCastEffect::GrantBuff { buff, .. } => vec![buff.name.clone()],
+        _ => Vec::new(),
+    };
+    let vitals = |manager: &NapcatMessageManager| {
+        manager
+            .player_characters
+            .get(caster_id)
+            .map(|character| {
+                (
+                    character.hp,
+                    character.mp,
+                    character.max_hp,
+                )
+            })
+            .unwrap_or_default()
+    };
+    let (hp_before, mp_before, _) = vitals(manager);
+    let action = QuickCastAction {
+        caster_id: caster_id.to_owned(),
+        skill: QuickCastSkill {
+            index: CONSUMABLE_ITEM_SKILL_INDEX,
+            name: item.name.clone(),
+            note: item.use_rule.clone(),
+            skill_type: None,
+            legacy_buff_machine_json: None,
+            mp_cost: 0.0,
+            cooldown_turns: 0,
+            cooldown_left: None,
+            target_count: None,
+            target_class: None,
+            range: None,
+            arg_values: SkillRuleArgs::default(),
+        },
+        targets: vec![caster_id.to_owned()],
+        effect: Some(effect),
+        cast_turn: quick_cast_cooldown_turn(manager, caster_id),
+        force: false,
+    };
+    if !apply_quick_cast_action(manager, rule_engine_state, action) {
+        return Err(format!(
+            "现在无法使用【{}】。",
+            item.name
+        ));
+    }
+    let remaining = manager
+        .player_characters
+        .get_mut(caster_id)
+        .and_then(|character| {
+            consume_character_item(character, item_index)?;
+            Some(
+                character
+                    .inventory
+                    .items
+                    .iter()
+                    .filter(|left| left.name == item.name)
+                    .map(|left| left.stack)
+                    .sum::<u32>(),
+            )
+        })
+        .unwrap_or_default();
+
+    let (hp_after, mp_after, max_hp) = vitals(manager);
+    let mut reply = format!(
+        "使用了【{}】，剩余{}个。",
+        item.name, remaining
+    );
+    if (hp_after - hp_before).abs() > f32::EPSILON {
+        reply.push_str(&format!(
+            "\n生命值：{} → {}/{}",
+            format_character_number(hp_before),
+            format_character_number(hp_after),
+            format_character_number(max_hp)
+        ));
+    }
+    if (mp_after - mp_before).abs() > f32::EPSILON {
+        reply.push_str(&format!(
+            "\n法力值：{} → {}",
+            format_character_number(mp_before),
+            format_character_number(mp_after)
+        ));
+    }
+    if !granted_buffs.is_empty() {
+        reply.push_str(&format!(
+            "\n获得状态：{}",
+            granted_buffs.join("、")
+        ));
+    }
+    Ok(reply)
+}
+
 fn apply_quick_cast_action_to_manager(
     manager: &mut NapcatMessageManager,
     mut action: QuickCastAction,
@@ -5179,13 +6965,15 @@ fn apply_quick_cast_action_to_manager(
         if !action.force {
             caster.mp = (caster.mp - action.skill.mp_cost).max(0.0);
         }
-        caster.skill_last_cast_turns.insert(
-            action.skill.index.to_string(),
-            action.cast_turn,
-        );
-        caster
-            .skill_cooldown_ready_turns
-            .remove(&action.skill.index.to_string());
+        if action.skill.index != CONSUMABLE_ITEM_SKILL_INDEX {
+            caster.skill_last_cast_turns.insert(
+                action.skill.index.to_string(),
+                action.cast_turn,
+            );
+            caster
+                .skill_cooldown_ready_turns
+                .remove(&action.skill.index.to_string());
+        }
         (
             source_damage_multiplier,
             source_healing_multiplier,
@@ -5440,6 +7228,16 @@ fn character_editor_ui(
             .text_edit_singleline(&mut character.nickname)
             .changed();
     });
+    ui.columns(2, |columns| {
+        columns[0].label("种族");
+        changed |= columns[0]
+            .text_edit_singleline(&mut character.race)
+            .changed();
+        columns[1].label("背景");
+        changed |= columns[1]
+            .text_edit_singleline(&mut character.background)
+            .changed();
+    });
     ui.label("图片URL");
     changed |= ui.text_edit_singleline(&mut character.image).changed();
 
@@ -5674,11 +7472,7 @@ fn character_editor_ui(
     );
 
     if derived_stats_changed {
-        if let Some(base_stats) = character.buff_base_stats.take() {
-            restore_character_base_stats(character, base_stats);
-        }
-        update_character_from_status_with_config(character, &stat_config);
-        sync_character_buffs(
+        refresh_character_derived_stats(
             target_id,
             character,
             &stat_config,
@@ -6491,9 +8285,14 @@ fn character_status_block(status: &CharacterStatus) -> StatusBlock {
     }
 }
 
-fn character_creation_step_options() -> [(CharacterCreationStep, &'static str); 14] {
+fn character_creation_step_options() -> [(CharacterCreationStep, &'static str); 17] {
     [
         (CharacterCreationStep::Normal, "普通"),
+        (CharacterCreationStep::Race, "种族"),
+        (
+            CharacterCreationStep::Background,
+            "背景",
+        ),
         (CharacterCreationStep::Str, "STR"),
         (CharacterCreationStep::Agi, "AGI"),
         (CharacterCreationStep::Dex, "DEX"),
@@ -6511,6 +8310,7 @@ fn character_creation_step_options() -> [(CharacterCreationStep, &'static str);
             CharacterCreationStep::ConfirmSkill,
             "确认技能",
         ),
+        (CharacterCreationStep::Kit, "起始装备"),
         (CharacterCreationStep::Image, "图片"),
         (CharacterCreationStep::Nickname, "昵称"),
     ]
@@ -6811,6 +8611,7 @@ fn character_buff_editor_ui(
                     remove_index = Some(index);
                 }
             });
+            changed |= buff_conditions_ui(ui, &mut buff.conditions);
             for effect in &buff.effects {
                 ui.small(format_buff_effect(effect));
             }
@@ -6849,6 +8650,7 @@ fn character_buff_editor_ui(
             buff_field_combo(ui, &mut draft.field);
             buff_value_ui(ui, &mut draft.value);
         });
+        buff_conditions_ui(ui, &mut draft.conditions);
         if ui.button("应用buff").clicked() {
             let name = draft.name.trim();
             character.active_buffs.push(BuffSpec {
@@ -6863,6 +8665,7 @@ fn character_buff_editor_ui(
                     value: draft.value,
                 }],
                 tick_actions: Vec::new(),
+                conditions: draft.conditions.clone(),
             });
             changed = true;
         }
@@ -7117,6 +8920,7 @@ fn character_inventory_editor_ui(
                                         ui,
                                         &mut item.stat_effects,
                                     );
+                                    changed |= item_use_rule_editor_ui(ui, &mut item.use_rule);
                                 },
                             );
                         });
@@ -7140,6 +8944,25 @@ fn character_inventory_editor_ui(
     (changed, equipment_changed)
 }
 
+fn buff_conditions_ui(ui: &mut Ui, conditions: &mut Vec<BuffCondition>) -> bool {
+    let mut changed = false;
+    ui.horizontal_wrapped(|ui| {
+        ui.small("控制");
+        for condition in BuffCondition::ALL {
+            let mut enabled = conditions.contains(&condition);
+            if ui.checkbox(&mut enabled, condition.label()).changed() {
+                if enabled {
+                    conditions.push(condition);
+                } else {
+                    conditions.retain(|existing| *existing != condition);
+                }
+                changed = true;
+            }
+        }
+    });
+    changed
+}
+
 fn buff_kind_combo(ui: &mut Ui, kind: &mut BuffKind) -> bool {
     let mut changed = false;
     egui::ComboBox::from_label("类型")
@@ -7266,117 +9089,6 @@ fn character_hotbar_slot_short_label(
     }
 }
 
-fn shift_character_hotbar_after_remove(
-    hotbar: &mut [CharacterHotbarSlot],
-    removed: CharacterHotbarSlot,
-) {
-    for slot in hotbar {
-        *slot = match (*slot, removed) {
-            (CharacterHotbarSlot::Item(index), CharacterHotbarSlot::Item(removed_index))
-                if index == removed_index =>
-            {
-                CharacterHotbarSlot::Empty
-            },
-            (CharacterHotbarSlot::Item(index), CharacterHotbarSlot::Item(removed_index))
-                if index > removed_index =>
-            {
-                CharacterHotbarSlot::Item(index - 1)
-            },
-            (CharacterHotbarSlot::Skill(index), CharacterHotbarSlot::Skill(removed_index))
-                if index == removed_index =>
-            {
-                CharacterHotbarSlot::Empty
-            },
-            (CharacterHotbarSlot::Skill(index), CharacterHotbarSlot::Skill(removed_index))
-                if index > removed_index =>
-            {
-                CharacterHotbarSlot::Skill(index - 1)
-            },
-            (current, _) => current,
-        };
-    }
-}
-
-fn remove_character_inventory_item(character: &mut PlayerCharacter, index: usize, equip: bool) {
-    if index >= character.inventory.items.len() {
-        return;
-    }
-    shift_character_hotbar_after_remove(
-        &mut character.inventory.hotbar,
-        CharacterHotbarSlot::Item(index),
-    );
-    if equip {
-        equip_inventory_item(&mut character.inventory, index);
-    } else {
-        character.inventory.items.remove(index);
-    }
-}
-
-fn normalize_item(item: &mut InventoryItem) -> bool {
-    let mut changed = false;
-    if item.max_stack == 0 {
-        item.max_stack = 1;
-        changed = true;
-    }
-    if item.stack == 0 {
-        item.stack = 1;
-        changed = true;
-    }
-    if item.stack > item.max_stack {
-        item.stack = item.max_stack;
-        changed = true;
-    }
-    changed
-}
-
-fn add_item_to_inventory(inventory: &mut CharacterInventory, mut item: InventoryItem) {
-    normalize_item(&mut item);
-    if !item.name.trim().is_empty() && item.max_stack > 1 {
-        let mut remaining = item.stack;
-        for existing in &mut inventory.items {
-            if same_stackable_item(existing, &item) && existing.stack < existing.max_stack {
-                let free = existing.max_stack - existing.stack;
-                let moved = free.min(remaining);
-                existing.stack += moved;
-                remaining -= moved;
-                if remaining == 0 {
-                    return;
-                }
-            }
-        }
-        item.stack = remaining;
-    }
-    inventory.items.push(item);
-}
-
-fn same_stackable_item(left: &InventoryItem, right: &InventoryItem) -> bool {
-    left.name == right.name
-        && left.description == right.description
-        && left.icon == right.icon
-        && left.quality == right.quality
-        && left.equipment_slot == right.equipment_slot
-        && left.max_stack == right.max_stack
-        && left.item_level == right.item_level
-        && left.soulbound == right.soulbound
-        && left.stat_effects == right.stat_effects
-        && left.max_stack > 1
-}
-
-fn equip_inventory_item(inventory: &mut CharacterInventory, index: usize) {
-    if index >= inventory.items.len() {
-        return;
-    }
-    let item = inventory.items.remove(index);
-    let slot = item.equipment_slot;
-    if slot == EquipmentSlot::None {
-        inventory.items.insert(index, item);
-        return;
-    }
-    if let Some(previous) = inventory.equipment.insert(slot, item) {
-        add_item_to_inventory(inventory, previous);
-    }
-}
-
 fn item_display_name(item: &InventoryItem) -> String {
     if item.name.trim().is_empty() {
         "未命名物品".to_owned()
@@ -7434,17 +9146,6 @@ fn inventory_quality_options() -> [InventoryQuality; 6] {
     ]
 }
 
-fn inventory_quality_label(quality: InventoryQuality) -> &'static str {
-    match quality {
-        InventoryQuality::Poor => "粗糙",
-        InventoryQuality::Common => "普通",
-        InventoryQuality::Uncommon => "优秀",
-        InventoryQuality::Rare => "精良",
-        InventoryQuality::Epic => "史诗",
-        InventoryQuality::Legendary => "传说",
-    }
-}
-
 fn item_quality_color(quality: InventoryQuality) -> egui::Color32 {
     match quality {
         InventoryQuality::Poor => egui::Color32::from_gray(150),
@@ -7456,46 +9157,25 @@ fn item_quality_color(quality: InventoryQuality) -> egui::Color32 {
     }
 }
 
-fn equipment_slot_options() -> [EquipmentSlot; 16] {
-    [
-        EquipmentSlot::None,
-        EquipmentSlot::Head,
-        EquipmentSlot::Neck,
-        EquipmentSlot::Shoulder,
-        EquipmentSlot::Back,
-        EquipmentSlot::Chest,
-        EquipmentSlot::Wrist,
-        EquipmentSlot::Hands,
-        EquipmentSlot::Waist,
-        EquipmentSlot::Legs,
-        EquipmentSlot::Feet,
-        EquipmentSlot::Finger,
-        EquipmentSlot::Trinket,
-        EquipmentSlot::MainHand,
-        EquipmentSlot::OffHand,
-        EquipmentSlot::Ranged,
-    ]
-}
-
-fn equipment_slot_label(slot: EquipmentSlot) -> &'static str {
-    match slot {
-        EquipmentSlot::Head => "头部",
-        EquipmentSlot::Neck => "颈部",
-        EquipmentSlot::Shoulder => "肩部",
-        EquipmentSlot::Back => "背部",
-        EquipmentSlot::Chest => "胸部",
-        EquipmentSlot::Wrist => "手腕",
-        EquipmentSlot::Hands => "手",
-        EquipmentSlot::Waist => "腰部",
-        EquipmentSlot::Legs => "腿部",
-        EquipmentSlot::Feet => "脚",
-        EquipmentSlot::Finger => "戒指",
-        EquipmentSlot::Trinket => "饰品",
-        EquipmentSlot::MainHand => "主手",
-        EquipmentSlot::OffHand => "副手",
-        EquipmentSlot::Ranged => "远程",
-        EquipmentSlot::None => "非装备",
+/// Recomputes level- and attribute-derived stats, then reapplies active buffs on top.
+pub(crate) fn refresh_character_derived_stats(
+    target_id: &str,
+    character: &mut PlayerCharacter,
+    stat_config: &TrpgBasicConfig,
+    rule_engine_state: &mut RuleEngineState,
+    skill_pool: &[SkillPoolEntry],
+) {
+    if let Some(base_stats) = character.buff_base_stats.take() {
+        restore_character_base_stats(character, base_stats);
     }
+    update_character_from_status_with_config(character, stat_config);
+    sync_character_buffs(
+        target_id,
+        character,
+        stat_config,
+        rule_engine_state,
+        skill_pool,
+    );
 }
 
 pub(crate) fn sync_character_buffs(
@@ -7549,6 +9229,33 @@ pub(crate) fn sync_character_buffs(
     }
 }
 
+/// Registers every player character's skill rules and buffs with the rule engine without
+/// touching the stored characters, so triggers also fire for characters no editor has opened.
+pub(crate) fn sync_rule_engine_characters(
+    manager: &NapcatMessageManager,
+    rule_engine_state: &mut RuleEngineState,
+) {
+    for (target_id, character) in &manager.player_characters {
+        let stat_config = manager.character_stat_config_for_target(target_id);
+        let stats = character
+            .buff_base_stats
+            .clone()
+            .unwrap_or_else(|| CharacterBuffBaseStats::from_character(character));
+        sync_character_skill_rules_with_stats(
+            target_id,
+            character,
+            &stats,
+            &stat_config,
+            rule_engine_state,
+            &manager.skill_pool,
+        );
+        rule_engine_state.replace_character_buffs(
+            target_id,
+            character_effective_buffs(target_id, character),
+        );
+    }
+}
+
 fn character_effective_buffs(target_id: &str, character: &PlayerCharacter) -> Vec<BuffSpec> {
     let mut buffs = character.active_buffs.clone();
     buffs.extend(character_equipment_buffs(
@@ -7587,6 +9294,7 @@ fn character_equipment_buffs(target_id: &str, character: &PlayerCharacter) -> Ve
             beneficial: true,
             effects: item.stat_effects.clone(),
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         })
         .collect()
 }
@@ -7642,6 +9350,7 @@ fn character_moonberry_talent_passive_buffs(
                 beneficial: true,
                 effects,
                 tick_actions: Vec::new(),
+                conditions: Vec::new(),
             })
         })
         .collect()
@@ -9008,20 +10717,7 @@ fn random_pool_total_weight(pool: &RandomPool) -> f32 {
 }
 
 fn pick_random_pool_entry(pool: &RandomPool) -> Option<RandomPoolEntry> {
-    let total = random_pool_total_weight(pool);
-    if total <= 0.0 {
-        return None;
-    }
-
-    let mut roll = rand::rng().random_range(0.0..total);
-    for entry in pool.entries.iter().filter(|entry| entry.enabled) {
-        let weight = entry.weight.max(0.0);
-        if roll < weight {
-            return Some(entry.clone());
-        }
-        roll -= weight;
-    }
-    None
+    pick_weighted_pool_entry(&pool.entries).cloned()
 }
 
 #[cfg(test)]
@@ -9262,6 +10958,7 @@ fn unit_pool_settings_ui(
                         note: "从玩家角色复制".to_owned(),
                         legacy_member_id: None,
                         character,
+                        loot: UnitLootTable::default(),
                     };
                     prepare_unit_pool_entry(&unit_id, &mut unit);
                     manager.unit_pool.insert(unit_id, unit);
@@ -9457,16 +11154,94 @@ fn unit_pool_entry_editor_ui(ui: &mut Ui, unit_id: &str, unit: &mut UnitPoolEntr
             changed = true;
         }
     });
-    ui.label("备注");
-    changed |= ui
-        .add(
-            egui::TextEdit::multiline(&mut unit.note)
-                .desired_rows(2)
-                .desired_width(ui.available_width().min(CHARACTER_FIELD_MAX_WIDTH)),
-        )
-        .changed();
-
-    changed |= unit_character_template_editor_ui(ui, unit_id, &mut unit.character);
+    ui.label("备注");
+    changed |= ui
+        .add(
+            egui::TextEdit::multiline(&mut unit.note)
+                .desired_rows(2)
+                .desired_width(ui.available_width().min(CHARACTER_FIELD_MAX_WIDTH)),
+        )
+        .changed();
+
+    changed |= unit_character_template_editor_ui(ui, unit_id, &mut unit.character);
+    changed |= unit_loot_table_editor_ui(ui, unit_id, &mut unit.loot);
+    changed
+}
+
+fn unit_loot_table_editor_ui(ui: &mut Ui, unit_id: &str, loot: &mut UnitLootTable) -> bool {
+    let mut changed = false;
+    egui::CollapsingHeader::new(format!(
+        "掉落表 ({})",
+        loot.entries.len()
+    ))
+    .id_salt(("unit_loot_table", unit_id))
+    .show(ui, |ui| {
+        ui.horizontal_wrapped(|ui| {
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut loot.rolls)
+                        .range(0..=99)
+                        .prefix("抽取次数 "),
+                )
+                .changed();
+            let mut drop_percent = loot.drop_chance * 100.0;
+            if ui
+                .add(
+                    egui::DragValue::new(&mut drop_percent)
+                        .range(0.0..=100.0)
+                        .speed(0.5)
+                        .prefix("掉落率 ")
+                        .suffix("%"),
+                )
+                .changed()
+            {
+                loot.drop_chance = drop_percent / 100.0;
+                changed = true;
+            }
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut loot.gold_min)
+                        .range(0..=999_999)
+                        .prefix("金币 "),
+                )
+                .changed();
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut loot.gold_max)
+                        .range(0..=999_999)
+                        .prefix("~ "),
+                )
+                .changed();
+            if loot.gold_max < loot.gold_min {
+                loot.gold_max = loot.gold_min;
+                changed = true;
+            }
+        });
+
+        let mut remove_index = None;
+        for (index, entry) in loot.entries.iter_mut().enumerate() {
+            ui.push_id(("unit_loot_entry", index), |ui| {
+                egui::Frame::new()
+                    .fill(ui.visuals().faint_bg_color)
+                    .corner_radius(4)
+                    .inner_margin(egui::Margin::symmetric(8, 6))
+                    .show(ui, |ui| {
+                        changed |= random_pool_entry_draft_ui(ui, entry);
+                        if ui.button("移除掉落项").clicked() {
+                            remove_index = Some(index);
+                        }
+                    });
+            });
+        }
+        if let Some(index) = remove_index {
+            loot.entries.remove(index);
+            changed = true;
+        }
+        if ui.button("添加掉落项").clicked() {
+            loot.entries.push(RandomPoolEntry::default());
+            changed = true;
+        }
+    });
     changed
 }
 
@@ -9652,249 +11427,805 @@ fn unit_character_status_editor_ui(ui: &mut Ui, status: &mut CharacterStatus) ->
     changed
 }
 
-fn skill_pool_settings_ui(
+fn skill_pool_settings_ui(
+    ui: &mut Ui,
+    manager: &mut NapcatMessageManager,
+    state: &mut TrpgGroupSettingsState,
+) -> bool {
+    let mut changed = false;
+    changed |= manager.sync_skill_pool_from_completed_characters();
+
+    let auto_count = manager
+        .skill_pool
+        .iter()
+        .filter(|entry| entry.source_key().is_some())
+        .count();
+    let manual_count = manager.skill_pool.len().saturating_sub(auto_count);
+    ui.heading("技能池");
+    ui.horizontal_wrapped(|ui| {
+        ui.small(format!("已兑换技能 {auto_count}"));
+        ui.small(format!("手动技能 {manual_count}"));
+        if ui.button("刷新已兑换技能").clicked() {
+            changed |= manager.sync_skill_pool_from_completed_characters();
+        }
+    });
+
+    if manager.skill_pool.is_empty() {
+        ui.label("还没有技能。完成角色兑换后，技能会自动进入这里。");
+    } else {
+        let mut remove_manual_index = None;
+        egui::ScrollArea::vertical()
+            .id_salt("skill_pool_settings")
+            .max_height(180.0)
+            .show(ui, |ui| {
+                egui::Grid::new(ui.next_auto_id())
+                    .num_columns(7)
+                    .spacing([8.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("技能");
+                        ui.strong("类型");
+                        ui.strong("标签");
+                        ui.strong("来源");
+                        ui.strong("MP");
+                        ui.strong("冷却");
+                        ui.strong("操作");
+                        ui.end_row();
+
+                        for (index, entry) in manager.skill_pool.iter().enumerate() {
+                            ui.label(skill_pool_entry_name(entry));
+                            ui.small(skill_pool_entry_category_label(entry));
+                            ui.small(skill_pool_entry_tags_label(entry));
+                            ui.small(entry.source_character_name.as_deref().unwrap_or("手动"));
+                            ui.label(format_character_number(entry.mp_cost));
+                            ui.label(entry.cooldown_turns.to_string());
+                            if entry.source_key().is_none() {
+                                if ui.button("-").on_hover_text("移除手动技能").clicked() {
+                                    remove_manual_index = Some(index);
+                                }
+                            } else {
+                                ui.small("自动");
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+        if let Some(index) = remove_manual_index {
+            manager.skill_pool.remove(index);
+            changed = true;
+        }
+    }
+
+    ui.collapsing("添加手动技能", |ui| {
+        let draft = &mut state.skill_pool_draft;
+        ui.horizontal_wrapped(|ui| {
+            ui.label("技能名");
+            ui.add(egui::TextEdit::singleline(&mut draft.name).desired_width(140.0));
+            ui.add(
+                egui::DragValue::new(&mut draft.mp_cost)
+                    .range(0.0..=9999.0)
+                    .speed(1.0)
+                    .prefix("MP "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut draft.cooldown_turns)
+                    .range(0..=999)
+                    .speed(1)
+                    .prefix("冷却 "),
+            );
+            ui.label("类型");
+            ui.add(
+                egui::TextEdit::singleline(draft.category.get_or_insert_with(String::new))
+                    .desired_width(100.0),
+            );
+            ui.label("标签");
+            let mut tags = draft.tags.join(" ");
+            if ui
+                .add(egui::TextEdit::singleline(&mut tags).desired_width(140.0))
+                .changed()
+            {
+                draft.tags = tags.split_whitespace().map(str::to_owned).collect();
+            }
+        });
+        ui.label("规则描述");
+        ui.add(
+            egui::TextEdit::multiline(&mut draft.note)
+                .desired_rows(2)
+                .desired_width(ui.available_width().min(CHARACTER_FIELD_MAX_WIDTH)),
+        );
+        if ui.button("加入技能池").clicked() {
+            if let Some(mut entry) = normalized_manual_skill_pool_entry(draft) {
+                entry.source_character_id = None;
+                entry.source_character_name = None;
+                entry.source_skill_index = None;
+                manager.skill_pool.push(entry);
+                state.skill_pool_draft = SkillPoolEntry::default();
+                changed = true;
+            }
+        }
+    });
+
+    changed
+}
+
+fn normalized_manual_skill_pool_entry(draft: &SkillPoolEntry) -> Option<SkillPoolEntry> {
+    let note = draft.note.trim();
+    if note.is_empty() {
+        return None;
+    }
+    let name = if draft.name.trim().is_empty() {
+        "未命名技能".to_owned()
+    } else {
+        draft.name.trim().to_owned()
+    };
+    Some(SkillPoolEntry {
+        name,
+        note: note.to_owned(),
+        mp_cost: draft.mp_cost.max(0.0),
+        cooldown_turns: draft.cooldown_turns,
+        source_character_id: None,
+        source_character_name: None,
+        source_skill_index: None,
+        tags: draft.tags.clone(),
+        category: draft.category.clone(),
+        args: draft.args.clone(),
+        legacy_buff_count: draft.legacy_buff_count,
+        legacy_event_buff_count: draft.legacy_event_buff_count,
+        legacy_has_graph: draft.legacy_has_graph,
+        legacy_buff_json: draft.legacy_buff_json.clone(),
+        legacy_event_buff_json: draft.legacy_event_buff_json.clone(),
+        legacy_graph_json: draft.legacy_graph_json.clone(),
+        legacy_buff_machine_json: draft.legacy_buff_machine_json.clone(),
+        ..Default::default()
+    })
+}
+
+fn item_pool_settings_ui(
+    ui: &mut Ui,
+    manager: &mut NapcatMessageManager,
+    state: &mut TrpgGroupSettingsState,
+    player_targets: &[String],
+) -> bool {
+    let mut changed = false;
+    ui.heading("物品池");
+    ui.small("物品池是GM模板库；发给玩家时会复制一份，装备后属性加成立即进入最终数值。");
+
+    if !player_targets.is_empty() {
+        if !player_targets.contains(&state.item_pool_award_target) {
+            state.item_pool_award_target = player_targets[0].clone();
+        }
+        egui::ComboBox::from_label("发放给玩家")
+            .selected_text(target_display_name(
+                manager,
+                &state.item_pool_award_target,
+            ))
+            .show_ui(ui, |ui| {
+                for target_id in player_targets {
+                    ui.selectable_value(
+                        &mut state.item_pool_award_target,
+                        target_id.clone(),
+                        target_display_name(manager, target_id),
+                    );
+                }
+            });
+    }
+
+    let mut remove_index = None;
+    let mut award_index = None;
+    for (index, item) in manager.item_pool.iter_mut().enumerate() {
+        ui.push_id(("item_pool_entry", index), |ui| {
+            ui.collapsing(
+                format!(
+                    "{} · {} · {}项加成",
+                    item_display_name(item),
+                    equipment_slot_label(item.equipment_slot),
+                    item.stat_effects.len()
+                ),
+                |ui| {
+                    changed |= inventory_item_definition_ui(ui, item);
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(
+                                !player_targets.is_empty(),
+                                egui::Button::new("发给玩家"),
+                            )
+                            .clicked()
+                        {
+                            award_index = Some(index);
+                        }
+                        if ui.button("从物品池移除").clicked() {
+                            remove_index = Some(index);
+                        }
+                    });
+                },
+            );
+        });
+    }
+    if let Some(index) = award_index {
+        if let Some(item) = manager.item_pool.get(index).cloned() {
+            let character = manager
+                .player_characters
+                .entry(state.item_pool_award_target.clone())
+                .or_default();
+            add_item_to_inventory(&mut character.inventory, item);
+            changed = true;
+        }
+    }
+    if let Some(index) = remove_index {
+        manager.item_pool.remove(index);
+        changed = true;
+    }
+
+    ui.separator();
+    ui.collapsing("添加物品模板", |ui| {
+        changed |= inventory_item_definition_ui(ui, &mut state.item_pool_draft);
+        if ui.button("加入物品池").clicked() {
+            let mut item = state.item_pool_draft.clone();
+            if !item.name.trim().is_empty() {
+                item.name = item.name.trim().to_owned();
+                normalize_item(&mut item);
+                manager.item_pool.push(item);
+                state.item_pool_draft = InventoryItem::default();
+                changed = true;
+            }
+        }
+    });
+    changed
+}
+
+fn current_shop_listing_count(manager: &NapcatMessageManager) -> usize {
+    manager
+        .current_group()
+        .map(|group| group.shop.listings.len())
+        .unwrap_or_default()
+}
+
+fn shop_settings_ui(
     ui: &mut Ui,
     manager: &mut NapcatMessageManager,
     state: &mut TrpgGroupSettingsState,
 ) -> bool {
     let mut changed = false;
-    changed |= manager.sync_skill_pool_from_completed_characters();
+    let Some(group_name) = manager.current_trpg_group.clone() else {
+        ui.label("请先在TRPG组设置里选择当前组。");
+        return false;
+    };
+    let Some(group) = manager.trpg_groups.get_mut(&group_name) else {
+        ui.label("当前TRPG组不存在。");
+        return false;
+    };
+    let world_turn = group.world_turn;
+    let shop = &mut group.shop;
+    changed |= shop.restock(world_turn);
 
-    let auto_count = manager
-        .skill_pool
-        .iter()
-        .filter(|entry| entry.source_key().is_some())
-        .count();
-    let manual_count = manager.skill_pool.len().saturating_sub(auto_count);
-    ui.heading("技能池");
+    ui.heading(format!("{group_name} · 商店"));
+    ui.small("玩家私聊【.商店】查看货架，【.购买 物品 数量】与【.出售 物品 数量】交易；每笔交易都记入下方流水。");
     ui.horizontal_wrapped(|ui| {
-        ui.small(format!("已兑换技能 {auto_count}"));
-        ui.small(format!("手动技能 {manual_count}"));
-        if ui.button("刷新已兑换技能").clicked() {
-            changed |= manager.sync_skill_pool_from_completed_characters();
+        ui.label("店名");
+        changed |= ui
+            .add(egui::TextEdit::singleline(&mut shop.name).desired_width(120.0))
+            .changed();
+        ui.label("回收比例");
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut shop.sell_rate_percent)
+                    .range(0..=100)
+                    .suffix("%"),
+            )
+            .changed();
+        if ui.button("全部补满").clicked() {
+            shop.refill();
+            changed = true;
         }
     });
+    ui.collapsing("品质定价", |ui| {
+        ui.small("未填写售价的商品按品质基础价计算，每级物品等级再加价一定比例。");
+        ui.horizontal_wrapped(|ui| {
+            for quality in inventory_quality_options() {
+                ui.colored_label(
+                    item_quality_color(quality),
+                    inventory_quality_label(quality),
+                );
+                changed |= ui
+                    .add(egui::DragValue::new(
+                        shop.pricing.quality_price_mut(quality),
+                    ))
+                    .changed();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("每级加价");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut shop.pricing.item_level_percent)
+                        .range(0..=1000)
+                        .suffix("%"),
+                )
+                .changed();
+        });
+    });
 
-    if manager.skill_pool.is_empty() {
-        ui.label("还没有技能。完成角色兑换后，技能会自动进入这里。");
+    ui.separator();
+    if shop.listings.is_empty() {
+        ui.label("货架为空，可以从物品池上架。");
+    }
+    let mut remove_index = None;
+    for index in 0..shop.listings.len() {
+        let price = shop.buy_price(&shop.listings[index]);
+        let listing = &mut shop.listings[index];
+        ui.push_id(("shop_listing", index), |ui| {
+            ui.horizontal_wrapped(|ui| {
+                ui.colored_label(
+                    item_quality_color(listing.item.quality),
+                    format!(
+                        "{}. {}",
+                        index + 1,
+                        item_display_name(&listing.item)
+                    ),
+                );
+                ui.label("售价");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut listing.price))
+                    .on_hover_text("0 = 按品质定价")
+                    .changed();
+                ui.small(format!("实际 {price}"));
+                ui.label("库存");
+                changed |= ui.add(egui::DragValue::new(&mut listing.stock)).changed();
+                ui.label("上限");
+                changed |= ui
+                    .add(egui::DragValue::new(
+                        &mut listing.max_stock,
+                    ))
+                    .on_hover_text("0 = 不限库存")
+                    .changed();
+                ui.label("每");
+                changed |= ui
+                    .add(egui::DragValue::new(
+                        &mut listing.restock_every_turns,
+                    ))
+                    .changed();
+                ui.label("轮补货");
+                changed |= ui
+                    .add(egui::DragValue::new(
+                        &mut listing.restock_amount,
+                    ))
+                    .on_hover_text("0 = 补满")
+                    .changed();
+                if ui.button("下架").clicked() {
+                    remove_index = Some(index);
+                }
+            });
+            ui.collapsing("物品属性", |ui| {
+                changed |= inventory_item_definition_ui(ui, &mut listing.item);
+            });
+        });
+    }
+    if let Some(index) = remove_index {
+        shop.listings.remove(index);
+        changed = true;
+    }
+
+    if manager.item_pool.is_empty() {
+        ui.small("物品池为空，先在物品池里添加模板。");
     } else {
-        let mut remove_manual_index = None;
-        egui::ScrollArea::vertical()
-            .id_salt("skill_pool_settings")
-            .max_height(180.0)
-            .show(ui, |ui| {
-                egui::Grid::new(ui.next_auto_id())
-                    .num_columns(7)
-                    .spacing([8.0, 4.0])
-                    .striped(true)
-                    .show(ui, |ui| {
-                        ui.strong("技能");
-                        ui.strong("类型");
-                        ui.strong("标签");
-                        ui.strong("来源");
-                        ui.strong("MP");
-                        ui.strong("冷却");
-                        ui.strong("操作");
+        state.shop_item_pick = state.shop_item_pick.min(manager.item_pool.len() - 1);
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("shop_item_pick")
+                .selected_text(item_display_name(
+                    &manager.item_pool[state.shop_item_pick],
+                ))
+                .show_ui(ui, |ui| {
+                    for (index, item) in manager.item_pool.iter().enumerate() {
+                        ui.selectable_value(
+                            &mut state.shop_item_pick,
+                            index,
+                            item_display_name(item),
+                        );
+                    }
+                });
+            if ui.button("从物品池上架").clicked() {
+                let listing = ShopListing::from_item(
+                    &manager.item_pool[state.shop_item_pick],
+                    world_turn,
+                );
+                if let Some(group) = manager.trpg_groups.get_mut(&group_name) {
+                    group.shop.listings.push(listing);
+                    changed = true;
+                }
+            }
+        });
+    }
+
+    let Some(shop) = manager
+        .trpg_groups
+        .get(&group_name)
+        .map(|group| &group.shop)
+    else {
+        return changed;
+    };
+    ui.separator();
+    ui.collapsing(
+        format!("交易流水 ({})", shop.audit_log.len()),
+        |ui| {
+            if shop.audit_log.is_empty() {
+                ui.small("还没有交易。");
+            }
+            egui::Grid::new("shop_audit_log")
+                .striped(true)
+                .show(ui, |ui| {
+                    for header in ["轮次", "玩家", "类型", "物品", "金额", "余额"] {
+                        ui.strong(header);
+                    }
+                    ui.end_row();
+                    for transaction in shop.audit_log.iter().rev() {
+                        ui.label(transaction.world_turn.to_string());
+                        ui.label(
+                            if transaction.character_name.trim().is_empty() {
+                                transaction.player_id.clone()
+                            } else {
+                                format!(
+                                    "{}（{}）",
+                                    transaction.character_name, transaction.player_id
+                                )
+                            },
+                        );
+                        ui.label(transaction.kind.label());
+                        ui.label(format!(
+                            "{} x{}",
+                            transaction.item_name, transaction.quantity
+                        ));
+                        ui.label(match transaction.kind {
+                            ShopTransactionKind::Buy => format!("-{}", transaction.total),
+                            ShopTransactionKind::Sell => format!("+{}", transaction.total),
+                        });
+                        ui.label(transaction.gold_after.to_string());
                         ui.end_row();
+                    }
+                });
+        },
+    );
+    changed
+}
 
-                        for (index, entry) in manager.skill_pool.iter().enumerate() {
-                            ui.label(skill_pool_entry_name(entry));
-                            ui.small(skill_pool_entry_category_label(entry));
-                            ui.small(skill_pool_entry_tags_label(entry));
-                            ui.small(entry.source_character_name.as_deref().unwrap_or("手动"));
-                            ui.label(format_character_number(entry.mp_cost));
-                            ui.label(entry.cooldown_turns.to_string());
-                            if entry.source_key().is_none() {
-                                if ui.button("-").on_hover_text("移除手动技能").clicked() {
-                                    remove_manual_index = Some(index);
+fn current_active_trade_count(manager: &NapcatMessageManager) -> usize {
+    manager
+        .current_group()
+        .map(|group| {
+            group
+                .trades
+                .trades
+                .iter()
+                .filter(|trade| trade.status.is_active())
+                .count()
+        })
+        .unwrap_or_default()
+}
+
+fn current_staged_loot_count(manager: &NapcatMessageManager) -> usize {
+    manager
+        .current_group()
+        .map(|group| group.loot.staged.len())
+        .unwrap_or_default()
+}
+
+fn loot_review_ui(
+    ui: &mut Ui,
+    manager: &mut NapcatMessageManager,
+    state: &mut TrpgGroupSettingsState,
+    player_targets: &[String],
+    napcat_sender: Option<&NapcatIOSender>,
+    ime: &mut ImeManager,
+) -> bool {
+    let mut changed = false;
+    let Some(group_name) = manager.current_trpg_group.clone() else {
+        ui.label("请先在TRPG组设置里选择当前组。");
+        return false;
+    };
+    let Some(group) = manager.trpg_groups.get_mut(&group_name) else {
+        ui.label("当前TRPG组不存在。");
+        return false;
+    };
+
+    ui.heading(format!("{group_name} · 战利品"));
+    ui.small("单位被击败后按单位池的掉落表掷出战利品，默认由击败它的玩家平分。");
+    changed |= ui
+        .checkbox(
+            &mut group.loot.require_gm_approval,
+            "战利品需GM分配",
+        )
+        .changed();
+    let staged = group.loot.staged.clone();
+    if !state.loot_review_status.is_empty() {
+        ui.small(&state.loot_review_status);
+    }
+    ui.separator();
+
+    if staged.is_empty() {
+        ui.label("没有待分配的战利品。");
+    }
+    let mut decision = None;
+    for drop in staged.iter() {
+        ui.push_id(("loot_review", drop.id), |ui| {
+            egui::Frame::new()
+                .fill(ui.visuals().faint_bg_color)
+                .corner_radius(4)
+                .inner_margin(egui::Margin::symmetric(8, 6))
+                .show(ui, |ui| {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.strong(format!("#{} {}", drop.id, drop.source));
+                        ui.label(loot_summary(&drop.items, drop.gold));
+                    });
+                    let recipients = state
+                        .loot_recipients
+                        .entry(drop.id)
+                        .or_insert_with(|| drop.contributors.iter().cloned().collect());
+                    ui.horizontal_wrapped(|ui| {
+                        for target_id in player_targets {
+                            let mut selected = recipients.contains(target_id);
+                            if ui
+                                .checkbox(
+                                    &mut selected,
+                                    target_display_name(manager, target_id),
+                                )
+                                .changed()
+                            {
+                                if selected {
+                                    recipients.insert(target_id.clone());
+                                } else {
+                                    recipients.remove(target_id);
                                 }
-                            } else {
-                                ui.small("自动");
                             }
-                            ui.end_row();
                         }
                     });
-            });
-        if let Some(index) = remove_manual_index {
-            manager.skill_pool.remove(index);
-            changed = true;
-        }
+                    ui.horizontal(|ui| {
+                        if ui.button("分配").clicked() {
+                            decision = Some((drop.id, true));
+                        }
+                        if ui.button("丢弃").clicked() {
+                            decision = Some((drop.id, false));
+                        }
+                    });
+                });
+        });
     }
 
-    ui.collapsing("添加手动技能", |ui| {
-        let draft = &mut state.skill_pool_draft;
-        ui.horizontal_wrapped(|ui| {
-            ui.label("技能名");
-            ui.add(egui::TextEdit::singleline(&mut draft.name).desired_width(140.0));
-            ui.add(
-                egui::DragValue::new(&mut draft.mp_cost)
-                    .range(0.0..=9999.0)
-                    .speed(1.0)
-                    .prefix("MP "),
-            );
-            ui.add(
-                egui::DragValue::new(&mut draft.cooldown_turns)
-                    .range(0..=999)
-                    .speed(1)
-                    .prefix("冷却 "),
-            );
-            ui.label("类型");
-            ui.add(
-                egui::TextEdit::singleline(draft.category.get_or_insert_with(String::new))
-                    .desired_width(100.0),
-            );
-            ui.label("标签");
-            let mut tags = draft.tags.join(" ");
-            if ui
-                .add(egui::TextEdit::singleline(&mut tags).desired_width(140.0))
-                .changed()
-            {
-                draft.tags = tags.split_whitespace().map(str::to_owned).collect();
-            }
-        });
-        ui.label("规则描述");
-        ui.add(
-            egui::TextEdit::multiline(&mut draft.note)
-                .desired_rows(2)
-                .desired_width(ui.available_width().min(CHARACTER_FIELD_MAX_WIDTH)),
-        );
-        if ui.button("加入技能池").clicked() {
-            if let Some(mut entry) = normalized_manual_skill_pool_entry(draft) {
-                entry.source_character_id = None;
-                entry.source_character_name = None;
-                entry.source_skill_index = None;
-                manager.skill_pool.push(entry);
-                state.skill_pool_draft = SkillPoolEntry::default();
+    if let Some((drop_id, approve)) = decision {
+        let recipients = state.loot_recipients.remove(&drop_id).unwrap_or_default();
+        let result = if approve {
+            let recipients = player_targets
+                .iter()
+                .filter(|target_id| recipients.contains(*target_id))
+                .cloned()
+                .collect::<Vec<_>>();
+            approve_staged_loot(
+                manager,
+                &group_name,
+                drop_id,
+                &recipients,
+            )
+        } else {
+            discard_staged_loot(manager, &group_name, drop_id).map(|reply| LootOutcome {
+                reply,
+                notices: Vec::new(),
+            })
+        };
+        match result {
+            Ok(outcome) => {
                 changed = true;
-            }
+                let mut unsent = 0;
+                for (recipient, text) in outcome.notices {
+                    let queued = recipient
+                        .parse::<u64>()
+                        .ok()
+                        .zip(napcat_sender)
+                        .is_some_and(|(user_id, sender)| {
+                            ime.queue_text_send(&recipient, &text, sender, vec![
+                                NapcatSendTarget::Private(user_id),
+                            ])
+                            .is_ok()
+                        });
+                    if !queued {
+                        unsent += 1;
+                    }
+                }
+                state.loot_review_status = if unsent == 0 {
+                    outcome.reply
+                } else {
+                    format!(
+                        "{}（{unsent}条通知未能发送）",
+                        outcome.reply
+                    )
+                };
+            },
+            Err(err) => {
+                state.loot_recipients.insert(drop_id, recipients);
+                state.loot_review_status = err;
+            },
         }
-    });
-
+    }
     changed
 }
 
-fn normalized_manual_skill_pool_entry(draft: &SkillPoolEntry) -> Option<SkillPoolEntry> {
-    let note = draft.note.trim();
-    if note.is_empty() {
-        return None;
-    }
-    let name = if draft.name.trim().is_empty() {
-        "未命名技能".to_owned()
-    } else {
-        draft.name.trim().to_owned()
-    };
-    Some(SkillPoolEntry {
-        name,
-        note: note.to_owned(),
-        mp_cost: draft.mp_cost.max(0.0),
-        cooldown_turns: draft.cooldown_turns,
-        source_character_id: None,
-        source_character_name: None,
-        source_skill_index: None,
-        tags: draft.tags.clone(),
-        category: draft.category.clone(),
-        args: draft.args.clone(),
-        legacy_buff_count: draft.legacy_buff_count,
-        legacy_event_buff_count: draft.legacy_event_buff_count,
-        legacy_has_graph: draft.legacy_has_graph,
-        legacy_buff_json: draft.legacy_buff_json.clone(),
-        legacy_event_buff_json: draft.legacy_event_buff_json.clone(),
-        legacy_graph_json: draft.legacy_graph_json.clone(),
-        legacy_buff_machine_json: draft.legacy_buff_machine_json.clone(),
-        ..Default::default()
-    })
+fn trade_parties_label(manager: &NapcatMessageManager, trade: &PlayerTrade) -> String {
+    format!(
+        "#{} {} ⇄ {}",
+        trade.id,
+        target_display_name(manager, &trade.initiator),
+        target_display_name(manager, &trade.partner)
+    )
 }
 
-fn item_pool_settings_ui(
+fn trade_review_ui(
     ui: &mut Ui,
     manager: &mut NapcatMessageManager,
     state: &mut TrpgGroupSettingsState,
-    player_targets: &[String],
+    napcat_sender: Option<&NapcatIOSender>,
+    ime: &mut ImeManager,
 ) -> bool {
     let mut changed = false;
-    ui.heading("物品池");
-    ui.small("物品池是GM模板库；发给玩家时会复制一份，装备后属性加成立即进入最终数值。");
+    let Some(group_name) = manager.current_trpg_group.clone() else {
+        ui.label("请先在TRPG组设置里选择当前组。");
+        return false;
+    };
+    let Some(group) = manager.trpg_groups.get_mut(&group_name) else {
+        ui.label("当前TRPG组不存在。");
+        return false;
+    };
 
-    if !player_targets.is_empty() {
-        if !player_targets.contains(&state.item_pool_award_target) {
-            state.item_pool_award_target = player_targets[0].clone();
-        }
-        egui::ComboBox::from_label("发放给玩家")
-            .selected_text(target_display_name(
-                manager,
-                &state.item_pool_award_target,
-            ))
-            .show_ui(ui, |ui| {
-                for target_id in player_targets {
-                    ui.selectable_value(
-                        &mut state.item_pool_award_target,
-                        target_id.clone(),
-                        target_display_name(manager, target_id),
-                    );
-                }
-            });
+    ui.heading(format!("{group_name} · 玩家交易"));
+    ui.small(format!(
+        "玩家私聊【.交易 @玩家】发起交易，双方确认后一次性交换；进行中的交易{}分钟无操作自动取消。",
+        TRADE_TIMEOUT_SECS / 60
+    ));
+    changed |= ui
+        .checkbox(
+            &mut group.trades.require_gm_approval,
+            "双方确认后需GM审核",
+        )
+        .changed();
+    let trades = group.trades.trades.clone();
+    if !state.trade_review_status.is_empty() {
+        ui.small(&state.trade_review_status);
     }
+    ui.separator();
 
-    let mut remove_index = None;
-    let mut award_index = None;
-    for (index, item) in manager.item_pool.iter_mut().enumerate() {
-        ui.push_id(("item_pool_entry", index), |ui| {
-            ui.collapsing(
-                format!(
-                    "{} · {} · {}项加成",
-                    item_display_name(item),
-                    equipment_slot_label(item.equipment_slot),
-                    item.stat_effects.len()
-                ),
-                |ui| {
-                    changed |= inventory_item_definition_ui(ui, item);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let mut decision = None;
+    let mut any_active = false;
+    for trade in trades.iter().rev().filter(|trade| trade.status.is_active()) {
+        any_active = true;
+        ui.push_id(("trade_review", trade.id), |ui| {
+            egui::Frame::new()
+                .fill(ui.visuals().faint_bg_color)
+                .corner_radius(4)
+                .inner_margin(egui::Margin::symmetric(8, 6))
+                .show(ui, |ui| {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.strong(trade_parties_label(manager, trade));
+                        ui.label(trade.status.label());
+                        if trade.status == TradeStatus::Open {
+                            ui.small(format!(
+                                "{}后超时",
+                                format_elapsed_ms(
+                                    (trade.updated_at + TRADE_TIMEOUT_SECS).saturating_sub(now)
+                                        * 1000
+                                )
+                            ));
+                        }
+                    });
+                    for (target_id, offer) in [
+                        (&trade.initiator, &trade.initiator_offer),
+                        (&trade.partner, &trade.partner_offer),
+                    ] {
+                        ui.label(format!(
+                            "{}{}：{}",
+                            target_display_name(manager, target_id),
+                            if offer.confirmed { "（已确认）" } else { "" },
+                            offer.summary()
+                        ));
+                    }
+                    if !trade.note.is_empty() {
+                        ui.small(format!("上次失败：{}", trade.note));
+                    }
                     ui.horizontal(|ui| {
-                        if ui
-                            .add_enabled(
-                                !player_targets.is_empty(),
-                                egui::Button::new("发给玩家"),
-                            )
-                            .clicked()
+                        let reason = state.trade_veto_reasons.entry(trade.id).or_default();
+                        ui.add(
+                            egui::TextEdit::singleline(reason)
+                                .hint_text("否决理由（可选）")
+                                .desired_width(160.0),
+                        );
+                        if trade.status == TradeStatus::AwaitingGm && ui.button("批准").clicked()
                         {
-                            award_index = Some(index);
+                            decision = Some((trade.id, true));
                         }
-                        if ui.button("从物品池移除").clicked() {
-                            remove_index = Some(index);
+                        if ui.button("否决").clicked() {
+                            decision = Some((trade.id, false));
                         }
                     });
-                },
-            );
+                });
         });
     }
-    if let Some(index) = award_index {
-        if let Some(item) = manager.item_pool.get(index).cloned() {
-            let character = manager
-                .player_characters
-                .entry(state.item_pool_award_target.clone())
-                .or_default();
-            add_item_to_inventory(&mut character.inventory, item);
-            changed = true;
-        }
+    if !any_active {
+        ui.label("没有进行中的交易。");
     }
-    if let Some(index) = remove_index {
-        manager.item_pool.remove(index);
-        changed = true;
+
+    if let Some((trade_id, approve)) = decision {
+        let result = if approve {
+            approve_trade(manager, &group_name, trade_id, now)
+        } else {
+            let reason = state
+                .trade_veto_reasons
+                .remove(&trade_id)
+                .unwrap_or_default();
+            veto_trade(
+                manager,
+                &group_name,
+                trade_id,
+                &reason,
+                now,
+            )
+        };
+        match result {
+            Ok(outcome) => {
+                changed = true;
+                let mut unsent = 0;
+                for (recipient, text) in outcome.notices {
+                    let queued = recipient
+                        .parse::<u64>()
+                        .ok()
+                        .zip(napcat_sender)
+                        .is_some_and(|(user_id, sender)| {
+                            ime.queue_text_send(&recipient, &text, sender, vec![
+                                NapcatSendTarget::Private(user_id),
+                            ])
+                            .is_ok()
+                        });
+                    if !queued {
+                        unsent += 1;
+                    }
+                }
+                state.trade_review_status = if unsent == 0 {
+                    outcome.reply
+                } else {
+                    format!(
+                        "{}（{unsent}条通知未能发送）",
+                        outcome.reply
+                    )
+                };
+            },
+            Err(err) => state.trade_review_status = err,
+        }
     }
 
     ui.separator();
-    ui.collapsing("添加物品模板", |ui| {
-        changed |= inventory_item_definition_ui(ui, &mut state.item_pool_draft);
-        if ui.button("加入物品池").clicked() {
-            let mut item = state.item_pool_draft.clone();
-            if !item.name.trim().is_empty() {
-                item.name = item.name.trim().to_owned();
-                normalize_item(&mut item);
-                manager.item_pool.push(item);
-                state.item_pool_draft = InventoryItem::default();
-                changed = true;
+    let closed = trades
+        .iter()
+        .rev()
+        .filter(|trade| !trade.status.is_active())
+        .collect::<Vec<_>>();
+    ui.collapsing(
+        format!("交易记录 ({})", closed.len()),
+        |ui| {
+            if closed.is_empty() {
+                ui.small("还没有结束的交易。");
             }
-        }
-    });
+            for trade in closed {
+                ui.label(format!(
+                    "{} · {}：{} ⇄ {}",
+                    trade_parties_label(manager, trade),
+                    trade.status.label(),
+                    trade.initiator_offer.summary(),
+                    trade.partner_offer.summary()
+                ));
+                if !trade.note.is_empty() {
+                    ui.small(&trade.note);
+                }
+            }
+        },
+    );
     changed
 }
 
@@ -9949,6 +12280,34 @@ fn inventory_item_definition_ui(ui: &mut Ui, item: &mut InventoryItem) -> bool {
             changed |= item_stat_effects_editor_ui(ui, &mut item.stat_effects);
         },
     );
+    changed |= item_use_rule_editor_ui(ui, &mut item.use_rule);
+    changed
+}
+
+fn item_use_rule_editor_ui(ui: &mut Ui, use_rule: &mut String) -> bool {
+    ui.label("使用效果");
+    let changed = ui
+        .add(
+            egui::TextEdit::singleline(use_rule)
+                .hint_text("主动使用对自己回复20点生命值")
+                .desired_width(ui.available_width().min(CHARACTER_FIELD_MAX_WIDTH)),
+        )
+        .changed();
+    if !use_rule.trim().is_empty() {
+        match parse_skill_note(
+            use_rule,
+            &SkillRuleArgs::default(),
+            None,
+        ) {
+            Ok(Some(ast)) => {
+                ui.small(ast.explain());
+            },
+            Ok(None) => {},
+            Err(err) => {
+                ui.colored_label(egui::Color32::RED, err);
+            },
+        }
+    }
     changed
 }
 
@@ -10468,31 +12827,222 @@ fn write_napcat_manager_export(manager: &NapcatMessageManager, path: &str) -> Re
     write_text_export(path, manager.to_export_json())
 }
 
-fn write_text_export(path: &str, text: Result<String, String>) -> Result<(), String> {
-    let path = Path::new(path.trim());
-    if path.as_os_str().is_empty() {
-        return Err("路径不能为空".to_owned());
+fn write_text_export(path: &str, text: Result<String, String>) -> Result<(), String> {
+    let path = Path::new(path.trim());
+    if path.as_os_str().is_empty() {
+        return Err("路径不能为空".to_owned());
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    fs::write(path, text?).map_err(|err| err.to_string())
+}
+
+fn read_napcat_manager_export(path: &str) -> Result<NapcatMessageManager, String> {
+    let text = read_text_import(path)?;
+    NapcatMessageManager::from_export_json(&text)
+}
+
+fn read_text_import(path: &str) -> Result<String, String> {
+    let path = Path::new(path.trim());
+    if path.as_os_str().is_empty() {
+        return Err("路径不能为空".to_owned());
+    }
+    fs::read_to_string(path).map_err(|err| err.to_string())
+}
+
+fn creation_template_ui(
+    ui: &mut Ui,
+    group_name: &str,
+    template: &mut TrpgCreationTemplate,
+    item_pool_names: &[String],
+    skill_pool_tags: &[String],
+) -> bool {
+    let mut changed = false;
+    ui.horizontal_wrapped(|ui| {
+        egui::ComboBox::from_id_salt(("creation_stat_mode", group_name))
+            .selected_text(template.stat_mode.label())
+            .show_ui(ui, |ui| {
+                for mode in CreationStatMode::ALL {
+                    changed |= ui
+                        .selectable_value(
+                            &mut template.stat_mode,
+                            mode,
+                            mode.label(),
+                        )
+                        .changed();
+                }
+            });
+        if template.stat_mode == CreationStatMode::Rolled {
+            ui.label("每项属性掷");
+            changed |= ui
+                .add(egui::TextEdit::singleline(&mut template.stat_roll).desired_width(80.0))
+                .changed();
+            if parse_dice_expression(&template.stat_roll).is_err() {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    "表达式无效",
+                );
+            }
+        } else {
+            ui.small("按初始属性点逐项分配");
+        }
+    });
+
+    changed |= ui.checkbox(&mut template.race_step, "种族步骤").changed();
+    if template.race_step {
+        changed |= creation_choices_ui(ui, &mut template.races, "种族");
+    }
+    changed |= ui
+        .checkbox(
+            &mut template.background_step,
+            "背景步骤",
+        )
+        .changed();
+    if template.background_step {
+        changed |= creation_choices_ui(ui, &mut template.backgrounds, "背景");
+    }
+
+    ui.horizontal_wrapped(|ui| {
+        ui.label("可选技能标签");
+        let mut tags = template.skill_tags.join(" ");
+        if ui
+            .add(egui::TextEdit::singleline(&mut tags).desired_width(160.0))
+            .on_hover_text("留空时玩家自由填写技能描述，由GM审核")
+            .changed()
+        {
+            template.skill_tags = tags.split_whitespace().map(str::to_owned).collect();
+            changed = true;
+        }
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut template.skill_picks)
+                    .range(0..=99)
+                    .prefix("可选数量 "),
+            )
+            .on_hover_text("0为不限")
+            .changed();
+    });
+    if !skill_pool_tags.is_empty() {
+        ui.small(format!(
+            "技能池标签：{}",
+            skill_pool_tags.join(" ")
+        ));
+    }
+
+    ui.label("起始装备");
+    let mut remove_kit = None;
+    for (index, kit) in template.starting_kits.iter_mut().enumerate() {
+        egui::Frame::new()
+            .fill(ui.visuals().faint_bg_color)
+            .corner_radius(4)
+            .inner_margin(egui::Margin::symmetric(8, 6))
+            .show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    changed |= ui
+                        .add(egui::TextEdit::singleline(&mut kit.name).desired_width(120.0))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut kit.gold)
+                                .range(0..=9_999_999)
+                                .prefix("金币 "),
+                        )
+                        .changed();
+                    ui.menu_button("添加物品", |ui| {
+                        if item_pool_names.is_empty() {
+                            ui.small("物品池为空");
+                        }
+                        for name in item_pool_names {
+                            if ui.button(name).clicked() {
+                                kit.items.push(name.clone());
+                                changed = true;
+                            }
+                        }
+                    });
+                    if ui.button("移除套装").clicked() {
+                        remove_kit = Some(index);
+                    }
+                });
+                let mut remove_item = None;
+                ui.horizontal_wrapped(|ui| {
+                    for (item_index, name) in kit.items.iter().enumerate() {
+                        let missing = !item_pool_names.contains(name);
+                        let response = ui.small_button(format!("{name} ×"));
+                        let response = if missing {
+                            response.on_hover_text("物品池中已没有这个物品，建卡时会跳过")
+                        } else {
+                            response
+                        };
+                        if response.clicked() {
+                            remove_item = Some(item_index);
+                        }
+                    }
+                });
+                if let Some(item_index) = remove_item {
+                    kit.items.remove(item_index);
+                    changed = true;
+                }
+            });
     }
-    if let Some(parent) = path
-        .parent()
-        .filter(|parent| !parent.as_os_str().is_empty())
-    {
-        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    if let Some(index) = remove_kit {
+        template.starting_kits.remove(index);
+        changed = true;
     }
-    fs::write(path, text?).map_err(|err| err.to_string())
-}
-
-fn read_napcat_manager_export(path: &str) -> Result<NapcatMessageManager, String> {
-    let text = read_text_import(path)?;
-    NapcatMessageManager::from_export_json(&text)
+    if ui.button("添加起始装备").clicked() {
+        template.starting_kits.push(StartingKit {
+            name: format!(
+                "套装{}",
+                template.starting_kits.len() + 1
+            ),
+            ..Default::default()
+        });
+        changed = true;
+    }
+    changed
 }
 
-fn read_text_import(path: &str) -> Result<String, String> {
-    let path = Path::new(path.trim());
-    if path.as_os_str().is_empty() {
-        return Err("路径不能为空".to_owned());
+fn creation_choices_ui(ui: &mut Ui, choices: &mut Vec<CreationChoice>, title: &str) -> bool {
+    let mut changed = false;
+    let mut remove_index = None;
+    for (index, choice) in choices.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            changed |= ui
+                .add(
+                    egui::TextEdit::singleline(&mut choice.name)
+                        .hint_text("名称")
+                        .desired_width(100.0),
+                )
+                .changed();
+            changed |= ui
+                .add(
+                    egui::TextEdit::singleline(&mut choice.description)
+                        .hint_text("简介")
+                        .desired_width(220.0),
+                )
+                .changed();
+            if ui.small_button("移除").clicked() {
+                remove_index = Some(index);
+            }
+        });
     }
-    fs::read_to_string(path).map_err(|err| err.to_string())
+    if let Some(index) = remove_index {
+        choices.remove(index);
+        changed = true;
+    }
+    ui.horizontal_wrapped(|ui| {
+        if ui.button(format!("添加{title}")).clicked() {
+            choices.push(CreationChoice::default());
+            changed = true;
+        }
+        if choices.is_empty() {
+            ui.small(format!("没有选项时玩家自由填写{title}"));
+        }
+    });
+    changed
 }
 
 fn trpg_basic_config_ui(ui: &mut Ui, config: &mut TrpgBasicConfig) -> bool {
@@ -10661,6 +13211,284 @@ fn trpg_basic_config_ui(ui: &mut Ui, config: &mut TrpgBasicConfig) -> bool {
     changed
 }
 
+fn trpg_check_config_ui(ui: &mut Ui, config: &mut TrpgCheckConfig) -> bool {
+    let mut changed = false;
+    ui.horizontal_wrapped(|ui| {
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut config.dice_sides)
+                    .range(2..=1000)
+                    .prefix("检定骰 d"),
+            )
+            .changed();
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut config.critical_success_roll)
+                    .range(0..=1000)
+                    .prefix("大成功骰值≥ "),
+            )
+            .on_hover_text("自然骰值达到该值即为大成功，0为关闭")
+            .changed();
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut config.critical_failure_roll)
+                    .range(0..=1000)
+                    .prefix("大失败骰值≤ "),
+            )
+            .on_hover_text("自然骰值不超过该值即为大失败，0为关闭")
+            .changed();
+    });
+    ui.horizontal_wrapped(|ui| {
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut config.critical_success_margin)
+                    .range(0..=999)
+                    .prefix("超出难度 "),
+            )
+            .on_hover_text("总值超出难度该值及以上即为大成功，0为关闭")
+            .changed();
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut config.critical_failure_margin)
+                    .range(0..=999)
+                    .prefix("低于难度 "),
+            )
+            .on_hover_text("总值低于难度该值及以上即为大失败，0为关闭")
+            .changed();
+    });
+    changed
+}
+
+fn check_request_scope_label(manager: &NapcatMessageManager, scope: &str) -> String {
+    if scope == BROADCAST_SCOPE_ALL || scope.starts_with(BROADCAST_SCOPE_PARTY_PREFIX) {
+        return broadcast_scope_label(None, scope);
+    }
+    target_display_name(manager, scope)
+}
+
+fn check_request_members(group: &TrpgGroup, scope: &str) -> Vec<String> {
+    if scope == BROADCAST_SCOPE_ALL {
+        return group.players.clone();
+    }
+    if let Some(party_id) = scope.strip_prefix(BROADCAST_SCOPE_PARTY_PREFIX) {
+        return group
+            .players
+            .iter()
+            .filter(|player_id| group.party_id_for_player(player_id) == Some(party_id))
+            .cloned()
+            .collect();
+    }
+    group
+        .players
+        .iter()
+        .filter(|player_id| player_id.as_str() == scope)
+        .cloned()
+        .collect()
+}
+
+fn check_request_input_id(group_name: &str, request_id: u64) -> String {
+    format!("check-request:{group_name}:{request_id}")
+}
+
+fn trpg_check_requests_ui(
+    ui: &mut Ui,
+    manager: &mut NapcatMessageManager,
+    state: &mut TrpgGroupSettingsState,
+    napcat_sender: Option<&NapcatIOSender>,
+    ime: &mut ImeManager,
+    group_name: &str,
+) -> bool {
+    let Some(snapshot) = manager.trpg_groups.get(group_name).cloned() else {
+        return false;
+    };
+    let mut changed = false;
+    if let Some(group) = manager.trpg_groups.get_mut(group_name) {
+        changed |= trpg_check_config_ui(ui, &mut group.check_config);
+    }
+    ui.separator();
+
+    let draft = state
+        .check_request_drafts
+        .entry(group_name.to_owned())
+        .or_default();
+    let mut scopes = vec![BROADCAST_SCOPE_ALL.to_owned()];
+    let mut party_names = snapshot.parties.keys().cloned().collect::<Vec<_>>();
+    party_names.sort();
+    scopes.extend(
+        party_names
+            .iter()
+            .map(|party_name| broadcast_party_scope(party_name)),
+    );
+    scopes.extend(snapshot.players.iter().cloned());
+    if !scopes.contains(&draft.scope) {
+        draft.scope = BROADCAST_SCOPE_ALL.to_owned();
+    }
+
+    let mut request_clicked = false;
+    ui.horizontal_wrapped(|ui| {
+        ui.label("检定对象");
+        egui::ComboBox::from_id_salt(("check_request_scope", group_name))
+            .selected_text(check_request_scope_label(
+                manager,
+                &draft.scope,
+            ))
+            .show_ui(ui, |ui| {
+                for scope in &scopes {
+                    ui.selectable_value(
+                        &mut draft.scope,
+                        scope.clone(),
+                        check_request_scope_label(manager, scope),
+                    );
+                }
+            });
+        egui::ComboBox::from_id_salt(("check_request_status", group_name))
+            .selected_text(draft.status.as_str())
+            .show_ui(ui, |ui| {
+                for status in CHARACTER_STATUS_NAMES {
+                    ui.selectable_value(
+                        &mut draft.status,
+                        status.to_owned(),
+                        status,
+                    );
+                }
+            });
+        ui.add(
+            egui::DragValue::new(&mut draft.dc)
+                .range(-999..=999)
+                .prefix("DC "),
+        );
+        request_clicked = ui.button("发起检定").clicked();
+    });
+
+    if request_clicked {
+        let draft = draft.clone();
+        let members = check_request_members(&snapshot, &draft.scope);
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let request_id = manager.trpg_groups.get_mut(group_name).and_then(|group| {
+            group.open_check_request(
+                &draft.status,
+                draft.dc,
+                members.clone(),
+                time,
+            )
+        });
+        let status = match (request_id, napcat_sender) {
+            (None, _) => "检定对象中没有玩家".to_owned(),
+            (Some(_), None) => {
+                changed = true;
+                "检定已记录，NapCat未连接，未发送通知".to_owned()
+            },
+            (Some(request_id), Some(sender)) => {
+                changed = true;
+                let targets = private_targets_for_member_ids(manager, members.iter());
+                let text = format!(
+                    "GM请求你进行【{}】检定（DC {}）。请发送【.检定】完成检定。",
+                    draft.status, draft.dc
+                );
+                match ime.queue_text_send(
+                    &check_request_input_id(group_name, request_id),
+                    text,
+                    sender,
+                    targets,
+                ) {
+                    Ok(()) => format!("已请求{}名玩家检定", members.len()),
+                    Err(err) => format!("检定已记录，通知发送失败：{err}"),
+                }
+            },
+        };
+        state
+            .check_request_status
+            .insert(group_name.to_owned(), status);
+    }
+    if let Some(status) = state.check_request_status.get(group_name) {
+        ui.small(status);
+    }
+
+    if snapshot.check_requests.is_empty() {
+        ui.small("还没有检定请求。");
+        return changed;
+    }
+
+    let mut close_request = None;
+    let mut delete_request = None;
+    for request in snapshot.check_requests.iter().rev() {
+        ui.separator();
+        ui.horizontal_wrapped(|ui| {
+            ui.strong(format!(
+                "#{} {}检定 DC {}",
+                request.id, request.status, request.dc
+            ));
+            ui.small(format!(
+                "已完成 {}/{}",
+                request.results.len(),
+                request.targets.len()
+            ));
+            if request.closed {
+                ui.small("已关闭");
+            } else if ui.button("关闭").clicked() {
+                close_request = Some(request.id);
+            }
+            if ui.button("删除").clicked() {
+                delete_request = Some(request.id);
+            }
+        });
+        egui::Grid::new((
+            "check_request_results",
+            group_name,
+            request.id,
+        ))
+        .num_columns(5)
+        .striped(true)
+        .show(ui, |ui| {
+            for header in ["玩家", "骰值", "属性", "总值", "结果"] {
+                ui.strong(header);
+            }
+            ui.end_row();
+            for target_id in &request.targets {
+                ui.label(target_display_name(manager, target_id));
+                match request.results.get(target_id) {
+                    Some(result) => {
+                        ui.label(result.natural.to_string());
+                        ui.label(result.modifier.to_string());
+                        ui.label(result.total.to_string());
+                        ui.label(result.outcome.explain());
+                    },
+                    None => {
+                        ui.label("-");
+                        ui.label("-");
+                        ui.label("-");
+                        ui.label(if request.closed { "未检定" } else { "等待中" });
+                    },
+                }
+                ui.end_row();
+            }
+        });
+    }
+
+    if let Some(group) = manager.trpg_groups.get_mut(group_name) {
+        if let Some(request_id) = close_request {
+            if let Some(request) = group
+                .check_requests
+                .iter_mut()
+                .find(|request| request.id == request_id)
+            {
+                request.closed = true;
+                changed = true;
+            }
+        }
+        if let Some(request_id) = delete_request {
+            group
+                .check_requests
+                .retain(|request| request.id != request_id);
+            changed = true;
+        }
+    }
+    changed
+}
+
 fn f32_config_drag(
     ui: &mut Ui,
     label: &str,
@@ -11742,6 +14570,19 @@ fn trpg_group_settings_window(
 
     let player_targets = sorted_pool_targets(manager, false);
     let group_chat_targets = sorted_pool_targets(manager, true);
+    let item_pool_names = manager
+        .item_pool
+        .iter()
+        .map(|item| item.name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>();
+    let mut skill_pool_tags = manager
+        .skill_pool
+        .iter()
+        .flat_map(|entry| entry.tags.iter().cloned())
+        .collect::<Vec<_>>();
+    skill_pool_tags.sort();
+    skill_pool_tags.dedup();
     let mut changed = false;
     let mut group_to_delete = None;
     let mut character_to_delete = None;
@@ -12031,6 +14872,15 @@ fn trpg_group_settings_window(
                                         changed |=
                                             trpg_basic_config_ui(ui, &mut group.basic_config);
                                     });
+                                    ui.collapsing("建卡模板", |ui| {
+                                        changed |= creation_template_ui(
+                                            ui,
+                                            &group_name,
+                                            &mut group.creation_template,
+                                            &item_pool_names,
+                                            &skill_pool_tags,
+                                        );
+                                    });
                                 }
                             });
 
@@ -12382,6 +15232,17 @@ fn trpg_group_settings_window(
                                 }
                             });
 
+                            ui.collapsing("属性检定", |ui| {
+                                changed |= trpg_check_requests_ui(
+                                    ui,
+                                    manager,
+                                    state,
+                                    napcat_sender,
+                                    ime,
+                                    &group_name,
+                                );
+                            });
+
                             if let Some(promotion) = legacy_group_surfaces_ui(
                                 ui,
                                 manager,
@@ -12607,6 +15468,8 @@ pub fn ui_system(
         &mut locals.group_broadcast_scopes;
     let chat_player_visible_previews: &mut Local<HashMap<String, String>> =
         &mut locals.chat_player_visible_previews;
+    let chat_forward_exports: &mut Local<HashMap<String, ForwardExportDraft>> =
+        &mut locals.chat_forward_exports;
     let chat_list_player_visible_filter: &mut Local<Option<String>> =
         &mut locals.chat_list_player_visible_filter;
     let voxel_editor: &mut VoxelEditorState = &mut locals.voxel_editor;
@@ -12685,6 +15548,37 @@ pub fn ui_system(
         }
     }
 
+    if let Some(profiles) = locals.napcat_connection_profiles.as_deref_mut() {
+        napcat_connections_window(
+            ctx,
+            &mut locals.napcat_connection_panel,
+            profiles,
+            locals.napcat_connection_statuses.as_deref(),
+        );
+    }
+    if let Some(queue) = locals.napcat_outbound_queue.as_deref_mut() {
+        napcat_delivery_log_window(
+            ctx,
+            &mut locals.napcat_delivery_log,
+            queue,
+        );
+    }
+    if let Some(scheduler) = locals.napcat_scheduler.as_deref_mut() {
+        napcat_schedule_window(
+            ctx,
+            &mut locals.napcat_schedule,
+            scheduler,
+            &manager,
+        );
+    }
+    if let Some(config) = locals.napcat_control_api.as_deref_mut() {
+        napcat_control_api_window(
+            ctx,
+            &mut locals.napcat_control_api_panel,
+            config,
+            locals.napcat_control_api_state.as_deref(),
+        );
+    }
     trpg_group_settings_window(
         ctx,
         &mut manager,
@@ -12763,6 +15657,10 @@ pub fn ui_system(
                     ui,
                     &mut rule_engine_state,
                     &mut battle_round_state,
+                    &mut locals.napcat_connection_panel.open,
+                    &mut locals.napcat_delivery_log.open,
+                    &mut locals.napcat_schedule.open,
+                    &mut locals.napcat_control_api_panel.open,
                 );
                 pool_menu_button(ui, &manager, trpg_group_settings);
             });
@@ -13757,6 +16655,7 @@ pub fn ui_system(
                     active_trpg_group.as_deref(),
                     turn_count_drafts,
                     chat_player_visible_previews,
+                    chat_forward_exports,
                     &mut rule_engine_state,
                     player_view_request.as_deref_mut(),
                 );
@@ -13838,14 +16737,9 @@ fn append_local_sent_message(
     let mut message = NapcatMessage {
         data: NapcatMessageData {
             time,
+            message_id: String::new(),
             message_type,
-            message: vec![NapcatMessageChain {
-                variant: NapcatMessageChainType::Text {
-                    data: TextData {
-                        text: text.to_owned(),
-                    },
-                },
-            }],
+            message: parse_outbound_segments(text),
             self_id,
             user_id: self_id,
             group_id,
@@ -13860,6 +16754,7 @@ fn append_local_sent_message(
             party_id: None,
             visibility: Visibility::Public,
             access_scope_resolved: false,
+            account_id: String::new(),
         },
     };
     manager.annotate_message_access(&target_id, &mut message);
@@ -13871,6 +16766,7 @@ fn append_local_sent_message(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::napcat::TextData;
 
     #[test]
     fn repeated_character_skill_rows_have_distinct_widget_ids() {
@@ -13902,6 +16798,7 @@ mod tests {
             skill_pool: Vec::new(),
             item_pool: Vec::new(),
             unit_pool: HashMap::default(),
+            chat_target_accounts: HashMap::default(),
         }
     }
 
@@ -13909,6 +16806,7 @@ mod tests {
         NapcatMessage {
             data: NapcatMessageData {
                 time: 1780132600,
+                message_id: String::new(),
                 message_type: NapcatMessageType::Private,
                 message: vec![NapcatMessageChain {
                     variant: NapcatMessageChainType::Text {
@@ -13931,6 +16829,7 @@ mod tests {
                 party_id: None,
                 visibility: Visibility::Public,
                 access_scope_resolved: false,
+                account_id: String::new(),
             },
         }
     }
@@ -13939,6 +16838,7 @@ mod tests {
         NapcatMessage {
             data: NapcatMessageData {
                 time: 1780132600,
+                message_id: String::new(),
                 message_type: NapcatMessageType::Group,
                 message: vec![NapcatMessageChain {
                     variant: NapcatMessageChainType::Text {
@@ -13961,6 +16861,7 @@ mod tests {
                 party_id: None,
                 visibility: Visibility::Public,
                 access_scope_resolved: false,
+                account_id: String::new(),
             },
         }
     }
@@ -14222,6 +17123,7 @@ mod tests {
                 value: BuffValue::Set(0.5),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         }
     }
 
@@ -14553,11 +17455,13 @@ mod tests {
                 request_id: first.request_id,
                 target_id: input_id.clone(),
                 error: None,
+                delivery_unknown: false,
             },
             crate::napcat::NapcatSendResult {
                 request_id: second.request_id,
                 target_id: input_id.clone(),
                 error: None,
+                delivery_unknown: false,
             },
         ]);
 
@@ -14631,11 +17535,13 @@ mod tests {
                 request_id: first.request_id,
                 target_id: input_id.clone(),
                 error: None,
+                delivery_unknown: false,
             },
             crate::napcat::NapcatSendResult {
                 request_id: second.request_id,
                 target_id: input_id.clone(),
                 error: None,
+                delivery_unknown: false,
             },
         ]);
 
@@ -14963,31 +17869,6 @@ mod tests {
         assert!(character.buff_base_stats.is_none());
     }
 
-    #[test]
-    fn equipping_item_moves_previous_item_to_bag() {
-        let mut inventory = CharacterInventory::default();
-        inventory.items.push(InventoryItem {
-            name: "旧剑".to_owned(),
-            equipment_slot: EquipmentSlot::MainHand,
-            ..Default::default()
-        });
-        inventory.items.push(InventoryItem {
-            name: "新剑".to_owned(),
-            equipment_slot: EquipmentSlot::MainHand,
-            ..Default::default()
-        });
-
-        equip_inventory_item(&mut inventory, 0);
-        equip_inventory_item(&mut inventory, 0);
-
-        assert_eq!(
-            inventory.equipment[&EquipmentSlot::MainHand].name,
-            "新剑"
-        );
-        assert_eq!(inventory.items.len(), 1);
-        assert_eq!(inventory.items[0].name, "旧剑");
-    }
-
     #[test]
     fn removing_inventory_and_skill_entries_repairs_hotbar_indexes() {
         let mut character = PlayerCharacter::default();
@@ -15288,6 +18169,7 @@ mod tests {
             request_id: outbound.request_id,
             target_id: outbound.target_id,
             error: None,
+            delivery_unknown: false,
         }]);
 
         assert_eq!(sent, vec![ChatInputSendCompletion {
@@ -16711,6 +19593,7 @@ mod tests {
                     Vec3::new(3.1, 0.0, 0.0),
                 ),
             ]),
+            cover: HashMap::new(),
         };
         let camera_positions = ScenePlayerCameraPositions {
             positions: HashMap::from([(1, Vec3::ZERO)]),
@@ -16752,6 +19635,7 @@ mod tests {
                 "far".to_owned(),
                 Vec3::new(3.1, 0.0, 0.0),
             )]),
+            cover: HashMap::new(),
         };
         let camera_positions = ScenePlayerCameraPositions {
             positions: HashMap::from([(1, Vec3::ZERO)]),
@@ -16795,6 +19679,7 @@ mod tests {
                 "far".to_owned(),
                 Vec3::new(20.0, 0.0, 0.0),
             )]),
+            cover: HashMap::new(),
         };
         let camera_positions = ScenePlayerCameraPositions {
             positions: HashMap::from([(1, Vec3::ZERO)]),
@@ -16861,6 +19746,7 @@ mod tests {
                 "far".to_owned(),
                 Vec3::new(10.4, 0.0, 0.0),
             )]),
+            cover: HashMap::new(),
         };
         let camera_positions = ScenePlayerCameraPositions {
             positions: HashMap::from([(1, Vec3::ZERO)]),
@@ -16918,6 +19804,7 @@ mod tests {
                     Vec3::new(3.1, 0.0, 0.0),
                 ),
             ]),
+            cover: HashMap::new(),
         };
         let camera_positions = ScenePlayerCameraPositions {
             positions: HashMap::from([(1, Vec3::ZERO)]),
@@ -17383,6 +20270,7 @@ mod tests {
                 value: BuffValue::AddPercent(100.0),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         };
         let guard_buff = crate::rule_engine::RuleBuffTemplate {
             name: "守护".to_owned(),
@@ -17395,6 +20283,7 @@ mod tests {
                 value: BuffValue::AddPercent(-50.0),
             }],
             tick_actions: Vec::new(),
+            conditions: Vec::new(),
         };
         let mut rule_engine_state = RuleEngineState::default();
         let config = TrpgBasicConfig::default();
@@ -1,6 +1,8 @@
 use std::{
+    cmp::Reverse,
     collections::{
         hash_map::DefaultHasher,
+        BinaryHeap,
         HashMap,
         HashSet,
     },
@@ -85,6 +87,10 @@ use tokio_tungstenite::tungstenite::protocol::Message;
 use voxxelmaxx::prelude::*;
 
 use crate::{
+    battle_round::{
+        BattleParticipantSnapshot,
+        BattleRoundStore,
+    },
     napcat::{
         CharacterHotbarSlot,
         NapcatIOSender,
@@ -93,12 +99,15 @@ use crate::{
         PlayerCharacter,
     },
     rule_engine::{
+        buff_conditions,
+        BuffCondition,
         BuffField,
         BuffValue,
     },
     scene::{
         SceneCaptureRequests,
         SceneCharacterPositions,
+        SceneCover,
     },
     voxel_radiance::{
         VoxelRadianceCascade,
@@ -107,7 +116,7 @@ use crate::{
     },
 };
 
-const VOXEL_SIZE: f32 = 0.25;
+pub(crate) const VOXEL_SIZE: f32 = 0.25;
 /// Horizontal physics-body chunk radius around the DM and every player camera.
 const VOXEL_PHYSICS_CHUNK_LOAD_RADIUS: i32 = 8;
 const VOXEL_DM_GIZMO_RENDER_LAYER: usize = 1;
@@ -141,7 +150,7 @@ const FIRST_PERSON_JUMP_SPEED: f32 = 3.4;
 const FIRST_PERSON_FLY_SPEED: f32 = 3.5;
 const FIRST_PERSON_FOV_RADIANS: f32 = 70.0_f32.to_radians();
 const FIRST_PERSON_DOUBLE_TAP_SECONDS: f32 = 0.32;
-const DEFAULT_POSSESSION_MOVEMENT_BONUS: f32 = 10.0;
+pub(crate) const DEFAULT_POSSESSION_MOVEMENT_BONUS: f32 = 10.0;
 const ORBITAL_LAYOUT_SCALE: i32 = 5;
 const RESEARCH_STATION_CENTER: IVec3 = IVec3::new(-100 * ORBITAL_LAYOUT_SCALE, 0, 0);
 const SENSOR_STATION_CENTER: IVec3 = IVec3::new(100 * ORBITAL_LAYOUT_SCALE, 0, 0);
@@ -231,9 +240,18 @@ struct PersistedVoxelPlayerCamera {
     rotation: [f32; 4],
 }
 
+/// Where the GM stood a battle unit, which has no observation camera to follow.
+#[derive(Clone, Serialize, Deserialize)]
+struct PersistedVoxelUnitPosition {
+    target_id: String,
+    translation: [f32; 3],
+}
+
 #[derive(Resource, Default, Serialize, Deserialize)]
 struct VoxelPlayerCameraStore {
     cameras: Vec<PersistedVoxelPlayerCamera>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    units: Vec<PersistedVoxelUnitPosition>,
 }
 
 #[derive(Resource, Default)]
@@ -1244,6 +1262,8 @@ impl Plugin for TrpgVoxelPlugin {
         .init_resource::<VoxelPlayerStandeeAssets>()
         .init_resource::<VoxelToolGunDragState>()
         .init_resource::<VoxelPhysicsChunkLoader>()
+        .init_resource::<VoxelBattleMovementState>()
+        .init_resource::<VoxelCoverRays>()
         .insert_resource(player_camera_store)
         .insert_resource(inventory_store)
         .insert_resource(toolbar_settings_store)
@@ -1294,11 +1314,22 @@ impl Plugin for TrpgVoxelPlugin {
                     control_first_person_player,
                     control_voxel_camera,
                     sync_possessed_player_camera,
+                    enforce_battle_standee_movement,
                     sync_voxel_player_cameras,
                     sync_voxel_player_standees,
-                    sync_voxel_scene_character_positions,
+                    (
+                        sync_voxel_scene_character_positions,
+                        sync_voxel_scene_cover,
+                    )
+                        .chain(),
                     capture_voxel_player_view,
-                    draw_voxel_target.run_if(crate::replay::replay_video_capture_inactive),
+                    (
+                        draw_voxel_target,
+                        draw_battle_reachable_area,
+                        draw_battle_cover_rays,
+                        draw_battle_unit_positions,
+                    )
+                        .run_if(crate::replay::replay_video_capture_inactive),
                     animate_planet_clouds,
                     animate_voxel_materials,
                     persist_voxel_inventory,
@@ -1310,7 +1341,11 @@ impl Plugin for TrpgVoxelPlugin {
         )
         .add_systems(
             EguiPrimaryContextPass,
-            voxel_player_camera_panel.run_if(crate::replay::replay_video_capture_inactive),
+            (
+                voxel_player_camera_panel,
+                voxel_battle_unit_panel,
+            )
+                .run_if(crate::replay::replay_video_capture_inactive),
         );
     }
 }
@@ -3604,19 +3639,151 @@ fn sync_voxel_player_standees(
     }
 }
 
+/// Player standees plus the units the GM stood in the scene for the running battle. The resource
+/// is only written when someone moved, so cover is measured again only then.
 fn sync_voxel_scene_character_positions(
     mut positions: ResMut<SceneCharacterPositions>,
+    battle_store: Option<Res<Persistent<BattleRoundStore>>>,
+    camera_store: Res<Persistent<VoxelPlayerCameraStore>>,
     standees: Query<(&VoxelPlayerStandee, &Transform)>,
 ) {
-    positions.positions.clear();
-    positions.positions.extend(
-        standees.iter().map(|(standee, transform)| {
+    let units = battle_store
+        .as_deref()
+        .map(|store| active_battle_unit_ids(store))
+        .unwrap_or_default();
+    let current = standees
+        .iter()
+        .map(|(standee, transform)| {
             (
                 standee.user_id.to_string(),
                 transform.translation,
             )
-        }),
-    );
+        })
+        .chain(
+            camera_store
+                .units
+                .iter()
+                .filter(|unit| units.contains(&unit.target_id))
+                .map(|unit| {
+                    (
+                        unit.target_id.clone(),
+                        Vec3::from(unit.translation),
+                    )
+                }),
+        )
+        .collect::<HashMap<_, _>>();
+    if positions.positions != current {
+        positions.positions = current;
+    }
+}
+
+/// Participant ids of the running battle's units, as opposed to players' characters.
+fn active_battle_unit_ids(store: &BattleRoundStore) -> HashSet<String> {
+    store
+        .active_encounter_id
+        .as_deref()
+        .and_then(|encounter_id| store.encounters.get(encounter_id))
+        .map(|encounter| {
+            encounter
+                .participants
+                .iter()
+                .filter(|participant| battle_participant_is_unit(participant))
+                .map(|participant| participant.target_id.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn battle_participant_is_unit(participant: &BattleParticipantSnapshot) -> bool {
+    participant.unit_template_id.is_some() || !participant.player_character
+}
+
+/// Lets the GM stand the running battle's units in the scene, so they give and get cover.
+fn voxel_battle_unit_panel(
+    mut contexts: EguiContexts,
+    battle_store: Option<Res<Persistent<BattleRoundStore>>>,
+    viewport_camera: Query<&Transform, With<VoxelViewportCamera>>,
+    mut camera_store: ResMut<Persistent<VoxelPlayerCameraStore>>,
+) {
+    let Some(encounter) = battle_store
+        .as_deref()
+        .and_then(|store| store.encounters.get(store.active_encounter_id.as_deref()?))
+    else {
+        return;
+    };
+    let units = encounter
+        .participants
+        .iter()
+        .filter(|participant| battle_participant_is_unit(participant))
+        .collect::<Vec<_>>();
+    if units.is_empty() {
+        return;
+    }
+    let Ok(ctx) = contexts.ctx_mut() else { return };
+
+    egui::Window::new("战斗单位站位")
+        .default_pos(egui::pos2(12.0, 620.0))
+        .default_width(300.0)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.small("单位站在GM视角所在的位置，掩护判定会把它算进视线。");
+            let mut changed = false;
+            for unit in units {
+                ui.horizontal(|ui| {
+                    ui.label(&unit.display_name);
+                    if ui.button("放到GM视角").clicked() {
+                        if let Ok(transform) = viewport_camera.single() {
+                            upsert_voxel_unit_position(
+                                &mut camera_store,
+                                &unit.target_id,
+                                transform.translation,
+                            );
+                            changed = true;
+                        }
+                    }
+                    let placed = camera_store
+                        .units
+                        .iter()
+                        .position(|placed| placed.target_id == unit.target_id);
+                    match placed {
+                        Some(index) => {
+                            if ui.button("移除").clicked() {
+                                camera_store.units.remove(index);
+                                changed = true;
+                            }
+                        },
+                        None => {
+                            ui.small("未放置");
+                        },
+                    }
+                });
+            }
+            if changed {
+                if let Err(err) = camera_store.persist() {
+                    eprintln!("failed to persist voxel unit positions: {err}");
+                }
+            }
+        });
+}
+
+fn upsert_voxel_unit_position(
+    store: &mut VoxelPlayerCameraStore,
+    target_id: &str,
+    translation: Vec3,
+) {
+    let persisted = PersistedVoxelUnitPosition {
+        target_id: target_id.to_owned(),
+        translation: translation.to_array(),
+    };
+    if let Some(unit) = store
+        .units
+        .iter_mut()
+        .find(|unit| unit.target_id == target_id)
+    {
+        *unit = persisted;
+    } else {
+        store.units.push(persisted);
+    }
 }
 
 fn voxel_player_standee_transform(camera_transform: &Transform) -> Transform { *camera_transform }
@@ -3961,6 +4128,7 @@ fn capture_voxel_player_view(
                 if let Err(err) = napcat_sender.0.try_send(NapcatOutboundMessage {
                     request_id: pending.request_id,
                     target_id: pending.user_id.to_string(),
+                    account_id: None,
                     message,
                 }) {
                     eprintln!("failed to queue voxel player capture: {err}");
@@ -7143,6 +7311,17 @@ fn possession_final_movement(
 }
 
 fn possession_character_movement(character: &PlayerCharacter) -> f32 {
+    if buff_conditions(&character.active_buffs)
+        .iter()
+        .any(|condition| {
+            matches!(
+                condition,
+                BuffCondition::Rooted | BuffCondition::Stunned
+            )
+        })
+    {
+        return 0.0;
+    }
     let mut speed = character.speed.max(0.0);
     if character.buff_base_stats.is_none() {
         let mut equipment = character.inventory.equipment.iter().collect::<Vec<_>>();
@@ -7684,6 +7863,504 @@ fn draw_voxel_target(
     }
 }
 
+/// Standees stand two voxels tall, like the first-person body.
+const BATTLE_MOVEMENT_BODY_VOXELS: i32 = 2;
+/// How far below a standee the floor may be before it counts as flying.
+const BATTLE_MOVEMENT_MAX_DROP_VOXELS: i32 = 4;
+
+/// Last accepted standee position of each player in a battle, and the cached reachable area of
+/// the current actor.
+#[derive(Resource, Default)]
+struct VoxelBattleMovementState {
+    anchors: HashMap<u64, Vec3>,
+    reachable: Option<VoxelReachableArea>,
+    unsaved_movement: bool,
+}
+
+struct VoxelReachableArea {
+    user_id: u64,
+    start: IVec3,
+    remaining: f32,
+    border: Vec<IVec3>,
+}
+
+fn voxel_cell_solid(grid: &Grid<u8>, cell: IVec3) -> bool {
+    grid.get(cell).is_some_and(TrpgVoxelConnector::solid)
+}
+
+fn voxel_cell_walkable(cell: IVec3, solid: &impl Fn(IVec3) -> bool) -> bool {
+    solid(cell - IVec3::Y)
+        && (0..BATTLE_MOVEMENT_BODY_VOXELS).all(|height| !solid(cell + IVec3::Y * height))
+}
+
+/// The floor cell a standee stands on, from its camera position; `None` when it floats.
+fn voxel_standing_cell(camera_position: Vec3, solid: &impl Fn(IVec3) -> bool) -> Option<IVec3> {
+    let feet =
+        first_person_player_position(camera_position) - Vec3::Y * PLAYER_STANDEE_HEIGHT * 0.5;
+    let feet = (feet / VOXEL_SIZE).floor().as_ivec3();
+    std::iter::once(1)
+        .chain((-BATTLE_MOVEMENT_MAX_DROP_VOXELS..=0).rev())
+        .map(|offset| feet + IVec3::Y * offset)
+        .find(|cell| voxel_cell_walkable(*cell, solid))
+}
+
+/// Walking cost in voxels from `start` to every standing cell within `budget`. Each step goes to
+/// one of the eight horizontal neighbours, climbing or dropping at most one voxel, and diagonal
+/// steps may not cut wall corners. Stops early once `goal` is settled.
+fn voxel_movement_costs(
+    start: IVec3,
+    budget: f32,
+    goal: Option<IVec3>,
+    solid: &impl Fn(IVec3) -> bool,
+) -> HashMap<IVec3, f32> {
+    let mut costs = HashMap::from([(start, 0.0_f32)]);
+    let mut frontier = BinaryHeap::from([Reverse((0.0_f32.to_bits(), start.to_array()))]);
+    let body_clear = |cell: IVec3| {
+        (0..BATTLE_MOVEMENT_BODY_VOXELS).all(|height| !solid(cell + IVec3::Y * height))
+    };
+    while let Some(Reverse((cost_bits, cell))) = frontier.pop() {
+        let cell = IVec3::from_array(cell);
+        let cost = f32::from_bits(cost_bits);
+        if costs.get(&cell).is_some_and(|best| *best < cost) {
+            continue;
+        }
+        if goal == Some(cell) {
+            break;
+        }
+        for (dx, dz) in [
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (1, -1),
+            (-1, 1),
+            (-1, -1),
+        ] {
+            let diagonal = dx != 0 && dz != 0;
+            if diagonal
+                && !(body_clear(cell + IVec3::new(dx, 0, 0))
+                    && body_clear(cell + IVec3::new(0, 0, dz)))
+            {
+                continue;
+            }
+            let step_cost = if diagonal { std::f32::consts::SQRT_2 } else { 1.0 };
+            let next_cost = cost + step_cost;
+            if next_cost > budget + 1e-3 {
+                continue;
+            }
+            let beside = cell + IVec3::new(dx, 0, dz);
+            for rise in [0, 1, -1] {
+                let next = beside + IVec3::Y * rise;
+                let clear = match rise {
+                    1 => !solid(cell + IVec3::Y * BATTLE_MOVEMENT_BODY_VOXELS),
+                    -1 => !solid(beside + IVec3::Y * (BATTLE_MOVEMENT_BODY_VOXELS - 1)),
+                    _ => true,
+                };
+                if !clear
+                    || !voxel_cell_walkable(next, solid)
+                    || costs.get(&next).is_some_and(|best| *best <= next_cost)
+                {
+                    continue;
+                }
+                costs.insert(next, next_cost);
+                frontier.push(Reverse((
+                    next_cost.to_bits(),
+                    next.to_array(),
+                )));
+            }
+        }
+    }
+    costs
+}
+
+/// Voxels a standee walks between two camera positions, or `None` when the destination can't be
+/// reached within `budget`. Standees that float above the floor move in a straight line.
+fn battle_movement_cost(
+    from: Vec3,
+    to: Vec3,
+    budget: f32,
+    solid: &impl Fn(IVec3) -> bool,
+) -> Option<f32> {
+    let Some(start) = voxel_standing_cell(from, solid) else {
+        let distance = from.distance(to) / VOXEL_SIZE;
+        return (distance <= budget + 1e-3).then_some(distance);
+    };
+    let goal = voxel_standing_cell(to, solid)?;
+    voxel_movement_costs(start, budget, Some(goal), solid)
+        .get(&goal)
+        .copied()
+}
+
+/// Reachable cells on the edge of the area, which is all the overlay needs to draw.
+fn voxel_reachable_border(costs: &HashMap<IVec3, f32>) -> Vec<IVec3> {
+    costs
+        .keys()
+        .filter(|cell| {
+            [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z]
+                .into_iter()
+                .any(|offset| {
+                    (-1..=1).all(|rise| !costs.contains_key(&(**cell + offset + IVec3::Y * rise)))
+                })
+        })
+        .copied()
+        .collect()
+}
+
+/// Charges battle movement when a fighting player's standee moves and puts the standee back when
+/// the walk is longer than their movement left. Possessed players are only charged, since
+/// possession already limits how far they walk.
+#[allow(clippy::too_many_arguments)]
+fn enforce_battle_standee_movement(
+    battle_store: Option<ResMut<Persistent<BattleRoundStore>>>,
+    manager: Option<Res<Persistent<NapcatMessageManager>>>,
+    grids: Query<&Grid<u8>, With<TrpgVoxelGrid>>,
+    possession: Res<VoxelPossessionState>,
+    mut editor: ResMut<VoxelEditorState>,
+    mut movement: ResMut<VoxelBattleMovementState>,
+    mut camera_store: ResMut<Persistent<VoxelPlayerCameraStore>>,
+    mut capture_cameras: Query<(
+        &VoxelPlayerCaptureCamera,
+        &mut Transform,
+    )>,
+) {
+    let (Some(mut battle_store), Ok(grid)) = (battle_store, grids.single()) else {
+        return;
+    };
+    let solid = |cell| voxel_cell_solid(grid, cell);
+    let mut moved = false;
+    let mut rejected = false;
+    for (camera, mut transform) in &mut capture_cameras {
+        let user_id = camera.user_id;
+        let target_id = user_id.to_string();
+        let Some(remaining) = battle_store.battle_movement_remaining(&target_id) else {
+            movement.anchors.remove(&user_id);
+            continue;
+        };
+        let anchor = *movement
+            .anchors
+            .entry(user_id)
+            .or_insert(transform.translation);
+        let current = transform.translation;
+        if (anchor / VOXEL_SIZE).floor() == (current / VOXEL_SIZE).floor() {
+            continue;
+        }
+        if possession.active_user_id == Some(user_id) {
+            let walked = Vec2::new(
+                current.x - anchor.x,
+                current.z - anchor.z,
+            )
+            .length()
+                / VOXEL_SIZE;
+            let _ = battle_store.spend_battle_movement(&target_id, walked.min(remaining));
+            movement.anchors.insert(user_id, current);
+            moved = true;
+            continue;
+        }
+        let spent = battle_movement_cost(anchor, current, remaining, &solid)
+            .ok_or_else(|| {
+                format!(
+                    "{}本轮只剩{:.1}格移动，走不到那里",
+                    voxel_player_display_name(manager.as_deref(), user_id),
+                    remaining
+                )
+            })
+            .and_then(|cost| battle_store.spend_battle_movement(&target_id, cost));
+        match spent {
+            Ok(left) => {
+                movement.anchors.insert(user_id, current);
+                moved = true;
+                if left.is_finite() {
+                    editor.physics_status = Some(format!(
+                        "{}本轮还剩{:.1}格移动",
+                        voxel_player_display_name(manager.as_deref(), user_id),
+                        left
+                    ));
+                }
+            },
+            Err(err) => {
+                transform.translation = anchor;
+                upsert_voxel_player_camera(&mut camera_store, user_id, &transform);
+                editor.physics_status = Some(err);
+                rejected = true;
+            },
+        }
+    }
+    if rejected {
+        if let Err(err) = camera_store.persist() {
+            eprintln!("failed to persist returned player camera: {err}");
+        }
+    }
+    // Walking charges every voxel; save once the standee stops.
+    if moved {
+        movement.unsaved_movement = true;
+    } else if movement.unsaved_movement {
+        movement.unsaved_movement = false;
+        if let Err(err) = battle_store.persist() {
+            eprintln!("failed to persist battle movement: {err}");
+        }
+    }
+}
+
+/// Outlines where the current actor can still walk this round. Gizmos only render for the GM.
+fn draw_battle_reachable_area(
+    mut gizmos: Gizmos,
+    battle_store: Option<Res<Persistent<BattleRoundStore>>>,
+    grids: Query<Ref<Grid<u8>>, With<TrpgVoxelGrid>>,
+    capture_cameras: Query<(&VoxelPlayerCaptureCamera, &Transform)>,
+    mut movement: ResMut<VoxelBattleMovementState>,
+) {
+    let mover = battle_store
+        .as_deref()
+        .and_then(|store| store.current_battle_mover())
+        .and_then(|(target_id, remaining)| {
+            Some((
+                target_id.parse::<u64>().ok()?,
+                remaining,
+            ))
+        });
+    let (Some((user_id, remaining)), Ok(grid)) = (mover, grids.single()) else {
+        movement.reachable = None;
+        return;
+    };
+    let position = movement.anchors.get(&user_id).copied().or_else(|| {
+        capture_cameras
+            .iter()
+            .find(|(camera, _)| camera.user_id == user_id)
+            .map(|(_, transform)| transform.translation)
+    });
+    let solid = |cell| voxel_cell_solid(&grid, cell);
+    let Some(start) = position.and_then(|position| voxel_standing_cell(position, &solid)) else {
+        movement.reachable = None;
+        return;
+    };
+    if grid.is_changed()
+        || movement.reachable.as_ref().is_none_or(|area| {
+            area.user_id != user_id || area.start != start || area.remaining != remaining
+        })
+    {
+        let costs = voxel_movement_costs(start, remaining, None, &solid);
+        movement.reachable = Some(VoxelReachableArea {
+            user_id,
+            start,
+            remaining,
+            border: voxel_reachable_border(&costs),
+        });
+    }
+    let Some(area) = &movement.reachable else {
+        return;
+    };
+    for cell in &area.border {
+        gizmos.rect(
+            Isometry3d::new(
+                Vec3::new(
+                    (cell.x as f32 + 0.5) * VOXEL_SIZE,
+                    cell.y as f32 * VOXEL_SIZE + 0.02,
+                    (cell.z as f32 + 0.5) * VOXEL_SIZE,
+                ),
+                Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+            ),
+            Vec2::splat(VOXEL_SIZE * 0.8),
+            Color::srgb(0.3, 0.85, 1.0),
+        );
+    }
+}
+
+/// Sight lines between every pair of standees from the last scene update.
+#[derive(Resource, Default)]
+struct VoxelCoverRays {
+    rays: Vec<VoxelCoverRay>,
+}
+
+struct VoxelCoverRay {
+    attacker: String,
+    from: Vec3,
+    to: Vec3,
+    blocked: bool,
+}
+
+/// Whether the straight segment passes through a solid voxel, ignoring the cells at either end
+/// where the standees themselves stand.
+fn voxel_segment_blocked(from: Vec3, to: Vec3, solid: &impl Fn(IVec3) -> bool) -> bool {
+    let start = (from / VOXEL_SIZE).floor().as_ivec3();
+    let end = (to / VOXEL_SIZE).floor().as_ivec3();
+    let length = from.distance(to);
+    let step = VOXEL_SIZE * 0.2;
+    let mut distance = 0.0;
+    while distance <= length {
+        let cell = (from.lerp(to, distance / length.max(f32::EPSILON)) / VOXEL_SIZE)
+            .floor()
+            .as_ivec3();
+        if cell != start && cell != end && solid(cell) {
+            return true;
+        }
+        distance += step;
+    }
+    false
+}
+
+/// Rays from the attacker's standee to the middle, both sides, top and bottom of the target's
+/// standee, each paired with whether a voxel blocks it.
+fn voxel_cover_rays(
+    from: Vec3,
+    target: Vec3,
+    target_half_size: Vec2,
+    solid: &impl Fn(IVec3) -> bool,
+) -> Vec<(Vec3, bool)> {
+    let side = Vec3::Y
+        .cross((target - from).with_y(0.0))
+        .try_normalize()
+        .unwrap_or(Vec3::X);
+    let across = side * target_half_size.x * 0.8;
+    let up = Vec3::Y * target_half_size.y * 0.8;
+    [
+        target,
+        target + across,
+        target - across,
+        target + up,
+        target - up,
+    ]
+    .into_iter()
+    .map(|point| {
+        (
+            point,
+            voxel_segment_blocked(from, point, solid),
+        )
+    })
+    .collect()
+}
+
+fn voxel_cover_from_rays(rays: &[(Vec3, bool)]) -> SceneCover {
+    match rays.iter().filter(|(_, blocked)| *blocked).count() {
+        0 => SceneCover::None,
+        blocked if blocked == rays.len() => SceneCover::Full,
+        _ => SceneCover::Partial,
+    }
+}
+
+/// Units have no standee image, so their sight-line target is a body-sized box.
+const VOXEL_UNIT_HALF_SIZE: Vec2 = Vec2::new(VOXEL_SIZE, PLAYER_STANDEE_HEIGHT * 0.5);
+
+/// Measures cover between every pair of placed participants, again only when someone moved, a
+/// standee changed or the grid was edited.
+fn sync_voxel_scene_cover(
+    mut positions: ResMut<SceneCharacterPositions>,
+    mut cover_rays: ResMut<VoxelCoverRays>,
+    grids: Query<Ref<Grid<u8>>, With<TrpgVoxelGrid>>,
+    standees: Query<Ref<VoxelPlayerStandee>>,
+) {
+    let Ok(grid) = grids.single() else {
+        if !positions.cover.is_empty() {
+            positions.cover.clear();
+        }
+        cover_rays.rays.clear();
+        return;
+    };
+    if !positions.is_changed()
+        && !grid.is_changed()
+        && !standees.iter().any(|standee| standee.is_changed())
+    {
+        return;
+    }
+    let half_sizes = standees
+        .iter()
+        .map(|standee| {
+            (
+                standee.user_id.to_string(),
+                standee.half_size,
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    let solid = |cell| voxel_cell_solid(&grid, cell);
+    let SceneCharacterPositions { positions, cover } = &mut *positions;
+    cover.clear();
+    cover_rays.rays.clear();
+    for (attacker_id, from) in positions.iter() {
+        for (target_id, target) in positions.iter() {
+            if attacker_id == target_id {
+                continue;
+            }
+            let rays = voxel_cover_rays(
+                *from,
+                *target,
+                half_sizes
+                    .get(target_id)
+                    .copied()
+                    .unwrap_or(VOXEL_UNIT_HALF_SIZE),
+                &solid,
+            );
+            cover.insert(
+                (attacker_id.clone(), target_id.clone()),
+                voxel_cover_from_rays(&rays),
+            );
+            cover_rays.rays.extend(
+                rays.into_iter().map(|(to, blocked)| VoxelCoverRay {
+                    attacker: attacker_id.clone(),
+                    from: *from,
+                    to,
+                    blocked,
+                }),
+            );
+        }
+    }
+}
+
+fn draw_battle_cover_rays(
+    mut gizmos: Gizmos,
+    battle_store: Option<Res<Persistent<BattleRoundStore>>>,
+    cover_rays: Res<VoxelCoverRays>,
+) {
+    let Some(attacker) = battle_store
+        .as_deref()
+        .and_then(|store| store.current_cover_attacker())
+    else {
+        return;
+    };
+    for ray in cover_rays
+        .rays
+        .iter()
+        .filter(|ray| ray.attacker == attacker)
+    {
+        let color = if ray.blocked {
+            Color::srgb(1.0, 0.25, 0.2)
+        } else {
+            Color::srgb(0.3, 1.0, 0.4)
+        };
+        gizmos.line(ray.from, ray.to, color);
+    }
+}
+
+/// Outlines the running battle's placed units, which have no standee to show where they are.
+fn draw_battle_unit_positions(
+    mut gizmos: Gizmos,
+    battle_store: Option<Res<Persistent<BattleRoundStore>>>,
+    camera_store: Res<Persistent<VoxelPlayerCameraStore>>,
+) {
+    let Some(battle_store) = battle_store.as_deref() else {
+        return;
+    };
+    if camera_store.units.is_empty() {
+        return;
+    }
+    let units = active_battle_unit_ids(battle_store);
+    for unit in camera_store
+        .units
+        .iter()
+        .filter(|unit| units.contains(&unit.target_id))
+    {
+        gizmos.cuboid(
+            Transform::from_translation(Vec3::from(unit.translation)).with_scale(
+                Vec3::new(
+                    VOXEL_UNIT_HALF_SIZE.x,
+                    VOXEL_UNIT_HALF_SIZE.y,
+                    VOXEL_UNIT_HALF_SIZE.x,
+                ) * 2.0,
+            ),
+            Color::srgb(1.0, 0.7, 0.2),
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -10006,4 +10683,194 @@ mod tests {
         assert_eq!(possession.movement_used, 0.0);
         assert!(!possession.reset_movement_requested);
     }
+
+    fn floor_with_wall(cell: IVec3) -> bool {
+        // A 9x9 floor at y = -1 with a wall along x = 1 that leaves a gap at z = 4.
+        let floor = cell.y == -1 && (-4..=4).contains(&cell.x) && (-4..=4).contains(&cell.z);
+        let wall = cell.x == 1 && (0..=2).contains(&cell.y) && cell.z < 4;
+        floor || wall
+    }
+
+    fn standing_camera(cell: IVec3) -> Vec3 {
+        Vec3::new(
+            (cell.x as f32 + 0.5) * VOXEL_SIZE,
+            cell.y as f32 * VOXEL_SIZE + PLAYER_STANDEE_HEIGHT * 0.5 + FIRST_PERSON_EYE_OFFSET,
+            (cell.z as f32 + 0.5) * VOXEL_SIZE,
+        )
+    }
+
+    #[test]
+    fn battle_movement_paths_around_walls_within_the_budget() {
+        assert_eq!(
+            voxel_standing_cell(
+                standing_camera(IVec3::ZERO),
+                &floor_with_wall
+            ),
+            Some(IVec3::ZERO)
+        );
+        assert_eq!(
+            voxel_standing_cell(
+                standing_camera(IVec3::new(0, 6, 0)),
+                &floor_with_wall,
+            ),
+            None
+        );
+
+        let from = standing_camera(IVec3::ZERO);
+        let to = standing_camera(IVec3::new(2, 0, 0));
+        let around = battle_movement_cost(from, to, 20.0, &floor_with_wall).unwrap();
+        assert!(around > 6.0, "{around}");
+        assert_eq!(
+            battle_movement_cost(from, to, 4.0, &floor_with_wall),
+            None
+        );
+        assert_eq!(
+            battle_movement_cost(
+                from,
+                standing_camera(IVec3::new(0, 0, 3)),
+                4.0,
+                &floor_with_wall,
+            ),
+            Some(3.0)
+        );
+        // Into the wall itself is never a standing cell.
+        assert_eq!(
+            battle_movement_cost(
+                from,
+                standing_camera(IVec3::new(1, 0, 0)),
+                20.0,
+                &floor_with_wall,
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn battle_movement_climbs_single_steps_and_outlines_the_reachable_area() {
+        let stairs = |cell: IVec3| {
+            (cell.y == -1 && (-3..=3).contains(&cell.x) && cell.z == 0)
+                || cell == IVec3::new(1, 0, 0)
+                || (cell.x == 2 && (0..=1).contains(&cell.y) && cell.z == 0)
+        };
+        let costs = voxel_movement_costs(IVec3::ZERO, 10.0, None, &stairs);
+
+        assert_eq!(
+            costs.get(&IVec3::new(1, 1, 0)),
+            Some(&1.0)
+        );
+        assert_eq!(
+            costs.get(&IVec3::new(2, 2, 0)),
+            Some(&2.0)
+        );
+        assert_eq!(costs.get(&IVec3::new(3, 0, 0)), None);
+        assert_eq!(
+            costs.get(&IVec3::new(-3, 0, 0)),
+            Some(&3.0)
+        );
+
+        let mut border = voxel_reachable_border(&costs);
+        border.sort_by_key(|cell| cell.x);
+        assert_eq!(border, vec![
+            IVec3::new(-3, 0, 0),
+            IVec3::new(-2, 0, 0),
+            IVec3::new(-1, 0, 0),
+            IVec3::ZERO,
+            IVec3::new(1, 1, 0),
+            IVec3::new(2, 2, 0),
+        ]);
+    }
+
+    #[test]
+    fn scene_cover_includes_units_and_waits_for_movement() {
+        let mut app = App::new();
+        app.world_mut().spawn((TrpgVoxelGrid, Grid::<u8>::new()));
+        app.init_resource::<VoxelCoverRays>()
+            .insert_resource(SceneCharacterPositions {
+                positions: HashMap::from([
+                    (
+                        "1001".to_owned(),
+                        Vec3::new(0.0, 1.0, 0.0),
+                    ),
+                    (
+                        "goblin".to_owned(),
+                        Vec3::new(2.0, 1.0, 0.0),
+                    ),
+                ]),
+                ..default()
+            })
+            .add_systems(Update, sync_voxel_scene_cover);
+        app.update();
+
+        let positions = app.world().resource::<SceneCharacterPositions>();
+        assert_eq!(positions.cover.len(), 2);
+        assert_eq!(
+            positions.cover_between("goblin", "1001"),
+            SceneCover::None
+        );
+        assert_eq!(
+            app.world().resource::<VoxelCoverRays>().rays.len(),
+            10
+        );
+
+        // Nobody moved, so the sight lines are not cast again.
+        app.world_mut()
+            .resource_mut::<VoxelCoverRays>()
+            .rays
+            .clear();
+        app.update();
+        assert!(app.world().resource::<VoxelCoverRays>().rays.is_empty());
+
+        app.world_mut()
+            .resource_mut::<SceneCharacterPositions>()
+            .positions
+            .insert(
+                "goblin".to_owned(),
+                Vec3::new(3.0, 1.0, 0.0),
+            );
+        app.update();
+        assert_eq!(
+            app.world().resource::<VoxelCoverRays>().rays.len(),
+            10
+        );
+    }
+
+    #[test]
+    fn cover_rays_grade_walls_between_standees() {
+        let half_size = Vec2::new(VOXEL_SIZE, PLAYER_STANDEE_HEIGHT * 0.5);
+        let attacker = standing_camera(IVec3::new(-2, 0, 0));
+        let behind_wall = standing_camera(IVec3::new(3, 0, 0));
+        let rays = voxel_cover_rays(
+            attacker,
+            behind_wall,
+            half_size,
+            &floor_with_wall,
+        );
+        assert_eq!(rays.len(), 5);
+        assert_eq!(
+            voxel_cover_from_rays(&rays),
+            SceneCover::Full
+        );
+
+        let in_the_open = standing_camera(IVec3::new(-2, 0, 3));
+        assert_eq!(
+            voxel_cover_from_rays(&voxel_cover_rays(
+                attacker,
+                in_the_open,
+                half_size,
+                &floor_with_wall
+            )),
+            SceneCover::None
+        );
+
+        let low_wall = |cell: IVec3| floor_with_wall(cell) && cell.y < 2;
+        assert_eq!(
+            voxel_cover_from_rays(&voxel_cover_rays(
+                attacker,
+                behind_wall,
+                half_size,
+                &low_wall
+            )),
+            SceneCover::Partial
+        );
+    }
 }